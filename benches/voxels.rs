@@ -0,0 +1,195 @@
+use std::{fs, sync::Arc};
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use glam::{IVec3, Vec3};
+use graphics_test::{
+    ecs::entities::spawner::sky_light_at,
+    noise::perlin::{perlin_3d, sample_grid},
+    voxels::{
+        biome_edges::MAX_EDGE_DISTANCE,
+        biome_profile::{get_biome_by_name, BiomeProfile, SampleContext},
+        voxel_data::VoxelData,
+        voxel_light::VoxelLighting,
+        voxel_registry::get_voxel_by_name,
+        voxel_scene::{VoxelChunk, VoxelScene, CHUNK_SIZE},
+        voxel_shapes::voxel_shape,
+    },
+};
+
+// The plains surface is at y 5, so chunk 0 0 0 holds terrain, the surface and open air
+const SURFACE_CHUNK: IVec3 = IVec3::ZERO;
+
+fn context(position: IVec3) -> SampleContext {
+    SampleContext {
+        position,
+        slope: Vec3::ZERO,
+        depth: 0.0,
+        moisture: 0.0,
+        temperature: 0.0,
+        density: 0.0,
+        edge_distance: MAX_EDGE_DISTANCE,
+    }
+}
+
+fn chunk_positions() -> impl Iterator<Item = IVec3> {
+    let size = CHUNK_SIZE as i32;
+    (0..size)
+        .flat_map(move |x| (0..size).flat_map(move |y| (0..size).map(move |z| IVec3::new(x, y, z))))
+}
+
+// A scene with the surface chunk and its neighbours generated, so meshing and lighting can look
+// across chunk borders
+fn surface_scene() -> VoxelScene {
+    let scene = VoxelScene::new();
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                let position = SURFACE_CHUNK + IVec3::new(x, y, z);
+                scene
+                    .chunks
+                    .insert(position, VoxelChunk::generate(position).unwrap());
+            }
+        }
+    }
+    scene
+}
+
+fn biome_sampling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("biome_sampling");
+    let plains = get_biome_by_name("plains".to_string()).unwrap();
+    let hills = BiomeProfile::from_json(fs::read_to_string("benches/fixtures/hills.json").unwrap())
+        .unwrap();
+    for (name, biome) in [("plains", &*plains), ("hills", &hills)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for position in chunk_positions() {
+                    let mut context = context(position);
+                    context.density = biome.sample_density(&context);
+                    if context.density > 0.0 {
+                        black_box(biome.sample_voxel(&mut context));
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// A full chunk of noise one position at a time against the SIMD grid that generation uses
+fn noise_sampling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("noise_sampling");
+    let frequency = 1.0 / 20.0;
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for position in chunk_positions() {
+                let position = position.as_vec3() * frequency;
+                black_box(perlin_3d(position.x, position.y, position.z));
+            }
+        })
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| sample_grid(black_box(SURFACE_CHUNK), CHUNK_SIZE, frequency))
+    });
+    group.finish();
+}
+
+fn chunk_generation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chunk_generation");
+    // Underground, on the surface and in the air above it
+    for (name, position) in [
+        ("underground", IVec3::new(0, -1, 0)),
+        ("surface", SURFACE_CHUNK),
+        ("air", IVec3::new(0, 1, 0)),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| VoxelChunk::generate(black_box(position)))
+        });
+    }
+    group.finish();
+}
+
+// Chunks store their voxels directly, this covers reading and writing them by position
+fn voxel_access(c: &mut Criterion) {
+    let mut group = c.benchmark_group("voxel_access");
+    let chunk = VoxelChunk::generate(SURFACE_CHUNK).unwrap();
+    let stone = VoxelData {
+        shape: voxel_shape::CUBE,
+        state: 0,
+        id: get_voxel_by_name("stone".to_string()).unwrap().id,
+    };
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            for position in chunk_positions() {
+                black_box(chunk.voxel_at(&position.as_uvec3()));
+            }
+        })
+    });
+    group.bench_function("set", |b| {
+        b.iter_batched_ref(
+            || chunk.clone(),
+            |chunk| {
+                for position in chunk_positions() {
+                    chunk.set_voxel_scenespace(&position, stone);
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn meshing(c: &mut Criterion) {
+    let scene = surface_scene();
+    let chunk = scene.chunks.get(&SURFACE_CHUNK).unwrap().clone();
+    c.bench_function("meshing/surface", |b| {
+        b.iter(|| chunk.generate_mesh(Arc::clone(&scene.chunks)))
+    });
+}
+
+// Light is worked out per position from the open sky above it
+fn light_propagation(c: &mut Criterion) {
+    let scene = surface_scene();
+    let base = SURFACE_CHUNK * CHUNK_SIZE as i32;
+    c.bench_function("light_propagation/surface", |b| {
+        b.iter(|| {
+            for position in chunk_positions() {
+                black_box(sky_light_at(&scene, base + position));
+            }
+        })
+    });
+}
+
+// A chunk that needs both, lit and meshed on separate jobs that each copy it or on one job sharing
+// the copy
+fn light_and_mesh(c: &mut Criterion) {
+    let scene = surface_scene();
+    let lighting = VoxelLighting::default();
+    let mut group = c.benchmark_group("light_and_mesh");
+    group.bench_function("separate", |b| {
+        b.iter(|| {
+            lighting.light_chunk(&scene.chunks, SURFACE_CHUNK);
+            let chunk = scene.chunks.get(&SURFACE_CHUNK).unwrap().clone();
+            black_box(chunk.generate_mesh(Arc::clone(&scene.chunks)))
+        })
+    });
+    group.bench_function("fused", |b| {
+        b.iter(|| {
+            let chunk = scene.chunks.get(&SURFACE_CHUNK).unwrap().clone();
+            lighting.light_snapshot(&scene.chunks, &chunk);
+            black_box(chunk.generate_mesh(Arc::clone(&scene.chunks)))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    biome_sampling,
+    noise_sampling,
+    chunk_generation,
+    voxel_access,
+    meshing,
+    light_propagation,
+    light_and_mesh
+);
+criterion_main!(benches);
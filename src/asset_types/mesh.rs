@@ -27,6 +27,13 @@ impl Mesh {
         }
     }
 
+    // Bytes of the vertex and index data kept on the CPU side
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.vertices.capacity() * std::mem::size_of::<Vertex>()
+            + self.indices.capacity() * std::mem::size_of::<u32>()
+    }
+
     pub fn append_custom(
         mut self,
         vertices: Vec<[f32; 3]>,
@@ -0,0 +1,177 @@
+use std::{collections::HashMap, time::Instant};
+#[cfg(feature = "render")]
+use std::{fs, sync::Arc};
+
+#[cfg(feature = "render")]
+use image::DynamicImage;
+#[cfg(feature = "render")]
+use parking_lot::RwLock;
+#[cfg(feature = "render")]
+use rayon::prelude::*;
+use tracing::{info, warn};
+
+#[cfg(feature = "render")]
+use crate::data_packs::texture_files;
+use crate::{
+    ecs::entities::entity_registry,
+    jobs::{self, JobClass, JobHandle},
+    localization,
+    progress::Progress,
+    voxels::{biome_profile, jigsaw, voxel_mesh, voxel_registry, voxel_shapes},
+};
+
+// A part of the startup load, run once the steps it reads from have loaded
+struct AssetStep {
+    name: &'static str,
+    dependencies: &'static [&'static str],
+    load: fn(),
+}
+
+// Steps come after their dependencies
+fn steps() -> Vec<AssetStep> {
+    let steps = vec![
+        AssetStep {
+            name: "shapes",
+            dependencies: &[],
+            load: || {
+                voxel_shapes::load();
+                voxel_mesh::load();
+            },
+        },
+        AssetStep {
+            name: "voxels",
+            dependencies: &[],
+            load: voxel_registry::load,
+        },
+        // Biomes name the voxels they place
+        AssetStep {
+            name: "biomes",
+            dependencies: &["voxels"],
+            load: biome_profile::load,
+        },
+        AssetStep {
+            name: "entities",
+            dependencies: &[],
+            load: entity_registry::load,
+        },
+        AssetStep {
+            name: "structures",
+            dependencies: &[],
+            load: jigsaw::load,
+        },
+        AssetStep {
+            name: "translations",
+            dependencies: &[],
+            load: localization::load,
+        },
+    ];
+    #[cfg(feature = "render")]
+    let steps = steps
+        .into_iter()
+        .chain([AssetStep {
+            name: "textures",
+            dependencies: &[],
+            load: load_textures,
+        }])
+        .collect();
+    steps
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub loaded: usize,
+    pub total: usize,
+    // The step that just finished
+    pub step: &'static str,
+}
+
+// Loads the registries on the job workers instead of each one blocking whatever reads it first, and
+// returns once everything has loaded. Has to run after the plugins registered their voxels, since
+// voxel ids are fixed once the voxels load. Nothing is generated before this, so it borrows the
+// generation workers
+pub fn load_assets(mut on_progress: impl FnMut(LoadProgress)) {
+    let start = Instant::now();
+    let steps = steps();
+    let (sender, receiver) = flume::unbounded();
+    let mut handles: HashMap<&'static str, JobHandle> = HashMap::new();
+    for step in &steps {
+        let dependencies = step
+            .dependencies
+            .iter()
+            .map(|name| handles[name].clone())
+            .collect::<Vec<_>>();
+        let (name, load, sender) = (step.name, step.load, sender.clone());
+        let handle = jobs::spawn(JobClass::Generation, &dependencies, move || {
+            load();
+            sender.send(name).unwrap();
+        });
+        handles.insert(name, handle);
+    }
+    drop(sender);
+
+    let progress = Progress::start("Loading assets");
+    progress.stage("Loading", steps.len() as u64);
+    // A step that panicked never reports, the channel closes once every job is done
+    let mut loaded = 0;
+    for step in receiver.iter() {
+        loaded += 1;
+        progress.advance(1);
+        on_progress(LoadProgress {
+            loaded,
+            total: steps.len(),
+            step,
+        });
+    }
+    progress.finish();
+    match loaded == steps.len() {
+        true => info!("Loaded assets in {:.2?}", start.elapsed()),
+        false => warn!("Only {loaded} of {} asset steps loaded", steps.len()),
+    }
+}
+
+pub fn log_progress(progress: LoadProgress) {
+    info!(
+        "Loaded {} ({}/{})",
+        progress.step, progress.loaded, progress.total
+    );
+}
+
+#[cfg(feature = "render")]
+lazy_static! {
+    // Decoded images from the textures folders by name, uploaded when a material needs them
+    static ref TEXTURES: RwLock<HashMap<String, Arc<DynamicImage>>> = RwLock::new(HashMap::new());
+}
+
+#[cfg(feature = "render")]
+fn load_textures() {
+    let textures = texture_files()
+        .into_par_iter()
+        .filter_map(|(name, path)| {
+            match fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Ok(image::load_from_memory(&bytes)?))
+            {
+                Ok(image) => Some((name, Arc::new(image))),
+                Err(e) => {
+                    warn!("Couldn't load the texture {}: {e}", path.display());
+                    None
+                }
+            }
+        })
+        .collect::<HashMap<_, _>>();
+    *TEXTURES.write() = textures;
+}
+
+// Loads the textures again after the texture pack changed, the atlas has to be rebuilt afterwards
+#[cfg(feature = "render")]
+pub fn reload_textures() {
+    let start = Instant::now();
+    load_textures();
+    info!("Reloaded textures in {:.2?}", start.elapsed());
+}
+
+// A texture by file name without the extension, such as "lapis_block"
+#[cfg(feature = "render")]
+pub fn texture_image(name: &str) -> Option<Arc<DynamicImage>> {
+    TEXTURES.read().get(name).cloned()
+}
@@ -0,0 +1,191 @@
+use std::time::{Duration, Instant};
+
+use glam::{IVec3, Vec3};
+use rand::seq::SliceRandom;
+use rodio::{OutputStreamHandle, Sink, Source};
+use tracing::warn;
+
+use crate::voxels::{
+    biome_profile::{get_biome_by_name, Ambience},
+    voxel_scene::VoxelScene,
+};
+
+use super::SoundLibrary;
+
+// Seconds it takes one biome's ambience to fade into the next
+const CROSSFADE_TIME: f32 = 3.0;
+const AMBIENT_VOLUME: f32 = 0.4;
+const MUSIC_VOLUME: f32 = 0.5;
+// Between the end of one track and the start of the next
+const MUSIC_PAUSE: Duration = Duration::from_secs(90);
+// A listener with a voxel at most this far above is underground
+const CAVE_CEILING: i32 = 24;
+const REVERB_DELAY: Duration = Duration::from_millis(70);
+const REVERB_AMOUNT: f32 = 0.35;
+
+// A sound playing in a layer, the name and reverb tell whether it's still the one wanted
+struct Track {
+    name: String,
+    reverb: bool,
+    sink: Sink,
+    volume: f32,
+}
+
+// One sound at a time, the one before fading out while the new one fades in
+#[derive(Default)]
+struct Layer {
+    current: Option<Track>,
+    fading: Vec<Track>,
+}
+
+impl Layer {
+    fn is_playing(&self, name: &str, reverb: bool) -> bool {
+        self.current
+            .as_ref()
+            .map_or(false, |track| track.name == name && track.reverb == reverb)
+    }
+
+    fn fade_out(&mut self) {
+        self.fading.extend(self.current.take());
+    }
+
+    fn play(&mut self, name: String, reverb: bool, sink: Sink) {
+        self.fade_out();
+        self.current = Some(Track {
+            name,
+            reverb,
+            sink,
+            volume: 0.0,
+        });
+    }
+
+    // Moves the volumes a step along their fades, finished tracks are dropped which stops them
+    fn step(&mut self, step: f32, full_volume: f32) {
+        if let Some(track) = &mut self.current {
+            track.volume = (track.volume + step).min(1.0);
+            track.sink.set_volume(track.volume * full_volume);
+        }
+        for track in &mut self.fading {
+            track.volume -= step;
+            track.sink.set_volume(track.volume.max(0.0) * full_volume);
+        }
+        self.fading.retain(|track| track.volume > 0.0);
+    }
+}
+
+// Crossfades the ambient loop and music of the biome the listener is in
+pub struct AmbiencePlayer {
+    ambient: Layer,
+    music: Layer,
+    // When the next track may start once the last one has finished
+    next_music: Instant,
+    last_update: Instant,
+}
+
+impl AmbiencePlayer {
+    pub fn new() -> Self {
+        Self {
+            ambient: Layer::default(),
+            music: Layer::default(),
+            next_music: Instant::now(),
+            last_update: Instant::now(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        handle: &OutputStreamHandle,
+        sounds: &SoundLibrary,
+        listener: Vec3,
+        scene: &VoxelScene,
+    ) {
+        let now = Instant::now();
+        let step = now.duration_since(self.last_update).as_secs_f32() / CROSSFADE_TIME;
+        self.last_update = now;
+
+        let position = listener.floor().as_ivec3();
+        let ambience = get_biome_by_name(scene.biome_at(&position))
+            .and_then(|biome| biome.ambience().cloned())
+            .unwrap_or_default();
+        let reverb = ambience.cave_reverb && is_underground(scene, position);
+
+        match &ambience.ambient {
+            Some(name) if !self.ambient.is_playing(name, reverb) => {
+                if let Some(sink) = start(handle, sounds, name, reverb, true) {
+                    self.ambient.play(name.clone(), reverb, sink);
+                }
+            }
+            Some(_) => {}
+            None => self.ambient.fade_out(),
+        }
+        self.update_music(handle, sounds, &ambience, reverb, now);
+
+        self.ambient.step(step, AMBIENT_VOLUME);
+        self.music.step(step, MUSIC_VOLUME);
+    }
+
+    fn update_music(
+        &mut self,
+        handle: &OutputStreamHandle,
+        sounds: &SoundLibrary,
+        ambience: &Ambience,
+        reverb: bool,
+        now: Instant,
+    ) {
+        // A track from another biome fades out, the new biome's music starts after the pause
+        match &self.music.current {
+            Some(track) if track.sink.empty() || !ambience.music.contains(&track.name) => {
+                self.music.fade_out();
+                self.next_music = now + MUSIC_PAUSE;
+            }
+            Some(_) => {}
+            None if now >= self.next_music => {
+                if let Some(name) = ambience.music.choose(&mut rand::thread_rng()) {
+                    match start(handle, sounds, name, reverb, false) {
+                        Some(sink) => self.music.play(name.clone(), reverb, sink),
+                        // Missing tracks are tried again after a pause rather than every frame
+                        None => self.next_music = now + MUSIC_PAUSE,
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+// A sink playing the sound at no volume yet, None if there's no such sound
+fn start(
+    handle: &OutputStreamHandle,
+    sounds: &SoundLibrary,
+    name: &str,
+    reverb: bool,
+    looped: bool,
+) -> Option<Sink> {
+    let source = sounds.decode(name)?;
+    let sink = match Sink::try_new(handle) {
+        Ok(sink) => sink,
+        Err(e) => {
+            warn!("Failed to play the sound {name}: {e}");
+            return None;
+        }
+    };
+    sink.set_volume(0.0);
+    let source: Box<dyn Source<Item = i16> + Send> = match reverb {
+        true => Box::new(source.buffered().reverb(REVERB_DELAY, REVERB_AMOUNT)),
+        false => Box::new(source),
+    };
+    match looped {
+        true => sink.append(source.repeat_infinite()),
+        false => sink.append(source),
+    }
+    Some(sink)
+}
+
+// Whether there's a voxel above, unloaded chunks count as open sky
+pub fn is_underground(scene: &VoxelScene, position: IVec3) -> bool {
+    (1..=CAVE_CEILING).any(|height| {
+        scene
+            .voxel_at(&(position + IVec3::Y * height))
+            .map_or(false, |voxel| voxel.id != 0)
+    })
+}
@@ -0,0 +1,314 @@
+#[cfg(feature = "audio")]
+pub mod ambience;
+pub mod sound_material;
+
+#[cfg(feature = "audio")]
+use std::{collections::HashMap, fs, io::Cursor, sync::Arc};
+
+#[cfg(feature = "audio")]
+use flume::{Receiver, Sender};
+#[cfg(feature = "audio")]
+use glam::Quat;
+use glam::{IVec3, Vec3};
+#[cfg(feature = "audio")]
+use rand::seq::SliceRandom;
+#[cfg(feature = "audio")]
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Source, SpatialSink};
+#[cfg(feature = "audio")]
+use tracing::{debug, info, warn};
+
+use crate::voxels::voxel_data::VoxelData;
+#[cfg(feature = "audio")]
+use crate::{
+    data_packs::resource_files,
+    environment::camera_environment::{self, MUFFLED_CUTOFF, MUFFLED_VOLUME},
+    voxels::{voxel_registry::get_voxel_by_id, voxel_scene::VoxelScene},
+};
+
+#[cfg(feature = "audio")]
+use self::{
+    ambience::AmbiencePlayer,
+    sound_material::{SoundKind, SoundMaterial},
+};
+
+// Sounds at normal loudness further away than this aren't played
+pub const MAX_DISTANCE: f32 = 32.0;
+// Closer sounds play at full volume
+const REFERENCE_DISTANCE: f32 = 2.0;
+#[cfg(feature = "audio")]
+const EAR_DISTANCE: f32 = 0.2;
+// Sounds emitted faster than the frames play them are dropped
+#[cfg(feature = "audio")]
+const MAX_QUEUED: usize = 256;
+
+// Something in the world that makes a sound. Sounds come from the "sounds" resource folder,
+// voxel sounds come from the sound set of the kind and the voxel's sound material like
+// "break_stone" and fall back to the kind alone
+#[derive(Clone, Copy)]
+pub enum SoundEvent {
+    VoxelBroken { position: IVec3, voxel: VoxelData },
+    VoxelPlaced { position: IVec3, voxel: VoxelData },
+    // A step onto the ground voxel at the feet
+    Footstep { position: Vec3, ground: VoxelData },
+    // Louder explosions are heard further away
+    Explosion { position: Vec3, power: f32 },
+}
+
+impl SoundEvent {
+    // The sound sets to try in order, where it plays from and how far it carries relative to normal
+    #[cfg(feature = "audio")]
+    fn sound(&self) -> (Vec<String>, Vec3, f32) {
+        let center = |position: IVec3| position.as_vec3() + Vec3::splat(0.5);
+        match *self {
+            SoundEvent::VoxelBroken { position, voxel } => (
+                voxel_sound_sets(SoundKind::Break, voxel),
+                center(position),
+                1.0,
+            ),
+            SoundEvent::VoxelPlaced { position, voxel } => (
+                voxel_sound_sets(SoundKind::Place, voxel),
+                center(position),
+                1.0,
+            ),
+            SoundEvent::Footstep { position, ground } => {
+                (voxel_sound_sets(SoundKind::Step, ground), position, 0.5)
+            }
+            SoundEvent::Explosion { position, power } => (
+                vec!["explosion".to_string()],
+                position,
+                1.0 + power.max(0.0),
+            ),
+        }
+    }
+
+    // The sound of a voxel changing from previous, None unless it was broken or placed
+    pub fn for_edit(position: IVec3, previous: VoxelData, voxel: VoxelData) -> Option<Self> {
+        match (previous.id, voxel.id) {
+            (0, 0) => None,
+            (0, _) => Some(SoundEvent::VoxelPlaced { position, voxel }),
+            (_, 0) => Some(SoundEvent::VoxelBroken {
+                position,
+                voxel: previous,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+fn voxel_sound_sets(kind: SoundKind, voxel: VoxelData) -> Vec<String> {
+    let material = get_voxel_by_id(voxel.id).and_then(|profile| profile.sound_material);
+    SoundMaterial::sound_sets(material, kind)
+}
+
+// The sound set a file belongs to, its name without a variation number like "_2"
+#[cfg(feature = "audio")]
+fn set_name(name: &str) -> &str {
+    match name.rsplit_once('_') {
+        Some((set, number)) if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) => {
+            set
+        }
+        _ => name,
+    }
+}
+
+// Volume from the distance, full up close, falling off with distance and silent at MAX_DISTANCE
+pub fn attenuation(distance: f32) -> f32 {
+    if distance >= MAX_DISTANCE {
+        return 0.0;
+    }
+    let falloff = REFERENCE_DISTANCE / distance.max(REFERENCE_DISTANCE);
+    // Fades the last of the falloff out so sounds don't cut off at the edge
+    falloff * (1.0 - distance / MAX_DISTANCE)
+}
+
+#[cfg(feature = "audio")]
+lazy_static! {
+    static ref QUEUE: (Sender<SoundEvent>, Receiver<SoundEvent>) = flume::bounded(MAX_QUEUED);
+}
+
+// Plays the sound on the next frame, from any thread
+#[cfg(feature = "audio")]
+pub fn emit(event: SoundEvent) {
+    let _ = QUEUE.0.try_send(event);
+}
+
+#[cfg(not(feature = "audio"))]
+pub fn emit(_event: SoundEvent) {}
+
+// The files from the sounds folder, kept encoded and decoded each time they play
+#[cfg(feature = "audio")]
+pub struct SoundLibrary {
+    sounds: HashMap<String, Arc<[u8]>>,
+    // The variations of each sound set, "step_stone" holds "step_stone", "step_stone_1" and so on
+    sets: HashMap<String, Vec<String>>,
+}
+
+#[cfg(feature = "audio")]
+impl SoundLibrary {
+    pub fn load() -> Self {
+        let sounds = resource_files("sounds")
+            .into_iter()
+            .filter_map(|(name, path)| match fs::read(&path) {
+                Ok(bytes) => Some((name, Arc::from(bytes))),
+                Err(e) => {
+                    warn!("Failed to read {}: {e}", path.display());
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>();
+        info!("Loaded {} sounds", sounds.len());
+        let mut sets = HashMap::<String, Vec<String>>::new();
+        for name in sounds.keys() {
+            sets.entry(set_name(name).to_string())
+                .or_default()
+                .push(name.clone());
+        }
+        Self { sounds, sets }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sounds.contains_key(name)
+    }
+
+    // One of the set's variations at random, None if the set is empty
+    pub fn pick(&self, set: &str) -> Option<&str> {
+        self.sets
+            .get(set)?
+            .choose(&mut rand::thread_rng())
+            .map(String::as_str)
+    }
+
+    // None if there's no such sound or it can't be decoded
+    pub fn decode(&self, name: &str) -> Option<Decoder<Cursor<Arc<[u8]>>>> {
+        let sound = self.sounds.get(name)?;
+        match Decoder::new(Cursor::new(Arc::clone(sound))) {
+            Ok(source) => Some(source),
+            Err(e) => {
+                warn!("Failed to decode the sound {name}: {e}");
+                None
+            }
+        }
+    }
+}
+
+// Plays emitted sounds and the biome ambience from where the camera is. The output stream has to
+// stay on the thread that opened it, so this lives in the frame loop and everything else goes
+// through emit
+#[cfg(feature = "audio")]
+pub struct AudioEngine {
+    // None without an audio device, sounds are dropped then
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    sounds: SoundLibrary,
+    ambience: AmbiencePlayer,
+}
+
+#[cfg(feature = "audio")]
+impl AudioEngine {
+    pub fn new() -> Self {
+        let output = match OutputStream::try_default() {
+            Ok(output) => Some(output),
+            Err(e) => {
+                warn!("Playing without sound: {e}");
+                None
+            }
+        };
+        Self {
+            output,
+            sounds: SoundLibrary::load(),
+            ambience: AmbiencePlayer::new(),
+        }
+    }
+
+    // Plays everything emitted since the last frame and moves the ambience along
+    pub fn update(&mut self, listener: Vec3, rotation: Quat, scene: &VoxelScene) {
+        let handle = match &self.output {
+            Some((_, handle)) => handle,
+            None => {
+                // Nothing is heard, but the queue still has to be emptied
+                QUEUE.1.try_iter().for_each(drop);
+                return;
+            }
+        };
+        let muffled = camera_environment::current().muffled;
+        for event in QUEUE.1.try_iter() {
+            play(handle, &self.sounds, &event, listener, rotation, muffled);
+        }
+        self.ambience.update(handle, &self.sounds, listener, scene);
+    }
+}
+
+#[cfg(feature = "audio")]
+fn play(
+    handle: &OutputStreamHandle,
+    sounds: &SoundLibrary,
+    event: &SoundEvent,
+    listener: Vec3,
+    rotation: Quat,
+    muffled: bool,
+) {
+    let (sets, position, range) = event.sound();
+    let volume = attenuation(position.distance(listener) / range);
+    if volume <= 0.0 {
+        return;
+    }
+    let source = match sets.iter().find_map(|set| sounds.pick(set)) {
+        Some(name) => match sounds.decode(name) {
+            Some(source) => source,
+            None => return,
+        },
+        None => {
+            debug!("There is no sound for {sets:?}");
+            return;
+        }
+    };
+    // The emitter is put a block away in the sound's direction, so rodio only pans it between
+    // the ears and the volume does the distance falloff
+    let direction = rotation.inverse() * (position - listener).normalize_or_zero();
+    let ear = Vec3::X * EAR_DISTANCE / 2.0;
+    match SpatialSink::try_new(
+        handle,
+        direction.to_array(),
+        (-ear).to_array(),
+        ear.to_array(),
+    ) {
+        Ok(sink) => {
+            // Heard from under water or inside a wall
+            match muffled {
+                true => {
+                    sink.set_volume(volume * MUFFLED_VOLUME);
+                    sink.append(source.convert_samples::<f32>().low_pass(MUFFLED_CUTOFF));
+                }
+                false => {
+                    sink.set_volume(volume);
+                    sink.append(source);
+                }
+            }
+            sink.detach();
+        }
+        Err(e) => warn!("Failed to play the sound {}: {e}", sets[0]),
+    }
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+
+    #[test]
+    fn volume_falls_off_with_distance() {
+        assert_eq!(attenuation(0.0), 1.0);
+        assert!(attenuation(REFERENCE_DISTANCE) > attenuation(8.0));
+        assert!(attenuation(8.0) > attenuation(MAX_DISTANCE - 1.0));
+        assert!(attenuation(MAX_DISTANCE - 1.0) > 0.0);
+        assert_eq!(attenuation(MAX_DISTANCE), 0.0);
+    }
+
+    #[cfg(feature = "audio")]
+    #[test]
+    fn variations_belong_to_their_sound_set() {
+        assert_eq!(set_name("step_stone_2"), "step_stone");
+        assert_eq!(set_name("step_stone"), "step_stone");
+        assert_eq!(set_name("explosion"), "explosion");
+        assert_eq!(set_name("break_"), "break_");
+    }
+}
@@ -0,0 +1,96 @@
+use crate::error::AssemblageError;
+
+// What a voxel sounds like when it's broken, placed or walked on. Each material has a sound set
+// per kind, the files in the sounds folder named like "step_stone", "step_stone_1", "step_stone_2"
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SoundMaterial {
+    Stone,
+    Wood,
+    Grass,
+    Glass,
+    Dirt,
+    Sand,
+    Snow,
+    Slime,
+}
+
+impl SoundMaterial {
+    pub const ALL: [SoundMaterial; 8] = [
+        SoundMaterial::Stone,
+        SoundMaterial::Wood,
+        SoundMaterial::Grass,
+        SoundMaterial::Glass,
+        SoundMaterial::Dirt,
+        SoundMaterial::Sand,
+        SoundMaterial::Snow,
+        SoundMaterial::Slime,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SoundMaterial::Stone => "stone",
+            SoundMaterial::Wood => "wood",
+            SoundMaterial::Grass => "grass",
+            SoundMaterial::Glass => "glass",
+            SoundMaterial::Dirt => "dirt",
+            SoundMaterial::Sand => "sand",
+            SoundMaterial::Snow => "snow",
+            SoundMaterial::Slime => "slime",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, AssemblageError> {
+        Self::ALL
+            .into_iter()
+            .find(|material| material.name() == name)
+            .ok_or_else(|| AssemblageError::unknown("sound material", name))
+    }
+
+    // The sound sets to try in order, the material's own and then the one every material shares
+    pub fn sound_sets(material: Option<SoundMaterial>, kind: SoundKind) -> Vec<String> {
+        material
+            .map(|material| format!("{}_{}", kind.name(), material.name()))
+            .into_iter()
+            .chain([kind.name().to_string()])
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundKind {
+    Break,
+    Place,
+    Step,
+}
+
+impl SoundKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SoundKind::Break => "break",
+            SoundKind::Place => "place",
+            SoundKind::Step => "step",
+        }
+    }
+}
+
+#[cfg(test)]
+mod sound_material_tests {
+    use super::*;
+
+    #[test]
+    fn materials_pick_their_own_sound_set_first() {
+        assert_eq!(
+            SoundMaterial::from_name("wood").unwrap(),
+            SoundMaterial::Wood
+        );
+        assert!(SoundMaterial::from_name("velvet").is_err());
+        assert_eq!(
+            SoundMaterial::sound_sets(Some(SoundMaterial::Glass), SoundKind::Break),
+            vec!["break_glass", "break"]
+        );
+        assert_eq!(
+            SoundMaterial::sound_sets(None, SoundKind::Step),
+            vec!["step"]
+        );
+    }
+}
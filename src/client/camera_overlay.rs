@@ -0,0 +1,19 @@
+use egui::{Color32, LayerId, Rgba};
+
+use crate::{environment::camera_environment, rendering::ui};
+
+// Covers the view in the camera environment's overlay color, black inside solid voxels
+pub fn add_camera_overlay_panel() {
+    ui::add_panel("camera overlay", |context| {
+        let color = match camera_environment::current().overlay {
+            Some(color) => color,
+            None => return,
+        };
+        let color = Rgba::from_rgba_unmultiplied(color.x, color.y, color.z, color.w);
+        context.layer_painter(LayerId::background()).rect_filled(
+            context.input().screen_rect(),
+            0.0,
+            Color32::from(color),
+        );
+    });
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    mem,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+use wgpu_glyph::{Section, Text};
+use winit::event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent};
+
+use crate::{
+    config,
+    data_packs::{available_packs, set_texture_pack},
+    ecs::entities::observer::{self, PathControl},
+    error::WrongUsage,
+    input_actions,
+    network::messages::ServerMessage,
+    rendering::{
+        text::{TextLayer, FONT_SIZE},
+        texture_atlas,
+    },
+};
+
+use super::{
+    debug_views::{self, DebugView},
+    Client,
+};
+
+const MAX_OUTPUT_LINES: usize = 200;
+// Output lines shown above the input line
+const VISIBLE_LINES: usize = 16;
+const MAX_HISTORY: usize = 100;
+const OUTPUT_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+const INPUT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+pub type ConsoleHandler = Arc<dyn Fn(&ConsoleContext, &[&str]) -> Result<String> + Send + Sync>;
+
+// What client side commands run with
+pub struct ConsoleContext<'a> {
+    pub client: &'a Client,
+}
+
+#[derive(Clone)]
+pub struct ConsoleCommand {
+    pub name: String,
+    pub usage: String,
+    pub description: String,
+    // Offered by tab completion for the first argument
+    pub completions: Vec<String>,
+    pub handler: ConsoleHandler,
+}
+
+impl ConsoleCommand {
+    pub fn new(
+        usage: &str,
+        description: &str,
+        handler: impl Fn(&ConsoleContext, &[&str]) -> Result<String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: usage
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            usage: usage.to_string(),
+            description: description.to_string(),
+            completions: Vec::new(),
+            handler: Arc::new(handler),
+        }
+    }
+
+    pub fn with_completions(mut self, completions: impl IntoIterator<Item = String>) -> Self {
+        self.completions = completions.into_iter().collect();
+        self
+    }
+}
+
+// The drop-down console, opened and closed with the key left of 1. Commands registered here run
+// on the client, every other line is sent to the server as a chat command
+pub struct Console {
+    pub open: bool,
+    input: String,
+    output: VecDeque<String>,
+    history: Vec<String>,
+    // The history entry shown while browsing with the arrow keys
+    history_index: Option<usize>,
+    commands: BTreeMap<String, ConsoleCommand>,
+    // The server's command names, only used for completion
+    server_commands: Vec<String>,
+    modifiers: ModifiersState,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        let mut console = Self {
+            open: false,
+            input: String::new(),
+            output: VecDeque::new(),
+            history: Vec::new(),
+            history_index: None,
+            commands: BTreeMap::new(),
+            server_commands: Vec::new(),
+            modifiers: ModifiersState::empty(),
+        };
+        console.register(
+            ConsoleCommand::new(
+                "debug [view]",
+                "Toggles a debug view, lists them without one",
+                debug,
+            )
+            .with_completions(DebugView::ALL.map(|view| view.name().to_string())),
+        );
+        console.register(
+            ConsoleCommand::new(
+                "bind [action] [inputs...]",
+                "Binds an action to keys or buttons, shows the bindings without inputs",
+                bind,
+            )
+            .with_completions(input_actions::actions()),
+        );
+        console.register(
+            ConsoleCommand::new(
+                "path <play|pause|resume|stop|seek|speed|loop|step|key|clear|save> [value]",
+                "Flies the camera along a camera path, play without a name plays the keyframes taken with key",
+                camera_path,
+            )
+            .with_completions(
+                ["play", "pause", "resume", "stop", "seek", "speed", "loop", "step", "key", "clear", "save"]
+                    .map(str::to_string),
+            ),
+        );
+        console.register(
+            ConsoleCommand::new(
+                "texturepack <name|off>",
+                "Draws the voxels with the textures of a pack, off goes back to the world's packs",
+                texture_pack,
+            )
+            .with_completions(available_packs(&config::current().resources.packs)),
+        );
+        console
+    }
+
+    // A command with the same name as a server command is run here instead
+    pub fn register(&mut self, command: ConsoleCommand) {
+        self.commands.insert(command.name.clone(), command);
+    }
+
+    pub fn register_command(
+        &mut self,
+        usage: &str,
+        description: &str,
+        handler: impl Fn(&ConsoleContext, &[&str]) -> Result<String> + Send + Sync + 'static,
+    ) {
+        self.register(ConsoleCommand::new(usage, description, handler));
+    }
+
+    pub fn set_server_commands(&mut self, names: Vec<String>) {
+        self.server_commands = names;
+    }
+
+    pub fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            if self.output.len() == MAX_OUTPUT_LINES {
+                self.output.pop_front();
+            }
+            self.output.push_back(line.to_string());
+        }
+    }
+
+    // Returns true when the console used the event, the game shouldn't see those
+    pub fn handle_event(&mut self, event: &WindowEvent, context: &ConsoleContext) -> bool {
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.handle_key(*key, context),
+            WindowEvent::KeyboardInput { .. } => self.open,
+            // The toggle key's own character isn't typed
+            WindowEvent::ReceivedCharacter(c) if self.open => {
+                if !c.is_control() && *c != '`' {
+                    self.input.push(*c);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn handle_key(&mut self, key: VirtualKeyCode, context: &ConsoleContext) -> bool {
+        // Shift with the same key types ~ for relative coordinates
+        if key == VirtualKeyCode::Grave && !self.modifiers.shift() {
+            self.open = !self.open;
+            return true;
+        }
+        if !self.open {
+            return false;
+        }
+        match key {
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => self.submit(context),
+            VirtualKeyCode::Tab => self.complete(),
+            VirtualKeyCode::Back => {
+                self.input.pop();
+            }
+            VirtualKeyCode::Up => self.browse_history(-1),
+            VirtualKeyCode::Down => self.browse_history(1),
+            VirtualKeyCode::Escape => self.open = false,
+            _ => {}
+        }
+        true
+    }
+
+    fn submit(&mut self, context: &ConsoleContext) {
+        let line = mem::take(&mut self.input);
+        self.history_index = None;
+        if line.trim().is_empty() {
+            return;
+        }
+        self.print(&format!("> {line}"));
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+            if self.history.len() > MAX_HISTORY {
+                self.history.remove(0);
+            }
+        }
+        if let Err(e) = self.execute(context, &line) {
+            self.print(&e.to_string());
+        }
+    }
+
+    pub fn execute(&mut self, context: &ConsoleContext, line: &str) -> Result<()> {
+        let args = split_arguments(line)?;
+        let (name, args) = args
+            .split_first()
+            .ok_or_else(|| anyhow!("No command given"))?;
+        let command = match self.commands.get(name.as_str()) {
+            Some(command) => command.clone(),
+            None => {
+                // The reply arrives as chat
+                context.client.send_chat(&format!("/{}", line.trim()));
+                return Ok(());
+            }
+        };
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        match (command.handler)(context, &args) {
+            Ok(output) => self.print(&output),
+            Err(e) if e.is::<WrongUsage>() => self.print(&format!("Usage: {}", command.usage)),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    // Shows chat and command output from the server, every other message is returned
+    pub fn receive_chat(&mut self, messages: Vec<ServerMessage>) -> Vec<ServerMessage> {
+        let mut remaining = Vec::new();
+        for message in messages {
+            match message {
+                ServerMessage::Chat {
+                    sender: Some(sender),
+                    text,
+                } => self.print(&format!("<{sender}> {text}")),
+                ServerMessage::Chat { sender: None, text } => self.print(&text),
+                other => remaining.push(other),
+            }
+        }
+        remaining
+    }
+
+    fn browse_history(&mut self, step: isize) {
+        if self.history.is_empty() {
+            return;
+        }
+        let last = self.history.len() as isize - 1;
+        let index = match self.history_index {
+            Some(index) => index as isize + step,
+            None if step < 0 => last,
+            None => return,
+        };
+        match index {
+            index if index < 0 => {}
+            index if index > last => {
+                self.history_index = None;
+                self.input.clear();
+            }
+            index => {
+                self.history_index = Some(index as usize);
+                self.input = self.history[index as usize].clone();
+            }
+        }
+    }
+
+    // Completes the command name, or the first argument of a console command. With several
+    // matches the input is completed as far as they agree and the matches are listed
+    fn complete(&mut self) {
+        let words = self.input.split(' ').collect::<Vec<_>>();
+        let candidates = match words.as_slice() {
+            [_] => self
+                .commands
+                .keys()
+                .chain(self.server_commands.iter())
+                .cloned()
+                .collect::<Vec<_>>(),
+            [name, _] => self
+                .commands
+                .get(*name)
+                .map_or_else(Vec::new, |command| command.completions.clone()),
+            _ => return,
+        };
+        let partial = words[words.len() - 1];
+        let matches = candidates
+            .iter()
+            .filter(|candidate| candidate.starts_with(partial))
+            .collect::<Vec<_>>();
+        let completed = match matches.as_slice() {
+            [] => return,
+            [only] => format!("{only} "),
+            _ => {
+                let listed = matches.iter().map(|m| m.as_str()).collect::<Vec<_>>();
+                self.print(&listed.join("  "));
+                common_prefix(&listed).to_string()
+            }
+        };
+        self.input.truncate(self.input.len() - partial.len());
+        self.input.push_str(&completed);
+    }
+
+    pub fn queue_text(&self, text: &mut TextLayer) {
+        if !self.open {
+            return;
+        }
+        let visible = self.output.len().saturating_sub(VISIBLE_LINES);
+        let output = self
+            .output
+            .iter()
+            .skip(visible)
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+        let input = format!("> {}_", self.input);
+        text.queue(Section {
+            screen_position: (10.0, 10.0),
+            text: vec![
+                Text::new(&output)
+                    .with_color(OUTPUT_COLOR)
+                    .with_scale(FONT_SIZE),
+                Text::new(&input)
+                    .with_color(INPUT_COLOR)
+                    .with_scale(FONT_SIZE),
+            ],
+            ..Section::default()
+        });
+    }
+}
+
+fn debug(_context: &ConsoleContext, args: &[&str]) -> Result<String> {
+    let state = |enabled| if enabled { "on" } else { "off" };
+    match args {
+        [] => Ok(DebugView::ALL
+            .iter()
+            .map(|view| format!("{} {}", view.name(), state(debug_views::is_enabled(*view))))
+            .collect::<Vec<_>>()
+            .join("\n")),
+        [name] => {
+            let view =
+                DebugView::from_name(name).ok_or_else(|| anyhow!("Unknown debug view {name}"))?;
+            Ok(format!("{name} is {}", state(debug_views::toggle(view))))
+        }
+        _ => bail!(WrongUsage),
+    }
+}
+
+fn bind(_context: &ConsoleContext, args: &[&str]) -> Result<String> {
+    let describe = |action: &str| {
+        let inputs = input_actions::bindings(action).unwrap_or_default();
+        format!("{action}: {}", inputs.join(" "))
+    };
+    match args {
+        [] => Ok(input_actions::actions()
+            .iter()
+            .map(|action| describe(action))
+            .collect::<Vec<_>>()
+            .join("\n")),
+        [action] => match input_actions::bindings(action) {
+            Some(_) => Ok(describe(action)),
+            None => bail!("Nothing is bound to {action}"),
+        },
+        [action, inputs @ ..] => {
+            input_actions::rebind(action, inputs)?;
+            Ok(describe(&action.to_lowercase()))
+        }
+    }
+}
+
+fn camera_path(_context: &ConsoleContext, args: &[&str]) -> Result<String> {
+    let number = |text: &str| {
+        text.parse::<f32>()
+            .map_err(|_| anyhow!("{text} isn't a number"))
+    };
+    let (control, reply) = match args {
+        ["play"] => (
+            PathControl::Play(observer::recorded_path()),
+            "Playing the keyframes".to_string(),
+        ),
+        ["play", name] => (
+            PathControl::Play(observer::load_camera_path(name)?),
+            format!("Playing {name}"),
+        ),
+        ["pause"] => (PathControl::Pause, "Paused".to_string()),
+        ["resume"] => (PathControl::Resume, "Resumed".to_string()),
+        ["stop"] => (PathControl::Stop, "Stopped".to_string()),
+        ["seek", time] => (
+            PathControl::Seek(number(time)?),
+            format!("Jumped to {time}s"),
+        ),
+        ["speed", speed] => (
+            PathControl::Speed(number(speed)?),
+            format!("Playing at {speed}x"),
+        ),
+        ["loop", "on"] => (PathControl::Loop(true), "Looping".to_string()),
+        ["loop", "off"] => (PathControl::Loop(false), "Not looping".to_string()),
+        // For recordings, every frame advances the path by the step whatever the frame time
+        ["step", "off"] => (
+            PathControl::FixedStep(None),
+            "Following the frame time".to_string(),
+        ),
+        ["step", step] => (
+            PathControl::FixedStep(Some(number(step)?)),
+            format!("Advancing {step}s a frame"),
+        ),
+        // Without a time the keyframe comes two seconds after the last one
+        ["key"] => {
+            let time = match observer::recorded_path().keyframes.last() {
+                Some(last) => last.time + 2.0,
+                None => 0.0,
+            };
+            (PathControl::Keyframe(time), format!("Keyframe at {time}s"))
+        }
+        ["key", time] => (
+            PathControl::Keyframe(number(time)?),
+            format!("Keyframe at {time}s"),
+        ),
+        ["clear"] => {
+            observer::clear_recording();
+            return Ok("Cleared the keyframes".to_string());
+        }
+        ["save", name] => {
+            let file = observer::save_camera_path(name, &observer::recorded_path())?;
+            return Ok(format!("Saved the keyframes to {}", file.display()));
+        }
+        _ => bail!(WrongUsage),
+    };
+    observer::control(control);
+    Ok(reply)
+}
+
+fn texture_pack(_context: &ConsoleContext, args: &[&str]) -> Result<String> {
+    let reply = match args {
+        ["off"] => {
+            set_texture_pack(None)?;
+            "Using the world's textures".to_string()
+        }
+        [name] => {
+            set_texture_pack(Some(name.to_string()))?;
+            format!("Using the textures of {name}")
+        }
+        _ => bail!(WrongUsage),
+    };
+    texture_atlas::request_rebuild();
+    Ok(reply)
+}
+
+// Splits on whitespace, text in double quotes stays together
+pub fn split_arguments(line: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let (mut quoted, mut in_word) = (false, false);
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    args.push(mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quoted {
+        bail!("A quote isn't closed");
+    }
+    if in_word {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+// Compared a character at a time and cut at a byte offset, so multi-byte characters aren't split
+fn common_prefix<'a>(words: &[&'a str]) -> &'a str {
+    let first = words[0];
+    let end = words[1..].iter().fold(first.len(), |end, word| {
+        first[..end]
+            .char_indices()
+            .zip(word.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(end.min(word.len()), |((i, _), _)| i)
+    });
+    &first[..end]
+}
+
+#[cfg(test)]
+mod console_tests {
+    use super::*;
+
+    #[test]
+    fn quoted_arguments_stay_together() {
+        assert_eq!(
+            split_arguments(r#"say "hello there"  world"#).unwrap(),
+            vec!["say", "hello there", "world"]
+        );
+        assert!(split_arguments(r#"say "hello"#).is_err());
+    }
+
+    #[test]
+    fn completion_stops_where_matches_differ() {
+        assert_eq!(common_prefix(&["regen", "reload", "redo"]), "re");
+        assert_eq!(common_prefix(&["tp"]), "tp");
+        assert_eq!(common_prefix(&["grüße", "grün", "grüne"]), "grü");
+        assert_eq!(common_prefix(&["ünder", "ü"]), "ü");
+        assert_eq!(common_prefix(&["é", "e"]), "");
+    }
+}
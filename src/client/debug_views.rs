@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Debug drawing and tools that can be switched on and off from the console
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    // Lights carried by entities, off shows the scene with sky light only
+    DynamicLights,
+    // The schematic copy and paste keys, see schematic_debug_tools
+    SchematicTools,
+    // Frame times, position and engine stats, also toggled with F3
+    Overlay,
+    // The editor brush keys, see brush_tools
+    Brushes,
+    // Skips entities the camera can't see, off draws every entity to compare
+    EntityCulling,
+    // Colors the terrain by the light reaching it, see heatmaps
+    LightHeatmap,
+    // Colors each chunk by how many triangles its mesh has
+    TriangleHeatmap,
+    // Colors each chunk by how long it took to generate, loaded chunks are grey
+    GenerationHeatmap,
+    // Bytes and packets of each connection by message category
+    Network,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 9] = [
+        DebugView::DynamicLights,
+        DebugView::SchematicTools,
+        DebugView::Overlay,
+        DebugView::Brushes,
+        DebugView::EntityCulling,
+        DebugView::LightHeatmap,
+        DebugView::TriangleHeatmap,
+        DebugView::GenerationHeatmap,
+        DebugView::Network,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::DynamicLights => "lights",
+            DebugView::SchematicTools => "schematic",
+            DebugView::Overlay => "overlay",
+            DebugView::Brushes => "brushes",
+            DebugView::EntityCulling => "culling",
+            DebugView::LightHeatmap => "lightmap",
+            DebugView::TriangleHeatmap => "triangles",
+            DebugView::GenerationHeatmap => "gentime",
+            DebugView::Network => "network",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|view| view.name() == name)
+    }
+}
+
+static ENABLED: [AtomicBool; DebugView::ALL.len()] = [
+    AtomicBool::new(true),
+    AtomicBool::new(true),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(true),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+pub fn is_enabled(view: DebugView) -> bool {
+    ENABLED[view as usize].load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(view: DebugView, enabled: bool) {
+    ENABLED[view as usize].store(enabled, Ordering::Relaxed);
+}
+
+// Returns whether the view is now enabled
+pub fn toggle(view: DebugView) -> bool {
+    !ENABLED[view as usize].fetch_xor(true, Ordering::Relaxed)
+}
@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use glam::{IVec3, Vec3};
+
+use crate::{asset_types::mesh::Mesh, voxels::voxel_scene::VoxelScene};
+
+use super::debug_views::{self, DebugView};
+
+// Chunks with this many triangles or more are drawn the hottest
+const TRIANGLE_SCALE: f32 = 8192.0;
+// Chunks that took this many milliseconds or more to generate are drawn the hottest
+const GENERATION_MS_SCALE: f32 = 20.0;
+const NOT_GENERATED: Vec3 = Vec3::new(0.4, 0.4, 0.4);
+
+// The heatmap the chunk meshes were last colored for, 0 when there was none
+static SHOWN: AtomicU8 = AtomicU8::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Heatmap {
+    // Drawn by the terrain shader, since moving lights change it every frame
+    Light,
+    Triangles,
+    GenerationTime,
+}
+
+// Only one heatmap is drawn at a time, the first one switched on in this order
+pub fn active() -> Option<Heatmap> {
+    [
+        (DebugView::LightHeatmap, Heatmap::Light),
+        (DebugView::TriangleHeatmap, Heatmap::Triangles),
+        (DebugView::GenerationHeatmap, Heatmap::GenerationTime),
+    ]
+    .into_iter()
+    .find(|(view, _)| debug_views::is_enabled(*view))
+    .map(|(_, heatmap)| heatmap)
+}
+
+// Blue through green and yellow to red as t goes from 0 to 1, the shader has the same ramp
+pub fn heat_color(t: f32) -> Vec3 {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    Vec3::new(
+        1.5 - (t - 3.0).abs(),
+        1.5 - (t - 2.0).abs(),
+        1.5 - (t - 1.0).abs(),
+    )
+    .clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+// Paints a chunk mesh in the color of the active per chunk heatmap, leaving it alone otherwise
+pub fn colorize(mesh: &mut Mesh, chunk: IVec3, scene: &VoxelScene) {
+    let color = match active() {
+        Some(Heatmap::Triangles) => heat_color(mesh.index_count as f32 / 3.0 / TRIANGLE_SCALE),
+        Some(Heatmap::GenerationTime) => {
+            scene.generation_time(&chunk).map_or(NOT_GENERATED, |time| {
+                heat_color(time.as_secs_f32() * 1000.0 / GENERATION_MS_SCALE)
+            })
+        }
+        _ => return,
+    };
+    let mut vertices = mesh.get_vertices().clone();
+    for vertex in &mut vertices {
+        vertex.color = color.extend(1.0).to_array();
+    }
+    mesh.set_vertices(vertices);
+}
+
+// Remeshes every loaded chunk when a different heatmap is switched on, or the last one off, so
+// the meshes are colored again. Called once a frame
+pub fn refresh(scene: &VoxelScene) {
+    let shown = active().map_or(0, |heatmap| heatmap as u8 + 1);
+    if SHOWN.swap(shown, Ordering::Relaxed) == shown {
+        return;
+    }
+    scene.remesh_all();
+}
+
+#[cfg(test)]
+mod heatmaps_tests {
+    use super::*;
+
+    #[test]
+    fn the_ramp_runs_from_blue_to_red() {
+        assert_eq!(heat_color(0.0), Vec3::new(0.0, 0.0, 0.5));
+        assert_eq!(heat_color(0.5), Vec3::new(0.5, 1.0, 0.5));
+        assert_eq!(heat_color(1.0), Vec3::new(0.5, 0.0, 0.0));
+        assert_eq!(heat_color(4.0), heat_color(1.0));
+    }
+}
@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use egui::{Color32, LayerId, Pos2, Rect, Shape, Stroke};
+use glam::{Mat4, Vec2, Vec3, Vec4Swizzles};
+use parking_lot::RwLock;
+
+use crate::{
+    rendering::{camera::Camera, ui},
+    targeting,
+    voxels::{
+        voxel_breaking::crack_stages,
+        voxel_mesh::{get_voxel_mesh, orient},
+        voxel_shapes::voxel_directions,
+    },
+};
+
+const OUTLINE_COLOR: Color32 = Color32::from_rgba_premultiplied(20, 20, 20, 200);
+const OUTLINE_WIDTH: f32 = 2.0;
+// Slightly bigger than the voxel so the faces don't hide the lines
+const OUTLINE_EXTENT: f32 = 0.502;
+const VALID_GHOST_COLOR: Color32 = Color32::from_rgba_premultiplied(40, 140, 40, 90);
+const INVALID_GHOST_COLOR: Color32 = Color32::from_rgba_premultiplied(160, 30, 30, 90);
+const CRACK_COLOR: Color32 = Color32::from_rgba_premultiplied(10, 10, 10, 220);
+const CRACK_WIDTH: f32 = 1.5;
+// Lines across a face from its center, each crack stage draws one more. Both coordinates run
+// from -0.5 to 0.5 over the face
+const CRACK_SEGMENTS: [(Vec2, Vec2); 10] = [
+    (Vec2::new(0.0, 0.0), Vec2::new(0.2, 0.1)),
+    (Vec2::new(0.0, 0.0), Vec2::new(-0.15, 0.2)),
+    (Vec2::new(0.0, 0.0), Vec2::new(-0.1, -0.2)),
+    (Vec2::new(0.2, 0.1), Vec2::new(0.35, 0.3)),
+    (Vec2::new(-0.15, 0.2), Vec2::new(-0.3, 0.15)),
+    (Vec2::new(-0.1, -0.2), Vec2::new(0.05, -0.38)),
+    (Vec2::new(0.2, 0.1), Vec2::new(0.4, -0.1)),
+    (Vec2::new(0.35, 0.3), Vec2::new(0.45, 0.48)),
+    (Vec2::new(-0.3, 0.15), Vec2::new(-0.42, 0.35)),
+    (Vec2::new(0.05, -0.38), Vec2::new(0.25, -0.45)),
+];
+
+// Projects world positions onto the screen with the camera of the current frame
+struct Projector {
+    view_projection: Mat4,
+    znear: f32,
+    screen: Rect,
+}
+
+impl Projector {
+    fn new(camera: &Camera, screen: Rect) -> Self {
+        Self {
+            view_projection: camera.build_projection_matrix() * camera.build_transform_matrix(),
+            znear: camera.znear,
+            screen,
+        }
+    }
+
+    // None for points behind the camera
+    fn project(&self, point: Vec3) -> Option<Pos2> {
+        let clip = self.view_projection * point.extend(1.0);
+        if clip.w <= self.znear {
+            return None;
+        }
+        let ndc = clip.xy() / clip.w;
+        Some(Pos2::new(
+            self.screen.left() + (ndc.x + 1.0) / 2.0 * self.screen.width(),
+            self.screen.top() + (1.0 - ndc.y) / 2.0 * self.screen.height(),
+        ))
+    }
+}
+
+// Outlines the targeted voxel, drawn under the UI windows
+pub fn add_highlight_panel(camera: Arc<RwLock<Camera>>) {
+    ui::add_panel("highlight", move |context| {
+        let voxel = match targeting::targeted_voxel() {
+            Some(voxel) => voxel,
+            None => return,
+        };
+        let projector = Projector::new(&camera.read(), context.input().screen_rect());
+        // Corners are numbered by their bits, x is the lowest. Edges join corners one bit apart
+        let corners = (0..8)
+            .map(|i| {
+                let sign = Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32);
+                projector.project(voxel.as_vec3() + (sign * 2.0 - 1.0) * OUTLINE_EXTENT)
+            })
+            .collect::<Vec<_>>();
+        let painter = context.layer_painter(LayerId::background());
+        for a in 0..8 {
+            for bit in [1, 2, 4] {
+                let b = a | bit;
+                if a == b {
+                    continue;
+                }
+                if let (Some(from), Some(to)) = (corners[a], corners[b]) {
+                    painter.line_segment([from, to], Stroke::new(OUTLINE_WIDTH, OUTLINE_COLOR));
+                }
+            }
+        }
+    });
+}
+
+// Draws the cracks of every voxel the player is partway through breaking on the faces turned
+// towards the camera, with more lines the further along it is
+pub fn add_crack_panel(camera: Arc<RwLock<Camera>>) {
+    ui::add_panel("cracks", move |context| {
+        let cracks = crack_stages();
+        if cracks.is_empty() {
+            return;
+        }
+        let camera = camera.read();
+        let eye = camera.position;
+        let projector = Projector::new(&camera, context.input().screen_rect());
+        let painter = context.layer_painter(LayerId::background());
+        for (voxel, stage) in cracks {
+            let center = voxel.as_vec3();
+            for direction in voxel_directions::ALL {
+                let normal = direction.as_vec().as_vec3();
+                let face = center + normal * OUTLINE_EXTENT;
+                if normal.dot(face - eye) >= 0.0 {
+                    continue;
+                }
+                // Any two axes along the face will do, the pattern has no up
+                let u = Vec3::new(normal.y.abs() + normal.z.abs(), normal.x.abs(), 0.0);
+                let v = normal.cross(u);
+                let point = |p: Vec2| projector.project(face + u * p.x + v * p.y);
+                for (from, to) in &CRACK_SEGMENTS[..=stage as usize] {
+                    if let (Some(from), Some(to)) = (point(*from), point(*to)) {
+                        painter.line_segment([from, to], Stroke::new(CRACK_WIDTH, CRACK_COLOR));
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Draws the voxel about to be placed as a see-through ghost with its shape and orientation,
+// green when it can be placed there and red when it can't
+pub fn add_placement_preview_panel(camera: Arc<RwLock<Camera>>) {
+    ui::add_panel("placement preview", move |context| {
+        let preview = match targeting::placement_preview() {
+            Some(preview) => preview,
+            None => return,
+        };
+        let camera = camera.read();
+        let eye = camera.position;
+        let projector = Projector::new(&camera, context.input().screen_rect());
+        let color = match preview.valid {
+            true => VALID_GHOST_COLOR,
+            false => INVALID_GHOST_COLOR,
+        };
+        let shape = preview.voxel.shape;
+        let center = preview.position.as_vec3();
+        let mesh = get_voxel_mesh(shape);
+        let painter = context.layer_painter(LayerId::background());
+        // Every face is drawn, nothing around the ghost hides them. Faces turned away from the
+        // camera are left out so the overlapping ones don't look darker
+        for part in [
+            &mesh.always,
+            &mesh.north,
+            &mesh.south,
+            &mesh.east,
+            &mesh.west,
+            &mesh.top,
+            &mesh.bottom,
+        ] {
+            let vertices = part.get_vertices();
+            for triangle in part.get_indices().chunks_exact(3) {
+                let corners = triangle
+                    .iter()
+                    .map(|index| center + orient(shape, vertices[*index as usize].position.into()))
+                    .collect::<Vec<_>>();
+                let normal = orient(shape, vertices[triangle[0] as usize].normal.into());
+                if normal.dot(corners[0] - eye) >= 0.0 {
+                    continue;
+                }
+                let points = corners
+                    .iter()
+                    .map(|corner| projector.project(*corner))
+                    .collect::<Option<Vec<_>>>();
+                if let Some(points) = points {
+                    painter.add(Shape::convex_polygon(points, color, Stroke::none()));
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,69 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use egui::{ColorImage, TextureHandle};
+use glam::{IVec2, Vec3};
+use parking_lot::RwLock;
+
+use crate::{
+    map::{MapImage, WorldMap},
+    rendering::{camera::Camera, ui},
+    voxels::voxel_scene::{VoxelScene, CHUNK_SIZE},
+};
+
+// Chunk columns shown in each direction around the camera
+const MINIMAP_RADIUS: i32 = 4;
+// Rendering reads every chunk around the camera, so it isn't done every frame
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const MARKER_COLOR: [u8; 4] = [255, 40, 40, 255];
+
+// A collapsible map window around the camera, only rendered while it's open
+pub fn add_minimap_panel(scene: Arc<RwLock<VoxelScene>>, camera: Arc<RwLock<Camera>>) {
+    let mut texture: Option<TextureHandle> = None;
+    let mut refreshed = Instant::now();
+    ui::add_panel("minimap", move |context| {
+        egui::Window::new("Map")
+            .default_open(false)
+            .resizable(false)
+            .show(context, |ui| {
+                if texture.is_none() || refreshed.elapsed() >= REFRESH_INTERVAL {
+                    let image = render_around(&scene.read(), camera.read().position);
+                    let image = ColorImage::from_rgba_unmultiplied(
+                        [image.width as usize, image.height as usize],
+                        &image.pixels,
+                    );
+                    match &mut texture {
+                        Some(texture) => texture.set(image),
+                        None => texture = Some(ui.ctx().load_texture("minimap", image)),
+                    }
+                    refreshed = Instant::now();
+                }
+                if let Some(texture) = &texture {
+                    ui.image(texture, texture.size_vec2() * 2.0);
+                }
+            });
+    });
+}
+
+fn render_around(scene: &VoxelScene, position: Vec3) -> MapImage {
+    let size = CHUNK_SIZE as i32;
+    let voxel = IVec2::new(position.x.floor() as i32, position.z.floor() as i32);
+    let center = IVec2::new(voxel.x.div_floor(size), voxel.y.div_floor(size));
+    let mut map = WorldMap::new();
+    map.add_loaded(scene, Some((center, MINIMAP_RADIUS)));
+    let min = center - IVec2::splat(MINIMAP_RADIUS);
+    let mut image = map.render_region(min, center + IVec2::splat(MINIMAP_RADIUS));
+
+    // Marks the camera with a small cross
+    let pixel = voxel - min * size;
+    for offset in [IVec2::ZERO, IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y] {
+        let (x, y) = ((pixel + offset).x as u32, (pixel + offset).y as u32);
+        if x < image.width && y < image.height {
+            let start = ((y * image.width + x) * 4) as usize;
+            image.pixels[start..start + 4].copy_from_slice(&MARKER_COLOR);
+        }
+    }
+    image
+}
@@ -0,0 +1,326 @@
+pub mod camera_overlay;
+pub mod console;
+pub mod debug_views;
+pub mod heatmaps;
+pub mod highlight;
+pub mod minimap;
+pub mod network_panel;
+pub mod overlay;
+pub mod prediction;
+pub mod progress_panel;
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use glam::IVec3;
+use legion::{Entity, IntoQuery};
+use parking_lot::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    audio::{self, SoundEvent},
+    ecs::{
+        components::{
+            network_components::RemoteEntity,
+            player_components::PlayerId,
+            transformation_components::{Position, Rotation},
+        },
+        entities::player::{find_player, set_game_mode},
+    },
+    network::{
+        chunk_stream::decode_chunk,
+        connection::Connection,
+        handshake::{JoinRequest, PROTOCOL_VERSION},
+        interpolation::{InterpolationBuffer, INTERPOLATION_DELAY},
+        messages::{ClientMessage, ServerMessage, MAX_CHAT_LENGTH},
+    },
+    voxels::{voxel_data::VoxelData, voxel_scene::VoxelScene},
+};
+
+use self::prediction::EditPredictor;
+
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The client side of a connection, rendering and input live in the frame loop and
+// only reach the server through the messages sent from here
+pub struct Client {
+    connection: Connection,
+    pub player_id: PlayerId,
+    // The revision of every streamed chunk, used to notice missed deltas
+    chunk_revisions: Mutex<HashMap<IVec3, u32>>,
+    // Replicated entities by their server id
+    remote_entities: Mutex<HashMap<u64, Entity>>,
+    // The last server time received and when it arrived
+    server_clock: Mutex<Option<(f64, Instant)>>,
+    predictor: Mutex<EditPredictor>,
+}
+
+impl Client {
+    // Waits for the server to accept the player, returns the client and the server's reply
+    pub fn join(
+        connection: Connection,
+        player_id: PlayerId,
+        secret: u128,
+    ) -> Result<(Self, ServerMessage)> {
+        connection.send(&ClientMessage::Join {
+            protocol_version: PROTOCOL_VERSION,
+            request: Some(JoinRequest::new(player_id, secret)),
+        })?;
+        let reply = connection.recv_timeout::<ServerMessage>(JOIN_TIMEOUT)?;
+        match &reply {
+            ServerMessage::JoinAccepted { world_name, .. } => {
+                info!("Joined {world_name}")
+            }
+            ServerMessage::Disconnected { reason } => {
+                bail!("The server refused to let the player join: {reason}")
+            }
+            _ => bail!("The server replied to the join with an unexpected message"),
+        }
+        Ok((
+            Self {
+                connection,
+                player_id,
+                chunk_revisions: Mutex::new(HashMap::new()),
+                remote_entities: Mutex::new(HashMap::new()),
+                server_clock: Mutex::new(None),
+                predictor: Mutex::new(EditPredictor::new()),
+            },
+            reply,
+        ))
+    }
+
+    // Messages are dropped with a warning once the server is gone, the frame loop keeps running
+    pub fn send(&self, message: ClientMessage) {
+        if let Err(e) = self.connection.send(&message) {
+            warn!("{e}");
+        }
+    }
+
+    // Remote clients show the edit right away, a local client's scene is the server's own
+    // so the edit appears once the server applies it
+    pub fn break_voxel(&self, scene: &VoxelScene, position: IVec3) {
+        let mut predictor = self.predictor.lock();
+        let sequence = predictor.next_sequence();
+        if !self.connection.is_local {
+            if let Some(voxel) = scene.voxel_at(&position) {
+                predictor.predict(scene, sequence, position, VoxelData { id: 0, ..voxel });
+            }
+        }
+        self.send(ClientMessage::BreakVoxel { sequence, position });
+    }
+
+    // Survival breaks are only accepted once the voxel's break time has passed since this was sent
+    pub fn start_break(&self, position: IVec3) {
+        self.send(ClientMessage::StartBreak(position));
+    }
+
+    pub fn place_voxel(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        let mut predictor = self.predictor.lock();
+        let sequence = predictor.next_sequence();
+        if !self.connection.is_local {
+            predictor.predict(scene, sequence, position, voxel);
+        }
+        self.send(ClientMessage::PlaceVoxel {
+            sequence,
+            position,
+            voxel,
+        });
+    }
+
+    // Text starting with a slash runs as a command on the server
+    pub fn send_chat(&self, text: &str) {
+        let text = text
+            .trim()
+            .chars()
+            .take(MAX_CHAT_LENGTH)
+            .collect::<String>();
+        if !text.is_empty() {
+            self.send(ClientMessage::Chat(text));
+        }
+    }
+
+    // Prints chat and command output, every other message is returned
+    pub fn receive_chat(&self, messages: Vec<ServerMessage>) -> Vec<ServerMessage> {
+        let mut remaining = Vec::new();
+        for message in messages {
+            match message {
+                ServerMessage::Chat {
+                    sender: Some(sender),
+                    text,
+                } => info!(target: "chat", "<{sender}> {text}"),
+                ServerMessage::Chat { sender: None, text } => text
+                    .lines()
+                    .for_each(|line| info!(target: "chat", "{line}")),
+                other => remaining.push(other),
+            }
+        }
+        remaining
+    }
+
+    // Returns every message that has arrived since the last call
+    pub fn poll(&self) -> Result<Vec<ServerMessage>> {
+        let mut messages = Vec::new();
+        while let Some(message) = self.connection.try_recv()? {
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    // Applies streamed chunks, voxel deltas and rejected edits to the client's scene, every other message is returned.
+    // Chunks that fail to decode aren't acknowledged, so the server sends them again
+    pub fn receive_chunks(
+        &self,
+        scene: &VoxelScene,
+        messages: Vec<ServerMessage>,
+    ) -> Vec<ServerMessage> {
+        let mut revisions = self.chunk_revisions.lock();
+        let mut predictor = self.predictor.lock();
+        let mut remaining = Vec::new();
+        for message in messages {
+            match message {
+                ServerMessage::ChunkData {
+                    sequence,
+                    revision,
+                    payload,
+                } => match decode_chunk(&payload) {
+                    Ok(chunk) => {
+                        revisions.insert(chunk.position, revision);
+                        scene.insert_chunk(
+                            chunk.position,
+                            chunk.voxels,
+                            chunk.biome.map(Arc::from),
+                        );
+                        predictor.reapply(scene, chunk.position);
+                        self.send(ClientMessage::ChunkAck(sequence));
+                    }
+                    Err(e) => warn!("Failed to decode a streamed chunk: {e}"),
+                },
+                ServerMessage::UnloadChunk(position) => {
+                    revisions.remove(&position);
+                    scene.remove_chunk(&position);
+                }
+                ServerMessage::VoxelDelta {
+                    position,
+                    voxel,
+                    revision,
+                } => {
+                    let chunk_pos = VoxelScene::chunk_at(&position);
+                    let expected = revisions.get(&chunk_pos).map(|r| r.wrapping_add(1));
+                    // A predicted voxel keeps showing until the server has answered the edit,
+                    // its sound already played when it was predicted
+                    let held = predictor.hold(position, voxel);
+                    let previous = scene.voxel_at(&position);
+                    let applied = held || scene.apply_replicated_voxel(&position, voxel);
+                    let sound = previous
+                        .filter(|_| applied && !held)
+                        .and_then(|previous| SoundEvent::for_edit(position, previous, voxel));
+                    if let Some(sound) = sound {
+                        audio::emit(sound);
+                    }
+                    if expected == Some(revision) && applied {
+                        revisions.insert(chunk_pos, revision);
+                    } else if revisions.remove(&chunk_pos).is_some() {
+                        // Later deltas for the chunk are ignored until the full copy arrives
+                        self.send(ClientMessage::ResyncChunk(chunk_pos));
+                    }
+                }
+                // Undoes the client's prediction with the server's voxel
+                ServerMessage::EditRejected {
+                    sequence,
+                    position,
+                    voxel,
+                    reason,
+                } => {
+                    info!("Edit at {position} was rejected: {reason}");
+                    predictor.resolve(scene, sequence, voxel);
+                }
+                ServerMessage::EditAccepted(sequence) => predictor.resolve(scene, sequence, None),
+                other => remaining.push(other),
+            }
+        }
+        remaining
+    }
+
+    // Spawns, removes and moves replicated entities, teleports the client's own player and changes
+    // its game mode, every other message is returned
+    pub fn receive_entities(
+        &self,
+        world: &mut legion::World,
+        messages: Vec<ServerMessage>,
+    ) -> Vec<ServerMessage> {
+        let mut remote_entities = self.remote_entities.lock();
+        let mut remaining = Vec::new();
+        for message in messages {
+            match message {
+                ServerMessage::EntitySpawn {
+                    id,
+                    kind,
+                    position,
+                    rotation,
+                } => {
+                    let entity = world.push((
+                        Position(position),
+                        Rotation(rotation),
+                        RemoteEntity {
+                            id,
+                            kind,
+                            buffer: InterpolationBuffer::default(),
+                        },
+                    ));
+                    if let Some(previous) = remote_entities.insert(id, entity) {
+                        world.remove(previous);
+                    }
+                }
+                ServerMessage::EntityDespawn(id) => {
+                    if let Some(entity) = remote_entities.remove(&id) {
+                        world.remove(entity);
+                    }
+                }
+                ServerMessage::EntityTransforms {
+                    server_time,
+                    updates,
+                } => {
+                    *self.server_clock.lock() = Some((server_time, Instant::now()));
+                    for (id, position, rotation) in updates {
+                        let entry = remote_entities.get(&id).and_then(|e| world.entry(*e));
+                        if let Some(mut entry) = entry {
+                            if let Ok(remote) = entry.get_component_mut::<RemoteEntity>() {
+                                remote.buffer.push(server_time, position, rotation);
+                            }
+                        }
+                    }
+                }
+                ServerMessage::Teleport(position) => {
+                    let mut query = <(&PlayerId, &mut Position)>::query();
+                    for (id, player_position) in query.iter_mut(world) {
+                        if *id == self.player_id {
+                            player_position.0 = position;
+                        }
+                    }
+                }
+                ServerMessage::GameModeChanged(game_mode) => {
+                    if let Some(entity) = find_player(world, self.player_id) {
+                        set_game_mode(world, entity, game_mode);
+                    }
+                }
+                other => remaining.push(other),
+            }
+        }
+        remaining
+    }
+
+    // The server time remote entities are drawn at, None until the first transforms arrive
+    pub fn render_time(&self) -> Option<f64> {
+        self.server_clock.lock().map(|(server_time, received)| {
+            server_time + received.elapsed().as_secs_f64() - INTERPOLATION_DELAY
+        })
+    }
+
+    pub fn disconnect(&self) {
+        self.send(ClientMessage::Disconnect);
+    }
+}
@@ -0,0 +1,56 @@
+use egui::{Align2, Grid};
+
+use crate::{
+    memory::format_bytes,
+    network::network_stats::{self, MessageCategory},
+    rendering::ui,
+};
+
+use super::debug_views::{self, DebugView};
+
+// The traffic of each connection by category, while the network debug view is on. Rates are over
+// the last second, totals since the connection opened
+pub fn add_network_panel() {
+    ui::add_panel("network", |context| {
+        if !debug_views::is_enabled(DebugView::Network) {
+            return;
+        }
+        egui::Window::new("Network")
+            .anchor(Align2::LEFT_BOTTOM, [10.0, -10.0])
+            .resizable(false)
+            .show(context, |ui| {
+                let connections = network_stats::connection_stats();
+                if connections.is_empty() {
+                    ui.label("No connections");
+                }
+                for stats in connections {
+                    ui.label(format!(
+                        "{}, open {}s",
+                        stats.remote,
+                        stats.open_for.as_secs()
+                    ));
+                    Grid::new(&stats.remote).striped(true).show(ui, |ui| {
+                        for heading in ["", "Up /s", "Up", "Down /s", "Down", "Packets"] {
+                            ui.label(heading);
+                        }
+                        ui.end_row();
+                        for category in MessageCategory::ALL {
+                            let sent = stats.sent.get(category);
+                            let received = stats.received.get(category);
+                            ui.label(category.name());
+                            ui.label(format_bytes(
+                                stats.sent_per_second.get(category).bytes as usize,
+                            ));
+                            ui.label(format_bytes(sent.bytes as usize));
+                            ui.label(format_bytes(
+                                stats.received_per_second.get(category).bytes as usize,
+                            ));
+                            ui.label(format_bytes(received.bytes as usize));
+                            ui.label(format!("{} / {}", sent.packets, received.packets));
+                            ui.end_row();
+                        }
+                    });
+                }
+            });
+    });
+}
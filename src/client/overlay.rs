@@ -0,0 +1,165 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use glam::Vec3;
+use wgpu_glyph::{HorizontalAlign, Layout, Section, Text};
+use winit::{
+    dpi::PhysicalSize,
+    event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
+};
+
+use crate::{
+    ecs::systems::render_systems::{DRAWN_ENTITIES, RENDERED_ENTITIES},
+    jobs::{self, JobClass},
+    localization::biome_display_name,
+    memory::MemoryUsage,
+    rendering::text::{TextLayer, FONT_SIZE},
+    targeting,
+    voxels::{voxel_registry::get_voxel_by_id, voxel_scene::VoxelScene},
+};
+
+use super::debug_views::{self, DebugView};
+
+// Frame times are averaged over about two seconds
+const FRAME_SAMPLES: usize = 120;
+// Measuring walks every chunk and entity, so it isn't done every frame
+const MEMORY_INTERVAL: Duration = Duration::from_secs(1);
+const COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// The F3 overlay in the top right corner
+pub struct DebugOverlay {
+    frame_times: VecDeque<Duration>,
+    last_frame: Instant,
+    memory: Option<(Instant, MemoryUsage)>,
+}
+
+impl DebugOverlay {
+    pub fn new() -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(FRAME_SAMPLES),
+            last_frame: Instant::now(),
+            memory: None,
+        }
+    }
+
+    // Called every frame, also while hidden so the frame times are right when it's shown
+    pub fn frame(&mut self) {
+        if self.frame_times.len() == FRAME_SAMPLES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(self.last_frame.elapsed());
+        self.last_frame = Instant::now();
+    }
+
+    // F3 toggles the overlay, returns true when the event was used
+    pub fn handle_event(&self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F3),
+                        ..
+                    },
+                ..
+            } => {
+                debug_views::toggle(DebugView::Overlay);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn queue_text(
+        &mut self,
+        text: &mut TextLayer,
+        size: PhysicalSize<u32>,
+        camera_position: Vec3,
+        world: &legion::World,
+        scene: &VoxelScene,
+    ) {
+        if !debug_views::is_enabled(DebugView::Overlay) {
+            // Measured again as soon as it's shown
+            self.memory = None;
+            return;
+        }
+        let stale = self
+            .memory
+            .as_ref()
+            .map_or(true, |(measured, _)| measured.elapsed() >= MEMORY_INTERVAL);
+        if stale {
+            self.memory = Some((Instant::now(), MemoryUsage::measure(world, scene)));
+        }
+        let memory = &self.memory.as_ref().unwrap().1;
+
+        let (average, slowest) = frame_stats(&self.frame_times);
+        let chunk = VoxelScene::chunk_at(&camera_position.floor().as_ivec3());
+        let jobs = JobClass::ALL
+            .iter()
+            .zip(jobs::queued())
+            .map(|(class, queued)| format!("{} {queued}", class.name()))
+            .collect::<Vec<_>>();
+        let target = match targeting::latest().hit() {
+            Some(hit) => format!(
+                "Looking at {} {} {} {}, face {} {} {}",
+                get_voxel_by_id(hit.voxel.id)
+                    .map_or_else(|| "unknown".to_string(), |profile| profile.display_name()),
+                hit.position.x,
+                hit.position.y,
+                hit.position.z,
+                hit.normal.x,
+                hit.normal.y,
+                hit.normal.z
+            ),
+            None => "Looking at nothing".to_string(),
+        };
+        let lines = [
+            format!(
+                "{:.0} fps {:.1} ms, {:.1} ms slowest",
+                1.0 / average.as_secs_f64().max(f64::EPSILON),
+                average.as_secs_f64() * 1000.0,
+                slowest.as_secs_f64() * 1000.0
+            ),
+            format!(
+                "Position {:.1} {:.1} {:.1}",
+                camera_position.x, camera_position.y, camera_position.z
+            ),
+            format!(
+                "Chunk {} {} {} in {}",
+                chunk.x,
+                chunk.y,
+                chunk.z,
+                biome_display_name(&scene.biome_at(&camera_position.floor().as_ivec3()))
+            ),
+            target,
+            format!("{} chunks loaded", scene.chunks.len()),
+            format!(
+                "{} of {} entities drawn",
+                DRAWN_ENTITIES.load(Ordering::Relaxed),
+                RENDERED_ENTITIES.load(Ordering::Relaxed)
+            ),
+            format!("Queued jobs {}", jobs.join(", ")),
+            memory.to_string(),
+        ];
+        let overlay = lines.join("\n");
+        text.queue(Section {
+            screen_position: (size.width as f32 - 10.0, 10.0),
+            layout: Layout::default().h_align(HorizontalAlign::Right),
+            text: vec![Text::new(&overlay).with_color(COLOR).with_scale(FONT_SIZE)],
+            ..Section::default()
+        });
+    }
+}
+
+// The average and the longest of the recorded frame times
+fn frame_stats(frame_times: &VecDeque<Duration>) -> (Duration, Duration) {
+    if frame_times.is_empty() {
+        return (Duration::ZERO, Duration::ZERO);
+    }
+    let total = frame_times.iter().sum::<Duration>();
+    let slowest = frame_times.iter().max().copied().unwrap_or_default();
+    (total / frame_times.len() as u32, slowest)
+}
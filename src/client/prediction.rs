@@ -0,0 +1,112 @@
+use glam::IVec3;
+
+use crate::voxels::{voxel_data::VoxelData, voxel_scene::VoxelScene};
+
+// An edit shown on the client before the server has answered
+struct PendingEdit {
+    sequence: u32,
+    position: IVec3,
+    predicted: VoxelData,
+    // The latest voxel the server has reported for the position, shown again if the edit is rejected
+    server_voxel: VoxelData,
+}
+
+// Applies the player's edits to the client's scene straight away and keeps track of them until the
+// server confirms or rejects them, deltas for a predicted voxel are held back so they don't flicker it
+pub struct EditPredictor {
+    next_sequence: u32,
+    pending: Vec<PendingEdit>,
+}
+
+impl EditPredictor {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    // Numbers every edit sent, predicted or not
+    pub fn next_sequence(&mut self) -> u32 {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        sequence
+    }
+
+    // Edits in chunks the client doesn't have are only sent, there's nothing to show them in
+    pub fn predict(
+        &mut self,
+        scene: &VoxelScene,
+        sequence: u32,
+        position: IVec3,
+        voxel: VoxelData,
+    ) {
+        let current = match scene.voxel_at(&position) {
+            Some(current) => current,
+            None => return,
+        };
+        let server_voxel = self
+            .pending
+            .iter()
+            .rev()
+            .find(|edit| edit.position == position)
+            .map_or(current, |edit| edit.server_voxel);
+        self.pending.push(PendingEdit {
+            sequence,
+            position,
+            predicted: voxel,
+            server_voxel,
+        });
+        scene.apply_replicated_voxel(&position, voxel);
+    }
+
+    // Records a delta from the server, returns true if a prediction is covering the voxel
+    pub fn hold(&mut self, position: IVec3, voxel: VoxelData) -> bool {
+        let mut held = false;
+        for edit in self
+            .pending
+            .iter_mut()
+            .filter(|edit| edit.position == position)
+        {
+            edit.server_voxel = voxel;
+            held = true;
+        }
+        held
+    }
+
+    // Ends the prediction once the server has answered, rejections carry the server's voxel.
+    // The last answer for a position shows the server's voxel, which is the predicted one if it was accepted
+    pub fn resolve(&mut self, scene: &VoxelScene, sequence: u32, server_voxel: Option<VoxelData>) {
+        let index = match self
+            .pending
+            .iter()
+            .position(|edit| edit.sequence == sequence)
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let edit = self.pending.remove(index);
+        let voxel = server_voxel.unwrap_or(edit.server_voxel);
+        if !self.hold(edit.position, voxel) {
+            scene.apply_replicated_voxel(&edit.position, voxel);
+        }
+    }
+
+    // A full copy of the chunk replaced the predictions in it, they're shown again on top
+    pub fn reapply(&mut self, scene: &VoxelScene, chunk_pos: IVec3) {
+        for edit in &mut self.pending {
+            if VoxelScene::chunk_at(&edit.position) != chunk_pos {
+                continue;
+            }
+            if let Some(voxel) = scene.voxel_at(&edit.position) {
+                edit.server_voxel = voxel;
+            }
+        }
+        // Later edits to the same voxel are applied last so the newest prediction is the one shown
+        for edit in &self.pending {
+            if VoxelScene::chunk_at(&edit.position) == chunk_pos {
+                scene.apply_replicated_voxel(&edit.position, edit.predicted);
+            }
+        }
+    }
+}
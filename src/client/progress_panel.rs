@@ -0,0 +1,28 @@
+use egui::{Align2, ProgressBar};
+
+use crate::{progress, rendering::ui};
+
+// Shows the long tasks that are running, such as a backup, until they finish. Drawn over the world
+// until there's a loading screen to put it on
+pub fn add_progress_panel() {
+    ui::add_panel("progress", |context| {
+        let tasks = progress::active();
+        if tasks.is_empty() {
+            return;
+        }
+        egui::Window::new("Working")
+            .anchor(Align2::CENTER_BOTTOM, [0.0, -40.0])
+            .resizable(false)
+            .collapsible(false)
+            .show(context, |ui| {
+                for task in tasks {
+                    ui.label(format!("{}: {}", task.task, task.stage));
+                    let text = match task.eta() {
+                        Some(eta) => format!("{:.0}%, {}s left", task.percent(), eta.as_secs()),
+                        None => format!("{:.0}%", task.percent()),
+                    };
+                    ui.add(ProgressBar::new(task.fraction()).text(text));
+                }
+            });
+    });
+}
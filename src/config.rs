@@ -0,0 +1,492 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use flume::{Receiver, Sender};
+use parking_lot::{Mutex, RwLock};
+use tracing::{info, warn};
+
+use crate::{
+    data_packs::{BASE_DIRECTORY, PACK_DIRECTORY},
+    jobs::JobClass,
+    localization::DEFAULT_LOCALE,
+    persistence::chunk_compression::ChunkCompression,
+    plugins::PLUGIN_DIRECTORY,
+    voxels::voxel_textures::{anisotropy_level, TextureFiltering},
+};
+pub const DEFAULT_VIEW_DISTANCE: u32 = 6;
+pub const DEFAULT_REMESH_BUDGET: usize = 8;
+pub const DEFAULT_UPLOAD_BUDGET: u64 = 4 << 20;
+pub const DEFAULT_UNDO_MEMORY: usize = 16 << 20;
+pub const DEFAULT_UNLOAD_MARGIN: u32 = 2;
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+// The generation and simulation settings a world can override, the rest belong to the player
+pub const WORLD_SETTINGS: [&str; 3] = ["View Distance", "Unload Margin", "Weather"];
+
+// Where the value of a setting comes from, each layer overrides the ones before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SettingsLayer {
+    Defaults,
+    // The settings file
+    Profile,
+    // The "Settings" of the open world's manifest
+    World,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourcePaths {
+    pub base: PathBuf,
+    pub packs: PathBuf,
+    pub plugins: PathBuf,
+}
+
+// The engine's part of the settings file, Logging has its own section. The registries, job
+// workers, undo history and translations read their settings once at startup, view distance, the remesh and upload
+// budgets, vsync, texture filtering, the placement preview and keybinds change live. "Low Memory"
+// starts from the low memory profile instead of the defaults. The open world can override the
+// WORLD_SETTINGS on top of the file
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineConfig {
+    pub resources: ResourcePaths,
+    // Set when the settings started from the low memory profile
+    pub low_memory: bool,
+    // Chunks streamed in every direction around a player
+    pub view_distance: u32,
+    // Chunks are only unloaded once they are this many chunks past the view distance, so walking
+    // along a border doesn't resend them
+    pub unload_margin: u32,
+    // Jobs of each class that run at once, in JobClass::ALL order
+    pub job_threads: [usize; JobClass::ALL.len()],
+    // Mesh jobs for edited chunks started per frame, the rest wait for the next frames
+    pub remesh_budget: usize,
+    // Bytes of mesh data copied to the GPU per frame
+    pub upload_budget: u64,
+    // Bytes the compressed undo history may hold before the oldest edits are forgotten
+    pub undo_memory: usize,
+    // Writes the open world's unsaved chunks and players when the game crashes
+    pub save_on_crash: bool,
+    // How saved chunks are compressed, adaptive picks fast levels for autosaves and small files
+    // for chunks that aren't played in
+    pub chunk_compression: ChunkCompression,
+    // Names a file in the lang resource folder, like "en_us"
+    pub locale: String,
+    pub vsync: bool,
+    pub texture_filtering: TextureFiltering,
+    // Texels sampled along surfaces seen at an angle, 1 turns it off. Only used with trilinear
+    // filtering
+    pub anisotropy: u8,
+    // Shows a ghost of the voxel about to be placed, tinted by whether it can be placed
+    pub placement_preview: bool,
+    // Dynamic lights keep their color, otherwise they're drawn white at the same brightness
+    pub colored_lights: bool,
+    // The weather changes over time, otherwise it clears and stays clear
+    pub weather: bool,
+    // Input names by lowercase action name, see input_actions for the names
+    pub keybinds: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            resources: ResourcePaths {
+                base: PathBuf::from(BASE_DIRECTORY),
+                packs: PathBuf::from(PACK_DIRECTORY),
+                plugins: PathBuf::from(PLUGIN_DIRECTORY),
+            },
+            low_memory: false,
+            view_distance: DEFAULT_VIEW_DISTANCE,
+            unload_margin: DEFAULT_UNLOAD_MARGIN,
+            job_threads: JobClass::ALL.map(|class| class.default_limit()),
+            remesh_budget: DEFAULT_REMESH_BUDGET,
+            upload_budget: DEFAULT_UPLOAD_BUDGET,
+            undo_memory: DEFAULT_UNDO_MEMORY,
+            save_on_crash: true,
+            chunk_compression: ChunkCompression::default(),
+            locale: DEFAULT_LOCALE.to_string(),
+            vsync: true,
+            texture_filtering: TextureFiltering::default(),
+            anisotropy: 1,
+            placement_preview: true,
+            colored_lights: true,
+            weather: true,
+            keybinds: [
+                ("forward", &["W"][..]),
+                ("back", &["S"]),
+                ("left", &["A"]),
+                ("right", &["D"]),
+                ("jump", &["Space"]),
+                ("descend", &["LShift"]),
+                ("look", &["MouseRight"]),
+                ("break", &["MouseLeft"]),
+                ("place", &["MouseMiddle"]),
+            ]
+            .into_iter()
+            .map(|(action, inputs)| {
+                let inputs = inputs.iter().map(|input| input.to_string()).collect();
+                (action.to_string(), inputs)
+            })
+            .collect(),
+        }
+    }
+}
+
+impl EngineConfig {
+    // Trades looks for memory on constrained devices: fewer chunks kept around and unloaded as soon
+    // as they're out of view, smaller budgets and undo history, fewer workers and white lights
+    pub fn low_memory() -> Self {
+        let mut job_threads = JobClass::ALL.map(|class| class.default_limit());
+        job_threads[JobClass::Generation as usize] = 1;
+        job_threads[JobClass::Meshing as usize] = 1;
+        Self {
+            low_memory: true,
+            view_distance: 3,
+            unload_margin: 0,
+            job_threads,
+            remesh_budget: 4,
+            upload_budget: 1 << 20,
+            undo_memory: 2 << 20,
+            colored_lights: false,
+            ..Self::default()
+        }
+    }
+
+    // Every value is optional, missing ones keep their defaults and keybinds are merged over them.
+    // With "Low Memory" the values that are left out come from the low memory profile
+    pub fn from_json(json: &serde_json::Value) -> Self {
+        Self::from_layers(&[json])
+    }
+
+    // Merges the layers over the defaults in order, starting from the low memory profile when the
+    // last layer saying anything about "Low Memory" turns it on
+    pub fn from_layers(layers: &[&serde_json::Value]) -> Self {
+        let low_memory = layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.get("Low Memory").and_then(|v| v.as_bool()));
+        let mut config = match low_memory {
+            Some(true) => Self::low_memory(),
+            _ => Self::default(),
+        };
+        for layer in layers {
+            config.merge_json(layer);
+        }
+        config
+    }
+
+    fn merge_json(&mut self, json: &serde_json::Value) {
+        let path = |section: &str, key: &str| {
+            json.get(section)
+                .and_then(|s| s.get(key))
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+        };
+        if let Some(base) = path("Resources", "Base") {
+            self.resources.base = base;
+        }
+        if let Some(packs) = path("Resources", "Packs") {
+            self.resources.packs = packs;
+        }
+        if let Some(plugins) = path("Resources", "Plugins") {
+            self.resources.plugins = plugins;
+        }
+        if let Some(distance) = json.get("View Distance").and_then(|v| v.as_u64()) {
+            self.view_distance = distance as u32;
+        }
+        if let Some(margin) = json.get("Unload Margin").and_then(|v| v.as_u64()) {
+            self.unload_margin = margin as u32;
+        }
+        if let Some(threads) = json.get("Threads") {
+            for class in JobClass::ALL {
+                // At least one, a class without workers would never finish its jobs
+                if let Some(count) = threads.get(format!("{class:?}")).and_then(|v| v.as_u64()) {
+                    self.job_threads[class as usize] = (count as usize).max(1);
+                }
+            }
+        }
+        if let Some(budget) = json.get("Remesh Budget").and_then(|v| v.as_u64()) {
+            self.remesh_budget = (budget as usize).max(1);
+        }
+        if let Some(budget) = json.get("Upload Budget").and_then(|v| v.as_u64()) {
+            self.upload_budget = budget;
+        }
+        if let Some(memory) = json.get("Undo Memory").and_then(|v| v.as_u64()) {
+            self.undo_memory = memory as usize;
+        }
+        if let Some(save) = json.get("Save On Crash").and_then(|v| v.as_bool()) {
+            self.save_on_crash = save;
+        }
+        if let Some(compression) = json.get("Chunk Compression") {
+            match ChunkCompression::from_json(compression) {
+                Some(compression) => self.chunk_compression = compression,
+                None => warn!("\"Chunk Compression\" has to be \"Off\", \"Adaptive\" or a level"),
+            }
+        }
+        if let Some(locale) = json.get("Locale").and_then(|v| v.as_str()) {
+            self.locale = locale.to_string();
+        }
+        if let Some(vsync) = json.get("Vsync").and_then(|v| v.as_bool()) {
+            self.vsync = vsync;
+        }
+        if let Some(filtering) = json.get("Texture Filtering") {
+            match TextureFiltering::from_json(filtering) {
+                Some(filtering) => self.texture_filtering = filtering,
+                None => warn!("\"Texture Filtering\" has to be \"Nearest\" or \"Trilinear\""),
+            }
+        }
+        if let Some(anisotropy) = json.get("Anisotropy").and_then(|v| v.as_u64()) {
+            self.anisotropy = anisotropy_level(anisotropy);
+        }
+        if let Some(preview) = json.get("Placement Preview").and_then(|v| v.as_bool()) {
+            self.placement_preview = preview;
+        }
+        if let Some(colored) = json.get("Colored Lights").and_then(|v| v.as_bool()) {
+            self.colored_lights = colored;
+        }
+        if let Some(weather) = json.get("Weather").and_then(|v| v.as_bool()) {
+            self.weather = weather;
+        }
+        if let Some(keybinds) = json.get("Keybinds").and_then(|v| v.as_object()) {
+            for (action, inputs) in keybinds {
+                // One input name or a list of them
+                let inputs = match inputs {
+                    serde_json::Value::String(input) => Some(vec![input.clone()]),
+                    serde_json::Value::Array(inputs) => inputs
+                        .iter()
+                        .map(|input| input.as_str().map(str::to_string))
+                        .collect(),
+                    _ => None,
+                };
+                match inputs {
+                    Some(inputs) => {
+                        self.keybinds.insert(action.to_lowercase(), inputs);
+                    }
+                    None => warn!("The keybind for {action} isn't a list of input names"),
+                }
+            }
+        }
+    }
+
+    // A missing file gives the defaults, a file that isn't valid toml is an error
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::from_json(&read_settings(path)?))
+    }
+}
+
+// The settings file read into json values like the world settings it's layered with, empty when
+// there is none
+fn read_settings(path: &Path) -> Result<serde_json::Value> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return Ok(serde_json::json!({})),
+    };
+    toml::from_str(&contents).with_context(|| format!("{} isn't valid toml", path.display()))
+}
+
+// Writes the keybinds into the settings file, keeping the rest of it as it is
+pub fn save_keybinds(path: &Path, keybinds: &BTreeMap<String, Vec<String>>) -> Result<()> {
+    let mut json = read_settings(path)?;
+    let keybinds = keybinds
+        .iter()
+        .map(|(action, inputs)| (capitalized(action), serde_json::json!(inputs)))
+        .collect::<serde_json::Map<_, _>>();
+    json.as_object_mut()
+        .with_context(|| format!("{} isn't a toml table", path.display()))?
+        .insert("Keybinds".to_string(), keybinds.into());
+    fs::write(path, toml::to_string_pretty(&json)?)
+        .with_context(|| format!("Couldn't write {}", path.display()))
+}
+
+// Settings keys are capitalized like the rest of the file
+fn capitalized(name: &str) -> String {
+    let mut chars = name.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+lazy_static! {
+    static ref CONFIG: RwLock<Arc<EngineConfig>> = RwLock::new(Arc::new(EngineConfig::default()));
+    static ref SUBSCRIBERS: Mutex<Vec<Sender<Arc<EngineConfig>>>> = Mutex::new(Vec::new());
+    // The profile and world layers the config was last resolved from
+    static ref LAYERS: Mutex<(serde_json::Value, serde_json::Value)> =
+        Mutex::new((serde_json::json!({}), serde_json::json!({})));
+}
+
+pub fn current() -> Arc<EngineConfig> {
+    Arc::clone(&CONFIG.read())
+}
+
+// Replaces the config and sends it to every subscriber if anything changed
+pub fn set(config: EngineConfig) {
+    if *current() == config {
+        return;
+    }
+    let config = Arc::new(config);
+    *CONFIG.write() = Arc::clone(&config);
+    // Subscribers that dropped their receiver are forgotten
+    SUBSCRIBERS
+        .lock()
+        .retain(|subscriber| subscriber.send(Arc::clone(&config)).is_ok());
+}
+
+// Resolves the config again from the defaults, the settings file and the open world's settings
+fn resolve() {
+    let config = {
+        let layers = LAYERS.lock();
+        EngineConfig::from_layers(&[&layers.0, &layers.1])
+    };
+    set(config);
+}
+
+fn set_profile(json: serde_json::Value) {
+    LAYERS.lock().0 = json;
+    resolve();
+}
+
+// Overrides the settings file with the open world's settings, settings a world can't override
+// are ignored with a warning. Empty settings go back to the settings file alone
+pub fn set_world_settings(settings: &BTreeMap<String, serde_json::Value>) {
+    let mut world = serde_json::Map::new();
+    for (key, value) in settings {
+        match WORLD_SETTINGS.contains(&key.as_str()) {
+            true => {
+                world.insert(key.clone(), value.clone());
+            }
+            false => warn!("Worlds can't override \"{key}\", it's left to the settings file"),
+        }
+    }
+    LAYERS.lock().1 = world.into();
+    resolve();
+}
+
+// The last layer that sets the key
+pub fn setting_source(key: &str) -> SettingsLayer {
+    let layers = LAYERS.lock();
+    match (layers.1.get(key), layers.0.get(key)) {
+        (Some(_), _) => SettingsLayer::World,
+        (None, Some(_)) => SettingsLayer::Profile,
+        (None, None) => SettingsLayer::Defaults,
+    }
+}
+
+// Receives the new config after each change, systems check it when they run
+pub fn subscribe() -> Receiver<Arc<EngineConfig>> {
+    let (sender, receiver) = flume::unbounded();
+    SUBSCRIBERS.lock().push(sender);
+    receiver
+}
+
+// Loads the settings file, has to run before the registries and job workers start
+pub fn init(path: &Path) {
+    match read_settings(path) {
+        Ok(json) => set_profile(json),
+        Err(e) => warn!("Using the default engine settings: {e}"),
+    }
+}
+
+// Loads the settings file again whenever it's saved. A file with mistakes is ignored until it's fixed
+pub fn watch(path: &Path) {
+    let path = path.to_path_buf();
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let mut last_modified: Option<SystemTime> = modified(&path);
+    thread::Builder::new()
+        .name("config-watcher".to_string())
+        .spawn(move || loop {
+            thread::sleep(WATCH_INTERVAL);
+            let current = modified(&path);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+            match read_settings(&path) {
+                Ok(json) => {
+                    info!("Reloaded {}", path.display());
+                    set_profile(json);
+                }
+                Err(e) => warn!("Keeping the previous engine settings: {e}"),
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_merged_over_the_defaults() {
+        let config = EngineConfig::from_json(&serde_json::json!({
+            "View Distance": 10,
+            "Threads": { "Generation": 6, "Io": 0 },
+            "Keybinds": { "Jump": "J", "Inventory": ["E", "Tab"] },
+            "Texture Filtering": "Trilinear",
+            "Anisotropy": 6,
+        }));
+        let defaults = EngineConfig::default();
+        assert_eq!(config.view_distance, 10);
+        assert_eq!(config.job_threads[JobClass::Generation as usize], 6);
+        assert_eq!(config.job_threads[JobClass::Io as usize], 1);
+        assert_eq!(config.keybinds["jump"], ["J"]);
+        assert_eq!(config.keybinds["inventory"], ["E", "Tab"]);
+        assert_eq!(config.keybinds["forward"], ["W"]);
+        assert_eq!(config.resources, defaults.resources);
+        assert_eq!(config.vsync, defaults.vsync);
+        assert_eq!(config.texture_filtering, TextureFiltering::Trilinear);
+        assert_eq!(config.anisotropy, 4);
+    }
+
+    #[test]
+    fn low_memory_is_one_switch_under_the_other_settings() {
+        let config = EngineConfig::from_json(&serde_json::json!({
+            "Low Memory": true,
+            "Unload Margin": 1,
+        }));
+        let profile = EngineConfig::low_memory();
+        assert!(config.low_memory && !config.colored_lights);
+        assert!(config.view_distance < DEFAULT_VIEW_DISTANCE);
+        assert_eq!(config.unload_margin, 1);
+        assert_eq!(config.undo_memory, profile.undo_memory);
+        assert_eq!(config.keybinds, EngineConfig::default().keybinds);
+        assert!(!EngineConfig::from_json(&serde_json::json!({})).low_memory);
+    }
+
+    #[test]
+    fn worlds_override_the_settings_file() {
+        let profile = serde_json::json!({ "Low Memory": true, "View Distance": 5, "Vsync": false });
+        let world = serde_json::json!({ "View Distance": 10, "Weather": false });
+        let config = EngineConfig::from_layers(&[&profile, &world]);
+        assert_eq!(config.view_distance, 10);
+        assert!(!config.weather && !config.vsync);
+        assert_eq!(
+            config.unload_margin,
+            EngineConfig::low_memory().unload_margin
+        );
+        let config = EngineConfig::from_layers(&[&profile]);
+        assert_eq!(config.view_distance, 5);
+        assert!(config.weather);
+    }
+
+    #[test]
+    fn keybinds_are_saved_into_the_settings_file() {
+        let path =
+            std::env::temp_dir().join(format!("assemblage_settings_{}.toml", std::process::id()));
+        fs::write(&path, "\"View Distance\" = 6\n\n[Keybinds]\nJump = \"J\"\n").unwrap();
+        let mut keybinds = EngineConfig::load(&path).unwrap().keybinds;
+        keybinds.insert(
+            "inventory".to_string(),
+            vec!["E".to_string(), "Tab".to_string()],
+        );
+        save_keybinds(&path, &keybinds).unwrap();
+        let config = EngineConfig::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.view_distance, 6);
+        assert_eq!(config.keybinds["jump"], ["J"]);
+        assert_eq!(config.keybinds["inventory"], ["E", "Tab"]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    backtrace::Backtrace,
+    cell::Cell,
+    fmt::Write as _,
+    fs,
+    panic::{self, AssertUnwindSafe, PanicHookInfo},
+    path::PathBuf,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use tracing::error;
+
+use crate::{config, jobs, logging};
+
+pub const CRASH_REPORT_DIRECTORY: &str = "./crash_reports";
+
+type EmergencySave = Box<dyn Fn() -> Result<usize> + Send + Sync>;
+
+// What the report says about the open world, set by the server while it runs
+#[derive(Default)]
+struct CrashContext {
+    world: Option<String>,
+    // Writes the dirty chunks and players right away, without the job system
+    emergency_save: Option<EmergencySave>,
+}
+
+lazy_static! {
+    static ref CONTEXT: Mutex<CrashContext> = Mutex::new(CrashContext::default());
+}
+
+thread_local! {
+    // Set while running work whose panics are caught and recovered from, like jobs
+    static CATCHING: Cell<bool> = Cell::new(false);
+}
+
+// Writes a crash report and saves the open world when a thread panics, the default hook still
+// prints the panic. Panics inside catch_unwind are only printed
+pub fn install() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if CATCHING.with(Cell::get) {
+            return;
+        }
+        match write_report(info) {
+            Ok(path) => error!("Wrote a crash report to {}", path.display()),
+            Err(e) => error!("Failed to write a crash report: {e}"),
+        }
+    }));
+}
+
+// Runs the work and catches its panic without writing a crash report, the panic is still printed
+pub fn catch_unwind<R>(work: impl FnOnce() -> R) -> thread::Result<R> {
+    let catching = CATCHING.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(work));
+    CATCHING.with(|c| c.set(catching));
+    result
+}
+
+// The world in the reports and what saves it when the game crashes, None once it's closed
+pub fn set_world(world: Option<(String, EmergencySave)>) {
+    let mut context = CONTEXT.lock();
+    match world {
+        Some((name, emergency_save)) => {
+            context.world = Some(name);
+            context.emergency_save = Some(emergency_save);
+        }
+        None => *context = CrashContext::default(),
+    }
+}
+
+fn write_report(info: &PanicHookInfo) -> Result<PathBuf> {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let report = report(info, seconds);
+    let directory = PathBuf::from(CRASH_REPORT_DIRECTORY);
+    fs::create_dir_all(&directory)?;
+    let path = directory.join(format!("crash-{seconds}.txt"));
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+fn report(info: &PanicHookInfo, seconds: u64) -> String {
+    let mut report = String::new();
+    let thread = thread::current();
+    let _ = writeln!(report, "Assemblage crash report");
+    let _ = writeln!(report, "Time: {seconds}");
+    let _ = writeln!(report, "Version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "Thread: {}", thread.name().unwrap_or("unnamed"));
+    let _ = writeln!(report, "Panic: {info}");
+
+    // The panicking thread may hold the context, then the world is left alone
+    match CONTEXT.try_lock() {
+        Some(context) => {
+            let world = context.world.as_deref().unwrap_or("none");
+            let _ = writeln!(report, "World: {world}");
+            let saved = match &context.emergency_save {
+                Some(_) if !config::current().save_on_crash => "disabled".to_string(),
+                Some(emergency_save) => match emergency_save() {
+                    Ok(saved) => format!("saved {saved} chunks"),
+                    Err(e) => format!("failed: {e}"),
+                },
+                None => "no world is open".to_string(),
+            };
+            let _ = writeln!(report, "Emergency save: {saved}");
+        }
+        None => {
+            let _ = writeln!(report, "World: unknown, the crash context was locked");
+        }
+    }
+
+    let _ = writeln!(report, "\nJobs:");
+    let _ = writeln!(report, "{}", jobs::summary());
+    let _ = writeln!(report, "\nBacktrace:\n{}", Backtrace::force_capture());
+    let _ = writeln!(report, "\nRecent log:");
+    for line in logging::recent_lines() {
+        let _ = writeln!(report, "{line}");
+    }
+    report
+}
+
+#[cfg(test)]
+mod crash_report_tests {
+    use super::*;
+
+    #[test]
+    fn caught_panics_leave_the_thread_reporting_again() {
+        let result = catch_unwind(|| {
+            assert!(CATCHING.with(Cell::get));
+            panic!("caught");
+        });
+        assert!(result.is_err());
+        assert!(!CATCHING.with(Cell::get));
+        assert_eq!(catch_unwind(|| 4).unwrap(), 4);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use parking_lot::RwLock;
+#[cfg(feature = "persistence")]
+use tracing::{info, warn};
+
+use crate::config;
+#[cfg(feature = "persistence")]
+use crate::persistence::world_save::WorldSave;
+
+// Data packs are folders laid out like the resources folder, dropped in here to add or replace content
+pub const PACK_DIRECTORY: &str = "./packs";
+// The built in resources, the bottom layer every pack overrides
+pub const BASE_DIRECTORY: &str = "./src/resources";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackEntry {
+    pub name: String,
+    pub enabled: bool,
+}
+
+impl PackEntry {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "Name": self.name,
+            "Enabled": self.enabled,
+        })
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            name: json.get("Name")?.as_str()?.to_string(),
+            enabled: json
+                .get("Enabled")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(true),
+        })
+    }
+}
+
+lazy_static! {
+    // The packs of the world being played in load order, later packs override earlier ones.
+    // None until a world has picked its packs, only the base resources are used until then
+    static ref PACKS: RwLock<Option<Vec<PackEntry>>> = RwLock::new(None);
+    // A pack whose textures go over every other layer, picked by the player rather than the world
+    static ref TEXTURE_PACK: RwLock<Option<String>> = RwLock::new(None);
+}
+
+// The names of the pack folders in the directory, sorted so new packs are added in a stable order
+pub fn available_packs(directory: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut names = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+// Keeps the order and enabled state saved with the world, packs added since are enabled at the end.
+// Packs that were removed stay listed so they come back in the same place when they're restored
+pub fn resolve_packs(saved: &[PackEntry], available: &[String]) -> Vec<PackEntry> {
+    let mut packs = saved.to_vec();
+    for name in available {
+        if !packs.iter().any(|pack| &pack.name == name) {
+            packs.push(PackEntry {
+                name: name.clone(),
+                enabled: true,
+            });
+        }
+    }
+    packs
+}
+
+// Has to happen before the registries load, they only read the packs once
+pub fn set_packs(packs: Vec<PackEntry>) {
+    *PACKS.write() = Some(packs);
+}
+
+pub fn packs() -> Option<Vec<PackEntry>> {
+    PACKS.read().clone()
+}
+
+// Only changes which textures load, the other resources of the pack are left out. Takes effect the
+// next time the textures load, None goes back to the world's packs
+pub fn set_texture_pack(name: Option<String>) -> Result<()> {
+    if let Some(name) = &name {
+        if !config::current()
+            .resources
+            .packs
+            .join(name)
+            .join("textures")
+            .is_dir()
+        {
+            bail!("There is no pack named {name} with textures");
+        }
+    }
+    *TEXTURE_PACK.write() = name;
+    Ok(())
+}
+
+pub fn texture_pack() -> Option<String> {
+    TEXTURE_PACK.read().clone()
+}
+
+// Picks the packs for the world in the directory from its manifest, a new world gets every available pack
+#[cfg(feature = "persistence")]
+pub fn enable_world_packs(world_directory: &Path) {
+    let saved = match WorldSave::exists(world_directory) {
+        true => match WorldSave::read_metadata(world_directory) {
+            Ok(metadata) => metadata.data_packs,
+            Err(e) => {
+                warn!("Couldn't read the data packs of the world: {e}");
+                Vec::new()
+            }
+        },
+        false => Vec::new(),
+    };
+    let pack_directory = config::current().resources.packs.clone();
+    let packs = resolve_packs(&saved, &available_packs(&pack_directory));
+    for pack in &packs {
+        match (pack.enabled, pack_directory.join(&pack.name).is_dir()) {
+            (true, true) => info!("Enabled data pack {}", pack.name),
+            (true, false) => warn!("Data pack {} is enabled but missing", pack.name),
+            (false, _) => {}
+        }
+    }
+    set_packs(packs);
+}
+
+// The folders resources are read from, the base resources first
+fn layers() -> Vec<PathBuf> {
+    let config = config::current();
+    let mut layers = vec![config.resources.base.clone()];
+    if let Some(packs) = &*PACKS.read() {
+        layers.extend(
+            packs
+                .iter()
+                .filter(|pack| pack.enabled)
+                .map(|pack| config.resources.packs.join(&pack.name))
+                .filter(|path| path.is_dir()),
+        );
+    }
+    layers
+}
+
+// Every file in a resource folder such as "voxel_profiles" across all layers, by name without the extension.
+// A pack file replaces the one with the same name where it was, so overriding a voxel keeps its id
+pub fn resource_files(category: &str) -> Vec<(String, PathBuf)> {
+    layered_files(&layers(), category)
+}
+
+fn layered_files(layers: &[PathBuf], category: &str) -> Vec<(String, PathBuf)> {
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    for layer in layers {
+        for (name, path) in layer_files(layer, category) {
+            match files.iter_mut().find(|(existing, _)| existing == &name) {
+                Some((_, existing)) => *existing = path,
+                None => files.push((name, path)),
+            }
+        }
+    }
+    files
+}
+
+// Like resource_files("textures") with the texture pack on top
+pub fn texture_files() -> Vec<(String, PathBuf)> {
+    let mut layers = layers();
+    if let Some(pack) = texture_pack() {
+        layers.push(config::current().resources.packs.join(pack));
+    }
+    layered_files(&layers, "textures")
+}
+
+// Like resource_files, but with every layer's copy of a file, base resources first. For files that
+// packs add to rather than replace, like the translations
+pub fn merged_resource_files(category: &str) -> Vec<(String, Vec<PathBuf>)> {
+    let mut files: Vec<(String, Vec<PathBuf>)> = Vec::new();
+    for layer in layers() {
+        for (name, path) in layer_files(&layer, category) {
+            match files.iter_mut().find(|(existing, _)| existing == &name) {
+                Some((_, paths)) => paths.push(path),
+                None => files.push((name, vec![path])),
+            }
+        }
+    }
+    files
+}
+
+fn layer_files(layer: &Path, category: &str) -> Vec<(String, PathBuf)> {
+    let entries = match fs::read_dir(layer.join(category)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().to_string();
+            Some((name, path))
+        })
+        .collect()
+}
+
+// The file at a path such as "textures/lapis_block.png" from the highest layer that has it
+pub fn find_resource(path: &str) -> Option<PathBuf> {
+    layers()
+        .into_iter()
+        .rev()
+        .map(|layer| layer.join(path))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod data_packs_tests {
+    use super::*;
+
+    #[test]
+    fn resolving_keeps_saved_order_and_appends_new_packs() {
+        let saved = vec![
+            PackEntry {
+                name: "b".to_string(),
+                enabled: false,
+            },
+            PackEntry {
+                name: "gone".to_string(),
+                enabled: true,
+            },
+        ];
+        let available = vec!["a".to_string(), "b".to_string()];
+        let names = resolve_packs(&saved, &available)
+            .into_iter()
+            .map(|pack| (pack.name, pack.enabled))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ("b".to_string(), false),
+                ("gone".to_string(), true),
+                ("a".to_string(), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn later_layers_replace_files_in_place() {
+        let root = std::env::temp_dir().join(format!("assemblage_packs_{}", std::process::id()));
+        let base = root.join("base");
+        let pack = root.join("pack");
+        fs::create_dir_all(base.join("voxel_profiles")).unwrap();
+        fs::create_dir_all(pack.join("voxel_profiles")).unwrap();
+        fs::write(base.join("voxel_profiles/stone.json"), "{}").unwrap();
+        fs::write(pack.join("voxel_profiles/stone.json"), "{}").unwrap();
+        fs::write(pack.join("voxel_profiles/marble.json"), "{}").unwrap();
+
+        let files = layered_files(&[base, pack.clone()], "voxel_profiles");
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0],
+            ("stone".to_string(), pack.join("voxel_profiles/stone.json"))
+        );
+        assert_eq!(files[1].0, "marble");
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+use glam::{EulerRot, Quat, Vec3};
+use legion::Entity;
+
+use crate::error::{expect_array, expect_f32, expect_f32s, required, AssemblageError};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    // Seconds from the start of the path
+    pub time: f32,
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+// Keyframes an observer flies through, sorted by time. Positions follow a Catmull-Rom spline
+// through the keyframes so the camera doesn't turn sharply at them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    // { "Keyframes": [{ "Time": 0, "Position": [x, y, z], "Yaw": 90, "Pitch": -10 }] } with the
+    // angles in degrees
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let mut path = Self::default();
+        for keyframe in expect_array(required(json, "Keyframes")?, "Keyframes")? {
+            let angle = |field| {
+                keyframe
+                    .get(field)
+                    .map_or(Ok(0.0), |v| expect_f32(v, field).map(f32::to_radians))
+            };
+            path.insert(Keyframe {
+                time: expect_f32(required(keyframe, "Time")?, "Time")?,
+                position: Vec3::from(expect_f32s::<3>(
+                    required(keyframe, "Position")?,
+                    "Position",
+                )?),
+                rotation: Quat::from_euler(EulerRot::YXZ, angle("Yaw")?, angle("Pitch")?, 0.0),
+            });
+        }
+        Ok(path)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let keyframes = self
+            .keyframes
+            .iter()
+            .map(|keyframe| {
+                let (yaw, pitch, _) = keyframe.rotation.to_euler(EulerRot::YXZ);
+                serde_json::json!({
+                    "Time": keyframe.time,
+                    "Position": keyframe.position.to_array(),
+                    "Yaw": yaw.to_degrees(),
+                    "Pitch": pitch.to_degrees(),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({ "Keyframes": keyframes })
+    }
+
+    // A keyframe at the time of an existing one replaces it
+    pub fn insert(&mut self, keyframe: Keyframe) {
+        let index = self.keyframes.partition_point(|k| k.time < keyframe.time);
+        match self.keyframes.get_mut(index) {
+            Some(existing) if existing.time == keyframe.time => *existing = keyframe,
+            _ => self.keyframes.insert(index, keyframe),
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // Where the camera is at the time, held at the first and last keyframe outside the path
+    pub fn sample(&self, time: f32) -> Option<(Vec3, Quat)> {
+        let keyframes = &self.keyframes;
+        let next = keyframes.partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 || next == keyframes.len() {
+            let keyframe = keyframes.get(next.saturating_sub(1))?;
+            return Some((keyframe.position, keyframe.rotation));
+        }
+        let (a, b) = (keyframes[next - 1], keyframes[next]);
+        let t = (time - a.time) / (b.time - a.time);
+        // The neighbours shape the curve, the ends repeat themselves
+        let before = keyframes[next.saturating_sub(2)].position;
+        let after = keyframes[(next + 1).min(keyframes.len() - 1)].position;
+        Some((
+            catmull_rom(before, a.position, b.position, after, t),
+            a.rotation.slerp(b.rotation, t),
+        ))
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+// How far an observer is along its path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathPlayback {
+    pub time: f32,
+    pub speed: f32,
+    pub playing: bool,
+    pub looping: bool,
+    // Advances the path by this many seconds every frame instead of by the frame time, so a
+    // recording gets every frame of the path however long rendering them takes
+    pub fixed_step: Option<f32>,
+}
+
+impl Default for PathPlayback {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            looping: false,
+            fixed_step: None,
+        }
+    }
+}
+
+impl PathPlayback {
+    // Returns false once a path that doesn't loop has played to its end
+    pub fn advance(&mut self, delta_time: f32, duration: f32) -> bool {
+        if !self.playing {
+            return true;
+        }
+        self.time += self.fixed_step.unwrap_or(delta_time) * self.speed;
+        if self.time <= duration {
+            return true;
+        }
+        match self.looping && duration > 0.0 {
+            true => {
+                self.time %= duration;
+                true
+            }
+            false => {
+                self.time = duration;
+                false
+            }
+        }
+    }
+}
+
+// An entity that only exists to carry the camera along a path, the entity that had the camera
+// gets it back when the path ends
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Observer {
+    pub previous: Option<Entity>,
+}
+
+#[cfg(test)]
+mod cinematic_components_tests {
+    use super::*;
+
+    fn keyframe(time: f32, x: f32) -> Keyframe {
+        Keyframe {
+            time,
+            position: Vec3::new(x, 0.0, 0.0),
+            rotation: Quat::IDENTITY,
+        }
+    }
+
+    #[test]
+    fn the_path_passes_through_its_keyframes() {
+        let mut path = CameraPath::default();
+        for (time, x) in [(2.0, 4.0), (0.0, 0.0), (1.0, 1.0), (3.0, 9.0)] {
+            path.insert(keyframe(time, x));
+        }
+        assert_eq!(path.duration(), 3.0);
+        for (time, x) in [(-1.0, 0.0), (1.0, 1.0), (2.0, 4.0), (5.0, 9.0)] {
+            let (position, _) = path.sample(time).unwrap();
+            assert!((position.x - x).abs() < 1e-5);
+        }
+        let (position, _) = path.sample(1.5).unwrap();
+        assert!(position.x > 1.0 && position.x < 4.0);
+        assert_eq!(CameraPath::default().sample(0.0), None);
+    }
+
+    #[test]
+    fn paths_round_trip_through_json() {
+        let json = serde_json::json!({ "Keyframes": [
+            { "Time": 0, "Position": [1, 2, 3], "Yaw": 90, "Pitch": -20 },
+            { "Time": 4, "Position": [5, 2, 3] },
+        ]});
+        let path = CameraPath::from_json(&json).unwrap();
+        let again = CameraPath::from_json(&path.to_json()).unwrap();
+        assert_eq!(again.keyframes.len(), 2);
+        assert!(again.keyframes[0]
+            .rotation
+            .abs_diff_eq(path.keyframes[0].rotation, 1e-5));
+        assert!(CameraPath::from_json(&serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn playback_stops_or_loops_at_the_end() {
+        let mut playback = PathPlayback::default();
+        assert!(playback.advance(1.5, 2.0));
+        assert!(!playback.advance(1.5, 2.0));
+        assert_eq!(playback.time, 2.0);
+
+        let mut playback = PathPlayback {
+            looping: true,
+            fixed_step: Some(0.5),
+            ..PathPlayback::default()
+        };
+        for _ in 0..5 {
+            assert!(playback.advance(10.0, 2.0));
+        }
+        assert!((playback.time - 0.5).abs() < 1e-5);
+    }
+}
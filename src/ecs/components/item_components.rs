@@ -0,0 +1,14 @@
+// A stack of voxels lying in the world, created when a voxel is broken
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DroppedItem {
+    pub voxel_id: u16,
+    pub count: u32,
+    // Seconds since the item was dropped
+    pub age: f32,
+}
+
+// Entities with this component pick up dropped items within the radius
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ItemCollector {
+    pub radius: f32,
+}
@@ -1,4 +1,11 @@
+#[cfg(feature = "render")]
 pub mod camera;
+pub mod cinematic_components;
+pub mod item_components;
+pub mod network_components;
+pub mod physics_components;
 pub mod player_components;
+pub mod projectile_components;
 pub mod rendering_components;
 pub mod transformation_components;
+pub mod voxel_components;
@@ -0,0 +1,9 @@
+use crate::network::interpolation::InterpolationBuffer;
+
+// An entity owned by a remote server, moved by interpolating the transforms the server sends
+#[derive(Clone)]
+pub struct RemoteEntity {
+    pub id: u64,
+    pub kind: String,
+    pub buffer: InterpolationBuffer,
+}
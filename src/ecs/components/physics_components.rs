@@ -0,0 +1,21 @@
+use glam::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Velocity(pub Vec3);
+
+// Axis aligned box centered on the entity position, used for collisions against the voxel scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Collider {
+    pub half_extents: Vec3,
+}
+
+// Downwards acceleration applied to the velocity every tick
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gravity(pub f32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Grounded(pub bool);
+
+// Entities with this component are neither pushed by other entities nor push them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoEntityCollision;
@@ -1,4 +1,137 @@
+use anyhow::{bail, Result};
+use glam::IVec3;
+
+use crate::voxels::schematic::SchematicTransform;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameMode {
+    // Voxels break instantly, nothing is dropped and the player can fly
+    Creative,
+    // Voxels take time to break and drop items, the player walks and falls
+    Survival,
+    // Flies through terrain without colliding and can't edit. Other players, item pickup,
+    // projectiles and spawning don't see the player
+    Spectator,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 3] = [GameMode::Creative, GameMode::Survival, GameMode::Spectator];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMode::Creative => "creative",
+            GameMode::Survival => "survival",
+            GameMode::Spectator => "spectator",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    pub fn can_fly(&self) -> bool {
+        matches!(self, GameMode::Creative | GameMode::Spectator)
+    }
+
+    pub fn can_edit(&self) -> bool {
+        !self.is_spectator()
+    }
+
+    pub fn is_spectator(&self) -> bool {
+        matches!(self, GameMode::Spectator)
+    }
+
+    pub fn breaks_instantly(&self) -> bool {
+        matches!(self, GameMode::Creative)
+    }
+
+    pub fn drops_items(&self) -> bool {
+        matches!(self, GameMode::Survival)
+    }
+}
+
+// How saves and messages store the game mode
+pub fn game_mode_to_u8(game_mode: GameMode) -> u8 {
+    match game_mode {
+        GameMode::Creative => 0,
+        GameMode::Survival => 1,
+        GameMode::Spectator => 2,
+    }
+}
+
+pub fn game_mode_from_u8(value: u8) -> Result<GameMode> {
+    Ok(match value {
+        0 => GameMode::Creative,
+        1 => GameMode::Survival,
+        2 => GameMode::Spectator,
+        _ => bail!("Unknown game mode {value}"),
+    })
+}
+
+// Identifies a player across sessions, formatted like a UUID when saved
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PlayerId(pub u128);
+
+impl PlayerId {
+    pub fn new() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn to_uuid_string(&self) -> String {
+        let hex = format!("{:032x}", self.0);
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+
+    pub fn parse(uuid: &str) -> Option<Self> {
+        u128::from_str_radix(&uuid.trim().replace('-', ""), 16)
+            .ok()
+            .map(Self)
+    }
+}
+
+// Opaque inventory data owned by the game, the engine only saves and loads it
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct PlayerInventory(pub Vec<u8>);
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Player {
     pub fly_speed: f32,
+    pub walk_speed: f32,
+    pub jump_speed: f32,
+    // Maximum distance in voxels the player can break and place at
+    pub reach: f32,
+    pub game_mode: GameMode,
+    // The voxel id placed by the player
+    pub selected_voxel: u16,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self {
+            fly_speed: 50.0,
+            walk_speed: 5.0,
+            jump_speed: 8.0,
+            reach: 6.0,
+            game_mode: GameMode::Creative,
+            selected_voxel: 1,
+        }
+    }
+}
+
+// Marks players in spectator mode, see set_game_mode. Systems looking for entities skip them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spectating;
+
+// State of the schematic debug keys, see schematic_debug_tools
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct SchematicClipboard {
+    pub corners: [Option<IVec3>; 2],
+    pub transform: SchematicTransform,
 }
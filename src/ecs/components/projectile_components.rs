@@ -0,0 +1,17 @@
+use glam::Vec3;
+use legion::Entity;
+
+// An arrow or thrown object flying through the world. Projectiles keep their own velocity instead
+// of a Velocity component, so integrate_entities doesn't move them through what they should hit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projectile {
+    pub velocity: Vec3,
+    // Downwards acceleration, 0 for a projectile flying in a straight line
+    pub gravity: f32,
+    // How far around its path the projectile hits entities, voxels are only hit by the path itself
+    pub radius: f32,
+    // The entity that fired the projectile can't be hit by it
+    pub owner: Option<Entity>,
+    // Seconds since the projectile was fired
+    pub age: f32,
+}
@@ -1,16 +1,21 @@
+#[cfg(feature = "render")]
 use std::sync::{
     atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
 
+use glam::Vec3;
+#[cfg(feature = "render")]
 use parking_lot::RwLock;
 
+#[cfg(feature = "render")]
 use crate::{
     asset_types::{asset::Asset, mesh::Mesh},
     next_id,
     rendering::material::Material,
 };
 
+#[cfg(feature = "render")]
 #[derive(Clone)]
 pub struct MeshRenderer {
     pub mesh: Arc<RwLock<Mesh>>,
@@ -20,6 +25,7 @@ pub struct MeshRenderer {
     id: u64,
 }
 
+#[cfg(feature = "render")]
 impl MeshRenderer {
     pub fn new(
         mesh: Arc<RwLock<Mesh>>,
@@ -50,3 +56,20 @@ impl MeshRenderer {
         self.id
     }
 }
+
+// Renders the mesh at the entity transform every frame through the instanced path
+#[cfg(feature = "render")]
+#[derive(Clone)]
+pub struct EntityRenderer {
+    pub mesh: Arc<RwLock<Mesh>>,
+    pub material: Arc<RwLock<dyn Material>>,
+    pub render_layer: String,
+}
+
+// A light that moves with the entity, such as a held torch or a glowing projectile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EntityLight {
+    pub color: Vec3,
+    pub intensity: f32,
+    pub radius: f32,
+}
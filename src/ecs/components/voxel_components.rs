@@ -0,0 +1,8 @@
+use crate::voxels::voxel_data::VoxelData;
+
+// A voxel that lost its support and is falling, placed back into the scene once it lands
+#[derive(Clone, Copy)]
+pub struct FallingVoxel {
+    pub voxel: VoxelData,
+    pub velocity: f32,
+}
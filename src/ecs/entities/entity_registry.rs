@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use glam::{Quat, Vec3};
+use legion::Entity;
+use tracing::{debug, error};
+
+use crate::{
+    data_packs::resource_files,
+    ecs::components::{
+        physics_components::{Collider, Gravity, Grounded, NoEntityCollision, Velocity},
+        rendering_components::EntityLight,
+        transformation_components::{Position, Rotation},
+    },
+    error::{expect_bool, expect_f32, expect_f32s, read_json, required, AssemblageError},
+    events::{self, EntitySpawned},
+    persistence::entity_persistence::{ChunkOwner, PersistentId},
+};
+
+lazy_static! {
+    static ref ENTITY_PROFILES: HashMap<String, EntityProfile> = load_entity_profiles();
+}
+
+fn load_entity_profiles() -> HashMap<String, EntityProfile> {
+    let mut map = HashMap::new();
+
+    for (name, path) in resource_files("entity_profiles") {
+        match read_json(&path).and_then(|json| EntityProfile::from_json(name.clone(), &json)) {
+            Ok(profile) => {
+                debug!(%name, "Created entity profile");
+                map.insert(name, profile);
+            }
+            Err(e) => error!("Skipping the entity {name}: {e}"),
+        }
+    }
+
+    map
+}
+
+pub fn load() {
+    lazy_static::initialize(&ENTITY_PROFILES);
+}
+
+pub fn get_entity_profile(name: &str) -> Option<&'static EntityProfile> {
+    ENTITY_PROFILES.get(name)
+}
+
+// Identifies which profile an entity was spawned from
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityKind(pub String);
+
+pub struct EntityProfile {
+    pub name: String,
+    pub collider: Option<Vec3>,
+    pub gravity: Option<f32>,
+    pub light: Option<EntityLight>,
+    // Whether the entity pushes and is pushed by other entities
+    pub entity_collision: bool,
+}
+
+impl EntityProfile {
+    pub fn from_json(name: String, json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let collider = json
+            .get("collider")
+            .map(|v| expect_f32s::<3>(v, "collider").map(Vec3::from))
+            .transpose()?;
+        let gravity = json
+            .get("gravity")
+            .map(|v| expect_f32(v, "gravity"))
+            .transpose()?;
+        let light = match json.get("light") {
+            Some(v) => Some(EntityLight {
+                color: Vec3::from(expect_f32s::<3>(required(v, "color")?, "color")?),
+                intensity: v
+                    .get("intensity")
+                    .map_or(Ok(1.0), |i| expect_f32(i, "intensity"))?,
+                radius: expect_f32(required(v, "radius")?, "radius")?,
+            }),
+            None => None,
+        };
+        let entity_collision = json
+            .get("entity collision")
+            .map_or(Ok(true), |v| expect_bool(v, "entity collision"))?;
+        Ok(Self {
+            name,
+            collider,
+            gravity,
+            light,
+            entity_collision,
+        })
+    }
+
+    pub fn spawn(&self, world: &mut legion::World, position: Vec3) -> Entity {
+        let entity = world.push((
+            EntityKind(self.name.clone()),
+            PersistentId::new(),
+            ChunkOwner(None),
+            Position(position),
+            Rotation(Quat::IDENTITY),
+            Velocity(Vec3::ZERO),
+        ));
+        let mut entry = world.entry(entity).unwrap();
+        if let Some(half_extents) = self.collider {
+            entry.add_component(Collider { half_extents });
+            entry.add_component(Grounded(false));
+        }
+        if !self.entity_collision {
+            entry.add_component(NoEntityCollision);
+        }
+        if let Some(gravity) = self.gravity {
+            entry.add_component(Gravity(gravity));
+        }
+        if let Some(light) = self.light {
+            entry.add_component(light);
+        }
+        events::emit(&mut EntitySpawned {
+            entity,
+            kind: self.name.clone(),
+            position,
+        });
+        entity
+    }
+}
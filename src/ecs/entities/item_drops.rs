@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use glam::{Quat, Vec3};
+use legion::Entity;
+use parking_lot::RwLock;
+use rand::Rng;
+
+use crate::{
+    ecs::components::{
+        item_components::DroppedItem,
+        physics_components::{Collider, Gravity, Grounded, NoEntityCollision, Velocity},
+        transformation_components::{Position, Rotation},
+    },
+    persistence::entity_persistence::{ChunkOwner, PersistentId},
+    random,
+};
+
+pub const MAX_STACK_SIZE: u32 = 64;
+// Seconds before a dropped item is removed from the world
+pub const DESPAWN_TIME: f32 = 300.0;
+// Seconds before a dropped item can be picked up
+pub const PICKUP_DELAY: f32 = 0.5;
+pub const MERGE_RADIUS: f32 = 1.0;
+const ITEM_HALF_EXTENTS: Vec3 = Vec3::splat(0.125);
+const ITEM_GRAVITY: f32 = 20.0;
+const DROP_SPEED: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ItemPickup {
+    pub collector: Entity,
+    pub voxel_id: u16,
+    pub count: u32,
+}
+
+// Returns the number of items that were accepted, the rest are left on the ground
+pub type PickupCallback = Arc<dyn Fn(&ItemPickup) -> u32 + Send + Sync>;
+
+lazy_static! {
+    static ref PICKUP_CALLBACKS: RwLock<Vec<PickupCallback>> = RwLock::new(Vec::new());
+}
+
+pub fn register_pickup_callback(callback: PickupCallback) {
+    PICKUP_CALLBACKS.write().push(callback);
+}
+
+// Without any callbacks registered items are simply collected
+pub fn handle_pickup(pickup: &ItemPickup) -> u32 {
+    let callbacks = PICKUP_CALLBACKS.read();
+    if callbacks.is_empty() {
+        return pickup.count;
+    }
+    let mut accepted = 0;
+    for callback in callbacks.iter() {
+        let remaining = pickup.count - accepted;
+        if remaining == 0 {
+            break;
+        }
+        accepted += callback(&ItemPickup {
+            count: remaining,
+            ..*pickup
+        })
+        .min(remaining);
+    }
+    accepted
+}
+
+// Spawns an item that pops out of the given position in a random direction
+pub fn spawn_dropped_item(
+    world: &mut legion::World,
+    position: Vec3,
+    voxel_id: u16,
+    count: u32,
+) -> Entity {
+    let mut rng = random::voxel_rng(position.round().as_ivec3(), "item_drop");
+    let velocity = Vec3::new(rng.gen_range(-0.5..0.5), 1.0, rng.gen_range(-0.5..0.5)) * DROP_SPEED;
+    let entity = world.push((
+        DroppedItem {
+            voxel_id,
+            count: count.min(MAX_STACK_SIZE),
+            age: 0.0,
+        },
+        PersistentId::new(),
+        ChunkOwner(None),
+        Position(position),
+        Rotation(Quat::IDENTITY),
+        Velocity(velocity),
+        Collider {
+            half_extents: ITEM_HALF_EXTENTS,
+        },
+        Gravity(ITEM_GRAVITY),
+        Grounded(false),
+    ));
+    // Dropped stacks merge instead of pushing each other away
+    if let Some(mut entry) = world.entry(entity) {
+        entry.add_component(NoEntityCollision);
+    }
+    entity
+}
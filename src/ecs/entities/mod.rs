@@ -1 +1,8 @@
+pub mod entity_registry;
 pub mod entity_types;
+pub mod item_drops;
+pub mod observer;
+pub mod player;
+pub mod projectiles;
+pub mod spawn_rules;
+pub mod spawner;
@@ -0,0 +1,74 @@
+use std::{fs, mem, path::PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use parking_lot::Mutex;
+
+use crate::{
+    config,
+    data_packs::resource_files,
+    ecs::components::cinematic_components::{CameraPath, Keyframe},
+    error::read_json,
+};
+
+lazy_static! {
+    static ref CONTROLS: Mutex<Vec<PathControl>> = Mutex::new(Vec::new());
+    // Keyframes taken from the camera with PathControl::Keyframe, until they are cleared
+    static ref RECORDING: Mutex<CameraPath> = Mutex::new(CameraPath::default());
+}
+
+// Playback controls for the observer, queued from the console and applied by direct_observers on
+// the next frame
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathControl {
+    // Hands the camera to an observer flying the path from its start
+    Play(CameraPath),
+    Pause,
+    Resume,
+    Seek(f32),
+    Speed(f32),
+    Loop(bool),
+    FixedStep(Option<f32>),
+    // Ends the flight and gives the camera back
+    Stop,
+    // Records the camera's current view as a keyframe at the time
+    Keyframe(f32),
+}
+
+pub fn control(control: PathControl) {
+    CONTROLS.lock().push(control);
+}
+
+pub fn take_controls() -> Vec<PathControl> {
+    mem::take(&mut *CONTROLS.lock())
+}
+
+pub fn record_keyframe(keyframe: Keyframe) {
+    RECORDING.lock().insert(keyframe);
+}
+
+pub fn recorded_path() -> CameraPath {
+    RECORDING.lock().clone()
+}
+
+pub fn clear_recording() {
+    *RECORDING.lock() = CameraPath::default();
+}
+
+// Camera paths are read from the "camera_paths" folder of the resources and data packs
+pub fn load_camera_path(name: &str) -> Result<CameraPath> {
+    let (_, path) = resource_files("camera_paths")
+        .into_iter()
+        .find(|(file, _)| file == name)
+        .ok_or_else(|| anyhow!("There is no camera path named {name}"))?;
+    Ok(read_json(&path).and_then(|json| CameraPath::from_json(&json))?)
+}
+
+// Written to the base resources, so the path can be played by name afterwards
+pub fn save_camera_path(name: &str, path: &CameraPath) -> Result<PathBuf> {
+    let folder = config::current().resources.base.join("camera_paths");
+    fs::create_dir_all(&folder)?;
+    let file = folder.join(format!("{name}.json"));
+    fs::write(&file, serde_json::to_string_pretty(&path.to_json())?)
+        .with_context(|| format!("Failed to write {}", file.display()))?;
+    Ok(file)
+}
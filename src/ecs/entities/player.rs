@@ -0,0 +1,101 @@
+#[cfg(feature = "render")]
+use std::sync::Arc;
+
+use glam::{Quat, Vec3};
+use legion::{Entity, IntoQuery};
+#[cfg(feature = "render")]
+use parking_lot::RwLock;
+
+use crate::ecs::components::{
+    item_components::ItemCollector,
+    physics_components::{Collider, Gravity, Grounded, NoEntityCollision, Velocity},
+    player_components::{GameMode, Player, PlayerId, SchematicClipboard, Spectating},
+    transformation_components::{Position, Rotation},
+};
+#[cfg(feature = "render")]
+use crate::{ecs::components::camera::Camera, rendering};
+
+pub const PLAYER_HALF_EXTENTS: Vec3 = Vec3::new(0.3, 0.9, 0.3);
+const PLAYER_GRAVITY: f32 = 25.0;
+const PLAYER_PICKUP_RADIUS: f32 = 2.0;
+
+// Spawns the server side of a player, the client attaches its camera with attach_camera
+pub fn spawn_player(
+    world: &mut legion::World,
+    position: Vec3,
+    rotation: Quat,
+    player: Player,
+) -> Entity {
+    let entity = world.push((
+        Position(position),
+        Rotation(rotation),
+        player,
+        ItemCollector {
+            radius: PLAYER_PICKUP_RADIUS,
+        },
+        SchematicClipboard::default(),
+        Velocity(Vec3::ZERO),
+        Collider {
+            half_extents: PLAYER_HALF_EXTENTS,
+        },
+        Grounded(false),
+    ));
+    // Clients move their own player, a push on the server would be overwritten
+    if let Some(mut entry) = world.entry(entity) {
+        entry.add_component(NoEntityCollision);
+    }
+    set_game_mode(world, entity, player.game_mode);
+    entity
+}
+
+#[cfg(feature = "render")]
+pub fn attach_camera(
+    world: &mut legion::World,
+    entity: Entity,
+    camera: Arc<RwLock<rendering::camera::Camera>>,
+) {
+    if let Some(mut entry) = world.entry(entity) {
+        entry.add_component(Camera { camera });
+    }
+}
+
+pub fn find_player(world: &legion::World, id: PlayerId) -> Option<Entity> {
+    let mut query = <(Entity, &PlayerId)>::query();
+    query
+        .iter(world)
+        .find(|(_, player_id)| **player_id == id)
+        .map(|(entity, _)| *entity)
+}
+
+// Players that can't fly are affected by gravity. Spectators lose their collider and stop picking
+// up items, they get them back when they change to another mode
+pub fn set_game_mode(world: &mut legion::World, entity: Entity, game_mode: GameMode) {
+    let mut entry = match world.entry(entity) {
+        Some(entry) => entry,
+        None => return,
+    };
+    if let Ok(player) = entry.get_component_mut::<Player>() {
+        player.game_mode = game_mode;
+    }
+    if game_mode.can_fly() {
+        entry.remove_component::<Gravity>();
+        if let Ok(velocity) = entry.get_component_mut::<Velocity>() {
+            velocity.0 = Vec3::ZERO;
+        }
+    } else {
+        entry.add_component(Gravity(PLAYER_GRAVITY));
+    }
+    if game_mode.is_spectator() {
+        entry.remove_component::<Collider>();
+        entry.remove_component::<ItemCollector>();
+        entry.add_component(Spectating);
+    } else if entry.get_component::<Spectating>().is_ok() {
+        entry.remove_component::<Spectating>();
+        entry.add_component(Collider {
+            half_extents: PLAYER_HALF_EXTENTS,
+        });
+        entry.add_component(ItemCollector {
+            radius: PLAYER_PICKUP_RADIUS,
+        });
+    }
+}
@@ -0,0 +1,161 @@
+use glam::{Quat, Vec3};
+use legion::Entity;
+
+use crate::{
+    ecs::components::{
+        projectile_components::Projectile,
+        transformation_components::{Position, Rotation},
+    },
+    voxels::{
+        voxel_interaction::{raycast, VoxelHit},
+        voxel_scene::VoxelScene,
+    },
+};
+
+// Seconds before a projectile that never hit anything is removed, so arrows shot into the sky or
+// into unloaded chunks don't pile up
+pub const PROJECTILE_LIFETIME: f32 = 30.0;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ProjectileTarget {
+    Voxel(VoxelHit),
+    Entity(Entity),
+}
+
+// Something a projectile hit along the path it moved this tick
+#[derive(Clone, Copy, Debug)]
+pub struct Impact {
+    pub target: ProjectileTarget,
+    // Where on its path the projectile hit
+    pub position: Vec3,
+    pub distance: f32,
+}
+
+// Fires a projectile from the position, the owner is left out of what it can hit
+pub fn spawn_projectile(
+    world: &mut legion::World,
+    position: Vec3,
+    velocity: Vec3,
+    gravity: f32,
+    radius: f32,
+    owner: Option<Entity>,
+) -> Entity {
+    world.push((
+        Position(position),
+        Rotation(facing(velocity)),
+        Projectile {
+            velocity,
+            gravity,
+            radius,
+            owner,
+            age: 0.0,
+        },
+    ))
+}
+
+// Points the projectile's -Z along its flight, like the camera looks down -Z
+pub fn facing(velocity: Vec3) -> Quat {
+    match velocity.try_normalize() {
+        Some(direction) => Quat::from_rotation_arc(-Vec3::Z, direction),
+        None => Quat::IDENTITY,
+    }
+}
+
+// The share of the way from start to end at which the segment enters the box, 0 if it starts inside
+pub fn segment_enters_box(start: Vec3, end: Vec3, center: Vec3, half_extents: Vec3) -> Option<f32> {
+    let delta = end - start;
+    let min = center - half_extents;
+    let max = center + half_extents;
+    let mut enter = 0.0_f32;
+    let mut exit = 1.0_f32;
+    for axis in 0..3 {
+        if delta[axis] == 0.0 {
+            if start[axis] < min[axis] || start[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let a = (min[axis] - start[axis]) / delta[axis];
+        let b = (max[axis] - start[axis]) / delta[axis];
+        enter = enter.max(a.min(b));
+        exit = exit.min(a.max(b));
+        if enter > exit {
+            return None;
+        }
+    }
+    Some(enter)
+}
+
+// Everything the projectile's path from start to end hits, closest first. Targets are entities with
+// their collider's center and half extents, the path ends at the first solid voxel so entities
+// behind a wall are safe
+pub fn sweep(
+    scene: &VoxelScene,
+    start: Vec3,
+    end: Vec3,
+    radius: f32,
+    targets: &[(Entity, Vec3, Vec3)],
+) -> Vec<Impact> {
+    let length = start.distance(end);
+    let voxel = raycast(scene, start, end - start, length).map(|hit| Impact {
+        target: ProjectileTarget::Voxel(hit),
+        position: start + (end - start).normalize_or_zero() * hit.distance,
+        distance: hit.distance,
+    });
+    let reach = voxel.map_or(length, |impact| impact.distance);
+
+    let mut impacts = targets
+        .iter()
+        .filter_map(|(entity, center, half_extents)| {
+            let share = segment_enters_box(start, end, *center, *half_extents + radius)?;
+            Some(Impact {
+                target: ProjectileTarget::Entity(*entity),
+                position: start.lerp(end, share),
+                distance: share * length,
+            })
+        })
+        .filter(|impact| impact.distance <= reach)
+        .collect::<Vec<_>>();
+    impacts.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+    impacts.extend(voxel);
+    impacts
+}
+
+#[cfg(test)]
+mod projectiles_tests {
+    use super::*;
+
+    #[test]
+    fn segments_enter_boxes_at_their_faces() {
+        let half = Vec3::splat(0.5);
+        let start = Vec3::new(-2.0, 0.0, 0.0);
+        let end = Vec3::new(2.0, 0.0, 0.0);
+        assert_eq!(
+            segment_enters_box(start, end, Vec3::ZERO, half),
+            Some(0.375)
+        );
+        assert_eq!(
+            segment_enters_box(Vec3::ZERO, end, Vec3::ZERO, half),
+            Some(0.0)
+        );
+        // Passes above the box
+        let above = Vec3::new(0.0, 1.0, 0.0);
+        assert_eq!(
+            segment_enters_box(start + above, end + above, Vec3::ZERO, half),
+            None
+        );
+        // Stops short of the box
+        assert_eq!(
+            segment_enters_box(start, Vec3::new(-1.0, 0.0, 0.0), Vec3::ZERO, half),
+            None
+        );
+    }
+
+    #[test]
+    fn projectiles_face_their_flight() {
+        let direction = Vec3::new(1.0, 1.0, 0.0);
+        let forward = facing(direction) * -Vec3::Z;
+        assert!(forward.abs_diff_eq(direction.normalize(), 1e-5));
+        assert_eq!(facing(Vec3::ZERO), Quat::IDENTITY);
+    }
+}
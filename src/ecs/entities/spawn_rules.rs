@@ -0,0 +1,68 @@
+use rand::Rng;
+
+use crate::error::{expect_array, expect_bool, expect_str, expect_u64, required, AssemblageError};
+
+// Biomes list the entities that may spawn in them in the "Spawns" field of their profile
+#[derive(Clone, Debug)]
+pub struct SpawnRule {
+    pub entity: String,
+    pub weight: u32,
+    // Only spawn on voxels that can see the sky
+    pub surface_only: bool,
+    pub min_light: u8,
+    pub max_light: u8,
+    // Maximum number of entities of this kind around the spawn position
+    pub cap: usize,
+    pub group_size: (u32, u32),
+}
+
+impl SpawnRule {
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let get_u64 =
+            |name: &str, default: u64| json.get(name).map_or(Ok(default), |v| expect_u64(v, name));
+        let group_size = match json.get("Group Size") {
+            Some(v) => match expect_array(v, "Group Size")? {
+                [min, max] => (
+                    expect_u64(min, "Group Size")? as u32,
+                    expect_u64(max, "Group Size")? as u32,
+                ),
+                _ => {
+                    return Err(AssemblageError::asset(
+                        "\"Group Size\" has to list the smallest and largest group",
+                    ))
+                }
+            },
+            None => (1, 1),
+        };
+        Ok(Self {
+            entity: expect_str(required(json, "Entity")?, "Entity")?.to_string(),
+            weight: get_u64("Weight", 1)? as u32,
+            surface_only: json
+                .get("Surface")
+                .map_or(Ok(false), |v| expect_bool(v, "Surface"))?,
+            min_light: get_u64("Min Light", 0)? as u8,
+            max_light: get_u64("Max Light", 15)? as u8,
+            cap: get_u64("Cap", 4)? as usize,
+            group_size,
+        })
+    }
+
+    pub fn allows_light(&self, light: u8) -> bool {
+        light >= self.min_light && light <= self.max_light
+    }
+}
+
+pub fn pick_rule<'a, R: Rng>(rules: &'a [SpawnRule], rng: &mut R) -> Option<&'a SpawnRule> {
+    let total: u32 = rules.iter().map(|rule| rule.weight).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut pick = rng.gen_range(0..total);
+    for rule in rules {
+        if pick < rule.weight {
+            return Some(rule);
+        }
+        pick -= rule.weight;
+    }
+    None
+}
@@ -0,0 +1,123 @@
+use glam::{IVec3, Vec3};
+use legion::EntityStore;
+use rand::Rng;
+
+use crate::{
+    ecs::spatial_index::SpatialIndex,
+    environment, random,
+    voxels::{
+        biome_profile::get_biome_by_name,
+        voxel_light::MAX_LIGHT,
+        voxel_scene::{VoxelScene, CHUNK_SIZE},
+        voxel_shapes::voxel_directions,
+    },
+};
+
+use super::{
+    entity_registry::{get_entity_profile, EntityKind},
+    spawn_rules::pick_rule,
+};
+
+// Number of simulation ticks between spawn attempts
+pub const SPAWN_INTERVAL: u64 = 40;
+pub const SPAWN_CAP_RADIUS: f32 = 48.0;
+// How far above a voxel is checked for cover when working out its sky light
+const SKY_CHECK_HEIGHT: i32 = 64;
+pub fn is_sky_exposed(scene: &VoxelScene, position: IVec3) -> bool {
+    (0..SKY_CHECK_HEIGHT).all(|height| {
+        let sample = position + voxel_directions::UP.as_vec() * height;
+        scene.voxel_at(&sample).map_or(true, |v| v.id == 0)
+    })
+}
+
+// Approximate light level from 0 to MAX_LIGHT, positions covered from the sky are always dark
+pub fn sky_light_at(scene: &VoxelScene, position: IVec3) -> u8 {
+    match is_sky_exposed(scene, position) {
+        true => (environment::current().daylight() * MAX_LIGHT as f32).round() as u8,
+        false => 0,
+    }
+}
+
+// Looks for the highest open voxel in the column that has something solid below it
+fn find_spawn_position(scene: &VoxelScene, chunk_pos: IVec3, x: i32, z: i32) -> Option<IVec3> {
+    let base = chunk_pos * CHUNK_SIZE as i32;
+    for y in (0..CHUNK_SIZE as i32).rev() {
+        let position = base + IVec3::new(x, y, z);
+        let is_open = |p: IVec3| scene.voxel_at(&p).map_or(false, |v| v.id == 0);
+        let below_solid = scene
+            .voxel_at(&(position + voxel_directions::DOWN.as_vec()))
+            .map_or(false, |v| v.id != 0);
+        if below_solid && is_open(position) && is_open(position + voxel_directions::UP.as_vec()) {
+            return Some(position);
+        }
+    }
+    None
+}
+
+fn count_nearby(world: &legion::World, index: &SpatialIndex, center: Vec3, kind: &str) -> usize {
+    index
+        .entities_near(center, SPAWN_CAP_RADIUS)
+        .iter()
+        .filter(|(entity, _)| {
+            world.entry_ref(*entity).map_or(false, |entry| {
+                entry
+                    .get_component::<EntityKind>()
+                    .map_or(false, |k| k.0 == kind)
+            })
+        })
+        .count()
+}
+
+// Makes one spawn attempt in every ticking chunk
+pub fn attempt_spawns(scene: &VoxelScene, world: &mut legion::World, index: &SpatialIndex) {
+    for chunk_pos in scene.ticking_chunks() {
+        if scene.chunks.get(&chunk_pos).map_or(true, |c| c.is_empty) {
+            continue;
+        }
+        let mut rng = random::chunk_rng(chunk_pos, "spawn");
+        let biome = match get_biome_by_name(scene.biome_at(&(chunk_pos * CHUNK_SIZE as i32))) {
+            Some(biome) => biome,
+            None => continue,
+        };
+        let rule = match pick_rule(biome.spawn_rules(), &mut rng) {
+            Some(rule) => rule,
+            None => continue,
+        };
+        let profile = match get_entity_profile(&rule.entity) {
+            Some(profile) => profile,
+            None => continue,
+        };
+
+        let position = match find_spawn_position(
+            scene,
+            chunk_pos,
+            rng.gen_range(0..CHUNK_SIZE as i32),
+            rng.gen_range(0..CHUNK_SIZE as i32),
+        ) {
+            Some(position) => position,
+            None => continue,
+        };
+
+        if rule.surface_only && !is_sky_exposed(scene, position) {
+            continue;
+        }
+        let light = sky_light_at(scene, position).max(scene.light_at(&position));
+        if !rule.allows_light(light) {
+            continue;
+        }
+
+        let spawn_center = position.as_vec3();
+        let nearby = count_nearby(world, index, spawn_center, &rule.entity);
+        if nearby >= rule.cap {
+            continue;
+        }
+
+        let group = rng
+            .gen_range(rule.group_size.0..=rule.group_size.1.max(rule.group_size.0))
+            .min((rule.cap - nearby) as u32);
+        for _ in 0..group {
+            let offset = Vec3::new(rng.gen_range(-0.5..0.5), 0.0, rng.gen_range(-0.5..0.5));
+            profile.spawn(world, spawn_center + offset);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod components;
 pub mod entities;
+pub mod spatial_index;
 pub mod systems;
 pub mod world;
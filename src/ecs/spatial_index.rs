@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use glam::{IVec3, Vec3};
+use legion::Entity;
+
+use crate::voxels::voxel_scene::CHUNK_SIZE;
+
+// Buckets entity positions by chunk so range queries only have to look at nearby chunks
+// Rebuilt every simulation tick, so positions can be up to one tick old
+#[derive(Default)]
+pub struct SpatialIndex {
+    buckets: HashMap<IVec3, Vec<(Entity, Vec3)>>,
+    count: usize,
+}
+
+impl SpatialIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn bucket_at(position: Vec3) -> IVec3 {
+        (position / CHUNK_SIZE as f32).floor().as_ivec3()
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.values_mut().for_each(|bucket| bucket.clear());
+        self.count = 0;
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: Vec3) {
+        self.buckets
+            .entry(Self::bucket_at(position))
+            .or_default()
+            .push((entity, position));
+        self.count += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn entities_in_chunk(&self, chunk_pos: &IVec3) -> &[(Entity, Vec3)] {
+        self.buckets
+            .get(chunk_pos)
+            .map_or(&[], |bucket| &bucket[..])
+    }
+
+    pub fn entities_in_aabb(&self, min: Vec3, max: Vec3) -> Vec<Entity> {
+        let min_bucket = Self::bucket_at(min);
+        let max_bucket = Self::bucket_at(max);
+        let mut result = Vec::new();
+        for x in min_bucket.x..=max_bucket.x {
+            for y in min_bucket.y..=max_bucket.y {
+                for z in min_bucket.z..=max_bucket.z {
+                    for (entity, position) in self.entities_in_chunk(&IVec3::new(x, y, z)) {
+                        if position.cmpge(min).all() && position.cmple(max).all() {
+                            result.push(*entity);
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    // Sorted from closest to furthest
+    pub fn entities_near(&self, center: Vec3, radius: f32) -> Vec<(Entity, f32)> {
+        let extents = Vec3::splat(radius);
+        let min_bucket = Self::bucket_at(center - extents);
+        let max_bucket = Self::bucket_at(center + extents);
+        let mut result = Vec::new();
+        for x in min_bucket.x..=max_bucket.x {
+            for y in min_bucket.y..=max_bucket.y {
+                for z in min_bucket.z..=max_bucket.z {
+                    for (entity, position) in self.entities_in_chunk(&IVec3::new(x, y, z)) {
+                        let distance = position.distance(center);
+                        if distance <= radius {
+                            result.push((*entity, distance));
+                        }
+                    }
+                }
+            }
+        }
+        result.sort_by(|a, b| a.1.total_cmp(&b.1));
+        result
+    }
+}
+
+#[cfg(test)]
+mod spatial_index_tests {
+    use glam::Vec3;
+
+    use super::SpatialIndex;
+
+    fn test_entities(count: usize) -> Vec<legion::Entity> {
+        let mut world = legion::World::default();
+        (0..count).map(|i| world.push((i,))).collect()
+    }
+
+    #[test]
+    fn aabb_test() {
+        let entities = test_entities(3);
+        let mut index = SpatialIndex::new();
+        index.insert(entities[0], Vec3::new(1.0, 1.0, 1.0));
+        index.insert(entities[1], Vec3::new(15.5, 2.0, 1.0));
+        index.insert(entities[2], Vec3::new(40.0, 2.0, 1.0));
+
+        let found = index.entities_in_aabb(Vec3::ZERO, Vec3::new(20.0, 5.0, 5.0));
+        assert_eq!(found.len(), 2);
+        assert!(found.contains(&entities[0]));
+        assert!(found.contains(&entities[1]));
+    }
+
+    #[test]
+    fn near_test() {
+        let entities = test_entities(3);
+        let mut index = SpatialIndex::new();
+        index.insert(entities[0], Vec3::new(-1.0, 0.0, 0.0));
+        index.insert(entities[1], Vec3::new(3.0, 0.0, 0.0));
+        index.insert(entities[2], Vec3::new(0.0, 10.0, 0.0));
+
+        let found = index.entities_near(Vec3::ZERO, 5.0);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, entities[0]);
+        assert_eq!(found[1].0, entities[1]);
+    }
+
+    #[test]
+    fn near_ignores_nan_positions() {
+        let entities = test_entities(2);
+        let mut index = SpatialIndex::new();
+        index.insert(entities[0], Vec3::new(f32::NAN, 0.0, 0.0));
+        index.insert(entities[1], Vec3::new(1.0, 0.0, 0.0));
+
+        let found = index.entities_near(Vec3::ZERO, 5.0);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, entities[1]);
+    }
+}
@@ -1,8 +1,18 @@
-use legion::system;
+use std::sync::Arc;
 
-use crate::ecs::components::{
-    camera::Camera,
-    transformation_components::{Position, Rotation},
+use legion::{system, systems::CommandBuffer, world::SubWorld, Entity, IntoQuery};
+use tracing::{info, warn};
+
+use crate::{
+    ecs::{
+        components::{
+            camera::Camera,
+            cinematic_components::{CameraPath, Keyframe, Observer, PathPlayback},
+            transformation_components::{Position, Rotation},
+        },
+        entities::observer::{self, record_keyframe, take_controls, PathControl},
+    },
+    time::Time,
 };
 
 #[system(for_each)]
@@ -12,3 +22,117 @@ pub fn update_camera(pos: &Position, rot: &Rotation, camera: &mut Camera) {
     cam_lock.rotation = rot.0;
     cam_lock.update_uniform();
 }
+
+// Applies the queued path controls and flies the observer along its path. Playing a path moves the
+// camera from its entity to a new observer, stopping or reaching the end of a path that doesn't loop
+// gives it back
+#[system]
+#[read_component(Camera)]
+#[read_component(Observer)]
+#[read_component(CameraPath)]
+#[write_component(PathPlayback)]
+#[write_component(Position)]
+#[write_component(Rotation)]
+pub fn direct_observers(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] time: &Time,
+) {
+    let camera = <(Entity, &Camera)>::query()
+        .iter(world)
+        .next()
+        .map(|(entity, camera)| (*entity, Arc::clone(&camera.camera)));
+    let observer = <(Entity, &Observer)>::query()
+        .iter(world)
+        .next()
+        .map(|(entity, observer)| (*entity, *observer));
+    let stop = |commands: &mut CommandBuffer, (entity, observer): (Entity, Observer)| {
+        commands.remove(entity);
+        if let (Some(previous), Some((_, camera))) = (observer.previous, &camera) {
+            commands.add_component(
+                previous,
+                Camera {
+                    camera: Arc::clone(camera),
+                },
+            );
+        }
+    };
+
+    let mut playbacks = <&mut PathPlayback>::query();
+    let mut controls = take_controls().into_iter();
+    while let Some(control) = controls.next() {
+        let playback = observer.and_then(|(entity, _)| playbacks.get_mut(world, entity).ok());
+        match (control, playback) {
+            (PathControl::Play(path), _) => match (&camera, path.sample(0.0)) {
+                (Some((holder, view)), Some((position, rotation))) => {
+                    // A new path replaces the one playing, the camera goes back to the same entity
+                    let previous = match observer {
+                        Some((entity, observer)) => {
+                            commands.remove(entity);
+                            observer.previous
+                        }
+                        None => {
+                            commands.remove_component::<Camera>(*holder);
+                            Some(*holder)
+                        }
+                    };
+                    info!("Playing a {:.1}s camera path", path.duration());
+                    commands.push((
+                        Position(position),
+                        Rotation(rotation),
+                        path,
+                        PathPlayback::default(),
+                        Observer { previous },
+                        Camera {
+                            camera: Arc::clone(view),
+                        },
+                    ));
+                    // The observer only exists once the commands ran, the controls after it wait
+                    // for the next frame
+                    controls.for_each(observer::control);
+                    return;
+                }
+                (None, _) => warn!("There is no camera to fly along the path"),
+                (_, None) => warn!("The camera path has no keyframes"),
+            },
+            (PathControl::Keyframe(time), _) => match &camera {
+                Some((_, view)) => {
+                    let view = view.read();
+                    record_keyframe(Keyframe {
+                        time,
+                        position: view.position,
+                        rotation: view.rotation,
+                    });
+                }
+                None => warn!("There is no camera to take the keyframe from"),
+            },
+            (PathControl::Stop, _) => {
+                if let Some(observer) = observer {
+                    stop(commands, observer);
+                }
+                return;
+            }
+            (_, None) => warn!("No camera path is playing"),
+            (PathControl::Pause, Some(playback)) => playback.playing = false,
+            (PathControl::Resume, Some(playback)) => playback.playing = true,
+            (PathControl::Seek(time), Some(playback)) => playback.time = time.max(0.0),
+            (PathControl::Speed(speed), Some(playback)) => playback.speed = speed,
+            (PathControl::Loop(looping), Some(playback)) => playback.looping = looping,
+            (PathControl::FixedStep(step), Some(playback)) => playback.fixed_step = step,
+        }
+    }
+
+    let mut query = <(&CameraPath, &mut PathPlayback, &mut Position, &mut Rotation)>::query();
+    for (path, playback, position, rotation) in query.iter_mut(world) {
+        let playing = playback.advance(time.delta_time as f32, path.duration());
+        if let Some((sampled_position, sampled_rotation)) = path.sample(playback.time) {
+            position.0 = sampled_position;
+            rotation.0 = sampled_rotation;
+        }
+        if !playing {
+            if let Some(observer) = observer {
+                stop(commands, observer);
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::{path::PathBuf, sync::Arc};
+
+use legion::system;
+use parking_lot::{Mutex, RwLock};
+use tracing::{debug, error};
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    client::debug_views::{self, DebugView},
+    ecs::components::player_components::SchematicClipboard,
+    editor::{apply_brush, Brush, BrushShape, MAX_BRUSH_RADIUS},
+    input_manager,
+    targeting::Targeting,
+    voxels::{
+        edit_history::{record_edit, EditHistory},
+        schematic::Schematic,
+        voxel_scene::VoxelScene,
+    },
+};
+
+const CLIPBOARD_PATH: &str = "./saves/schematics/clipboard.schematic";
+
+// F6 marks the targeted voxel as a corner, F7 copies the marked box,
+// F8 pastes against the targeted face and F9 rotates the next paste,
+// pastes can be undone with ctrl+Z and redone with ctrl+Y
+#[system(for_each)]
+pub fn schematic_debug_tools(
+    clipboard: &mut SchematicClipboard,
+    #[resource] targeting: &Targeting,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+    #[resource] history: &Arc<Mutex<EditHistory>>,
+) {
+    if !debug_views::is_enabled(DebugView::SchematicTools) {
+        return;
+    }
+    if input_manager::get_key_down(VirtualKeyCode::F9) {
+        clipboard.transform = clipboard.transform.rotated();
+        debug!(
+            "Paste rotation: {}",
+            clipboard.transform.rotation as u32 * 90
+        );
+    }
+
+    let scene_lock = scene.read();
+    let hit = targeting.hit();
+
+    if input_manager::get_key_down(VirtualKeyCode::F6) {
+        if let Some(hit) = hit {
+            clipboard.corners = [Some(hit.position), clipboard.corners[0]];
+            debug!("Marked corner {}", hit.position);
+        }
+    }
+
+    if input_manager::get_key_down(VirtualKeyCode::F7) {
+        if let [Some(a), Some(b)] = clipboard.corners {
+            let schematic = Schematic::capture(&scene_lock, a, b);
+            match schematic.save(&PathBuf::from(CLIPBOARD_PATH)) {
+                Ok(()) => debug!("Copied {} to {CLIPBOARD_PATH}", schematic.size),
+                Err(e) => error!("Failed to save the schematic: {e}"),
+            }
+        }
+    }
+
+    if input_manager::get_key_down(VirtualKeyCode::F8) {
+        if let Some(hit) = hit {
+            match Schematic::load(&PathBuf::from(CLIPBOARD_PATH)) {
+                Ok(schematic) => {
+                    let placed = record_edit(history, &scene_lock, "Paste schematic", |scene| {
+                        schematic.place(scene, hit.position + hit.normal, clipboard.transform)
+                    });
+                    debug!("Pasted {placed} voxels");
+                }
+                Err(e) => error!("Failed to load the schematic: {e}"),
+            }
+        }
+    }
+
+    if input_manager::get_key(VirtualKeyCode::LControl) {
+        if input_manager::get_key_down(VirtualKeyCode::Z) {
+            let undone = history.lock().undo(&scene_lock, 1);
+            debug!("Undid {undone} edits");
+        }
+        if input_manager::get_key_down(VirtualKeyCode::Y) {
+            let redone = history.lock().redo(&scene_lock, 1);
+            debug!("Redid {redone} edits");
+        }
+    }
+}
+
+// While the brushes view is on B applies the brush at the targeted voxel, N switches the
+// operation, M the shape, [ and ] change the radius and V takes the targeted voxel as material
+#[system]
+pub fn brush_tools(
+    #[state] brush: &mut Brush,
+    #[resource] targeting: &Targeting,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+    #[resource] history: &Arc<Mutex<EditHistory>>,
+) {
+    if !debug_views::is_enabled(DebugView::Brushes) {
+        return;
+    }
+    if input_manager::get_key_down(VirtualKeyCode::N) {
+        brush.operation = brush.operation.next();
+        debug!("Brush operation: {}", brush.operation.name());
+    }
+    if input_manager::get_key_down(VirtualKeyCode::M) {
+        brush.shape = match brush.shape {
+            BrushShape::Sphere => BrushShape::Cube,
+            BrushShape::Cube => BrushShape::Sphere,
+        };
+        debug!("Brush shape: {:?}", brush.shape);
+    }
+    if input_manager::get_key_down(VirtualKeyCode::LBracket) {
+        brush.radius = brush.radius.saturating_sub(1);
+        debug!("Brush radius: {}", brush.radius);
+    }
+    if input_manager::get_key_down(VirtualKeyCode::RBracket) {
+        brush.radius = (brush.radius + 1).min(MAX_BRUSH_RADIUS);
+        debug!("Brush radius: {}", brush.radius);
+    }
+
+    let hit = match targeting.hit() {
+        Some(hit) => hit,
+        None => return,
+    };
+    let scene_lock = scene.read();
+    if input_manager::get_key_down(VirtualKeyCode::V) {
+        brush.material = hit.voxel;
+        debug!("Brush material: {}", hit.voxel.id);
+    }
+    if input_manager::get_key_down(VirtualKeyCode::B) {
+        let changed = apply_brush(history, &scene_lock, brush, &hit);
+        debug!("Brush changed {changed} voxels");
+    }
+}
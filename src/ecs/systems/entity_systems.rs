@@ -0,0 +1,235 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use glam::{IVec3, Vec3};
+use legion::{query::component, system, world::SubWorld, Entity, IntoQuery};
+use parking_lot::RwLock;
+
+use crate::{
+    ecs::{
+        components::{
+            physics_components::{Collider, Gravity, Grounded, NoEntityCollision, Velocity},
+            player_components::Spectating,
+            transformation_components::Position,
+        },
+        spatial_index::SpatialIndex,
+    },
+    time::Time,
+    voxels::voxel_scene::VoxelScene,
+};
+
+#[system(for_each)]
+pub fn apply_gravity(velocity: &mut Velocity, gravity: &Gravity, #[resource] time: &Time) {
+    velocity.0.y -= gravity.0 * time.delta_time as f32;
+}
+
+// Moves entities by their velocity, entities with a collider are stopped by solid voxels one axis at a time
+#[system(for_each)]
+pub fn integrate_entities(
+    pos: &mut Position,
+    velocity: &mut Velocity,
+    collider: Option<&Collider>,
+    grounded: Option<&mut Grounded>,
+    #[resource] time: &Time,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    let delta = velocity.0 * time.delta_time as f32;
+    let collider = match collider {
+        Some(collider) => collider,
+        None => {
+            pos.0 += delta;
+            return;
+        }
+    };
+
+    let scene_lock = scene.read();
+    let mut on_ground = false;
+    for axis in 0..3 {
+        let mut step = Vec3::ZERO;
+        step[axis] = delta[axis];
+        let target = pos.0 + step;
+        if collides(&scene_lock, target, collider.half_extents) {
+            if axis == 1 && delta.y < 0.0 {
+                on_ground = true;
+            }
+            velocity.0[axis] = 0.0;
+        } else {
+            pos.0 = target;
+        }
+    }
+
+    if let Some(grounded) = grounded {
+        grounded.0 = on_ground;
+    }
+}
+
+// Voxels are centered on their integer position and span half a unit in every direction
+pub fn collides(scene: &VoxelScene, center: Vec3, half_extents: Vec3) -> bool {
+    let min = (center - half_extents + 0.5).floor().as_ivec3();
+    let max = (center + half_extents + 0.5).ceil().as_ivec3() - IVec3::ONE;
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                if scene
+                    .voxel_at(&IVec3::new(x, y, z))
+                    .map_or(false, |voxel| voxel.id != 0)
+                {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// Share of the overlap between two entities resolved each tick, so crowds spread out over a few
+// ticks instead of jumping apart
+const PUSH_STRENGTH: f32 = 0.25;
+
+// How far the first box has to move to stop overlapping the second, or None if they don't overlap.
+// Entities are pushed apart horizontally along the axis they overlap least on, boxes exactly on top
+// of each other are told apart by which_first
+pub fn push_out(center: Vec3, half_extents: Vec3, other: Vec3, other_half: Vec3) -> Option<Vec3> {
+    let offset = center - other;
+    let overlap = half_extents + other_half - offset.abs();
+    if overlap.cmple(Vec3::ZERO).any() {
+        return None;
+    }
+    let axis = match overlap.x <= overlap.z {
+        true => 0,
+        false => 2,
+    };
+    let mut push = Vec3::ZERO;
+    push[axis] = match offset[axis] < 0.0 {
+        true => -overlap[axis],
+        false => overlap[axis],
+    };
+    Some(push)
+}
+
+// An order for entities at the same position, both of them have to agree on who moves which way
+fn which_first(entity: Entity, other: Entity) -> bool {
+    let order = |entity: Entity| {
+        let mut hasher = DefaultHasher::new();
+        entity.hash(&mut hasher);
+        hasher.finish()
+    };
+    order(entity) < order(other)
+}
+
+// Pushes overlapping entities with colliders apart. Neighbours are found in the spatial index from
+// the last tick, the overlap is measured with the positions integrate_entities just moved them to
+#[system]
+#[read_component(Collider)]
+#[read_component(NoEntityCollision)]
+#[write_component(Position)]
+pub fn separate_entities(
+    world: &mut SubWorld,
+    #[resource] index: &Arc<RwLock<SpatialIndex>>,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    let mut query =
+        <(Entity, &Position, &Collider)>::query().filter(!component::<NoEntityCollision>());
+    let bodies = query
+        .iter(world)
+        .map(|(entity, position, collider)| (*entity, (position.0, collider.half_extents)))
+        .collect::<HashMap<_, _>>();
+    let reach = bodies
+        .values()
+        .fold(Vec3::ZERO, |reach, (_, half_extents)| {
+            reach.max(*half_extents)
+        });
+
+    let index_lock = index.read();
+    let mut pushes = Vec::new();
+    for (entity, (center, half_extents)) in &bodies {
+        let extents = *half_extents + reach;
+        let mut push = Vec3::ZERO;
+        for other in index_lock.entities_in_aabb(*center - extents, *center + extents) {
+            let (other_center, other_half) = match bodies.get(&other) {
+                Some(body) if other != *entity => body,
+                _ => continue,
+            };
+            let mut other_center = *other_center;
+            if other_center.x == center.x && other_center.z == center.z {
+                other_center.x += match which_first(*entity, other) {
+                    true => 1e-3,
+                    false => -1e-3,
+                };
+            }
+            if let Some(out) = push_out(*center, *half_extents, other_center, *other_half) {
+                push += out * PUSH_STRENGTH;
+            }
+        }
+        if push != Vec3::ZERO {
+            pushes.push((*entity, push));
+        }
+    }
+    drop(index_lock);
+
+    // Like integrate_entities, an entity isn't pushed into solid voxels
+    let scene_lock = scene.read();
+    let mut positions = <&mut Position>::query();
+    for (entity, push) in pushes {
+        let half_extents = bodies[&entity].1;
+        if let Ok(position) = positions.get_mut(world, entity) {
+            for axis in [0, 2] {
+                let mut target = position.0;
+                target[axis] += push[axis];
+                if !collides(&scene_lock, target, half_extents) {
+                    position.0 = target;
+                }
+            }
+        }
+    }
+}
+
+// Spectators are left out, so pushing, pickup, projectiles and spawn caps never find them
+#[system]
+#[read_component(Position)]
+#[read_component(Spectating)]
+pub fn rebuild_spatial_index(world: &SubWorld, #[resource] index: &Arc<RwLock<SpatialIndex>>) {
+    let mut index_lock = index.write();
+    index_lock.clear();
+    let mut query = <(Entity, &Position)>::query().filter(!component::<Spectating>());
+    query.iter(world).for_each(|(entity, position)| {
+        index_lock.insert(*entity, position.0);
+    });
+}
+
+#[cfg(test)]
+mod entity_systems_tests {
+    use glam::Vec3;
+
+    use super::push_out;
+
+    #[test]
+    fn overlapping_boxes_are_pushed_apart_along_the_shallow_axis() {
+        let half = Vec3::new(0.5, 1.0, 0.5);
+        let push = push_out(
+            Vec3::new(0.0, 0.0, 0.25),
+            half,
+            Vec3::new(0.75, 0.5, 0.0),
+            half,
+        );
+        assert_eq!(push, Some(Vec3::new(-0.25, 0.0, 0.0)));
+        let push = push_out(Vec3::new(0.0, 0.0, 0.9), half, Vec3::ZERO, half).unwrap();
+        assert!((push.z - 0.1).abs() < 1e-6 && push.x == 0.0);
+    }
+
+    #[test]
+    fn separate_boxes_are_left_alone() {
+        let half = Vec3::splat(0.5);
+        assert_eq!(
+            push_out(Vec3::ZERO, half, Vec3::new(1.0, 0.0, 0.0), half),
+            None
+        );
+        assert_eq!(
+            push_out(Vec3::ZERO, half, Vec3::new(0.0, 2.0, 0.0), half),
+            None
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::{collections::HashMap, sync::Arc};
+
+use glam::Vec3;
+use legion::{system, systems::CommandBuffer, world::SubWorld, Entity, IntoQuery};
+use parking_lot::RwLock;
+
+use crate::{
+    ecs::{
+        components::{
+            item_components::{DroppedItem, ItemCollector},
+            transformation_components::Position,
+        },
+        entities::item_drops::{
+            handle_pickup, ItemPickup, DESPAWN_TIME, MAX_STACK_SIZE, MERGE_RADIUS, PICKUP_DELAY,
+        },
+        spatial_index::SpatialIndex,
+    },
+    persistence::entity_persistence::{mark_owner_dirty, ChunkOwner},
+    time::Time,
+    voxels::voxel_scene::VoxelScene,
+};
+
+#[system(for_each)]
+pub fn age_dropped_items(
+    entity: &Entity,
+    item: &mut DroppedItem,
+    owner: Option<&ChunkOwner>,
+    commands: &mut CommandBuffer,
+    #[resource] time: &Time,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    item.age += time.delta_time as f32;
+    if item.age >= DESPAWN_TIME {
+        if let Some(owner) = owner {
+            mark_owner_dirty(&scene.read(), owner);
+        }
+        commands.remove(*entity);
+    }
+}
+
+// Nearby stacks of the same voxel are combined into one entity
+#[system]
+#[read_component(Position)]
+#[read_component(ChunkOwner)]
+#[write_component(DroppedItem)]
+pub fn merge_dropped_items(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] index: &Arc<RwLock<SpatialIndex>>,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    let mut query = <(Entity, &Position, &DroppedItem)>::query();
+    let mut items: HashMap<Entity, (Vec3, DroppedItem)> = query
+        .iter(world)
+        .map(|(entity, position, item)| (*entity, (position.0, *item)))
+        .collect();
+    if items.len() < 2 {
+        return;
+    }
+
+    // Sorted so the same stack always absorbs the others
+    let mut order = items.keys().copied().collect::<Vec<_>>();
+    order.sort();
+
+    let index_lock = index.read();
+    let mut changed = Vec::new();
+    let mut removed = Vec::new();
+    for entity in order {
+        let (position, mut target) = match items.get(&entity) {
+            Some(item) => *item,
+            None => continue, // Already merged into another stack
+        };
+        for (other, _) in index_lock.entities_near(position, MERGE_RADIUS) {
+            if other == entity {
+                continue;
+            }
+            let other_item = match items.get(&other) {
+                Some((_, item)) => *item,
+                None => continue,
+            };
+            if other_item.voxel_id != target.voxel_id
+                || target.count + other_item.count > MAX_STACK_SIZE
+            {
+                continue;
+            }
+            target.count += other_item.count;
+            target.age = target.age.min(other_item.age);
+            items.remove(&other);
+            commands.remove(other);
+            removed.push(other);
+            changed.push(entity);
+        }
+        items.insert(entity, (position, target));
+    }
+    drop(index_lock);
+    mark_owners_dirty(world, &scene.read(), removed.iter().chain(&changed));
+
+    let mut query = <&mut DroppedItem>::query();
+    for entity in changed {
+        if let (Ok(item), Some((_, merged))) = (query.get_mut(world, entity), items.get(&entity)) {
+            *item = *merged;
+        }
+    }
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(ItemCollector)]
+#[read_component(ChunkOwner)]
+#[write_component(DroppedItem)]
+pub fn pickup_dropped_items(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] index: &Arc<RwLock<SpatialIndex>>,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    let mut collector_query = <(Entity, &Position, &ItemCollector)>::query();
+    let collectors = collector_query
+        .iter(world)
+        .map(|(entity, position, collector)| (*entity, position.0, collector.radius))
+        .collect::<Vec<_>>();
+    if collectors.is_empty() {
+        return;
+    }
+
+    let index_lock = index.read();
+    let mut item_query = <&mut DroppedItem>::query();
+    let mut changed = Vec::new();
+    for (collector, position, radius) in collectors {
+        for (entity, _) in index_lock.entities_near(position, radius) {
+            let item = match item_query.get_mut(world, entity) {
+                Ok(item) if item.count > 0 && item.age >= PICKUP_DELAY => item,
+                _ => continue,
+            };
+            let accepted = handle_pickup(&ItemPickup {
+                collector,
+                voxel_id: item.voxel_id,
+                count: item.count,
+            });
+            item.count -= accepted.min(item.count);
+            if item.count == 0 {
+                commands.remove(entity);
+            }
+            if accepted > 0 {
+                changed.push(entity);
+            }
+        }
+    }
+    drop(index_lock);
+    mark_owners_dirty(world, &scene.read(), &changed);
+}
+
+// Stacks that were removed or changed count are saved again with the chunk that owns them
+fn mark_owners_dirty<'a>(
+    world: &SubWorld,
+    scene: &VoxelScene,
+    entities: impl IntoIterator<Item = &'a Entity>,
+) {
+    let mut query = <&ChunkOwner>::query();
+    for entity in entities {
+        if let Ok(owner) = query.get(world, *entity) {
+            mark_owner_dirty(scene, owner);
+        }
+    }
+}
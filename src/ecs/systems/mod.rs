@@ -1,3 +1,13 @@
+#[cfg(feature = "render")]
 pub mod camera_systems;
+#[cfg(feature = "render")]
+pub mod debug_systems;
+pub mod entity_systems;
+pub mod item_systems;
+pub mod network_systems;
+#[cfg(feature = "render")]
 pub mod player_controller;
+pub mod projectile_systems;
+#[cfg(feature = "render")]
 pub mod render_systems;
+pub mod voxel_systems;
@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use legion::system;
+
+use crate::{
+    client::Client,
+    ecs::components::{
+        network_components::RemoteEntity,
+        transformation_components::{Position, Rotation},
+    },
+};
+
+#[system(for_each)]
+pub fn interpolate_remote_entities(
+    pos: &mut Position,
+    rot: &mut Rotation,
+    remote: &mut RemoteEntity,
+    #[resource] client: &Arc<Client>,
+) {
+    let time = match client.render_time() {
+        Some(time) => time,
+        None => return,
+    };
+    if let Some((position, rotation)) = remote.buffer.sample(time) {
+        pos.0 = position;
+        rot.0 = rotation;
+    }
+}
@@ -1,21 +1,46 @@
-use glam::{Quat, Vec3};
+use std::sync::Arc;
+
+use glam::{IVec3, Quat, Vec3};
 use legion::system;
-use winit::event::{MouseButton, VirtualKeyCode};
+use parking_lot::RwLock;
 
 use crate::{
+    audio::{self, SoundEvent},
+    client::Client,
     components::{
+        camera::Camera,
+        physics_components::{Grounded, Velocity},
         player_components::Player,
         transformation_components::{Position, Rotation},
     },
-    input_manager::{self, get_mouse_delta},
+    config,
+    ecs::entities::player::PLAYER_HALF_EXTENTS,
+    input_actions::{action_down, action_pressed},
+    input_manager::get_mouse_delta,
+    server::validation::check_place,
+    targeting::{self, PlacementPreview, Targeting},
     time::Time,
+    voxels::{
+        voxel_breaking::{BreakingTool, VoxelBreaking},
+        voxel_data::VoxelData,
+        voxel_interaction::{overlaps_colliders, EditingPlayer},
+        voxel_scene::VoxelScene,
+        voxel_shapes::voxel_shape,
+    },
 };
 
+// Blocks walked between footsteps
+const STEP_LENGTH: f32 = 1.8;
+
 #[system(for_each)]
 pub fn update_players(
     pos: &mut Position,
     rot: &mut Rotation,
     player: &Player,
+    // The player stands still while an observer has the camera
+    _camera: &Camera,
+    velocity: Option<&mut Velocity>,
+    grounded: Option<&Grounded>,
     #[resource] time: &Time,
 ) {
     let mut forward: Vec3 = rot.0.mul_vec3(Vec3::Z).into();
@@ -23,33 +48,177 @@ pub fn update_players(
     let right: Vec3 = rot.0.mul_vec3(Vec3::X).into();
     let up: Vec3 = Vec3::Y;
 
-    if input_manager::get_key(VirtualKeyCode::W) {
-        pos.0 += forward * time.delta_time as f32 * player.fly_speed;
+    let mut input = Vec3::ZERO;
+    if action_pressed("forward") {
+        input += forward;
+    }
+    if action_pressed("back") {
+        input -= forward;
+    }
+    if action_pressed("right") {
+        input += right;
+    }
+    if action_pressed("left") {
+        input -= right;
     }
 
-    if input_manager::get_key(VirtualKeyCode::S) {
-        pos.0 -= forward * time.delta_time as f32 * player.fly_speed;
+    match (player.game_mode.can_fly(), velocity) {
+        // Walking players are moved by the physics systems through their velocity
+        (false, Some(velocity)) => {
+            let horizontal = input.normalize_or_zero() * player.walk_speed;
+            velocity.0.x = horizontal.x;
+            velocity.0.z = horizontal.z;
+            let on_ground = grounded.map_or(false, |g| g.0);
+            if on_ground && action_pressed("jump") {
+                velocity.0.y = player.jump_speed;
+            }
+        }
+        // Spectators have no collider, so nothing stops them inside terrain
+        _ => {
+            if action_pressed("jump") {
+                input += up;
+            }
+            if action_pressed("descend") {
+                input -= up;
+            }
+            pos.0 += input * time.delta_time as f32 * player.fly_speed;
+        }
     }
 
-    if input_manager::get_key(VirtualKeyCode::D) {
-        pos.0 += right * time.delta_time as f32 * player.fly_speed;
+    if action_pressed("look") {
+        let delta = get_mouse_delta() * 0.003;
+        rot.0 = Quat::from_axis_angle(right, delta.y) * rot.0;
+        rot.0 = Quat::from_axis_angle(up, delta.x) * rot.0;
     }
+}
 
-    if input_manager::get_key(VirtualKeyCode::A) {
-        pos.0 -= right * time.delta_time as f32 * player.fly_speed;
+// Finds what the camera's player is looking at within reach, once for everything that uses it
+#[system(for_each)]
+pub fn update_target(
+    pos: &Position,
+    rot: &Rotation,
+    player: &Player,
+    _camera: &Camera,
+    #[resource] targeting: &mut Targeting,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    let direction = rot.0.mul_vec3(Vec3::Z);
+    targeting.update(&scene.read(), pos.0, direction, player.reach);
+}
+
+// The break action, left click by default, breaks the targeted voxel and place, middle click,
+// places the selected voxel against it.
+// The edits are predicted on the client and sent to the server, which validates and applies them
+#[system(for_each)]
+pub fn player_interaction(
+    pos: &Position,
+    player: &Player,
+    _camera: &Camera,
+    #[resource] breaking: &mut VoxelBreaking,
+    #[resource] targeting: &Targeting,
+    #[resource] time: &Time,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+    #[resource] client: &Arc<Client>,
+) {
+    // The server would reject a spectator's edits anyway
+    if !player.game_mode.can_edit() {
+        targeting::set_placement_preview(None);
+        return;
     }
+    let hit = match targeting.hit() {
+        Some(hit) => hit,
+        None => {
+            targeting::set_placement_preview(None);
+            return;
+        }
+    };
+    let scene_lock = scene.read();
+    let position = hit.position + hit.normal;
+    let voxel = VoxelData {
+        shape: voxel_shape::CUBE,
+        state: 0,
+        id: player.selected_voxel,
+    };
+
+    // Other entities are only checked by the server, the client knows where its own player is
+    let colliders = [(pos.0, PLAYER_HALF_EXTENTS)];
+
+    // Regions aren't sent to clients, a protected spot only shows up when the server rejects it
+    let preview = config::current().placement_preview.then(|| {
+        let editor = EditingPlayer {
+            id: client.player_id,
+            player: *player,
+            eye: pos.0,
+            operator: false,
+        };
+        PlacementPreview {
+            position,
+            voxel,
+            valid: check_place(&scene_lock, None, &editor, position, voxel, &colliders).is_ok(),
+        }
+    });
+    targeting::set_placement_preview(preview);
 
-    if input_manager::get_key(VirtualKeyCode::Space) {
-        pos.0 += up * time.delta_time as f32 * player.fly_speed;
+    if player.game_mode.breaks_instantly() {
+        if action_down("break") {
+            break_voxel(client, &scene_lock, hit.position);
+        }
+    } else if action_pressed("break") {
+        let delta = time.delta_time as f32;
+        // A voxel resumed before it healed keeps the start the server already has
+        if breaking.progress(hit.position) == 0.0 {
+            client.start_break(hit.position);
+        }
+        if breaking.hit(hit.position, hit.voxel.id, &BreakingTool::HAND, delta) {
+            break_voxel(client, &scene_lock, hit.position);
+        }
     }
 
-    if input_manager::get_key(VirtualKeyCode::LShift) {
-        pos.0 -= up * time.delta_time as f32 * player.fly_speed;
+    if action_down("place") && !overlaps_colliders(position, &colliders) {
+        client.place_voxel(&scene_lock, position, voxel);
+        audio::emit(SoundEvent::VoxelPlaced { position, voxel });
     }
+}
 
-    if input_manager::get_button(MouseButton::Right) {
-        let delta = get_mouse_delta() * 0.003;
-        rot.0 = Quat::from_axis_angle(right, delta.y) * rot.0;
-        rot.0 = Quat::from_axis_angle(up, delta.x) * rot.0;
+// Heals the voxels the player stopped breaking, after player_interaction has hit this frame's
+#[system]
+pub fn update_breaking(#[resource] breaking: &mut VoxelBreaking, #[resource] time: &Time) {
+    breaking.update(time.delta_time as f32);
+}
+
+// The sound plays right away, like the predicted edit
+fn break_voxel(client: &Client, scene: &VoxelScene, position: IVec3) {
+    if let Some(voxel) = scene.voxel_at(&position) {
+        audio::emit(SoundEvent::VoxelBroken { position, voxel });
+    }
+    client.break_voxel(scene, position);
+}
+
+// Plays a footstep from the voxel under the camera's player every STEP_LENGTH blocks it walks
+#[system(for_each)]
+pub fn footsteps(
+    pos: &Position,
+    velocity: &Velocity,
+    grounded: &Grounded,
+    _camera: &Camera,
+    #[state] walked: &mut f32,
+    #[resource] time: &Time,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    if !grounded.0 {
+        return;
+    }
+    *walked += (velocity.0 * Vec3::new(1.0, 0.0, 1.0)).length() * time.delta_time as f32;
+    if *walked < STEP_LENGTH {
+        return;
+    }
+    *walked = 0.0;
+    let feet = pos.0 - Vec3::Y * PLAYER_HALF_EXTENTS.y;
+    let below = (feet - Vec3::Y * 0.5).floor().as_ivec3();
+    if let Some(ground) = scene.read().voxel_at(&below).filter(|voxel| voxel.id != 0) {
+        audio::emit(SoundEvent::Footstep {
+            position: feet,
+            ground,
+        });
     }
 }
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, sync::Arc};
+
+use glam::Vec3;
+use legion::{
+    query::component, system, systems::CommandBuffer, world::SubWorld, Entity, IntoQuery,
+};
+use parking_lot::RwLock;
+
+use crate::{
+    ecs::{
+        components::{
+            item_components::DroppedItem,
+            physics_components::Collider,
+            projectile_components::Projectile,
+            transformation_components::{Position, Rotation},
+        },
+        entities::projectiles::{facing, sweep, PROJECTILE_LIFETIME},
+        spatial_index::SpatialIndex,
+    },
+    events::{self, ProjectileHit},
+    time::Time,
+    voxels::voxel_scene::VoxelScene,
+};
+
+// Moves projectiles along their ballistic path and checks the part they flew this tick for voxels
+// and entities, so fast projectiles can't skip through thin walls or small entities. Each hit is
+// announced with a ProjectileHit event and removes the projectile unless a handler cancelled it
+#[system]
+#[read_component(Collider)]
+#[read_component(DroppedItem)]
+#[write_component(Position)]
+#[write_component(Rotation)]
+#[write_component(Projectile)]
+pub fn move_projectiles(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] time: &Time,
+    #[resource] index: &Arc<RwLock<SpatialIndex>>,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    let delta_time = time.delta_time as f32;
+    let mut colliders =
+        <(Entity, &Position, &Collider)>::query().filter(!component::<DroppedItem>());
+    let bodies = colliders
+        .iter(world)
+        .map(|(entity, position, collider)| (*entity, (position.0, collider.half_extents)))
+        .collect::<HashMap<_, _>>();
+    let reach = bodies
+        .values()
+        .fold(Vec3::ZERO, |reach, (_, half_extents)| {
+            reach.max(*half_extents)
+        });
+
+    let index_lock = index.read();
+    let scene_lock = scene.read();
+    let mut projectiles = <(Entity, &mut Position, &mut Rotation, &mut Projectile)>::query();
+    for (entity, position, rotation, projectile) in projectiles.iter_mut(world) {
+        projectile.age += delta_time;
+        if projectile.age >= PROJECTILE_LIFETIME {
+            commands.remove(*entity);
+            continue;
+        }
+        projectile.velocity.y -= projectile.gravity * delta_time;
+        let start = position.0;
+        let end = start + projectile.velocity * delta_time;
+
+        // Candidates come from the index of the last tick, their boxes from where they are now
+        let extents = reach + projectile.radius;
+        let targets = index_lock
+            .entities_in_aabb(start.min(end) - extents, start.max(end) + extents)
+            .into_iter()
+            .filter(|target| Some(*target) != projectile.owner)
+            .filter_map(|target| {
+                let (center, half_extents) = bodies.get(&target)?;
+                Some((target, *center, *half_extents))
+            })
+            .collect::<Vec<_>>();
+
+        let mut stopped = false;
+        for impact in sweep(&scene_lock, start, end, projectile.radius, &targets) {
+            let mut event = ProjectileHit {
+                projectile: *entity,
+                owner: projectile.owner,
+                target: impact.target,
+                position: impact.position,
+                velocity: projectile.velocity,
+            };
+            if events::emit(&mut event) {
+                position.0 = impact.position;
+                commands.remove(*entity);
+                stopped = true;
+                break;
+            }
+        }
+        if !stopped {
+            position.0 = end;
+            rotation.0 = facing(projectile.velocity);
+        }
+    }
+}
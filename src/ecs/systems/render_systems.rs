@@ -1,27 +1,53 @@
-use std::sync::{atomic::Ordering, Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use glam::{Mat4, Quat, Vec3};
+use glam::{IVec3, Mat4, Quat, Vec3};
 use legion::{IntoQuery, World};
 
 use crate::{
-    ecs::components::{rendering_components::MeshRenderer, transformation_components::Position},
-    rendering::render_pass_data::render_layers,
+    asset_types::{asset::Asset, mesh::Mesh},
+    client::{
+        debug_views::{self, DebugView},
+        heatmaps::{self, Heatmap},
+    },
+    config,
+    ecs::components::{
+        camera::Camera,
+        rendering_components::{EntityLight, EntityRenderer, MeshRenderer},
+        transformation_components::{Position, Rotation, Scale},
+    },
+    environment::{self, atmosphere},
+    rendering::{
+        dynamic_lights::{PointLightRaw, MAX_DYNAMIC_LIGHTS},
+        instancing::{get_or_create_batch, InstanceRaw, INSTANCED_BATCHES},
+        render_pass_data::render_layers,
+        visibility::{Aabb, CameraView, Frustum},
+    },
     state::State,
+    voxels::voxel_scene::VoxelScene,
 };
 
+// Entities drawn and entities with a renderer in the last frame, for the overlay
+pub static DRAWN_ENTITIES: AtomicUsize = AtomicUsize::new(0);
+pub static RENDERED_ENTITIES: AtomicUsize = AtomicUsize::new(0);
+
 pub fn construct_buffers(state: &State, world: &World) {
-    // Loop through all mesh renderers and append their data to the pass buffers if their data is dirty
+    // Meshes past the frame's upload budget stay dirty and are written on the next frames
+    let mut staging = state.staging.lock();
+    staging.begin_frame(&state.device, config::current().upload_budget);
+
+    // Loop through all mesh renderers and write their data to the pass buffers if their data is dirty
     let mut query = <(&MeshRenderer, &Position)>::query();
     query.iter(world).for_each(|(renderer, position)| {
         if !renderer.dirty.load(Ordering::Relaxed) {
             return;
         }
 
-        let mesh_lock = renderer.mesh.read();
-        if mesh_lock.vertex_count == 0 {
-            return;
-        }
-
         let layer = render_layers::get_layer_by_name(renderer.render_layer.to_string());
         let layer = match layer {
             Some(layer) => layer,
@@ -33,9 +59,186 @@ pub fn construct_buffers(state: &State, world: &World) {
         let transform =
             Mat4::from_scale_rotation_translation(Vec3::ONE, Quat::IDENTITY, position.0);
 
-        pass.write()
-            .insert_mesh(&state, Arc::clone(&renderer.mesh), &transform);
+        // A mesh that became empty gives its range back
+        let (id, empty) = {
+            let mesh_lock = renderer.mesh.read();
+            (mesh_lock.get_id(), mesh_lock.vertex_count == 0)
+        };
+        let mut pass_lock = pass.write();
+        let written = match empty {
+            true => {
+                pass_lock.remove_mesh(id);
+                true
+            }
+            false => {
+                pass_lock.insert_mesh(state, &mut staging, Arc::clone(&renderer.mesh), &transform)
+            }
+        };
 
-        renderer.dirty.store(false, Ordering::Relaxed);
+        if written {
+            renderer.dirty.store(false, Ordering::Relaxed);
+        }
     });
+    staging.submit(&state.queue);
+}
+
+// What the main camera may see this frame. Entities are only drawn in chunks the terrain walk
+// reaches, so a cave full of them behind solid rock costs nothing, and only if their bounds are in
+// the frustum
+struct EntityCulling {
+    frustum: Frustum,
+    chunks: HashSet<IVec3>,
+}
+
+impl EntityCulling {
+    fn new(world: &World, scene: &VoxelScene) -> Option<Self> {
+        if !debug_views::is_enabled(DebugView::EntityCulling) {
+            return None;
+        }
+        let camera = <&Camera>::query().iter(world).next()?.camera.read();
+        let view = CameraView {
+            position: camera.position,
+            view_projection: camera.build_projection_matrix() * camera.build_transform_matrix(),
+        };
+        // The streamed chunks plus the margin they're unloaded at
+        let config = config::current();
+        let radius = (config.view_distance + config.unload_margin) as i32;
+        let chunks = view.visible_chunks(radius, |chunk| scene.face_connectivity(&chunk));
+        Some(Self {
+            frustum: view.frustum(),
+            chunks,
+        })
+    }
+
+    fn is_visible(&self, position: Vec3, bounds: Aabb, transform: &Mat4) -> bool {
+        let chunk = VoxelScene::chunk_at(&position.round().as_ivec3());
+        self.chunks.contains(&chunk) && self.frustum.intersects(&bounds.transformed(transform))
+    }
+}
+
+fn mesh_bounds(mesh: &Mesh) -> Aabb {
+    mesh.get_vertices().iter().fold(
+        Aabb::new(Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |bounds, vertex| {
+            let position = Vec3::from(vertex.position);
+            Aabb::new(bounds.min.min(position), bounds.max.max(position))
+        },
+    )
+}
+
+// Entity transforms change every frame, so the instance buffers are rebuilt from scratch each time
+pub fn construct_instances(state: &State, world: &World, scene: &VoxelScene) {
+    let culling = EntityCulling::new(world, scene);
+    let mut instances: HashMap<(u64, u64), (&EntityRenderer, Vec<InstanceRaw>)> = HashMap::new();
+    // Meshes are shared by many entities, their bounds are only worked out once a frame
+    let mut bounds: HashMap<u64, Aabb> = HashMap::new();
+    let mut rendered = 0;
+    let mut query = <(
+        &EntityRenderer,
+        &Position,
+        Option<&Rotation>,
+        Option<&Scale>,
+    )>::query();
+    query
+        .iter(world)
+        .for_each(|(renderer, position, rotation, scale)| {
+            rendered += 1;
+            let mesh = renderer.mesh.read();
+            let key = (mesh.get_id(), renderer.material.read().get_id());
+            let transform = Mat4::from_scale_rotation_translation(
+                scale.map_or(Vec3::ONE, |s| s.0),
+                rotation.map_or(Quat::IDENTITY, |r| r.0),
+                position.0,
+            );
+            if let Some(culling) = &culling {
+                let bounds = *bounds.entry(key.0).or_insert_with(|| mesh_bounds(&mesh));
+                if !culling.is_visible(position.0, bounds, &transform) {
+                    return;
+                }
+            }
+            instances
+                .entry(key)
+                .or_insert_with(|| (renderer, Vec::new()))
+                .1
+                .push(InstanceRaw::new(&transform));
+        });
+    RENDERED_ENTITIES.store(rendered, Ordering::Relaxed);
+    DRAWN_ENTITIES.store(
+        instances.values().map(|(_, raw)| raw.len()).sum(),
+        Ordering::Relaxed,
+    );
+
+    // Batches without any entities left are kept around but skipped while drawing
+    INSTANCED_BATCHES.iter().for_each(|batch| {
+        if !instances.contains_key(batch.key()) {
+            batch.value().write().instance_count = 0;
+        }
+    });
+
+    for (_key, (renderer, raw)) in instances {
+        let batch = get_or_create_batch(
+            state,
+            &renderer.mesh,
+            &renderer.material,
+            &renderer.render_layer,
+        );
+        batch.write().write_instances(state, &raw);
+    }
+}
+
+// Only the lights closest to the camera fit into the light uniform. The fog of the biome the
+// camera is in goes along with them
+pub fn construct_lights(state: &mut State, world: &World, scene: &VoxelScene) {
+    let environment = environment::current();
+    state.dynamic_lights.set_sun(
+        environment.sun_direction(),
+        environment.sun_intensity(),
+        environment.ambient_light(),
+    );
+    let mut camera_query = <&Camera>::query();
+    let camera_position = camera_query
+        .iter(world)
+        .next()
+        .map_or(Vec3::ZERO, |camera| camera.camera.read().position);
+    atmosphere::update(scene, camera_position);
+    let (fog_color, fog_density) =
+        atmosphere::current().fog(&environment, atmosphere::is_underwater());
+    state
+        .dynamic_lights
+        .set_fog(fog_color, fog_density, camera_position);
+    state
+        .dynamic_lights
+        .set_light_heatmap(heatmaps::active() == Some(Heatmap::Light));
+
+    // Clearing the lights turns them off while the view is hidden
+    if !debug_views::is_enabled(DebugView::DynamicLights) {
+        state.dynamic_lights.set_lights(&state.queue, &[]);
+        return;
+    }
+
+    let colored = config::current().colored_lights;
+    let mut query = <(&EntityLight, &Position)>::query();
+    let mut lights = query
+        .iter(world)
+        .filter(|(light, _)| light.intensity > 0.0 && light.radius > 0.0)
+        .map(|(light, position)| {
+            // White at the color's luminance
+            let color = match colored {
+                true => light.color,
+                false => Vec3::splat(light.color.dot(Vec3::new(0.2126, 0.7152, 0.0722))),
+            };
+            (
+                position.0.distance_squared(camera_position),
+                PointLightRaw::new(position.0, color, light.intensity, light.radius),
+            )
+        })
+        .collect::<Vec<_>>();
+    lights.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    lights.truncate(MAX_DYNAMIC_LIGHTS);
+
+    let raw = lights
+        .into_iter()
+        .map(|(_, light)| light)
+        .collect::<Vec<_>>();
+    state.dynamic_lights.set_lights(&state.queue, &raw);
 }
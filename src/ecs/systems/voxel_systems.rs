@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use legion::{system, systems::CommandBuffer, Entity};
+use parking_lot::RwLock;
+
+use crate::{
+    ecs::components::{transformation_components::Position, voxel_components::FallingVoxel},
+    persistence::entity_persistence::{mark_owner_dirty, ChunkOwner},
+    time::Time,
+    voxels::{voxel_scene::VoxelScene, voxel_shapes::voxel_directions},
+};
+
+const FALLING_GRAVITY: f32 = 30.0;
+const FALLING_TERMINAL_VELOCITY: f32 = 40.0;
+
+#[system(for_each)]
+pub fn update_falling_voxels(
+    entity: &Entity,
+    pos: &mut Position,
+    falling: &mut FallingVoxel,
+    owner: Option<&ChunkOwner>,
+    commands: &mut CommandBuffer,
+    #[resource] time: &Time,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    let scene_lock = scene.read();
+    let velocity = (falling.velocity + FALLING_GRAVITY * time.delta_time as f32)
+        .min(FALLING_TERMINAL_VELOCITY);
+    let target_y = pos.0.y - velocity * time.delta_time as f32;
+
+    // At terminal velocity the voxel moves more than one cell per tick, so every cell it passes
+    // through is checked for something to land on
+    let mut cell = pos.0.round().as_ivec3();
+    loop {
+        let below = match scene_lock.voxel_at(&(cell + voxel_directions::DOWN.as_vec())) {
+            Some(below) => below,
+            None => {
+                // Wait for the chunk below to load before falling into it
+                pos.0.y = target_y.max(cell.y as f32);
+                return;
+            }
+        };
+        if below.id != 0 {
+            if target_y > cell.y as f32 {
+                break;
+            }
+            // Landed, the voxel is placed back into the scene if there is room for it and
+            // dropped as an item otherwise
+            if scene_lock.voxel_at(&cell).map_or(false, |v| v.id == 0) {
+                scene_lock.set_voxel(&cell, falling.voxel);
+            } else {
+                scene_lock.drop_item(&cell, falling.voxel);
+            }
+            if let Some(owner) = owner {
+                mark_owner_dirty(&scene_lock, owner);
+            }
+            commands.remove(*entity);
+            return;
+        }
+        if target_y >= cell.y as f32 - 0.5 {
+            break;
+        }
+        cell += voxel_directions::DOWN.as_vec();
+    }
+
+    falling.velocity = velocity;
+    pos.0.y = target_y;
+}
+
+#[cfg(test)]
+mod voxel_systems_tests {
+    use glam::{IVec3, Vec3};
+    use legion::{Resources, Schedule, World};
+
+    use super::*;
+    use crate::voxels::{
+        voxel_data::VoxelData, voxel_scene::VoxelChunk, voxel_shapes::voxel_shape,
+    };
+
+    fn voxel(id: u16) -> VoxelData {
+        VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id,
+        }
+    }
+
+    // Drops a voxel from the height at terminal velocity onto a one voxel thick floor at y 2,
+    // with the given voxels already in the scene
+    fn fall_onto_floor(height: f32, occupied: &[IVec3]) -> VoxelScene {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        scene.set_voxel(&IVec3::new(4, 2, 4), voxel(1));
+        for position in occupied {
+            scene.set_voxel(position, voxel(5));
+        }
+        let scene = Arc::new(RwLock::new(scene));
+
+        let mut world = World::default();
+        world.push((
+            Position(Vec3::new(4.0, height, 4.0)),
+            FallingVoxel {
+                voxel: voxel(7),
+                velocity: FALLING_TERMINAL_VELOCITY,
+            },
+        ));
+        let mut resources = Resources::default();
+        resources.insert(Time {
+            time: 0.0,
+            delta_time: 1.0 / 20.0,
+        });
+        resources.insert(scene.clone());
+        let mut schedule = Schedule::builder()
+            .add_system(update_falling_voxels_system())
+            .build();
+        for _ in 0..20 {
+            schedule.execute(&mut world, &mut resources);
+        }
+        assert_eq!(world.len(), 0, "the voxel should have landed");
+        drop(resources);
+        Arc::try_unwrap(scene).ok().unwrap().into_inner()
+    }
+
+    #[test]
+    fn fast_voxels_land_on_thin_floors() {
+        // Two voxels per tick, so the fall never ends a tick between y 2.5 and y 3
+        let scene = fall_onto_floor(12.4, &[]);
+        assert_eq!({ scene.voxel_at(&IVec3::new(4, 3, 4)).unwrap().id }, 7);
+        assert_eq!({ scene.voxel_at(&IVec3::new(4, 2, 4)).unwrap().id }, 1);
+        assert_eq!({ scene.voxel_at(&IVec3::new(4, 1, 4)).unwrap().id }, 0);
+        assert!(scene.get_item_drop_receiver().is_empty());
+    }
+
+    #[test]
+    fn voxels_landing_in_an_occupied_cell_are_dropped() {
+        let occupied = IVec3::new(4, 3, 4);
+        let scene = fall_onto_floor(3.3, &[occupied]);
+        assert_eq!({ scene.voxel_at(&occupied).unwrap().id }, 5);
+        let drops = scene
+            .get_item_drop_receiver()
+            .try_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(drops.len(), 1);
+        assert_eq!(drops[0].0, occupied);
+        assert_eq!({ drops[0].1.id }, 7);
+    }
+}
@@ -1,3 +1,114 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use glam::{IVec3, Vec3};
+use parking_lot::Mutex;
+
+use crate::{
+    jobs::JobHandle,
+    voxels::{
+        edit_history::{record_edit, EditHistory},
+        voxel_scene::VoxelScene,
+    },
+};
+
+// Shared behind an RwLock like the VoxelScene. Code holding both locks the world first and the scene
+// second, the order the entity systems use, so two threads can't deadlock on them
 pub struct World {
     pub legion_world: legion::World,
+    // Shared with the systems that make undoable edits
+    pub edit_history: Arc<Mutex<EditHistory>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self {
+            legion_world: legion::World::default(),
+            edit_history: Arc::new(Mutex::new(EditHistory::new())),
+        }
+    }
+
+    // Groups every voxel change made by the edit into a single undoable operation
+    pub fn edit<R>(
+        &self,
+        scene: &VoxelScene,
+        label: &str,
+        edit: impl FnOnce(&VoxelScene) -> R,
+    ) -> R {
+        record_edit(&self.edit_history, scene, label, edit)
+    }
+
+    // Returns the number of operations undone, which is less than count when the history runs out
+    pub fn undo(&self, scene: &VoxelScene, count: usize) -> usize {
+        self.edit_history.lock().undo(scene, count)
+    }
+
+    pub fn redo(&self, scene: &VoxelScene, count: usize) -> usize {
+        self.edit_history.lock().redo(scene, count)
+    }
+
+    // Loads or generates and meshes every chunk within radius chunks of the center, so whatever
+    // arrives there once the returned future completes doesn't find itself in the void
+    pub fn prepare_area(&self, scene: &VoxelScene, center: Vec3, radius: i32) -> AreaReady {
+        let center = VoxelScene::chunk_at(&center.floor().as_ivec3());
+        let mut handles = Vec::new();
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    handles.extend(scene.prepare_chunk(center + IVec3::new(x, y, z)));
+                }
+            }
+        }
+        AreaReady {
+            handles,
+            cancelled: false,
+        }
+    }
+}
+
+// Completes with true once every chunk of the area is ready, or false when some of them were
+// cancelled, for example because they were unloaded again
+pub struct AreaReady {
+    handles: Vec<JobHandle>,
+    cancelled: bool,
+}
+
+impl AreaReady {
+    // Polls without a waker, for callers checking once a tick
+    pub fn check(&mut self) -> Option<bool> {
+        self.drop_finished();
+        match self.handles.is_empty() {
+            true => Some(!self.cancelled),
+            false => None,
+        }
+    }
+
+    fn drop_finished(&mut self) {
+        let cancelled = &mut self.cancelled;
+        self.handles.retain(|handle| {
+            *cancelled |= handle.is_cancelled();
+            !handle.is_finished()
+        });
+    }
+}
+
+impl Future for AreaReady {
+    type Output = bool;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<bool> {
+        let this = self.get_mut();
+        loop {
+            this.drop_finished();
+            match this.handles.first() {
+                None => return Poll::Ready(!this.cancelled),
+                // Polled again once that job finishes, the rest are checked then
+                Some(handle) if handle.wake_when_finished(context.waker()) => return Poll::Pending,
+                Some(_) => {}
+            }
+        }
+    }
 }
@@ -0,0 +1,236 @@
+use glam::IVec3;
+use parking_lot::Mutex;
+
+use crate::voxels::{
+    edit_history::{record_edit, EditHistory},
+    voxel_data::VoxelData,
+    voxel_interaction::VoxelHit,
+    voxel_scene::VoxelScene,
+    voxel_shapes::voxel_shape,
+};
+
+pub const MAX_BRUSH_RADIUS: u32 = 16;
+// A voxel is solid after smoothing when more than this many of its 26 neighbours are
+const SMOOTH_THRESHOLD: usize = 13;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    Sphere,
+    Cube,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushOperation {
+    // Sets every voxel in the brush to the material, an Empty material carves instead
+    Fill,
+    // Voxels follow most of their neighbours, which rounds off edges and closes small holes
+    Smooth,
+    // Empties everything above the hit voxel and fills the gaps at or below it with its material
+    Flatten,
+    // Swaps voxels of the hit voxel's type for the material and leaves the rest alone
+    Replace,
+}
+
+impl BrushOperation {
+    pub const ALL: [BrushOperation; 4] = [
+        BrushOperation::Fill,
+        BrushOperation::Smooth,
+        BrushOperation::Flatten,
+        BrushOperation::Replace,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BrushOperation::Fill => "fill",
+            BrushOperation::Smooth => "smooth",
+            BrushOperation::Flatten => "flatten",
+            BrushOperation::Replace => "replace",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+// An edit centered on a raycast hit, the same brush can be applied over and over while dragging
+#[derive(Clone, Copy)]
+pub struct Brush {
+    pub shape: BrushShape,
+    pub operation: BrushOperation,
+    pub radius: u32,
+    // Placed by fill and replace
+    pub material: VoxelData,
+}
+
+impl Brush {
+    pub fn new(material: VoxelData) -> Self {
+        Self {
+            shape: BrushShape::Sphere,
+            operation: BrushOperation::Fill,
+            radius: 3,
+            material,
+        }
+    }
+
+    pub fn contains(&self, offset: IVec3) -> bool {
+        let radius = self.radius as i32;
+        match self.shape {
+            BrushShape::Sphere => {
+                // The half voxel keeps small spheres from turning into crosses
+                offset.as_vec3().length_squared() <= (radius as f32 + 0.5).powi(2)
+            }
+            BrushShape::Cube => offset.abs().max_element() <= radius,
+        }
+    }
+
+    pub fn positions(&self, center: IVec3) -> impl Iterator<Item = IVec3> + '_ {
+        let radius = self.radius.min(MAX_BRUSH_RADIUS) as i32;
+        (-radius..=radius)
+            .flat_map(move |x| {
+                (-radius..=radius)
+                    .flat_map(move |y| (-radius..=radius).map(move |z| IVec3::new(x, y, z)))
+            })
+            .filter(|offset| self.contains(*offset))
+            .map(move |offset| center + offset)
+    }
+
+    // Every change the brush would make, worked out before anything is written so smoothing
+    // reads the terrain as it was. Voxels in chunks that aren't loaded are left out
+    pub fn plan(&self, scene: &VoxelScene, hit: &VoxelHit) -> Vec<(IVec3, VoxelData)> {
+        let empty = VoxelData { id: 0, ..hit.voxel };
+        self.positions(hit.position)
+            .filter_map(|position| {
+                let current = scene.voxel_at(&position)?;
+                let target = match self.operation {
+                    BrushOperation::Fill => self.material,
+                    BrushOperation::Smooth => smoothed(scene, position, current),
+                    BrushOperation::Flatten if position.y > hit.position.y => empty,
+                    BrushOperation::Flatten if current.id == 0 => hit.voxel,
+                    BrushOperation::Replace if current.id == hit.voxel.id => self.material,
+                    BrushOperation::Flatten | BrushOperation::Replace => current,
+                };
+                (!same_voxel(target, current)).then(|| (position, target))
+            })
+            .collect()
+    }
+
+    // Returns the number of voxels changed
+    pub fn apply(&self, scene: &VoxelScene, hit: &VoxelHit) -> usize {
+        scene.set_voxels(self.plan(scene, hit)).len()
+    }
+}
+
+// Applies the brush as one operation that can be undone
+pub fn apply_brush(
+    history: &Mutex<EditHistory>,
+    scene: &VoxelScene,
+    brush: &Brush,
+    hit: &VoxelHit,
+) -> usize {
+    let label = format!("Brush {}", brush.operation.name());
+    record_edit(history, scene, &label, |scene| brush.apply(scene, hit))
+}
+
+// A voxel that becomes solid takes the material of one of its solid neighbours
+fn smoothed(scene: &VoxelScene, position: IVec3, current: VoxelData) -> VoxelData {
+    let solid = neighbour_offsets()
+        .filter_map(|offset| scene.voxel_at(&(position + offset)))
+        .filter(|voxel| voxel.id != 0)
+        .collect::<Vec<_>>();
+    match (current.id != 0, solid.first()) {
+        (true, _) if solid.len() < SMOOTH_THRESHOLD => VoxelData { id: 0, ..current },
+        (false, Some(neighbour)) if solid.len() > SMOOTH_THRESHOLD => VoxelData {
+            shape: voxel_shape::CUBE,
+            ..*neighbour
+        },
+        _ => current,
+    }
+}
+
+fn neighbour_offsets() -> impl Iterator<Item = IVec3> {
+    (-1..=1)
+        .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+        .filter(|offset| *offset != IVec3::ZERO)
+}
+
+fn same_voxel(a: VoxelData, b: VoxelData) -> bool {
+    a.id == b.id && a.state == b.state && a.shape == b.shape
+}
+
+#[cfg(test)]
+mod editor_tests {
+    use crate::voxels::voxel_scene::{VoxelChunk, CHUNK_SIZE};
+
+    use super::*;
+
+    const STONE: VoxelData = VoxelData {
+        shape: voxel_shape::CUBE,
+        state: 0,
+        id: 1,
+    };
+
+    // One empty chunk with a floor of stone at y 4
+    fn floor_scene() -> VoxelScene {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        for x in 0..CHUNK_SIZE as i32 {
+            for z in 0..CHUNK_SIZE as i32 {
+                for y in 0..=4 {
+                    scene.set_voxel(&IVec3::new(x, y, z), STONE);
+                }
+            }
+        }
+        scene
+    }
+
+    fn hit(scene: &VoxelScene, position: IVec3) -> VoxelHit {
+        VoxelHit {
+            position,
+            normal: IVec3::Y,
+            voxel: scene.voxel_at(&position).unwrap(),
+            distance: 0.0,
+        }
+    }
+
+    #[test]
+    fn cube_brushes_cover_their_whole_box() {
+        let mut brush = Brush::new(STONE);
+        brush.shape = BrushShape::Cube;
+        brush.radius = 1;
+        assert_eq!(brush.positions(IVec3::ZERO).count(), 27);
+        brush.shape = BrushShape::Sphere;
+        assert!(brush.positions(IVec3::ZERO).count() < 27);
+    }
+
+    #[test]
+    fn flatten_levels_the_ground_at_the_hit() {
+        let scene = floor_scene();
+        let pillar = IVec3::new(8, 5, 8);
+        scene.set_voxel(&pillar, STONE);
+        scene.set_voxel(&IVec3::new(6, 4, 8), VoxelData { id: 0, ..STONE });
+
+        let mut brush = Brush::new(STONE);
+        brush.operation = BrushOperation::Flatten;
+        let changed = brush.apply(&scene, &hit(&scene, IVec3::new(7, 4, 8)));
+        assert_eq!(changed, 2);
+        assert_eq!(scene.voxel_at(&pillar).unwrap().id, 0);
+        assert_eq!(scene.voxel_at(&IVec3::new(6, 4, 8)).unwrap().id, 1);
+    }
+
+    #[test]
+    fn smoothing_removes_lone_voxels() {
+        let scene = floor_scene();
+        let lone = IVec3::new(8, 6, 8);
+        scene.set_voxel(&lone, STONE);
+
+        let mut brush = Brush::new(STONE);
+        brush.operation = BrushOperation::Smooth;
+        brush.apply(&scene, &hit(&scene, lone));
+        assert_eq!(scene.voxel_at(&lone).unwrap().id, 0);
+        // The flat floor is already smooth
+        assert_eq!(scene.voxel_at(&IVec3::new(8, 4, 8)).unwrap().id, 1);
+    }
+}
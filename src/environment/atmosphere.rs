@@ -0,0 +1,162 @@
+use std::time::Instant;
+
+use glam::Vec3;
+use parking_lot::RwLock;
+
+use crate::voxels::{
+    biome_profile::{get_biome_by_name, AtmosphereOverrides},
+    voxel_scene::VoxelScene,
+};
+
+use super::{camera_environment, Environment};
+
+// Seconds it takes to mostly blend into the atmosphere of a biome the camera moved into
+const BLEND_TIME: f32 = 1.5;
+// Under water the fog is thick enough to hide everything a few voxels away
+const UNDERWATER_FOG_DENSITY: f32 = 0.12;
+
+lazy_static! {
+    static ref ATMOSPHERE: RwLock<AtmosphereBlend> = RwLock::new(AtmosphereBlend::new());
+}
+
+// Fog, sky and water colors as the renderer uses them. The colors are for full daylight, the
+// environment darkens them at night and under clouds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Atmosphere {
+    pub fog_color: Vec3,
+    pub fog_density: f32,
+    pub sky_tint: Vec3,
+    pub water_color: Vec3,
+}
+
+impl Default for Atmosphere {
+    // Fog the color of the daytime sky, too thin to see
+    fn default() -> Self {
+        Self {
+            fog_color: Vec3::new(0.3, 0.4, 0.6),
+            fog_density: 0.0,
+            sky_tint: Vec3::ONE,
+            water_color: Vec3::new(0.1, 0.25, 0.45),
+        }
+    }
+}
+
+impl Atmosphere {
+    pub fn with_overrides(overrides: &AtmosphereOverrides) -> Self {
+        let default = Self::default();
+        Self {
+            fog_color: overrides.fog_color.unwrap_or(default.fog_color),
+            fog_density: overrides.fog_density.unwrap_or(default.fog_density),
+            sky_tint: overrides.sky_tint.unwrap_or(default.sky_tint),
+            water_color: overrides.water_color.unwrap_or(default.water_color),
+        }
+    }
+
+    // The atmosphere of the biome with the name, the default for unknown biomes
+    pub fn of_biome(name: String) -> Self {
+        get_biome_by_name(name).map_or_else(Self::default, |biome| {
+            Self::with_overrides(&biome.atmosphere())
+        })
+    }
+
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            fog_color: self.fog_color.lerp(other.fog_color, t),
+            fog_density: self.fog_density + (other.fog_density - self.fog_density) * t,
+            sky_tint: self.sky_tint.lerp(other.sky_tint, t),
+            water_color: self.water_color.lerp(other.water_color, t),
+        }
+    }
+
+    // The sky behind the terrain, cleared to before anything is drawn. Under water it's the
+    // water's fog so the distance fades into it
+    pub fn sky_color(&self, environment: &Environment, underwater: bool) -> Vec3 {
+        match underwater {
+            true => self.fog(environment, true).0,
+            false => environment.sky_color() * self.sky_tint,
+        }
+    }
+
+    // The fog color and density for a camera in or out of water, lit like the sky
+    pub fn fog(&self, environment: &Environment, underwater: bool) -> (Vec3, f32) {
+        let light =
+            (0.1 + environment.daylight() * 0.9) * environment.weather.current.sky_brightness();
+        match underwater {
+            true => (self.water_color * light, UNDERWATER_FOG_DENSITY),
+            false => (self.fog_color * light, self.fog_density),
+        }
+    }
+}
+
+// Eases from the atmosphere the camera was in towards the one it's in now, so crossing a biome
+// border fades instead of switching
+pub struct AtmosphereBlend {
+    current: Atmosphere,
+    last_update: Option<Instant>,
+}
+
+impl AtmosphereBlend {
+    pub fn new() -> Self {
+        Self {
+            current: Atmosphere::default(),
+            last_update: None,
+        }
+    }
+
+    // The first update starts in the target, there's nothing to blend from yet
+    pub fn step(&mut self, target: &Atmosphere, elapsed: Option<f32>) {
+        let t = elapsed.map_or(1.0, |elapsed| 1.0 - (-elapsed / BLEND_TIME).exp());
+        self.current = self.current.lerp(target, t);
+    }
+}
+
+// Moves the shared atmosphere towards the biome around the camera and looks at what the camera is
+// in, once a frame
+pub fn update(scene: &VoxelScene, camera_position: Vec3) {
+    camera_environment::update(scene, camera_position);
+    let position = camera_position.round().as_ivec3();
+    let target = Atmosphere::of_biome(scene.biome_at(&position));
+
+    let mut blend = ATMOSPHERE.write();
+    let now = Instant::now();
+    let elapsed = blend
+        .last_update
+        .map(|last| now.duration_since(last).as_secs_f32());
+    blend.step(&target, elapsed);
+    blend.last_update = Some(now);
+}
+
+pub fn current() -> Atmosphere {
+    ATMOSPHERE.read().current
+}
+
+// Whether the camera was inside a fluid at the last update
+pub fn is_underwater() -> bool {
+    camera_environment::current().is_underwater()
+}
+
+#[cfg(test)]
+mod atmosphere_tests {
+    use super::*;
+
+    #[test]
+    fn overrides_blend_in_over_time() {
+        let overrides = AtmosphereOverrides::from_json(&serde_json::json!({
+            "Fog Color": "#ff0000",
+            "Fog Density": 0.02,
+        }))
+        .unwrap();
+        let target = Atmosphere::with_overrides(&overrides);
+        assert_eq!(target.fog_color, Vec3::X);
+        assert_eq!(target.sky_tint, Atmosphere::default().sky_tint);
+
+        let mut blend = AtmosphereBlend::new();
+        blend.step(&target, Some(BLEND_TIME));
+        assert!(blend.current.fog_density > 0.0 && blend.current.fog_density < 0.02);
+        blend.step(&target, None);
+        assert_eq!(blend.current, target);
+
+        let bad = serde_json::json!({ "Sky Tint": "blue" });
+        assert!(AtmosphereOverrides::from_json(&bad).is_err());
+    }
+}
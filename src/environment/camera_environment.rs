@@ -0,0 +1,121 @@
+use glam::{Vec3, Vec4};
+use parking_lot::RwLock;
+
+use crate::{
+    events::{self, CameraEnvironmentChanged},
+    voxels::{voxel_registry::get_voxel_by_id, voxel_scene::VoxelScene},
+};
+
+// Opaque black, inside a solid voxel nothing can be seen. The faces around the camera would show
+// the world through them otherwise
+const SOLID_OVERLAY: Vec4 = Vec4::W;
+// Share of the volume sounds keep when the camera is muffled
+pub const MUFFLED_VOLUME: f32 = 0.4;
+// Frequencies above this are cut from muffled sounds
+pub const MUFFLED_CUTOFF: u32 = 800;
+
+lazy_static! {
+    static ref CAMERA: RwLock<CameraEnvironment> = RwLock::new(CameraEnvironment::default());
+}
+
+// What the camera is inside of. Voxels count as filling their whole space like in collisions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMedium {
+    Air,
+    // Voxels tagged fluid, seen through a fog of the biome's water color
+    Fluid,
+    Solid,
+}
+
+impl CameraMedium {
+    pub fn of_voxel(id: u16) -> Self {
+        match get_voxel_by_id(id) {
+            Some(profile) if id != 0 && profile.has_tag("fluid") => Self::Fluid,
+            Some(_) if id != 0 => Self::Solid,
+            _ => Self::Air,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Air => "air",
+            Self::Fluid => "fluid",
+            Self::Solid => "solid",
+        }
+    }
+}
+
+// The voxel the camera is in and the effects it has on the view and the sounds heard. Games
+// change the effects by handling CameraEnvironmentChanged
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraEnvironment {
+    pub medium: CameraMedium,
+    pub voxel: u16,
+    // A color drawn over the whole view, alpha not premultiplied
+    pub overlay: Option<Vec4>,
+    // Sounds are quieter and lose their highs
+    pub muffled: bool,
+}
+
+impl Default for CameraEnvironment {
+    fn default() -> Self {
+        Self::new(0, CameraMedium::Air)
+    }
+}
+
+impl CameraEnvironment {
+    // The effects a medium has before any handler changed them
+    pub fn new(voxel: u16, medium: CameraMedium) -> Self {
+        Self {
+            medium,
+            voxel,
+            overlay: match medium {
+                CameraMedium::Solid => Some(SOLID_OVERLAY),
+                _ => None,
+            },
+            muffled: medium != CameraMedium::Air,
+        }
+    }
+
+    pub fn is_underwater(&self) -> bool {
+        self.medium == CameraMedium::Fluid
+    }
+}
+
+// Looks at the voxel the camera is in, once a frame. Handlers are told whenever the camera moves
+// into a different kind of voxel
+pub fn update(scene: &VoxelScene, camera_position: Vec3) {
+    let id = scene
+        .voxel_at(&camera_position.round().as_ivec3())
+        .map_or(0, |voxel| voxel.id);
+    let previous = current();
+    if id == previous.voxel {
+        return;
+    }
+    let mut event = CameraEnvironmentChanged {
+        previous: previous.medium,
+        environment: CameraEnvironment::new(id, CameraMedium::of_voxel(id)),
+    };
+    events::emit(&mut event);
+    *CAMERA.write() = event.environment;
+}
+
+pub fn current() -> CameraEnvironment {
+    *CAMERA.read()
+}
+
+#[cfg(test)]
+mod camera_environment_tests {
+    use super::*;
+
+    #[test]
+    fn mediums_have_their_effects() {
+        let air = CameraEnvironment::default();
+        assert!(!air.muffled && air.overlay.is_none() && !air.is_underwater());
+        let water = CameraEnvironment::new(3, CameraMedium::Fluid);
+        assert!(water.muffled && water.overlay.is_none() && water.is_underwater());
+        let stone = CameraEnvironment::new(1, CameraMedium::Solid);
+        assert!(stone.muffled);
+        assert_eq!(stone.overlay, Some(SOLID_OVERLAY));
+    }
+}
@@ -0,0 +1,99 @@
+pub mod atmosphere;
+pub mod camera_environment;
+pub mod weather;
+pub mod world_time;
+
+use glam::Vec3;
+use parking_lot::RwLock;
+
+use crate::config;
+
+use self::{
+    weather::{Weather, WeatherState},
+    world_time::WorldTime,
+};
+
+lazy_static! {
+    static ref ENVIRONMENT: RwLock<Environment> = RwLock::new(Environment::new());
+}
+
+// The environment is shared by the simulation, the renderer and voxel behaviors
+#[derive(Clone)]
+pub struct Environment {
+    pub time: WorldTime,
+    pub weather: WeatherState,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self {
+            time: WorldTime::new(0),
+            weather: WeatherState::new(),
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.time.step();
+        match config::current().weather {
+            true => self.weather.step(),
+            // Turning the weather off clears it for good
+            false if self.weather.current != Weather::Clear => self.weather = WeatherState::new(),
+            false => {}
+        }
+    }
+
+    // Air temperature in celsius, shifted by the season, the time of day and the weather
+    pub fn temperature(&self) -> f32 {
+        let season = (self.time.year_progress() * std::f32::consts::TAU).cos();
+        let daylight = self.time.sun_height();
+        12.0 - season * 14.0 + daylight * 4.0 + self.weather.current.temperature_offset()
+    }
+
+    pub fn is_precipitating(&self) -> bool {
+        self.weather.current != Weather::Clear
+    }
+
+    pub fn is_snowing(&self) -> bool {
+        self.is_precipitating() && self.temperature() < 0.0
+    }
+
+    // 0 at night and 1 during the day, with a short transition around sunrise and sunset
+    pub fn daylight(&self) -> f32 {
+        (self.time.sun_height() * 2.0 + 0.5).clamp(0.0, 1.0)
+    }
+
+    // Points towards the sun, which rises in +x, sets in -x and leans towards -z at midday
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = self.time.time_of_day() * std::f32::consts::TAU;
+        Vec3::new(angle.sin(), self.time.sun_height(), -0.3).normalize()
+    }
+
+    // How strongly faces turned towards the sun are lit, clouds dim it
+    pub fn sun_intensity(&self) -> f32 {
+        self.daylight() * self.weather.current.sky_brightness()
+    }
+
+    // Light every face gets regardless of where it faces
+    pub fn ambient_light(&self) -> f32 {
+        0.12 + self.daylight() * 0.18
+    }
+
+    pub fn sky_color(&self) -> Vec3 {
+        let day_color = Vec3::new(0.3, 0.4, 0.6);
+        let night_color = Vec3::new(0.02, 0.02, 0.06);
+        (night_color.lerp(day_color, self.daylight())) * self.weather.current.sky_brightness()
+    }
+}
+
+pub fn current() -> Environment {
+    ENVIRONMENT.read().clone()
+}
+
+// Used when loading a world so the day and season continue from where they were saved
+pub fn set_world_time(time: WorldTime) {
+    ENVIRONMENT.write().time = time;
+}
+
+pub fn step_environment() {
+    ENVIRONMENT.write().step();
+}
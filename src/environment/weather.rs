@@ -0,0 +1,95 @@
+use rand::Rng;
+
+use crate::{random, voxels::voxel_simulation::TICKS_PER_SECOND};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Weather {
+    Clear,
+    Rain,
+    Storm,
+}
+
+impl Weather {
+    // The weathers this weather can change into, with their weights
+    fn transitions(&self) -> &'static [(Weather, u32)] {
+        match self {
+            Weather::Clear => &[(Weather::Clear, 6), (Weather::Rain, 3), (Weather::Storm, 1)],
+            Weather::Rain => &[(Weather::Clear, 5), (Weather::Rain, 2), (Weather::Storm, 2)],
+            Weather::Storm => &[(Weather::Rain, 3), (Weather::Clear, 1)],
+        }
+    }
+
+    // Range of the duration in seconds
+    fn duration(&self) -> (u32, u32) {
+        match self {
+            Weather::Clear => (300, 1200),
+            Weather::Rain => (120, 600),
+            Weather::Storm => (60, 240),
+        }
+    }
+
+    pub fn temperature_offset(&self) -> f32 {
+        match self {
+            Weather::Clear => 0.0,
+            Weather::Rain => -3.0,
+            Weather::Storm => -5.0,
+        }
+    }
+
+    pub fn sky_brightness(&self) -> f32 {
+        match self {
+            Weather::Clear => 1.0,
+            Weather::Rain => 0.7,
+            Weather::Storm => 0.45,
+        }
+    }
+
+    // Multiplier for the growth chance of crops
+    pub fn growth_boost(&self) -> f32 {
+        match self {
+            Weather::Clear => 1.0,
+            Weather::Rain => 2.0,
+            Weather::Storm => 1.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WeatherState {
+    pub current: Weather,
+    pub ticks_remaining: u32,
+}
+
+impl WeatherState {
+    pub fn new() -> Self {
+        Self {
+            current: Weather::Clear,
+            ticks_remaining: Weather::Clear.duration().0 * TICKS_PER_SECOND,
+        }
+    }
+
+    pub fn step(&mut self) {
+        if self.ticks_remaining > 0 {
+            self.ticks_remaining -= 1;
+            return;
+        }
+        let mut rng = random::tick_rng("weather");
+        let transitions = self.current.transitions();
+        let total: u32 = transitions.iter().map(|(_, weight)| weight).sum();
+        let mut pick = rng.gen_range(0..total);
+        for (weather, weight) in transitions {
+            if pick < *weight {
+                self.set(*weather);
+                break;
+            }
+            pick -= weight;
+        }
+    }
+
+    pub fn set(&mut self, weather: Weather) {
+        let (min, max) = weather.duration();
+        self.current = weather;
+        self.ticks_remaining =
+            random::tick_rng("weather_duration").gen_range(min..=max) * TICKS_PER_SECOND;
+    }
+}
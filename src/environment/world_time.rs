@@ -0,0 +1,42 @@
+use crate::voxels::voxel_simulation::TICKS_PER_SECOND;
+
+// 20 minutes per day at the default tick rate
+pub const TICKS_PER_DAY: u64 = TICKS_PER_SECOND as u64 * 60 * 20;
+pub const DAYS_PER_YEAR: u64 = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldTime {
+    pub tick: u64,
+}
+
+impl WorldTime {
+    pub fn new(tick: u64) -> Self {
+        Self { tick }
+    }
+
+    pub fn step(&mut self) {
+        self.tick += 1;
+    }
+
+    pub fn day(&self) -> u64 {
+        self.tick / TICKS_PER_DAY
+    }
+
+    // 0.0 is midnight, 0.5 is midday
+    pub fn time_of_day(&self) -> f32 {
+        (self.tick % TICKS_PER_DAY) as f32 / TICKS_PER_DAY as f32
+    }
+
+    pub fn year_progress(&self) -> f32 {
+        (self.day() % DAYS_PER_YEAR) as f32 / DAYS_PER_YEAR as f32
+    }
+
+    // Ranges from -1 at midnight to 1 at midday
+    pub fn sun_height(&self) -> f32 {
+        -(self.time_of_day() * std::f32::consts::TAU).cos()
+    }
+
+    pub fn is_night(&self) -> bool {
+        self.sun_height() < 0.0
+    }
+}
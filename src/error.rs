@@ -0,0 +1,179 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+// Errors from loading the engine's data, returned to whoever asked for it instead of stopping the
+// game. They convert into anyhow errors with ?
+#[derive(Debug)]
+pub enum AssemblageError {
+    // A resource file or plugin data that doesn't describe a valid asset
+    Asset(String),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // A name none of the registry's entries have, like a voxel a biome places
+    Registry {
+        registry: &'static str,
+        name: String,
+    },
+    // A world that can't be generated, like a biome formula with an unknown instruction
+    Generation(String),
+}
+
+impl AssemblageError {
+    pub fn asset(reason: impl fmt::Display) -> Self {
+        AssemblageError::Asset(reason.to_string())
+    }
+
+    pub fn generation(reason: impl fmt::Display) -> Self {
+        AssemblageError::Generation(reason.to_string())
+    }
+
+    pub fn unknown(registry: &'static str, name: &str) -> Self {
+        AssemblageError::Registry {
+            registry,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AssemblageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssemblageError::Asset(reason) => write!(f, "Invalid asset: {reason}"),
+            AssemblageError::Io { path, source } => {
+                write!(f, "Couldn't read {}: {source}", path.display())
+            }
+            AssemblageError::Registry { registry, name } => {
+                write!(f, "The {registry} {name} is not defined")
+            }
+            AssemblageError::Generation(reason) => write!(f, "Generation failed: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for AssemblageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AssemblageError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Returned by a command handler when the arguments don't fit, the server's and the console's
+// registries answer with the command's usage
+#[derive(Debug)]
+pub struct WrongUsage;
+
+impl fmt::Display for WrongUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Wrong usage")
+    }
+}
+
+impl std::error::Error for WrongUsage {}
+
+impl From<serde_json::Error> for AssemblageError {
+    fn from(e: serde_json::Error) -> Self {
+        AssemblageError::asset(format!("the JSON doesn't parse, {e}"))
+    }
+}
+
+pub fn read_json(path: &Path) -> Result<Value, AssemblageError> {
+    let contents = fs::read_to_string(path).map_err(|source| AssemblageError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+// Accessors for the fields of asset JSON, a missing or mistyped field is an asset error naming it
+pub fn required<'a>(json: &'a Value, field: &str) -> Result<&'a Value, AssemblageError> {
+    json.get(field)
+        .ok_or_else(|| AssemblageError::asset(format!("\"{field}\" is missing")))
+}
+
+pub fn expect_str<'a>(value: &'a Value, field: &str) -> Result<&'a str, AssemblageError> {
+    value
+        .as_str()
+        .ok_or_else(|| AssemblageError::asset(format!("\"{field}\" has to be a string")))
+}
+
+pub fn expect_f32(value: &Value, field: &str) -> Result<f32, AssemblageError> {
+    value
+        .as_f64()
+        .map(|v| v as f32)
+        .ok_or_else(|| AssemblageError::asset(format!("\"{field}\" has to be a number")))
+}
+
+pub fn expect_u64(value: &Value, field: &str) -> Result<u64, AssemblageError> {
+    value.as_u64().ok_or_else(|| {
+        AssemblageError::asset(format!("\"{field}\" has to be a whole positive number"))
+    })
+}
+
+pub fn expect_i32(value: &Value, field: &str) -> Result<i32, AssemblageError> {
+    value
+        .as_i64()
+        .and_then(|v| i32::try_from(v).ok())
+        .ok_or_else(|| AssemblageError::asset(format!("\"{field}\" has to be a whole number")))
+}
+
+pub fn expect_bool(value: &Value, field: &str) -> Result<bool, AssemblageError> {
+    value
+        .as_bool()
+        .ok_or_else(|| AssemblageError::asset(format!("\"{field}\" has to be true or false")))
+}
+
+pub fn expect_array<'a>(value: &'a Value, field: &str) -> Result<&'a [Value], AssemblageError> {
+    value
+        .as_array()
+        .map(Vec::as_slice)
+        .ok_or_else(|| AssemblageError::asset(format!("\"{field}\" has to be a list")))
+}
+
+// A list of exactly N numbers, like a color or a size
+pub fn expect_f32s<const N: usize>(
+    value: &Value,
+    field: &str,
+) -> Result<[f32; N], AssemblageError> {
+    let values = expect_array(value, field)?;
+    if values.len() != N {
+        return Err(AssemblageError::asset(format!(
+            "\"{field}\" has to list {N} numbers"
+        )));
+    }
+    let mut numbers = [0.0; N];
+    for (number, value) in numbers.iter_mut().zip(values) {
+        *number = expect_f32(value, field)?;
+    }
+    Ok(numbers)
+}
+
+#[cfg(test)]
+mod error_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn mistyped_fields_name_the_field() {
+        let json = json!({ "Size": [1.0, 2.0], "Name": 4 });
+        let e = expect_f32s::<3>(required(&json, "Size").unwrap(), "Size").unwrap_err();
+        assert!(e.to_string().contains("\"Size\""));
+        assert!(expect_str(&json["Name"], "Name").is_err());
+        assert!(matches!(
+            required(&json, "Color"),
+            Err(AssemblageError::Asset(_))
+        ));
+        assert!(matches!(
+            read_json(Path::new("does/not/exist.json")),
+            Err(AssemblageError::Io { .. })
+        ));
+    }
+}
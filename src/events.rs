@@ -0,0 +1,353 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, Result};
+use glam::{IVec3, Vec3};
+use legion::Entity;
+use parking_lot::RwLock;
+
+use crate::{
+    ecs::{components::player_components::PlayerId, entities::projectiles::ProjectileTarget},
+    environment::camera_environment::{CameraEnvironment, CameraMedium},
+    voxels::{voxel_data::VoxelData, voxel_registry::get_voxel_by_id},
+};
+
+// Something the engine announces to anyone who subscribed. Handlers of cancellable events can stop
+// the action before it happens, the others are told after the fact. Events are emitted while the
+// engine holds the world or scene locks, so handlers must not lock them
+pub trait Event: Send + Sync + 'static {
+    // The name plugins subscribe with
+    const NAME: &'static str;
+    const CANCELLABLE: bool;
+
+    // What plugins get, they can't see the engine's types
+    fn to_json(&self) -> serde_json::Value;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Propagation {
+    Continue,
+    // Skips the remaining handlers and stops the action, ignored for events that can't be cancelled
+    Cancel,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Handler<E> = Arc<dyn Fn(&mut E) -> Propagation + Send + Sync>;
+pub type JsonHandler = Arc<dyn Fn(&serde_json::Value) -> Propagation + Send + Sync>;
+
+struct Subscription {
+    id: SubscriptionId,
+    priority: i32,
+    // A Handler<E> for the event type the subscription is stored under
+    handler: Box<dyn Any + Send + Sync>,
+}
+
+pub struct EventBus {
+    next_id: AtomicU64,
+    subscriptions: RwLock<HashMap<TypeId, Vec<Subscription>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            subscriptions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Higher priorities run first, handlers with the same priority run in the order they subscribed
+    pub fn subscribe<E: Event>(
+        &self,
+        priority: i32,
+        handler: impl Fn(&mut E) -> Propagation + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let handler: Handler<E> = Arc::new(handler);
+        let mut subscriptions = self.subscriptions.write();
+        let list = subscriptions.entry(TypeId::of::<E>()).or_default();
+        let index = list.partition_point(|existing| existing.priority >= priority);
+        list.insert(
+            index,
+            Subscription {
+                id,
+                priority,
+                handler: Box::new(handler),
+            },
+        );
+        id
+    }
+
+    // Subscribes to an event by its name, for plugins
+    pub fn subscribe_json(
+        &self,
+        event: &str,
+        priority: i32,
+        handler: JsonHandler,
+    ) -> Result<SubscriptionId> {
+        fn forward<E: Event>(
+            bus: &EventBus,
+            priority: i32,
+            handler: JsonHandler,
+        ) -> SubscriptionId {
+            bus.subscribe::<E>(priority, move |event| handler(&event.to_json()))
+        }
+        Ok(match event {
+            BlockBroken::NAME => forward::<BlockBroken>(self, priority, handler),
+            CameraEnvironmentChanged::NAME => {
+                forward::<CameraEnvironmentChanged>(self, priority, handler)
+            }
+            ChunkGenerated::NAME => forward::<ChunkGenerated>(self, priority, handler),
+            EntitySpawned::NAME => forward::<EntitySpawned>(self, priority, handler),
+            PlayerJoined::NAME => forward::<PlayerJoined>(self, priority, handler),
+            ProjectileHit::NAME => forward::<ProjectileHit>(self, priority, handler),
+            _ => bail!("There is no event named {event}"),
+        })
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscriptions = self.subscriptions.write();
+        for list in subscriptions.values_mut() {
+            if let Some(index) = list.iter().position(|subscription| subscription.id == id) {
+                list.remove(index);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Runs the handlers and returns false if one of them cancelled the event. The handlers are collected
+    // first, so a handler can subscribe or unsubscribe without deadlocking
+    pub fn emit<E: Event>(&self, event: &mut E) -> bool {
+        let handlers = match self.subscriptions.read().get(&TypeId::of::<E>()) {
+            Some(list) => list
+                .iter()
+                .filter_map(|subscription| subscription.handler.downcast_ref::<Handler<E>>())
+                .cloned()
+                .collect::<Vec<_>>(),
+            None => return true,
+        };
+        for handler in handlers {
+            if handler(event) == Propagation::Cancel && E::CANCELLABLE {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+lazy_static! {
+    static ref EVENTS: EventBus = EventBus::new();
+}
+
+pub fn subscribe<E: Event>(
+    priority: i32,
+    handler: impl Fn(&mut E) -> Propagation + Send + Sync + 'static,
+) -> SubscriptionId {
+    EVENTS.subscribe(priority, handler)
+}
+
+pub fn subscribe_json(event: &str, priority: i32, handler: JsonHandler) -> Result<SubscriptionId> {
+    EVENTS.subscribe_json(event, priority, handler)
+}
+
+pub fn unsubscribe(id: SubscriptionId) -> bool {
+    EVENTS.unsubscribe(id)
+}
+
+pub fn emit<E: Event>(event: &mut E) -> bool {
+    EVENTS.emit(event)
+}
+
+fn voxel_json(voxel: VoxelData) -> serde_json::Value {
+    serde_json::json!({
+        "id": voxel.id,
+        "state": voxel.state,
+        "shape": voxel.shape.data,
+    })
+}
+
+// A player is about to break a voxel, cancelling leaves the voxel in place
+pub struct BlockBroken {
+    pub position: IVec3,
+    pub voxel: VoxelData,
+    pub player: PlayerId,
+}
+
+impl Event for BlockBroken {
+    const NAME: &'static str = "block_broken";
+    const CANCELLABLE: bool = true;
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "x": self.position.x,
+            "y": self.position.y,
+            "z": self.position.z,
+            "voxel": voxel_json(self.voxel),
+            "player": self.player.to_uuid_string(),
+        })
+    }
+}
+
+// The camera moved into a different kind of voxel. Handlers can change the effects it gets, plugins
+// only see what it's in
+pub struct CameraEnvironmentChanged {
+    pub previous: CameraMedium,
+    pub environment: CameraEnvironment,
+}
+
+impl Event for CameraEnvironmentChanged {
+    const NAME: &'static str = "camera_environment_changed";
+    const CANCELLABLE: bool = false;
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "medium": self.environment.medium.name(),
+            "previous": self.previous.name(),
+            "voxel": get_voxel_by_id(self.environment.voxel).map(|profile| profile.name.clone()),
+        })
+    }
+}
+
+// A chunk's terrain and features were generated, before it is added to the scene
+pub struct ChunkGenerated {
+    pub position: IVec3,
+}
+
+impl Event for ChunkGenerated {
+    const NAME: &'static str = "chunk_generated";
+    const CANCELLABLE: bool = false;
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "x": self.position.x,
+            "y": self.position.y,
+            "z": self.position.z,
+        })
+    }
+}
+
+// An entity was spawned from its profile
+pub struct EntitySpawned {
+    pub entity: Entity,
+    pub kind: String,
+    pub position: Vec3,
+}
+
+impl Event for EntitySpawned {
+    const NAME: &'static str = "entity_spawned";
+    const CANCELLABLE: bool = false;
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "kind": self.kind,
+            "x": self.position.x,
+            "y": self.position.y,
+            "z": self.position.z,
+        })
+    }
+}
+
+// A player passed the join checks, cancelling refuses the join with the reason if one was set
+pub struct PlayerJoined {
+    pub player: PlayerId,
+    pub reason: Option<String>,
+}
+
+impl Event for PlayerJoined {
+    const NAME: &'static str = "player_joined";
+    const CANCELLABLE: bool = true;
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "player": self.player.to_uuid_string(),
+        })
+    }
+}
+
+// A projectile hit a voxel or an entity, cancelling lets it fly on through the target
+pub struct ProjectileHit {
+    pub projectile: Entity,
+    pub owner: Option<Entity>,
+    pub target: ProjectileTarget,
+    pub position: Vec3,
+    pub velocity: Vec3,
+}
+
+impl Event for ProjectileHit {
+    const NAME: &'static str = "projectile_hit";
+    const CANCELLABLE: bool = true;
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({
+            "x": self.position.x,
+            "y": self.position.y,
+            "z": self.position.z,
+            "speed": self.velocity.length(),
+        });
+        match self.target {
+            ProjectileTarget::Voxel(hit) => {
+                json["target"] = "voxel".into();
+                json["voxel"] = voxel_json(hit.voxel);
+            }
+            ProjectileTarget::Entity(_) => json["target"] = "entity".into(),
+        }
+        json
+    }
+}
+
+#[cfg(test)]
+mod events_tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn handlers_run_by_priority_until_cancelled() {
+        let bus = EventBus::new();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        for (priority, name, propagation) in [
+            (0, "low", Propagation::Continue),
+            (10, "high", Propagation::Continue),
+            (5, "cancel", Propagation::Cancel),
+        ] {
+            let calls = Arc::clone(&calls);
+            bus.subscribe::<PlayerJoined>(priority, move |_| {
+                calls.lock().unwrap().push(name);
+                propagation
+            });
+        }
+        let mut event = PlayerJoined {
+            player: PlayerId(1),
+            reason: None,
+        };
+        assert!(!bus.emit(&mut event));
+        assert_eq!(*calls.lock().unwrap(), vec!["high", "cancel"]);
+    }
+
+    #[test]
+    fn events_that_cant_be_cancelled_reach_every_handler() {
+        let bus = EventBus::new();
+        let first = bus.subscribe::<ChunkGenerated>(0, |_| Propagation::Cancel);
+        let calls = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&calls);
+        bus.subscribe::<ChunkGenerated>(0, move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Propagation::Continue
+        });
+        let mut event = ChunkGenerated {
+            position: IVec3::ZERO,
+        };
+        assert!(bus.emit(&mut event));
+        assert!(bus.unsubscribe(first));
+        assert!(bus.emit(&mut event));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,214 @@
+use std::path::Path;
+
+use anyhow::*;
+use glam::{IVec3, Vec3};
+use serde_json::json;
+
+use crate::{
+    asset_types::mesh::Mesh,
+    persistence::atomic_file::write_atomic,
+    voxels::voxel_scene::{VoxelScene, CHUNK_SIZE},
+};
+
+use super::{region_meshes, ExportRegion};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const JSON_CHUNK: &[u8; 4] = b"JSON";
+const BIN_CHUNK: &[u8; 4] = b"BIN\0";
+// Component types and buffer targets from the glTF specification
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+// Meshes every loaded chunk touching the region with the chunk mesher and writes them into one binary
+// glTF file, each chunk is a node with its own mesh
+pub fn export_gltf(scene: &VoxelScene, region: ExportRegion, path: &Path) -> Result<usize> {
+    let (bytes, triangles) = write_glb(&region_meshes(scene, region))?;
+    write_atomic(path, &bytes)?;
+    Ok(triangles)
+}
+
+fn extend_floats(buffer: &mut Vec<u8>, values: &[f32]) {
+    for value in values {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+// Positions, normals and colors are stored as floats and the indices as u32, returns the file and the
+// number of triangles
+fn write_glb(meshes: &[(IVec3, Mesh)]) -> Result<(Vec<u8>, usize)> {
+    if meshes.is_empty() {
+        bail!("There is nothing to export in the region");
+    }
+
+    let mut buffer = Vec::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut gltf_meshes = Vec::new();
+    let mut nodes = Vec::new();
+    let mut triangles = 0;
+    let mut add_view = |buffer: &mut Vec<u8>, data: Vec<u8>, target: u32| {
+        views.push(json!({
+            "buffer": 0,
+            "byteOffset": buffer.len(),
+            "byteLength": data.len(),
+            "target": target,
+        }));
+        buffer.extend(data);
+        views.len() - 1
+    };
+
+    for (chunk_pos, mesh) in meshes {
+        let vertices = mesh.get_vertices();
+        let indices = mesh.get_indices();
+        let count = vertices.len();
+
+        let (mut positions, mut normals, mut colors) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut min, mut max) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+        for vertex in vertices {
+            let position = Vec3::from(vertex.position);
+            min = min.min(position);
+            max = max.max(position);
+            extend_floats(&mut positions, &vertex.position);
+            extend_floats(&mut normals, &vertex.normal);
+            extend_floats(&mut colors, &vertex.color);
+        }
+        let index_bytes = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        let first = accessors.len();
+        let position_view = add_view(&mut buffer, positions, ARRAY_BUFFER);
+        accessors.push(json!({
+            "bufferView": position_view,
+            "componentType": FLOAT,
+            "count": count,
+            "type": "VEC3",
+            "min": min.to_array(),
+            "max": max.to_array(),
+        }));
+        let normal_view = add_view(&mut buffer, normals, ARRAY_BUFFER);
+        accessors.push(json!({
+            "bufferView": normal_view,
+            "componentType": FLOAT,
+            "count": count,
+            "type": "VEC3",
+        }));
+        let color_view = add_view(&mut buffer, colors, ARRAY_BUFFER);
+        accessors.push(json!({
+            "bufferView": color_view,
+            "componentType": FLOAT,
+            "count": count,
+            "type": "VEC4",
+        }));
+        let index_view = add_view(&mut buffer, index_bytes, ELEMENT_ARRAY_BUFFER);
+        accessors.push(json!({
+            "bufferView": index_view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+
+        gltf_meshes.push(json!({
+            "primitives": [{
+                "attributes": {
+                    "POSITION": first,
+                    "NORMAL": first + 1,
+                    "COLOR_0": first + 2,
+                },
+                "indices": first + 3,
+            }],
+        }));
+        let origin = chunk_pos.as_vec3() * CHUNK_SIZE as f32;
+        nodes.push(json!({
+            "name": format!("chunk_{}_{}_{}", chunk_pos.x, chunk_pos.y, chunk_pos.z),
+            "mesh": gltf_meshes.len() - 1,
+            "translation": origin.to_array(),
+        }));
+        triangles += indices.len() / 3;
+    }
+
+    let document = json!({
+        "asset": { "version": "2.0", "generator": "Assemblage" },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": gltf_meshes,
+        "accessors": accessors,
+        "bufferViews": views,
+        "buffers": [{ "byteLength": buffer.len() }],
+    });
+
+    // Both chunks are padded to 4 bytes, the JSON with spaces and the binary data with zeros
+    let mut json_bytes = serde_json::to_vec(&document)?;
+    json_bytes.resize(json_bytes.len().next_multiple_of(4), b' ');
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+    let length = 12 + 8 + json_bytes.len() + 8 + buffer.len();
+    let mut bytes = Vec::with_capacity(length);
+    bytes.extend_from_slice(GLB_MAGIC);
+    bytes.extend_from_slice(&GLB_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(length as u32).to_le_bytes());
+    for (id, content) in [(JSON_CHUNK, &json_bytes), (BIN_CHUNK, &buffer)] {
+        bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(content);
+    }
+    Ok((bytes, triangles))
+}
+
+#[cfg(test)]
+mod gltf_tests {
+    use super::*;
+    use crate::rendering::vertex::Vertex;
+
+    fn read_u32(bytes: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn meshes_are_written_as_a_binary_gltf() {
+        let mut mesh = Mesh::new();
+        let mut vertices = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+        ]
+        .map(Vertex::new)
+        .to_vec();
+        mesh.append_vertices(&mut vertices);
+        mesh.append_indices(&mut vec![0, 1, 2, 2, 1, 3]);
+
+        let (bytes, triangles) = write_glb(&[(IVec3::new(0, 2, 0), mesh)]).unwrap();
+        assert_eq!(triangles, 2);
+        assert_eq!(&bytes[0..4], GLB_MAGIC);
+        assert_eq!(read_u32(&bytes, 8), bytes.len());
+
+        let json_length = read_u32(&bytes, 12);
+        assert_eq!(&bytes[16..20], JSON_CHUNK);
+        assert_eq!(json_length % 4, 0);
+        let document: serde_json::Value =
+            serde_json::from_slice(&bytes[20..20 + json_length]).unwrap();
+        let bin_start = 20 + json_length;
+        assert_eq!(&bytes[bin_start + 4..bin_start + 8], BIN_CHUNK);
+        let bin_length = read_u32(&bytes, bin_start);
+        assert_eq!(bin_start + 8 + bin_length, bytes.len());
+
+        // 4 vertices with a position, normal and color plus 6 indices
+        assert_eq!(
+            document["buffers"][0]["byteLength"],
+            4 * (3 + 3 + 4) * 4 + 6 * 4
+        );
+        assert_eq!(document["accessors"][0]["count"], 4);
+        assert_eq!(document["accessors"][0]["max"], json!([1.0, 1.0, 0.0]));
+        assert_eq!(document["accessors"][3]["count"], 6);
+        let translation = document["nodes"][0]["translation"][1].as_f64().unwrap();
+        assert_eq!(translation, 2.0 * CHUNK_SIZE as f64);
+    }
+
+    #[test]
+    fn empty_regions_are_rejected() {
+        assert!(write_glb(&[]).is_err());
+    }
+}
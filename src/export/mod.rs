@@ -0,0 +1,53 @@
+pub mod gltf;
+pub mod obj;
+pub mod vox;
+
+use std::sync::Arc;
+
+use glam::IVec3;
+
+use crate::{asset_types::mesh::Mesh, voxels::voxel_scene::VoxelScene};
+
+// Inclusive box of voxel positions to export
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExportRegion {
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+impl ExportRegion {
+    pub fn new(a: IVec3, b: IVec3) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn size(&self) -> IVec3 {
+        self.max - self.min + IVec3::ONE
+    }
+}
+
+// Meshes every loaded chunk touching the region with the chunk mesher, chunks without faces are left out
+fn region_meshes(scene: &VoxelScene, region: ExportRegion) -> Vec<(IVec3, Mesh)> {
+    let min_chunk = VoxelScene::chunk_at(&region.min);
+    let max_chunk = VoxelScene::chunk_at(&region.max);
+
+    let mut meshes = Vec::new();
+    for x in min_chunk.x..=max_chunk.x {
+        for y in min_chunk.y..=max_chunk.y {
+            for z in min_chunk.z..=max_chunk.z {
+                let chunk_pos = IVec3::new(x, y, z);
+                let chunk = match scene.chunks.get(&chunk_pos) {
+                    Some(chunk) if !chunk.is_empty => chunk.clone(),
+                    _ => continue,
+                };
+                let mesh = chunk.generate_mesh(Arc::clone(&scene.chunks));
+                if !mesh.get_indices().is_empty() {
+                    meshes.push((chunk_pos, mesh));
+                }
+            }
+        }
+    }
+    meshes
+}
@@ -0,0 +1,82 @@
+use std::{fmt::Write, path::Path};
+
+use anyhow::*;
+use glam::{IVec3, Vec3};
+
+use crate::{
+    asset_types::mesh::Mesh,
+    persistence::atomic_file::write_atomic,
+    voxels::voxel_scene::{VoxelScene, CHUNK_SIZE},
+};
+
+use super::{region_meshes, ExportRegion};
+
+// Meshes every loaded chunk touching the region with the chunk mesher and writes them into one OBJ file,
+// vertex colors are written after the position which most tools understand
+pub fn export_obj(scene: &VoxelScene, region: ExportRegion, path: &Path) -> Result<usize> {
+    let (obj, triangles) = write_obj(&region_meshes(scene, region))?;
+    write_atomic(path, obj.as_bytes())?;
+    Ok(triangles)
+}
+
+// Every chunk becomes one object, returns the text and the number of triangles
+fn write_obj(meshes: &[(IVec3, Mesh)]) -> Result<(String, usize)> {
+    let mut obj = String::new();
+    writeln!(obj, "# Exported from Assemblage")?;
+    let mut vertex_offset = 1; // OBJ indices start at 1
+    let mut triangles = 0;
+    for (chunk_pos, mesh) in meshes {
+        writeln!(
+            obj,
+            "o chunk_{}_{}_{}",
+            chunk_pos.x, chunk_pos.y, chunk_pos.z
+        )?;
+        let origin = chunk_pos.as_vec3() * CHUNK_SIZE as f32;
+        for vertex in mesh.get_vertices() {
+            let position = origin + Vec3::from(vertex.position);
+            let [r, g, b, _] = vertex.color;
+            writeln!(
+                obj,
+                "v {} {} {} {r} {g} {b}",
+                position.x, position.y, position.z
+            )?;
+        }
+        for vertex in mesh.get_vertices() {
+            let [nx, ny, nz] = vertex.normal;
+            writeln!(obj, "vn {nx} {ny} {nz}")?;
+        }
+        for triangle in mesh.get_indices().chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize + vertex_offset);
+            writeln!(obj, "f {a}//{a} {b}//{b} {c}//{c}")?;
+            triangles += 1;
+        }
+        vertex_offset += mesh.get_vertices().len();
+    }
+    Ok((obj, triangles))
+}
+
+#[cfg(test)]
+mod obj_tests {
+    use super::*;
+    use crate::rendering::vertex::Vertex;
+
+    #[test]
+    fn chunks_are_written_at_their_position() {
+        let mut mesh = Mesh::new();
+        let mut vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .map(Vertex::new)
+            .to_vec();
+        mesh.append_vertices(&mut vertices);
+        mesh.append_indices(&mut vec![0, 1, 2]);
+        let chunk_pos = IVec3::new(1, 0, -1);
+
+        let (obj, triangles) = write_obj(&[(chunk_pos, mesh)]).unwrap();
+        assert_eq!(triangles, 1);
+        let size = CHUNK_SIZE as f32;
+        let lines = obj.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"o chunk_1_0_-1"));
+        assert!(lines.contains(&format!("v {} 0 {} 1 1 1", size + 1.0, -size).as_str()));
+        assert!(lines.contains(&"f 1//1 2//2 3//3"));
+        assert_eq!(lines.iter().filter(|l| l.starts_with("vn ")).count(), 3);
+    }
+}
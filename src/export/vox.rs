@@ -0,0 +1,140 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::*;
+use glam::IVec3;
+
+use crate::{
+    persistence::atomic_file::write_atomic,
+    voxels::{voxel_registry::get_voxel_by_id, voxel_scene::VoxelScene},
+};
+
+use super::ExportRegion;
+
+const VOX_VERSION: i32 = 150;
+// MagicaVoxel models can't be larger than this on any axis
+const MAX_MODEL_SIZE: i32 = 256;
+// Palette index 0 is reserved for empty space
+const MAX_PALETTE_SIZE: usize = 255;
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    bytes.extend_from_slice(&(children.len() as i32).to_le_bytes());
+    bytes.extend_from_slice(content);
+    bytes.extend_from_slice(children);
+}
+
+// Writes the region to a MagicaVoxel file, every voxel type gets one palette entry using its profile color
+pub fn export_vox(scene: &VoxelScene, region: ExportRegion, path: &Path) -> Result<usize> {
+    let size = region.size();
+    if size.max_element() > MAX_MODEL_SIZE {
+        bail!("Region {size} is larger than the {MAX_MODEL_SIZE} voxel limit of the vox format");
+    }
+
+    let mut palette: HashMap<u16, u8> = HashMap::new();
+    let mut colors = Vec::new();
+    let mut voxels = Vec::new();
+    for x in region.min.x..=region.max.x {
+        for y in region.min.y..=region.max.y {
+            for z in region.min.z..=region.max.z {
+                let voxel = match scene.voxel_at(&IVec3::new(x, y, z)) {
+                    Some(voxel) if voxel.id != 0 => voxel,
+                    _ => continue,
+                };
+                let id = voxel.id;
+                let index = match palette.get(&id) {
+                    Some(index) => *index,
+                    None => {
+                        if colors.len() >= MAX_PALETTE_SIZE {
+                            bail!("Region has more than {MAX_PALETTE_SIZE} voxel types");
+                        }
+                        let color = get_voxel_by_id(id).map_or([255; 4], |profile| {
+                            (profile.color * 255.0).round().to_array().map(|c| c as u8)
+                        });
+                        colors.push(color);
+                        palette.insert(id, colors.len() as u8);
+                        colors.len() as u8
+                    }
+                };
+                // MagicaVoxel is z up, z has to be flipped as well so the model isn't mirrored
+                let local = IVec3::new(x, y, z) - region.min;
+                let flipped_z = size.z - 1 - local.z;
+                voxels.push([local.x as u8, flipped_z as u8, local.y as u8, index]);
+            }
+        }
+    }
+
+    let mut size_content = Vec::new();
+    for axis in [size.x, size.z, size.y] {
+        size_content.extend_from_slice(&axis.to_le_bytes());
+    }
+    let mut xyzi_content = (voxels.len() as i32).to_le_bytes().to_vec();
+    xyzi_content.extend(voxels.iter().flatten());
+    // Palette entry i is stored at position i - 1
+    let mut rgba_content = Vec::with_capacity(256 * 4);
+    for i in 0..256 {
+        rgba_content.extend_from_slice(&colors.get(i).copied().unwrap_or([0; 4]));
+    }
+
+    let mut children = Vec::new();
+    write_chunk(&mut children, b"SIZE", &size_content, &[]);
+    write_chunk(&mut children, b"XYZI", &xyzi_content, &[]);
+    write_chunk(&mut children, b"RGBA", &rgba_content, &[]);
+
+    let mut bytes = b"VOX ".to_vec();
+    bytes.extend_from_slice(&VOX_VERSION.to_le_bytes());
+    write_chunk(&mut bytes, b"MAIN", &[], &children);
+    write_atomic(path, &bytes)?;
+    Ok(voxels.len())
+}
+
+#[cfg(test)]
+mod vox_tests {
+    use super::*;
+    use crate::voxels::{
+        voxel_data::VoxelData, voxel_scene::VoxelChunk, voxel_shapes::voxel_shape,
+    };
+
+    #[test]
+    fn models_keep_their_handedness() {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        let voxel = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        for position in [
+            IVec3::new(1, 0, 0),
+            IVec3::new(0, 1, 0),
+            IVec3::new(0, 0, 2),
+        ] {
+            scene.set_voxel(&position, voxel);
+        }
+
+        let path =
+            std::env::temp_dir().join(format!("assemblage_export_{}.vox", std::process::id()));
+        let region = ExportRegion::new(IVec3::ZERO, IVec3::new(1, 1, 2));
+        let exported = export_vox(&scene, region, &path);
+        let bytes = std::fs::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(exported.unwrap(), 3);
+        let bytes = bytes.unwrap();
+
+        // MAIN's header is followed by SIZE, then XYZI
+        let int = |offset: usize| i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&bytes[20..24], b"SIZE");
+        assert_eq!([int(32), int(36), int(40)], [2, 3, 2]);
+        assert_eq!(&bytes[44..48], b"XYZI");
+        assert_eq!(int(56), 3);
+        let mut voxels = bytes[60..72]
+            .chunks_exact(4)
+            .map(|v| [v[0], v[1], v[2]])
+            .collect::<Vec<_>>();
+        voxels.sort();
+        // x stays, -z becomes y and y becomes z
+        assert_eq!(voxels, [[0, 0, 0], [0, 2, 1], [1, 2, 0]]);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use tracing::warn;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use crate::{
+    config::{self, EngineConfig},
+    input_manager,
+    logging::SETTINGS_FILE,
+};
+
+// Keys that can be bound, named as in VirtualKeyCode
+#[rustfmt::skip]
+const KEYS: [VirtualKeyCode; 94] = {
+    use VirtualKeyCode::*;
+    [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key0, Key1,
+        Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10,
+        F11, F12, Escape, Tab, Space, Return, Back, Insert, Delete, Home, End, PageUp, PageDown,
+        Up, Down, Left, Right, LShift, RShift, LControl, RControl, LAlt, RAlt, Grave, Minus,
+        Equals, LBracket, RBracket, Backslash, Semicolon, Apostrophe, Comma, Period, Slash,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        NumpadAdd, NumpadSubtract, NumpadEnter, Capital,
+    ]
+};
+
+// Something an action can be bound to. Keys use their VirtualKeyCode names such as "W" or
+// "LShift" and mouse buttons are "MouseLeft" or "Mouse4"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+impl Input {
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(button) = name.strip_prefix("Mouse") {
+            return match button {
+                "Left" => Some(Input::Mouse(MouseButton::Left)),
+                "Right" => Some(Input::Mouse(MouseButton::Right)),
+                "Middle" => Some(Input::Mouse(MouseButton::Middle)),
+                other => other
+                    .parse()
+                    .ok()
+                    .map(|n| Input::Mouse(MouseButton::Other(n))),
+            };
+        }
+        KEYS.into_iter()
+            .find(|key| format!("{key:?}") == name)
+            .map(Input::Key)
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Input::Key(key) => format!("{key:?}"),
+            Input::Mouse(MouseButton::Other(n)) => format!("Mouse{n}"),
+            Input::Mouse(button) => format!("Mouse{button:?}"),
+        }
+    }
+
+    fn pressed(self) -> bool {
+        match self {
+            Input::Key(key) => input_manager::get_key(key),
+            Input::Mouse(button) => input_manager::get_button(button),
+        }
+    }
+
+    fn down(self) -> bool {
+        match self {
+            Input::Key(key) => input_manager::get_key_down(key),
+            Input::Mouse(button) => input_manager::get_button_down(button),
+        }
+    }
+
+    fn up(self) -> bool {
+        match self {
+            Input::Key(key) => input_manager::get_key_up(key),
+            Input::Mouse(button) => input_manager::get_button_up(button),
+        }
+    }
+}
+
+// The keybinds from the config with their input names parsed
+struct ActionMap {
+    config: Arc<EngineConfig>,
+    bindings: BTreeMap<String, Vec<Input>>,
+}
+
+impl ActionMap {
+    fn new(config: Arc<EngineConfig>) -> Self {
+        let bindings = config
+            .keybinds
+            .iter()
+            .map(|(action, names)| {
+                let inputs = names
+                    .iter()
+                    .filter_map(|name| {
+                        let input = Input::from_name(name);
+                        if input.is_none() {
+                            warn!("Ignoring the unknown input {name} bound to {action}");
+                        }
+                        input
+                    })
+                    .collect();
+                (action.clone(), inputs)
+            })
+            .collect();
+        Self { config, bindings }
+    }
+}
+
+lazy_static! {
+    static ref ACTION_MAP: RwLock<Arc<ActionMap>> =
+        RwLock::new(Arc::new(ActionMap::new(config::current())));
+}
+
+// Parsed again whenever the config has changed since the last lookup
+fn action_map() -> Arc<ActionMap> {
+    let config = config::current();
+    let map = Arc::clone(&ACTION_MAP.read());
+    if Arc::ptr_eq(&map.config, &config) {
+        return map;
+    }
+    let map = Arc::new(ActionMap::new(config));
+    *ACTION_MAP.write() = Arc::clone(&map);
+    map
+}
+
+fn any_input(action: &str, check: impl Fn(Input) -> bool) -> bool {
+    action_map()
+        .bindings
+        .get(action)
+        .map_or(false, |inputs| inputs.iter().any(|input| check(*input)))
+}
+
+// Whether any input bound to the action is down, actions are named in lowercase like "jump"
+pub fn action_pressed(action: &str) -> bool {
+    any_input(action, Input::pressed)
+}
+
+// Whether an input bound to the action was pressed this frame
+pub fn action_down(action: &str) -> bool {
+    any_input(action, Input::down)
+}
+
+// Whether an input bound to the action was released this frame
+pub fn action_up(action: &str) -> bool {
+    any_input(action, Input::up)
+}
+
+pub fn actions() -> Vec<String> {
+    config::current().keybinds.keys().cloned().collect()
+}
+
+pub fn bindings(action: &str) -> Option<Vec<String>> {
+    config::current().keybinds.get(action).cloned()
+}
+
+// Binds the action to the inputs in place of its old ones and saves them to the settings file
+pub fn rebind(action: &str, inputs: &[&str]) -> Result<()> {
+    if let Some(unknown) = inputs.iter().find(|name| Input::from_name(name).is_none()) {
+        return Err(anyhow!("Unknown input {unknown}"));
+    }
+    let mut config = (*config::current()).clone();
+    config.keybinds.insert(
+        action.to_lowercase(),
+        inputs.iter().map(|name| name.to_string()).collect(),
+    );
+    config::save_keybinds(Path::new(SETTINGS_FILE), &config.keybinds)?;
+    config::set(config);
+    Ok(())
+}
+
+#[cfg(test)]
+mod input_actions_tests {
+    use super::*;
+
+    #[test]
+    fn input_names_round_trip() {
+        let inputs = KEYS
+            .map(Input::Key)
+            .into_iter()
+            .chain([MouseButton::Left, MouseButton::Other(4)].map(Input::Mouse));
+        for input in inputs {
+            assert_eq!(Input::from_name(&input.name()), Some(input));
+        }
+        assert_eq!(
+            Input::from_name("LShift"),
+            Some(Input::Key(VirtualKeyCode::LShift))
+        );
+        assert_eq!(Input::from_name("Shift"), None);
+    }
+}
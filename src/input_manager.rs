@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{hash::Hash, sync::Arc};
 
 use dashmap::DashMap;
 use glam::Vec2;
@@ -32,56 +32,8 @@ lazy_static! {
 }
 
 pub fn update_inputs() {
-    // Pressed -> Held
-    INPUT_MAP.iter_mut().for_each(|mut key| {
-        if *key.value() == PressState::Pressed
-            && PREVIOUS_INPUT_MAP
-                .get(key.key())
-                .map_or(false, |previous| *previous.value() == PressState::Pressed)
-        {
-            *key.value_mut() = PressState::Held;
-        }
-    });
-
-    MOUSE_MAP.iter_mut().for_each(|mut button| {
-        if *button.value() == PressState::Pressed
-            && PREVIOUS_MOUSE_MAP
-                .get(button.key())
-                .map_or(false, |previous| *previous.value() == PressState::Pressed)
-        {
-            *button.value_mut() = PressState::Held;
-        }
-    });
-
-    // Released -> None
-    INPUT_MAP.iter_mut().for_each(|mut key| {
-        if *key.value() == PressState::Released
-            && PREVIOUS_INPUT_MAP
-                .get(key.key())
-                .map_or(false, |previous| *previous.value() == PressState::Released)
-        {
-            *key.value_mut() = PressState::None;
-        }
-    });
-
-    MOUSE_MAP.iter_mut().for_each(|mut button| {
-        if *button.value() == PressState::Released
-            && PREVIOUS_MOUSE_MAP
-                .get(button.key())
-                .map_or(false, |previous| *previous.value() == PressState::Released)
-        {
-            *button.value_mut() = PressState::None;
-        }
-    });
-
-    // Update previous map
-    INPUT_MAP.iter().for_each(|key| {
-        PREVIOUS_INPUT_MAP.insert(*key.key(), *key.value());
-    });
-
-    MOUSE_MAP.iter().for_each(|button| {
-        PREVIOUS_MOUSE_MAP.insert(*button.key(), *button.value());
-    });
+    advance_states(&INPUT_MAP, &PREVIOUS_INPUT_MAP);
+    advance_states(&MOUSE_MAP, &PREVIOUS_MOUSE_MAP);
 
     let mut mouse_delta_lock = MOUSE_DELTA.write();
     let mouse_pos_lock = MOUSE_POS.read();
@@ -158,3 +110,27 @@ pub fn set_mouse_pos(pos: &PhysicalPosition<f64>) {
     let mut lock = MOUSE_POS.write();
     (lock.x, lock.y) = (pos.x, pos.y);
 }
+
+// Pressed becomes Held and Released becomes None once they've lasted a whole frame
+fn advance_states<K: Eq + Hash + Copy>(
+    map: &DashMap<K, PressState>,
+    previous: &DashMap<K, PressState>,
+) {
+    map.iter_mut().for_each(|mut entry| {
+        let last = previous.get(entry.key()).map(|previous| *previous.value());
+        match (*entry.value(), last) {
+            (PressState::Pressed, Some(PressState::Pressed)) => {
+                *entry.value_mut() = PressState::Held
+            }
+            (PressState::Released, Some(PressState::Released)) => {
+                *entry.value_mut() = PressState::None
+            }
+            _ => {}
+        }
+    });
+
+    // Update previous map
+    map.iter().for_each(|entry| {
+        previous.insert(*entry.key(), *entry.value());
+    });
+}
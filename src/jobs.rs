@@ -0,0 +1,485 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::Waker,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use tracing::{debug, error, info};
+
+use crate::{config, crash_report, profile_scope};
+
+// Background work is split into classes, a free worker always takes a job from the first class
+// in this order that has one ready and hasn't reached its thread limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JobClass {
+    // Saving, comes first since the game waits on it when a world is closed
+    Io,
+    Generation,
+    Meshing,
+    Lighting,
+}
+
+impl JobClass {
+    pub const ALL: [JobClass; 4] = [
+        JobClass::Io,
+        JobClass::Generation,
+        JobClass::Meshing,
+        JobClass::Lighting,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            JobClass::Io => "io",
+            JobClass::Generation => "generation",
+            JobClass::Meshing => "meshing",
+            JobClass::Lighting => "lighting",
+        }
+    }
+
+    // How many jobs of the class run at once by default, together they make up the worker threads
+    pub fn default_limit(self) -> usize {
+        match self {
+            JobClass::Io => 1,
+            JobClass::Generation => 3,
+            JobClass::Meshing => 3,
+            JobClass::Lighting => 1,
+        }
+    }
+}
+
+// What happens to the generation, meshing and lighting jobs that haven't started when the engine stops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PendingJobs {
+    // They still run, the chunks they were generating end up in the save
+    Drain,
+    Cancel,
+}
+
+// Refers to a spawned job, used to cancel it or to make other jobs wait for it
+#[derive(Clone)]
+pub struct JobHandle {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+    finished: Arc<AtomicBool>,
+    // Futures waiting for the job, woken once it finishes
+    wakers: Arc<Mutex<Vec<Waker>>>,
+}
+
+impl JobHandle {
+    // A job that hasn't started is dropped along with every job depending on it, a running job finishes
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Also true for jobs that were cancelled
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    // Wakes the waker once the job has finished, returns false when it already has
+    pub fn wake_when_finished(&self, waker: &Waker) -> bool {
+        let mut wakers = self.wakers.lock();
+        if self.is_finished() {
+            return false;
+        }
+        wakers.push(waker.clone());
+        true
+    }
+}
+
+struct Job {
+    class: JobClass,
+    // Taken when the job starts, the job stays listed until it finishes so dependents can still be added
+    work: Option<Box<dyn FnOnce() + Send>>,
+    handle: JobHandle,
+    // Unfinished dependencies, the job is queued once this reaches zero
+    waiting_on: usize,
+    dependents: Vec<u64>,
+}
+
+struct State {
+    next_id: u64,
+    jobs: HashMap<u64, Job>,
+    ready: [VecDeque<u64>; JobClass::ALL.len()],
+    running: [usize; JobClass::ALL.len()],
+    limits: [usize; JobClass::ALL.len()],
+    // Only Io jobs are accepted once stopping, so the world can still be saved
+    stopping: bool,
+    // Set once every job has finished, the workers exit and nothing is accepted anymore
+    exiting: bool,
+}
+
+impl State {
+    fn next_ready(&mut self) -> Option<u64> {
+        for class in JobClass::ALL {
+            let index = class.index();
+            if self.running[index] < self.limits[index] {
+                if let Some(id) = self.ready[index].pop_front() {
+                    return Some(id);
+                }
+            }
+        }
+        None
+    }
+
+    // Removes the job and queues the dependents that were only waiting on it
+    fn finish(&mut self, id: u64) {
+        let job = match self.jobs.remove(&id) {
+            Some(job) => job,
+            None => return,
+        };
+        job.handle.finished.store(true, Ordering::Release);
+        job.handle.wakers.lock().drain(..).for_each(Waker::wake);
+        for dependent in job.dependents {
+            if let Some(waiting) = self.jobs.get_mut(&dependent) {
+                if job.handle.is_cancelled() {
+                    waiting.handle.cancel();
+                }
+                waiting.waiting_on -= 1;
+                if waiting.waiting_on == 0 {
+                    self.ready[waiting.class.index()].push_back(dependent);
+                }
+            }
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    available: Condvar,
+    // Notified whenever a job finishes, for the threads waiting for the scheduler to empty
+    finished: Condvar,
+}
+
+pub struct JobScheduler {
+    shared: Arc<Shared>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl JobScheduler {
+    // Starts one worker thread for every slot across the class limits
+    pub fn new(limits: [usize; JobClass::ALL.len()]) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                next_id: 0,
+                jobs: HashMap::new(),
+                ready: Default::default(),
+                running: [0; JobClass::ALL.len()],
+                limits,
+                stopping: false,
+                exiting: false,
+            }),
+            available: Condvar::new(),
+            finished: Condvar::new(),
+        });
+        let threads = limits.iter().sum::<usize>();
+        let workers = (0..threads)
+            .map(|i| {
+                let shared = Arc::clone(&shared);
+                thread::Builder::new()
+                    .name(format!("jobs-{i}"))
+                    .spawn(move || Self::worker(&shared))
+                    .unwrap()
+            })
+            .collect();
+        info!("Job scheduler started with {threads} threads");
+        Self {
+            shared,
+            workers: Mutex::new(workers),
+        }
+    }
+
+    // Queues the work to run once every dependency has finished. If a dependency is cancelled the
+    // job is cancelled too, so a mesh is never built for a chunk that wasn't generated. Jobs
+    // refused because the scheduler is stopping come back cancelled and finished
+    pub fn spawn(
+        &self,
+        class: JobClass,
+        dependencies: &[JobHandle],
+        work: impl FnOnce() + Send + 'static,
+    ) -> JobHandle {
+        let mut state = self.shared.state.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        let handle = JobHandle {
+            id,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            finished: Arc::new(AtomicBool::new(false)),
+            wakers: Arc::new(Mutex::new(Vec::new())),
+        };
+        if state.exiting || (state.stopping && class != JobClass::Io) {
+            debug!("Refused a {class:?} job, the scheduler is stopping");
+            handle.cancel();
+            handle.finished.store(true, Ordering::Release);
+            return handle;
+        }
+
+        let mut waiting_on = 0;
+        for dependency in dependencies {
+            if dependency.is_cancelled() {
+                handle.cancel();
+            }
+            // Dependencies that already finished aren't listed anymore
+            if let Some(job) = state.jobs.get_mut(&dependency.id) {
+                job.dependents.push(id);
+                waiting_on += 1;
+            }
+        }
+        state.jobs.insert(
+            id,
+            Job {
+                class,
+                work: Some(Box::new(work)),
+                handle: handle.clone(),
+                waiting_on,
+                dependents: Vec::new(),
+            },
+        );
+        if waiting_on == 0 {
+            state.ready[class.index()].push_back(id);
+            self.shared.available.notify_one();
+        }
+        handle
+    }
+
+    // Jobs of each class that haven't started, including the ones waiting on dependencies
+    pub fn queued(&self) -> [usize; JobClass::ALL.len()] {
+        let state = self.shared.state.lock();
+        let mut queued = [0; JobClass::ALL.len()];
+        for job in state.jobs.values().filter(|job| job.work.is_some()) {
+            queued[job.class.index()] += 1;
+        }
+        queued
+    }
+
+    // Running and queued jobs of each class for crash reports, gives up if the scheduler stays
+    // locked since the crashing thread may hold it
+    pub fn summary(&self) -> String {
+        let state = match self.shared.state.try_lock_for(Duration::from_millis(100)) {
+            Some(state) => state,
+            None => return "The job scheduler is locked".to_string(),
+        };
+        let mut summary = String::new();
+        for class in JobClass::ALL {
+            let queued = state
+                .jobs
+                .values()
+                .filter(|job| job.class == class && job.work.is_some())
+                .count();
+            let _ = writeln!(
+                summary,
+                "{class:?}: {} of {} running, {queued} queued",
+                state.running[class.index()],
+                state.limits[class.index()]
+            );
+        }
+        let _ = write!(summary, "Stopping: {}", state.stopping);
+        summary
+    }
+
+    // Refuses every job but Io from now on and cancels or drains the queued ones, returns once only
+    // Io jobs are left
+    pub fn stop(&self, pending: PendingJobs) {
+        let mut state = self.shared.state.lock();
+        state.stopping = true;
+        if pending == PendingJobs::Cancel {
+            for job in state.jobs.values().filter(|job| job.class != JobClass::Io) {
+                job.handle.cancel();
+            }
+        }
+        while state.jobs.values().any(|job| job.class != JobClass::Io) {
+            self.shared.finished.wait(&mut state);
+        }
+    }
+
+    // Waits for every job to finish and joins the workers, nothing is accepted afterwards
+    pub fn shutdown(&self) {
+        let mut state = self.shared.state.lock();
+        state.stopping = true;
+        while !state.jobs.is_empty() {
+            self.shared.finished.wait(&mut state);
+        }
+        state.exiting = true;
+        self.shared.available.notify_all();
+        drop(state);
+        for worker in self.workers.lock().drain(..) {
+            let _ = worker.join();
+        }
+        info!("Job scheduler stopped");
+    }
+
+    fn worker(shared: &Shared) {
+        let mut state = shared.state.lock();
+        loop {
+            let id = match state.next_ready() {
+                Some(id) => id,
+                None if state.exiting => return,
+                None => {
+                    shared.available.wait(&mut state);
+                    continue;
+                }
+            };
+            let job = state.jobs.get_mut(&id).unwrap();
+            let (class, work) = (job.class, job.work.take());
+            if let (Some(work), false) = (work, job.handle.is_cancelled()) {
+                state.running[class.index()] += 1;
+                MutexGuard::unlocked(&mut state, || {
+                    profile_scope!("job", class.name());
+                    // A panicking job shouldn't take the worker down with it
+                    if crash_report::catch_unwind(work).is_err() {
+                        error!("A {class:?} job panicked");
+                    }
+                });
+                state.running[class.index()] -= 1;
+            }
+            state.finish(id);
+            // Finishing can free a slot of the class and make dependents ready
+            shared.available.notify_all();
+            shared.finished.notify_all();
+        }
+    }
+}
+
+lazy_static! {
+    // Sized from the engine config when the first job is spawned
+    static ref JOBS: JobScheduler = JobScheduler::new(config::current().job_threads);
+}
+
+pub fn spawn(
+    class: JobClass,
+    dependencies: &[JobHandle],
+    work: impl FnOnce() + Send + 'static,
+) -> JobHandle {
+    JOBS.spawn(class, dependencies, work)
+}
+
+pub fn queued() -> [usize; JobClass::ALL.len()] {
+    JOBS.queued()
+}
+
+pub fn stop(pending: PendingJobs) {
+    JOBS.stop(pending)
+}
+
+pub fn summary() -> String {
+    JOBS.summary()
+}
+
+pub fn shutdown() {
+    JOBS.shutdown()
+}
+
+#[cfg(test)]
+mod jobs_tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn dependents_run_after_their_dependencies() {
+        let scheduler = JobScheduler::new([1, 2, 2, 1]);
+        let (sender, receiver) = flume::unbounded();
+        let (gate_sender, gate_receiver) = flume::bounded::<()>(0);
+
+        let generate_sender = sender.clone();
+        let generate = scheduler.spawn(JobClass::Generation, &[], move || {
+            gate_receiver.recv().unwrap();
+            generate_sender.send("generate").unwrap();
+        });
+        let mesh = scheduler.spawn(JobClass::Meshing, &[generate.clone()], move || {
+            sender.send("mesh").unwrap();
+        });
+        assert!(!mesh.is_finished());
+        gate_sender.send(()).unwrap();
+
+        let order = receiver.iter().take(2).collect::<Vec<_>>();
+        assert_eq!(order, vec!["generate", "mesh"]);
+    }
+
+    #[test]
+    fn cancelling_skips_the_job_and_its_dependents() {
+        let scheduler = JobScheduler::new([1, 1, 1, 1]);
+        let (gate_sender, gate_receiver) = flume::bounded::<()>(0);
+        let (sender, receiver) = flume::unbounded();
+
+        // Holds the only generation slot so the next job stays queued
+        scheduler.spawn(JobClass::Generation, &[], move || {
+            gate_receiver.recv().unwrap();
+        });
+        let skipped_sender = sender.clone();
+        let generate = scheduler.spawn(JobClass::Generation, &[], move || {
+            skipped_sender.send("generate").unwrap();
+        });
+        let mesh = scheduler.spawn(JobClass::Meshing, &[generate.clone()], move || {
+            sender.send("mesh").unwrap();
+        });
+        generate.cancel();
+        gate_sender.send(()).unwrap();
+
+        while !mesh.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(mesh.is_cancelled());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn waiting_jobs_count_as_queued() {
+        let scheduler = JobScheduler::new([1, 1, 1, 1]);
+        let (gate_sender, gate_receiver) = flume::bounded::<()>(0);
+        let generate = scheduler.spawn(JobClass::Generation, &[], move || {
+            gate_receiver.recv().unwrap();
+        });
+        let mesh = scheduler.spawn(JobClass::Meshing, &[generate], || {});
+        assert_eq!(scheduler.queued()[JobClass::Meshing.index()], 1);
+
+        gate_sender.send(()).unwrap();
+        while !mesh.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(scheduler.queued(), [0; JobClass::ALL.len()]);
+    }
+
+    #[test]
+    fn stopping_cancels_queued_jobs_but_still_saves() {
+        let scheduler = JobScheduler::new([1, 1, 1, 1]);
+        let (gate_sender, gate_receiver) = flume::bounded::<()>(0);
+        let (started_sender, started_receiver) = flume::bounded(1);
+        let (sender, receiver) = flume::unbounded();
+        let running = scheduler.spawn(JobClass::Generation, &[], move || {
+            started_sender.send(()).unwrap();
+            gate_receiver.recv().unwrap();
+        });
+        let queued_sender = sender.clone();
+        let queued = scheduler.spawn(JobClass::Generation, &[], move || {
+            queued_sender.send("queued").unwrap();
+        });
+        started_receiver.recv().unwrap();
+        thread::spawn(move || gate_sender.send(()).unwrap());
+        scheduler.stop(PendingJobs::Cancel);
+        assert!(running.is_finished() && !running.is_cancelled());
+        assert!(queued.is_finished() && queued.is_cancelled());
+
+        let refused = scheduler.spawn(JobClass::Meshing, &[], || {});
+        assert!(refused.is_finished() && refused.is_cancelled());
+        scheduler.spawn(JobClass::Io, &[], move || sender.send("save").unwrap());
+        scheduler.shutdown();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec!["save"]);
+        assert!(scheduler.spawn(JobClass::Io, &[], || {}).is_cancelled());
+    }
+}
@@ -0,0 +1,47 @@
+#![feature(int_roundings)]
+#![feature(portable_simd)]
+
+#[macro_use]
+extern crate lazy_static;
+extern crate nalgebra as na;
+
+pub mod asset_types;
+pub mod assets;
+pub mod audio;
+#[cfg(feature = "render")]
+pub mod client;
+pub mod config;
+pub mod crash_report;
+pub mod data_packs;
+pub mod ecs;
+pub mod editor;
+pub mod environment;
+pub mod error;
+pub mod events;
+pub mod export;
+#[cfg(feature = "render")]
+pub mod input_actions;
+#[cfg(feature = "render")]
+pub mod input_manager;
+pub mod jobs;
+pub mod localization;
+pub mod logging;
+pub mod map;
+pub mod memory;
+pub mod network;
+pub mod noise;
+pub mod persistence;
+pub mod physics;
+pub mod plugins;
+pub mod profiling;
+pub mod progress;
+pub mod random;
+pub mod rendering;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "render")]
+pub mod state;
+#[cfg(feature = "render")]
+pub mod targeting;
+pub mod time;
+pub mod voxels;
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+use tracing::{error, info, warn};
+
+use crate::{config, data_packs::merged_resource_files, error::read_json};
+
+// Strings missing from the chosen locale come from this one
+pub const DEFAULT_LOCALE: &str = "en_us";
+
+// The strings of the chosen locale and the default one, from the "lang" resource folder. A locale
+// file is one json object of keys to strings, packs add keys to it and replace single strings
+struct Translations {
+    locale: String,
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+lazy_static! {
+    static ref TRANSLATIONS: RwLock<Translations> =
+        RwLock::new(load_translations(&config::current().locale));
+}
+
+fn load_translations(locale: &str) -> Translations {
+    let fallback = load_locale(DEFAULT_LOCALE);
+    let strings = match locale == DEFAULT_LOCALE {
+        true => HashMap::new(),
+        false => load_locale(locale),
+    };
+    match (locale == DEFAULT_LOCALE, strings.is_empty()) {
+        (true, _) => info!("Loaded {} {locale} translations", fallback.len()),
+        (false, true) => warn!("There are no translations for the locale {locale}"),
+        (false, false) => info!("Loaded {} {locale} translations", strings.len()),
+    }
+    Translations {
+        locale: locale.to_string(),
+        strings,
+        fallback,
+    }
+}
+
+fn load_locale(locale: &str) -> HashMap<String, String> {
+    let mut strings = HashMap::new();
+    let paths = merged_resource_files("lang")
+        .into_iter()
+        .find(|(name, _)| name == locale)
+        .map_or_else(Vec::new, |(_, paths)| paths);
+    for path in paths {
+        let json = match read_json(&path) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Skipping the translations in {}: {e}", path.display());
+                continue;
+            }
+        };
+        let object = match json.as_object() {
+            Some(object) => object,
+            None => {
+                error!("Skipping {}, it isn't a json object", path.display());
+                continue;
+            }
+        };
+        for (key, value) in object {
+            match value.as_str() {
+                Some(value) => {
+                    strings.insert(key.clone(), value.to_string());
+                }
+                None => warn!("Ignoring the translation {key} in {}", path.display()),
+            }
+        }
+    }
+    strings
+}
+
+// Loads the locale from the config now rather than on first access, see assets::load_assets
+pub fn load() {
+    lazy_static::initialize(&TRANSLATIONS);
+}
+
+// Switches to another locale, names looked up afterwards are in the new language
+pub fn set_locale(locale: &str) {
+    *TRANSLATIONS.write() = load_translations(locale);
+}
+
+pub fn locale() -> String {
+    TRANSLATIONS.read().locale.clone()
+}
+
+// The string in the current locale, the default locale's when it has none
+pub fn translate(key: &str) -> Option<String> {
+    let translations = TRANSLATIONS.read();
+    translations
+        .strings
+        .get(key)
+        .or_else(|| translations.fallback.get(key))
+        .cloned()
+}
+
+// The string in the current locale, or the key itself so missing translations stand out
+pub fn tr(key: &str) -> String {
+    translate(key).unwrap_or_else(|| key.to_string())
+}
+
+// Biomes are named by "biome.<name>", falling back to the name
+pub fn biome_display_name(name: &str) -> String {
+    translate(&format!("biome.{name}")).unwrap_or_else(|| name.to_string())
+}
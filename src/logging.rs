@@ -0,0 +1,176 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter, Layer, Registry};
+
+// Engine settings, every section is optional and missing values keep their defaults. The logging
+// section is read here, the rest by config
+pub const SETTINGS_FILE: &str = "./settings.toml";
+// Lines kept for crash reports
+const RECENT_LINES: usize = 100;
+
+lazy_static! {
+    static ref RECENT: parking_lot::Mutex<VecDeque<String>> =
+        parking_lot::Mutex::new(VecDeque::with_capacity(RECENT_LINES));
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogSettings {
+    // A filter such as "info,graphics_test::voxels=debug,chat=off" giving each module its own level.
+    // RUST_LOG replaces it when set
+    pub level: String,
+    // Logs are also written to this file when set
+    pub file: Option<PathBuf>,
+    // Writes the file as one json object per line instead of text
+    pub json: bool,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            file: None,
+            json: false,
+        }
+    }
+}
+
+impl LogSettings {
+    pub fn from_json(json: &serde_json::Value) -> Self {
+        let defaults = Self::default();
+        Self {
+            level: json
+                .get("Level")
+                .and_then(|v| v.as_str())
+                .map_or(defaults.level, str::to_string),
+            file: json.get("File").and_then(|v| v.as_str()).map(PathBuf::from),
+            json: json
+                .get("Json")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(defaults.json),
+        }
+    }
+
+    // The "Logging" section of the settings file, nothing is logged yet so problems go to stderr
+    pub fn load(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+        match toml::from_str::<serde_json::Value>(&contents) {
+            Ok(json) => json
+                .get("Logging")
+                .map_or_else(Self::default, Self::from_json),
+            Err(e) => {
+                eprintln!(
+                    "[WARN] Ignoring {}, it isn't valid toml: {e}",
+                    path.display()
+                );
+                Self::default()
+            }
+        }
+    }
+}
+
+// Logs to the console and the file from the settings, has to run once before anything is logged
+pub fn init(settings: &LogSettings) {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&settings.level))
+        .unwrap_or_else(|e| {
+            eprintln!("[WARN] Invalid log level {}: {e}", settings.level);
+            EnvFilter::new("info")
+        });
+    let file_layer = settings
+        .file
+        .as_ref()
+        .and_then(|path| match open_log_file(path) {
+            Ok(file) => Some(file_layer(file, settings.json)),
+            Err(e) => {
+                eprintln!("[WARN] Logging to the console only: {e}");
+                None
+            }
+        });
+    let recent_layer = fmt::layer().with_writer(|| RecentWriter).with_ansi(false);
+    let subscriber = tracing_subscriber::registry()
+        .with(file_layer)
+        .with(recent_layer)
+        .with(fmt::layer())
+        .with(filter);
+    // Spans that pass the filter show up in Tracy, lower the level to see the chunk spans
+    #[cfg(feature = "tracy")]
+    let subscriber = subscriber.with(tracing_tracy::TracyLayer::new());
+    subscriber.init();
+}
+
+// The previous session's log is replaced
+fn open_log_file(path: &Path) -> Result<File> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    File::create(path).with_context(|| format!("Failed to create {}", path.display()))
+}
+
+// The last lines logged, oldest first. Empty if the log stays locked, the crashing thread may be
+// the one writing to it
+pub fn recent_lines() -> Vec<String> {
+    match RECENT.try_lock_for(Duration::from_millis(100)) {
+        Some(recent) => recent.iter().cloned().collect(),
+        None => Vec::new(),
+    }
+}
+
+// Keeps the formatted lines in RECENT
+struct RecentWriter;
+
+impl io::Write for RecentWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut recent = RECENT.lock();
+        for line in String::from_utf8_lossy(bytes).lines() {
+            if recent.len() == RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line.to_string());
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn file_layer(file: File, json: bool) -> Box<dyn Layer<Registry> + Send + Sync> {
+    let layer = fmt::layer().with_writer(Mutex::new(file)).with_ansi(false);
+    match json {
+        true => layer.json().boxed(),
+        false => layer.boxed(),
+    }
+}
+
+#[cfg(test)]
+mod logging_tests {
+    use super::*;
+
+    #[test]
+    fn missing_settings_keep_their_defaults() {
+        let settings = LogSettings::from_json(&serde_json::json!({
+            "Level": "warn,chat=info",
+            "Json": true,
+        }));
+        assert_eq!(
+            settings,
+            LogSettings {
+                level: "warn,chat=info".to_string(),
+                file: None,
+                json: true,
+            }
+        );
+    }
+}
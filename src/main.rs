@@ -1,72 +1,254 @@
-#![feature(int_roundings)]
-
-mod asset_types;
-mod ecs;
-mod input_manager;
-mod noise;
-mod physics;
-mod rendering;
-mod state;
-mod time;
-mod voxels;
-
-use crate::noise::simplex::Simplex1D;
-
-use ecs::{
-    components::{
-        self,
-        camera::Camera,
-        player_components::Player,
-        rendering_components::MeshRenderer,
-        transformation_components::{Position, Rotation},
-    },
+#[cfg(feature = "client")]
+use graphics_test::noise::simplex::Simplex1D;
+
+use graphics_test::assets::{self, load_assets};
+#[cfg(feature = "client")]
+use graphics_test::audio::AudioEngine;
+#[cfg(feature = "client")]
+use graphics_test::client::{
+    camera_overlay::add_camera_overlay_panel,
+    console::{Console, ConsoleContext},
+    heatmaps,
+    highlight::{add_crack_panel, add_highlight_panel, add_placement_preview_panel},
+    minimap::add_minimap_panel,
+    network_panel::add_network_panel,
+    overlay::DebugOverlay,
+    progress_panel::add_progress_panel,
+    Client,
+};
+use graphics_test::config;
+use graphics_test::crash_report;
+use graphics_test::data_packs::enable_world_packs;
+#[cfg(feature = "client")]
+use graphics_test::ecs::{
+    components::{self, camera::Camera},
+    entities::player::{attach_camera, find_player},
     systems::{
-        camera_systems::update_camera_system, player_controller::update_players_system,
-        render_systems::construct_buffers,
+        camera_systems::{direct_observers_system, update_camera_system},
+        debug_systems::{brush_tools_system, schematic_debug_tools_system},
+        network_systems::interpolate_remote_entities_system,
+        player_controller::{
+            footsteps_system, player_interaction_system, update_breaking_system,
+            update_players_system, update_target_system,
+        },
     },
     world::World,
 };
-use input_manager::update_inputs;
-use legion::IntoQuery;
-use legion::{Resources, Schedule};
-use mimalloc::MiMalloc;
-use parking_lot::RwLock;
-use pollster::block_on;
-use rendering::{
+#[cfg(feature = "client")]
+use graphics_test::input_manager::update_inputs;
+#[cfg(feature = "client")]
+use graphics_test::jobs::PendingJobs;
+use graphics_test::logging::{self, LogSettings, SETTINGS_FILE};
+use graphics_test::persistence::anvil_import::{self, ImportOptions};
+use graphics_test::persistence::integrity::{self, CheckOptions};
+use graphics_test::persistence::world_save::set_allow_newer_worlds;
+#[cfg(feature = "client")]
+use graphics_test::persistence::world_save::{WorldMetadata, WorldSave};
+use graphics_test::plugins::{hot_reload::set_dev_mode, load_plugins};
+use graphics_test::profile_scope;
+use graphics_test::profiling::{self, FrameSource};
+#[cfg(feature = "client")]
+use graphics_test::rendering::{
+    self,
+    backend::RenderBackend,
     material::{Material, MaterialDiffuseTexture},
     render_pass_data::render_layers,
-    texture::Texture,
+    texture_atlas,
+    wgpu_backend::WgpuBackend,
 };
-use state::*;
-use std::{
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
-    },
-    time::Instant,
+#[cfg(feature = "client")]
+use graphics_test::server::Server;
+use graphics_test::server::{
+    self, headless::HeadlessOptions, null_render::NullRenderOptions, pregen::PregenOptions,
+    preview::PreviewOptions, replay::ReplayOptions,
 };
-use time::Time;
-use voxels::voxel_scene::CHUNK_SIZE;
+#[cfg(feature = "client")]
+use graphics_test::state::*;
+#[cfg(feature = "client")]
+use graphics_test::targeting::Targeting;
+#[cfg(feature = "client")]
+use graphics_test::time::Time;
+#[cfg(feature = "client")]
+use graphics_test::voxels::voxel_breaking::VoxelBreaking;
+#[cfg(feature = "client")]
+use legion::IntoQuery;
+#[cfg(feature = "client")]
+use legion::{Resources, Schedule};
+use mimalloc::MiMalloc;
+#[cfg(feature = "client")]
+use parking_lot::{Mutex, RwLock};
+#[cfg(feature = "client")]
+use pollster::block_on;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "client")]
+use std::{path::PathBuf, sync::Arc, time::Instant};
+use tracing::error;
+#[cfg(feature = "client")]
+use tracing::info;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
 #[macro_use]
 extern crate lazy_static;
-extern crate nalgebra as na;
 
-use glam::{EulerRot, IVec3, Quat, UVec3, Vec3};
+#[cfg(feature = "client")]
+use glam::{IVec3, Quat, UVec3, Vec3};
+#[cfg(feature = "client")]
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
-use crate::rendering::mesh::Mesh;
 
-use crate::voxels::voxel_scene::VoxelScene;
+#[cfg(feature = "client")]
+use graphics_test::voxels::voxel_scene::VoxelScene;
+#[cfg(feature = "client")]
+use graphics_test::{
+    editor::Brush,
+    voxels::{voxel_data::VoxelData, voxel_registry::get_voxel_by_name, voxel_shapes::voxel_shape},
+};
+
+// Runs the dedicated server without a window, the only mode of a build without the client feature
+fn run_headless() -> Result<(), ()> {
+    let options = HeadlessOptions::from_args(std::env::args().skip(1));
+    profiling::start(FrameSource::ServerTick);
+    enable_world_packs(&options.world_path);
+    set_dev_mode(options.dev);
+    load_plugins(&config::current().resources.plugins);
+    load_assets(assets::log_progress);
+    server::headless::run(options).map_err(|e| error!("{e}"))
+}
+
+// Generates and saves the chunks around the spawn, then exits
+fn run_pregen() -> Result<(), ()> {
+    let options = PregenOptions::from_args(std::env::args().skip(1)).map_err(|e| error!("{e}"))?;
+    enable_world_packs(&options.world_path);
+    load_plugins(&config::current().resources.plugins);
+    load_assets(assets::log_progress);
+    server::pregen::run(options)
+        .map(|_| ())
+        .map_err(|e| error!("{e}"))
+}
+
+// Renders a map of a seed's terrain without generating a world, then exits
+fn run_preview() -> Result<(), ()> {
+    let options = PreviewOptions::from_args(std::env::args().skip(1)).map_err(|e| error!("{e}"))?;
+    load_plugins(&config::current().resources.plugins);
+    load_assets(assets::log_progress);
+    server::preview::run(options)
+        .map(|_| ())
+        .map_err(|e| error!("{e}"))
+}
+
+// Runs the frame loop against the null render backend on an in-memory world, for CI and machines
+// without a GPU
+fn run_null_render() -> Result<(), ()> {
+    let options =
+        NullRenderOptions::from_args(std::env::args().skip(1)).map_err(|e| error!("{e}"))?;
+    load_plugins(&config::current().resources.plugins);
+    load_assets(assets::log_progress);
+    server::null_render::run(options)
+        .map(|_| ())
+        .map_err(|e| error!("{e}"))
+}
+
+// Plays a recording back on an in-memory world, failing when the chunks it changed don't match
+fn run_replay() -> Result<(), ()> {
+    let options = ReplayOptions::from_args(std::env::args().skip(1)).map_err(|e| error!("{e}"))?;
+    load_plugins(&config::current().resources.plugins);
+    load_assets(assets::log_progress);
+    server::replay::run(options)
+        .map(|_| ())
+        .map_err(|e| error!("{e}"))
+}
+
+// Checks the world's chunks and manifest, repairing or quarantining bad chunks when asked to
+fn run_check() -> Result<(), ()> {
+    let options = CheckOptions::from_args(std::env::args().skip(1)).map_err(|e| error!("{e}"))?;
+    enable_world_packs(&options.world_path);
+    load_plugins(&config::current().resources.plugins);
+    load_assets(assets::log_progress);
+    integrity::run(options)
+        .map(|_| ())
+        .map_err(|e| error!("{e}"))
+}
+
+// Converts a Minecraft world's region files into chunks of the world, then exits
+fn run_import() -> Result<(), ()> {
+    let options = ImportOptions::from_args(std::env::args().skip(1)).map_err(|e| error!("{e}"))?;
+    enable_world_packs(&options.world_path);
+    load_plugins(&config::current().resources.plugins);
+    load_assets(assets::log_progress);
+    anvil_import::run(options)
+        .map(|_| ())
+        .map_err(|e| error!("{e}"))
+}
+
+// Logging and the engine config come first, the job workers and registries read the config
+fn load_settings() {
+    let settings = std::path::Path::new(SETTINGS_FILE);
+    logging::init(&LogSettings::load(settings));
+    crash_report::install();
+    config::init(settings);
+    config::watch(settings);
+    set_allow_newer_worlds(std::env::args().any(|arg| arg == "--allow-newer-world"));
+}
+
+#[cfg(not(feature = "client"))]
+fn main() -> Result<(), ()> {
+    load_settings();
+    if std::env::args().any(|arg| arg == "--pregen") {
+        return run_pregen();
+    }
+    if std::env::args().any(|arg| arg == "--preview") {
+        return run_preview();
+    }
+    if std::env::args().any(|arg| arg == "--check") {
+        return run_check();
+    }
+    if std::env::args().any(|arg| arg == "--import") {
+        return run_import();
+    }
+    if std::env::args().any(|arg| arg == "--null-render") {
+        return run_null_render();
+    }
+    if std::env::args().any(|arg| arg == "--replay") {
+        return run_replay();
+    }
+    run_headless()
+}
 
+#[cfg(feature = "client")]
 fn main() -> Result<(), ()> {
-    env_logger::init(); // Tells WGPU to inform us of errors, rather than failing silently
+    // Also picks up wgpu's errors, rather than them failing silently
+    load_settings();
+    if std::env::args().any(|arg| arg == "--pregen") {
+        return run_pregen();
+    }
+    if std::env::args().any(|arg| arg == "--preview") {
+        return run_preview();
+    }
+    if std::env::args().any(|arg| arg == "--check") {
+        return run_check();
+    }
+    if std::env::args().any(|arg| arg == "--import") {
+        return run_import();
+    }
+    if std::env::args().any(|arg| arg == "--null-render") {
+        return run_null_render();
+    }
+    if std::env::args().any(|arg| arg == "--replay") {
+        return run_replay();
+    }
+    if std::env::args().any(|arg| arg == "--server") {
+        return run_headless();
+    }
+    profiling::start(FrameSource::Render);
+    enable_world_packs(std::path::Path::new("./saves/world"));
+    set_dev_mode(std::env::args().any(|arg| arg == "--dev"));
+    load_plugins(&config::current().resources.plugins);
+    load_assets(assets::log_progress);
 
     let event_loop = EventLoop::new();
     // Create a window
@@ -80,28 +262,18 @@ fn main() -> Result<(), ()> {
     // Setup entity world
     let state_clone = Arc::clone(&state);
     // Create a Legion world (ECS)
-    let world = Arc::new(RwLock::new(World {
-        legion_world: legion::World::default(),
-    }));
+    let world = Arc::new(RwLock::new(World::new()));
 
     let state_lock = state_clone.write();
     let camera = Arc::new(RwLock::new(rendering::camera::Camera::new(&state_lock)));
 
-    let diffuse_bytes = include_bytes!("textures/lapis_block.png");
-    let texture = Arc::new(
-        Texture::from_bytes(
-            &state_lock.device,
-            &state_lock.queue,
-            diffuse_bytes,
-            "lapis",
-        )
-        .unwrap(),
-    );
-
-    let material: Arc<RwLock<dyn Material>> = Arc::new(RwLock::new(MaterialDiffuseTexture::new(
+    // Voxels are drawn from an atlas of the textures they name, rebuilt when the texture pack changes
+    let texture = Arc::new(texture_atlas::upload_voxel_atlas(&state_lock).unwrap());
+    let voxel_material = Arc::new(RwLock::new(MaterialDiffuseTexture::new(
         &state_lock,
         texture,
     )));
+    let material: Arc<RwLock<dyn Material>> = voxel_material.clone();
 
     drop(state_lock);
 
@@ -112,31 +284,79 @@ fn main() -> Result<(), ()> {
     camera_lock.add_render_layer("Default".to_string());
     drop(camera_lock);
 
+    let world_save = Arc::new(
+        WorldSave::open_or_create(
+            PathBuf::from("./saves/world"),
+            WorldMetadata::new("world".to_string(), rand::random(), "plains".to_string()),
+        )
+        .map_err(|e| error!("Failed to open the world: {e}"))?,
+    );
+    let server = Server::start(Arc::clone(&world_save), Arc::clone(&world));
+    let scene = Arc::clone(&server.scene);
+    let backend: Arc<Mutex<dyn RenderBackend>> = Arc::new(Mutex::new(WgpuBackend::new(
+        Arc::clone(&state),
+        Arc::clone(&world),
+        Arc::clone(&scene),
+        material,
+        "Default".to_string(),
+    )));
+    add_minimap_panel(Arc::clone(&scene), Arc::clone(&camera));
+    add_highlight_panel(Arc::clone(&camera));
+    add_placement_preview_panel(Arc::clone(&camera));
+    add_crack_panel(Arc::clone(&camera));
+    // After the outlines, so inside a wall they are covered too
+    add_camera_overlay_panel();
+    add_progress_panel();
+    add_network_panel();
+
+    // Singleplayer runs the client against the server in the same process
+    let (player_id, secret) = world_save.player_storage().local_identity().unwrap();
+    let (client, _) = Client::join(server.connect_local(), player_id, secret).unwrap();
+    let client = Arc::new(client);
+
+    // The server and client share the entity world in process, so the camera is attached to the server's player
     let mut world_lock = world.write();
-    world_lock.legion_world.push((
-        Position(Vec3::new(0.0, 80.0, 0.0)), // Middle of world
-        Rotation(Quat::from_euler(
-            EulerRot::XYZ,
-            0.0,
-            (45.0 as f32).to_radians(),
-            0.0,
-        )),
-        Player { fly_speed: 50.0 },
-        components::camera::Camera { camera },
-    ));
+    match find_player(&world_lock.legion_world, player_id) {
+        Some(entity) => attach_camera(&mut world_lock.legion_world, entity, camera),
+        None => error!("The player joined but isn't in the world"),
+    }
     drop(world_lock);
 
     let world_clone = Arc::clone(&world);
+    let scene_clone = Arc::clone(&scene);
+    let edit_history = Arc::clone(&world.read().edit_history);
+    let brush_material = VoxelData {
+        shape: voxel_shape::CUBE,
+        state: 0,
+        id: get_voxel_by_name("stone".to_string()).map_or(0, |profile| profile.id),
+    };
+    let client_clone = Arc::clone(&client);
     rayon::spawn(move || {
         // Add systems
         let mut schedule = Schedule::builder()
             .add_system(update_players_system())
+            .add_system(update_target_system())
+            .add_system(player_interaction_system())
+            .add_system(update_breaking_system())
+            .add_system(footsteps_system(0.0))
+            .add_system(schematic_debug_tools_system())
+            .add_system(brush_tools_system(Brush::new(brush_material)))
+            .add_system(interpolate_remote_entities_system())
+            .add_system(direct_observers_system())
+            // The observer and the camera hand over in the commands of direct_observers
+            .flush()
             .add_system(update_camera_system())
             .build();
         let start = Instant::now();
         let mut loop_time = Instant::now();
         let mut resources = Resources::default(); // Resources are accessible to all systems that use them
+        resources.insert(scene_clone);
+        resources.insert(edit_history);
+        resources.insert(client_clone);
+        resources.insert(Targeting::default());
+        resources.insert(VoxelBreaking::default());
         loop {
+            profile_scope!("update_systems");
             update_inputs(); // Update the inputs before sending firing the systems
             resources.insert(Time {
                 time: start.elapsed().as_secs_f64(),
@@ -149,12 +369,9 @@ fn main() -> Result<(), ()> {
         }
     });
 
-    // Setup voxel scene
-    let scene = Arc::new(RwLock::new(VoxelScene::new()));
     generate_world(
         Arc::clone(&scene),
-        Arc::clone(&world),
-        Arc::clone(&material),
+        Arc::clone(&backend),
         UVec3::new(50, 5, 50),
     );
 
@@ -167,21 +384,54 @@ fn main() -> Result<(), ()> {
         //noise.iter().for_each(|v| println!("{v}"));
     });
 
+    let mut console = Console::new();
+    let mut overlay = DebugOverlay::new();
+    let mut audio = AudioEngine::new();
+    console.set_server_commands(
+        server
+            .commands
+            .read()
+            .commands()
+            .map(|command| command.name.clone())
+            .collect(),
+    );
+
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::WindowEvent {
                 ref event,
                 window_id,
             } if window_id == window.id() => {
+                // Typing into the open console doesn't move the player
+                if console.handle_event(event, &ConsoleContext { client: &client })
+                    || overlay.handle_event(event)
+                {
+                    return;
+                }
                 let mut state_lock = state.write();
+                if state_lock.ui.handle_event(event) {
+                    return;
+                }
                 if !state_lock.input(event) {
                     match event {
-                        WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                        WindowEvent::Resized(physical_size) => {
-                            state_lock.resize(*physical_size);
+                        WindowEvent::CloseRequested => {
+                            client.disconnect();
+                            match server.shutdown(PendingJobs::Cancel) {
+                                Ok(saved) => info!("Saved {saved} chunks"),
+                                Err(e) => error!("Failed to save the world: {e}"),
+                            }
+                            *control_flow = ControlFlow::Exit
+                        }
+                        // The backend locks the state itself
+                        WindowEvent::Resized(size) => {
+                            drop(state_lock);
+                            backend.lock().resize(size.width, size.height);
                         }
                         WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            state_lock.resize(**new_inner_size);
+                            drop(state_lock);
+                            backend
+                                .lock()
+                                .resize(new_inner_size.width, new_inner_size.height);
                         }
                         _ => {}
                     }
@@ -189,6 +439,10 @@ fn main() -> Result<(), ()> {
             }
 
             Event::RedrawRequested(window_id) if window_id == window.id() => {
+                // Ends the previous frame before this one's scopes start
+                profiling::new_frame(FrameSource::Render);
+                profile_scope!("frame");
+                overlay.frame();
                 let world_lock = world.read();
                 let mut query = <&Camera>::query();
 
@@ -198,19 +452,54 @@ fn main() -> Result<(), ()> {
                     .collect();
 
                 let mut state_lock = state.write();
-                construct_buffers(&state_lock, &world_lock.legion_world);
-
-                match state_lock.render(cameras) {
-                    Ok(_) => {}
-                    // Reconfigure the surface if lost
-                    Err(wgpu::SurfaceError::Lost) => {
-                        let size = state_lock.size;
-                        state_lock.resize(size);
+                if texture_atlas::take_rebuild_request() {
+                    let scene = scene.read();
+                    if let Err(e) = texture_atlas::rebuild(&state_lock, &voxel_material, &scene) {
+                        error!("Failed to rebuild the texture atlas: {e}");
+                    }
+                } else if texture_atlas::filtering_changed() {
+                    if let Err(e) = texture_atlas::refilter(&state_lock, &voxel_material) {
+                        error!("Failed to change the texture filtering: {e}");
                     }
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // All other errors (Outdated, Timeout) should be resolved by the next frame
-                    Err(e) => eprintln!("{:?}", e),
+                }
+                // Meshes for edited chunks are started a few a frame, big edits finish over several
+                heatmaps::refresh(&scene.read());
+                scene
+                    .read()
+                    .spawn_queued_meshes(config::current().remesh_budget);
+
+                // The local client shares the server's scene and world, only chat is left to show
+                match client.poll() {
+                    Ok(messages) => {
+                        console.receive_chat(messages);
+                    }
+                    Err(e) => error!("Lost the connection to the server: {e}"),
+                }
+                state_lock.ui.run(&window);
+                console.queue_text(&mut state_lock.text);
+                let (camera_position, camera_rotation) =
+                    cameras
+                        .first()
+                        .map_or((Vec3::ZERO, Quat::IDENTITY), |camera| {
+                            let camera = camera.read();
+                            (camera.position, camera.rotation)
+                        });
+                audio.update(camera_position, camera_rotation, &scene.read());
+                let size = state_lock.size;
+                overlay.queue_text(
+                    &mut state_lock.text,
+                    size,
+                    camera_position,
+                    &world_lock.legion_world,
+                    &scene.read(),
+                );
+
+                // The backend takes the locks itself
+                drop(state_lock);
+                drop(world_lock);
+                if let Err(e) = backend.lock().render_frame() {
+                    error!("Stopped rendering: {e}");
+                    *control_flow = ControlFlow::Exit;
                 }
             }
             Event::MainEventsCleared => {
@@ -223,12 +512,14 @@ fn main() -> Result<(), ()> {
     });
 }
 
+#[cfg(feature = "client")]
 pub fn generate_world(
     scene: Arc<RwLock<VoxelScene>>,
-    world: Arc<RwLock<World>>,
-    material: Arc<RwLock<dyn Material>>,
+    backend: Arc<Mutex<dyn RenderBackend>>,
     size: UVec3,
 ) {
+    let (tx, rx) = flume::unbounded();
+    scene.write().set_mesh_sender(tx);
     for x in 0..size.x {
         for y in 0..size.y {
             for z in 0..size.z {
@@ -239,22 +530,10 @@ pub fn generate_world(
         }
     }
 
-    let (tx, rx) = flume::unbounded();
-    scene.write().setup_chunk_processors(tx);
     rayon::spawn(move || {
-        //let mut saved_meshes = HashMap::new();
-        loop {
-            let (mesh_pos, mesh) = rx.recv().unwrap();
-            let mut world_lock = world.write();
-            world_lock.legion_world.push((
-                Position(mesh_pos.as_vec3() * CHUNK_SIZE as f32),
-                Rotation(Quat::IDENTITY),
-                MeshRenderer::new(
-                    Arc::new(RwLock::new(mesh)),
-                    Arc::clone(&material),
-                    "Default".to_string(),
-                ),
-            ));
+        for (mesh_pos, mut mesh) in rx.iter() {
+            heatmaps::colorize(&mut mesh, mesh_pos, &scene.read());
+            backend.lock().upload_chunk_mesh(mesh_pos, mesh);
         }
     });
 }
@@ -0,0 +1,301 @@
+use std::{collections::HashMap, fs, io::Write, path::Path};
+
+use anyhow::Result;
+use flate2::{write::ZlibEncoder, Compression};
+use glam::{IVec2, IVec3, UVec3, Vec3};
+
+use crate::{
+    persistence::{atomic_file::write_atomic, binary::crc32, chunk_storage::ChunkStorage},
+    voxels::{
+        biome_profile::get_biome_by_name,
+        voxel_data::VoxelData,
+        voxel_registry::get_voxel_by_id,
+        voxel_scene::{biome_at, pos_to_index, VoxelScene, CHUNK_SIZE},
+    },
+};
+
+const COLUMN_AREA: usize = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+// Slopes facing north are lit and the ones facing away are in shadow
+const HIGHER_SHADE: f32 = 1.15;
+const LOWER_SHADE: f32 = 0.8;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// The surface of one chunk column, a pixel for every x z position in it
+#[derive(Clone)]
+pub struct MapTile {
+    // None where the column has no voxels, indexed by x * CHUNK_SIZE + z
+    heights: Vec<Option<i32>>,
+    colors: Vec<Vec3>,
+}
+
+impl MapTile {
+    // Takes the chunks of the column from the top down, later chunks are only read while some
+    // positions haven't found their surface yet
+    pub fn render(column: IVec2, chunks: impl Iterator<Item = (i32, Vec<VoxelData>)>) -> Self {
+        let mut tile = Self {
+            heights: vec![None; COLUMN_AREA],
+            colors: vec![Vec3::ZERO; COLUMN_AREA],
+        };
+        for (y, voxels) in chunks {
+            let tint = get_biome_by_name(biome_at(IVec3::new(column.x, y, column.y)))
+                .and_then(|biome| biome.map_tint());
+            for index in 0..COLUMN_AREA {
+                if tile.heights[index].is_some() {
+                    continue;
+                }
+                let (x, z) = (index as u32 / CHUNK_SIZE, index as u32 % CHUNK_SIZE);
+                let surface = (0..CHUNK_SIZE).rev().find_map(|voxel_y| {
+                    let voxel = voxels[pos_to_index(&UVec3::new(x, voxel_y, z)) as usize];
+                    (voxel.id != 0).then(|| (voxel_y, voxel.id))
+                });
+                if let Some((voxel_y, id)) = surface {
+                    let color = get_voxel_by_id(id).map_or(Vec3::ZERO, |p| p.color.truncate());
+                    tile.heights[index] = Some(y * CHUNK_SIZE as i32 + voxel_y as i32);
+                    tile.colors[index] = match tint {
+                        Some(tint) => color.lerp(tint.truncate(), tint.w),
+                        None => color,
+                    };
+                }
+            }
+            if tile.heights.iter().all(Option::is_some) {
+                break;
+            }
+        }
+        tile
+    }
+
+    // A tile for surfaces found some other way, indexed by x * CHUNK_SIZE + z like the rest
+    pub fn from_surface(heights: Vec<Option<i32>>, colors: Vec<Vec3>) -> Self {
+        debug_assert!(heights.len() == COLUMN_AREA && colors.len() == COLUMN_AREA);
+        Self { heights, colors }
+    }
+}
+
+// Map tiles by chunk column, filled from saved chunks, loaded chunks or both
+pub struct WorldMap {
+    tiles: HashMap<IVec2, MapTile>,
+}
+
+impl WorldMap {
+    pub fn new() -> Self {
+        Self {
+            tiles: HashMap::new(),
+        }
+    }
+
+    // Renders every saved chunk column, loading chunks only until each column's surface is found
+    pub fn add_saved(&mut self, storage: &ChunkStorage) -> Result<()> {
+        for (column, ys) in group_columns(storage.stored_chunks()?) {
+            let chunks = ys.into_iter().filter_map(|y| {
+                let payload = storage.load(&IVec3::new(column.x, y, column.y)).ok()??;
+                Some((y, payload.voxels))
+            });
+            self.tiles.insert(column, MapTile::render(column, chunks));
+        }
+        Ok(())
+    }
+
+    // Loaded chunks are newer than the saved ones, so their columns replace the saved tiles.
+    // Only columns within the radius of the center are rendered when one is given
+    pub fn add_loaded(&mut self, scene: &VoxelScene, area: Option<(IVec2, i32)>) {
+        let positions = scene
+            .chunks
+            .iter()
+            .map(|chunk| *chunk.key())
+            .filter(|position| {
+                area.map_or(true, |(center, radius)| {
+                    (IVec2::new(position.x, position.z) - center)
+                        .abs()
+                        .max_element()
+                        <= radius
+                })
+            })
+            .collect();
+        for (column, ys) in group_columns(positions) {
+            let chunks = ys.into_iter().filter_map(|y| {
+                let chunk = scene.chunks.get(&IVec3::new(column.x, y, column.y))?;
+                Some((y, chunk.voxels().clone()))
+            });
+            self.tiles.insert(column, MapTile::render(column, chunks));
+        }
+    }
+
+    pub fn insert(&mut self, column: IVec2, tile: MapTile) {
+        self.tiles.insert(column, tile);
+    }
+
+    // The smallest and largest chunk column with a tile
+    pub fn bounds(&self) -> Option<(IVec2, IVec2)> {
+        let mut columns = self.tiles.keys();
+        let first = *columns.next()?;
+        Some(columns.fold((first, first), |(min, max), column| {
+            (min.min(*column), max.max(*column))
+        }))
+    }
+
+    fn height_at(&self, position: IVec2) -> Option<i32> {
+        let size = CHUNK_SIZE as i32;
+        let column = IVec2::new(position.x.div_floor(size), position.y.div_floor(size));
+        let local = position - column * size;
+        self.tiles.get(&column)?.heights[(local.x * size + local.y) as usize]
+    }
+
+    // North is up, so x runs to the right and z down. Columns without a tile are transparent
+    pub fn render_region(&self, min: IVec2, max: IVec2) -> MapImage {
+        let size = CHUNK_SIZE as i32;
+        let origin = min * size;
+        let (width, height) = (
+            ((max.x - min.x + 1) * size) as u32,
+            ((max.y - min.y + 1) * size) as u32,
+        );
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        for (column, tile) in &self.tiles {
+            if column.cmplt(min).any() || column.cmpgt(max).any() {
+                continue;
+            }
+            for index in 0..COLUMN_AREA {
+                let height = match tile.heights[index] {
+                    Some(height) => height,
+                    None => continue,
+                };
+                let position =
+                    *column * size + IVec2::new(index as i32 / size, index as i32 % size);
+                let shade = match self.height_at(position - IVec2::Y) {
+                    Some(north) if height > north => HIGHER_SHADE,
+                    Some(north) if height < north => LOWER_SHADE,
+                    _ => 1.0,
+                };
+                let color = (tile.colors[index] * shade).clamp(Vec3::ZERO, Vec3::ONE) * 255.0;
+                let pixel = position - origin;
+                let start = ((pixel.y as u32 * width + pixel.x as u32) * 4) as usize;
+                pixels[start..start + 4].copy_from_slice(&[
+                    color.x as u8,
+                    color.y as u8,
+                    color.z as u8,
+                    255,
+                ]);
+            }
+        }
+        MapImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // Everything with a tile, None if there are no tiles
+    pub fn render_all(&self) -> Option<MapImage> {
+        let (min, max) = self.bounds()?;
+        Some(self.render_region(min, max))
+    }
+}
+
+// Chunk positions grouped into columns with the y positions from the top down
+fn group_columns(positions: Vec<IVec3>) -> HashMap<IVec2, Vec<i32>> {
+    let mut columns: HashMap<IVec2, Vec<i32>> = HashMap::new();
+    for position in positions {
+        columns
+            .entry(IVec2::new(position.x, position.z))
+            .or_default()
+            .push(position.y);
+    }
+    for ys in columns.values_mut() {
+        ys.sort_unstable_by(|a, b| b.cmp(a));
+    }
+    columns
+}
+
+// RGBA with 8 bits a channel, rows from the top
+pub struct MapImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl MapImage {
+    pub fn save_png(&self, path: &Path) -> Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        write_atomic(path, &self.encode_png()?)
+    }
+
+    // Written here rather than through the image crate, which only the client builds with
+    pub fn encode_png(&self) -> Result<Vec<u8>> {
+        let mut header = Vec::with_capacity(13);
+        header.extend(self.width.to_be_bytes());
+        header.extend(self.height.to_be_bytes());
+        // 8 bits per channel, RGBA, then the default compression, filter and interlace methods
+        header.extend([8, 6, 0, 0, 0]);
+
+        // Every row starts with its filter type, 0 leaves the row as it is
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for row in self.pixels.chunks((self.width * 4) as usize) {
+            encoder.write_all(&[0])?;
+            encoder.write_all(row)?;
+        }
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_png_chunk(&mut png, b"IHDR", &header);
+        write_png_chunk(&mut png, b"IDAT", &encoder.finish()?);
+        write_png_chunk(&mut png, b"IEND", &[]);
+        Ok(png)
+    }
+}
+
+fn write_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc32(kind.iter().chain(data)).to_be_bytes());
+}
+
+#[cfg(test)]
+mod map_tests {
+    use crate::voxels::voxel_shapes::voxel_shape;
+
+    use super::*;
+
+    fn flat_chunk(surface: u32) -> Vec<VoxelData> {
+        let mut voxels = vec![
+            VoxelData {
+                shape: voxel_shape::CUBE,
+                state: 0,
+                id: 0,
+            };
+            COLUMN_AREA * CHUNK_SIZE as usize
+        ];
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..=surface {
+                    voxels[pos_to_index(&UVec3::new(x, y, z)) as usize].id = 1;
+                }
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn the_highest_voxel_is_the_surface() {
+        // The empty chunk above is passed over
+        let empty: Vec<VoxelData> = flat_chunk(0)
+            .into_iter()
+            .map(|v| VoxelData { id: 0, ..v })
+            .collect();
+        let chunks = vec![(1, empty), (0, flat_chunk(4))];
+        let tile = MapTile::render(IVec2::ZERO, chunks.into_iter());
+        assert!(tile.heights.iter().all(|height| *height == Some(4)));
+    }
+
+    #[test]
+    fn png_checksums_match_the_spec() {
+        // The checksum every PNG ends with
+        assert_eq!(crc32(b"IEND".iter()), 0xAE42_6082);
+        let image = MapImage {
+            width: 1,
+            height: 1,
+            pixels: vec![255, 0, 0, 255],
+        };
+        assert!(image.encode_png().unwrap().starts_with(PNG_SIGNATURE));
+    }
+}
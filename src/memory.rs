@@ -0,0 +1,165 @@
+#[cfg(feature = "render")]
+use std::{collections::HashSet, sync::Arc};
+use std::{fmt, mem::size_of};
+
+use legion::{storage::Component, Entity, IntoQuery, World};
+
+#[cfg(feature = "render")]
+use crate::ecs::components::rendering_components::{EntityRenderer, MeshRenderer};
+use crate::{
+    ecs::components::{
+        item_components::{DroppedItem, ItemCollector},
+        network_components::RemoteEntity,
+        physics_components::{Collider, Gravity, Grounded, NoEntityCollision, Velocity},
+        player_components::{Player, PlayerId, PlayerInventory, SchematicClipboard},
+        projectile_components::Projectile,
+        rendering_components::EntityLight,
+        transformation_components::{Position, Rotation, Scale},
+        voxel_components::FallingVoxel,
+    },
+    voxels::voxel_scene::VoxelScene,
+};
+
+// Bytes held by the larger engine data, measured from the live structures so view distance and
+// budgets can be tuned against real numbers. Only memory on the CPU side is counted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub chunks: usize,
+    pub chunk_voxels: usize,
+    pub meshes: usize,
+    pub mesh_bytes: usize,
+    // Sky light is worked out from the voxels when it's needed, so only the dynamic lights hold
+    // light data
+    pub light: usize,
+    pub entities: usize,
+    pub entity_bytes: usize,
+}
+
+impl MemoryUsage {
+    // Takes the world and then the scene, so the caller locks them in that order
+    pub fn measure(world: &World, scene: &VoxelScene) -> Self {
+        let chunk_voxels = scene.chunks.iter().map(|chunk| chunk.memory_usage()).sum();
+        let (meshes, mesh_bytes) = mesh_usage(world);
+        Self {
+            chunks: scene.chunks.len(),
+            chunk_voxels,
+            meshes,
+            mesh_bytes,
+            light: component_bytes::<EntityLight>(world),
+            entities: world.len(),
+            entity_bytes: entity_bytes(world),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.chunk_voxels + self.mesh_bytes + self.light + self.entity_bytes
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Chunk voxels {} in {} chunks",
+            format_bytes(self.chunk_voxels),
+            self.chunks
+        )?;
+        writeln!(
+            f,
+            "Meshes {} in {} meshes",
+            format_bytes(self.mesh_bytes),
+            self.meshes
+        )?;
+        writeln!(f, "Light {}", format_bytes(self.light))?;
+        writeln!(
+            f,
+            "Entities {} in {} entities",
+            format_bytes(self.entity_bytes),
+            self.entities
+        )?;
+        write!(f, "Total {}", format_bytes(self.total()))
+    }
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn component_bytes<T: Component>(world: &World) -> usize {
+    <&T>::query().iter(world).count() * size_of::<T>()
+}
+
+// Legion doesn't report its storage, so the components the engine adds are counted one type at a
+// time along with what they own on the heap
+fn entity_bytes(world: &World) -> usize {
+    let inventories = <&PlayerInventory>::query()
+        .iter(world)
+        .map(|inventory| inventory.0.capacity())
+        .sum::<usize>();
+    world.len() * size_of::<Entity>()
+        + inventories
+        + component_bytes::<Position>(world)
+        + component_bytes::<Rotation>(world)
+        + component_bytes::<Scale>(world)
+        + component_bytes::<Velocity>(world)
+        + component_bytes::<Collider>(world)
+        + component_bytes::<Gravity>(world)
+        + component_bytes::<Grounded>(world)
+        + component_bytes::<NoEntityCollision>(world)
+        + component_bytes::<Player>(world)
+        + component_bytes::<PlayerId>(world)
+        + component_bytes::<PlayerInventory>(world)
+        + component_bytes::<SchematicClipboard>(world)
+        + component_bytes::<DroppedItem>(world)
+        + component_bytes::<ItemCollector>(world)
+        + component_bytes::<FallingVoxel>(world)
+        + component_bytes::<Projectile>(world)
+        + component_bytes::<RemoteEntity>(world)
+}
+
+// Renderers can share a mesh, each one is counted once
+#[cfg(feature = "render")]
+fn mesh_usage(world: &World) -> (usize, usize) {
+    let chunk_meshes = <&MeshRenderer>::query()
+        .iter(world)
+        .map(|renderer| &renderer.mesh);
+    let entity_meshes = <&EntityRenderer>::query()
+        .iter(world)
+        .map(|renderer| &renderer.mesh);
+    let mut seen = HashSet::new();
+    let mut bytes = 0;
+    for mesh in chunk_meshes.chain(entity_meshes) {
+        if seen.insert(Arc::as_ptr(mesh)) {
+            bytes += mesh.read().memory_usage();
+        }
+    }
+    (seen.len(), bytes)
+}
+
+// The server doesn't build meshes
+#[cfg(not(feature = "render"))]
+fn mesh_usage(_world: &World) -> (usize, usize) {
+    (0, 0)
+}
+
+#[cfg(test)]
+mod memory_tests {
+    use super::*;
+
+    #[test]
+    fn bytes_use_the_largest_fitting_unit() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(64 * 1024 * 1024), "64.0 MiB");
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use glam::IVec3;
+
+use crate::{
+    config,
+    persistence::{
+        binary::{ByteReader, ByteWriter},
+        chunk_storage::ChunkPayload,
+    },
+    voxels::{voxel_data::VoxelData, voxel_scene::VoxelScene},
+};
+
+use super::{messages::ServerMessage, send_queue::SendQueue};
+
+// Chunks within this many chunks of the player are streamed to the client, the view distance setting
+pub fn stream_radius() -> i32 {
+    config::current().view_distance as i32
+}
+const MAX_CHUNKS_PER_TICK: usize = 8;
+// Stops sending new chunks while this many are waiting for an acknowledgment
+const MAX_UNACKNOWLEDGED: usize = 32;
+pub const RESEND_TIMEOUT: Duration = Duration::from_secs(2);
+// A decompressed chunk is far smaller than this, anything bigger is a broken or hostile payload
+const MAX_DECOMPRESSED_SIZE: u64 = 1024 * 1024;
+
+// Chunks are sent as the same palette encoded payload used on disk, without entities, then deflated
+pub fn encode_chunk(
+    position: IVec3,
+    voxels: Vec<VoxelData>,
+    biome: Option<String>,
+) -> Result<Vec<u8>> {
+    let mut writer = ByteWriter::new();
+    ChunkPayload {
+        position,
+        voxels,
+        entities: Vec::new(),
+        light: None,
+        biome,
+        player_modified: false,
+    }
+    .write(&mut writer);
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(&writer.bytes)?;
+    Ok(encoder.finish()?)
+}
+
+pub fn decode_chunk(compressed: &[u8]) -> Result<ChunkPayload> {
+    let mut bytes = Vec::new();
+    DeflateDecoder::new(compressed)
+        .take(MAX_DECOMPRESSED_SIZE + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        bail!("Chunk payload is larger than {MAX_DECOMPRESSED_SIZE} bytes");
+    }
+    ChunkPayload::read(&mut ByteReader::new(&bytes))
+}
+
+struct SentChunk {
+    sequence: u32,
+    // The number of deltas sent for the chunk, kept across resends
+    revision: u32,
+    sent_at: Instant,
+    acknowledged: bool,
+}
+
+// Tracks which chunks a client has, one per remote session
+pub struct ChunkStreamer {
+    sent: HashMap<IVec3, SentChunk>,
+    // Sequence numbers waiting for an acknowledgment mapped to their chunk
+    pending: HashMap<u32, IVec3>,
+    next_sequence: u32,
+}
+
+impl ChunkStreamer {
+    pub fn new() -> Self {
+        Self {
+            sent: HashMap::new(),
+            pending: HashMap::new(),
+            next_sequence: 0,
+        }
+    }
+
+    pub fn acknowledge(&mut self, sequence: u32) {
+        if let Some(position) = self.pending.remove(&sequence) {
+            if let Some(chunk) = self.sent.get_mut(&position) {
+                if chunk.sequence == sequence {
+                    chunk.acknowledged = true;
+                }
+            }
+        }
+    }
+
+    pub fn has_chunk(&self, position: &IVec3) -> bool {
+        self.sent
+            .get(position)
+            .map_or(false, |chunk| chunk.acknowledged)
+    }
+
+    // Deltas are sent for every chunk the client has or has been sent, the queue keeps them behind the
+    // chunk they apply to
+    pub fn send_delta(
+        &mut self,
+        position: IVec3,
+        voxel: VoxelData,
+        center: IVec3,
+        queue: &mut SendQueue,
+    ) -> Result<()> {
+        let chunk_pos = VoxelScene::chunk_at(&position);
+        let chunk = match self.sent.get_mut(&chunk_pos) {
+            Some(chunk) => chunk,
+            None => return Ok(()),
+        };
+        chunk.revision = chunk.revision.wrapping_add(1);
+        queue.push_chunk(
+            &ServerMessage::VoxelDelta {
+                position,
+                voxel,
+                revision: chunk.revision,
+            },
+            chunk_pos,
+            center,
+        )
+    }
+
+    // The client missed a delta, forget the chunk so the next update sends it in full
+    pub fn resync(&mut self, position: IVec3) {
+        if let Some(chunk) = self.sent.remove(&position) {
+            self.pending.remove(&chunk.sequence);
+        }
+    }
+
+    // Unloads chunks that are out of range, resends chunks that were never acknowledged
+    // and sends the closest missing chunks
+    // Nothing new is sent while the queue is congested, the chunks that didn't fit are picked up by a later update
+    pub fn update(
+        &mut self,
+        scene: &VoxelScene,
+        center: IVec3,
+        queue: &mut SendQueue,
+    ) -> Result<()> {
+        let stream_radius = stream_radius();
+        let unload_distance = stream_radius + config::current().unload_margin as i32;
+        let out_of_range = self
+            .sent
+            .keys()
+            .filter(|position| horizontal_distance(**position, center) > unload_distance)
+            .copied()
+            .collect::<Vec<_>>();
+        for position in out_of_range {
+            if let Some(chunk) = self.sent.remove(&position) {
+                self.pending.remove(&chunk.sequence);
+            }
+            queue.push_chunk(&ServerMessage::UnloadChunk(position), position, center)?;
+        }
+        if queue.is_congested() {
+            return Ok(());
+        }
+
+        let timed_out = self
+            .sent
+            .iter()
+            .filter(|(_, chunk)| !chunk.acknowledged && chunk.sent_at.elapsed() > RESEND_TIMEOUT)
+            .map(|(position, _)| *position)
+            .collect::<Vec<_>>();
+        let mut budget = MAX_CHUNKS_PER_TICK;
+        for position in timed_out {
+            if budget == 0 {
+                return Ok(());
+            }
+            if self.send_chunk(scene, position, center, queue)? {
+                budget -= 1;
+            }
+        }
+
+        let mut missing = scene
+            .chunks
+            .iter()
+            .map(|chunk| *chunk.key())
+            .filter(|position| {
+                horizontal_distance(*position, center) <= stream_radius
+                    && !self.sent.contains_key(position)
+            })
+            .collect::<Vec<_>>();
+        missing.sort_by_key(|position| {
+            (
+                horizontal_distance(*position, center),
+                (position.y - center.y).abs(),
+            )
+        });
+        for position in missing {
+            if budget == 0 || self.pending.len() >= MAX_UNACKNOWLEDGED {
+                break;
+            }
+            if self.send_chunk(scene, position, center, queue)? {
+                budget -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Every send gets a new sequence number, so a late acknowledgment of an older send is ignored
+    fn send_chunk(
+        &mut self,
+        scene: &VoxelScene,
+        position: IVec3,
+        center: IVec3,
+        queue: &mut SendQueue,
+    ) -> Result<bool> {
+        let (voxels, biome) = match scene.chunks.get(&position) {
+            Some(chunk) => (
+                chunk.voxels().clone(),
+                chunk.biome.as_deref().map(str::to_string),
+            ),
+            None => return Ok(false),
+        };
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let revision = self.sent.get(&position).map_or(0, |chunk| chunk.revision);
+        queue.push_chunk(
+            &ServerMessage::ChunkData {
+                sequence,
+                revision,
+                payload: encode_chunk(position, voxels, biome)?,
+            },
+            position,
+            center,
+        )?;
+        if let Some(previous) = self.sent.insert(
+            position,
+            SentChunk {
+                sequence,
+                revision,
+                sent_at: Instant::now(),
+                acknowledged: false,
+            },
+        ) {
+            self.pending.remove(&previous.sequence);
+        }
+        self.pending.insert(sequence, position);
+        Ok(true)
+    }
+}
+
+// Vertical distance is ignored, the whole column around the player is streamed
+pub fn horizontal_distance(a: IVec3, b: IVec3) -> i32 {
+    let offset = (a - b).abs();
+    offset.x.max(offset.z)
+}
@@ -0,0 +1,176 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use flume::{Receiver, Sender, TryRecvError};
+use tracing::warn;
+
+use crate::persistence::binary::{ByteReader, ByteWriter};
+
+use super::{
+    messages::Message,
+    network_stats::{MessageCategory, TrafficCounter},
+};
+
+// Frames larger than this are treated as a broken connection rather than allocated
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+// A two way stream of encoded messages. In process both ends are channels,
+// over a socket a reader and a writer thread move the frames between the channels and the stream
+pub struct Connection {
+    outgoing: Sender<Vec<u8>>,
+    incoming: Receiver<Vec<u8>>,
+    pub remote: String,
+    // Both ends of a local connection share the same world and scene
+    pub is_local: bool,
+    // Bytes handed to the writer thread that haven't reached the socket yet, always 0 in process
+    unsent: Arc<AtomicUsize>,
+    stats: Arc<TrafficCounter>,
+}
+
+impl Connection {
+    // Used for singleplayer, where the client and server run in the same process
+    pub fn local_pair() -> (Self, Self) {
+        let (client_sender, server_receiver) = flume::unbounded();
+        let (server_sender, client_receiver) = flume::unbounded();
+        (
+            Self {
+                outgoing: client_sender,
+                incoming: client_receiver,
+                remote: "local server".to_string(),
+                is_local: true,
+                unsent: Arc::new(AtomicUsize::new(0)),
+                stats: TrafficCounter::register("local server", true),
+            },
+            Self {
+                outgoing: server_sender,
+                incoming: server_receiver,
+                remote: "local client".to_string(),
+                is_local: true,
+                unsent: Arc::new(AtomicUsize::new(0)),
+                stats: TrafficCounter::register("local client", true),
+            },
+        )
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> Result<Self> {
+        Self::from_stream(TcpStream::connect(address)?)
+    }
+
+    // Frames are written as a little endian u32 length followed by the message
+    pub fn from_stream(stream: TcpStream) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        let remote = stream.peer_addr()?.to_string();
+        let (outgoing, outgoing_receiver) = flume::unbounded::<Vec<u8>>();
+        let (incoming_sender, incoming) = flume::unbounded();
+        let unsent = Arc::new(AtomicUsize::new(0));
+
+        let mut write_stream = stream.try_clone()?;
+        let writer_unsent = Arc::clone(&unsent);
+        thread::Builder::new()
+            .name(format!("{remote} writer"))
+            .spawn(move || {
+                for frame in outgoing_receiver.iter() {
+                    let length = (frame.len() as u32).to_le_bytes();
+                    if write_stream.write_all(&length).is_err()
+                        || write_stream.write_all(&frame).is_err()
+                    {
+                        break;
+                    }
+                    writer_unsent.fetch_sub(frame.len(), Ordering::Relaxed);
+                }
+                let _ = write_stream.shutdown(std::net::Shutdown::Both);
+            })?;
+
+        let mut read_stream = stream;
+        let reader_remote = remote.clone();
+        thread::Builder::new()
+            .name(format!("{remote} reader"))
+            .spawn(move || loop {
+                let mut length = [0; 4];
+                if read_stream.read_exact(&mut length).is_err() {
+                    break;
+                }
+                let length = u32::from_le_bytes(length) as usize;
+                if length > MAX_FRAME_SIZE {
+                    warn!("{reader_remote} sent a frame of {length} bytes, closing the connection");
+                    break;
+                }
+                let mut frame = vec![0; length];
+                if read_stream.read_exact(&mut frame).is_err()
+                    || incoming_sender.send(frame).is_err()
+                {
+                    break;
+                }
+            })?;
+
+        Ok(Self {
+            outgoing,
+            incoming,
+            stats: TrafficCounter::register(&remote, false),
+            remote,
+            is_local: false,
+            unsent,
+        })
+    }
+
+    pub fn send<M: Message>(&self, message: &M) -> Result<()> {
+        self.send_frame(message.category(), encode(message))
+    }
+
+    // Sends a message that was already encoded with encode
+    pub fn send_frame(&self, category: MessageCategory, frame: Vec<u8>) -> Result<()> {
+        if !self.is_local {
+            self.unsent.fetch_add(frame.len(), Ordering::Relaxed);
+        }
+        self.stats.record_sent(category, frame.len());
+        self.outgoing
+            .send(frame)
+            .map_err(|_| anyhow!("Connection to {} is closed", self.remote))
+    }
+
+    pub fn unsent_bytes(&self) -> usize {
+        self.unsent.load(Ordering::Relaxed)
+    }
+
+    // Returns None when nothing has arrived, and an error once the other end is gone
+    pub fn try_recv<M: Message>(&self) -> Result<Option<M>> {
+        match self.incoming.try_recv() {
+            Ok(frame) => Ok(Some(self.decode(&frame)?)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => bail!("Connection to {} is closed", self.remote),
+        }
+    }
+
+    pub fn recv_timeout<M: Message>(&self, timeout: Duration) -> Result<M> {
+        let frame = self
+            .incoming
+            .recv_timeout(timeout)
+            .map_err(|e| anyhow!("No message from {}: {e}", self.remote))?;
+        self.decode(&frame)
+    }
+
+    fn decode<M: Message>(&self, frame: &[u8]) -> Result<M> {
+        let mut reader = ByteReader::new(frame);
+        let message = M::read(&mut reader)?;
+        if !reader.is_empty() {
+            bail!("Message has trailing data");
+        }
+        self.stats.record_received(message.category(), frame.len());
+        Ok(message)
+    }
+}
+
+pub fn encode<M: Message>(message: &M) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+    message.write(&mut writer);
+    writer.bytes
+}
@@ -0,0 +1,137 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    ecs::components::player_components::PlayerId,
+    persistence::binary::{ByteReader, ByteWriter},
+    voxels::voxel_registry::voxel_id_mappings,
+};
+
+// Bumped whenever a message changes, clients and servers only talk to each other on the same version.
+// The join message starts with it and Disconnected keeps its layout so a mismatch can always be explained
+pub const PROTOCOL_VERSION: u32 = 4;
+
+// Who the player is and what the client's registries look like, checked by the server before the player joins
+#[derive(Clone, PartialEq, Debug)]
+pub struct JoinRequest {
+    pub player_id: PlayerId,
+    // Proves the client owns the player id, the server remembers the first secret used with an id
+    pub secret: u128,
+    // Voxel ids depend on the order the profiles were loaded in, so both sides have to agree on them
+    pub voxel_ids: Vec<(u16, String)>,
+}
+
+impl JoinRequest {
+    pub fn new(player_id: PlayerId, secret: u128) -> Self {
+        Self {
+            player_id,
+            secret,
+            voxel_ids: voxel_id_mappings(),
+        }
+    }
+
+    pub fn write(&self, writer: &mut ByteWriter) {
+        writer.write_bytes(&self.player_id.0.to_le_bytes());
+        writer.write_bytes(&self.secret.to_le_bytes());
+        writer.write_leb128(self.voxel_ids.len() as u64);
+        for (id, name) in &self.voxel_ids {
+            writer.write_leb128(*id as u64);
+            writer.write_string(name);
+        }
+    }
+
+    pub fn read(reader: &mut ByteReader) -> Result<Self> {
+        let player_id = PlayerId(u128::from_le_bytes(reader.read_bytes()?.try_into()?));
+        let secret = u128::from_le_bytes(reader.read_bytes()?.try_into()?);
+        let count = reader.read_leb128()?;
+        let mut voxel_ids = Vec::new();
+        for _ in 0..count {
+            voxel_ids.push((reader.read_leb128()? as u16, reader.read_string()?));
+        }
+        Ok(Self {
+            player_id,
+            secret,
+            voxel_ids,
+        })
+    }
+}
+
+pub fn check_protocol_version(client_version: u32) -> Result<()> {
+    match client_version {
+        PROTOCOL_VERSION => Ok(()),
+        older if older < PROTOCOL_VERSION => bail!(
+            "The client is outdated, it uses protocol version {older} but the server uses {PROTOCOL_VERSION}"
+        ),
+        newer => bail!(
+            "The server is outdated, it uses protocol version {PROTOCOL_VERSION} but the client uses {newer}"
+        ),
+    }
+}
+
+// Names the first voxel the two sides disagree on, so the player knows which resources differ
+pub fn check_voxel_ids(server: &[(u16, String)], client: &[(u16, String)]) -> Result<()> {
+    for (id, name) in server {
+        match client.iter().find(|(client_id, _)| client_id == id) {
+            Some((_, client_name)) if client_name == name => {}
+            Some((_, client_name)) => {
+                bail!("Voxel {id} is {name} on the server but {client_name} on the client")
+            }
+            None => bail!("The client is missing the voxel {name}"),
+        }
+    }
+    if let Some((_, name)) = client
+        .iter()
+        .find(|(id, _)| !server.iter().any(|(server_id, _)| server_id == id))
+    {
+        bail!("The server is missing the voxel {name}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod handshake_tests {
+    use super::*;
+
+    #[test]
+    fn version_mismatch_is_explained() {
+        assert!(check_protocol_version(PROTOCOL_VERSION).is_ok());
+        let error = check_protocol_version(PROTOCOL_VERSION + 1).unwrap_err();
+        assert!(error.to_string().contains("server is outdated"));
+    }
+
+    #[test]
+    fn voxel_id_mismatch_names_the_voxel() {
+        let server = vec![
+            (0, "Empty".to_string()),
+            (1, "stone".to_string()),
+            (2, "sand".to_string()),
+        ];
+        let swapped = vec![
+            (0, "Empty".to_string()),
+            (1, "sand".to_string()),
+            (2, "stone".to_string()),
+        ];
+        assert!(check_voxel_ids(&server, &server).is_ok());
+        let error = check_voxel_ids(&server, &swapped).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Voxel 1 is stone on the server but sand on the client"
+        );
+        let error = check_voxel_ids(&server[..2], &server).unwrap_err();
+        assert_eq!(error.to_string(), "The server is missing the voxel sand");
+    }
+
+    #[test]
+    fn join_request_round_trip() {
+        let request = JoinRequest {
+            player_id: PlayerId(42),
+            secret: u128::MAX,
+            voxel_ids: vec![(0, "Empty".to_string()), (7, "glass".to_string())],
+        };
+        let mut writer = ByteWriter::new();
+        request.write(&mut writer);
+        assert_eq!(
+            JoinRequest::read(&mut ByteReader::new(&writer.bytes)).unwrap(),
+            request
+        );
+    }
+}
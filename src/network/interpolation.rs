@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+
+use glam::{Quat, Vec3};
+
+// Remote entities are drawn this far in the past, so there is usually a newer snapshot to move towards
+pub const INTERPOLATION_DELAY: f64 = 0.1;
+// How far past the newest snapshot an entity keeps moving before it stops and waits
+pub const MAX_EXTRAPOLATION: f64 = 0.25;
+const MAX_SNAPSHOTS: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Snapshot {
+    time: f64,
+    position: Vec3,
+    rotation: Quat,
+}
+
+// Server transforms of one entity, ordered by server time
+#[derive(Clone, Default)]
+pub struct InterpolationBuffer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl InterpolationBuffer {
+    // Snapshots older than the newest one arrived out of order and are ignored
+    pub fn push(&mut self, time: f64, position: Vec3, rotation: Quat) {
+        if self
+            .snapshots
+            .back()
+            .map_or(false, |last| last.time >= time)
+        {
+            return;
+        }
+        self.snapshots.push_back(Snapshot {
+            time,
+            position,
+            rotation,
+        });
+        while self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    // Interpolates between the snapshots around the time, or extrapolates from the last two when it is past the newest
+    pub fn sample(&mut self, time: f64) -> Option<(Vec3, Quat)> {
+        // Keep one snapshot before the time so there is always something to interpolate from
+        while self.snapshots.len() > 2 && self.snapshots[1].time <= time {
+            self.snapshots.pop_front();
+        }
+        let first = *self.snapshots.front()?;
+        if time <= first.time || self.snapshots.len() == 1 {
+            return Some((first.position, first.rotation));
+        }
+        let second = self.snapshots[1];
+        let span = second.time - first.time;
+        let t = match time > second.time {
+            true => ((time - first.time).min(span + MAX_EXTRAPOLATION)) / span,
+            false => (time - first.time) / span,
+        } as f32;
+        Some((
+            first.position.lerp(second.position, t),
+            first.rotation.slerp(second.rotation, t.min(1.0)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod interpolation_tests {
+    use glam::{Quat, Vec3};
+
+    use super::{InterpolationBuffer, MAX_EXTRAPOLATION};
+
+    #[test]
+    fn interpolate_test() {
+        let mut buffer = InterpolationBuffer::default();
+        buffer.push(1.0, Vec3::ZERO, Quat::IDENTITY);
+        buffer.push(2.0, Vec3::new(10.0, 0.0, 0.0), Quat::IDENTITY);
+        let (position, _) = buffer.sample(1.5).unwrap();
+        assert!((position.x - 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn extrapolate_test() {
+        let mut buffer = InterpolationBuffer::default();
+        buffer.push(1.0, Vec3::ZERO, Quat::IDENTITY);
+        buffer.push(2.0, Vec3::new(10.0, 0.0, 0.0), Quat::IDENTITY);
+        let (position, _) = buffer.sample(2.1).unwrap();
+        assert!((position.x - 11.0).abs() < 1e-4);
+        // Extrapolation stops once it has gone too far past the newest snapshot
+        let (position, _) = buffer.sample(5.0).unwrap();
+        assert!((position.x - 10.0 * (1.0 + MAX_EXTRAPOLATION as f32)).abs() < 1e-4);
+    }
+}
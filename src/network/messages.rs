@@ -0,0 +1,422 @@
+use anyhow::*;
+use glam::{IVec3, Quat, Vec3};
+
+use crate::{
+    ecs::components::player_components::{game_mode_from_u8, game_mode_to_u8, GameMode},
+    network::{
+        handshake::{JoinRequest, PROTOCOL_VERSION},
+        network_stats::MessageCategory,
+    },
+    persistence::binary::{ByteReader, ByteWriter},
+    voxels::{voxel_data::VoxelData, voxel_shapes::VoxelShape},
+};
+
+// Longer chat messages are cut off by the server
+pub const MAX_CHAT_LENGTH: usize = 256;
+
+// Anything that can be sent over a connection
+pub trait Message: Sized {
+    fn write(&self, writer: &mut ByteWriter);
+    fn read(reader: &mut ByteReader) -> Result<Self>;
+    // Which traffic the message is counted as
+    fn category(&self) -> MessageCategory;
+}
+
+fn write_voxel(writer: &mut ByteWriter, voxel: VoxelData) {
+    writer.write_u8(voxel.shape.data);
+    writer.write_u8(voxel.state);
+    writer.write_leb128(voxel.id as u64);
+}
+
+fn read_voxel(reader: &mut ByteReader) -> Result<VoxelData> {
+    Ok(VoxelData {
+        shape: VoxelShape {
+            data: reader.read_u8()?,
+        },
+        state: reader.read_u8()?,
+        id: reader.read_leb128()? as u16,
+    })
+}
+
+// Sent from the client layer to the server core
+#[derive(Clone)]
+pub enum ClientMessage {
+    // The request is None if the client speaks a different protocol version and couldn't be read
+    Join {
+        protocol_version: u32,
+        request: Option<JoinRequest>,
+    },
+    // Sequence numbers the edit so the server's confirmation or rejection can be matched to the client's prediction
+    BreakVoxel {
+        sequence: u32,
+        position: IVec3,
+    },
+    PlaceVoxel {
+        sequence: u32,
+        position: IVec3,
+        voxel: VoxelData,
+    },
+    // The client started hitting a voxel in survival, the server times the break from it
+    StartBreak(IVec3),
+    Disconnect,
+    // Confirms a chunk was received, unacknowledged chunks are sent again
+    ChunkAck(u32),
+    // Asks for a full copy of a chunk after a delta was missed
+    ResyncChunk(IVec3),
+    // A chat message, or a command for the server's command registry if it starts with a slash
+    Chat(String),
+}
+
+// Sent from the server core to a client
+#[derive(Clone)]
+pub enum ServerMessage {
+    JoinAccepted {
+        world_name: String,
+        position: Vec3,
+        rotation: Quat,
+        game_mode: GameMode,
+    },
+    Disconnected {
+        reason: String,
+    },
+    // A full compressed chunk, see chunk_stream::encode_chunk
+    ChunkData {
+        sequence: u32,
+        revision: u32,
+        payload: Vec<u8>,
+    },
+    UnloadChunk(IVec3),
+    // A single changed voxel, revision counts the deltas sent for the chunk so a gap means one was missed
+    VoxelDelta {
+        position: IVec3,
+        voxel: VoxelData,
+        revision: u32,
+    },
+    // Kind is the entity profile name, or player, item:<voxel id> or falling_voxel:<voxel id>
+    EntitySpawn {
+        id: u64,
+        kind: String,
+        position: Vec3,
+        rotation: Quat,
+    },
+    EntityDespawn(u64),
+    // Seconds since the server started, used to place the transforms on the client's interpolation timeline
+    EntityTransforms {
+        server_time: f64,
+        updates: Vec<(u64, Vec3, Quat)>,
+    },
+    // The server refused a voxel edit, voxel is the server's copy the client rolls back to,
+    // or None if the chunk isn't loaded on the server
+    EditRejected {
+        sequence: u32,
+        position: IVec3,
+        voxel: Option<VoxelData>,
+        reason: String,
+    },
+    // The edit was applied, sent after the deltas carrying its result
+    EditAccepted(u32),
+    // Sender is None for the server's own messages, such as command output
+    Chat {
+        sender: Option<String>,
+        text: String,
+    },
+    // A command moved the client's own player
+    Teleport(Vec3),
+    // A command changed the game mode of the client's own player
+    GameModeChanged(GameMode),
+}
+
+impl Message for ClientMessage {
+    fn write(&self, writer: &mut ByteWriter) {
+        match self {
+            // The request is nested so a server on another version can skip it and still read the version
+            ClientMessage::Join {
+                protocol_version,
+                request,
+            } => {
+                writer.write_u8(0);
+                writer.write_leb128(*protocol_version as u64);
+                let mut request_writer = ByteWriter::new();
+                if let Some(request) = request {
+                    request.write(&mut request_writer);
+                }
+                writer.write_bytes(&request_writer.bytes);
+            }
+            ClientMessage::BreakVoxel { sequence, position } => {
+                writer.write_u8(1);
+                writer.write_leb128(*sequence as u64);
+                writer.write_ivec3(*position);
+            }
+            ClientMessage::PlaceVoxel {
+                sequence,
+                position,
+                voxel,
+            } => {
+                writer.write_u8(2);
+                writer.write_leb128(*sequence as u64);
+                writer.write_ivec3(*position);
+                write_voxel(writer, *voxel);
+            }
+            ClientMessage::Disconnect => writer.write_u8(3),
+            ClientMessage::ChunkAck(sequence) => {
+                writer.write_u8(4);
+                writer.write_leb128(*sequence as u64);
+            }
+            ClientMessage::ResyncChunk(position) => {
+                writer.write_u8(5);
+                writer.write_ivec3(*position);
+            }
+            ClientMessage::Chat(text) => {
+                writer.write_u8(6);
+                writer.write_string(text);
+            }
+            ClientMessage::StartBreak(position) => {
+                writer.write_u8(7);
+                writer.write_ivec3(*position);
+            }
+        }
+    }
+
+    fn read(reader: &mut ByteReader) -> Result<Self> {
+        Ok(match reader.read_u8()? {
+            0 => {
+                let protocol_version = reader.read_leb128()? as u32;
+                let request_bytes = reader.read_bytes()?;
+                let request = match protocol_version {
+                    PROTOCOL_VERSION => {
+                        Some(JoinRequest::read(&mut ByteReader::new(request_bytes))?)
+                    }
+                    _ => None,
+                };
+                ClientMessage::Join {
+                    protocol_version,
+                    request,
+                }
+            }
+            1 => ClientMessage::BreakVoxel {
+                sequence: reader.read_leb128()? as u32,
+                position: reader.read_ivec3()?,
+            },
+            2 => ClientMessage::PlaceVoxel {
+                sequence: reader.read_leb128()? as u32,
+                position: reader.read_ivec3()?,
+                voxel: read_voxel(reader)?,
+            },
+            3 => ClientMessage::Disconnect,
+            4 => ClientMessage::ChunkAck(reader.read_leb128()? as u32),
+            5 => ClientMessage::ResyncChunk(reader.read_ivec3()?),
+            6 => ClientMessage::Chat(reader.read_string()?),
+            7 => ClientMessage::StartBreak(reader.read_ivec3()?),
+            other => bail!("Unknown client message {other}"),
+        })
+    }
+
+    fn category(&self) -> MessageCategory {
+        match self {
+            ClientMessage::Join { .. } | ClientMessage::Disconnect => MessageCategory::Control,
+            ClientMessage::BreakVoxel { .. }
+            | ClientMessage::PlaceVoxel { .. }
+            | ClientMessage::StartBreak(_) => MessageCategory::Edits,
+            ClientMessage::ChunkAck(_) | ClientMessage::ResyncChunk(_) => MessageCategory::Chunks,
+            ClientMessage::Chat(_) => MessageCategory::Chat,
+        }
+    }
+}
+
+impl Message for ServerMessage {
+    fn write(&self, writer: &mut ByteWriter) {
+        match self {
+            ServerMessage::JoinAccepted {
+                world_name,
+                position,
+                rotation,
+                game_mode,
+            } => {
+                writer.write_u8(0);
+                writer.write_string(world_name);
+                writer.write_vec3(*position);
+                writer.write_quat(*rotation);
+                writer.write_u8(game_mode_to_u8(*game_mode));
+            }
+            ServerMessage::Disconnected { reason } => {
+                writer.write_u8(1);
+                writer.write_string(reason);
+            }
+            ServerMessage::ChunkData {
+                sequence,
+                revision,
+                payload,
+            } => {
+                writer.write_u8(2);
+                writer.write_leb128(*sequence as u64);
+                writer.write_leb128(*revision as u64);
+                writer.write_bytes(payload);
+            }
+            ServerMessage::UnloadChunk(position) => {
+                writer.write_u8(3);
+                writer.write_ivec3(*position);
+            }
+            ServerMessage::VoxelDelta {
+                position,
+                voxel,
+                revision,
+            } => {
+                writer.write_u8(4);
+                writer.write_ivec3(*position);
+                write_voxel(writer, *voxel);
+                writer.write_leb128(*revision as u64);
+            }
+            ServerMessage::EntitySpawn {
+                id,
+                kind,
+                position,
+                rotation,
+            } => {
+                writer.write_u8(5);
+                writer.write_leb128(*id);
+                writer.write_string(kind);
+                writer.write_vec3(*position);
+                writer.write_quat(*rotation);
+            }
+            ServerMessage::EntityDespawn(id) => {
+                writer.write_u8(6);
+                writer.write_leb128(*id);
+            }
+            ServerMessage::EntityTransforms {
+                server_time,
+                updates,
+            } => {
+                writer.write_u8(7);
+                writer.write_f64(*server_time);
+                writer.write_leb128(updates.len() as u64);
+                for (id, position, rotation) in updates {
+                    writer.write_leb128(*id);
+                    writer.write_vec3(*position);
+                    writer.write_quat(*rotation);
+                }
+            }
+            ServerMessage::EditRejected {
+                sequence,
+                position,
+                voxel,
+                reason,
+            } => {
+                writer.write_u8(8);
+                writer.write_leb128(*sequence as u64);
+                writer.write_ivec3(*position);
+                writer.write_u8(voxel.is_some() as u8);
+                if let Some(voxel) = voxel {
+                    write_voxel(writer, *voxel);
+                }
+                writer.write_string(reason);
+            }
+            ServerMessage::EditAccepted(sequence) => {
+                writer.write_u8(9);
+                writer.write_leb128(*sequence as u64);
+            }
+            ServerMessage::Chat { sender, text } => {
+                writer.write_u8(10);
+                writer.write_u8(sender.is_some() as u8);
+                if let Some(sender) = sender {
+                    writer.write_string(sender);
+                }
+                writer.write_string(text);
+            }
+            ServerMessage::Teleport(position) => {
+                writer.write_u8(11);
+                writer.write_vec3(*position);
+            }
+            ServerMessage::GameModeChanged(game_mode) => {
+                writer.write_u8(12);
+                writer.write_u8(game_mode_to_u8(*game_mode));
+            }
+        }
+    }
+
+    fn read(reader: &mut ByteReader) -> Result<Self> {
+        Ok(match reader.read_u8()? {
+            0 => ServerMessage::JoinAccepted {
+                world_name: reader.read_string()?,
+                position: reader.read_vec3()?,
+                rotation: reader.read_quat()?,
+                game_mode: game_mode_from_u8(reader.read_u8()?)?,
+            },
+            1 => ServerMessage::Disconnected {
+                reason: reader.read_string()?,
+            },
+            2 => ServerMessage::ChunkData {
+                sequence: reader.read_leb128()? as u32,
+                revision: reader.read_leb128()? as u32,
+                payload: reader.read_bytes()?.to_vec(),
+            },
+            3 => ServerMessage::UnloadChunk(reader.read_ivec3()?),
+            4 => ServerMessage::VoxelDelta {
+                position: reader.read_ivec3()?,
+                voxel: read_voxel(reader)?,
+                revision: reader.read_leb128()? as u32,
+            },
+            5 => ServerMessage::EntitySpawn {
+                id: reader.read_leb128()?,
+                kind: reader.read_string()?,
+                position: reader.read_vec3()?,
+                rotation: reader.read_quat()?,
+            },
+            6 => ServerMessage::EntityDespawn(reader.read_leb128()?),
+            7 => {
+                let server_time = reader.read_f64()?;
+                let count = reader.read_leb128()?;
+                let mut updates = Vec::new();
+                for _ in 0..count {
+                    updates.push((
+                        reader.read_leb128()?,
+                        reader.read_vec3()?,
+                        reader.read_quat()?,
+                    ));
+                }
+                ServerMessage::EntityTransforms {
+                    server_time,
+                    updates,
+                }
+            }
+            8 => ServerMessage::EditRejected {
+                sequence: reader.read_leb128()? as u32,
+                position: reader.read_ivec3()?,
+                voxel: match reader.read_u8()? {
+                    0 => None,
+                    _ => Some(read_voxel(reader)?),
+                },
+                reason: reader.read_string()?,
+            },
+            9 => ServerMessage::EditAccepted(reader.read_leb128()? as u32),
+            10 => ServerMessage::Chat {
+                sender: match reader.read_u8()? {
+                    0 => None,
+                    _ => Some(reader.read_string()?),
+                },
+                text: reader.read_string()?,
+            },
+            11 => ServerMessage::Teleport(reader.read_vec3()?),
+            12 => ServerMessage::GameModeChanged(game_mode_from_u8(reader.read_u8()?)?),
+            other => bail!("Unknown server message {other}"),
+        })
+    }
+
+    fn category(&self) -> MessageCategory {
+        match self {
+            ServerMessage::JoinAccepted { .. }
+            | ServerMessage::Disconnected { .. }
+            | ServerMessage::GameModeChanged(_) => MessageCategory::Control,
+            ServerMessage::ChunkData { .. } | ServerMessage::UnloadChunk(_) => {
+                MessageCategory::Chunks
+            }
+            ServerMessage::VoxelDelta { .. }
+            | ServerMessage::EditRejected { .. }
+            | ServerMessage::EditAccepted(_) => MessageCategory::Edits,
+            ServerMessage::EntitySpawn { .. }
+            | ServerMessage::EntityDespawn(_)
+            | ServerMessage::EntityTransforms { .. }
+            | ServerMessage::Teleport(_) => MessageCategory::Entities,
+            ServerMessage::Chat { .. } => MessageCategory::Chat,
+        }
+    }
+}
@@ -0,0 +1,8 @@
+pub mod chunk_stream;
+pub mod connection;
+pub mod handshake;
+pub mod interpolation;
+pub mod messages;
+pub mod network_stats;
+pub mod replication;
+pub mod send_queue;
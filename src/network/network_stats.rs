@@ -0,0 +1,270 @@
+use std::{
+    fmt,
+    ops::AddAssign,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::memory::format_bytes;
+
+// Rates are counted over whole seconds, the last finished one is reported
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    // Every open connection's counters, dropped connections are pruned when the stats are read
+    static ref CONNECTIONS: Mutex<Vec<Weak<TrafficCounter>>> = Mutex::new(Vec::new());
+}
+
+// What a message is about, traffic is counted separately for each
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageCategory {
+    // Joining, leaving and anything else about the session itself
+    Control,
+    // Streamed chunks, unloads and their acknowledgements
+    Chunks,
+    // Spawns, despawns and transforms
+    Entities,
+    // Voxel edits, their answers and the deltas replicating changed voxels
+    Edits,
+    Chat,
+}
+
+impl MessageCategory {
+    pub const ALL: [MessageCategory; 5] = [
+        MessageCategory::Control,
+        MessageCategory::Chunks,
+        MessageCategory::Entities,
+        MessageCategory::Edits,
+        MessageCategory::Chat,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageCategory::Control => "control",
+            MessageCategory::Chunks => "chunks",
+            MessageCategory::Entities => "entities",
+            MessageCategory::Edits => "edits",
+            MessageCategory::Chat => "chat",
+        }
+    }
+}
+
+// Bytes are counted as encoded, without the length prefix sockets add to every frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub bytes: u64,
+    pub packets: u64,
+}
+
+impl AddAssign for Traffic {
+    fn add_assign(&mut self, other: Self) {
+        self.bytes += other.bytes;
+        self.packets += other.packets;
+    }
+}
+
+// Traffic in one direction by category
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CategoryTraffic([Traffic; MessageCategory::ALL.len()]);
+
+impl CategoryTraffic {
+    pub fn get(&self, category: MessageCategory) -> Traffic {
+        self.0[category as usize]
+    }
+
+    pub fn total(&self) -> Traffic {
+        let mut total = Traffic::default();
+        for traffic in self.0 {
+            total += traffic;
+        }
+        total
+    }
+
+    fn record(&mut self, category: MessageCategory, bytes: usize) {
+        self.0[category as usize] += Traffic {
+            bytes: bytes as u64,
+            packets: 1,
+        };
+    }
+}
+
+struct Counts {
+    sent: CategoryTraffic,
+    received: CategoryTraffic,
+    // Sent and received during the second being counted and during the one before it
+    window: (CategoryTraffic, CategoryTraffic),
+    last_window: (CategoryTraffic, CategoryTraffic),
+    window_start: Instant,
+}
+
+impl Counts {
+    // Starts a new window once a second has passed. After a quiet second the last one is empty
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+        self.last_window = match elapsed < RATE_WINDOW * 2 {
+            true => self.window,
+            false => Default::default(),
+        };
+        self.window = Default::default();
+        self.window_start = now;
+    }
+}
+
+// Counts the traffic of one connection, shared between it and the registry
+pub struct TrafficCounter {
+    remote: String,
+    is_local: bool,
+    opened: Instant,
+    counts: Mutex<Counts>,
+}
+
+impl TrafficCounter {
+    // The counter is listed by connection_stats until the connection drops it
+    pub fn register(remote: &str, is_local: bool) -> Arc<Self> {
+        let now = Instant::now();
+        let counter = Arc::new(Self {
+            remote: remote.to_string(),
+            is_local,
+            opened: now,
+            counts: Mutex::new(Counts {
+                sent: CategoryTraffic::default(),
+                received: CategoryTraffic::default(),
+                window: Default::default(),
+                last_window: Default::default(),
+                window_start: now,
+            }),
+        });
+        CONNECTIONS.lock().push(Arc::downgrade(&counter));
+        counter
+    }
+
+    pub fn record_sent(&self, category: MessageCategory, bytes: usize) {
+        let mut counts = self.counts.lock();
+        counts.roll(Instant::now());
+        counts.sent.record(category, bytes);
+        counts.window.0.record(category, bytes);
+    }
+
+    pub fn record_received(&self, category: MessageCategory, bytes: usize) {
+        let mut counts = self.counts.lock();
+        counts.roll(Instant::now());
+        counts.received.record(category, bytes);
+        counts.window.1.record(category, bytes);
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        let mut counts = self.counts.lock();
+        counts.roll(Instant::now());
+        ConnectionStats {
+            remote: self.remote.clone(),
+            is_local: self.is_local,
+            open_for: self.opened.elapsed(),
+            sent: counts.sent,
+            received: counts.received,
+            sent_per_second: counts.last_window.0,
+            received_per_second: counts.last_window.1,
+        }
+    }
+}
+
+// A snapshot of a connection's traffic, seen from this end
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionStats {
+    pub remote: String,
+    pub is_local: bool,
+    pub open_for: Duration,
+    pub sent: CategoryTraffic,
+    pub received: CategoryTraffic,
+    // Over the last full second
+    pub sent_per_second: CategoryTraffic,
+    pub received_per_second: CategoryTraffic,
+}
+
+impl fmt::Display for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (sent, received) = (self.sent.total(), self.received.total());
+        write!(
+            f,
+            "{}, open {}s: sent {} in {} packets, received {} in {} packets",
+            self.remote,
+            self.open_for.as_secs(),
+            format_bytes(sent.bytes as usize),
+            sent.packets,
+            format_bytes(received.bytes as usize),
+            received.packets
+        )?;
+        for category in MessageCategory::ALL {
+            let (sent, received) = (self.sent.get(category), self.received.get(category));
+            if sent.packets == 0 && received.packets == 0 {
+                continue;
+            }
+            write!(
+                f,
+                "\n  {} up {}/s {}, down {}/s {}",
+                category.name(),
+                format_bytes(self.sent_per_second.get(category).bytes as usize),
+                format_bytes(sent.bytes as usize),
+                format_bytes(self.received_per_second.get(category).bytes as usize),
+                format_bytes(received.bytes as usize)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// The traffic of every open connection in this process. In singleplayer both ends of the local
+// connection are listed
+pub fn connection_stats() -> Vec<ConnectionStats> {
+    let mut connections = CONNECTIONS.lock();
+    connections.retain(|counter| counter.strong_count() > 0);
+    connections
+        .iter()
+        .filter_map(|counter| Some(counter.upgrade()?.stats()))
+        .collect()
+}
+
+#[cfg(test)]
+mod network_stats_tests {
+    use super::*;
+
+    #[test]
+    fn traffic_is_counted_by_category_and_second() {
+        let counter = TrafficCounter::register("test", true);
+        counter.record_sent(MessageCategory::Chunks, 100);
+        counter.record_sent(MessageCategory::Chunks, 50);
+        counter.record_received(MessageCategory::Chat, 7);
+        let stats = counter.stats();
+        assert_eq!(
+            stats.sent.get(MessageCategory::Chunks),
+            Traffic {
+                bytes: 150,
+                packets: 2
+            }
+        );
+        assert_eq!(stats.received.total().bytes, 7);
+        assert_eq!(stats.sent.get(MessageCategory::Chat), Traffic::default());
+        // Nothing has been counted for a full second yet
+        assert_eq!(stats.sent_per_second.total(), Traffic::default());
+
+        let mut counts = counter.counts.lock();
+        let start = counts.window_start;
+        counts.roll(start + RATE_WINDOW);
+        assert_eq!(counts.last_window.0.total().bytes, 150);
+        counts.roll(start + RATE_WINDOW * 4);
+        assert_eq!(counts.last_window.0.total(), Traffic::default());
+        drop(counts);
+
+        assert!(connection_stats()
+            .iter()
+            .any(|stats| stats.remote == "test"));
+        drop(counter);
+        assert!(!connection_stats()
+            .iter()
+            .any(|stats| stats.remote == "test"));
+    }
+}
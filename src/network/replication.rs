@@ -0,0 +1,191 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use glam::{IVec3, Quat, Vec3};
+use legion::{query::component, Entity, IntoQuery};
+
+use crate::{
+    ecs::{
+        components::{
+            item_components::DroppedItem,
+            player_components::{Player, Spectating},
+            transformation_components::{Position, Rotation},
+            voxel_components::FallingVoxel,
+        },
+        entities::entity_registry::EntityKind,
+    },
+    voxels::voxel_scene::VoxelScene,
+};
+
+use super::{
+    chunk_stream::stream_radius,
+    messages::ServerMessage,
+    send_queue::{Priority, SendQueue},
+};
+
+pub const DEFAULT_ENTITY_UPDATE_RATE: u32 = 10;
+// Transforms that moved less than this since the last update aren't sent again
+const POSITION_EPSILON: f32 = 0.01;
+const ROTATION_EPSILON: f32 = 0.001;
+
+// An entity as it is seen by clients
+#[derive(Clone)]
+pub struct ReplicatedEntity {
+    pub id: u64,
+    pub entity: Entity,
+    pub kind: String,
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+// Gives entities ids that stay the same for as long as the entity exists, legion entities aren't stable across worlds
+pub struct NetworkIds {
+    ids: HashMap<Entity, u64>,
+    next_id: u64,
+}
+
+impl NetworkIds {
+    pub fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    // Collects every entity clients should see, ids of entities that no longer exist are forgotten.
+    // Spectators aren't seen, other clients despawn a player that starts spectating
+    pub fn collect(&mut self, world: &legion::World) -> Vec<ReplicatedEntity> {
+        let mut query = <(
+            Entity,
+            &Position,
+            Option<&Rotation>,
+            Option<&EntityKind>,
+            Option<&Player>,
+            Option<&DroppedItem>,
+            Option<&FallingVoxel>,
+        )>::query()
+        .filter(!component::<Spectating>());
+        let mut entities = Vec::new();
+        let mut alive = HashSet::new();
+        for (entity, position, rotation, kind, player, item, falling) in query.iter(world) {
+            let kind = match (kind, player, item, falling) {
+                (Some(kind), _, _, _) => kind.0.clone(),
+                (_, Some(_), _, _) => "player".to_string(),
+                (_, _, Some(item), _) => format!("item:{}", item.voxel_id),
+                (_, _, _, Some(falling)) => format!("falling_voxel:{}", { falling.voxel.id }),
+                _ => continue,
+            };
+            let next_id = &mut self.next_id;
+            let id = *self.ids.entry(*entity).or_insert_with(|| {
+                *next_id += 1;
+                *next_id - 1
+            });
+            alive.insert(*entity);
+            entities.push(ReplicatedEntity {
+                id,
+                entity: *entity,
+                kind,
+                position: position.0,
+                rotation: rotation.map_or(Quat::IDENTITY, |r| r.0),
+            });
+        }
+        self.ids.retain(|entity, _| alive.contains(entity));
+        entities
+    }
+}
+
+// Tracks which entities a client knows about and the transforms it was last sent
+pub struct EntityReplicator {
+    known: HashMap<u64, (Vec3, Quat)>,
+    last_update: Instant,
+    start: Instant,
+}
+
+impl EntityReplicator {
+    pub fn new() -> Self {
+        Self {
+            known: HashMap::new(),
+            last_update: Instant::now(),
+            start: Instant::now(),
+        }
+    }
+
+    // Spawns and despawns are sent straight away, transforms only at the update rate and not while the
+    // queue is congested. The client's own player is skipped since the client moves it
+    pub fn update(
+        &mut self,
+        entities: &[ReplicatedEntity],
+        own_player: Option<Entity>,
+        center: IVec3,
+        update_rate: u32,
+        queue: &mut SendQueue,
+    ) -> Result<()> {
+        let stream_radius = stream_radius();
+        let visible = entities
+            .iter()
+            .filter(|e| Some(e.entity) != own_player)
+            .filter(|e| {
+                let offset = (VoxelScene::chunk_at(&e.position.round().as_ivec3()) - center).abs();
+                offset.x.max(offset.z) <= stream_radius
+            })
+            .collect::<Vec<_>>();
+
+        let visible_ids = visible.iter().map(|e| e.id).collect::<HashSet<_>>();
+        let gone = self
+            .known
+            .keys()
+            .filter(|id| !visible_ids.contains(id))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in gone {
+            self.known.remove(&id);
+            queue.push(&ServerMessage::EntityDespawn(id), Priority::Near)?;
+        }
+
+        for entity in &visible {
+            if !self.known.contains_key(&entity.id) {
+                self.known
+                    .insert(entity.id, (entity.position, entity.rotation));
+                queue.push(
+                    &ServerMessage::EntitySpawn {
+                        id: entity.id,
+                        kind: entity.kind.clone(),
+                        position: entity.position,
+                        rotation: entity.rotation,
+                    },
+                    Priority::Near,
+                )?;
+            }
+        }
+
+        let interval = Duration::from_secs_f64(1.0 / update_rate.max(1) as f64);
+        // Skipped transforms aren't lost, the known transforms stay as they were so the next update includes them
+        if self.last_update.elapsed() < interval || queue.is_congested() {
+            return Ok(());
+        }
+        self.last_update = Instant::now();
+
+        let mut updates = Vec::new();
+        for entity in visible {
+            let (position, rotation) = self.known.get_mut(&entity.id).unwrap();
+            if position.distance(entity.position) > POSITION_EPSILON
+                || rotation.angle_between(entity.rotation) > ROTATION_EPSILON
+            {
+                *position = entity.position;
+                *rotation = entity.rotation;
+                updates.push((entity.id, entity.position, entity.rotation));
+            }
+        }
+        // An empty update still carries the server time, which keeps the client's clock in step
+        queue.push(
+            &ServerMessage::EntityTransforms {
+                server_time: self.start.elapsed().as_secs_f64(),
+                updates,
+            },
+            Priority::Near,
+        )
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use glam::IVec3;
+
+use super::{
+    chunk_stream::horizontal_distance,
+    connection::{encode, Connection},
+    messages::Message,
+    network_stats::MessageCategory,
+};
+
+// Bytes per second sent to each client unless the server is configured otherwise
+pub const DEFAULT_BANDWIDTH: usize = 512 * 1024;
+// Chunk messages this many chunks or closer to the player go before anything further away
+pub const NEAR_RADIUS: i32 = 2;
+// Frames stay queued here while the connection already has this many bytes waiting for the socket,
+// so the priorities still apply to a client that reads slowly
+const MAX_UNSENT: usize = 64 * 1024;
+// New chunks and entity transforms are held back while this much is queued
+const CONGESTION_THRESHOLD: usize = 256 * 1024;
+// A client that falls this far behind is disconnected instead of growing the queue without limit
+const MAX_QUEUED: usize = 8 * 1024 * 1024;
+
+// Lower priorities are sent first, messages with the same priority keep their order
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Priority {
+    // Join replies and anything else the session can't continue without
+    Control,
+    // Entities, edit answers and chunks around the player
+    Near,
+    Far,
+}
+
+impl Priority {
+    fn for_chunk(chunk_pos: IVec3, center: IVec3) -> Self {
+        match horizontal_distance(chunk_pos, center) <= NEAR_RADIUS {
+            true => Priority::Near,
+            false => Priority::Far,
+        }
+    }
+}
+
+// Outgoing messages of one remote session, sent in priority order within a bandwidth budget
+pub struct SendQueue {
+    // Chunk messages remember their chunk, see push_chunk
+    queues: [VecDeque<(MessageCategory, Vec<u8>, Option<IVec3>)>; 3],
+    // The priority of the chunks with messages still queued and how many there are
+    queued_chunks: HashMap<IVec3, (Priority, usize)>,
+    queued_bytes: usize,
+    // Bytes that can be sent before the budget runs out, refilled at the bandwidth
+    allowance: f64,
+    last_flush: Instant,
+}
+
+impl SendQueue {
+    pub fn new() -> Self {
+        Self {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            queued_chunks: HashMap::new(),
+            queued_bytes: 0,
+            allowance: 0.0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn push<M: Message>(&mut self, message: &M, priority: Priority) -> Result<()> {
+        self.push_frame(message, priority, None)
+    }
+
+    // Chunk data, deltas and unloads are prioritized by the chunk's distance to the player. While a
+    // message about the chunk is still queued the next ones keep its priority, so a delta, resend or
+    // unload never overtakes what was queued for the chunk before it, even after the player moved
+    pub fn push_chunk<M: Message>(
+        &mut self,
+        message: &M,
+        chunk_pos: IVec3,
+        center: IVec3,
+    ) -> Result<()> {
+        let priority = match self.queued_chunks.get(&chunk_pos) {
+            Some((priority, _)) => *priority,
+            None => Priority::for_chunk(chunk_pos, center),
+        };
+        self.push_frame(message, priority, Some(chunk_pos))
+    }
+
+    fn push_frame<M: Message>(
+        &mut self,
+        message: &M,
+        priority: Priority,
+        chunk_pos: Option<IVec3>,
+    ) -> Result<()> {
+        let frame = encode(message);
+        if self.queued_bytes + frame.len() > MAX_QUEUED {
+            bail!("The client is too slow to keep up with the server");
+        }
+        self.queued_bytes += frame.len();
+        if let Some(chunk_pos) = chunk_pos {
+            self.queued_chunks
+                .entry(chunk_pos)
+                .or_insert((priority, 0))
+                .1 += 1;
+        }
+        self.queues[priority as usize].push_back((message.category(), frame, chunk_pos));
+        Ok(())
+    }
+
+    // Optional traffic such as new chunks waits while this is true
+    pub fn is_congested(&self) -> bool {
+        self.queued_bytes > CONGESTION_THRESHOLD
+    }
+
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes
+    }
+
+    // Sends the highest priority frames the budget allows. A frame bigger than the allowance still goes
+    // once the allowance is positive and the debt is paid off over the next flushes, so big chunks aren't stuck
+    pub fn flush(&mut self, connection: &Connection, bandwidth: usize) -> Result<()> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_flush).as_secs_f64();
+        self.last_flush = now;
+        // Up to a second of unused budget is kept, so bursts after a quiet period go out at once
+        self.allowance = (self.allowance + elapsed * bandwidth as f64).min(bandwidth as f64);
+        // In process connections have no bandwidth to save
+        if connection.is_local {
+            self.allowance = f64::INFINITY;
+        }
+
+        while self.allowance > 0.0 && connection.unsent_bytes() < MAX_UNSENT {
+            let next = self.queues.iter_mut().find_map(|queue| queue.pop_front());
+            let (category, frame, chunk_pos) = match next {
+                Some(queued) => queued,
+                None => break,
+            };
+            if let Some(chunk_pos) = chunk_pos {
+                if let Some((_, count)) = self.queued_chunks.get_mut(&chunk_pos) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.queued_chunks.remove(&chunk_pos);
+                    }
+                }
+            }
+            self.queued_bytes -= frame.len();
+            self.allowance -= frame.len() as f64;
+            connection.send_frame(category, frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod send_queue_tests {
+    use super::*;
+    use crate::network::messages::ServerMessage;
+
+    #[test]
+    fn higher_priorities_are_sent_first() {
+        let (client, server) = Connection::local_pair();
+        let mut queue = SendQueue::new();
+        queue
+            .push(&ServerMessage::UnloadChunk(IVec3::X), Priority::Far)
+            .unwrap();
+        queue
+            .push(&ServerMessage::EntityDespawn(7), Priority::Near)
+            .unwrap();
+        queue
+            .push(
+                &ServerMessage::Disconnected {
+                    reason: String::new(),
+                },
+                Priority::Control,
+            )
+            .unwrap();
+        queue.allowance = 1024.0;
+        queue.flush(&server, 1024 * 1024).unwrap();
+
+        assert!(matches!(
+            client.try_recv().unwrap(),
+            Some(ServerMessage::Disconnected { .. })
+        ));
+        assert!(matches!(
+            client.try_recv().unwrap(),
+            Some(ServerMessage::EntityDespawn(7))
+        ));
+        assert!(matches!(
+            client.try_recv().unwrap(),
+            Some(ServerMessage::UnloadChunk(_))
+        ));
+        assert_eq!(queue.queued_bytes(), 0);
+    }
+
+    #[test]
+    fn chunk_messages_keep_their_order() {
+        let (client, server) = Connection::local_pair();
+        let mut queue = SendQueue::new();
+        let chunk_pos = IVec3::new(NEAR_RADIUS + 1, 0, 0);
+        // Unloaded while the chunk is far, then the player walks back and it's sent again
+        queue
+            .push_chunk(
+                &ServerMessage::UnloadChunk(chunk_pos),
+                chunk_pos,
+                IVec3::ZERO,
+            )
+            .unwrap();
+        queue
+            .push_chunk(
+                &ServerMessage::ChunkData {
+                    sequence: 1,
+                    revision: 0,
+                    payload: Vec::new(),
+                },
+                chunk_pos,
+                chunk_pos,
+            )
+            .unwrap();
+        queue
+            .push(&ServerMessage::EntityDespawn(7), Priority::Near)
+            .unwrap();
+        queue.allowance = 1024.0;
+        queue.flush(&server, 1024 * 1024).unwrap();
+
+        assert!(matches!(
+            client.try_recv().unwrap(),
+            Some(ServerMessage::EntityDespawn(7))
+        ));
+        assert!(matches!(
+            client.try_recv().unwrap(),
+            Some(ServerMessage::UnloadChunk(_))
+        ));
+        assert!(matches!(
+            client.try_recv().unwrap(),
+            Some(ServerMessage::ChunkData { .. })
+        ));
+        assert!(queue.queued_chunks.is_empty());
+        // Nothing is queued for the chunk anymore, so its priority is picked again
+        queue
+            .push_chunk(&ServerMessage::UnloadChunk(chunk_pos), chunk_pos, chunk_pos)
+            .unwrap();
+        assert_eq!(queue.queued_chunks[&chunk_pos].0, Priority::Near);
+    }
+
+    #[test]
+    fn budget_limits_each_flush() {
+        let (client, server) = Connection::local_pair();
+        let mut queue = SendQueue::new();
+        for id in 0..10 {
+            queue
+                .push(&ServerMessage::EntityDespawn(id), Priority::Near)
+                .unwrap();
+        }
+        queue.allowance = 1.0;
+        queue.flush(&server, 1).unwrap();
+
+        assert!(client.try_recv::<ServerMessage>().unwrap().is_some());
+        assert!(client.try_recv::<ServerMessage>().unwrap().is_none());
+    }
+}
@@ -1 +1,3 @@
+pub mod perlin;
+#[cfg(feature = "render")]
 pub mod simplex;
@@ -3,6 +3,7 @@ use std::{borrow::Cow, time::Instant};
 
 use crate::state::State;
 
+use tracing::debug;
 use wgpu::BufferUsages;
 
 pub struct Simplex1D {
@@ -71,7 +72,7 @@ impl Simplex1D {
         // Submits command encoder for processing
         state.queue.submit(Some(encoder.finish()));
 
-        println!("Submitted in: {:?}", now.elapsed());
+        debug!("Submitted in: {:?}", now.elapsed());
 
         // Note that we're not calling `.await` here.
         let buffer_slice = output_buffer.slice(..);
@@ -93,7 +94,7 @@ impl Simplex1D {
             drop(data);
             output_buffer.unmap();
 
-            println!("Total: {:?}", now.elapsed());
+            debug!("Total: {:?}", now.elapsed());
             result
         } else {
             panic!("failed to run noise compute on gpu!")
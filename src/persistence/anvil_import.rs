@@ -0,0 +1,588 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use glam::{IVec3, UVec3};
+use tracing::{info, warn};
+
+use crate::data_packs::find_resource;
+use crate::progress::Progress;
+use crate::voxels::{
+    voxel_data::VoxelData,
+    voxel_registry::get_voxel_by_name,
+    voxel_scene::{pos_to_index, CHUNK_SIZE},
+    voxel_shapes::voxel_shape,
+};
+
+use super::{
+    chunk_storage::{ChunkPayload, ChunkStorage},
+    nbt::{read_nbt, NbtTag},
+    world_save::{WorldMetadata, WorldSave},
+};
+
+const SECTOR_SIZE: usize = 4096;
+const SECTION_VOLUME: usize = 16 * 16 * 16;
+// Block states stopped spanning two longs in snapshot 20w17a, before 1.16
+const FIRST_PADDED_DATA_VERSION: i64 = 2529;
+const DEFAULT_MAPPING: &str = "import/anvil_block_map.json";
+
+pub struct ImportOptions {
+    pub world_path: PathBuf,
+    pub region_directory: PathBuf,
+    // The mapping in the resources when None
+    pub mapping: Option<PathBuf>,
+    pub y_offset: i32,
+}
+
+impl ImportOptions {
+    // Reads --import <region directory>, --world <path>, --mapping <file> and --y-offset <chunks>
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut world_path = PathBuf::from("./saves/world");
+        let mut region_directory = None;
+        let mut mapping = None;
+        let mut y_offset = 0;
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.peek()) {
+                ("--import", Some(_)) => region_directory = args.next().map(PathBuf::from),
+                ("--world", Some(_)) => world_path = PathBuf::from(args.next().unwrap()),
+                ("--mapping", Some(_)) => mapping = args.next().map(PathBuf::from),
+                ("--y-offset", Some(_)) => {
+                    let value = args.next().unwrap();
+                    y_offset = value
+                        .parse()
+                        .map_err(|_| anyhow!("--y-offset expects a number, got {value}"))?;
+                }
+                ("--allow-newer-world", _) => {}
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        let region_directory = region_directory
+            .ok_or_else(|| anyhow!("--import needs the region directory of a Minecraft world"))?;
+        Ok(Self {
+            world_path,
+            region_directory,
+            mapping,
+            y_offset,
+        })
+    }
+}
+
+// Converts Minecraft block names to voxel ids, loaded from a JSON file shaped like
+// { "Default": null, "Blocks": { "minecraft:stone": "stone", "minecraft:air": null } }
+pub struct BlockMapping {
+    blocks: HashMap<String, u16>,
+    // Used for blocks that are missing from the table, air when None
+    default: Option<u16>,
+}
+
+fn voxel_id(name: &serde_json::Value) -> Result<u16> {
+    match name.as_str() {
+        None => Ok(0),
+        Some(name) => get_voxel_by_name(name.to_string())
+            .map(|profile| profile.id)
+            .ok_or_else(|| anyhow!("Block mapping refers to unknown voxel \"{name}\"")),
+    }
+}
+
+impl BlockMapping {
+    pub fn from_json(json: &serde_json::Value) -> Result<Self> {
+        let mut blocks = HashMap::new();
+        if let Some(table) = json.get("Blocks").and_then(|b| b.as_object()) {
+            for (block, voxel) in table {
+                blocks.insert(block.clone(), voxel_id(voxel)?);
+            }
+        }
+        let default = match json.get("Default") {
+            Some(voxel) if !voxel.is_null() => Some(voxel_id(voxel)?),
+            _ => None,
+        };
+        Ok(Self { blocks, default })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_json(&serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    // Returns None for blocks that aren't in the table
+    pub fn get(&self, block: &str) -> Option<u16> {
+        self.blocks.get(block).copied()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub chunks: usize,
+    // Minecraft chunks that couldn't be read and were skipped
+    pub failed: usize,
+    // Block names that were not in the mapping table, with how often they appeared
+    pub unmapped: HashMap<String, usize>,
+}
+
+impl ImportReport {
+    fn merge(&mut self, other: ImportReport) {
+        self.chunks += other.chunks;
+        self.failed += other.failed;
+        for (block, count) in other.unmapped {
+            *self.unmapped.entry(block).or_default() += count;
+        }
+    }
+}
+
+// Imports the region directory into the world, creating the world if there is none yet
+pub fn run(options: ImportOptions) -> Result<ImportReport> {
+    let mapping_path = match options.mapping {
+        Some(path) => path,
+        None => find_resource(DEFAULT_MAPPING)
+            .ok_or_else(|| anyhow!("There is no block mapping at {DEFAULT_MAPPING}"))?,
+    };
+    let mapping = BlockMapping::load(&mapping_path).with_context(|| {
+        format!(
+            "Failed to load the block mapping {}",
+            mapping_path.display()
+        )
+    })?;
+    let save = WorldSave::open_or_create(
+        options.world_path.clone(),
+        WorldMetadata::new("world".to_string(), rand::random(), "plains".to_string()),
+    )?;
+    let report = import_region_directory(
+        &options.region_directory,
+        &mapping,
+        &save.chunk_storage(),
+        options.y_offset,
+    )?;
+    save.write_manifest()?;
+
+    let mut unmapped = report.unmapped.iter().collect::<Vec<_>>();
+    unmapped.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    for (block, count) in unmapped {
+        warn!("{block} isn't in the block mapping, found in {count} sections");
+    }
+    info!(
+        "Imported {} chunks into {}, {} Minecraft chunks couldn't be read",
+        report.chunks,
+        options.world_path.display(),
+        report.failed
+    );
+    Ok(report)
+}
+
+// Imports every r.x.z.mca file in a Minecraft region directory
+pub fn import_region_directory(
+    directory: &Path,
+    mapping: &BlockMapping,
+    storage: &ChunkStorage,
+    y_offset: i32,
+) -> Result<ImportReport> {
+    let mut regions = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |e| e == "mca") {
+            regions.push(path);
+        }
+    }
+    let progress = Progress::start(&format!("Importing {}", directory.display()));
+    progress.stage("Converting regions", regions.len() as u64);
+    let mut report = ImportReport::default();
+    for path in regions {
+        report.merge(
+            import_region(&path, mapping, storage, y_offset)
+                .with_context(|| format!("Failed to import {}", path.display()))?,
+        );
+        progress.advance(1);
+    }
+    progress.finish();
+    Ok(report)
+}
+
+// Every 16x16x16 Minecraft chunk section becomes one chunk, shifted vertically by y_offset chunks.
+// Chunks that can't be read are skipped and counted as failed
+pub fn import_region(
+    path: &Path,
+    mapping: &BlockMapping,
+    storage: &ChunkStorage,
+    y_offset: i32,
+) -> Result<ImportReport> {
+    let bytes = fs::read(path)?;
+    if bytes.len() < SECTOR_SIZE * 2 {
+        bail!("Region file is too small to contain a header");
+    }
+
+    let mut report = ImportReport::default();
+    for index in 0..1024 {
+        // The header holds a 3 byte sector offset and a 1 byte sector count for every chunk
+        let location = &bytes[index * 4..index * 4 + 4];
+        let offset = u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize;
+        if offset == 0 || location[3] == 0 {
+            continue;
+        }
+        let converted = read_region_chunk(&bytes, offset * SECTOR_SIZE)
+            .and_then(|nbt| convert_chunk(&nbt, mapping, y_offset, &mut report.unmapped));
+        let payloads = match converted {
+            Ok(payloads) => payloads,
+            Err(e) => {
+                warn!("Skipping chunk {index} of {}: {e}", path.display());
+                report.failed += 1;
+                continue;
+            }
+        };
+        for payload in payloads {
+            storage.save(&payload)?;
+            report.chunks += 1;
+        }
+    }
+    Ok(report)
+}
+
+fn read_region_chunk(bytes: &[u8], start: usize) -> Result<NbtTag> {
+    if start + 5 > bytes.len() {
+        bail!("Chunk at byte {start} is outside of the region file");
+    }
+    let length = u32::from_be_bytes(bytes[start..start + 4].try_into()?) as usize;
+    if length == 0 || start + 4 + length > bytes.len() {
+        bail!("Chunk at byte {start} has an invalid length {length}");
+    }
+    let compression = bytes[start + 4];
+    let data = &bytes[start + 5..start + 4 + length];
+    let mut decompressed = Vec::new();
+    match compression {
+        1 => GzDecoder::new(data).read_to_end(&mut decompressed)?,
+        2 => ZlibDecoder::new(data).read_to_end(&mut decompressed)?,
+        3 => {
+            decompressed.extend_from_slice(data);
+            data.len()
+        }
+        _ => bail!("Unsupported chunk compression {compression}"),
+    };
+    read_nbt(&decompressed)
+}
+
+// Chunks from 1.18 onwards keep their sections at the root, older versions nest them in "Level"
+fn convert_chunk(
+    nbt: &NbtTag,
+    mapping: &BlockMapping,
+    y_offset: i32,
+    unmapped: &mut HashMap<String, usize>,
+) -> Result<Vec<ChunkPayload>> {
+    let (root, sections, states_name, palette_name, data_name) = match nbt.get("sections") {
+        Some(sections) => (nbt, sections, Some("block_states"), "palette", "data"),
+        None => {
+            let level = nbt
+                .get("Level")
+                .ok_or_else(|| anyhow!("Chunk has no sections"))?;
+            let sections = level
+                .get("Sections")
+                .ok_or_else(|| anyhow!("Chunk has no sections"))?;
+            (level, sections, None, "Palette", "BlockStates")
+        }
+    };
+    let chunk_x = root
+        .get("xPos")
+        .and_then(|v| v.as_i64())
+        .ok_or_else(|| anyhow!("Chunk has no xPos"))?;
+    let chunk_z = root
+        .get("zPos")
+        .and_then(|v| v.as_i64())
+        .ok_or_else(|| anyhow!("Chunk has no zPos"))?;
+    let data_version = nbt.get("DataVersion").and_then(|v| v.as_i64());
+
+    let mut payloads = Vec::new();
+    for section in sections.as_list().unwrap_or(&[]) {
+        let section_y = match section.get("Y").and_then(|v| v.as_i64()) {
+            Some(y) => y,
+            None => continue,
+        };
+        let states = match states_name {
+            Some(name) => match section.get(name) {
+                Some(states) => states,
+                None => continue,
+            },
+            None => section,
+        };
+        let palette = match states.get(palette_name).and_then(|p| p.as_list()) {
+            Some(palette) => palette,
+            None => continue, // Sections that only store lighting
+        };
+
+        let ids = palette
+            .iter()
+            .map(|entry| {
+                let name = entry
+                    .get("Name")
+                    .and_then(|n| n.as_str())
+                    .unwrap_or("minecraft:air");
+                match mapping.get(name) {
+                    Some(id) => id,
+                    None => {
+                        *unmapped.entry(name.to_string()).or_default() += 1;
+                        mapping.default.unwrap_or(0)
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        let indices = match states.get(data_name).and_then(|d| d.as_long_array()) {
+            Some(data) => unpack_indices(data, palette.len(), data_version)?,
+            None => vec![0; SECTION_VOLUME], // Single entry palettes store no data
+        };
+
+        let mut voxels = vec![
+            VoxelData {
+                shape: voxel_shape::CUBE,
+                state: 0,
+                id: 0,
+            };
+            (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize
+        ];
+        for (index, palette_index) in indices.iter().enumerate() {
+            // Minecraft orders blocks by y, then z, then x
+            let index = index as u32;
+            let position = UVec3::new(index % 16, index / 256, (index / 16) % 16);
+            let id = *ids.get(*palette_index).ok_or_else(|| {
+                anyhow!("Block state index {palette_index} is outside of the palette")
+            })?;
+            voxels[pos_to_index(&position) as usize].id = id;
+        }
+        payloads.push(ChunkPayload {
+            position: IVec3::new(chunk_x as i32, section_y as i32 + y_offset, chunk_z as i32),
+            voxels,
+            entities: Vec::new(),
+            light: None,
+            biome: None,
+            // Imported terrain can't be regenerated, so it is never pruned
+            player_modified: true,
+        });
+    }
+    Ok(payloads)
+}
+
+// Block states are packed into longs using at least 4 bits each. Since 1.16 an entry never spans two
+// longs, before that they were packed back to back. Chunks without a data version are told apart by
+// the length of the data, which only differs when the entries don't divide a long evenly
+fn unpack_indices(
+    data: &[i64],
+    palette_size: usize,
+    data_version: Option<i64>,
+) -> Result<Vec<usize>> {
+    let bits = (usize::BITS - (palette_size.max(1) - 1).leading_zeros()).max(4) as usize;
+    let mask = (1_u64 << bits) - 1;
+    let spanning_length = (SECTION_VOLUME * bits).div_ceil(64);
+    let spanning = match data_version {
+        Some(version) => version < FIRST_PADDED_DATA_VERSION,
+        None => data.len() == spanning_length,
+    };
+    if spanning {
+        if data.len() < spanning_length {
+            bail!("Block state data is too short for {bits} bits per block");
+        }
+        return Ok((0..SECTION_VOLUME)
+            .map(|i| {
+                let (long, offset) = (i * bits / 64, i * bits % 64);
+                let mut value = data[long] as u64 >> offset;
+                if offset + bits > 64 {
+                    value |= (data[long + 1] as u64) << (64 - offset);
+                }
+                (value & mask) as usize
+            })
+            .collect());
+    }
+
+    let per_long = 64 / bits;
+    if data.len() * per_long < SECTION_VOLUME {
+        bail!("Block state data is too short for {bits} bits per block");
+    }
+    Ok((0..SECTION_VOLUME)
+        .map(|i| ((data[i / per_long] as u64 >> ((i % per_long) * bits)) & mask) as usize)
+        .collect())
+}
+
+#[cfg(test)]
+mod anvil_import_tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+    use crate::persistence::nbt::write_nbt;
+
+    fn compound(values: Vec<(&str, NbtTag)>) -> NbtTag {
+        NbtTag::Compound(
+            values
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn block(name: &str) -> NbtTag {
+        compound(vec![("Name", NbtTag::String(name.to_string()))])
+    }
+
+    fn mapping() -> BlockMapping {
+        BlockMapping {
+            blocks: HashMap::from([
+                ("minecraft:air".to_string(), 0),
+                ("minecraft:stone".to_string(), 7),
+            ]),
+            default: None,
+        }
+    }
+
+    fn pack_spanning(indices: &[usize], bits: usize) -> Vec<i64> {
+        let mut data = vec![0_u64; (indices.len() * bits).div_ceil(64)];
+        for (i, index) in indices.iter().enumerate() {
+            let (long, offset) = (i * bits / 64, i * bits % 64);
+            data[long] |= (*index as u64) << offset;
+            if offset + bits > 64 {
+                data[long + 1] |= (*index as u64) >> (64 - offset);
+            }
+        }
+        data.into_iter().map(|v| v as i64).collect()
+    }
+
+    fn pack_padded(indices: &[usize], bits: usize) -> Vec<i64> {
+        let per_long = 64 / bits;
+        let mut data = vec![0_u64; indices.len().div_ceil(per_long)];
+        for (i, index) in indices.iter().enumerate() {
+            data[i / per_long] |= (*index as u64) << ((i % per_long) * bits);
+        }
+        data.into_iter().map(|v| v as i64).collect()
+    }
+
+    #[test]
+    fn both_block_state_layouts_are_unpacked() {
+        // 20 palette entries need 5 bits, which don't divide a long evenly
+        let indices = (0..SECTION_VOLUME).map(|i| i % 20).collect::<Vec<_>>();
+        let spanning = pack_spanning(&indices, 5);
+        let padded = pack_padded(&indices, 5);
+        assert_ne!(spanning.len(), padded.len());
+
+        assert_eq!(unpack_indices(&spanning, 20, Some(1976)).unwrap(), indices);
+        assert_eq!(unpack_indices(&padded, 20, Some(2586)).unwrap(), indices);
+        assert_eq!(unpack_indices(&spanning, 20, None).unwrap(), indices);
+        assert_eq!(unpack_indices(&padded, 20, None).unwrap(), indices);
+        assert!(unpack_indices(&padded[..10], 20, Some(2586)).is_err());
+    }
+
+    #[test]
+    fn old_and_new_chunks_are_converted() {
+        let mut indices = vec![0; SECTION_VOLUME];
+        indices[1] = 1; // x 1, y 0, z 0
+        indices[16] = 2; // x 0, y 0, z 1
+        let palette = NbtTag::List(vec![
+            block("minecraft:air"),
+            block("minecraft:stone"),
+            block("minecraft:dirt"),
+        ]);
+        let data = NbtTag::LongArray(pack_padded(&indices, 4));
+
+        let modern = compound(vec![
+            ("DataVersion", NbtTag::Int(2975)),
+            ("xPos", NbtTag::Int(3)),
+            ("zPos", NbtTag::Int(-2)),
+            (
+                "sections",
+                NbtTag::List(vec![compound(vec![
+                    ("Y", NbtTag::Byte(-1)),
+                    (
+                        "block_states",
+                        compound(vec![("palette", palette.clone()), ("data", data.clone())]),
+                    ),
+                ])]),
+            ),
+        ]);
+        let legacy = compound(vec![
+            ("DataVersion", NbtTag::Int(1976)),
+            (
+                "Level",
+                compound(vec![
+                    ("xPos", NbtTag::Int(3)),
+                    ("zPos", NbtTag::Int(-2)),
+                    (
+                        "Sections",
+                        NbtTag::List(vec![
+                            // Lighting only
+                            compound(vec![("Y", NbtTag::Byte(0))]),
+                            compound(vec![
+                                ("Y", NbtTag::Byte(-1)),
+                                ("Palette", palette),
+                                ("BlockStates", data),
+                            ]),
+                        ]),
+                    ),
+                ]),
+            ),
+        ]);
+
+        for chunk in [modern, legacy] {
+            let mut unmapped = HashMap::new();
+            let payloads = convert_chunk(&chunk, &mapping(), 4, &mut unmapped).unwrap();
+            assert_eq!(payloads.len(), 1);
+            let payload = &payloads[0];
+            assert_eq!(payload.position, IVec3::new(3, 3, -2));
+            let id = |x, y, z| payload.voxels[pos_to_index(&UVec3::new(x, y, z)) as usize].id;
+            assert_eq!(id(1, 0, 0), 7);
+            assert_eq!(id(0, 0, 1), 0);
+            assert_eq!(id(0, 0, 0), 0);
+            assert_eq!(unmapped.get("minecraft:dirt"), Some(&1));
+        }
+    }
+
+    #[test]
+    fn unreadable_chunks_are_skipped() {
+        let chunk = compound(vec![
+            ("DataVersion", NbtTag::Int(2975)),
+            ("xPos", NbtTag::Int(0)),
+            ("zPos", NbtTag::Int(0)),
+            (
+                "sections",
+                NbtTag::List(vec![compound(vec![
+                    ("Y", NbtTag::Byte(0)),
+                    (
+                        "block_states",
+                        compound(vec![(
+                            "palette",
+                            NbtTag::List(vec![block("minecraft:stone")]),
+                        )]),
+                    ),
+                ])]),
+            ),
+        ]);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&write_nbt(&chunk)).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut region = vec![0_u8; SECTOR_SIZE * 4];
+        // Chunk 0 is readable, chunk 1 uses an unknown compression and chunk 2 points past the end
+        region[0..4].copy_from_slice(&[0, 0, 2, 1]);
+        region[4..8].copy_from_slice(&[0, 0, 3, 1]);
+        region[8..12].copy_from_slice(&[0, 0, 9, 1]);
+        let start = SECTOR_SIZE * 2;
+        region[start..start + 4].copy_from_slice(&(compressed.len() as u32 + 1).to_be_bytes());
+        region[start + 4] = 2;
+        region[start + 5..start + 5 + compressed.len()].copy_from_slice(&compressed);
+        let start = SECTOR_SIZE * 3;
+        region[start..start + 4].copy_from_slice(&2_u32.to_be_bytes());
+        region[start + 4] = 9;
+
+        let path = std::env::temp_dir().join(format!(
+            "assemblage_anvil_import_{}.mca",
+            std::process::id()
+        ));
+        fs::write(&path, &region).unwrap();
+        let storage = ChunkStorage::in_memory();
+        let report = import_region(&path, &mapping(), &storage, 0);
+        fs::remove_file(&path).unwrap();
+        let report = report.unwrap();
+
+        assert_eq!(report.chunks, 1);
+        assert_eq!(report.failed, 2);
+        let payload = storage.load(&IVec3::ZERO).unwrap().unwrap();
+        assert!(payload.voxels.iter().all(|voxel| { voxel.id } == 7));
+    }
+}
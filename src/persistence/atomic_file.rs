@@ -0,0 +1,48 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+
+const TEMP_EXTENSION: &str = "tmp";
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map_or(OsString::new(), |n| n.to_owned());
+    name.push(".");
+    name.push(TEMP_EXTENSION);
+    path.with_file_name(name)
+}
+
+// Writes to a temporary file next to the target and renames it over the target once it is on disk,
+// so a crash mid-write leaves either the old or the new file but never a partial one
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let temp = temp_path(path);
+    let mut file = File::create(&temp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, path)?;
+
+    // The rename itself is only durable once the directory entry is flushed
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// Removes temporary files left behind by writes that were interrupted, returns how many were removed
+pub fn remove_stale_temp_files(directory: &Path) -> Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |e| e == TEMP_EXTENSION) {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
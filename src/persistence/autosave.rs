@@ -0,0 +1,211 @@
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use flume::{RecvTimeoutError, Sender};
+use parking_lot::{Mutex, RwLock};
+use tracing::error;
+
+use crate::{
+    ecs::world::World,
+    jobs::{self, JobClass, JobHandle},
+    voxels::voxel_scene::VoxelScene,
+};
+
+use super::{
+    chunk_storage::ChunkPayload,
+    player_data::{collect_players, PlayerData},
+    snapshot_dirty_chunks, snapshot_locked_chunks,
+    world_save::WorldSave,
+    write_payloads,
+};
+
+pub const DEFAULT_AUTOSAVE_INTERVAL: Duration = Duration::from_secs(120);
+// How long an emergency save waits for the world to be unlocked
+const EMERGENCY_LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+// How long the crashing thread waits for the whole emergency save
+const EMERGENCY_SAVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Periodically snapshots the dirty chunks while holding the locks, then writes them in an Io job
+// so the game never waits on the disk
+pub struct Autosave {
+    save: Arc<WorldSave>,
+    scene: Arc<RwLock<VoxelScene>>,
+    world: Arc<RwLock<World>>,
+    // The chunks saved since the last flush, or the first error
+    result: Arc<Mutex<Result<usize>>>,
+    // Each job waits on the one queued before it, so saves land on disk in order
+    last_job: Mutex<Option<JobHandle>>,
+    last_save: Mutex<Instant>,
+    pub interval: Duration,
+    // Wakes the timer thread so it exits
+    stop_timer: Sender<()>,
+    timer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Autosave {
+    pub fn start(
+        save: Arc<WorldSave>,
+        scene: Arc<RwLock<VoxelScene>>,
+        world: Arc<RwLock<World>>,
+        interval: Duration,
+    ) -> Arc<Self> {
+        let (stop_timer, stopped) = flume::bounded(1);
+        let autosave = Arc::new(Self {
+            save,
+            scene,
+            world,
+            result: Arc::new(Mutex::new(Ok(0))),
+            last_job: Mutex::new(None),
+            last_save: Mutex::new(Instant::now()),
+            interval,
+            stop_timer,
+            timer: Mutex::new(None),
+        });
+
+        let timer = Arc::clone(&autosave);
+        let thread = thread::Builder::new()
+            .name("autosave".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(Duration::from_secs(1))
+                {
+                    timer.tick();
+                }
+            })
+            .unwrap();
+        *autosave.timer.lock() = Some(thread);
+
+        autosave
+    }
+
+    fn queue_job(&self, work: impl FnOnce() + Send + 'static) {
+        let mut last_job = self.last_job.lock();
+        let dependencies = last_job.iter().cloned().collect::<Vec<_>>();
+        *last_job = Some(jobs::spawn(JobClass::Io, &dependencies, work));
+    }
+
+    fn queue_write(&self, payloads: Vec<ChunkPayload>, players: Vec<PlayerData>) {
+        let save = Arc::clone(&self.save);
+        let scene = Arc::clone(&self.scene);
+        let result = Arc::clone(&self.result);
+        self.queue_job(move || {
+            let player_storage = save.player_storage();
+            let written = write_payloads(&save.chunk_storage(), &scene, &payloads)
+                .and_then(|saved| {
+                    players
+                        .iter()
+                        .try_for_each(|player| player_storage.save(player))
+                        .map(|_| saved)
+                })
+                .and_then(|saved| save.write_manifest().map(|_| saved));
+            let mut result = result.lock();
+            match (&mut *result, written) {
+                (Ok(total), Ok(saved)) => *total += saved,
+                (Ok(_), Err(e)) => {
+                    error!("Autosave failed: {e}");
+                    *result = Err(e);
+                }
+                (Err(_), Err(e)) => error!("Autosave failed: {e}"),
+                (Err(_), Ok(_)) => {}
+            }
+        });
+    }
+
+    // Queues a save if the interval has passed since the last one
+    pub fn tick(&self) {
+        let mut last_save = self.last_save.lock();
+        if last_save.elapsed() < self.interval {
+            return;
+        }
+        *last_save = Instant::now();
+        drop(last_save);
+        self.queue_save();
+    }
+
+    pub fn queue_save(&self) {
+        let payloads = snapshot_dirty_chunks(&self.scene, &self.world);
+        let players = collect_players(&self.world.read().legion_world);
+        self.queue_write(payloads, players);
+    }
+
+    // Queues a save of a single player, used when the player leaves the world
+    pub fn save_player(&self, player: PlayerData) {
+        self.queue_write(Vec::new(), vec![player]);
+    }
+
+    // Saves everything that is still dirty and waits until all queued saves are on disk,
+    // returns the number of chunks written since the previous flush
+    pub fn flush_blocking(&self) -> Result<usize> {
+        *self.last_save.lock() = Instant::now();
+        self.queue_save();
+        let (reply_sender, reply_receiver) = flume::bounded(1);
+        let result = Arc::clone(&self.result);
+        self.queue_job(move || {
+            let _ = reply_sender.send(std::mem::replace(&mut *result.lock(), Ok(0)));
+        });
+        reply_receiver.recv()?
+    }
+
+    // Saves on a helper thread without the job system, for when the game is crashing. The crashing
+    // thread may still hold the world, the scene or one of the chunks, so it only waits so long for
+    // the save and then leaves the helper behind
+    pub fn emergency_save(&self) -> Result<usize> {
+        let save = Arc::clone(&self.save);
+        let scene = Arc::clone(&self.scene);
+        let world = Arc::clone(&self.world);
+        let (sender, receiver) = flume::bounded(1);
+        thread::Builder::new()
+            .name("emergency save".to_string())
+            .spawn(move || {
+                let _ = sender.send(emergency_write(&save, &scene, &world));
+            })?;
+        match receiver.recv_timeout(EMERGENCY_SAVE_TIMEOUT) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => bail!("The world stayed locked"),
+            Err(RecvTimeoutError::Disconnected) => bail!("The emergency save panicked"),
+        }
+    }
+
+    // Stops the timer and flushes, for when the world is closed
+    pub fn stop(&self) -> Result<usize> {
+        let _ = self.stop_timer.try_send(());
+        if let Some(timer) = self.timer.lock().take() {
+            let _ = timer.join();
+        }
+        self.flush_blocking()
+    }
+}
+
+// The locks are kept from the moment they're acquired until the chunks are copied, and released
+// before writing, which marks the chunks that fail dirty again
+fn emergency_write(
+    save: &WorldSave,
+    scene: &Arc<RwLock<VoxelScene>>,
+    world: &Arc<RwLock<World>>,
+) -> Result<usize> {
+    let (payloads, players) = {
+        let world_lock = match world.try_read_for(EMERGENCY_LOCK_TIMEOUT) {
+            Some(lock) => lock,
+            None => bail!("The world stayed locked"),
+        };
+        let scene_lock = match scene.try_read_for(EMERGENCY_LOCK_TIMEOUT) {
+            Some(lock) => lock,
+            None => bail!("The scene stayed locked"),
+        };
+        (
+            snapshot_locked_chunks(&world_lock, &scene_lock),
+            collect_players(&world_lock.legion_world),
+        )
+    };
+    let saved = write_payloads(&save.chunk_storage(), scene, &payloads)?;
+    let player_storage = save.player_storage();
+    players
+        .iter()
+        .try_for_each(|player| player_storage.save(player))?;
+    save.write_manifest()?;
+    Ok(saved)
+}
@@ -0,0 +1,362 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use tracing::warn;
+
+use crate::progress::Progress;
+
+use super::{
+    atomic_file::write_atomic,
+    binary::{ByteReader, ByteWriter},
+};
+
+pub const BACKUP_DIRECTORY: &str = "backups";
+const BACKUP_EXTENSION: &str = "backup";
+const BACKUP_MAGIC: &str = "ASSEMBLAGE BACKUP";
+// Version 1 compressed the header along with the files, later versions store it uncompressed in
+// front of them so backups can be listed without decompressing them
+const BACKUP_FORMAT_VERSION: u64 = 2;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// Labels are cut to this many characters so the header always fits in HEADER_LIMIT bytes
+const MAX_LABEL_LENGTH: usize = 256;
+const HEADER_LIMIT: u64 = 4096;
+// Bytes compressed between progress updates
+const COMPRESSION_PIECE: usize = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackupInfo {
+    // File name without the extension, used to restore the backup
+    pub name: String,
+    pub label: String,
+    pub created: SystemTime,
+    // Compressed size in bytes
+    pub size: u64,
+}
+
+// Files are stored relative to the save directory, the backups themselves are skipped
+fn collect_files(root: &Path, directory: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path == root.join(BACKUP_DIRECTORY) {
+                continue;
+            }
+            collect_files(root, &path, files)?;
+        } else {
+            files.push(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn sanitize_label(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+// Packs every file in the save directory into one compressed file inside its backups directory
+pub fn create_backup(save_directory: &Path, label: &str) -> Result<BackupInfo> {
+    let label = label.chars().take(MAX_LABEL_LENGTH).collect::<String>();
+    let created = SystemTime::now();
+    let seconds = created.duration_since(UNIX_EPOCH)?.as_secs();
+
+    let progress = Progress::start(&format!("Backing up {label}"));
+    let mut files = Vec::new();
+    collect_files(save_directory, save_directory, &mut files)?;
+    files.sort();
+    progress.stage("Reading files", files.len() as u64);
+
+    let mut header = ByteWriter::new();
+    header.write_string(BACKUP_MAGIC);
+    header.write_leb128(BACKUP_FORMAT_VERSION);
+    header.write_string(&label);
+    header.write_u64(seconds);
+
+    let mut writer = ByteWriter::new();
+    writer.write_leb128(files.len() as u64);
+    for file in &files {
+        writer.write_string(&file.to_string_lossy().replace('\\', "/"));
+        writer.write_bytes(&fs::read(save_directory.join(file))?);
+        progress.advance(1);
+    }
+
+    progress.stage("Compressing", writer.bytes.len() as u64);
+    let mut encoder = GzEncoder::new(header.bytes, Compression::default());
+    for piece in writer.bytes.chunks(COMPRESSION_PIECE) {
+        encoder.write_all(piece)?;
+        progress.advance(piece.len() as u64);
+    }
+    let compressed = encoder.finish()?;
+
+    let backup_directory = save_directory.join(BACKUP_DIRECTORY);
+    fs::create_dir_all(&backup_directory)?;
+    // Backups made within the same second get a counter so they don't replace each other
+    let base_name = format!("{seconds}_{}", sanitize_label(&label));
+    let mut name = base_name.clone();
+    let mut counter = 1;
+    while backup_path(save_directory, &name).exists() {
+        counter += 1;
+        name = format!("{base_name}_{counter}");
+    }
+    write_atomic(&backup_path(save_directory, &name), &compressed)?;
+    progress.finish();
+
+    Ok(BackupInfo {
+        name,
+        label,
+        created,
+        size: compressed.len() as u64,
+    })
+}
+
+fn backup_path(save_directory: &Path, name: &str) -> PathBuf {
+    save_directory
+        .join(BACKUP_DIRECTORY)
+        .join(format!("{name}.{BACKUP_EXTENSION}"))
+}
+
+// Only the start of the file is read, version 1 backups are decompressed just far enough
+fn read_header_bytes(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    fs::File::open(path)?
+        .take(HEADER_LIMIT)
+        .read_to_end(&mut bytes)?;
+    if bytes.starts_with(&GZIP_MAGIC) {
+        bytes.clear();
+        GzDecoder::new(fs::File::open(path)?)
+            .take(HEADER_LIMIT)
+            .read_to_end(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+// The decompressed file list that follows the header
+fn read_files(path: &Path) -> Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    let mut files = Vec::new();
+    if bytes.starts_with(&GZIP_MAGIC) {
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut files)?;
+        let mut reader = ByteReader::new(&files);
+        read_header(&mut reader)?;
+        let header_length = reader.offset();
+        files.drain(..header_length);
+    } else {
+        let mut reader = ByteReader::new(&bytes);
+        read_header(&mut reader)?;
+        GzDecoder::new(&bytes[reader.offset()..]).read_to_end(&mut files)?;
+    }
+    Ok(files)
+}
+
+fn read_header(reader: &mut ByteReader) -> Result<(String, SystemTime)> {
+    if reader.read_string()? != BACKUP_MAGIC {
+        bail!("File is not a world backup");
+    }
+    let version = reader.read_leb128()?;
+    if version > BACKUP_FORMAT_VERSION {
+        bail!(
+            "Backup format version {version} is newer than the supported version {BACKUP_FORMAT_VERSION}"
+        );
+    }
+    let label = reader.read_string()?;
+    let created = UNIX_EPOCH + Duration::from_secs(reader.read_u64()?);
+    Ok((label, created))
+}
+
+// Newest backups first
+pub fn list_backups(save_directory: &Path) -> Result<Vec<BackupInfo>> {
+    let backup_directory = save_directory.join(BACKUP_DIRECTORY);
+    if !backup_directory.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(backup_directory)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |e| e != BACKUP_EXTENSION) {
+            continue;
+        }
+        let header =
+            read_header_bytes(&path).and_then(|bytes| read_header(&mut ByteReader::new(&bytes)));
+        let (label, created) = match header {
+            Ok(header) => header,
+            Err(e) => {
+                warn!("Skipping unreadable backup {}: {e}", path.display());
+                continue;
+            }
+        };
+        backups.push(BackupInfo {
+            name: path.file_stem().unwrap().to_string_lossy().to_string(),
+            label,
+            created,
+            size: fs::metadata(&path)?.len(),
+        });
+    }
+    backups.sort_by(|a, b| b.created.cmp(&a.created));
+    Ok(backups)
+}
+
+// The directory next to the save with the suffix added to its name
+fn sibling(directory: &Path, suffix: &str) -> PathBuf {
+    let mut name = directory.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{suffix}"));
+    directory.with_file_name(name)
+}
+
+// Replaces the contents of the save directory with the backup, the world must not be open while restoring
+pub fn restore_backup(save_directory: &Path, name: &str) -> Result<()> {
+    let path = backup_path(save_directory, name);
+    let progress = Progress::start(&format!("Restoring {name}"));
+    progress.stage("Reading the backup", 1);
+    let bytes = read_files(&path).with_context(|| format!("Failed to read backup {name}"))?;
+    progress.advance(1);
+    let mut reader = ByteReader::new(&bytes);
+
+    // Read the whole backup before touching the save so a corrupt backup can't leave it half restored
+    let count = reader.read_leb128()?;
+    let mut files = Vec::new();
+    for _ in 0..count {
+        let relative = PathBuf::from(reader.read_string()?);
+        if relative.is_absolute() || relative.components().any(|c| c.as_os_str() == "..") {
+            bail!(
+                "Backup {name} contains an invalid path {}",
+                relative.display()
+            );
+        }
+        files.push((relative, reader.read_bytes()?));
+    }
+
+    // The files are written next to the save and swapped in once they are all there, so an error
+    // while writing leaves the save as it was
+    let staging = sibling(save_directory, "restoring");
+    let previous = sibling(save_directory, "previous");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    progress.stage("Writing files", files.len() as u64);
+    let written: Result<()> = files.into_iter().try_for_each(|(relative, contents)| {
+        let target = staging.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, contents)?;
+        progress.advance(1);
+        Ok(())
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e).with_context(|| format!("Failed to restore backup {name}"));
+    }
+    fs::create_dir_all(&staging)?;
+
+    if previous.exists() {
+        fs::remove_dir_all(&previous)?;
+    }
+    fs::rename(save_directory, &previous)?;
+    if let Err(e) = fs::rename(&staging, save_directory) {
+        fs::rename(&previous, save_directory)?;
+        return Err(e).with_context(|| format!("Failed to restore backup {name}"));
+    }
+    // The backups stay with the save, the replaced files are only removed once they moved over
+    let backups = previous.join(BACKUP_DIRECTORY);
+    if backups.exists() {
+        fs::rename(&backups, save_directory.join(BACKUP_DIRECTORY)).with_context(|| {
+            format!(
+                "Restored {name} but the backups are still in {}",
+                previous.display()
+            )
+        })?;
+    }
+    fs::remove_dir_all(&previous)?;
+    progress.finish();
+    Ok(())
+}
+
+#[cfg(test)]
+mod backup_tests {
+    use super::*;
+
+    fn test_save(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("chunks")).unwrap();
+        fs::write(directory.join("manifest.json"), b"{}").unwrap();
+        fs::write(directory.join("chunks/0_0_0.chunk"), [1, 2, 3]).unwrap();
+        directory
+    }
+
+    #[test]
+    fn backups_are_listed_and_restored() {
+        let directory = test_save("assemblage_backup_world");
+        let first = create_backup(&directory, "first").unwrap();
+        let second = create_backup(&directory, "first").unwrap();
+        // Made within the same second, neither replaces the other
+        assert_ne!(first.name, second.name);
+        let backups = list_backups(&directory).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert!(backups.iter().all(|backup| backup.label == "first"));
+
+        fs::write(directory.join("chunks/0_0_0.chunk"), [9]).unwrap();
+        fs::write(directory.join("new.json"), b"{}").unwrap();
+        restore_backup(&directory, &first.name).unwrap();
+        assert_eq!(
+            fs::read(directory.join("chunks/0_0_0.chunk")).unwrap(),
+            [1, 2, 3]
+        );
+        assert!(!directory.join("new.json").exists());
+        assert_eq!(list_backups(&directory).unwrap().len(), 2);
+        assert!(!sibling(&directory, "restoring").exists());
+        assert!(!sibling(&directory, "previous").exists());
+
+        // A missing backup leaves the save alone
+        assert!(restore_backup(&directory, "missing").is_err());
+        assert!(directory.join("manifest.json").exists());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn version_1_backups_are_still_read() {
+        let directory = test_save("assemblage_backup_v1_world");
+        let mut writer = ByteWriter::new();
+        writer.write_string(BACKUP_MAGIC);
+        writer.write_leb128(1);
+        writer.write_string("old");
+        writer.write_u64(1_600_000_000);
+        writer.write_leb128(1);
+        writer.write_string("manifest.json");
+        writer.write_bytes(b"{\"old\": true}");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&writer.bytes).unwrap();
+        fs::create_dir_all(directory.join(BACKUP_DIRECTORY)).unwrap();
+        fs::write(
+            backup_path(&directory, "1600000000_old"),
+            encoder.finish().unwrap(),
+        )
+        .unwrap();
+
+        let backups = list_backups(&directory).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].label, "old");
+        assert_eq!(
+            backups[0].created,
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+        restore_backup(&directory, "1600000000_old").unwrap();
+        assert_eq!(
+            fs::read(directory.join("manifest.json")).unwrap(),
+            b"{\"old\": true}"
+        );
+        assert!(!directory.join("chunks").exists());
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::*;
+use glam::{IVec3, Quat, Vec3};
+
+// Little endian writer, lengths and ids are written as LEB128 like the model format
+pub struct ByteWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl ByteWriter {
+    pub fn new() -> Self {
+        Self { bytes: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_leb128(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                break;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_ivec3(&mut self, value: IVec3) {
+        self.write_i32(value.x);
+        self.write_i32(value.y);
+        self.write_i32(value.z);
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_vec3(&mut self, value: Vec3) {
+        self.write_f32(value.x);
+        self.write_f32(value.y);
+        self.write_f32(value.z);
+    }
+
+    pub fn write_quat(&mut self, value: Quat) {
+        self.write_f32(value.x);
+        self.write_f32(value.y);
+        self.write_f32(value.z);
+        self.write_f32(value.w);
+    }
+
+    pub fn write_string(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+    }
+
+    // Length prefixed
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_leb128(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+}
+
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, cursor: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cursor >= self.bytes.len()
+    }
+
+    pub fn offset(&self) -> usize {
+        self.cursor
+    }
+
+    // The bytes read since the offset
+    pub fn since(&self, offset: usize) -> &'a [u8] {
+        &self.bytes[offset.min(self.cursor)..self.cursor]
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.cursor + length > self.bytes.len() {
+            bail!(
+                "Unexpected end of data, wanted {length} bytes at offset {}",
+                self.cursor
+            );
+        }
+        let slice = &self.bytes[self.cursor..self.cursor + length];
+        self.cursor += length;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_leb128(&mut self) -> Result<u64> {
+        let mut value = 0_u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift >= 64 {
+                bail!("LEB128 value is too large");
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub fn read_ivec3(&mut self) -> Result<IVec3> {
+        Ok(IVec3::new(
+            self.read_i32()?,
+            self.read_i32()?,
+            self.read_i32()?,
+        ))
+    }
+
+    pub fn read_vec3(&mut self) -> Result<Vec3> {
+        Ok(Vec3::new(
+            self.read_f32()?,
+            self.read_f32()?,
+            self.read_f32()?,
+        ))
+    }
+
+    pub fn read_quat(&mut self) -> Result<Quat> {
+        Ok(Quat::from_xyzw(
+            self.read_f32()?,
+            self.read_f32()?,
+            self.read_f32()?,
+            self.read_f32()?,
+        ))
+    }
+
+    pub fn read_string(&mut self) -> Result<String> {
+        Ok(String::from_utf8(self.read_bytes()?.to_vec())?)
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8]> {
+        let length = self.read_leb128()? as usize;
+        self.take(length)
+    }
+}
+
+// CRC-32 as used by PNG and zlib
+pub fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0_u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
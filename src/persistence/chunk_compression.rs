@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use glam::IVec3;
+use parking_lot::Mutex;
+
+// Chunk files starting with this byte are zlib streams of the chunk. An uncompressed chunk starts
+// with its LEB128 format version, which can't be this large
+pub const COMPRESSED_MARKER: u8 = 0xff;
+// A chunk saved again within this long is being autosaved while it's played in
+const HOT_WINDOW: Duration = Duration::from_secs(300);
+// Saved bytes per second the adaptive levels aim for. Autosaves run while the game is played and
+// have to keep up with edits, cold chunks are written once and can take longer to get smaller
+const HOT_THROUGHPUT: f64 = 32.0 * 1024.0 * 1024.0;
+const COLD_THROUGHPUT: f64 = 4.0 * 1024.0 * 1024.0;
+// Weight of the newest save in the measured throughput
+const SMOOTHING: f64 = 0.2;
+
+// How chunk files are compressed, the "Chunk Compression" setting. Changing it only affects chunks
+// saved afterwards, every chunk file loads whatever it was written with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkCompression {
+    Off,
+    // A zlib level from 1 for the fastest to 9 for the smallest files
+    Level(u32),
+    // Picks the level per chunk from how recently it was saved and how fast saves have been
+    Adaptive,
+}
+
+impl Default for ChunkCompression {
+    fn default() -> Self {
+        Self::Adaptive
+    }
+}
+
+impl ChunkCompression {
+    // "Off", "Adaptive" or a level, 0 turns compression off
+    pub fn from_json(json: &serde_json::Value) -> Option<Self> {
+        match json {
+            serde_json::Value::String(mode) => match mode.as_str() {
+                "Off" => Some(Self::Off),
+                "Adaptive" => Some(Self::Adaptive),
+                _ => None,
+            },
+            _ => match json.as_u64()? {
+                0 => Some(Self::Off),
+                level => Some(Self::Level(level.min(9) as u32)),
+            },
+        }
+    }
+}
+
+// A level that follows the measured throughput, lowered while saves are slower than the target
+// and raised again once they are well above it
+#[derive(Clone, Debug)]
+struct LevelTuner {
+    level: u32,
+    min: u32,
+    max: u32,
+    target: f64,
+    // Bytes per second at the current level, None until a save at the level was measured
+    throughput: Option<f64>,
+}
+
+impl LevelTuner {
+    fn new(min: u32, max: u32, target: f64) -> Self {
+        Self {
+            level: max,
+            min,
+            max,
+            target,
+            throughput: None,
+        }
+    }
+
+    fn record(&mut self, bytes: usize, elapsed: Duration) {
+        let sample = bytes as f64 / elapsed.as_secs_f64().max(1e-6);
+        let throughput = self.throughput.map_or(sample, |throughput| {
+            throughput + (sample - throughput) * SMOOTHING
+        });
+        self.throughput = Some(throughput);
+        // Twice the target before going up again, so the level doesn't flip every save
+        if throughput < self.target && self.level > self.min {
+            self.level -= 1;
+            self.throughput = None;
+        } else if throughput > self.target * 2.0 && self.level < self.max {
+            self.level += 1;
+            self.throughput = None;
+        }
+    }
+}
+
+struct AdaptiveState {
+    hot: LevelTuner,
+    cold: LevelTuner,
+    last_saved: HashMap<IVec3, Instant>,
+}
+
+// Compresses the chunks of one chunk storage, keeping what the adaptive mode measured
+pub struct ChunkCompressor {
+    state: Mutex<AdaptiveState>,
+}
+
+impl Default for ChunkCompressor {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(AdaptiveState {
+                hot: LevelTuner::new(1, 3, HOT_THROUGHPUT),
+                cold: LevelTuner::new(4, 9, COLD_THROUGHPUT),
+                last_saved: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl ChunkCompressor {
+    // The level the chunk is written with, None for an uncompressed file. Chunks saved again soon
+    // after are hot and get a fast level, the others are written for the archive
+    pub fn level(&self, mode: ChunkCompression, position: IVec3) -> Option<u32> {
+        match mode {
+            ChunkCompression::Off => None,
+            ChunkCompression::Level(level) => Some(level),
+            ChunkCompression::Adaptive => {
+                let state = self.state.lock();
+                let tuner = match self.is_hot(&state, position) {
+                    true => &state.hot,
+                    false => &state.cold,
+                };
+                Some(tuner.level)
+            }
+        }
+    }
+
+    fn is_hot(&self, state: &AdaptiveState, position: IVec3) -> bool {
+        state
+            .last_saved
+            .get(&position)
+            .map_or(false, |saved| saved.elapsed() < HOT_WINDOW)
+    }
+
+    // Called once the chunk is on disk, with the bytes it had before compression and how long
+    // compressing and writing it took
+    pub fn record(&self, position: IVec3, bytes: usize, elapsed: Duration) {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        match self.is_hot(state, position) {
+            true => state.hot.record(bytes, elapsed),
+            false => state.cold.record(bytes, elapsed),
+        }
+        state.last_saved.insert(position, Instant::now());
+        // Chunks that went cold don't need to be remembered
+        if state.last_saved.len() > 4096 {
+            state
+                .last_saved
+                .retain(|_, saved| saved.elapsed() < HOT_WINDOW);
+        }
+    }
+
+    // Chunks that were deleted start out cold when they are saved again
+    pub fn forget(&self, position: &IVec3) {
+        self.state.lock().last_saved.remove(position);
+    }
+}
+
+pub fn compress(bytes: &[u8], level: Option<u32>) -> Result<Vec<u8>> {
+    let level = match level {
+        Some(level) => level,
+        None => return Ok(bytes.to_vec()),
+    };
+    let mut encoder = ZlibEncoder::new(vec![COMPRESSED_MARKER], Compression::new(level));
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+// Files without the marker are returned as they are
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
+    match bytes.split_first() {
+        Some((&COMPRESSED_MARKER, stream)) => {
+            let mut decompressed = Vec::new();
+            ZlibDecoder::new(stream)
+                .read_to_end(&mut decompressed)
+                .context("The chunk file isn't a valid zlib stream")?;
+            Ok(decompressed)
+        }
+        _ => Ok(bytes.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod chunk_compression_tests {
+    use super::*;
+
+    #[test]
+    fn setting_parses_modes_and_levels() {
+        let parse = |json| ChunkCompression::from_json(&json);
+        assert_eq!(parse(serde_json::json!("Off")), Some(ChunkCompression::Off));
+        assert_eq!(parse(serde_json::json!(0)), Some(ChunkCompression::Off));
+        assert_eq!(
+            parse(serde_json::json!(12)),
+            Some(ChunkCompression::Level(9))
+        );
+        assert_eq!(
+            parse(serde_json::json!("Adaptive")),
+            Some(ChunkCompression::Adaptive)
+        );
+        assert_eq!(parse(serde_json::json!("Fast")), None);
+    }
+
+    #[test]
+    fn compressed_and_plain_files_load() {
+        let bytes = vec![3; 4096];
+        let compressed = compress(&bytes, Some(6)).unwrap();
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(decompress(&compressed).unwrap(), bytes);
+        assert_eq!(decompress(&compress(&bytes, None).unwrap()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn slow_saves_lower_the_level() {
+        let mut tuner = LevelTuner::new(4, 9, COLD_THROUGHPUT);
+        tuner.record(1024, Duration::from_secs(1));
+        assert_eq!(tuner.level, 8);
+        // Far above the target, so the level goes back up
+        tuner.record(64 << 20, Duration::from_secs(1));
+        assert_eq!(tuner.level, 9);
+    }
+
+    #[test]
+    fn chunks_saved_again_soon_are_hot() {
+        let compressor = ChunkCompressor::default();
+        let position = IVec3::new(1, 2, 3);
+        let mode = ChunkCompression::Adaptive;
+        assert_eq!(compressor.level(mode, position), Some(9));
+        compressor.record(position, 4096, Duration::from_micros(10));
+        assert_eq!(compressor.level(mode, position), Some(3));
+        compressor.forget(&position);
+        assert_eq!(compressor.level(mode, position), Some(9));
+    }
+}
@@ -0,0 +1,656 @@
+use std::{collections::HashMap, path::PathBuf, time::Instant};
+
+use anyhow::{anyhow, bail, Result};
+use glam::IVec3;
+use tracing::warn;
+
+use crate::{
+    config,
+    voxels::{voxel_data::VoxelData, voxel_scene::CHUNK_SIZE, voxel_shapes::VoxelShape},
+};
+
+use super::{
+    atomic_file::remove_stale_temp_files,
+    binary::{crc32, ByteReader, ByteWriter},
+    chunk_compression::{compress, decompress, ChunkCompressor},
+    entity_persistence::SavedEntity,
+    storage_backend::StorageBackend,
+};
+
+pub const CHUNK_FORMAT_VERSION: u64 = 3;
+const VOXEL_COUNT: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+const PLAYER_MODIFIED: u8 = 0b_0000_0001;
+// Sections after the voxels, each is only written when it has something in it. Readers skip
+// sections they don't know, so new ones don't need a new version
+const ENTITY_SECTION: u8 = 1;
+const LIGHT_SECTION: u8 = 2;
+const BIOME_SECTION: u8 = 4;
+// Written last, the CRC-32 of everything before the section count and the sections before it
+const CHECKSUM_SECTION: u8 = 3;
+
+// Everything that is written to disk for a single chunk, and sent to clients without the entities
+pub struct ChunkPayload {
+    pub position: IVec3,
+    pub voxels: Vec<VoxelData>,
+    pub entities: Vec<SavedEntity>,
+    // Light levels from 0 to 15 for every voxel, in the same order as the voxels
+    pub light: Option<Vec<u8>>,
+    // The biome the chunk was generated with, chunks saved before it was stored leave it out
+    pub biome: Option<String>,
+    // Set once a player has edited the chunk, chunks without it can be pruned and regenerated
+    pub player_modified: bool,
+}
+
+pub fn same_voxel(a: &VoxelData, b: &VoxelData) -> bool {
+    let (a_id, b_id) = (a.id, b.id);
+    a.shape == b.shape && a.state == b.state && a_id == b_id
+}
+
+// Bits needed for an index into a palette of this size, a single entry needs none
+fn index_bits(palette_size: usize) -> u32 {
+    usize::BITS - palette_size.saturating_sub(1).leading_zeros()
+}
+
+// Writes the indices as one little endian bit stream, an index may span two bytes
+fn pack_indices(indices: &[usize], bits: u32) -> Vec<u8> {
+    let mut packed = vec![0; (indices.len() * bits as usize + 7) / 8];
+    for (i, index) in indices.iter().enumerate() {
+        for bit in 0..bits as usize {
+            if index >> bit & 1 == 1 {
+                let position = i * bits as usize + bit;
+                packed[position / 8] |= 1 << (position % 8);
+            }
+        }
+    }
+    packed
+}
+
+fn unpack_indices(packed: &[u8], bits: u32, count: usize) -> Vec<usize> {
+    (0..count)
+        .map(|i| {
+            (0..bits as usize).fold(0, |index, bit| {
+                let position = i * bits as usize + bit;
+                index | ((packed[position / 8] >> (position % 8) & 1) as usize) << bit
+            })
+        })
+        .collect()
+}
+
+impl ChunkPayload {
+    pub fn write(&self, writer: &mut ByteWriter) {
+        let start = writer.bytes.len();
+        writer.write_leb128(CHUNK_FORMAT_VERSION);
+        writer.write_i32(self.position.x);
+        writer.write_i32(self.position.y);
+        writer.write_i32(self.position.z);
+        writer.write_u8(match self.player_modified {
+            true => PLAYER_MODIFIED,
+            false => 0,
+        });
+
+        // Every distinct voxel goes into the palette once, the voxels are indices into it packed
+        // with as few bits as the palette needs. A chunk of a single voxel has no indices at all
+        let mut palette: Vec<VoxelData> = Vec::new();
+        let mut palette_indices: HashMap<(u8, u8, u16), usize> = HashMap::new();
+        let indices = self
+            .voxels
+            .iter()
+            .map(|voxel| {
+                *palette_indices
+                    .entry((voxel.shape.data, voxel.state, voxel.id))
+                    .or_insert_with(|| {
+                        palette.push(*voxel);
+                        palette.len() - 1
+                    })
+            })
+            .collect::<Vec<_>>();
+        writer.write_leb128(palette.len() as u64);
+        for voxel in &palette {
+            writer.write_u8(voxel.shape.data);
+            writer.write_u8(voxel.state);
+            writer.write_leb128(voxel.id as u64);
+        }
+        writer.write_bytes(&pack_indices(&indices, index_bits(palette.len())));
+
+        let mut sections = Vec::new();
+        if !self.entities.is_empty() {
+            let mut section = ByteWriter::new();
+            section.write_leb128(self.entities.len() as u64);
+            for entity in &self.entities {
+                entity.write(&mut section);
+            }
+            sections.push((ENTITY_SECTION, section.bytes));
+        }
+        if let Some(light) = &self.light {
+            // Two levels to a byte
+            let packed = light
+                .chunks(2)
+                .map(|pair| pair[0] & 0xf | pair.get(1).map_or(0, |level| level << 4))
+                .collect();
+            sections.push((LIGHT_SECTION, packed));
+        }
+        if let Some(biome) = &self.biome {
+            sections.push((BIOME_SECTION, biome.as_bytes().to_vec()));
+        }
+        let checksum = crc32(
+            writer.bytes[start..]
+                .iter()
+                .chain(sections.iter().flat_map(|(_, bytes)| bytes)),
+        );
+        sections.push((CHECKSUM_SECTION, checksum.to_le_bytes().to_vec()));
+        writer.write_leb128(sections.len() as u64);
+        for (kind, bytes) in sections {
+            writer.write_u8(kind);
+            writer.write_bytes(&bytes);
+        }
+    }
+
+    pub fn read(reader: &mut ByteReader) -> Result<Self> {
+        let start = reader.offset();
+        let version = reader.read_leb128()?;
+        if version > CHUNK_FORMAT_VERSION {
+            bail!("Chunk format version {version} is newer than the supported version {CHUNK_FORMAT_VERSION}");
+        }
+        let position = IVec3::new(reader.read_i32()?, reader.read_i32()?, reader.read_i32()?);
+        // Version 1 didn't track edits, so those chunks are assumed to be modified
+        let player_modified = match version {
+            1 => true,
+            _ => reader.read_u8()? & PLAYER_MODIFIED != 0,
+        };
+        let mut payload = Self {
+            position,
+            voxels: Vec::new(),
+            entities: Vec::new(),
+            light: None,
+            biome: None,
+            player_modified,
+        };
+
+        // Versions before 3 stored run length encoded voxels followed by the entities
+        if version < 3 {
+            payload.voxels = read_runs(reader, position)?;
+            payload.entities = read_entities(reader)?;
+            return Ok(payload);
+        }
+
+        let palette_size = reader.read_leb128()? as usize;
+        if palette_size == 0 || palette_size > VOXEL_COUNT {
+            bail!("Chunk {position} has a palette of {palette_size} voxels");
+        }
+        let mut palette = Vec::with_capacity(palette_size);
+        for _ in 0..palette_size {
+            palette.push(read_voxel(reader)?);
+        }
+        let bits = index_bits(palette_size);
+        let packed = reader.read_bytes()?;
+        if packed.len() != (VOXEL_COUNT * bits as usize + 7) / 8 {
+            bail!(
+                "Chunk {position} has {} bytes of voxel indices",
+                packed.len()
+            );
+        }
+        payload.voxels = unpack_indices(packed, bits, VOXEL_COUNT)
+            .into_iter()
+            .map(|index| {
+                palette.get(index).copied().ok_or_else(|| {
+                    anyhow!("Chunk {position} has a voxel index {index} outside of its palette")
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let header = reader.since(start);
+        let mut checked = Vec::new();
+        let section_count = reader.read_leb128()?;
+        for _ in 0..section_count {
+            let kind = reader.read_u8()?;
+            let bytes = reader.read_bytes()?;
+            if kind == CHECKSUM_SECTION {
+                let checksum = crc32(header.iter().chain(checked.iter().copied().flatten()));
+                if bytes != &checksum.to_le_bytes()[..] {
+                    bail!("Chunk {position} doesn't match its checksum");
+                }
+                continue;
+            }
+            checked.push(bytes);
+            match kind {
+                ENTITY_SECTION => {
+                    let mut section = ByteReader::new(bytes);
+                    payload.entities = read_entities(&mut section)?;
+                }
+                LIGHT_SECTION => {
+                    if bytes.len() != (VOXEL_COUNT + 1) / 2 {
+                        bail!("Chunk {position} has {} bytes of light", bytes.len());
+                    }
+                    let light = bytes
+                        .iter()
+                        .flat_map(|pair| [pair & 0xf, pair >> 4])
+                        .take(VOXEL_COUNT)
+                        .collect();
+                    payload.light = Some(light);
+                }
+                BIOME_SECTION => {
+                    let biome = String::from_utf8(bytes.to_vec())
+                        .map_err(|_| anyhow!("Chunk {position} has a biome that isn't UTF-8"))?;
+                    payload.biome = Some(biome);
+                }
+                _ => {}
+            }
+        }
+        Ok(payload)
+    }
+}
+
+fn read_voxel(reader: &mut ByteReader) -> Result<VoxelData> {
+    Ok(VoxelData {
+        shape: VoxelShape {
+            data: reader.read_u8()?,
+        },
+        state: reader.read_u8()?,
+        id: reader.read_leb128()? as u16,
+    })
+}
+
+fn read_runs(reader: &mut ByteReader, position: IVec3) -> Result<Vec<VoxelData>> {
+    let run_count = reader.read_leb128()?;
+    let mut voxels = Vec::with_capacity(VOXEL_COUNT);
+    for _ in 0..run_count {
+        let count = reader.read_leb128()? as usize;
+        let voxel = read_voxel(reader)?;
+        if voxels.len() + count > VOXEL_COUNT {
+            bail!("Chunk {position} contains more than {VOXEL_COUNT} voxels");
+        }
+        voxels.extend(std::iter::repeat(voxel).take(count));
+    }
+    if voxels.len() != VOXEL_COUNT {
+        bail!("Chunk {position} only contains {} voxels", voxels.len());
+    }
+    Ok(voxels)
+}
+
+fn read_entities(reader: &mut ByteReader) -> Result<Vec<SavedEntity>> {
+    let entity_count = reader.read_leb128()?;
+    let mut entities = Vec::new();
+    for _ in 0..entity_count {
+        entities.push(SavedEntity::read(reader)?);
+    }
+    Ok(entities)
+}
+
+pub struct ChunkStorage {
+    backend: StorageBackend,
+    compressor: ChunkCompressor,
+}
+
+impl ChunkStorage {
+    pub fn new(directory: PathBuf) -> Result<Self> {
+        let backend = StorageBackend::disk(directory.clone())?;
+        let removed = remove_stale_temp_files(&directory)?;
+        if removed > 0 {
+            warn!("Removed {removed} interrupted chunk writes");
+        }
+        Ok(Self {
+            backend,
+            compressor: ChunkCompressor::default(),
+        })
+    }
+
+    // Chunks are kept until the storage is dropped, nothing is written to disk
+    pub fn in_memory() -> Self {
+        Self {
+            backend: StorageBackend::memory(),
+            compressor: ChunkCompressor::default(),
+        }
+    }
+
+    fn chunk_name(position: &IVec3) -> String {
+        format!("{}_{}_{}.chunk", position.x, position.y, position.z)
+    }
+
+    // Compressed with the level the Chunk Compression setting picks for the chunk
+    pub fn save(&self, payload: &ChunkPayload) -> Result<()> {
+        let start = Instant::now();
+        let mut writer = ByteWriter::new();
+        payload.write(&mut writer);
+        let mode = config::current().chunk_compression;
+        let level = self.compressor.level(mode, payload.position);
+        self.backend.write(
+            &Self::chunk_name(&payload.position),
+            &compress(&writer.bytes, level)?,
+        )?;
+        self.compressor
+            .record(payload.position, writer.bytes.len(), start.elapsed());
+        Ok(())
+    }
+
+    // Returns the number of bytes freed
+    pub fn delete(&self, position: &IVec3) -> Result<u64> {
+        self.compressor.forget(position);
+        self.backend.remove(&Self::chunk_name(position))
+    }
+
+    // The positions of every stored chunk, parsed from the file names
+    pub fn stored_chunks(&self) -> Result<Vec<IVec3>> {
+        let mut positions = Vec::new();
+        for file_name in self.backend.names()? {
+            let name = match file_name.strip_suffix(".chunk") {
+                Some(name) => name,
+                None => continue,
+            };
+            let coordinates = name
+                .split('_')
+                .map(|c| c.parse::<i32>())
+                .collect::<Result<Vec<_>, _>>();
+            match coordinates.as_deref() {
+                Ok([x, y, z]) => positions.push(IVec3::new(*x, *y, *z)),
+                _ => warn!("Ignoring unexpected file {name}.chunk in the chunk directory"),
+            }
+        }
+        Ok(positions)
+    }
+
+    pub fn contains(&self, position: &IVec3) -> bool {
+        self.backend.contains(&Self::chunk_name(position))
+    }
+
+    pub fn load(&self, position: &IVec3) -> Result<Option<ChunkPayload>> {
+        match self.backend.read(&Self::chunk_name(position))? {
+            Some(bytes) => {
+                let bytes = decompress(&bytes)?;
+                Ok(Some(ChunkPayload::read(&mut ByteReader::new(&bytes))?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod chunk_storage_tests {
+    use glam::{IVec3, Quat, Vec3};
+
+    use super::{
+        same_voxel, ChunkPayload, ChunkStorage, CHUNK_FORMAT_VERSION, PLAYER_MODIFIED, VOXEL_COUNT,
+    };
+    use crate::{
+        ecs::components::item_components::DroppedItem,
+        persistence::{
+            binary::{ByteReader, ByteWriter},
+            entity_persistence::SavedEntity,
+        },
+        voxels::{voxel_data::VoxelData, voxel_shapes::VoxelShape},
+    };
+
+    fn voxel(shape: u8, state: u8, id: u16) -> VoxelData {
+        VoxelData {
+            shape: VoxelShape { data: shape },
+            state,
+            id,
+        }
+    }
+
+    fn bare_entity(id: u64) -> SavedEntity {
+        SavedEntity {
+            id,
+            position: Vec3::new(1.5, -2.0, 300.25),
+            rotation: None,
+            velocity: None,
+            collider: None,
+            gravity: None,
+            falling_voxel: None,
+            kind: None,
+            dropped_item: None,
+            entity_collision: true,
+        }
+    }
+
+    fn full_entity(id: u64) -> SavedEntity {
+        SavedEntity {
+            rotation: Some(Quat::from_rotation_y(1.0)),
+            velocity: Some(Vec3::new(0.0, -9.5, 2.0)),
+            collider: Some(Vec3::new(0.3, 0.9, 0.3)),
+            gravity: Some(25.0),
+            falling_voxel: Some((3, 7, 1000, -4.0)),
+            kind: Some("slime".to_string()),
+            dropped_item: Some(DroppedItem {
+                voxel_id: 40000,
+                count: 64,
+                age: 12.5,
+            }),
+            entity_collision: false,
+            ..bare_entity(id)
+        }
+    }
+
+    fn round_trip(payload: &ChunkPayload) -> ChunkPayload {
+        let mut writer = ByteWriter::new();
+        payload.write(&mut writer);
+        let mut reader = ByteReader::new(&writer.bytes);
+        let read = ChunkPayload::read(&mut reader).unwrap();
+        assert!(reader.is_empty());
+        read
+    }
+
+    fn assert_same(a: &ChunkPayload, b: &ChunkPayload) {
+        assert_eq!(a.position, b.position);
+        assert_eq!(a.player_modified, b.player_modified);
+        assert_eq!(a.voxels.len(), b.voxels.len());
+        for (index, (a, b)) in a.voxels.iter().zip(&b.voxels).enumerate() {
+            assert!(same_voxel(a, b), "Voxel {index} changed");
+        }
+        assert_eq!(a.entities, b.entities);
+        assert_eq!(a.light, b.light);
+        assert_eq!(a.biome, b.biome);
+    }
+
+    #[test]
+    fn empty_chunk_round_trip() {
+        let payload = ChunkPayload {
+            position: IVec3::new(-3, 0, 7),
+            voxels: vec![voxel(0, 0, 0); VOXEL_COUNT],
+            entities: Vec::new(),
+            light: None,
+            biome: None,
+            player_modified: false,
+        };
+        assert_same(&payload, &round_trip(&payload));
+    }
+
+    // Every voxel is different, the largest palette and the widest indices
+    #[test]
+    fn unique_voxels_round_trip() {
+        let voxels = (0..VOXEL_COUNT)
+            .map(|i| voxel(i as u8, (i >> 8) as u8, (i * 17) as u16))
+            .collect();
+        let payload = ChunkPayload {
+            position: IVec3::new(i32::MIN, i32::MAX, -1),
+            voxels,
+            entities: Vec::new(),
+            light: None,
+            biome: None,
+            player_modified: true,
+        };
+        assert_same(&payload, &round_trip(&payload));
+    }
+
+    #[test]
+    fn entities_and_biome_round_trip() {
+        let mut voxels = vec![voxel(0, 0, 1); VOXEL_COUNT];
+        voxels[100] = voxel(5, 255, u16::MAX);
+        let payload = ChunkPayload {
+            position: IVec3::new(1, 2, 3),
+            voxels,
+            entities: vec![bare_entity(1), full_entity(u64::MAX)],
+            light: None,
+            biome: Some("plains".to_string()),
+            player_modified: true,
+        };
+        assert_same(&payload, &round_trip(&payload));
+    }
+
+    #[test]
+    fn light_round_trip_and_unknown_sections_skipped() {
+        let mut voxels = vec![voxel(0, 0, 0); VOXEL_COUNT];
+        voxels[VOXEL_COUNT / 2..].fill(voxel(0, 0, 3));
+        let payload = ChunkPayload {
+            position: IVec3::new(0, -1, 0),
+            voxels,
+            entities: Vec::new(),
+            light: Some((0..VOXEL_COUNT).map(|i| (i % 16) as u8).collect()),
+            biome: None,
+            player_modified: false,
+        };
+        let mut writer = ByteWriter::new();
+        payload.write(&mut writer);
+        // Two voxel types take a bit each, and the light half a byte
+        assert!(writer.bytes.len() < VOXEL_COUNT / 8 + VOXEL_COUNT / 2 + 32);
+
+        // A section from a later build, written after the known ones and the 6 byte checksum
+        let section_count = writer.bytes.len() - VOXEL_COUNT / 2 - 3 - 6;
+        assert_eq!(writer.bytes[section_count], 2);
+        writer.bytes[section_count] = 3;
+        writer.write_u8(200);
+        writer.write_bytes(&[1, 2, 3]);
+        let read = ChunkPayload::read(&mut ByteReader::new(&writer.bytes)).unwrap();
+        assert_same(&payload, &read);
+    }
+
+    // Chunks written by every earlier format version must still load
+    #[test]
+    fn version_1_compatibility() {
+        let mut writer = ByteWriter::new();
+        writer.write_leb128(1);
+        writer.write_i32(4);
+        writer.write_i32(-5);
+        writer.write_i32(6);
+        writer.write_leb128(2);
+        writer.write_leb128(VOXEL_COUNT as u64 - 1);
+        writer.write_u8(0);
+        writer.write_u8(0);
+        writer.write_leb128(0);
+        writer.write_leb128(1);
+        writer.write_u8(2);
+        writer.write_u8(9);
+        writer.write_leb128(300);
+        writer.write_leb128(1);
+        full_entity(7).write(&mut writer);
+
+        let payload = ChunkPayload::read(&mut ByteReader::new(&writer.bytes)).unwrap();
+        assert_eq!(payload.position, IVec3::new(4, -5, 6));
+        assert!(payload.player_modified);
+        assert!(same_voxel(
+            &payload.voxels[VOXEL_COUNT - 1],
+            &voxel(2, 9, 300)
+        ));
+        assert_eq!(payload.entities, vec![full_entity(7)]);
+    }
+
+    // Version 2 added the flags byte after the position, the voxels were still run length encoded
+    #[test]
+    fn version_2_compatibility() {
+        for (flags, player_modified) in [(0, false), (PLAYER_MODIFIED, true)] {
+            let mut writer = ByteWriter::new();
+            writer.write_leb128(2);
+            writer.write_i32(-1);
+            writer.write_i32(0);
+            writer.write_i32(i32::MAX);
+            writer.write_u8(flags);
+            writer.write_leb128(2);
+            writer.write_leb128(1);
+            writer.write_u8(4);
+            writer.write_u8(1);
+            writer.write_leb128(1000);
+            writer.write_leb128(VOXEL_COUNT as u64 - 1);
+            writer.write_u8(0);
+            writer.write_u8(0);
+            writer.write_leb128(0);
+            writer.write_leb128(2);
+            bare_entity(1).write(&mut writer);
+            full_entity(2).write(&mut writer);
+
+            let mut reader = ByteReader::new(&writer.bytes);
+            let payload = ChunkPayload::read(&mut reader).unwrap();
+            assert!(reader.is_empty());
+            assert_eq!(payload.position, IVec3::new(-1, 0, i32::MAX));
+            assert_eq!(payload.player_modified, player_modified);
+            assert!(same_voxel(&payload.voxels[0], &voxel(4, 1, 1000)));
+            assert!(same_voxel(&payload.voxels[1], &voxel(0, 0, 0)));
+            assert_eq!(payload.entities, vec![bare_entity(1), full_entity(2)]);
+            assert_eq!(payload.light, None);
+            assert_eq!(payload.biome, None);
+        }
+    }
+
+    #[test]
+    fn newer_version_rejected() {
+        let mut writer = ByteWriter::new();
+        ChunkPayload {
+            position: IVec3::ZERO,
+            voxels: vec![voxel(0, 0, 0); VOXEL_COUNT],
+            entities: Vec::new(),
+            light: None,
+            biome: None,
+            player_modified: false,
+        }
+        .write(&mut writer);
+        writer.bytes[0] = CHUNK_FORMAT_VERSION as u8 + 1;
+        assert!(ChunkPayload::read(&mut ByteReader::new(&writer.bytes)).is_err());
+    }
+
+    #[test]
+    fn corrupted_chunk_fails_its_checksum() {
+        let mut voxels = vec![voxel(0, 0, 0); VOXEL_COUNT];
+        voxels[1] = voxel(0, 0, 1);
+        let mut writer = ByteWriter::new();
+        ChunkPayload {
+            position: IVec3::ZERO,
+            voxels,
+            entities: vec![full_entity(1)],
+            light: None,
+            biome: None,
+            player_modified: false,
+        }
+        .write(&mut writer);
+        assert!(ChunkPayload::read(&mut ByteReader::new(&writer.bytes)).is_ok());
+        // The indices come after the version, position, flags, the palette of two voxels and their
+        // length. Clearing the second voxel's bit still leaves a readable chunk
+        let first_indices = 1 + 12 + 1 + 1 + 2 * 3 + 2;
+        assert_eq!(writer.bytes[first_indices], 0b10);
+        writer.bytes[first_indices] = 0;
+        let error = ChunkPayload::read(&mut ByteReader::new(&writer.bytes)).unwrap_err();
+        assert!(error.to_string().contains("checksum"));
+    }
+
+    // Chunks saved twice in a row are hot and compressed at another level, both load the same
+    #[test]
+    fn stored_chunks_are_compressed() {
+        let storage = ChunkStorage::in_memory();
+        let payload = ChunkPayload {
+            position: IVec3::new(2, 0, -2),
+            voxels: vec![voxel(0, 0, 1); VOXEL_COUNT],
+            entities: vec![full_entity(3)],
+            light: Some(vec![15; VOXEL_COUNT]),
+            biome: None,
+            player_modified: true,
+        };
+        for _ in 0..2 {
+            storage.save(&payload).unwrap();
+            assert_same(&payload, &storage.load(&payload.position).unwrap().unwrap());
+        }
+    }
+
+    #[test]
+    fn truncated_chunk_rejected() {
+        let mut writer = ByteWriter::new();
+        ChunkPayload {
+            position: IVec3::ZERO,
+            voxels: vec![voxel(0, 0, 1); VOXEL_COUNT],
+            entities: vec![full_entity(1)],
+            light: None,
+            biome: None,
+            player_modified: false,
+        }
+        .write(&mut writer);
+        for length in 0..writer.bytes.len() {
+            assert!(ChunkPayload::read(&mut ByteReader::new(&writer.bytes[..length])).is_err());
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::*;
+use glam::{IVec3, Quat, Vec3};
+use legion::{system, world::EntryRef, Entity, EntityStore, IntoQuery};
+use parking_lot::{Mutex, RwLock};
+use rand::RngCore;
+
+use crate::{
+    ecs::{
+        components::{
+            item_components::DroppedItem,
+            physics_components::{Collider, Gravity, NoEntityCollision, Velocity},
+            transformation_components::{Position, Rotation},
+            voxel_components::FallingVoxel,
+        },
+        entities::entity_registry::EntityKind,
+    },
+    random,
+    voxels::{voxel_data::VoxelData, voxel_scene::VoxelScene, voxel_shapes::VoxelShape},
+};
+
+use super::binary::{ByteReader, ByteWriter};
+
+// Entities with this component are written into the save payload of the chunk that owns them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PersistentId(pub u64);
+
+lazy_static! {
+    // The tick ids were last made on and how many were made on it
+    static ref IDS_THIS_TICK: Mutex<(u64, u64)> = Mutex::new((0, 0));
+}
+
+impl PersistentId {
+    // Drawn from the tick's random numbers so a replay spawns entities with the ids they were
+    // recorded with, numbering the ids made this tick keeps them apart
+    pub fn new() -> Self {
+        let mut ids = IDS_THIS_TICK.lock();
+        let tick = random::tick();
+        if ids.0 != tick {
+            *ids = (tick, 0);
+        }
+        ids.1 += 1;
+        Self(random::tick_rng(&format!("persistent_id {}", ids.1)).next_u64())
+    }
+}
+
+// The chunk an entity is saved with, None until the entity has been placed in a chunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkOwner(pub Option<IVec3>);
+
+const HAS_ROTATION: u8 = 0b_0000_0001;
+const HAS_VELOCITY: u8 = 0b_0000_0010;
+const HAS_COLLIDER: u8 = 0b_0000_0100;
+const HAS_GRAVITY: u8 = 0b_0000_1000;
+const HAS_FALLING_VOXEL: u8 = 0b_0001_0000;
+const HAS_KIND: u8 = 0b_0010_0000;
+const HAS_DROPPED_ITEM: u8 = 0b_0100_0000;
+const NO_ENTITY_COLLISION: u8 = 0b_1000_0000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedEntity {
+    pub id: u64,
+    pub position: Vec3,
+    pub rotation: Option<Quat>,
+    pub velocity: Option<Vec3>,
+    pub collider: Option<Vec3>,
+    pub gravity: Option<f32>,
+    // Stored as (shape, state, id, velocity)
+    pub falling_voxel: Option<(u8, u8, u16, f32)>,
+    pub kind: Option<String>,
+    pub dropped_item: Option<DroppedItem>,
+    pub entity_collision: bool,
+}
+
+impl SavedEntity {
+    pub fn from_entry(entry: &EntryRef) -> Option<Self> {
+        let id = entry.get_component::<PersistentId>().ok()?.0;
+        let position = entry.get_component::<Position>().ok()?.0;
+        Some(Self {
+            id,
+            position,
+            rotation: entry.get_component::<Rotation>().ok().map(|r| r.0),
+            velocity: entry.get_component::<Velocity>().ok().map(|v| v.0),
+            collider: entry
+                .get_component::<Collider>()
+                .ok()
+                .map(|c| c.half_extents),
+            gravity: entry.get_component::<Gravity>().ok().map(|g| g.0),
+            falling_voxel: entry.get_component::<FallingVoxel>().ok().map(|f| {
+                let voxel = f.voxel;
+                (voxel.shape.data, voxel.state, voxel.id, f.velocity)
+            }),
+            kind: entry
+                .get_component::<EntityKind>()
+                .ok()
+                .map(|k| k.0.clone()),
+            dropped_item: entry.get_component::<DroppedItem>().ok().copied(),
+            entity_collision: entry.get_component::<NoEntityCollision>().is_err(),
+        })
+    }
+
+    pub fn spawn(&self, world: &mut legion::World) -> Entity {
+        let entity = world.push((
+            PersistentId(self.id),
+            ChunkOwner(None),
+            Position(self.position),
+        ));
+        let mut entry = world.entry(entity).unwrap();
+        if let Some(rotation) = self.rotation {
+            entry.add_component(Rotation(rotation));
+        }
+        if let Some(velocity) = self.velocity {
+            entry.add_component(Velocity(velocity));
+        }
+        if let Some(half_extents) = self.collider {
+            entry.add_component(Collider { half_extents });
+        }
+        if let Some(gravity) = self.gravity {
+            entry.add_component(Gravity(gravity));
+        }
+        if let Some((shape, state, id, velocity)) = self.falling_voxel {
+            entry.add_component(FallingVoxel {
+                voxel: VoxelData {
+                    shape: VoxelShape { data: shape },
+                    state,
+                    id,
+                },
+                velocity,
+            });
+        }
+        if let Some(kind) = &self.kind {
+            entry.add_component(EntityKind(kind.clone()));
+        }
+        if let Some(item) = self.dropped_item {
+            entry.add_component(item);
+        }
+        if !self.entity_collision {
+            entry.add_component(NoEntityCollision);
+        }
+        entity
+    }
+
+    pub fn write(&self, writer: &mut ByteWriter) {
+        writer.write_u64(self.id);
+        writer.write_vec3(self.position);
+        let mut flags = 0;
+        if self.rotation.is_some() {
+            flags |= HAS_ROTATION;
+        }
+        if self.velocity.is_some() {
+            flags |= HAS_VELOCITY;
+        }
+        if self.collider.is_some() {
+            flags |= HAS_COLLIDER;
+        }
+        if self.gravity.is_some() {
+            flags |= HAS_GRAVITY;
+        }
+        if self.falling_voxel.is_some() {
+            flags |= HAS_FALLING_VOXEL;
+        }
+        if self.kind.is_some() {
+            flags |= HAS_KIND;
+        }
+        if self.dropped_item.is_some() {
+            flags |= HAS_DROPPED_ITEM;
+        }
+        if !self.entity_collision {
+            flags |= NO_ENTITY_COLLISION;
+        }
+        writer.write_u8(flags);
+        if let Some(rotation) = self.rotation {
+            writer.write_quat(rotation);
+        }
+        if let Some(velocity) = self.velocity {
+            writer.write_vec3(velocity);
+        }
+        if let Some(half_extents) = self.collider {
+            writer.write_vec3(half_extents);
+        }
+        if let Some(gravity) = self.gravity {
+            writer.write_f32(gravity);
+        }
+        if let Some((shape, state, id, velocity)) = self.falling_voxel {
+            writer.write_u8(shape);
+            writer.write_u8(state);
+            writer.write_leb128(id as u64);
+            writer.write_f32(velocity);
+        }
+        if let Some(kind) = &self.kind {
+            writer.write_string(kind);
+        }
+        if let Some(item) = self.dropped_item {
+            writer.write_leb128(item.voxel_id as u64);
+            writer.write_leb128(item.count as u64);
+            writer.write_f32(item.age);
+        }
+    }
+
+    pub fn read(reader: &mut ByteReader) -> Result<Self> {
+        let id = reader.read_u64()?;
+        let position = reader.read_vec3()?;
+        let flags = reader.read_u8()?;
+        Ok(Self {
+            id,
+            position,
+            rotation: match flags & HAS_ROTATION != 0 {
+                true => Some(reader.read_quat()?),
+                false => None,
+            },
+            velocity: match flags & HAS_VELOCITY != 0 {
+                true => Some(reader.read_vec3()?),
+                false => None,
+            },
+            collider: match flags & HAS_COLLIDER != 0 {
+                true => Some(reader.read_vec3()?),
+                false => None,
+            },
+            gravity: match flags & HAS_GRAVITY != 0 {
+                true => Some(reader.read_f32()?),
+                false => None,
+            },
+            falling_voxel: match flags & HAS_FALLING_VOXEL != 0 {
+                true => Some((
+                    reader.read_u8()?,
+                    reader.read_u8()?,
+                    reader.read_leb128()? as u16,
+                    reader.read_f32()?,
+                )),
+                false => None,
+            },
+            kind: match flags & HAS_KIND != 0 {
+                true => Some(reader.read_string()?),
+                false => None,
+            },
+            dropped_item: match flags & HAS_DROPPED_ITEM != 0 {
+                true => Some(DroppedItem {
+                    voxel_id: reader.read_leb128()? as u16,
+                    count: reader.read_leb128()? as u32,
+                    age: reader.read_f32()?,
+                }),
+                false => None,
+            },
+            entity_collision: flags & NO_ENTITY_COLLISION == 0,
+        })
+    }
+}
+
+pub fn owning_chunk(position: Vec3) -> IVec3 {
+    VoxelScene::chunk_at(&position.round().as_ivec3())
+}
+
+// Entities are collected by the chunk their position is in right now, not the chunk they were loaded from
+pub fn collect_chunk_entities(world: &legion::World, chunk_pos: IVec3) -> Vec<SavedEntity> {
+    let mut query = <(Entity, &PersistentId, &Position)>::query();
+    query
+        .iter(world)
+        .filter(|(_, _, position)| owning_chunk(position.0) == chunk_pos)
+        .filter_map(|(entity, _, _)| {
+            world
+                .entry_ref(*entity)
+                .ok()
+                .and_then(|entry| SavedEntity::from_entry(&entry))
+        })
+        .collect()
+}
+
+// Spawns entities that were loaded with their chunks, skipping any that are already in the world
+pub fn spawn_loaded_entities(world: &mut legion::World, entities: Vec<SavedEntity>) {
+    let mut query = <&PersistentId>::query();
+    let existing = query.iter(world).map(|id| id.0).collect::<HashSet<_>>();
+    for entity in entities {
+        if existing.contains(&entity.id) {
+            continue;
+        }
+        entity.spawn(world);
+    }
+}
+
+// An entity that is removed or changed in place has to be saved again with its chunk, otherwise
+// the stale copy comes back when the chunk is loaded
+pub fn mark_owner_dirty(scene: &VoxelScene, owner: &ChunkOwner) {
+    if let Some(chunk_pos) = owner.0 {
+        scene.mark_chunk_dirty(&chunk_pos);
+    }
+}
+
+// When an entity crosses a chunk border both chunks need to be saved again,
+// otherwise the entity would be lost or duplicated when only one of them is written
+#[system(for_each)]
+pub fn update_chunk_owners(
+    pos: &Position,
+    owner: &mut ChunkOwner,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    let current = owning_chunk(pos.0);
+    if owner.0 == Some(current) {
+        return;
+    }
+    let scene_lock = scene.read();
+    if let Some(previous) = owner.0 {
+        scene_lock.mark_chunk_dirty(&previous);
+    }
+    scene_lock.mark_chunk_dirty(&current);
+    owner.0 = Some(current);
+}
@@ -0,0 +1,168 @@
+use super::{
+    chunk_storage::CHUNK_FORMAT_VERSION, player_data::PLAYER_FORMAT_VERSION,
+    world_save::WORLD_FORMAT_VERSION,
+};
+
+// Describes the files written by the persistence module, checked against "save format docs.md" by the tests
+// so the documentation can't fall behind the format
+pub fn format_documentation() -> String {
+    format!(
+        r#"# Save Format
+
+Generated by `persistence::format_docs`, run `cargo test` after changing the save format to check this file is up to date.
+
+All numbers are little endian. Lengths, counts and voxel ids are LEB128 encoded, strings and byte blobs are prefixed with their length.
+
+<br>
+
+---
+
+<br>
+
+## World Directory
+
+> ## world.json
+> The world manifest, format version {world}. Contains `Name`, `Seed`, `Format Version`, `Generator Preset`, `Play Time`, `Statistics`, the `Voxels Broken`, `Voxels Placed`, `Distance Traveled` and `Chunks Generated` over the world's life, `World Tick`, `Biome Overrides`, `Dimensions`, the settings of each dimension as written in world presets, `Settings`, the `View Distance`, `Unload Margin` and `Weather` this world uses in place of the settings file, `Data Packs`, the data packs in load order with their `Name` and whether they're `Enabled`, `Voxel Ids`, mapping the voxel ids in the chunks to the voxel names they stood for when the manifest was written, `Engine Version`, the engine that last wrote it, and `Mods`, the version of every loaded mod by id. Worlds written by a newer engine are refused unless the game is started with `--allow-newer-world`, which backs the world up before opening it. Mods older than the ones recorded only log a warning
+
+> ## chunks/x_y_z.chunk
+> One file per saved chunk, named after the chunk position
+
+> ## dimensions/name/chunks/x_y_z.chunk
+> The saved chunks of every dimension besides the overworld, laid out like `chunks/`
+
+> ## players/uuid.player
+> One file per player, named after the player id. `players/local_player` contains the id of the player on this machine followed by its secret in hex on the next line
+
+> ## players/uuid.secret
+> The SHA-256 hash of the secret the player first joined with, later joins with a different secret are refused
+
+> ## operators
+> The ids of the players allowed to run operator commands, one per line
+
+> ## regions.json
+> Protected regions and player groups. `Groups` maps group names to the ids of their members, `Regions` lists regions with their `Name`, either inclusive `Min` and `Max` voxel corners or a list of `Chunks`, `Allow Everyone` and the `Allow` and `Deny` rules naming player ids or `group:name`. Operators are in the `operators` group
+
+> ## backups/
+> Snapshots of the world directory, named after the time they were made and their label. An uncompressed header with the label and the creation time is followed by the files as a gzip stream, so backups can be listed without decompressing them
+
+<br>
+
+---
+
+<br>
+
+## Chunk File
+
+Chunk format version {chunk}
+
+> ## Compression
+> Files starting with the byte 0xFF are the rest of this layout as a zlib stream. The level depends on the `Chunk Compression` setting, files without the byte are not compressed
+
+> ## Version
+> LEB128. Files newer than this version are rejected
+
+> ## Position
+> Three i32 chunk coordinates
+
+> ## Flags
+> u8, missing in version 1. Bit 0 is set once a player has edited the chunk, version 1 chunks are treated as edited
+
+> ## Palette
+> LEB128 palette size followed by the distinct voxels of the chunk as u8 shape, u8 state and LEB128 voxel id
+
+> ## Voxels
+> LEB128 length followed by the palette index of all 4096 voxels ordered by x, then y, then z. Each index takes just enough bits for the palette size, none for a palette of one voxel, packed into a little endian bit stream
+
+> ## Sections
+> LEB128 section count followed by sections of a u8 kind and a LEB128 length prefixed body. Sections of unknown kinds are skipped
+> - 1, entities: LEB128 entity count followed by the entities
+> - 2, light: the light level of every voxel from 0 to 15, two to a byte with the first in the low bits
+> - 4, biome: the UTF-8 name of the biome the chunk was generated with, written before the checksum. Chunks without it take the biome their dimension picks for them
+> - 3, checksum: u32 CRC-32 of every byte before the section count followed by the bodies of the sections before it, written last. Chunks that don't match are rejected
+
+> Versions before 3 stored the voxels as a LEB128 run count followed by runs of LEB128 length, u8 shape, u8 state and LEB128 voxel id, then a LEB128 entity count and the entities
+
+<br>
+
+## Entity
+
+> ## Id
+> u64 persistent id
+
+> ## Position
+> Three f32
+
+> ## Flags
+> u8 marking which of the optional fields follow, in this order
+
+> ## Rotation
+> Bit 0, four f32 quaternion components
+
+> ## Velocity
+> Bit 1, three f32
+
+> ## Collider
+> Bit 2, three f32 half extents
+
+> ## Gravity
+> Bit 3, f32
+
+> ## Falling Voxel
+> Bit 4, u8 shape, u8 state, LEB128 voxel id and f32 velocity
+
+> ## Kind
+> Bit 5, string entity profile name
+
+> ## Dropped Item
+> Bit 6, LEB128 voxel id, LEB128 count and f32 age
+
+> ## No Entity Collision
+> Bit 7, no data. The entity doesn't push or get pushed by other entities
+
+<br>
+
+---
+
+<br>
+
+## Player File
+
+Player format version {player}
+
+> ## Version
+> LEB128. Files newer than this version are rejected
+
+> ## Id
+> Byte blob holding the 16 byte player id
+
+> ## Position
+> Three f32
+
+> ## Rotation
+> Four f32 quaternion components
+
+> ## Game Mode
+> u8, 0 for creative, 1 for survival and 2 for spectator
+
+> ## Inventory
+> Byte blob owned by the game
+"#,
+        world = WORLD_FORMAT_VERSION,
+        chunk = CHUNK_FORMAT_VERSION,
+        player = PLAYER_FORMAT_VERSION,
+    )
+}
+
+#[cfg(test)]
+mod format_docs_tests {
+    use super::format_documentation;
+
+    #[test]
+    fn documentation_up_to_date() {
+        let documentation = include_str!("../../save format docs.md");
+        assert!(
+            documentation == format_documentation(),
+            "The save format changed, update \"save format docs.md\" with the output of format_documentation"
+        );
+    }
+}
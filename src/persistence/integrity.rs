@@ -0,0 +1,449 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use glam::IVec3;
+use tracing::{info, warn};
+
+use crate::{
+    progress::{Progress, ProgressBar},
+    voxels::voxel_registry::{get_voxel_by_name, voxel_id_mappings},
+};
+
+use super::{
+    atomic_file::write_atomic,
+    binary::{ByteReader, ByteWriter},
+    chunk_compression::decompress,
+    chunk_storage::ChunkPayload,
+    entity_persistence::owning_chunk,
+    world_save::{WorldSave, CHUNK_DIRECTORY, DIMENSION_DIRECTORY, MANIFEST_FILE},
+};
+
+// Chunks that can't be read are moved here, the game generates them again
+const QUARANTINE_DIRECTORY: &str = "quarantine";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckMode {
+    // Only reports what's wrong
+    Report,
+    // Fixes what can be fixed and quarantines chunks that can't be read
+    Repair,
+    // Moves every chunk with a problem into the quarantine as it is
+    Quarantine,
+}
+
+pub struct CheckOptions {
+    pub world_path: PathBuf,
+    pub mode: CheckMode,
+}
+
+impl CheckOptions {
+    // Reads --world <path>, --repair and --quarantine
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self {
+            world_path: PathBuf::from("./saves/world"),
+            mode: CheckMode::Report,
+        };
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.peek()) {
+                ("--world", Some(_)) => options.world_path = PathBuf::from(args.next().unwrap()),
+                ("--repair", _) => options.mode = CheckMode::Repair,
+                ("--quarantine", _) => options.mode = CheckMode::Quarantine,
+                ("--check", _) => {}
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub chunks: usize,
+    // One line for every problem found, in the order they were found
+    pub problems: Vec<String>,
+    pub repaired: usize,
+    pub quarantined: usize,
+}
+
+// What the voxel ids saved in the chunks become with the voxels registered now. Ids missing from
+// the map don't exist anymore
+struct IdMapping {
+    ids: HashMap<u16, u16>,
+    // Set when the saved id table doesn't match the registry anymore
+    changed: bool,
+}
+
+impl IdMapping {
+    // Worlds saved before the manifest had an id table are assumed to use today's ids
+    fn new(saved: &BTreeMap<u16, String>) -> Self {
+        let mut mapping = Self {
+            ids: HashMap::new(),
+            changed: false,
+        };
+        for (id, _) in voxel_id_mappings() {
+            if !saved.contains_key(&id) {
+                mapping.ids.insert(id, id);
+            }
+        }
+        for (id, name) in saved {
+            match get_voxel_by_name(name.clone()) {
+                Some(profile) => {
+                    mapping.ids.insert(*id, profile.id);
+                    mapping.changed |= profile.id != *id;
+                }
+                None => mapping.changed = true,
+            }
+        }
+        // Air isn't registered
+        mapping.ids.insert(0, 0);
+        mapping
+    }
+
+    fn map(&self, id: u16) -> Option<u16> {
+        self.ids.get(&id).copied()
+    }
+}
+
+// Reads every chunk of every dimension and looks for chunks that don't parse or fail their
+// checksum, chunks saved under the wrong file name, voxel ids that are unknown or changed since the
+// world was saved, and entities that are broken or stored in the wrong chunk
+pub fn run(options: CheckOptions) -> Result<CheckReport> {
+    let directory = &options.world_path;
+    let metadata = WorldSave::read_metadata(directory)?;
+    let mapping = IdMapping::new(&metadata.voxel_ids);
+    let mut report = CheckReport::default();
+    for (id, name) in &metadata.voxel_ids {
+        match mapping.map(*id) {
+            None => report.problems.push(format!(
+                "Voxel {name} with id {id} isn't registered anymore"
+            )),
+            Some(now) if now != *id => report
+                .problems
+                .push(format!("Voxel {name} moved from id {id} to {now}")),
+            Some(_) => {}
+        }
+    }
+
+    // The overworld's chunks and then those of the other dimensions, each with the folder its
+    // chunks are reported and quarantined under
+    let mut folders = vec![(
+        PathBuf::new(),
+        chunk_files(&directory.join(CHUNK_DIRECTORY))?,
+    )];
+    let mut dimensions = match fs::read_dir(directory.join(DIMENSION_DIRECTORY)) {
+        Ok(entries) => entries
+            .map(|entry| -> Result<PathBuf> {
+                Ok(Path::new(DIMENSION_DIRECTORY).join(entry?.file_name()))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Err(_) => Vec::new(),
+    };
+    dimensions.sort();
+    for dimension in dimensions {
+        let chunks = directory.join(&dimension).join(CHUNK_DIRECTORY);
+        // Dimensions nobody has been to yet have no chunks
+        if chunks.is_dir() {
+            folders.push((dimension, chunk_files(&chunks)?));
+        }
+    }
+
+    let progress = Progress::start("Checking");
+    let total = folders.iter().map(|(_, files)| files.len()).sum::<usize>();
+    progress.stage("Reading chunks", total as u64);
+    let mut bar = ProgressBar::new("chunks");
+    for (folder, files) in folders {
+        // Entity ids with the first chunk of the dimension they were found in
+        let mut entity_ids = HashMap::new();
+        for path in files {
+            report.chunks += 1;
+            let name = folder.join(file_name(&path)).display().to_string();
+            match check_chunk(&path, &name, &mapping, &mut entity_ids, options.mode) {
+                Ok(ChunkCheck::Fine) => {}
+                Ok(ChunkCheck::Problems(problems, repaired)) => {
+                    report.problems.extend(problems);
+                    match options.mode {
+                        CheckMode::Report => {}
+                        CheckMode::Repair => {
+                            let mut writer = ByteWriter::new();
+                            repaired.write(&mut writer);
+                            write_atomic(&path, &writer.bytes)?;
+                            report.repaired += 1;
+                        }
+                        CheckMode::Quarantine => {
+                            quarantine(directory, &folder, &path)?;
+                            report.quarantined += 1;
+                        }
+                    }
+                }
+                Err(e) => {
+                    report.problems.push(format!("{name} can't be read: {e}"));
+                    if options.mode != CheckMode::Report {
+                        quarantine(directory, &folder, &path)?;
+                        report.quarantined += 1;
+                    }
+                }
+            }
+            progress.advance(1);
+            bar.update(&progress.snapshot());
+        }
+    }
+    bar.finish();
+    progress.finish();
+
+    // The repaired chunks use today's ids now, and so does the table
+    if options.mode == CheckMode::Repair && mapping.changed {
+        let mut metadata = metadata;
+        metadata.voxel_ids = voxel_id_mappings().into_iter().collect();
+        let json = serde_json::to_string_pretty(&metadata.to_json())?;
+        write_atomic(&directory.join(MANIFEST_FILE), json.as_bytes())?;
+    }
+
+    for problem in &report.problems {
+        warn!("{problem}");
+    }
+    info!(
+        "Checked {} chunks, found {} problems, repaired {} chunks and quarantined {}",
+        report.chunks,
+        report.problems.len(),
+        report.repaired,
+        report.quarantined
+    );
+    Ok(report)
+}
+
+enum ChunkCheck {
+    Fine,
+    // The problems and the chunk with them fixed
+    Problems(Vec<String>, ChunkPayload),
+}
+
+// The name is the chunk's file name, under its dimension's folder for chunks outside the overworld
+fn check_chunk(
+    path: &Path,
+    name: &str,
+    mapping: &IdMapping,
+    entity_ids: &mut HashMap<u64, IVec3>,
+    mode: CheckMode,
+) -> Result<ChunkCheck> {
+    let position = match parse_position(&file_name(path)) {
+        Some(position) => position,
+        None => bail!("the name isn't a chunk position"),
+    };
+    let bytes = decompress(&fs::read(path)?)?;
+    let mut reader = ByteReader::new(&bytes);
+    let mut payload = ChunkPayload::read(&mut reader)?;
+    let mut problems = Vec::new();
+    if !reader.is_empty() {
+        problems.push(format!(
+            "{name} has {} bytes after the chunk",
+            bytes.len() - reader.offset()
+        ));
+    }
+
+    // The file name is what the chunk is found by, so the position inside follows it
+    if payload.position != position {
+        problems.push(format!("{name} holds chunk {}", payload.position));
+        payload.position = position;
+    }
+
+    let mut unknown = HashMap::<u16, usize>::new();
+    let mut remapped = 0;
+    for voxel in &mut payload.voxels {
+        match mapping.map(voxel.id) {
+            Some(id) if id != voxel.id => {
+                voxel.id = id;
+                remapped += 1;
+            }
+            Some(_) => {}
+            None => {
+                *unknown.entry(voxel.id).or_default() += 1;
+                voxel.id = 0;
+            }
+        }
+    }
+    for (id, count) in unknown {
+        problems.push(format!("{name} has {count} voxels of unknown id {id}"));
+    }
+    // Only a problem for this chunk when a repair is going to rewrite it
+    if remapped > 0 && mode == CheckMode::Repair {
+        problems.push(format!("{name} has {remapped} voxels with changed ids"));
+    }
+
+    payload.entities.retain(|entity| {
+        let problem = if !entity.position.is_finite() {
+            Some(format!(
+                "{name} has entity {} at an invalid position",
+                entity.id
+            ))
+        } else if owning_chunk(entity.position) != position {
+            Some(format!(
+                "{name} has entity {} which is in chunk {}",
+                entity.id,
+                owning_chunk(entity.position)
+            ))
+        } else if entity
+            .falling_voxel
+            .map_or(false, |(_, _, id, _)| mapping.map(id).is_none())
+        {
+            Some(format!(
+                "{name} has entity {} falling as an unknown voxel",
+                entity.id
+            ))
+        } else {
+            None
+        };
+        if let Some(first) = entity_ids.insert(entity.id, position) {
+            problems.push(format!(
+                "{name} has entity {} which is also in chunk {first}",
+                entity.id
+            ));
+        }
+        problems.extend(problem.clone());
+        problem.is_none()
+    });
+
+    match problems.is_empty() {
+        true => Ok(ChunkCheck::Fine),
+        false => Ok(ChunkCheck::Problems(problems, payload)),
+    }
+}
+
+// Chunks of other dimensions go under their dimension's folder so they don't replace each other
+fn quarantine(directory: &Path, folder: &Path, path: &Path) -> Result<()> {
+    let quarantine = directory.join(QUARANTINE_DIRECTORY).join(folder);
+    fs::create_dir_all(&quarantine)?;
+    fs::rename(path, quarantine.join(file_name(path)))?;
+    Ok(())
+}
+
+fn chunk_files(chunk_directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(chunk_directory)? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |e| e == "chunk") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().to_string())
+}
+
+fn parse_position(name: &str) -> Option<IVec3> {
+    let coordinates = name
+        .strip_suffix(".chunk")?
+        .split('_')
+        .map(|c| c.parse::<i32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match coordinates.as_slice() {
+        [x, y, z] => Some(IVec3::new(*x, *y, *z)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod integrity_tests {
+    use super::*;
+    use crate::{
+        persistence::world_save::WorldMetadata,
+        voxels::{voxel_data::VoxelData, voxel_shapes::VoxelShape},
+    };
+
+    fn write_chunk(directory: &Path, name: &str, position: IVec3) {
+        let payload = ChunkPayload {
+            position,
+            voxels: vec![
+                VoxelData {
+                    shape: VoxelShape { data: 0 },
+                    state: 0,
+                    id: 0,
+                };
+                4096
+            ],
+            entities: Vec::new(),
+            light: None,
+            biome: None,
+            player_modified: true,
+        };
+        let mut writer = ByteWriter::new();
+        payload.write(&mut writer);
+        fs::write(directory.join(CHUNK_DIRECTORY).join(name), writer.bytes).unwrap();
+    }
+
+    #[test]
+    fn repair_fixes_misplaced_chunks_and_quarantines_broken_ones() {
+        let world = std::env::temp_dir().join(format!("assemblage_check_{}", std::process::id()));
+        fs::create_dir_all(world.join(CHUNK_DIRECTORY)).unwrap();
+        let metadata = WorldMetadata::new("check".to_string(), 1, "plains".to_string());
+        fs::write(world.join(MANIFEST_FILE), metadata.to_json().to_string()).unwrap();
+        write_chunk(&world, "0_0_0.chunk", IVec3::ZERO);
+        write_chunk(&world, "1_0_0.chunk", IVec3::new(5, 5, 5));
+        fs::write(world.join(CHUNK_DIRECTORY).join("2_0_0.chunk"), [3, 1, 2]).unwrap();
+
+        let report = run(CheckOptions {
+            world_path: world.clone(),
+            mode: CheckMode::Repair,
+        })
+        .unwrap();
+        assert_eq!(report.chunks, 3);
+        assert_eq!(report.problems.len(), 2);
+        assert_eq!((report.repaired, report.quarantined), (1, 1));
+        assert!(world
+            .join(QUARANTINE_DIRECTORY)
+            .join("2_0_0.chunk")
+            .exists());
+        let bytes = fs::read(world.join(CHUNK_DIRECTORY).join("1_0_0.chunk")).unwrap();
+        let repaired = ChunkPayload::read(&mut ByteReader::new(&bytes)).unwrap();
+        assert_eq!(repaired.position, IVec3::X);
+
+        fs::remove_dir_all(world).unwrap();
+    }
+
+    #[test]
+    fn chunks_of_other_dimensions_are_checked() {
+        let world = std::env::temp_dir().join(format!(
+            "assemblage_check_dimensions_{}",
+            std::process::id()
+        ));
+        let nether = Path::new(DIMENSION_DIRECTORY).join("nether");
+        fs::create_dir_all(world.join(CHUNK_DIRECTORY)).unwrap();
+        fs::create_dir_all(world.join(&nether).join(CHUNK_DIRECTORY)).unwrap();
+        let metadata = WorldMetadata::new("check".to_string(), 1, "plains".to_string());
+        fs::write(world.join(MANIFEST_FILE), metadata.to_json().to_string()).unwrap();
+        write_chunk(&world, "0_0_0.chunk", IVec3::ZERO);
+        write_chunk(&world.join(&nether), "0_0_0.chunk", IVec3::ZERO);
+        fs::write(
+            world
+                .join(&nether)
+                .join(CHUNK_DIRECTORY)
+                .join("1_0_0.chunk"),
+            [3, 1, 2],
+        )
+        .unwrap();
+
+        let report = run(CheckOptions {
+            world_path: world.clone(),
+            mode: CheckMode::Quarantine,
+        })
+        .unwrap();
+        assert_eq!(report.chunks, 3);
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].starts_with(&nether.join("1_0_0.chunk").display().to_string()));
+        assert!(world
+            .join(QUARANTINE_DIRECTORY)
+            .join(&nether)
+            .join("1_0_0.chunk")
+            .exists());
+
+        fs::remove_dir_all(world).unwrap();
+    }
+}
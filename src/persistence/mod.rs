@@ -0,0 +1,117 @@
+#[cfg(feature = "persistence")]
+pub mod anvil_import;
+pub mod atomic_file;
+#[cfg(feature = "persistence")]
+pub mod autosave;
+#[cfg(feature = "persistence")]
+pub mod backup;
+pub mod binary;
+pub mod chunk_compression;
+pub mod chunk_storage;
+pub mod entity_persistence;
+#[cfg(feature = "persistence")]
+pub mod format_docs;
+#[cfg(feature = "persistence")]
+pub mod integrity;
+#[cfg(feature = "persistence")]
+pub mod nbt;
+#[cfg(feature = "persistence")]
+pub mod player_data;
+#[cfg(feature = "persistence")]
+pub mod pruning;
+pub mod storage_backend;
+#[cfg(feature = "persistence")]
+pub mod world_preset;
+#[cfg(feature = "persistence")]
+pub mod world_save;
+pub mod world_stats;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::RwLock;
+
+use crate::{ecs::world::World, voxels::voxel_scene::VoxelScene};
+
+use self::{
+    chunk_storage::{ChunkPayload, ChunkStorage},
+    entity_persistence::collect_chunk_entities,
+};
+
+// Copies every dirty chunk together with the entities inside it and marks them as clean,
+// the payloads can then be written without holding any locks
+pub fn snapshot_dirty_chunks(
+    scene: &Arc<RwLock<VoxelScene>>,
+    world: &Arc<RwLock<World>>,
+) -> Vec<ChunkPayload> {
+    snapshot_locked_chunks(&world.read(), &scene.read())
+}
+
+// The same for a caller already holding both locks
+pub fn snapshot_locked_chunks(world_lock: &World, scene_lock: &VoxelScene) -> Vec<ChunkPayload> {
+    let dirty = scene_lock
+        .chunks
+        .iter()
+        .filter(|chunk| chunk.dirty)
+        .map(|chunk| *chunk.key())
+        .collect::<Vec<_>>();
+
+    let mut payloads = Vec::with_capacity(dirty.len());
+    for chunk_pos in dirty {
+        let (voxels, biome, player_modified) = match scene_lock.chunks.get_mut(&chunk_pos) {
+            Some(mut chunk) => {
+                chunk.dirty = false;
+                let biome = chunk.biome.as_deref().map(str::to_string);
+                (chunk.voxels().clone(), biome, chunk.player_modified)
+            }
+            None => continue,
+        };
+        payloads.push(ChunkPayload {
+            position: chunk_pos,
+            voxels,
+            entities: collect_chunk_entities(&world_lock.legion_world, chunk_pos),
+            light: None,
+            biome,
+            player_modified,
+        });
+    }
+    payloads
+}
+
+// Writes every dirty chunk together with the entities inside it, returns the number of chunks saved
+pub fn save_dirty_chunks(
+    scene: &Arc<RwLock<VoxelScene>>,
+    world: &Arc<RwLock<World>>,
+) -> Result<usize> {
+    let storage = match scene.read().get_storage() {
+        Some(storage) => storage,
+        None => return Ok(0),
+    };
+    let payloads = snapshot_dirty_chunks(scene, world);
+    write_payloads(&storage, scene, &payloads)
+}
+
+// Chunks that fail to save are marked dirty again so the next save retries them
+pub fn write_payloads(
+    storage: &ChunkStorage,
+    scene: &Arc<RwLock<VoxelScene>>,
+    payloads: &[ChunkPayload],
+) -> Result<usize> {
+    let mut result = Ok(0);
+    for payload in payloads {
+        match storage.save(payload) {
+            Ok(()) => {
+                if let Ok(saved) = &mut result {
+                    *saved += 1;
+                }
+            }
+            Err(e) => {
+                scene.read().mark_chunk_dirty(&payload.position);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+    result
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use anyhow::*;
+
+// Minecraft's named binary tag format, only reading is supported
+#[derive(Clone, Debug, PartialEq)]
+pub enum NbtTag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<NbtTag>),
+    Compound(HashMap<String, NbtTag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl NbtTag {
+    pub fn get(&self, name: &str) -> Option<&NbtTag> {
+        match self {
+            NbtTag::Compound(values) => values.get(name),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            NbtTag::Byte(v) => Some(*v as i64),
+            NbtTag::Short(v) => Some(*v as i64),
+            NbtTag::Int(v) => Some(*v as i64),
+            NbtTag::Long(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            NbtTag::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[NbtTag]> {
+        match self {
+            NbtTag::List(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_long_array(&self) -> Option<&[i64]> {
+        match self {
+            NbtTag::LongArray(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+// Everything in NBT is big endian, unlike the little endian save format
+struct NbtReader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> NbtReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.cursor + length > self.bytes.len() {
+            bail!("Unexpected end of NBT data at offset {}", self.cursor);
+        }
+        let slice = &self.bytes[self.cursor..self.cursor + length];
+        self.cursor += length;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn read_i64(&mut self) -> Result<i64> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn read_length(&mut self) -> Result<usize> {
+        let length = self.read_i32()?;
+        if length < 0 {
+            bail!("Negative NBT length {length}");
+        }
+        Ok(length as usize)
+    }
+
+    // Strings are modified UTF-8, which only differs from UTF-8 for characters that never appear in block names
+    fn read_string(&mut self) -> Result<String> {
+        let length = u16::from_be_bytes(self.take(2)?.try_into()?) as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).to_string())
+    }
+
+    fn read_payload(&mut self, tag_type: u8) -> Result<NbtTag> {
+        Ok(match tag_type {
+            1 => NbtTag::Byte(self.read_u8()? as i8),
+            2 => NbtTag::Short(self.read_i16()?),
+            3 => NbtTag::Int(self.read_i32()?),
+            4 => NbtTag::Long(self.read_i64()?),
+            5 => NbtTag::Float(f32::from_bits(self.read_i32()? as u32)),
+            6 => NbtTag::Double(f64::from_bits(self.read_i64()? as u64)),
+            7 => {
+                let length = self.read_length()?;
+                NbtTag::ByteArray(self.take(length)?.iter().map(|b| *b as i8).collect())
+            }
+            8 => NbtTag::String(self.read_string()?),
+            9 => {
+                let element_type = self.read_u8()?;
+                let length = self.read_length()?;
+                let mut values = Vec::with_capacity(length.min(4096));
+                for _ in 0..length {
+                    values.push(self.read_payload(element_type)?);
+                }
+                NbtTag::List(values)
+            }
+            10 => {
+                let mut values = HashMap::new();
+                loop {
+                    let child_type = self.read_u8()?;
+                    if child_type == 0 {
+                        break;
+                    }
+                    let name = self.read_string()?;
+                    values.insert(name, self.read_payload(child_type)?);
+                }
+                NbtTag::Compound(values)
+            }
+            11 => {
+                let length = self.read_length()?;
+                let mut values = Vec::with_capacity(length.min(4096));
+                for _ in 0..length {
+                    values.push(self.read_i32()?);
+                }
+                NbtTag::IntArray(values)
+            }
+            12 => {
+                let length = self.read_length()?;
+                let mut values = Vec::with_capacity(length.min(4096));
+                for _ in 0..length {
+                    values.push(self.read_i64()?);
+                }
+                NbtTag::LongArray(values)
+            }
+            _ => bail!("Unknown NBT tag type {tag_type}"),
+        })
+    }
+}
+
+// Reads an uncompressed NBT document, the name of the root compound is discarded
+pub fn read_nbt(bytes: &[u8]) -> Result<NbtTag> {
+    let mut reader = NbtReader { bytes, cursor: 0 };
+    let root_type = reader.read_u8()?;
+    if root_type != 10 {
+        bail!("NBT root is not a compound");
+    }
+    reader.read_string()?;
+    reader.read_payload(root_type)
+}
+
+// Writes the tag as an uncompressed document with an empty root name, the tests build their input
+// with it
+#[cfg(test)]
+pub(crate) fn write_nbt(root: &NbtTag) -> Vec<u8> {
+    fn tag_type(tag: &NbtTag) -> u8 {
+        match tag {
+            NbtTag::Byte(_) => 1,
+            NbtTag::Short(_) => 2,
+            NbtTag::Int(_) => 3,
+            NbtTag::Long(_) => 4,
+            NbtTag::Float(_) => 5,
+            NbtTag::Double(_) => 6,
+            NbtTag::ByteArray(_) => 7,
+            NbtTag::String(_) => 8,
+            NbtTag::List(_) => 9,
+            NbtTag::Compound(_) => 10,
+            NbtTag::IntArray(_) => 11,
+            NbtTag::LongArray(_) => 12,
+        }
+    }
+    fn write_string(value: &str, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(value.as_bytes());
+    }
+    fn write_payload(tag: &NbtTag, bytes: &mut Vec<u8>) {
+        match tag {
+            NbtTag::Byte(v) => bytes.push(*v as u8),
+            NbtTag::Short(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+            NbtTag::Int(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+            NbtTag::Long(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+            NbtTag::Float(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+            NbtTag::Double(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+            NbtTag::ByteArray(values) => {
+                bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                bytes.extend(values.iter().map(|v| *v as u8));
+            }
+            NbtTag::String(v) => write_string(v, bytes),
+            NbtTag::List(values) => {
+                bytes.push(values.first().map_or(0, tag_type));
+                bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                for value in values {
+                    write_payload(value, bytes);
+                }
+            }
+            NbtTag::Compound(values) => {
+                for (name, value) in values {
+                    bytes.push(tag_type(value));
+                    write_string(name, bytes);
+                    write_payload(value, bytes);
+                }
+                bytes.push(0);
+            }
+            NbtTag::IntArray(values) => {
+                bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                for v in values {
+                    bytes.extend_from_slice(&v.to_be_bytes());
+                }
+            }
+            NbtTag::LongArray(values) => {
+                bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                for v in values {
+                    bytes.extend_from_slice(&v.to_be_bytes());
+                }
+            }
+        }
+    }
+    let mut bytes = vec![tag_type(root)];
+    write_string("", &mut bytes);
+    write_payload(root, &mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod nbt_tests {
+    use super::*;
+
+    fn compound(values: Vec<(&str, NbtTag)>) -> NbtTag {
+        NbtTag::Compound(
+            values
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn every_tag_type_is_read_back() {
+        let document = compound(vec![
+            ("byte", NbtTag::Byte(-3)),
+            ("short", NbtTag::Short(-300)),
+            ("int", NbtTag::Int(70_000)),
+            ("long", NbtTag::Long(-5_000_000_000)),
+            ("float", NbtTag::Float(1.5)),
+            ("double", NbtTag::Double(-2.25)),
+            ("bytes", NbtTag::ByteArray(vec![1, -1, 7])),
+            ("name", NbtTag::String("minecraft:stone".to_string())),
+            (
+                "list",
+                NbtTag::List(vec![
+                    compound(vec![("Y", NbtTag::Byte(0))]),
+                    compound(vec![("Y", NbtTag::Byte(1))]),
+                ]),
+            ),
+            ("empty", NbtTag::List(Vec::new())),
+            ("ints", NbtTag::IntArray(vec![i32::MIN, 0, i32::MAX])),
+            ("longs", NbtTag::LongArray(vec![i64::MIN, -1, i64::MAX])),
+        ]);
+        let read = read_nbt(&write_nbt(&document)).unwrap();
+        assert_eq!(read, document);
+        assert_eq!(read.get("int").and_then(|v| v.as_i64()), Some(70_000));
+        assert_eq!(read.get("byte").and_then(|v| v.as_i64()), Some(-3));
+        assert_eq!(
+            read.get("name").and_then(|v| v.as_str()),
+            Some("minecraft:stone")
+        );
+        assert_eq!(read.get("list").and_then(|v| v.as_list()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn broken_documents_are_rejected() {
+        let bytes = write_nbt(&compound(vec![("long", NbtTag::Long(5))]));
+        assert!(read_nbt(&bytes[..bytes.len() - 3]).is_err());
+        assert!(read_nbt(&write_nbt(&NbtTag::Int(5))).is_err());
+        // A negative array length
+        let mut bytes = write_nbt(&compound(vec![("ints", NbtTag::IntArray(vec![1]))]));
+        let length = 1 + 2 + 1 + 2 + 4 + 4;
+        bytes[length - 4..length].copy_from_slice(&(-1_i32).to_be_bytes());
+        assert!(read_nbt(&bytes).is_err());
+    }
+}
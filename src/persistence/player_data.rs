@@ -0,0 +1,237 @@
+use std::path::PathBuf;
+
+use anyhow::*;
+use glam::{Quat, Vec3};
+use legion::{world::EntryRef, Entity, EntityStore, IntoQuery};
+use sha2::{Digest, Sha256};
+
+use crate::ecs::{
+    components::{
+        player_components::{
+            game_mode_from_u8, game_mode_to_u8, GameMode, Player, PlayerId, PlayerInventory,
+        },
+        transformation_components::{Position, Rotation},
+    },
+    entities::player::set_game_mode,
+};
+
+use super::{
+    binary::{ByteReader, ByteWriter},
+    storage_backend::StorageBackend,
+};
+
+pub const PLAYER_FORMAT_VERSION: u64 = 1;
+const LOCAL_PLAYER_FILE: &str = "local_player";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerData {
+    pub id: PlayerId,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub game_mode: GameMode,
+    pub inventory: Vec<u8>,
+}
+
+impl PlayerData {
+    pub fn from_entry(entry: &EntryRef) -> Option<Self> {
+        Some(Self {
+            id: *entry.get_component::<PlayerId>().ok()?,
+            position: entry.get_component::<Position>().ok()?.0,
+            rotation: entry
+                .get_component::<Rotation>()
+                .map_or(Quat::IDENTITY, |r| r.0),
+            game_mode: entry.get_component::<Player>().ok()?.game_mode,
+            inventory: entry
+                .get_component::<PlayerInventory>()
+                .map_or(Vec::new(), |i| i.0.clone()),
+        })
+    }
+
+    // Moves an already spawned player entity to the saved state
+    pub fn apply(&self, world: &mut legion::World, entity: Entity) {
+        if let Some(mut entry) = world.entry(entity) {
+            entry.add_component(self.id);
+            entry.add_component(Position(self.position));
+            entry.add_component(Rotation(self.rotation));
+            entry.add_component(PlayerInventory(self.inventory.clone()));
+        }
+        set_game_mode(world, entity, self.game_mode);
+    }
+
+    pub fn write(&self, writer: &mut ByteWriter) {
+        writer.write_leb128(PLAYER_FORMAT_VERSION);
+        writer.write_bytes(&self.id.0.to_le_bytes());
+        writer.write_vec3(self.position);
+        writer.write_quat(self.rotation);
+        writer.write_u8(game_mode_to_u8(self.game_mode));
+        writer.write_bytes(&self.inventory);
+    }
+
+    pub fn read(reader: &mut ByteReader) -> Result<Self> {
+        let version = reader.read_leb128()?;
+        if version > PLAYER_FORMAT_VERSION {
+            bail!("Player format version {version} is newer than the supported version {PLAYER_FORMAT_VERSION}");
+        }
+        Ok(Self {
+            id: PlayerId(u128::from_le_bytes(reader.read_bytes()?.try_into()?)),
+            position: reader.read_vec3()?,
+            rotation: reader.read_quat()?,
+            game_mode: game_mode_from_u8(reader.read_u8()?)?,
+            inventory: reader.read_bytes()?.to_vec(),
+        })
+    }
+}
+
+// Every player with an id is saved, players without one haven't joined yet
+pub fn collect_players(world: &legion::World) -> Vec<PlayerData> {
+    let mut query = <(Entity, &PlayerId)>::query();
+    query
+        .iter(world)
+        .filter_map(|(entity, _)| {
+            world
+                .entry_ref(*entity)
+                .ok()
+                .and_then(|entry| PlayerData::from_entry(&entry))
+        })
+        .collect()
+}
+
+// One file per player, named after the player id
+pub struct PlayerStorage {
+    backend: StorageBackend,
+}
+
+impl PlayerStorage {
+    pub fn new(directory: PathBuf) -> Result<Self> {
+        Ok(Self {
+            backend: StorageBackend::disk(directory)?,
+        })
+    }
+
+    // Players are kept until the storage is dropped, nothing is written to disk
+    pub fn in_memory() -> Self {
+        Self {
+            backend: StorageBackend::memory(),
+        }
+    }
+
+    fn player_name(id: PlayerId) -> String {
+        format!("{}.player", id.to_uuid_string())
+    }
+
+    pub fn save(&self, player: &PlayerData) -> Result<()> {
+        let mut writer = ByteWriter::new();
+        player.write(&mut writer);
+        self.backend
+            .write(&Self::player_name(player.id), &writer.bytes)
+    }
+
+    pub fn load(&self, id: PlayerId) -> Result<Option<PlayerData>> {
+        match self.backend.read(&Self::player_name(id))? {
+            Some(bytes) => Ok(Some(PlayerData::read(&mut ByteReader::new(&bytes))?)),
+            None => Ok(None),
+        }
+    }
+
+    fn secret_name(id: PlayerId) -> String {
+        format!("{}.secret", id.to_uuid_string())
+    }
+
+    // The id and secret of the player on this machine, created the first time the world is played.
+    // Files from before secrets existed only hold the id, a secret is added to them
+    pub fn local_identity(&self) -> Result<(PlayerId, u128)> {
+        let contents = self
+            .backend
+            .read(LOCAL_PLAYER_FILE)
+            .ok()
+            .flatten()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .unwrap_or_default();
+        let mut lines = contents.lines();
+        let id = lines.next().and_then(PlayerId::parse);
+        let secret = lines
+            .next()
+            .and_then(|s| u128::from_str_radix(s.trim(), 16).ok());
+        if let (Some(id), Some(secret)) = (id, secret) {
+            return Ok((id, secret));
+        }
+        let id = id.unwrap_or_else(PlayerId::new);
+        let secret = rand::random::<u128>();
+        self.backend.write(
+            LOCAL_PLAYER_FILE,
+            format!("{}\n{secret:032x}\n", id.to_uuid_string()).as_bytes(),
+        )?;
+        Ok((id, secret))
+    }
+
+    // The first secret a player id joins with claims it, later joins have to use the same one.
+    // Only a hash is kept so the save can't be used to impersonate players
+    pub fn authenticate(&self, id: PlayerId, secret: u128) -> Result<bool> {
+        let hash = Sha256::digest(secret.to_le_bytes());
+        match self.backend.read(&Self::secret_name(id))? {
+            Some(saved) => Ok(saved == hash.as_slice()),
+            None => {
+                self.backend.write(&Self::secret_name(id), &hash)?;
+                Ok(true)
+            }
+        }
+    }
+
+    // Gives the entity its id and restores the saved state if the player has been here before
+    pub fn join(&self, world: &mut legion::World, entity: Entity, id: PlayerId) -> Result<()> {
+        match self.load(id)? {
+            Some(data) => data.apply(world, entity),
+            None => {
+                if let Some(mut entry) = world.entry(entity) {
+                    entry.add_component(id);
+                    entry.add_component(PlayerInventory::default());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_player(&self, world: &legion::World, entity: Entity) -> Result<()> {
+        let entry = world.entry_ref(entity)?;
+        match PlayerData::from_entry(&entry) {
+            Some(data) => self.save(&data),
+            None => bail!("Entity is not a player that has joined"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod player_data_tests {
+    use glam::{Quat, Vec3};
+
+    use super::PlayerData;
+    use crate::{
+        ecs::components::player_components::{GameMode, PlayerId},
+        persistence::binary::{ByteReader, ByteWriter},
+    };
+
+    #[test]
+    fn player_round_trip() {
+        for game_mode in GameMode::ALL {
+            let player = PlayerData {
+                id: PlayerId(u128::MAX - 5),
+                position: Vec3::new(10.0, 80.5, -3.0),
+                rotation: Quat::from_rotation_x(0.5),
+                game_mode,
+                inventory: (0..=255).collect(),
+            };
+            let mut writer = ByteWriter::new();
+            player.write(&mut writer);
+            let mut reader = ByteReader::new(&writer.bytes);
+            assert_eq!(PlayerData::read(&mut reader).unwrap(), player);
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn uuid_round_trip() {
+        let id = PlayerId(0x0123_4567_89ab_cdef_0011_2233_4455_6677);
+        assert_eq!(id.to_uuid_string(), "01234567-89ab-cdef-0011-223344556677");
+        assert_eq!(PlayerId::parse(&id.to_uuid_string()), Some(id));
+    }
+}
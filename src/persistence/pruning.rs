@@ -0,0 +1,130 @@
+use anyhow::Result;
+use glam::IVec3;
+use tracing::warn;
+
+use super::chunk_storage::ChunkStorage;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PruneOptions {
+    // Chunks further than radius chunks from the center on the horizontal plane are removed
+    pub center: IVec3,
+    pub radius: Option<u32>,
+    // Removes chunks no player has edited, they are generated again when next visited. Chunks with
+    // saved entities are kept, the terrain would come back but the entities wouldn't
+    pub remove_unmodified: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PruneReport {
+    pub examined: usize,
+    pub removed: usize,
+    pub bytes_freed: u64,
+}
+
+impl PruneOptions {
+    fn outside_radius(&self, chunk_pos: IVec3) -> bool {
+        match self.radius {
+            Some(radius) => {
+                let offset = (chunk_pos - self.center).abs();
+                offset.x.max(offset.z) as u32 > radius
+            }
+            None => false,
+        }
+    }
+}
+
+// Deletes the chunks matching the options from disk. Loaded chunks are only written again once they
+// change, so a running world should be saved first to keep its unsaved edits
+pub fn prune_chunks(storage: &ChunkStorage, options: &PruneOptions) -> Result<PruneReport> {
+    let mut report = PruneReport::default();
+    for chunk_pos in storage.stored_chunks()? {
+        report.examined += 1;
+        let remove = match options.outside_radius(chunk_pos) {
+            true => true,
+            false if options.remove_unmodified => match storage.load(&chunk_pos) {
+                Ok(Some(payload)) => !payload.player_modified && payload.entities.is_empty(),
+                Ok(None) => false,
+                // Unreadable chunks are kept so nothing is lost by accident
+                Err(e) => {
+                    warn!("Skipping chunk {chunk_pos} while pruning: {e}");
+                    false
+                }
+            },
+            false => false,
+        };
+        if remove {
+            report.bytes_freed += storage.delete(&chunk_pos)?;
+            report.removed += 1;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod pruning_tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{
+        persistence::{chunk_storage::ChunkPayload, entity_persistence::SavedEntity},
+        voxels::{voxel_data::VoxelData, voxel_scene::CHUNK_SIZE, voxel_shapes::voxel_shape},
+    };
+
+    fn payload(position: IVec3, player_modified: bool, entities: Vec<SavedEntity>) -> ChunkPayload {
+        let air = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 0,
+        };
+        ChunkPayload {
+            position,
+            voxels: vec![air; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize],
+            entities,
+            light: None,
+            biome: None,
+            player_modified,
+        }
+    }
+
+    #[test]
+    fn unmodified_chunks_with_entities_are_kept() {
+        let storage = ChunkStorage::in_memory();
+        let entity = SavedEntity {
+            id: 1,
+            position: Vec3::new(20.0, 4.0, 3.0),
+            rotation: None,
+            velocity: None,
+            collider: None,
+            gravity: None,
+            falling_voxel: None,
+            kind: Some("slime".to_string()),
+            dropped_item: None,
+            entity_collision: true,
+        };
+        let (generated, edited, inhabited, far) = (
+            IVec3::new(0, 0, 0),
+            IVec3::new(1, 0, 0),
+            IVec3::new(2, 0, 0),
+            IVec3::new(9, 0, 0),
+        );
+        storage
+            .save(&payload(generated, false, Vec::new()))
+            .unwrap();
+        storage.save(&payload(edited, true, Vec::new())).unwrap();
+        storage
+            .save(&payload(inhabited, false, vec![entity]))
+            .unwrap();
+        storage.save(&payload(far, true, Vec::new())).unwrap();
+
+        let options = PruneOptions {
+            center: IVec3::ZERO,
+            radius: Some(4),
+            remove_unmodified: true,
+        };
+        let report = prune_chunks(&storage, &options).unwrap();
+        assert_eq!((report.examined, report.removed), (4, 2));
+        let mut kept = storage.stored_chunks().unwrap();
+        kept.sort_by_key(|position| position.x);
+        assert_eq!(kept, [edited, inhabited]);
+    }
+}
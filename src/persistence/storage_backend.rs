@@ -0,0 +1,125 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use super::atomic_file::write_atomic;
+
+// Where the files of a save live. Memory keeps them in a map until the backend is dropped, so tests,
+// benchmarks and tools can run the whole world without touching the filesystem
+pub enum StorageBackend {
+    Disk(PathBuf),
+    Memory(Mutex<BTreeMap<String, Vec<u8>>>),
+}
+
+impl StorageBackend {
+    pub fn disk(directory: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&directory)?;
+        Ok(Self::Disk(directory))
+    }
+
+    pub fn memory() -> Self {
+        Self::Memory(Mutex::new(BTreeMap::new()))
+    }
+
+    // None for memory backends
+    pub fn directory(&self) -> Option<&Path> {
+        match self {
+            Self::Disk(directory) => Some(directory),
+            Self::Memory(_) => None,
+        }
+    }
+
+    // Replaces the file if it exists, on disk the write is atomic
+    pub fn write(&self, name: &str, bytes: &[u8]) -> Result<()> {
+        match self {
+            Self::Disk(directory) => write_atomic(&directory.join(name), bytes),
+            Self::Memory(files) => {
+                files.lock().insert(name.to_string(), bytes.to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    pub fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Disk(directory) => match fs::read(directory.join(name)) {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            Self::Memory(files) => Ok(files.lock().get(name).cloned()),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            Self::Disk(directory) => directory.join(name).exists(),
+            Self::Memory(files) => files.lock().contains_key(name),
+        }
+    }
+
+    // Returns the number of bytes freed, 0 if there was no such file
+    pub fn remove(&self, name: &str) -> Result<u64> {
+        match self {
+            Self::Disk(directory) => {
+                let path = directory.join(name);
+                if !path.exists() {
+                    return Ok(0);
+                }
+                let size = fs::metadata(&path)?.len();
+                fs::remove_file(path)?;
+                Ok(size)
+            }
+            Self::Memory(files) => Ok(files
+                .lock()
+                .remove(name)
+                .map_or(0, |bytes| bytes.len() as u64)),
+        }
+    }
+
+    // The names of every file, subdirectories and names that aren't valid UTF-8 are left out
+    pub fn names(&self) -> Result<Vec<String>> {
+        match self {
+            Self::Disk(directory) => {
+                let mut names = Vec::new();
+                for entry in fs::read_dir(directory)? {
+                    let entry = entry?;
+                    if !entry.file_type()?.is_file() {
+                        continue;
+                    }
+                    if let Some(name) = entry.file_name().to_str() {
+                        names.push(name.to_string());
+                    }
+                }
+                Ok(names)
+            }
+            Self::Memory(files) => Ok(files.lock().keys().cloned().collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod storage_backend_tests {
+    use super::StorageBackend;
+
+    #[test]
+    fn memory_backend_keeps_files() {
+        let backend = StorageBackend::memory();
+        assert!(backend.directory().is_none());
+        assert_eq!(backend.read("a").unwrap(), None);
+        backend.write("a", &[1, 2, 3]).unwrap();
+        backend.write("b", &[4]).unwrap();
+        backend.write("a", &[5, 6]).unwrap();
+        assert!(backend.contains("a"));
+        assert_eq!(backend.read("a").unwrap(), Some(vec![5, 6]));
+        assert_eq!(backend.names().unwrap(), vec!["a", "b"]);
+        assert_eq!(backend.remove("a").unwrap(), 2);
+        assert_eq!(backend.remove("a").unwrap(), 0);
+        assert_eq!(backend.names().unwrap(), vec!["b"]);
+    }
+}
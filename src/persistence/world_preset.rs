@@ -0,0 +1,104 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::*;
+
+use super::{atomic_file::write_atomic, world_save::WorldMetadata};
+
+pub const PRESET_FORMAT_VERSION: u64 = 1;
+
+// Everything needed to generate a world again, shared as a single JSON file
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldPreset {
+    pub seed: u64,
+    pub generator_preset: String,
+    pub biome_overrides: BTreeMap<String, serde_json::Value>,
+    // Biomes, climate, height and generation stages of each dimension, see Dimension::from_value
+    pub dimensions: BTreeMap<String, serde_json::Value>,
+}
+
+impl WorldPreset {
+    pub fn from_metadata(metadata: &WorldMetadata) -> Self {
+        Self {
+            seed: metadata.seed,
+            generator_preset: metadata.generator_preset.clone(),
+            biome_overrides: metadata.biome_overrides.clone(),
+            dimensions: metadata.dimensions.clone(),
+        }
+    }
+
+    pub fn to_metadata(&self, name: String) -> WorldMetadata {
+        let mut metadata = WorldMetadata::new(name, self.seed, self.generator_preset.clone());
+        metadata.biome_overrides = self.biome_overrides.clone();
+        metadata.dimensions = self.dimensions.clone();
+        metadata
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "Format Version": PRESET_FORMAT_VERSION,
+            "Seed": self.seed,
+            "Generator Preset": self.generator_preset,
+            "Biome Overrides": self.biome_overrides,
+            "Dimensions": self.dimensions,
+        })
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> Result<Self> {
+        let version = json
+            .get("Format Version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| anyhow!("World preset is missing \"Format Version\""))?;
+        if version > PRESET_FORMAT_VERSION {
+            bail!("World preset version {version} is newer than the supported version {PRESET_FORMAT_VERSION}");
+        }
+        let biome_overrides = match json.get("Biome Overrides") {
+            Some(overrides) => overrides
+                .as_object()
+                .ok_or_else(|| anyhow!("\"Biome Overrides\" in the world preset is not an object"))?
+                .iter()
+                .map(|(name, biome)| (name.clone(), biome.clone()))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        let dimensions = match json.get("Dimensions") {
+            Some(dimensions) => dimensions
+                .as_object()
+                .ok_or_else(|| anyhow!("\"Dimensions\" in the world preset is not an object"))?
+                .iter()
+                .map(|(name, dimension)| (name.clone(), dimension.clone()))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            seed: json
+                .get("Seed")
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| anyhow!("World preset is missing \"Seed\""))?,
+            generator_preset: json
+                .get("Generator Preset")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("World preset is missing \"Generator Preset\""))?
+                .to_string(),
+            biome_overrides,
+            dimensions,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.to_json())?;
+        write_atomic(path, json.as_bytes())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the world preset {}", path.display()))?;
+        Self::from_json(&serde_json::from_str(&data)?)
+    }
+
+    // Adds a biome from a biome definition file, replacing the biome with the same name
+    pub fn override_biome(&mut self, name: String, path: &Path) -> Result<()> {
+        let biome = serde_json::from_str(&fs::read_to_string(path)?)?;
+        self.biome_overrides.insert(name, biome);
+        Ok(())
+    }
+}
@@ -0,0 +1,644 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::*;
+use parking_lot::{Mutex, RwLock};
+use tracing::warn;
+
+use crate::{
+    config,
+    data_packs::{self, PackEntry},
+    ecs::world::World,
+    environment::{self, world_time::WorldTime},
+    plugins::manifest::Version,
+    random::OVERWORLD,
+    voxels::{
+        biome_profile::{set_world_biomes, BiomeProfile},
+        dimension::{set_world_dimensions, Dimension},
+        voxel_registry::{get_voxel_by_id, loaded_mods, voxel_id_mappings},
+        voxel_scene::VoxelScene,
+    },
+};
+
+use super::{
+    backup::{self, BackupInfo},
+    chunk_storage::ChunkStorage,
+    player_data::{collect_players, PlayerStorage},
+    pruning::{self, PruneOptions, PruneReport},
+    save_dirty_chunks,
+    storage_backend::StorageBackend,
+    world_preset::WorldPreset,
+    world_stats::{self, WorldStatistics},
+};
+
+pub const WORLD_FORMAT_VERSION: u64 = 1;
+pub(super) const MANIFEST_FILE: &str = "world.json";
+pub(super) const CHUNK_DIRECTORY: &str = "chunks";
+pub(super) const DIMENSION_DIRECTORY: &str = "dimensions";
+const PLAYER_DIRECTORY: &str = "players";
+
+// A newer engine can save things this one doesn't know about and would drop, so its worlds are
+// only opened with --allow-newer-world, after a backup
+static ALLOW_NEWER_WORLDS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_allow_newer_worlds(allowed: bool) {
+    ALLOW_NEWER_WORLDS.store(allowed, Ordering::Relaxed);
+}
+
+pub fn engine_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("The crate version is a valid version")
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldMetadata {
+    pub name: String,
+    pub seed: u64,
+    pub format_version: u64,
+    // The biome the overworld generates when the world doesn't configure its overworld
+    pub generator_preset: String,
+    // Voxels broken and placed, distance traveled, chunks generated and play time
+    pub statistics: WorldStatistics,
+    // Environment tick, so the time of day and season survive a reload
+    pub world_tick: u64,
+    // Biome definitions that replace the biomes with the same name while this world is loaded
+    pub biome_overrides: BTreeMap<String, serde_json::Value>,
+    // Dimension settings by name, an overworld listed here replaces the default one
+    pub dimensions: BTreeMap<String, serde_json::Value>,
+    // Generation and simulation settings overriding the settings file while this world is open, see
+    // config::WORLD_SETTINGS
+    pub settings: BTreeMap<String, serde_json::Value>,
+    // Data packs in load order, disabled packs stay listed so they keep their place
+    pub data_packs: Vec<PackEntry>,
+    // The voxel names the ids in the chunks stood for when the manifest was last written
+    pub voxel_ids: BTreeMap<u16, String>,
+    // The engine that last wrote the manifest, None for worlds saved before it was recorded
+    pub engine_version: Option<Version>,
+    // The versions of the mods loaded when the manifest was last written, by mod id
+    pub mods: BTreeMap<String, Version>,
+}
+
+impl WorldMetadata {
+    pub fn new(name: String, seed: u64, generator_preset: String) -> Self {
+        Self {
+            name,
+            seed,
+            format_version: WORLD_FORMAT_VERSION,
+            generator_preset,
+            statistics: WorldStatistics::default(),
+            world_tick: 0,
+            biome_overrides: BTreeMap::new(),
+            dimensions: BTreeMap::new(),
+            settings: BTreeMap::new(),
+            data_packs: Vec::new(),
+            voxel_ids: BTreeMap::new(),
+            engine_version: None,
+            mods: BTreeMap::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "Name": self.name,
+            "Seed": self.seed,
+            "Format Version": self.format_version,
+            "Generator Preset": self.generator_preset,
+            "Play Time": self.statistics.play_time,
+            "Statistics": self.statistics.to_json(),
+            "World Tick": self.world_tick,
+            "Biome Overrides": self.biome_overrides,
+            "Dimensions": self.dimensions,
+            "Settings": self.settings,
+            "Data Packs": self.data_packs.iter().map(|pack| pack.to_json()).collect::<Vec<_>>(),
+            "Voxel Ids": self.voxel_ids,
+            "Engine Version": self.engine_version.map(|version| version.to_string()),
+            "Mods": self.mods.iter().map(|(id, version)| (id.clone(), version.to_string())).collect::<BTreeMap<_, _>>(),
+        })
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> Result<Self> {
+        let get = |name: &str| {
+            json.get(name)
+                .ok_or_else(|| anyhow!("World manifest is missing \"{name}\""))
+        };
+        let as_u64 = |name: &str| -> Result<u64> {
+            get(name)?
+                .as_u64()
+                .ok_or_else(|| anyhow!("\"{name}\" in the world manifest is not an integer"))
+        };
+        let as_string = |name: &str| -> Result<String> {
+            Ok(get(name)?
+                .as_str()
+                .ok_or_else(|| anyhow!("\"{name}\" in the world manifest is not a string"))?
+                .to_string())
+        };
+        Ok(Self {
+            name: as_string("Name")?,
+            seed: as_u64("Seed")?,
+            format_version: as_u64("Format Version")?,
+            generator_preset: as_string("Generator Preset")?,
+            statistics: WorldStatistics::from_json(
+                json.get("Statistics"),
+                json.get("Play Time")
+                    .and_then(|v| v.as_f64())
+                    .unwrap_or(0.0),
+            ),
+            world_tick: json.get("World Tick").and_then(|v| v.as_u64()).unwrap_or(0),
+            biome_overrides: json
+                .get("Biome Overrides")
+                .and_then(|v| v.as_object())
+                .map_or(BTreeMap::new(), |overrides| {
+                    overrides
+                        .iter()
+                        .map(|(name, biome)| (name.clone(), biome.clone()))
+                        .collect()
+                }),
+            dimensions: json.get("Dimensions").and_then(|v| v.as_object()).map_or(
+                BTreeMap::new(),
+                |dimensions| {
+                    dimensions
+                        .iter()
+                        .map(|(name, dimension)| (name.clone(), dimension.clone()))
+                        .collect()
+                },
+            ),
+            settings: json.get("Settings").and_then(|v| v.as_object()).map_or(
+                BTreeMap::new(),
+                |settings| {
+                    settings
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect()
+                },
+            ),
+            data_packs: json
+                .get("Data Packs")
+                .and_then(|v| v.as_array())
+                .map_or(Vec::new(), |packs| {
+                    packs.iter().filter_map(PackEntry::from_json).collect()
+                }),
+            voxel_ids: json.get("Voxel Ids").and_then(|v| v.as_object()).map_or(
+                BTreeMap::new(),
+                |ids| {
+                    ids.iter()
+                        .filter_map(|(id, name)| {
+                            Some((id.parse().ok()?, name.as_str()?.to_string()))
+                        })
+                        .collect()
+                },
+            ),
+            engine_version: json
+                .get("Engine Version")
+                .and_then(|v| v.as_str())
+                .and_then(|version| Version::parse(version).ok()),
+            mods: json
+                .get("Mods")
+                .and_then(|v| v.as_object())
+                .map_or(BTreeMap::new(), |mods| {
+                    mods.iter()
+                        .filter_map(|(id, version)| {
+                            Some((id.clone(), Version::parse(version.as_str()?).ok()?))
+                        })
+                        .collect()
+                }),
+        })
+    }
+}
+
+// How a world is opened, the default opens the save directory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorldSettings {
+    // Keeps the manifest, chunks and players in memory instead of the save directory. The world
+    // starts from the given metadata and is gone once the save is dropped, for unit tests,
+    // benchmarks and preview tools
+    pub in_memory: bool,
+}
+
+// Owns a save directory laid out as
+// world.json - the manifest holding the world metadata
+// chunks/     - chunk payloads with the entities inside them
+// dimensions/ - a chunks/ folder for every dimension besides the overworld
+// players/    - per player data
+// backups/    - compressed snapshots of everything above
+pub struct WorldSave {
+    // The save directory itself, with the manifest and the other files of the world
+    files: StorageBackend,
+    metadata: RwLock<WorldMetadata>,
+    chunk_storage: Arc<ChunkStorage>,
+    // The chunk storages of the other dimensions, opened when a scene first asks for them
+    dimension_storage: Mutex<HashMap<String, Arc<ChunkStorage>>>,
+    player_storage: Arc<PlayerStorage>,
+}
+
+impl WorldSave {
+    pub fn exists(directory: &Path) -> bool {
+        directory.join(MANIFEST_FILE).exists()
+    }
+
+    // Creates a new world, fails if there is already a world in the directory
+    pub fn create(directory: PathBuf, metadata: WorldMetadata) -> Result<Self> {
+        if Self::exists(&directory) {
+            bail!("A world already exists in {}", directory.display());
+        }
+        let save = Self::from_parts(Some(directory), metadata)?;
+        save.write_manifest()?;
+        Ok(save)
+    }
+
+    // Opens or creates the world in the directory, or a new world without one in memory
+    pub fn open_or_create_with(
+        directory: PathBuf,
+        metadata: WorldMetadata,
+        settings: WorldSettings,
+    ) -> Result<Self> {
+        if !settings.in_memory {
+            return Self::open_or_create(directory, metadata);
+        }
+        let save = Self::from_parts(None, metadata)?;
+        save.write_manifest()?;
+        Ok(save)
+    }
+
+    pub fn open(directory: PathBuf) -> Result<Self> {
+        let metadata = Self::read_metadata(&directory)?;
+        let ids_changed = metadata
+            .voxel_ids
+            .iter()
+            .any(|(id, name)| get_voxel_by_id(*id).map_or(true, |voxel| voxel.name != *name));
+        if ids_changed {
+            warn!(
+                "Voxel ids changed since {} was saved, run with --check --repair to update its chunks",
+                directory.display()
+            );
+        }
+        Self::check_versions(&directory, &metadata)?;
+        environment::set_world_time(WorldTime::new(metadata.world_tick));
+        Self::from_parts(Some(directory), metadata)
+    }
+
+    // Refuses worlds saved by a newer engine unless they're allowed, then backs them up before this
+    // engine writes over them. Older mods than the world was saved with only get a warning
+    fn check_versions(directory: &Path, metadata: &WorldMetadata) -> Result<()> {
+        for (id, version) in loaded_mods() {
+            match metadata.mods.get(&id) {
+                Some(saved) if *saved > version => warn!(
+                    "{} was saved with {id} {saved}, {version} is loaded and may not know everything it saved",
+                    directory.display()
+                ),
+                _ => {}
+            }
+        }
+        let saved = match metadata.engine_version {
+            Some(saved) if saved > engine_version() => saved,
+            _ => return Ok(()),
+        };
+        if !ALLOW_NEWER_WORLDS.load(Ordering::Relaxed) {
+            bail!(
+                "{} was saved by engine version {saved}, newer than this engine's {}. Run with --allow-newer-world to open it anyway after a backup",
+                directory.display(),
+                engine_version()
+            );
+        }
+        let backup = backup::create_backup(
+            directory,
+            &format!("before opening with {}", engine_version()),
+        )?;
+        warn!(
+            "Opening {} saved by the newer engine version {saved}, backed up as {}",
+            directory.display(),
+            backup.name
+        );
+        Ok(())
+    }
+
+    // Reads the manifest without opening the world, used to pick the data packs before the registries load
+    pub fn read_metadata(directory: &Path) -> Result<WorldMetadata> {
+        let manifest = fs::read_to_string(directory.join(MANIFEST_FILE)).with_context(|| {
+            format!(
+                "Failed to read the world manifest in {}",
+                directory.display()
+            )
+        })?;
+        let metadata = WorldMetadata::from_json(&serde_json::from_str(&manifest)?)?;
+        if metadata.format_version > WORLD_FORMAT_VERSION {
+            bail!(
+                "World format version {} is newer than the supported version {WORLD_FORMAT_VERSION}",
+                metadata.format_version
+            );
+        }
+        Ok(metadata)
+    }
+
+    pub fn open_or_create(directory: PathBuf, metadata: WorldMetadata) -> Result<Self> {
+        match Self::exists(&directory) {
+            true => Self::open(directory),
+            false => Self::create(directory, metadata),
+        }
+    }
+
+    // Without a directory everything is kept in memory
+    fn from_parts(directory: Option<PathBuf>, mut metadata: WorldMetadata) -> Result<Self> {
+        // The packs picked when the game started, including ones added to the packs folder since the last save
+        if let Some(packs) = data_packs::packs() {
+            metadata.data_packs = packs;
+        }
+        let mut biomes = HashMap::new();
+        for (name, biome) in &metadata.biome_overrides {
+            let profile = BiomeProfile::from_value(biome)
+                .map_err(|e| anyhow!("The world's biome {name} can't be loaded: {e}"))?;
+            biomes.insert(name.clone(), profile);
+        }
+        let mut dimensions = HashMap::new();
+        for (name, dimension) in &metadata.dimensions {
+            let dimension = Dimension::from_value(dimension)
+                .map_err(|e| anyhow!("The world's dimension {name} can't be loaded: {e}"))?;
+            dimensions.insert(name.clone(), dimension);
+        }
+        set_world_biomes(biomes);
+        set_world_dimensions(&metadata.generator_preset, dimensions);
+        config::set_world_settings(&metadata.settings);
+        let (files, chunk_storage, player_storage) = match directory {
+            Some(directory) => (
+                StorageBackend::disk(directory.clone())?,
+                ChunkStorage::new(directory.join(CHUNK_DIRECTORY))?,
+                PlayerStorage::new(directory.join(PLAYER_DIRECTORY))?,
+            ),
+            None => (
+                StorageBackend::memory(),
+                ChunkStorage::in_memory(),
+                PlayerStorage::in_memory(),
+            ),
+        };
+        world_stats::begin(metadata.statistics);
+        Ok(Self {
+            files,
+            metadata: RwLock::new(metadata),
+            chunk_storage: Arc::new(chunk_storage),
+            dimension_storage: Mutex::new(HashMap::new()),
+            player_storage: Arc::new(player_storage),
+        })
+    }
+
+    // None for worlds kept in memory
+    pub fn directory(&self) -> Option<&Path> {
+        self.files.directory()
+    }
+
+    pub fn players_directory(&self) -> Option<PathBuf> {
+        self.directory()
+            .map(|directory| directory.join(PLAYER_DIRECTORY))
+    }
+
+    fn disk_directory(&self) -> Result<&Path> {
+        self.directory()
+            .ok_or_else(|| anyhow!("{} is only kept in memory", self.metadata.read().name))
+    }
+
+    // Reads a file next to the manifest, like the regions or the operators
+    pub fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.files.read(name)
+    }
+
+    pub fn write_file(&self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.files.write(name, bytes)
+    }
+
+    pub fn chunk_storage(&self) -> Arc<ChunkStorage> {
+        Arc::clone(&self.chunk_storage)
+    }
+
+    // The overworld's chunks are the world's chunk storage, the other dimensions are kept apart
+    pub fn dimension_chunk_storage(&self, dimension: &str) -> Result<Arc<ChunkStorage>> {
+        if dimension == OVERWORLD {
+            return Ok(self.chunk_storage());
+        }
+        let mut storages = self.dimension_storage.lock();
+        if let Some(storage) = storages.get(dimension) {
+            return Ok(Arc::clone(storage));
+        }
+        let storage = Arc::new(match self.directory() {
+            Some(directory) => ChunkStorage::new(
+                directory
+                    .join(DIMENSION_DIRECTORY)
+                    .join(dimension)
+                    .join(CHUNK_DIRECTORY),
+            )?,
+            None => ChunkStorage::in_memory(),
+        });
+        storages.insert(dimension.to_string(), Arc::clone(&storage));
+        Ok(storage)
+    }
+
+    pub fn player_storage(&self) -> Arc<PlayerStorage> {
+        Arc::clone(&self.player_storage)
+    }
+
+    // The metadata as it would be written right now, including the current session
+    pub fn metadata(&self) -> WorldMetadata {
+        let mut metadata = self.metadata.read().clone();
+        metadata.statistics = world_stats::current();
+        metadata.world_tick = environment::current().time.tick;
+        metadata
+    }
+
+    // Records the voxel ids, engine and mod versions of this session along with the rest, the chunks
+    // are written with them
+    pub fn write_manifest(&self) -> Result<()> {
+        {
+            let mut metadata = self.metadata.write();
+            metadata.voxel_ids = voxel_id_mappings().into_iter().collect();
+            metadata.engine_version = Some(engine_version());
+            metadata.mods = loaded_mods().into_iter().collect();
+        }
+        let json = serde_json::to_string_pretty(&self.metadata().to_json())?;
+        self.files.write(MANIFEST_FILE, json.as_bytes())
+    }
+
+    // Saves every dirty chunk with its entities, the players and then the manifest, returns the number of chunks saved
+    pub fn save(
+        &self,
+        scene: &Arc<RwLock<VoxelScene>>,
+        world: &Arc<RwLock<World>>,
+    ) -> Result<usize> {
+        let saved = save_dirty_chunks(scene, world)?;
+        for player in collect_players(&world.read().legion_world) {
+            self.player_storage.save(&player)?;
+        }
+        self.write_manifest()?;
+        Ok(saved)
+    }
+
+    // Creates a new world that generates the same terrain as the world the preset was exported from
+    pub fn create_from_preset(
+        directory: PathBuf,
+        name: String,
+        preset: &WorldPreset,
+    ) -> Result<Self> {
+        Self::create(directory, preset.to_metadata(name))
+    }
+
+    pub fn export_preset(&self, path: &Path) -> Result<()> {
+        WorldPreset::from_metadata(&self.metadata.read()).save(path)
+    }
+
+    // Removes chunks from disk to shrink the save, see prune_chunks
+    pub fn prune_chunks(&self, options: &PruneOptions) -> Result<PruneReport> {
+        pruning::prune_chunks(&self.chunk_storage, options)
+    }
+
+    // Snapshots what is on disk right now, save first to include unsaved changes
+    pub fn create_backup(&self, label: &str) -> Result<BackupInfo> {
+        self.write_manifest()?;
+        backup::create_backup(self.disk_directory()?, label)
+    }
+
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        backup::list_backups(self.disk_directory()?)
+    }
+
+    // Restoring replaces the world on disk, so it is done before the world is opened
+    pub fn restore_backup(directory: &Path, name: &str) -> Result<()> {
+        backup::restore_backup(directory, name)
+    }
+}
+
+#[cfg(test)]
+mod world_save_tests {
+    use glam::IVec3;
+
+    use super::{
+        engine_version, set_allow_newer_worlds, WorldMetadata, WorldSave, WorldSettings,
+        MANIFEST_FILE,
+    };
+    use crate::{
+        persistence::{backup, chunk_storage::ChunkPayload},
+        plugins::manifest::Version,
+        random::OVERWORLD,
+        voxels::{voxel_data::VoxelData, voxel_scene::CHUNK_SIZE, voxel_shapes::VoxelShape},
+    };
+
+    #[test]
+    fn in_memory_world_stays_off_disk() {
+        let directory = std::env::temp_dir().join("assemblage_in_memory_world");
+        let metadata = WorldMetadata::new("memory".to_string(), 7, "plains".to_string());
+        let save = WorldSave::open_or_create_with(
+            directory.clone(),
+            metadata,
+            WorldSettings { in_memory: true },
+        )
+        .unwrap();
+        assert!(save.directory().is_none());
+
+        let position = IVec3::new(1, -2, 3);
+        let voxel = VoxelData {
+            shape: VoxelShape { data: 0 },
+            state: 0,
+            id: 4,
+        };
+        save.chunk_storage()
+            .save(&ChunkPayload {
+                position,
+                voxels: vec![voxel; CHUNK_SIZE.pow(3) as usize],
+                entities: Vec::new(),
+                light: None,
+                biome: None,
+                player_modified: true,
+            })
+            .unwrap();
+        let loaded = save.chunk_storage().load(&position).unwrap().unwrap();
+        assert!(loaded.player_modified);
+        assert_eq!({ loaded.voxels[0].id }, 4);
+        assert_eq!(
+            save.chunk_storage().stored_chunks().unwrap(),
+            vec![position]
+        );
+
+        let identity = save.player_storage().local_identity().unwrap();
+        assert_eq!(save.player_storage().local_identity().unwrap(), identity);
+        save.write_file("regions.json", b"{}").unwrap();
+        assert_eq!(
+            save.read_file("regions.json").unwrap(),
+            Some(b"{}".to_vec())
+        );
+        save.write_manifest().unwrap();
+        assert!(save.create_backup("test").is_err());
+        assert!(!directory.exists());
+    }
+
+    #[test]
+    fn dimensions_keep_their_own_chunks() {
+        let directory = std::env::temp_dir().join("assemblage_dimension_world");
+        let save = WorldSave::open_or_create_with(
+            directory,
+            WorldMetadata::new("dimensions".to_string(), 7, "plains".to_string()),
+            WorldSettings { in_memory: true },
+        )
+        .unwrap();
+        let position = IVec3::new(0, -3, 0);
+        let caves = save.dimension_chunk_storage("caves").unwrap();
+        caves
+            .save(&ChunkPayload {
+                position,
+                voxels: vec![
+                    VoxelData {
+                        shape: VoxelShape { data: 0 },
+                        state: 0,
+                        id: 0,
+                    };
+                    CHUNK_SIZE.pow(3) as usize
+                ],
+                entities: Vec::new(),
+                light: None,
+                biome: None,
+                player_modified: false,
+            })
+            .unwrap();
+        assert!(save
+            .dimension_chunk_storage("caves")
+            .unwrap()
+            .contains(&position));
+        assert!(!save
+            .dimension_chunk_storage(OVERWORLD)
+            .unwrap()
+            .contains(&position));
+    }
+
+    #[test]
+    fn worlds_from_newer_engines_are_refused() {
+        let directory = std::env::temp_dir().join("assemblage_newer_engine_world");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let current = engine_version();
+        let mut metadata = WorldMetadata::new("newer".to_string(), 7, "plains".to_string());
+        metadata.engine_version = Some(Version {
+            major: current.major + 1,
+            ..current
+        });
+        metadata
+            .mods
+            .insert("lanterns".to_string(), Version::parse("1.2.0").unwrap());
+        let json = metadata.to_json();
+        assert_eq!(WorldMetadata::from_json(&json).unwrap(), metadata);
+        std::fs::write(directory.join(MANIFEST_FILE), json.to_string()).unwrap();
+
+        let error = WorldSave::open(directory.clone()).err().unwrap();
+        assert!(error.to_string().contains("--allow-newer-world"));
+        assert!(backup::list_backups(&directory).unwrap().is_empty());
+
+        set_allow_newer_worlds(true);
+        let checked = WorldSave::check_versions(&directory, &metadata);
+        set_allow_newer_worlds(false);
+        checked.unwrap();
+        assert_eq!(backup::list_backups(&directory).unwrap().len(), 1);
+
+        metadata.engine_version = Some(current);
+        WorldSave::check_versions(&directory, &metadata).unwrap();
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use serde_json::Value;
+
+// Totals over the whole life of a world, saved in its manifest
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorldStatistics {
+    pub voxels_broken: u64,
+    pub voxels_placed: u64,
+    // Blocks walked, flown and fallen by every player together, teleports don't count
+    pub distance_traveled: f64,
+    pub chunks_generated: u64,
+    // Seconds the world has been played for across all sessions
+    pub play_time: f64,
+}
+
+impl WorldStatistics {
+    // The play time is written next to the statistics, where manifests kept it before
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "Voxels Broken": self.voxels_broken,
+            "Voxels Placed": self.voxels_placed,
+            "Distance Traveled": self.distance_traveled,
+            "Chunks Generated": self.chunks_generated,
+        })
+    }
+
+    // Missing statistics start at 0, worlds saved before they were tracked have none
+    pub fn from_json(json: Option<&Value>, play_time: f64) -> Self {
+        let count = |name: &str| json.and_then(|json| json.get(name)?.as_u64()).unwrap_or(0);
+        Self {
+            voxels_broken: count("Voxels Broken"),
+            voxels_placed: count("Voxels Placed"),
+            distance_traveled: json
+                .and_then(|json| json.get("Distance Traveled")?.as_f64())
+                .unwrap_or(0.0),
+            chunks_generated: count("Chunks Generated"),
+            play_time,
+        }
+    }
+
+    fn plus(&self, other: &WorldStatistics) -> Self {
+        Self {
+            voxels_broken: self.voxels_broken + other.voxels_broken,
+            voxels_placed: self.voxels_placed + other.voxels_placed,
+            distance_traveled: self.distance_traveled + other.distance_traveled,
+            chunks_generated: self.chunks_generated + other.chunks_generated,
+            play_time: self.play_time + other.play_time,
+        }
+    }
+}
+
+// The statistics of the open world, what was saved when it was opened plus this session
+struct Tracker {
+    saved: WorldStatistics,
+    session: WorldStatistics,
+    opened: Instant,
+}
+
+lazy_static! {
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Tracker {
+        saved: WorldStatistics::default(),
+        session: WorldStatistics::default(),
+        opened: Instant::now(),
+    });
+}
+
+// Starts counting for a world that was just opened, with its saved totals
+pub fn begin(saved: WorldStatistics) {
+    *TRACKER.lock() = Tracker {
+        saved,
+        session: WorldStatistics::default(),
+        opened: Instant::now(),
+    };
+}
+
+// Counts something that happened in the open world, like
+// record(|stats| stats.voxels_broken += 1)
+pub fn record(update: impl FnOnce(&mut WorldStatistics)) {
+    update(&mut TRACKER.lock().session);
+}
+
+// The totals of the open world including this session, for game UIs and achievements
+pub fn current() -> WorldStatistics {
+    let tracker = TRACKER.lock();
+    let mut session = tracker.session;
+    session.play_time = tracker.opened.elapsed().as_secs_f64();
+    tracker.saved.plus(&session)
+}
+
+#[cfg(test)]
+mod world_stats_tests {
+    use super::*;
+
+    #[test]
+    fn statistics_survive_a_round_trip() {
+        let statistics = WorldStatistics {
+            voxels_broken: 12,
+            voxels_placed: 7,
+            distance_traveled: 130.5,
+            chunks_generated: 40,
+            play_time: 60.0,
+        };
+        let json = statistics.to_json();
+        assert_eq!(WorldStatistics::from_json(Some(&json), 60.0), statistics);
+        assert_eq!(
+            WorldStatistics::from_json(None, 0.0),
+            WorldStatistics::default()
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use glam::Vec3;
 use rapier3d::prelude::*;
+use tracing::debug;
 
 pub struct PhysicsScene {
     rigidbodies: RigidBodySet,
@@ -54,7 +55,7 @@ impl PhysicsScene {
     }
 
     fn physics_scene_processor() {
-        println!("Started physics scene processor");
+        debug!("Started physics scene processor");
         loop {
             // Fixed update loop
         }
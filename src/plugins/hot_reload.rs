@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use anyhow::{bail, Result};
+use glam::IVec3;
+use parking_lot::{Mutex, RwLock};
+use tracing::{error, info, warn};
+
+use crate::{
+    events::{subscribe_json, unsubscribe, SubscriptionId},
+    voxels::{
+        features::{register_feature, unregister_feature},
+        voxel_behavior::{register_behavior, VoxelBehavior},
+        voxel_data::VoxelData,
+        voxel_registry::{is_frozen, register_voxel},
+        voxel_scene::VoxelScene,
+    },
+};
+
+use super::{load_entry, manifest::ModManifest, register_plugin_biomes, LoadedPlugin};
+
+// Reloading is only allowed in dev mode, a reload throws away the plugin's state and a half
+// written file can break it, which is fine while working on a mod but not on a server people play on
+static DEV_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_dev_mode(enabled: bool) {
+    DEV_MODE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn dev_mode() -> bool {
+    DEV_MODE.load(Ordering::Relaxed)
+}
+
+// Voxel profiles keep the behavior they were loaded with, so plugin behaviors are registered through
+// this and a reload swaps what it forwards to. A behavior the new version dropped does nothing
+struct ReloadableBehavior {
+    current: RwLock<Option<Arc<dyn VoxelBehavior>>>,
+}
+
+impl ReloadableBehavior {
+    // Cloned out so a reload doesn't wait for a hook to finish
+    fn current(&self) -> Option<Arc<dyn VoxelBehavior>> {
+        self.current.read().clone()
+    }
+}
+
+impl VoxelBehavior for ReloadableBehavior {
+    fn on_random_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        if let Some(behavior) = self.current() {
+            behavior.on_random_tick(scene, position, voxel);
+        }
+    }
+
+    fn on_scheduled_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        if let Some(behavior) = self.current() {
+            behavior.on_scheduled_tick(scene, position, voxel);
+        }
+    }
+
+    fn on_neighbor_changed(
+        &self,
+        scene: &VoxelScene,
+        position: IVec3,
+        voxel: VoxelData,
+        neighbor_position: IVec3,
+    ) {
+        if let Some(behavior) = self.current() {
+            behavior.on_neighbor_changed(scene, position, voxel, neighbor_position);
+        }
+    }
+
+    fn on_signal_changed(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData, power: u8) {
+        if let Some(behavior) = self.current() {
+            behavior.on_signal_changed(scene, position, voxel, power);
+        }
+    }
+}
+
+// What a loaded mod registered, so the next version can take its place
+pub struct ModRecord {
+    manifest: ModManifest,
+    modified: Option<SystemTime>,
+    behaviors: HashMap<String, Arc<ReloadableBehavior>>,
+    features: Vec<String>,
+    subscriptions: Vec<SubscriptionId>,
+    voxels: Vec<String>,
+}
+
+impl ModRecord {
+    pub fn new(manifest: ModManifest) -> Self {
+        Self {
+            modified: last_modified(&manifest),
+            manifest,
+            behaviors: HashMap::new(),
+            features: Vec::new(),
+            subscriptions: Vec::new(),
+            voxels: Vec::new(),
+        }
+    }
+}
+
+lazy_static! {
+    // Mods in load order
+    static ref MODS: Mutex<Vec<ModRecord>> = Mutex::new(Vec::new());
+}
+
+pub fn track_mod(record: ModRecord) {
+    MODS.lock().push(record);
+}
+
+// Registers what a plugin loaded in place of what its previous version registered, returns the biomes
+// since they can only be parsed once the voxel registry has loaded
+pub fn apply_registrations(plugin: LoadedPlugin, record: &mut ModRecord) -> Vec<(String, String)> {
+    let name = plugin.name;
+
+    // Behaviors go first so the plugin's voxels can use them
+    let mut behaviors = HashMap::new();
+    for (behavior, hooks) in plugin.behaviors {
+        let slot = match record.behaviors.remove(&behavior) {
+            Some(slot) => {
+                *slot.current.write() = Some(hooks);
+                slot
+            }
+            None => {
+                let slot = Arc::new(ReloadableBehavior {
+                    current: RwLock::new(Some(hooks)),
+                });
+                register_behavior(&behavior, Arc::clone(&slot) as Arc<dyn VoxelBehavior>);
+                slot
+            }
+        };
+        behaviors.insert(behavior, slot);
+    }
+    for slot in record.behaviors.values() {
+        *slot.current.write() = None;
+    }
+    record.behaviors = behaviors;
+
+    for feature in &record.features {
+        if !plugin.features.iter().any(|(new, _)| new == feature) {
+            unregister_feature(feature);
+        }
+    }
+    record.features = plugin
+        .features
+        .iter()
+        .map(|(feature, _)| feature.clone())
+        .collect();
+    for (feature, placer) in plugin.features {
+        register_feature(&feature, placer);
+    }
+
+    for id in record.subscriptions.drain(..) {
+        unsubscribe(id);
+    }
+    for (event, priority, handler) in plugin.subscriptions {
+        match subscribe_json(&event, priority, handler) {
+            Ok(id) => record.subscriptions.push(id),
+            Err(e) => warn!("Plugin {name} couldn't subscribe: {e}"),
+        }
+    }
+
+    match is_frozen() {
+        false => {
+            for (voxel, json) in plugin.voxels {
+                match register_voxel(&voxel, json) {
+                    Ok(()) => record.voxels.push(voxel),
+                    Err(e) => warn!("Plugin {name} couldn't register a voxel: {e}"),
+                }
+            }
+        }
+        true => {
+            let voxels = plugin
+                .voxels
+                .into_iter()
+                .map(|(voxel, _)| voxel)
+                .collect::<Vec<_>>();
+            if voxels != record.voxels {
+                warn!("Plugin {name} changed its voxels, restart to apply them");
+            }
+        }
+    }
+    plugin.biomes
+}
+
+// The newest change to the mod's entry file or manifest
+fn last_modified(manifest: &ModManifest) -> Option<SystemTime> {
+    let mut files = vec![manifest.entry.clone()];
+    if let Some(directory) = manifest.entry.parent() {
+        files.push(directory.join(super::manifest::MANIFEST_FILE));
+    }
+    files
+        .iter()
+        .filter_map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+        .max()
+}
+
+// Loads mods again and swaps in what they register, returns the ids of the reloaded mods. Voxels keep
+// their ids and new mods or changed dependencies need a restart. A mod that fails to load keeps its
+// previous version
+pub fn reload_plugins(changed_only: bool) -> Result<Vec<String>> {
+    if !dev_mode() {
+        bail!("Plugins can only be reloaded in dev mode, start with --dev");
+    }
+    #[cfg(feature = "wasm")]
+    let engine = super::wasm::create_engine();
+
+    let mut mods = MODS.lock();
+    let mut reloaded = Vec::new();
+    for record in mods.iter_mut() {
+        let modified = last_modified(&record.manifest);
+        if changed_only && modified == record.modified {
+            continue;
+        }
+        record.modified = modified;
+        let id = record.manifest.id.clone();
+        let result = load_entry(
+            #[cfg(feature = "wasm")]
+            &engine,
+            &record.manifest,
+        );
+        match result {
+            Ok(plugin) => {
+                let biomes = apply_registrations(plugin, record);
+                register_plugin_biomes(&id, &biomes);
+                info!("Reloaded mod {id}");
+                reloaded.push(id);
+            }
+            Err(e) => error!("Failed to reload mod {id}, the previous version stays loaded: {e}"),
+        }
+    }
+    Ok(reloaded)
+}
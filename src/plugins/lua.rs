@@ -0,0 +1,516 @@
+use std::{
+    cell::RefCell,
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use glam::IVec3;
+use mlua::{
+    Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, MultiValue, RegistryKey, Scope, StdLib,
+    Table, Value,
+};
+use parking_lot::Mutex;
+use rand::Rng;
+use tracing::{error, info};
+
+use crate::{
+    events::{JsonHandler, Propagation},
+    localization,
+    persistence::world_stats,
+    random::WorldRng,
+    voxels::{
+        features::{FeaturePlacer, NeighborTerrain},
+        voxel_batch::VoxelBatch,
+        voxel_behavior::VoxelBehavior,
+        voxel_data::VoxelData,
+        voxel_registry::{get_voxel_by_id, get_voxel_by_name, is_frozen},
+        voxel_scene::{VoxelChunk, VoxelScene, CHUNK_SIZE},
+        voxel_shapes::{voxel_shape, VoxelShape},
+    },
+};
+
+use super::LoadedPlugin;
+
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+// Instructions a script may run while loading and per hook, checked every BUDGET_INTERVAL instructions
+const LOAD_BUDGET: i64 = 100_000_000;
+const HOOK_BUDGET: i64 = 1_000_000;
+const BUDGET_INTERVAL: u32 = 1000;
+
+// What a script registers while it loads, kept as app data until the script has run
+#[derive(Default)]
+struct Registrations {
+    behaviors: Vec<(String, RegistryKey)>,
+    features: Vec<(String, RegistryKey)>,
+    voxels: Vec<(String, serde_json::Value)>,
+    biomes: Vec<(String, String)>,
+    subscriptions: Vec<(String, i32, RegistryKey)>,
+}
+
+// A loaded script. Only the table, string, math and utf8 libraries are available, so scripts can't
+// reach files or the process, and calls are serialized since the Lua state isn't thread safe
+pub struct LuaPlugin {
+    pub name: String,
+    lua: Mutex<Lua>,
+    // Instructions left in the current call
+    budget: Arc<AtomicI64>,
+    // Callback tables of the behaviors and functions of the features, behaviors and features index into it
+    callbacks: Vec<RegistryKey>,
+    // Set when a callback errors, callbacks of a failed plugin do nothing
+    failed: AtomicBool,
+}
+
+impl LuaPlugin {
+    fn call(
+        &self,
+        index: usize,
+        call: impl for<'lua> FnOnce(&'lua Lua, Value<'lua>) -> mlua::Result<()>,
+    ) {
+        if self.failed.load(Ordering::Relaxed) {
+            return;
+        }
+        let lua = self.lua.lock();
+        self.budget.store(HOOK_BUDGET, Ordering::Relaxed);
+        let result = lua
+            .registry_value::<Value>(&self.callbacks[index])
+            .and_then(|callback| call(&lua, callback));
+        if let Err(e) = result {
+            error!("Plugin {} failed and was disabled: {e}", self.name);
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Event callbacks get the event as a table and return false to cancel it
+    fn call_event(&self, index: usize, event: &serde_json::Value) -> Propagation {
+        let mut propagation = Propagation::Continue;
+        self.call(index, |lua, callback| {
+            let function = match callback {
+                Value::Function(function) => function,
+                _ => return Ok(()),
+            };
+            if let Value::Boolean(false) = function.call::<_, Value>(lua.to_value(event)?)? {
+                propagation = Propagation::Cancel;
+            }
+            Ok(())
+        });
+        propagation
+    }
+}
+
+// Calls the functions of a behavior's callback table, hooks the table doesn't have do nothing
+struct LuaBehavior {
+    plugin: Arc<LuaPlugin>,
+    index: usize,
+}
+
+impl LuaBehavior {
+    // Hooks are called with the world, the position, the voxel and then the extra arguments
+    fn call_hook(
+        &self,
+        scene: &VoxelScene,
+        hook: &str,
+        position: IVec3,
+        voxel: VoxelData,
+        extra: &[i64],
+    ) {
+        self.plugin.call(self.index, |lua, callbacks| {
+            let function = match callbacks {
+                Value::Table(callbacks) => callbacks.get::<_, Option<Function>>(hook)?,
+                _ => None,
+            };
+            let function = match function {
+                Some(function) => function,
+                None => return Ok(()),
+            };
+            lua.scope(|scope| {
+                let mut args = vec![
+                    Value::Table(world_table(lua, scope, scene)?),
+                    Value::Integer(position.x as i64),
+                    Value::Integer(position.y as i64),
+                    Value::Integer(position.z as i64),
+                    Value::Table(voxel_to_lua(lua, voxel)?),
+                ];
+                args.extend(extra.iter().map(|value| Value::Integer(*value)));
+                function.call::<_, ()>(MultiValue::from_vec(args))
+            })
+        });
+    }
+}
+
+impl VoxelBehavior for LuaBehavior {
+    fn on_random_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        self.call_hook(scene, "on_random_tick", position, voxel, &[]);
+    }
+
+    fn on_scheduled_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        self.call_hook(scene, "on_scheduled_tick", position, voxel, &[]);
+    }
+
+    fn on_neighbor_changed(
+        &self,
+        scene: &VoxelScene,
+        position: IVec3,
+        voxel: VoxelData,
+        neighbor_position: IVec3,
+    ) {
+        let neighbor = neighbor_position.to_array().map(|v| v as i64);
+        self.call_hook(scene, "on_neighbor_changed", position, voxel, &neighbor);
+    }
+
+    fn on_signal_changed(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData, power: u8) {
+        self.call_hook(scene, "on_signal_changed", position, voxel, &[power as i64]);
+    }
+}
+
+// Calls a feature function with a table for the chunk being generated
+struct LuaFeature {
+    plugin: Arc<LuaPlugin>,
+    index: usize,
+}
+
+impl FeaturePlacer for LuaFeature {
+    fn place(&self, chunk: &mut VoxelChunk, neighbors: &NeighborTerrain, rng: &mut WorldRng) {
+        let origin = chunk.scenespace_pos();
+        let chunk = RefCell::new(chunk);
+        let rng = RefCell::new(rng);
+        self.plugin.call(self.index, |lua, callback| {
+            let function = match callback {
+                Value::Function(function) => function,
+                _ => return Ok(()),
+            };
+            lua.scope(|scope| {
+                let table = lua.create_table()?;
+                table.set("x", origin.x)?;
+                table.set("y", origin.y)?;
+                table.set("z", origin.z)?;
+                table.set("size", CHUNK_SIZE)?;
+                table.set(
+                    "get_voxel",
+                    // Positions outside the chunk read the neighbours' base terrain
+                    scope.create_function(|lua, (x, y, z): (i32, i32, i32)| {
+                        let position = IVec3::new(x, y, z);
+                        chunk
+                            .borrow()
+                            .voxel_scenespace_at(&position)
+                            .copied()
+                            .or_else(|| neighbors.voxel_at(&position))
+                            .map(|voxel| voxel_to_lua(lua, voxel))
+                            .transpose()
+                    })?,
+                )?;
+                table.set(
+                    "set_voxel",
+                    scope.create_function(|_, (x, y, z, voxel): (i32, i32, i32, Table)| {
+                        let voxel = voxel_from_lua(&voxel)?;
+                        Ok(chunk
+                            .borrow_mut()
+                            .set_voxel_scenespace(&IVec3::new(x, y, z), voxel))
+                    })?,
+                )?;
+                // The same chunk always gets the same numbers
+                table.set(
+                    "random",
+                    scope.create_function(|_, ()| Ok(rng.borrow_mut().gen::<f64>()))?,
+                )?;
+                function.call::<_, ()>(table)
+            })
+        });
+    }
+}
+
+// Functions to read and change the scene, only valid during the hook they're passed to
+fn world_table<'lua, 'scope>(
+    lua: &'lua Lua,
+    scope: &Scope<'lua, 'scope>,
+    scene: &'scope VoxelScene,
+) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set(
+        "get_voxel",
+        scope.create_function(move |lua, (x, y, z): (i32, i32, i32)| {
+            scene
+                .voxel_at(&IVec3::new(x, y, z))
+                .map(|voxel| voxel_to_lua(lua, voxel))
+                .transpose()
+        })?,
+    )?;
+    table.set(
+        "set_voxel",
+        scope.create_function(move |_, (x, y, z, voxel): (i32, i32, i32, Table)| {
+            let voxel = voxel_from_lua(&voxel)?;
+            Ok(scene.set_voxel(&IVec3::new(x, y, z), voxel).is_some())
+        })?,
+    )?;
+    // Breaking drops the voxel as an item, unlike setting it to air
+    table.set(
+        "break_voxel",
+        scope.create_function(move |_, (x, y, z): (i32, i32, i32)| {
+            Ok(scene.break_voxel(&IVec3::new(x, y, z)).is_some())
+        })?,
+    )?;
+    table.set(
+        "schedule_tick",
+        scope.create_function(move |_, (x, y, z, delay): (i32, i32, i32, u32)| {
+            scene.schedule_tick(IVec3::new(x, y, z), delay);
+            Ok(())
+        })?,
+    )?;
+    // Calls edit(batch) and sets every voxel it staged together once it returns, returns how many
+    // changed. Nothing is set if edit fails
+    table.set(
+        "batch",
+        scope.create_function(move |lua, edit: Function| {
+            let batch = RefCell::new(VoxelBatch::default());
+            lua.scope(|scope| {
+                let table = lua.create_table()?;
+                table.set(
+                    "get_voxel",
+                    scope.create_function(|lua, (x, y, z): (i32, i32, i32)| {
+                        batch
+                            .borrow()
+                            .voxel_at(scene, &IVec3::new(x, y, z))
+                            .map(|voxel| voxel_to_lua(lua, voxel))
+                            .transpose()
+                    })?,
+                )?;
+                table.set(
+                    "set_voxel",
+                    scope.create_function(|_, (x, y, z, voxel): (i32, i32, i32, Table)| {
+                        let voxel = voxel_from_lua(&voxel)?;
+                        batch.borrow_mut().set_voxel(IVec3::new(x, y, z), voxel);
+                        Ok(())
+                    })?,
+                )?;
+                edit.call::<_, ()>(table)
+            })?;
+            Ok(batch.into_inner().commit(scene).len())
+        })?,
+    )?;
+    Ok(table)
+}
+
+// Voxels are tables with an id, a state and a shape, the state and shape can be left out
+fn voxel_to_lua(lua: &Lua, voxel: VoxelData) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("id", voxel.id)?;
+    table.set("state", voxel.state)?;
+    table.set("shape", voxel.shape.data)?;
+    Ok(table)
+}
+
+fn voxel_from_lua(table: &Table) -> mlua::Result<VoxelData> {
+    let id = table.get::<_, u16>("id")?;
+    if get_voxel_by_id(id).is_none() {
+        return Err(mlua::Error::RuntimeError(format!("{id} isn't a voxel id")));
+    }
+    Ok(VoxelData {
+        id,
+        state: table.get::<_, Option<u8>>("state")?.unwrap_or(0),
+        shape: VoxelShape {
+            data: table
+                .get::<_, Option<u8>>("shape")?
+                .unwrap_or(voxel_shape::CUBE.data),
+        },
+    })
+}
+
+fn registration_error() -> mlua::Error {
+    mlua::Error::RuntimeError("Registrations are only accepted while the script loads".to_string())
+}
+
+// The assemblage table scripts register their content with
+fn create_api(lua: &Lua, name: &str) -> mlua::Result<()> {
+    let api = lua.create_table()?;
+    let plugin_name = name.to_string();
+    api.set(
+        "log",
+        lua.create_function(move |_, text: String| {
+            info!("[{plugin_name}] {text}");
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "register_behavior",
+        lua.create_function(|lua, (name, callbacks): (String, Table)| {
+            let key = lua.create_registry_value(callbacks)?;
+            let mut registrations = lua
+                .app_data_mut::<Registrations>()
+                .ok_or_else(registration_error)?;
+            registrations.behaviors.push((name, key));
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "register_feature",
+        lua.create_function(|lua, (name, place): (String, Function)| {
+            let key = lua.create_registry_value(place)?;
+            let mut registrations = lua
+                .app_data_mut::<Registrations>()
+                .ok_or_else(registration_error)?;
+            registrations.features.push((name, key));
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "register_voxel",
+        lua.create_function(|lua, (name, profile): (String, Table)| {
+            let json = lua.from_value::<serde_json::Value>(Value::Table(profile))?;
+            let mut registrations = lua
+                .app_data_mut::<Registrations>()
+                .ok_or_else(registration_error)?;
+            registrations.voxels.push((name, json));
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "register_biome",
+        lua.create_function(|lua, (name, profile): (String, Table)| {
+            let json = lua.from_value::<serde_json::Value>(Value::Table(profile))?;
+            let mut registrations = lua
+                .app_data_mut::<Registrations>()
+                .ok_or_else(registration_error)?;
+            registrations.biomes.push((name, json.to_string()));
+            Ok(())
+        })?,
+    )?;
+    // Callbacks run on the thread emitting the event, a higher priority runs first
+    api.set(
+        "subscribe",
+        lua.create_function(
+            |lua, (event, priority, callback): (String, i32, Function)| {
+                let key = lua.create_registry_value(callback)?;
+                let mut registrations = lua
+                    .app_data_mut::<Registrations>()
+                    .ok_or_else(registration_error)?;
+                registrations.subscriptions.push((event, priority, key));
+                Ok(())
+            },
+        )?,
+    )?;
+    // Ids are assigned once every plugin has loaded, so this returns nil while scripts load
+    api.set(
+        "voxel_id",
+        lua.create_function(|_, name: String| {
+            Ok(match is_frozen() {
+                true => get_voxel_by_name(name).map(|profile| profile.id),
+                false => None,
+            })
+        })?,
+    )?;
+    // The string in the player's locale, or the key when nothing translates it
+    api.set(
+        "tr",
+        lua.create_function(|_, key: String| Ok(localization::tr(&key)))?,
+    )?;
+    // The open world's totals including this session, play time is in seconds
+    api.set(
+        "statistics",
+        lua.create_function(|lua, ()| {
+            let stats = world_stats::current();
+            let table = lua.create_table()?;
+            table.set("voxels_broken", stats.voxels_broken)?;
+            table.set("voxels_placed", stats.voxels_placed)?;
+            table.set("distance_traveled", stats.distance_traveled)?;
+            table.set("chunks_generated", stats.chunks_generated)?;
+            table.set("play_time", stats.play_time)?;
+            Ok(table)
+        })?,
+    )?;
+    lua.globals().set("assemblage", api)
+}
+
+// Runs the script and returns what it registered
+pub fn load_plugin(name: &str, path: &Path) -> Result<LoadedPlugin> {
+    let name = name.to_string();
+    let source = fs::read_to_string(path)?;
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8,
+        LuaOptions::new(),
+    )?;
+    lua.set_memory_limit(MEMORY_LIMIT)?;
+    let budget = Arc::new(AtomicI64::new(LOAD_BUDGET));
+    let hook_budget = Arc::clone(&budget);
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(BUDGET_INTERVAL),
+            ..Default::default()
+        },
+        move |_, _| match hook_budget.fetch_sub(BUDGET_INTERVAL as i64, Ordering::Relaxed) > 0 {
+            true => Ok(()),
+            false => Err(mlua::Error::RuntimeError(
+                "The script ran out of instructions".to_string(),
+            )),
+        },
+    )?;
+    create_api(&lua, &name)?;
+
+    lua.set_app_data(Registrations::default());
+    lua.load(&source).exec()?;
+    let registrations = lua.remove_app_data::<Registrations>().unwrap_or_default();
+
+    let mut callbacks = Vec::new();
+    let mut behaviors = Vec::new();
+    for (behavior, key) in registrations.behaviors {
+        behaviors.push((behavior, callbacks.len()));
+        callbacks.push(key);
+    }
+    let mut features = Vec::new();
+    for (feature, key) in registrations.features {
+        features.push((feature, callbacks.len()));
+        callbacks.push(key);
+    }
+    let mut subscriptions = Vec::new();
+    for (event, priority, key) in registrations.subscriptions {
+        subscriptions.push((event, priority, callbacks.len()));
+        callbacks.push(key);
+    }
+    let plugin = Arc::new(LuaPlugin {
+        name: name.clone(),
+        lua: Mutex::new(lua),
+        budget,
+        callbacks,
+        failed: AtomicBool::new(false),
+    });
+
+    let behaviors = behaviors
+        .into_iter()
+        .map(|(behavior, index)| {
+            let hooks: Arc<dyn VoxelBehavior> = Arc::new(LuaBehavior {
+                plugin: Arc::clone(&plugin),
+                index,
+            });
+            (behavior, hooks)
+        })
+        .collect();
+    let features = features
+        .into_iter()
+        .map(|(feature, index)| {
+            let placer: Arc<dyn FeaturePlacer> = Arc::new(LuaFeature {
+                plugin: Arc::clone(&plugin),
+                index,
+            });
+            (feature, placer)
+        })
+        .collect();
+    let subscriptions = subscriptions
+        .into_iter()
+        .map(|(event, priority, index)| {
+            let events = Arc::clone(&plugin);
+            let handler: JsonHandler = Arc::new(move |event| events.call_event(index, event));
+            (event, priority, handler)
+        })
+        .collect();
+    Ok(LoadedPlugin {
+        name,
+        behaviors,
+        features,
+        subscriptions,
+        voxels: registrations.voxels,
+        biomes: registrations.biomes,
+    })
+}
@@ -0,0 +1,339 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+
+// The manifest inside a mod folder, loose plugin files get a manifest made up from their file name
+pub const MANIFEST_FILE: &str = "mod.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    // Missing parts are zero, so "1.2" is 1.2.0
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parts = text.trim().split('.');
+        let mut next = || -> Result<u32> {
+            match parts.next() {
+                Some(part) => part
+                    .parse()
+                    .map_err(|_| anyhow!("{text} isn't a version like 1.2.3")),
+                None => Ok(0),
+            }
+        };
+        let version = Self {
+            major: next()?,
+            minor: next()?,
+            patch: next()?,
+        };
+        if parts.next().is_some() {
+            bail!("{text} isn't a version like 1.2.3");
+        }
+        Ok(version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+// "*" matches anything, ">=1.2" and "=1.2.3" compare directly and a bare "1.2" accepts 1.2.0 and newer
+// versions with the same major version, since those shouldn't break the mods depending on it
+#[derive(Clone, Debug, PartialEq)]
+pub enum VersionRequirement {
+    Any,
+    AtLeast(Version),
+    Exactly(Version),
+    Compatible(Version),
+}
+
+impl VersionRequirement {
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        Ok(match text {
+            "*" | "" => Self::Any,
+            _ => match (text.strip_prefix(">="), text.strip_prefix('=')) {
+                (Some(version), _) => Self::AtLeast(Version::parse(version)?),
+                (None, Some(version)) => Self::Exactly(Version::parse(version)?),
+                (None, None) => Self::Compatible(Version::parse(text)?),
+            },
+        })
+    }
+
+    pub fn matches(&self, version: Version) -> bool {
+        match self {
+            Self::Any => true,
+            Self::AtLeast(minimum) => version >= *minimum,
+            Self::Exactly(exact) => version == *exact,
+            Self::Compatible(minimum) => version.major == minimum.major && version >= *minimum,
+        }
+    }
+}
+
+impl fmt::Display for VersionRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any => write!(f, "*"),
+            Self::AtLeast(version) => write!(f, ">={version}"),
+            Self::Exactly(version) => write!(f, "={version}"),
+            Self::Compatible(version) => write!(f, "{version}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModManifest {
+    pub id: String,
+    pub version: Version,
+    pub dependencies: BTreeMap<String, VersionRequirement>,
+    // Mods that can't be loaded together with this one
+    pub conflicts: Vec<String>,
+    // The .wasm or .lua file to load
+    pub entry: PathBuf,
+}
+
+impl ModManifest {
+    pub fn from_json(json: &serde_json::Value, directory: &Path) -> Result<Self> {
+        let as_string = |name: &str| -> Result<&str> {
+            json.get(name)
+                .ok_or_else(|| anyhow!("Mod manifest is missing \"{name}\""))?
+                .as_str()
+                .ok_or_else(|| anyhow!("\"{name}\" in the mod manifest is not a string"))
+        };
+        let mut dependencies = BTreeMap::new();
+        if let Some(value) = json.get("Dependencies") {
+            let object = value
+                .as_object()
+                .ok_or_else(|| anyhow!("\"Dependencies\" in the mod manifest is not an object"))?;
+            for (id, requirement) in object {
+                let requirement = requirement
+                    .as_str()
+                    .ok_or_else(|| anyhow!("The version of dependency {id} is not a string"))?;
+                dependencies.insert(id.clone(), VersionRequirement::parse(requirement)?);
+            }
+        }
+        let conflicts =
+            json.get("Conflicts")
+                .and_then(|v| v.as_array())
+                .map_or(Vec::new(), |conflicts| {
+                    conflicts
+                        .iter()
+                        .filter_map(|id| id.as_str().map(str::to_string))
+                        .collect()
+                });
+        Ok(Self {
+            id: as_string("Id")?.to_string(),
+            version: Version::parse(as_string("Version")?)?,
+            dependencies,
+            conflicts,
+            entry: directory.join(as_string("Entry")?),
+        })
+    }
+
+    pub fn load(directory: &Path) -> Result<Self> {
+        let path = directory.join(MANIFEST_FILE);
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_json(&serde_json::from_str(&contents)?, directory)
+    }
+
+    // A single plugin file without a manifest, named after the file and without dependencies
+    pub fn for_file(path: &Path) -> Self {
+        Self {
+            id: path
+                .file_stem()
+                .map_or(String::new(), |stem| stem.to_string_lossy().to_string()),
+            version: Version {
+                major: 0,
+                minor: 0,
+                patch: 0,
+            },
+            dependencies: BTreeMap::new(),
+            conflicts: Vec::new(),
+            entry: path.to_path_buf(),
+        }
+    }
+}
+
+pub struct Resolution {
+    // Mods in the order they have to load, every mod comes after its dependencies
+    pub order: Vec<ModManifest>,
+    // Why the mods left out of the order were rejected
+    pub problems: Vec<String>,
+}
+
+// Drops mods that are duplicated, conflict or miss a dependency, then sorts the rest so dependencies load
+// first. Mods that don't depend on each other load in id order, so the voxel ids come out the same every time
+pub fn resolve_load_order(manifests: Vec<ModManifest>) -> Resolution {
+    let mut problems = Vec::new();
+    let mut mods: BTreeMap<String, ModManifest> = BTreeMap::new();
+    for manifest in manifests {
+        match mods.get(&manifest.id) {
+            Some(existing) => problems.push(format!(
+                "Mod {} is installed twice, {} is used and {} is ignored",
+                manifest.id,
+                existing.entry.display(),
+                manifest.entry.display()
+            )),
+            None => {
+                mods.insert(manifest.id.clone(), manifest);
+            }
+        }
+    }
+
+    // Both sides of a conflict are dropped, there's no telling which one the player wanted
+    let mut rejected = BTreeSet::new();
+    for manifest in mods.values() {
+        for conflict in &manifest.conflicts {
+            if mods.contains_key(conflict) {
+                problems.push(format!("Mod {} conflicts with {conflict}", manifest.id));
+                rejected.insert(manifest.id.clone());
+                rejected.insert(conflict.clone());
+            }
+        }
+    }
+
+    // Rejecting a mod can leave others without a dependency, repeat until nothing changes
+    loop {
+        let mut changed = false;
+        for manifest in mods.values() {
+            if rejected.contains(&manifest.id) {
+                continue;
+            }
+            for (id, requirement) in &manifest.dependencies {
+                let problem = match mods.get(id) {
+                    None => Some(format!(
+                        "Mod {} needs {id} {requirement}, which isn't installed",
+                        manifest.id
+                    )),
+                    Some(_) if rejected.contains(id) => Some(format!(
+                        "Mod {} needs {id}, which can't be loaded",
+                        manifest.id
+                    )),
+                    Some(dependency) if !requirement.matches(dependency.version) => Some(format!(
+                        "Mod {} needs {id} {requirement}, but {} is installed",
+                        manifest.id, dependency.version
+                    )),
+                    Some(_) => None,
+                };
+                if let Some(problem) = problem {
+                    problems.push(problem);
+                    rejected.insert(manifest.id.clone());
+                    changed = true;
+                    break;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    for id in &rejected {
+        mods.remove(id);
+    }
+
+    // Kahn's algorithm, always taking the lowest id that is ready
+    let mut order = Vec::new();
+    let mut loaded = BTreeSet::new();
+    while !mods.is_empty() {
+        let ready = mods
+            .values()
+            .find(|manifest| manifest.dependencies.keys().all(|id| loaded.contains(id)))
+            .map(|manifest| manifest.id.clone());
+        match ready {
+            Some(id) => {
+                loaded.insert(id.clone());
+                order.push(mods.remove(&id).unwrap());
+            }
+            None => {
+                let cycle = mods.keys().cloned().collect::<Vec<_>>().join(", ");
+                problems.push(format!(
+                    "Mods {cycle} can't be loaded, their dependencies form a cycle"
+                ));
+                break;
+            }
+        }
+    }
+    Resolution { order, problems }
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use super::*;
+
+    fn manifest(id: &str, version: &str, dependencies: &[(&str, &str)]) -> ModManifest {
+        ModManifest {
+            id: id.to_string(),
+            version: Version::parse(version).unwrap(),
+            dependencies: dependencies
+                .iter()
+                .map(|(id, requirement)| {
+                    (
+                        id.to_string(),
+                        VersionRequirement::parse(requirement).unwrap(),
+                    )
+                })
+                .collect(),
+            conflicts: Vec::new(),
+            entry: PathBuf::from(format!("{id}.lua")),
+        }
+    }
+
+    fn ids(resolution: &Resolution) -> Vec<&str> {
+        resolution.order.iter().map(|m| m.id.as_str()).collect()
+    }
+
+    #[test]
+    fn dependencies_load_first() {
+        let resolution = resolve_load_order(vec![
+            manifest("a_trees", "1.0", &[("z_core", ">=1.1")]),
+            manifest("b_ores", "1.0", &[]),
+            manifest("z_core", "1.2.0", &[]),
+        ]);
+        assert!(resolution.problems.is_empty());
+        assert_eq!(ids(&resolution), vec!["b_ores", "z_core", "a_trees"]);
+    }
+
+    #[test]
+    fn missing_and_outdated_dependencies_are_reported() {
+        let resolution = resolve_load_order(vec![
+            manifest("trees", "1.0", &[("core", "2.0")]),
+            manifest("core", "1.4", &[]),
+            manifest("fences", "1.0", &[("walls", "*")]),
+            manifest("gates", "1.0", &[("fences", "*")]),
+        ]);
+        assert_eq!(ids(&resolution), vec!["core"]);
+        assert_eq!(resolution.problems.len(), 3);
+    }
+
+    #[test]
+    fn cycles_and_conflicts_are_rejected() {
+        let mut first = manifest("first", "1.0", &[]);
+        first.conflicts.push("second".to_string());
+        let resolution = resolve_load_order(vec![
+            first,
+            manifest("second", "1.0", &[]),
+            manifest("x", "1.0", &[("y", "*")]),
+            manifest("y", "1.0", &[("x", "*")]),
+        ]);
+        assert!(resolution.order.is_empty());
+        assert_eq!(resolution.problems.len(), 2);
+    }
+
+    #[test]
+    fn compatible_requirement_stays_within_major_version() {
+        let requirement = VersionRequirement::parse("1.2").unwrap();
+        assert!(requirement.matches(Version::parse("1.4.1").unwrap()));
+        assert!(!requirement.matches(Version::parse("1.1.9").unwrap()));
+        assert!(!requirement.matches(Version::parse("2.0").unwrap()));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{collections::HashSet, fs, path::Path, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use tracing::{error, info, warn};
+
+use crate::{
+    events::JsonHandler,
+    voxels::{
+        biome_profile::{register_biome, BiomeProfile},
+        features::FeaturePlacer,
+        voxel_behavior::VoxelBehavior,
+        voxel_registry::set_loaded_mods,
+    },
+};
+
+use self::{
+    hot_reload::{apply_registrations, track_mod, ModRecord},
+    manifest::{resolve_load_order, ModManifest, MANIFEST_FILE},
+};
+
+pub mod hot_reload;
+#[cfg(feature = "lua")]
+pub mod lua;
+pub mod manifest;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+// Plugins are loaded from here when the game or server starts
+pub const PLUGIN_DIRECTORY: &str = "./plugins";
+
+// Everything a plugin registered while it loaded, applied by apply_registrations
+pub struct LoadedPlugin {
+    pub name: String,
+    pub behaviors: Vec<(String, Arc<dyn VoxelBehavior>)>,
+    pub features: Vec<(String, Arc<dyn FeaturePlacer>)>,
+    pub subscriptions: Vec<(String, i32, JsonHandler)>,
+    // Only added before the voxel registry loads, since voxel ids can't change afterwards
+    pub voxels: Vec<(String, serde_json::Value)>,
+    // Biome profile json by name, registered once every plugin has loaded
+    pub biomes: Vec<(String, String)>,
+}
+
+// Loads every plugin in the directory in dependency order and returns how many loaded. Has to run before
+// the voxel registry is first used, since the voxels plugins register are added when it loads
+pub fn load_plugins(directory: &Path) -> usize {
+    let resolution = resolve_load_order(find_mods(directory));
+    for problem in &resolution.problems {
+        error!("{problem}");
+    }
+    #[cfg(feature = "wasm")]
+    let engine = wasm::create_engine();
+
+    let mut biomes = Vec::new();
+    let mut loaded_ids = HashSet::new();
+    let mut loaded_mods = Vec::new();
+    for manifest in &resolution.order {
+        // A dependency that was resolved can still fail to load
+        if let Some(missing) = manifest
+            .dependencies
+            .keys()
+            .find(|id| !loaded_ids.contains(*id))
+        {
+            error!(
+                "Skipping mod {}, its dependency {missing} failed to load",
+                manifest.id
+            );
+            continue;
+        }
+        let result = load_entry(
+            #[cfg(feature = "wasm")]
+            &engine,
+            manifest,
+        );
+        match result {
+            Ok(plugin) => {
+                info!("Loaded mod {} {}", manifest.id, manifest.version);
+                let mut record = ModRecord::new(manifest.clone());
+                biomes.push((
+                    manifest.id.clone(),
+                    apply_registrations(plugin, &mut record),
+                ));
+                track_mod(record);
+                loaded_ids.insert(manifest.id.clone());
+                loaded_mods.push((manifest.id.clone(), manifest.version));
+            }
+            Err(e) => error!("Failed to load mod {}: {e}", manifest.id),
+        }
+    }
+
+    // Before the biomes, parsing them loads the voxel registry
+    if let Err(e) = set_loaded_mods(loaded_mods) {
+        error!("{e}");
+    }
+    for (id, biomes) in &biomes {
+        register_plugin_biomes(id, biomes);
+    }
+    loaded_ids.len()
+}
+
+// Loads a mod's entry file without registering anything
+fn load_entry(
+    #[cfg(feature = "wasm")] engine: &wasmtime::Engine,
+    manifest: &ModManifest,
+) -> Result<LoadedPlugin> {
+    let path = &manifest.entry;
+    match path.extension().and_then(|e| e.to_str()) {
+        #[cfg(feature = "wasm")]
+        Some("wasm") => wasm::load_plugin(engine, &manifest.id, path),
+        #[cfg(not(feature = "wasm"))]
+        Some("wasm") => Err(anyhow!("this build has no WASM support")),
+        #[cfg(feature = "lua")]
+        Some("lua") => lua::load_plugin(&manifest.id, path),
+        #[cfg(not(feature = "lua"))]
+        Some("lua") => Err(anyhow!("this build has no Lua support")),
+        _ => Err(anyhow!("{} isn't a .wasm or .lua file", path.display())),
+    }
+}
+
+// Folders with a manifest and loose .wasm and .lua files, which are mods without dependencies
+fn find_mods(directory: &Path) -> Vec<ModManifest> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut mods = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.join(MANIFEST_FILE).is_file() {
+            match ModManifest::load(&path) {
+                Ok(manifest) => mods.push(manifest),
+                Err(e) => error!("Skipping mod {}: {e}", path.display()),
+            }
+            continue;
+        }
+        if let Some("wasm" | "lua") = path.extension().and_then(|e| e.to_str()) {
+            mods.push(ModManifest::for_file(&path));
+        }
+    }
+    mods
+}
+
+// A broken plugin biome is skipped instead of stopping the game
+fn register_plugin_biomes(plugin: &str, biomes: &[(String, String)]) {
+    for (name, json) in biomes {
+        match BiomeProfile::from_json(json.clone()) {
+            Ok(profile) => register_biome(name.clone(), profile),
+            Err(e) => {
+                warn!("Plugin {plugin} registered the biome {name}, which couldn't be parsed: {e}")
+            }
+        }
+    }
+}
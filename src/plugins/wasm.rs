@@ -0,0 +1,530 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, bail, Result};
+use glam::IVec3;
+use parking_lot::Mutex;
+use tracing::{error, info};
+use wasmtime::{
+    Caller, Config, Engine, Extern, Instance, Linker, Module, Store, StoreLimits,
+    StoreLimitsBuilder, Val,
+};
+
+use crate::{
+    events::{JsonHandler, Propagation},
+    voxels::{
+        voxel_batch::VoxelBatch,
+        voxel_behavior::VoxelBehavior,
+        voxel_data::VoxelData,
+        voxel_registry::{get_voxel_by_id, get_voxel_by_name, is_frozen},
+        voxel_scene::VoxelScene,
+        voxel_shapes::VoxelShape,
+    },
+};
+
+use super::LoadedPlugin;
+
+// Modules have to export assemblage_abi_version returning this, see plugin docs.md for the whole ABI
+pub const ABI_VERSION: i32 = 1;
+// Host functions are imported from this module name
+const HOST_MODULE: &str = "assemblage";
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+// Fuel is roughly one unit per instruction, a plugin that runs out is stopped and disabled
+const INIT_FUEL: u64 = 100_000_000;
+const HOOK_FUEL: u64 = 1_000_000;
+// Strings passed to the host are cut off here
+const MAX_STRING_LENGTH: usize = 64 * 1024;
+
+// The scene a behavior hook was called with, only set while that hook runs
+#[derive(Clone, Copy)]
+struct SceneRef(*const VoxelScene);
+
+// The pointer is only dereferenced by host functions the hook calls, on the thread running the hook
+unsafe impl Send for SceneRef {}
+
+struct PluginState {
+    name: String,
+    limits: StoreLimits,
+    // Registrations are only accepted during assemblage_init
+    initializing: bool,
+    behaviors: Vec<String>,
+    voxels: Vec<(String, serde_json::Value)>,
+    biomes: Vec<(String, String)>,
+    subscriptions: Vec<(String, i32)>,
+    scene: Option<SceneRef>,
+    // Opened by begin_batch, voxels set while it's open are staged in it
+    batch: Option<VoxelBatch>,
+}
+
+impl PluginState {
+    fn scene(&self) -> Option<&VoxelScene> {
+        // Safe while the hook that set it runs, it's cleared before the hook returns
+        self.scene.map(|scene| unsafe { &*scene.0 })
+    }
+}
+
+struct WasmInstance {
+    store: Store<PluginState>,
+    instance: Instance,
+}
+
+// A loaded module. Calls are serialized, the module's memory is only touched by one hook at a time
+pub struct WasmPlugin {
+    pub name: String,
+    inner: Mutex<WasmInstance>,
+    // Set when the module traps, hooks of a failed plugin do nothing
+    failed: AtomicBool,
+}
+
+impl WasmPlugin {
+    fn call_hook(&self, scene: &VoxelScene, hook: &str, params: &[Val]) {
+        if self.failed.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        let WasmInstance { store, instance } = &mut *inner;
+        let func = match instance.get_func(&mut *store, hook) {
+            Some(func) => func,
+            None => return,
+        };
+        store.data_mut().scene = Some(SceneRef(scene));
+        let result = refuel(store, HOOK_FUEL).and_then(|_| func.call(&mut *store, params, &mut []));
+        // A batch the hook left open is committed, unless the hook failed
+        if let Some(batch) = store.data_mut().batch.take() {
+            if result.is_ok() {
+                batch.commit(scene);
+            }
+        }
+        store.data_mut().scene = None;
+        if let Err(e) = result {
+            error!(
+                "Plugin {} failed in {hook} and was disabled: {e}",
+                self.name
+            );
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Copies the event json into memory from assemblage_alloc and calls assemblage_on_event with it,
+    // a non zero result cancels the event
+    fn call_event(&self, handle: i32, event: &serde_json::Value) -> Propagation {
+        if self.failed.load(Ordering::Relaxed) {
+            return Propagation::Continue;
+        }
+        let json = event.to_string();
+        let mut inner = self.inner.lock();
+        let WasmInstance { store, instance } = &mut *inner;
+        let mut deliver = || -> Result<i32> {
+            refuel(store, HOOK_FUEL)?;
+            let alloc = instance.get_typed_func::<i32, i32, _>(&mut *store, "assemblage_alloc")?;
+            let on_event = instance
+                .get_typed_func::<(i32, i32, i32), i32, _>(&mut *store, "assemblage_on_event")?;
+            let memory = instance
+                .get_memory(&mut *store, "memory")
+                .ok_or_else(|| anyhow!("The module doesn't export its memory"))?;
+            let ptr = alloc.call(&mut *store, json.len() as i32)?;
+            memory.write(&mut *store, ptr as u32 as usize, json.as_bytes())?;
+            on_event.call(&mut *store, (handle, ptr, json.len() as i32))
+        };
+        match deliver() {
+            Ok(0) => Propagation::Continue,
+            Ok(_) => Propagation::Cancel,
+            Err(e) => {
+                error!(
+                    "Plugin {} failed in assemblage_on_event and was disabled: {e}",
+                    self.name
+                );
+                self.failed.store(true, Ordering::Relaxed);
+                Propagation::Continue
+            }
+        }
+    }
+}
+
+// Forwards a voxel behavior's hooks to the plugin that registered it
+struct WasmBehavior {
+    plugin: Arc<WasmPlugin>,
+    handle: i32,
+}
+
+impl WasmBehavior {
+    fn params(&self, position: IVec3, voxel: VoxelData) -> Vec<Val> {
+        vec![
+            Val::I32(self.handle),
+            Val::I32(position.x),
+            Val::I32(position.y),
+            Val::I32(position.z),
+            Val::I64(pack_voxel(voxel)),
+        ]
+    }
+}
+
+impl VoxelBehavior for WasmBehavior {
+    fn on_random_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        let params = self.params(position, voxel);
+        self.plugin
+            .call_hook(scene, "assemblage_on_random_tick", &params);
+    }
+
+    fn on_scheduled_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        let params = self.params(position, voxel);
+        self.plugin
+            .call_hook(scene, "assemblage_on_scheduled_tick", &params);
+    }
+
+    fn on_neighbor_changed(
+        &self,
+        scene: &VoxelScene,
+        position: IVec3,
+        voxel: VoxelData,
+        neighbor_position: IVec3,
+    ) {
+        let mut params = self.params(position, voxel);
+        params.extend([
+            Val::I32(neighbor_position.x),
+            Val::I32(neighbor_position.y),
+            Val::I32(neighbor_position.z),
+        ]);
+        self.plugin
+            .call_hook(scene, "assemblage_on_neighbor_changed", &params);
+    }
+
+    fn on_signal_changed(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData, power: u8) {
+        let mut params = self.params(position, voxel);
+        params.push(Val::I32(power as i32));
+        self.plugin
+            .call_hook(scene, "assemblage_on_signal_changed", &params);
+    }
+}
+
+// Voxels cross the ABI as an i64 holding the id in the low 16 bits, then the state and the shape
+pub fn pack_voxel(voxel: VoxelData) -> i64 {
+    voxel.id as i64 | (voxel.state as i64) << 16 | (voxel.shape.data as i64) << 24
+}
+
+pub fn unpack_voxel(packed: i64) -> VoxelData {
+    VoxelData {
+        id: packed as u16,
+        state: (packed >> 16) as u8,
+        shape: VoxelShape {
+            data: (packed >> 24) as u8,
+        },
+    }
+}
+
+pub fn create_engine() -> Engine {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    Engine::new(&config).unwrap()
+}
+
+// Instantiates the module, runs its init and returns what it registered
+pub fn load_plugin(engine: &Engine, name: &str, path: &Path) -> Result<LoadedPlugin> {
+    let name = name.to_string();
+    let module = Module::from_file(engine, path)?;
+    let mut linker = Linker::new(engine);
+    add_host_functions(&mut linker)?;
+
+    let mut store = Store::new(
+        engine,
+        PluginState {
+            name: name.clone(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MEMORY_LIMIT)
+                .instances(1)
+                .build(),
+            initializing: true,
+            behaviors: Vec::new(),
+            voxels: Vec::new(),
+            biomes: Vec::new(),
+            subscriptions: Vec::new(),
+            scene: None,
+            batch: None,
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    refuel(&mut store, INIT_FUEL)?;
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let version = instance
+        .get_typed_func::<(), i32, _>(&mut store, "assemblage_abi_version")
+        .map_err(|_| anyhow!("The module doesn't export assemblage_abi_version"))?
+        .call(&mut store, ())?;
+    if version != ABI_VERSION {
+        bail!("The plugin uses ABI version {version} but the engine uses {ABI_VERSION}");
+    }
+    if let Ok(init) = instance.get_typed_func::<(), (), _>(&mut store, "assemblage_init") {
+        init.call(&mut store, ())?;
+    }
+
+    let state = store.data_mut();
+    state.initializing = false;
+    let behaviors = std::mem::take(&mut state.behaviors);
+    let voxels = std::mem::take(&mut state.voxels);
+    let biomes = std::mem::take(&mut state.biomes);
+    let subscriptions = std::mem::take(&mut state.subscriptions);
+
+    let plugin = Arc::new(WasmPlugin {
+        name: name.clone(),
+        inner: Mutex::new(WasmInstance { store, instance }),
+        failed: AtomicBool::new(false),
+    });
+    let behaviors = behaviors
+        .into_iter()
+        .enumerate()
+        .map(|(handle, behavior)| {
+            let hooks: Arc<dyn VoxelBehavior> = Arc::new(WasmBehavior {
+                plugin: Arc::clone(&plugin),
+                handle: handle as i32,
+            });
+            (behavior, hooks)
+        })
+        .collect();
+    let subscriptions = subscriptions
+        .into_iter()
+        .enumerate()
+        .map(|(handle, (event, priority))| {
+            let events = Arc::clone(&plugin);
+            let handler: JsonHandler =
+                Arc::new(move |event| events.call_event(handle as i32, event));
+            (event, priority, handler)
+        })
+        .collect();
+    Ok(LoadedPlugin {
+        name,
+        behaviors,
+        features: Vec::new(),
+        subscriptions,
+        voxels,
+        biomes,
+    })
+}
+
+fn refuel(store: &mut Store<PluginState>, fuel: u64) -> Result<()> {
+    let remaining = store.consume_fuel(0)?;
+    store.add_fuel(fuel.saturating_sub(remaining))?;
+    Ok(())
+}
+
+fn read_string(caller: &mut Caller<'_, PluginState>, ptr: i32, len: i32) -> Option<String> {
+    if len < 0 || len as usize > MAX_STRING_LENGTH {
+        return None;
+    }
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let mut bytes = vec![0; len as usize];
+    memory.read(&caller, ptr as u32 as usize, &mut bytes).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+// Functions return a negative number when the call was invalid, the plugin keeps running
+fn add_host_functions(linker: &mut Linker<PluginState>) -> Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "log",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| {
+            if let Some(text) = read_string(&mut caller, ptr, len) {
+                info!("[{}] {text}", caller.data().name);
+            }
+        },
+    )?;
+
+    // Returns the handle the behavior's hooks are called with
+    linker.func_wrap(
+        HOST_MODULE,
+        "register_behavior",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            let name = match read_string(&mut caller, ptr, len) {
+                Some(name) if caller.data().initializing => name,
+                _ => return -1,
+            };
+            let behaviors = &mut caller.data_mut().behaviors;
+            behaviors.push(name);
+            behaviors.len() as i32 - 1
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "register_voxel",
+        |mut caller: Caller<'_, PluginState>,
+         name_ptr: i32,
+         name_len: i32,
+         json_ptr: i32,
+         json_len: i32|
+         -> i32 {
+            let name = read_string(&mut caller, name_ptr, name_len);
+            let json = read_string(&mut caller, json_ptr, json_len)
+                .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+                .filter(|json| json.is_object());
+            match (name, json) {
+                (Some(name), Some(json)) if caller.data().initializing => {
+                    caller.data_mut().voxels.push((name, json));
+                    0
+                }
+                _ => -1,
+            }
+        },
+    )?;
+
+    // The json is a biome profile like the files in the resources folder
+    linker.func_wrap(
+        HOST_MODULE,
+        "register_biome",
+        |mut caller: Caller<'_, PluginState>,
+         name_ptr: i32,
+         name_len: i32,
+         json_ptr: i32,
+         json_len: i32|
+         -> i32 {
+            let name = read_string(&mut caller, name_ptr, name_len);
+            let json = read_string(&mut caller, json_ptr, json_len);
+            match (name, json) {
+                (Some(name), Some(json)) if caller.data().initializing => {
+                    caller.data_mut().biomes.push((name, json));
+                    0
+                }
+                _ => -1,
+            }
+        },
+    )?;
+
+    // Returns the handle assemblage_on_event is called with for this event
+    linker.func_wrap(
+        HOST_MODULE,
+        "subscribe",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32, priority: i32| -> i32 {
+            let event = match read_string(&mut caller, ptr, len) {
+                Some(event) if caller.data().initializing => event,
+                _ => return -1,
+            };
+            let subscriptions = &mut caller.data_mut().subscriptions;
+            subscriptions.push((event, priority));
+            subscriptions.len() as i32 - 1
+        },
+    )?;
+
+    // Ids are only known once the registry has loaded, so this fails during init
+    linker.func_wrap(
+        HOST_MODULE,
+        "voxel_id",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            match read_string(&mut caller, ptr, len) {
+                Some(name) if is_frozen() => {
+                    get_voxel_by_name(name).map_or(-1, |profile| profile.id as i32)
+                }
+                _ => -1,
+            }
+        },
+    )?;
+
+    // The scene is only available inside behavior hooks
+    linker.func_wrap(
+        HOST_MODULE,
+        "get_voxel",
+        |caller: Caller<'_, PluginState>, x: i32, y: i32, z: i32| -> i64 {
+            let state = caller.data();
+            let position = IVec3::new(x, y, z);
+            state
+                .scene()
+                .and_then(|scene| match &state.batch {
+                    Some(batch) => batch.voxel_at(scene, &position),
+                    None => scene.voxel_at(&position),
+                })
+                .map_or(-1, pack_voxel)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "set_voxel",
+        |mut caller: Caller<'_, PluginState>, x: i32, y: i32, z: i32, voxel: i64| -> i32 {
+            let voxel = unpack_voxel(voxel);
+            if get_voxel_by_id(voxel.id).is_none() {
+                return -1;
+            }
+            let position = IVec3::new(x, y, z);
+            // Staged voxels aren't checked against the loaded chunks until the batch is committed
+            if let Some(batch) = &mut caller.data_mut().batch {
+                batch.set_voxel(position, voxel);
+                return 0;
+            }
+            let set = caller
+                .data()
+                .scene()
+                .and_then(|scene| scene.set_voxel(&position, voxel));
+            match set {
+                Some(_) => 0,
+                None => -1,
+            }
+        },
+    )?;
+
+    // Voxels set between begin_batch and commit_batch are set together, remeshing each chunk once
+    linker.func_wrap(
+        HOST_MODULE,
+        "begin_batch",
+        |mut caller: Caller<'_, PluginState>| -> i32 {
+            let state = caller.data_mut();
+            if state.scene.is_none() || state.batch.is_some() {
+                return -1;
+            }
+            state.batch = Some(VoxelBatch::default());
+            0
+        },
+    )?;
+
+    // Returns the number of voxels set
+    linker.func_wrap(
+        HOST_MODULE,
+        "commit_batch",
+        |mut caller: Caller<'_, PluginState>| -> i32 {
+            let batch = match caller.data_mut().batch.take() {
+                Some(batch) => batch,
+                None => return -1,
+            };
+            match caller.data().scene() {
+                Some(scene) => batch.commit(scene).len() as i32,
+                None => -1,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "schedule_tick",
+        |caller: Caller<'_, PluginState>, x: i32, y: i32, z: i32, delay: i32| -> i32 {
+            match caller.data().scene() {
+                Some(scene) if delay >= 0 => {
+                    scene.schedule_tick(IVec3::new(x, y, z), delay as u32);
+                    0
+                }
+                _ => -1,
+            }
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod wasm_tests {
+    use super::*;
+
+    #[test]
+    fn voxels_round_trip_through_the_abi() {
+        let voxel = VoxelData {
+            id: 513,
+            state: 7,
+            shape: VoxelShape { data: 200 },
+        };
+        let unpacked = unpack_voxel(pack_voxel(voxel));
+        assert_eq!(
+            (unpacked.id, unpacked.state, unpacked.shape.data),
+            (513, 7, 200)
+        );
+    }
+}
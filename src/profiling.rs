@@ -0,0 +1,61 @@
+#[cfg(feature = "profiling")]
+use parking_lot::Mutex;
+#[cfg(feature = "profiling")]
+use tracing::{info, warn};
+
+// Connect puffin_viewer here to see the scopes of the running game or server
+#[cfg(feature = "profiling")]
+pub const PROFILER_ADDRESS: &str = "0.0.0.0:8585";
+
+// Marks the rest of the block as a scope in the profiler, an optional second argument is shown
+// next to the name. Compiles to nothing without the profiling feature
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        #[cfg(feature = "profiling")]
+        puffin::profile_scope!($name);
+    };
+    ($name:expr, $data:expr) => {
+        #[cfg(feature = "profiling")]
+        puffin::profile_scope!($name, $data);
+    };
+}
+
+// What ends a profiler frame, the client's frames when there is a window and server ticks otherwise
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameSource {
+    Render,
+    ServerTick,
+}
+
+#[cfg(feature = "profiling")]
+lazy_static! {
+    static ref PROFILER: Mutex<Option<(FrameSource, puffin_http::Server)>> = Mutex::new(None);
+}
+
+// Starts recording scopes and serving them to viewers
+#[cfg(feature = "profiling")]
+pub fn start(frame_source: FrameSource) {
+    match puffin_http::Server::new(PROFILER_ADDRESS) {
+        Ok(server) => {
+            puffin::set_scopes_on(true);
+            *PROFILER.lock() = Some((frame_source, server));
+            info!("Profiler listening on {PROFILER_ADDRESS}");
+        }
+        Err(e) => warn!("Couldn't start the profiler: {e}"),
+    }
+}
+
+#[cfg(not(feature = "profiling"))]
+pub fn start(_frame_source: FrameSource) {}
+
+// Ends the profiler frame if the source is the one frames were started with
+#[cfg(feature = "profiling")]
+pub fn new_frame(source: FrameSource) {
+    if matches!(&*PROFILER.lock(), Some((frame_source, _)) if *frame_source == source) {
+        puffin::GlobalProfiler::lock().new_frame();
+    }
+}
+
+#[cfg(not(feature = "profiling"))]
+pub fn new_frame(_source: FrameSource) {}
@@ -0,0 +1,204 @@
+use std::{
+    io::{self, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+lazy_static! {
+    // Tasks that haven't finished, in the order they started
+    static ref ACTIVE: Mutex<Vec<Progress>> = Mutex::new(Vec::new());
+}
+
+// How far a long task like pregeneration, an import or a backup has come. The task updates it from
+// whatever thread does the work, the CLI and the UI read snapshots of every active task
+#[derive(Clone)]
+pub struct Progress {
+    state: Arc<Mutex<ProgressState>>,
+}
+
+struct ProgressState {
+    task: String,
+    stage: String,
+    done: u64,
+    total: u64,
+    started: Instant,
+    stage_started: Instant,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgressSnapshot {
+    pub task: String,
+    // What the task is doing right now, each stage counts up to its own total
+    pub stage: String,
+    pub done: u64,
+    pub total: u64,
+    pub elapsed: Duration,
+    pub stage_elapsed: Duration,
+}
+
+impl ProgressSnapshot {
+    // From 0 to 1, a stage without any work counts as done
+    pub fn fraction(&self) -> f32 {
+        match self.total {
+            0 => 1.0,
+            total => (self.done.min(total) as f64 / total as f64) as f32,
+        }
+    }
+
+    pub fn percent(&self) -> f32 {
+        self.fraction() * 100.0
+    }
+
+    // Items a second in the current stage
+    pub fn rate(&self) -> f64 {
+        self.done as f64 / self.stage_elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    // Time left in the current stage at the rate so far, None until something is done
+    pub fn eta(&self) -> Option<Duration> {
+        if self.done == 0 {
+            return None;
+        }
+        let left = self.total.saturating_sub(self.done) as f64;
+        Some(Duration::from_secs_f64(left / self.rate()))
+    }
+}
+
+impl Progress {
+    // Shows up in active until it's finished or every clone is dropped
+    pub fn start(task: &str) -> Self {
+        let now = Instant::now();
+        let progress = Self {
+            state: Arc::new(Mutex::new(ProgressState {
+                task: task.to_string(),
+                stage: String::new(),
+                done: 0,
+                total: 0,
+                started: now,
+                stage_started: now,
+            })),
+        };
+        ACTIVE.lock().push(progress.clone());
+        progress
+    }
+
+    // Starts counting again from 0 towards the total
+    pub fn stage(&self, stage: &str, total: u64) {
+        let mut state = self.state.lock();
+        state.stage = stage.to_string();
+        state.done = 0;
+        state.total = total;
+        state.stage_started = Instant::now();
+    }
+
+    pub fn set(&self, done: u64) {
+        self.state.lock().done = done;
+    }
+
+    pub fn advance(&self, amount: u64) {
+        self.state.lock().done += amount;
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let state = self.state.lock();
+        ProgressSnapshot {
+            task: state.task.clone(),
+            stage: state.stage.clone(),
+            done: state.done,
+            total: state.total,
+            elapsed: state.started.elapsed(),
+            stage_elapsed: state.stage_started.elapsed(),
+        }
+    }
+
+    pub fn finish(self) {
+        ACTIVE
+            .lock()
+            .retain(|active| !Arc::ptr_eq(&active.state, &self.state));
+    }
+}
+
+// Snapshots of the unfinished tasks, for a loading screen. A task whose progress was dropped
+// without finishing is removed here
+pub fn active() -> Vec<ProgressSnapshot> {
+    let mut active = ACTIVE.lock();
+    active.retain(|progress| Arc::strong_count(&progress.state) > 1);
+    active.iter().map(Progress::snapshot).collect()
+}
+
+const BAR_WIDTH: usize = 40;
+const BAR_INTERVAL: Duration = Duration::from_millis(100);
+
+// Redraws a single line on stderr, which stays apart from the log output
+pub struct ProgressBar {
+    unit: &'static str,
+    drawn: Option<Instant>,
+}
+
+impl ProgressBar {
+    // The unit names what's counted, like chunks
+    pub fn new(unit: &'static str) -> Self {
+        Self { unit, drawn: None }
+    }
+
+    // Draws at most every BAR_INTERVAL, except for the last update of a stage
+    pub fn update(&mut self, progress: &ProgressSnapshot) {
+        let finished = progress.done >= progress.total;
+        if self
+            .drawn
+            .map_or(false, |drawn| drawn.elapsed() < BAR_INTERVAL)
+            && !finished
+        {
+            return;
+        }
+        self.drawn = Some(Instant::now());
+        let filled = (progress.fraction() * BAR_WIDTH as f32) as usize;
+        let eta = match progress.eta() {
+            Some(eta) => format!(", {}s left", eta.as_secs()),
+            None => String::new(),
+        };
+        let mut stderr = io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r{}: [{}{}] {}/{} {}, {:.0}/s{eta}",
+            progress.stage,
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            progress.done,
+            progress.total,
+            self.unit,
+            progress.rate()
+        );
+        let _ = stderr.flush();
+    }
+
+    pub fn finish(&self) {
+        if self.drawn.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use super::*;
+
+    #[test]
+    fn stages_count_towards_their_total() {
+        let progress = Progress::start("backup");
+        progress.stage("packing", 4);
+        progress.advance(1);
+        let snapshot = progress.snapshot();
+        assert_eq!(snapshot.percent(), 25.0);
+        assert!(snapshot.eta().is_some());
+        assert!(active().iter().any(|active| active.task == "backup"));
+
+        progress.stage("compressing", 0);
+        assert_eq!(progress.snapshot().fraction(), 1.0);
+        assert_eq!(progress.snapshot().eta(), None);
+        progress.finish();
+        assert!(!active().iter().any(|active| active.task == "backup"));
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use glam::IVec3;
+use rand::{impls, Error, RngCore};
+
+use crate::voxels::voxel_scene::VoxelScene;
+
+// Random decisions in generation and ticking draw from seeds derived down a tree: the world seed,
+// the dimension, the chunk and then the feature or system deciding. A seed only depends on the keys
+// leading to it, so nothing changes with the order chunks, features or voxels are evaluated in
+
+// The only dimension for now
+pub const OVERWORLD: &str = "overworld";
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+static WORLD_SEED: AtomicU64 = AtomicU64::new(0);
+// The world tick the simulation is on, ticking decisions are keyed by it
+static TICK: AtomicU64 = AtomicU64::new(0);
+
+// The SplitMix64 output function, spreads every input bit over the whole result
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(GOLDEN_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// FNV-1a, std's hasher isn't guaranteed to stay the same between releases
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Seed(pub u64);
+
+impl Seed {
+    pub fn derive(self, key: u64) -> Self {
+        Self(mix(self.0 ^ mix(key)))
+    }
+
+    pub fn derive_name(self, name: &str) -> Self {
+        self.derive(hash_name(name))
+    }
+
+    pub fn dimension(self, name: &str) -> Self {
+        self.derive_name(name)
+    }
+
+    pub fn chunk(self, position: IVec3) -> Self {
+        self.derive(position.x as u32 as u64)
+            .derive(position.y as u32 as u64)
+            .derive(position.z as u32 as u64)
+    }
+
+    // A voxel position, for decisions about a single voxel
+    pub fn position(self, position: IVec3) -> Self {
+        self.chunk(position)
+    }
+
+    pub fn feature(self, name: &str) -> Self {
+        self.derive_name(name)
+    }
+
+    pub fn tick(self, tick: u64) -> Self {
+        self.derive(tick)
+    }
+
+    pub fn rng(self) -> WorldRng {
+        WorldRng { state: self.0 }
+    }
+}
+
+// SplitMix64. Used instead of StdRng, whose sequence may change with a rand update and would change
+// worlds along with it
+#[derive(Clone, Debug)]
+pub struct WorldRng {
+    state: u64,
+}
+
+impl RngCore for WorldRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let value = mix(self.state);
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        value
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// Set when a world is opened, before anything is generated
+pub fn set_world_seed(seed: u64) {
+    WORLD_SEED.store(seed, Ordering::Relaxed);
+}
+
+pub fn world_seed() -> Seed {
+    Seed(WORLD_SEED.load(Ordering::Relaxed))
+}
+
+pub fn overworld() -> Seed {
+    world_seed().dimension(OVERWORLD)
+}
+
+// Called by the simulation at the start of every tick
+pub fn set_tick(tick: u64) {
+    TICK.store(tick, Ordering::Relaxed);
+}
+
+pub fn tick() -> u64 {
+    TICK.load(Ordering::Relaxed)
+}
+
+// For decisions made once per tick, such as the weather changing
+pub fn tick_rng(name: &str) -> WorldRng {
+    overworld()
+        .tick(TICK.load(Ordering::Relaxed))
+        .derive_name(name)
+        .rng()
+}
+
+// For decisions made in a chunk this tick, such as picking the voxels to random tick
+pub fn chunk_rng(chunk: IVec3, name: &str) -> WorldRng {
+    overworld()
+        .chunk(chunk)
+        .tick(TICK.load(Ordering::Relaxed))
+        .derive_name(name)
+        .rng()
+}
+
+// For decisions about a voxel this tick, such as a crop growing. The name keeps two behaviors
+// deciding at the same voxel from drawing the same numbers
+pub fn voxel_rng(position: IVec3, name: &str) -> WorldRng {
+    overworld()
+        .chunk(VoxelScene::chunk_at(&position))
+        .position(position)
+        .tick(TICK.load(Ordering::Relaxed))
+        .derive_name(name)
+        .rng()
+}
+
+#[cfg(test)]
+mod random_tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_splitmix64_reference() {
+        let mut rng = Seed(0).rng();
+        assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(rng.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+    }
+
+    #[test]
+    fn seeds_only_depend_on_their_keys() {
+        let chunk = Seed(42).dimension(OVERWORLD).chunk(IVec3::new(3, -1, 7));
+        // Deriving other branches first doesn't change anything
+        let _ = Seed(42)
+            .dimension(OVERWORLD)
+            .chunk(IVec3::ZERO)
+            .feature("ores");
+        assert_eq!(
+            chunk.feature("trees"),
+            Seed(42)
+                .dimension(OVERWORLD)
+                .chunk(IVec3::new(3, -1, 7))
+                .feature("trees")
+        );
+        assert_ne!(chunk.feature("trees"), chunk.feature("ores"));
+        assert_ne!(
+            chunk,
+            Seed(43).dimension(OVERWORLD).chunk(IVec3::new(3, -1, 7))
+        );
+    }
+}
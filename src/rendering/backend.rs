@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use glam::IVec3;
+
+use crate::asset_types::mesh::Mesh;
+
+// Draws the game. The wgpu backend renders the world to the window, the null backend only keeps
+// track of what it was given, so the game loop and chunk meshing run in CI and on servers without
+// a GPU. The game only talks to the backend through this trait, either one can be picked at start
+pub trait RenderBackend: Send {
+    fn name(&self) -> &'static str;
+
+    // Replaces the chunk's mesh if it already has one
+    fn upload_chunk_mesh(&mut self, position: IVec3, mesh: Mesh);
+
+    fn resize(&mut self, width: u32, height: u32);
+
+    // Errors mean the backend can't draw anymore and the game should stop, problems the next frame
+    // can recover from are handled by the backend
+    fn render_frame(&mut self) -> Result<()>;
+}
+
+// Counts what a real backend would have drawn
+#[derive(Default)]
+pub struct NullBackend {
+    // The vertex and index count of each chunk's latest mesh
+    chunks: HashMap<IVec3, (usize, usize)>,
+    uploads: usize,
+    frames: u64,
+    size: (u32, u32),
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.chunks.values().map(|(vertices, _)| vertices).sum()
+    }
+
+    // Including the meshes that replaced an earlier one
+    pub fn uploads(&self) -> usize {
+        self.uploads
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+impl RenderBackend for NullBackend {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn upload_chunk_mesh(&mut self, position: IVec3, mesh: Mesh) {
+        self.chunks.insert(
+            position,
+            (mesh.get_vertices().len(), mesh.get_indices().len()),
+        );
+        self.uploads += 1;
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.size = (width, height);
+    }
+
+    fn render_frame(&mut self) -> Result<()> {
+        self.frames += 1;
+        Ok(())
+    }
+}
@@ -32,6 +32,13 @@ impl Camera {
         self.uniform.transform = self.build_transform_matrix().to_cols_array_2d();
     }
 
+    pub fn set_aspect(&mut self, aspect: f32) {
+        if aspect != self.aspect {
+            self.aspect = aspect;
+            self.update_uniform();
+        }
+    }
+
     pub fn new(state: &State) -> Camera {
         let uniform = CameraUniform::new();
 
@@ -0,0 +1,127 @@
+use glam::Vec3;
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer};
+
+// Moving light sources can't be stored in the voxel light grid, so the closest ones
+// are sent to the shaders every frame and added to the lighting per vertex. The sun shares
+// the uniform since it changes every frame too
+pub const MAX_DYNAMIC_LIGHTS: usize = 16;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, PartialEq)]
+pub struct PointLightRaw {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl PointLightRaw {
+    pub fn new(position: Vec3, color: Vec3, intensity: f32, radius: f32) -> Self {
+        Self {
+            position: position.to_array(),
+            radius,
+            color: color.to_array(),
+            intensity,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, PartialEq)]
+pub struct LightsUniform {
+    lights: [PointLightRaw; MAX_DYNAMIC_LIGHTS],
+    count: u32,
+    _padding: [u32; 3],
+    sun_direction: [f32; 3],
+    sun_intensity: f32,
+    ambient: f32,
+    _sun_padding: [u32; 3],
+    fog_color: [f32; 3],
+    fog_density: f32,
+    eye: [f32; 3],
+    light_heatmap: u32,
+}
+
+impl LightsUniform {
+    pub fn new() -> Self {
+        Self {
+            sun_direction: Vec3::new(-0.5, 0.6, -0.3).normalize().to_array(),
+            sun_intensity: 1.0,
+            ambient: 0.3,
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+}
+
+pub struct DynamicLights {
+    pub uniform: LightsUniform,
+    pub buffer: Buffer,
+    pub bind_group: BindGroup,
+}
+
+impl DynamicLights {
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("lights_bind_group_layout"),
+        })
+    }
+
+    pub fn new(device: &wgpu::Device, layout: &BindGroupLayout) -> Self {
+        let uniform = LightsUniform::new();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lights Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("lights_bind_group"),
+        });
+        Self {
+            uniform,
+            buffer,
+            bind_group,
+        }
+    }
+
+    // Sent to the GPU together with the next set_lights
+    pub fn set_sun(&mut self, direction: Vec3, intensity: f32, ambient: f32) {
+        self.uniform.sun_direction = direction.to_array();
+        self.uniform.sun_intensity = intensity;
+        self.uniform.ambient = ambient;
+    }
+
+    // Fog thickens with the distance from the eye, a density of 0 turns it off
+    pub fn set_fog(&mut self, color: Vec3, density: f32, eye: Vec3) {
+        self.uniform.fog_color = color.to_array();
+        self.uniform.fog_density = density;
+        self.uniform.eye = eye.to_array();
+    }
+
+    // Terrain is drawn in the color of how brightly it's lit rather than its own
+    pub fn set_light_heatmap(&mut self, enabled: bool) {
+        self.uniform.light_heatmap = enabled as u32;
+    }
+
+    // Lights past MAX_DYNAMIC_LIGHTS are ignored, callers should pass the most important ones first
+    pub fn set_lights(&mut self, queue: &wgpu::Queue, lights: &[PointLightRaw]) {
+        let count = lights.len().min(MAX_DYNAMIC_LIGHTS);
+        self.uniform.lights[..count].copy_from_slice(&lights[..count]);
+        self.uniform.count = count as u32;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use glam::Mat4;
+use parking_lot::RwLock;
+use wgpu::{util::DeviceExt, BufferUsages, RenderPipeline};
+
+use crate::{
+    asset_types::{asset::Asset, mesh::Mesh},
+    state::State,
+};
+
+use super::{
+    material::{create_pipeline_with_buffers, Material},
+    vertex::Vertex,
+};
+
+// Moving entities are drawn with one instanced draw call per mesh and material pair, rather than being baked into the pass buffers
+lazy_static! {
+    pub static ref INSTANCED_BATCHES: DashMap<(u64, u64), Arc<RwLock<InstancedBatch>>> =
+        DashMap::default();
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceRaw {
+    pub model: [[f32; 4]; 4],
+}
+
+impl InstanceRaw {
+    pub fn new(transform: &Mat4) -> Self {
+        Self {
+            model: transform.to_cols_array_2d(),
+        }
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                // Model matrix columns, locations 0 to 3 are used by the vertex
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+pub struct InstancedBatch {
+    pub material: Arc<RwLock<dyn Material>>,
+    pub render_layer: String,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub index_count: u32,
+    pub instance_buffer: wgpu::Buffer,
+    pub instance_count: u32,
+    instance_capacity: usize,
+    pipeline: Arc<RenderPipeline>,
+}
+
+impl InstancedBatch {
+    pub fn new(
+        state: &State,
+        mesh: &Mesh,
+        material: Arc<RwLock<dyn Material>>,
+        render_layer: String,
+    ) -> Self {
+        let vertex_buffer = state
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Instanced Vertex Buffer"),
+                contents: bytemuck::cast_slice(mesh.get_vertices()),
+                usage: BufferUsages::VERTEX,
+            });
+        let index_buffer = state
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Instanced Index Buffer"),
+                contents: bytemuck::cast_slice(mesh.get_indices()),
+                usage: BufferUsages::INDEX,
+            });
+
+        let material_lock = material.read();
+        let shader = Arc::new(
+            state
+                .device
+                .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                    label: Some("Instanced Shader"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        include_str!("../shaders/instanced_shader.wgsl").into(),
+                    ),
+                }),
+        );
+        let pipeline = Arc::new(create_pipeline_with_buffers(
+            state,
+            material_lock.get_texture_bind_group_layout(state),
+            shader,
+            &[Vertex::desc(), InstanceRaw::desc()],
+        ));
+        drop(material_lock);
+
+        let instance_capacity = 64;
+        Self {
+            material,
+            render_layer,
+            vertex_buffer,
+            index_buffer,
+            index_count: mesh.index_count as u32,
+            instance_buffer: Self::create_instance_buffer(state, instance_capacity),
+            instance_count: 0,
+            instance_capacity,
+            pipeline,
+        }
+    }
+
+    fn create_instance_buffer(state: &State, capacity: usize) -> wgpu::Buffer {
+        state.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn write_instances(&mut self, state: &State, instances: &[InstanceRaw]) {
+        if instances.len() > self.instance_capacity {
+            self.instance_capacity = instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(state, self.instance_capacity);
+        }
+        state
+            .queue
+            .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(instances));
+        self.instance_count = instances.len() as u32;
+    }
+
+    pub fn get_pipeline(&self) -> Arc<RenderPipeline> {
+        Arc::clone(&self.pipeline)
+    }
+}
+
+pub fn get_or_create_batch(
+    state: &State,
+    mesh: &Arc<RwLock<Mesh>>,
+    material: &Arc<RwLock<dyn Material>>,
+    render_layer: &str,
+) -> Arc<RwLock<InstancedBatch>> {
+    let mesh_lock = mesh.read();
+    let key = (mesh_lock.get_id(), material.read().get_id());
+    if let Some(batch) = INSTANCED_BATCHES.get(&key) {
+        return Arc::clone(batch.value());
+    }
+    let batch = Arc::new(RwLock::new(InstancedBatch::new(
+        state,
+        &mesh_lock,
+        Arc::clone(material),
+        render_layer.to_string(),
+    )));
+    INSTANCED_BATCHES.insert(key, Arc::clone(&batch));
+    batch
+}
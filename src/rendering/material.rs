@@ -111,13 +111,26 @@ pub fn create_pipeline(
     state: &State,
     texture_bind_group_layout: Arc<BindGroupLayout>,
     shader: Arc<ShaderModule>,
+) -> RenderPipeline {
+    create_pipeline_with_buffers(state, texture_bind_group_layout, shader, &[Vertex::desc()])
+}
+
+pub fn create_pipeline_with_buffers(
+    state: &State,
+    texture_bind_group_layout: Arc<BindGroupLayout>,
+    shader: Arc<ShaderModule>,
+    buffers: &[wgpu::VertexBufferLayout],
 ) -> RenderPipeline {
     let render_pipeline_layout =
         state
             .device
             .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &state.camera_bind_group_layout],
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &state.camera_bind_group_layout,
+                    &state.lights_bind_group_layout,
+                ],
                 push_constant_ranges: &[],
             });
 
@@ -129,7 +142,7 @@ pub fn create_pipeline(
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
+                buffers,
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
@@ -1,5 +1,27 @@
+// Only the vertex type, the backend trait and visibility are built without the client feature, the
+// voxel meshing code and the null backend use them and visibility is tested without a GPU
+pub mod backend;
+#[cfg(feature = "render")]
 pub mod camera;
+#[cfg(feature = "render")]
+pub mod dynamic_lights;
+#[cfg(feature = "render")]
+pub mod instancing;
+#[cfg(feature = "render")]
 pub mod material;
+#[cfg(feature = "render")]
 pub mod render_pass_data;
+#[cfg(feature = "render")]
+pub mod staging;
+#[cfg(feature = "render")]
+pub mod text;
+#[cfg(feature = "render")]
 pub mod texture;
+#[cfg(feature = "render")]
+pub mod texture_atlas;
+#[cfg(feature = "render")]
+pub mod ui;
 pub mod vertex;
+pub mod visibility;
+#[cfg(feature = "render")]
+pub mod wgpu_backend;
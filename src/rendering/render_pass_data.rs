@@ -1,11 +1,16 @@
-use std::sync::Arc;
+use std::{collections::HashMap, ops::Range, sync::Arc};
 
-use crate::{asset_types::mesh::Mesh, state::State};
+use crate::{
+    asset_types::{asset::Asset, mesh::Mesh},
+    profile_scope,
+    rendering::{staging::StagingRing, vertex::Vertex, visibility::Frustum},
+    state::State,
+};
 
 use wgpu::{BufferDescriptor, BufferUsages};
 
 use super::material::Material;
-use glam::Mat4;
+use glam::{Mat4, Vec3};
 use parking_lot::RwLock;
 
 // Render layers are a convenient way to filter what a camera renders
@@ -77,50 +82,160 @@ pub mod render_layers {
     }
 }
 
+// Vertices and indices the buffers of a pass start with, they double whenever a mesh doesn't fit
+const INITIAL_VERTEX_CAPACITY: u32 = 1 << 18;
+const INITIAL_INDEX_CAPACITY: u32 = 1 << 19;
+const VERTEX_SIZE: u64 = std::mem::size_of::<Vertex>() as u64;
+const INDEX_SIZE: u64 = std::mem::size_of::<u32>() as u64;
+// Draw commands the indirect buffer of a pass starts with room for
+const INITIAL_DRAW_CAPACITY: u32 = 1024;
+const DRAW_COMMAND_SIZE: u64 = std::mem::size_of::<DrawIndexedCommand>() as u64;
+
+// The free ranges of a buffer in elements, sorted and merged with their neighbours
 #[derive(Debug)]
+struct FreeList {
+    free: Vec<Range<u32>>,
+    capacity: u32,
+}
+
+impl FreeList {
+    fn new(capacity: u32) -> Self {
+        Self {
+            free: vec![0..capacity],
+            capacity,
+        }
+    }
+
+    // First fit, returns the start of the range
+    fn allocate(&mut self, length: u32) -> Option<u32> {
+        let index = self
+            .free
+            .iter()
+            .position(|range| range.end - range.start >= length)?;
+        let start = self.free[index].start;
+        self.free[index].start += length;
+        if self.free[index].is_empty() {
+            self.free.remove(index);
+        }
+        Some(start)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let index = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(index, range);
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+
+    fn used(&self) -> u32 {
+        self.capacity
+            - self
+                .free
+                .iter()
+                .map(|range| range.end - range.start)
+                .sum::<u32>()
+    }
+}
+
+// Where a mesh lives in its pass's buffers, its indices are relative to vertex_start. Matches the
+// arguments of an indexed draw so the entries can become indirect draws later
+#[derive(Clone, Copy, Debug)]
 pub struct MeshBufferEntry {
-    pub vertex_start: usize,
-    pub vertex_length: usize,
-    pub index_start: usize,
-    pub index_length: usize,
+    pub vertex_start: u32,
+    pub vertex_length: u32,
+    pub index_start: u32,
+    pub index_length: u32,
+    // Bounds of the transformed vertices, for culling
+    pub min: Vec3,
+    pub max: Vec3,
 }
 
+impl MeshBufferEntry {
+    pub fn index_range(&self) -> Range<u32> {
+        self.index_start..self.index_start + self.index_length
+    }
+}
+
+// Laid out like the arguments of an indexed indirect draw
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawIndexedCommand {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
+impl DrawIndexedCommand {
+    pub fn index_range(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
+}
+
+// One pair of large buffers per pass that meshes are allocated ranges in. A remeshed chunk frees its
+// old range first, and when nothing fits the live ranges are packed into new buffers, grown if needed
 #[derive(Debug)]
 pub struct MeshBuffer {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
-    pub vertex_offset: u64,
-    pub index_offset: u64,
-    pub vertex_count: u32,
-    pub index_count: u32,
+    // Filled with the visible meshes for each camera, see write_draw_commands
+    pub draw_buffer: wgpu::Buffer,
+    draw_capacity: u32,
+    vertex_space: FreeList,
+    index_space: FreeList,
+    // By mesh id
+    entries: HashMap<u64, MeshBufferEntry>,
 }
 
 impl MeshBuffer {
     pub fn new(device: &wgpu::Device) -> Self {
-        let vertex_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("Vertex Buffer"),
-            size: 500_000_000, // 500mb (Maybe too much!)
-            usage: BufferUsages::COPY_DST | BufferUsages::VERTEX,
-            mapped_at_creation: false,
-        });
-        let index_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("Index Buffer"),
-            size: 500_000_000, // 500mb (Maybe too much!)
-            usage: BufferUsages::COPY_DST | BufferUsages::INDEX,
-            mapped_at_creation: false,
-        });
+        Self::with_capacity(device, INITIAL_VERTEX_CAPACITY, INITIAL_INDEX_CAPACITY)
+    }
+
+    fn with_capacity(device: &wgpu::Device, vertices: u32, indices: u32) -> Self {
+        let (vertex_buffer, index_buffer) = create_buffers(device, vertices, indices);
         MeshBuffer {
             vertex_buffer,
             index_buffer,
-            vertex_offset: 0,
-            index_offset: 0,
-            vertex_count: 0,
-            index_count: 0,
+            draw_buffer: create_draw_buffer(device, INITIAL_DRAW_CAPACITY),
+            draw_capacity: INITIAL_DRAW_CAPACITY,
+            vertex_space: FreeList::new(vertices),
+            index_space: FreeList::new(indices),
+            entries: HashMap::new(),
         }
     }
 
-    pub fn insert_mesh(&mut self, state: &State, mesh: Arc<RwLock<Mesh>>, transform: &Mat4) {
+    pub fn entries(&self) -> impl Iterator<Item = &MeshBufferEntry> {
+        self.entries.values()
+    }
+
+    // Replaces the mesh's previous data if it was inserted before
+    // Replaces the mesh's previous data if it was inserted before. Returns false without changing
+    // anything if the frame's uploads have no room left for it, it's inserted on a later frame
+    pub fn insert_mesh(
+        &mut self,
+        state: &State,
+        staging: &mut StagingRing,
+        mesh: Arc<RwLock<Mesh>>,
+        transform: &Mat4,
+    ) -> bool {
         let mesh_lock = mesh.read();
+        let bytes =
+            mesh_lock.vertex_count as u64 * VERTEX_SIZE + mesh_lock.index_count as u64 * INDEX_SIZE;
+        if !staging.has_room(bytes) {
+            return false;
+        }
+        let id = mesh_lock.get_id();
+        self.remove_mesh(id);
+
         // Prepare data
         let mut new_vertices = mesh_lock.get_vertices().clone();
         new_vertices.iter_mut().for_each(|vertex| {
@@ -128,27 +243,195 @@ impl MeshBuffer {
             // Transforming the normal is not always required, perhaps find a way to avoid doing this in those cases
             vertex.normal = transform.transform_vector3(vertex.normal.into()).into();
         });
-        let vertex_data = bytemuck::cast_slice(&new_vertices);
-
-        let mut new_indices = mesh_lock.get_indices().clone();
-        new_indices
-            .iter_mut()
-            .for_each(|index| *index += self.vertex_count);
-        let index_data = bytemuck::cast_slice(&new_indices);
-
-        // write data into buffers
-        state
-            .queue
-            .write_buffer(&self.vertex_buffer, self.vertex_offset, vertex_data);
-        state
-            .queue
-            .write_buffer(&self.index_buffer, self.index_offset, index_data);
-
-        self.vertex_offset += vertex_data.len() as u64;
-        self.index_offset += index_data.len() as u64;
-        self.vertex_count += mesh_lock.vertex_count as u32;
-        self.index_count += mesh_lock.index_count as u32;
+        let indices = mesh_lock.get_indices();
+        let (vertex_length, index_length) = (new_vertices.len() as u32, indices.len() as u32);
+
+        let mut entry = match self.allocate(vertex_length, index_length) {
+            Some(entry) => entry,
+            None => {
+                self.compact(state, staging, vertex_length, index_length);
+                self.allocate(vertex_length, index_length)
+                    .expect("Compacting makes room for the mesh")
+            }
+        };
+        let positions = new_vertices
+            .iter()
+            .map(|vertex| Vec3::from(vertex.position));
+        entry.min = positions.clone().fold(Vec3::splat(f32::MAX), Vec3::min);
+        entry.max = positions.fold(Vec3::splat(f32::MIN), Vec3::max);
+
+        // Copied into the buffers when the frame's uploads are submitted
+        staging.upload(
+            &state.device,
+            &self.vertex_buffer,
+            entry.vertex_start as u64 * VERTEX_SIZE,
+            bytemuck::cast_slice(&new_vertices),
+        );
+        staging.upload(
+            &state.device,
+            &self.index_buffer,
+            entry.index_start as u64 * INDEX_SIZE,
+            bytemuck::cast_slice(indices),
+        );
+        self.entries.insert(id, entry);
+        true
+    }
+
+    // Returns false if the mesh wasn't in the buffers
+    pub fn remove_mesh(&mut self, id: u64) -> bool {
+        match self.entries.remove(&id) {
+            Some(entry) => {
+                self.vertex_space
+                    .free(entry.vertex_start..entry.vertex_start + entry.vertex_length);
+                self.index_space.free(entry.index_range());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn allocate(&mut self, vertex_length: u32, index_length: u32) -> Option<MeshBufferEntry> {
+        let vertex_start = self.vertex_space.allocate(vertex_length)?;
+        let index_start = match self.index_space.allocate(index_length) {
+            Some(start) => start,
+            None => {
+                self.vertex_space
+                    .free(vertex_start..vertex_start + vertex_length);
+                return None;
+            }
+        };
+        Some(MeshBufferEntry {
+            vertex_start,
+            vertex_length,
+            index_start,
+            index_length,
+            min: Vec3::ZERO,
+            max: Vec3::ZERO,
+        })
+    }
+
+    // The culling pass, a draw command for every mesh the frustum may see
+    pub fn cull(&self, frustum: &Frustum) -> Vec<DrawIndexedCommand> {
+        profile_scope!("cull_meshes");
+        self.entries
+            .values()
+            .filter(|entry| frustum.intersects_box(entry.min, entry.max))
+            .map(|entry| DrawIndexedCommand {
+                index_count: entry.index_length,
+                instance_count: 1,
+                first_index: entry.index_start,
+                base_vertex: entry.vertex_start as i32,
+                first_instance: 0,
+            })
+            .collect()
+    }
+
+    // Writes the commands to the start of the draw buffer, which grows to fit them. Each camera
+    // submits its own commands so the next camera can reuse the buffer
+    pub fn write_draw_commands(&mut self, state: &State, commands: &[DrawIndexedCommand]) {
+        if commands.len() as u32 > self.draw_capacity {
+            self.draw_capacity = (commands.len() as u32).next_power_of_two();
+            self.draw_buffer = create_draw_buffer(&state.device, self.draw_capacity);
+        }
+        if !commands.is_empty() {
+            state
+                .queue
+                .write_buffer(&self.draw_buffer, 0, bytemuck::cast_slice(commands));
+        }
     }
+
+    // Copies the live ranges to the start of new buffers on the GPU, leaving the free space in one
+    // range at the end. The buffers double until the extra vertices and indices fit as well. The
+    // copies are recorded with the uploads so ones made earlier this frame land before them
+    fn compact(
+        &mut self,
+        state: &State,
+        staging: &mut StagingRing,
+        extra_vertices: u32,
+        extra_indices: u32,
+    ) {
+        profile_scope!("compact_mesh_buffer");
+        let grow = |capacity: u32, needed: u32| {
+            let mut capacity = capacity;
+            while capacity < needed {
+                capacity *= 2;
+            }
+            capacity
+        };
+        let vertex_capacity = grow(
+            self.vertex_space.capacity,
+            self.vertex_space.used() + extra_vertices,
+        );
+        let index_capacity = grow(
+            self.index_space.capacity,
+            self.index_space.used() + extra_indices,
+        );
+        let (vertex_buffer, index_buffer) =
+            create_buffers(&state.device, vertex_capacity, index_capacity);
+
+        let encoder = staging.encoder(&state.device);
+        let (mut vertex_end, mut index_end) = (0, 0);
+        for entry in self.entries.values_mut() {
+            encoder.copy_buffer_to_buffer(
+                &self.vertex_buffer,
+                entry.vertex_start as u64 * VERTEX_SIZE,
+                &vertex_buffer,
+                vertex_end as u64 * VERTEX_SIZE,
+                entry.vertex_length as u64 * VERTEX_SIZE,
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.index_buffer,
+                entry.index_start as u64 * INDEX_SIZE,
+                &index_buffer,
+                index_end as u64 * INDEX_SIZE,
+                entry.index_length as u64 * INDEX_SIZE,
+            );
+            entry.vertex_start = vertex_end;
+            entry.index_start = index_end;
+            vertex_end += entry.vertex_length;
+            index_end += entry.index_length;
+        }
+
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffer = index_buffer;
+        self.vertex_space = FreeList {
+            free: vec![vertex_end..vertex_capacity],
+            capacity: vertex_capacity,
+        };
+        self.index_space = FreeList {
+            free: vec![index_end..index_capacity],
+            capacity: index_capacity,
+        };
+    }
+}
+
+fn create_buffers(
+    device: &wgpu::Device,
+    vertices: u32,
+    indices: u32,
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let vertex_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Vertex Buffer"),
+        size: vertices as u64 * VERTEX_SIZE,
+        usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC | BufferUsages::VERTEX,
+        mapped_at_creation: false,
+    });
+    let index_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Index Buffer"),
+        size: indices as u64 * INDEX_SIZE,
+        usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC | BufferUsages::INDEX,
+        mapped_at_creation: false,
+    });
+    (vertex_buffer, index_buffer)
+}
+
+fn create_draw_buffer(device: &wgpu::Device, commands: u32) -> wgpu::Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Draw Buffer"),
+        size: commands as u64 * DRAW_COMMAND_SIZE,
+        usage: BufferUsages::COPY_DST | BufferUsages::INDIRECT,
+        mapped_at_creation: false,
+    })
 }
 
 #[derive(Debug)]
@@ -159,8 +442,18 @@ pub struct RenderPassData<M: Material + ?Sized> {
 }
 
 impl RenderPassData<dyn Material> {
-    pub fn insert_mesh(&mut self, state: &State, mesh: Arc<RwLock<Mesh>>, transform: &Mat4) {
-        self.buffer.insert_mesh(state, mesh, transform)
+    pub fn insert_mesh(
+        &mut self,
+        state: &State,
+        staging: &mut StagingRing,
+        mesh: Arc<RwLock<Mesh>>,
+        transform: &Mat4,
+    ) -> bool {
+        self.buffer.insert_mesh(state, staging, mesh, transform)
+    }
+
+    pub fn remove_mesh(&mut self, id: u64) -> bool {
+        self.buffer.remove_mesh(id)
     }
 }
 
@@ -175,3 +468,24 @@ pub fn create_render_pass(
         buffer: MeshBuffer::new(&state.device),
     }
 }
+
+#[cfg(test)]
+mod render_pass_data_tests {
+    use super::*;
+
+    #[test]
+    fn freed_ranges_merge_with_their_neighbours() {
+        let mut space = FreeList::new(100);
+        let a = space.allocate(10).unwrap();
+        let b = space.allocate(20).unwrap();
+        let c = space.allocate(30).unwrap();
+        assert_eq!((a, b, c), (0, 10, 30));
+        assert_eq!(space.allocate(50), None);
+
+        space.free(a..a + 10);
+        space.free(c..c + 30);
+        assert_eq!(space.used(), 20);
+        space.free(b..b + 20);
+        assert_eq!(space.free, vec![0..100]);
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use tracing::error;
+use wgpu::{BufferAsyncError, BufferDescriptor, BufferUsages, MapMode};
+
+use crate::profile_scope;
+
+// Frames of uploads in flight, a buffer is reused once the GPU is done copying from it
+const STAGING_BUFFERS: usize = 3;
+// The most one frame can upload, a budget above this is capped to it
+pub const STAGING_BUFFER_SIZE: u64 = 16 << 20;
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), BufferAsyncError>> + Send>>;
+
+enum StagingState {
+    Mapped,
+    // Submitted copies are reading it, it's mapped again once they finish
+    Mapping(MapFuture),
+}
+
+struct StagingBuffer {
+    buffer: wgpu::Buffer,
+    state: StagingState,
+}
+
+// Mesh data is written into mapped staging buffers and copied to the pass buffers by an upload
+// command buffer submitted ahead of the frame's rendering, so the render queue never waits on a
+// large write. Every frame takes the next mapped buffer in the ring, a frame without one uploads
+// nothing and the meshes wait
+pub struct StagingRing {
+    buffers: Vec<StagingBuffer>,
+    // The buffer this frame writes into, None if the ring hasn't been mapped again yet
+    current: Option<usize>,
+    next: usize,
+    used: u64,
+    budget: u64,
+    encoder: Option<wgpu::CommandEncoder>,
+}
+
+impl StagingRing {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffers = (0..STAGING_BUFFERS)
+            .map(|_| StagingBuffer {
+                buffer: device.create_buffer(&BufferDescriptor {
+                    label: Some("Staging Buffer"),
+                    size: STAGING_BUFFER_SIZE,
+                    usage: BufferUsages::MAP_WRITE | BufferUsages::COPY_SRC,
+                    mapped_at_creation: true,
+                }),
+                state: StagingState::Mapped,
+            })
+            .collect();
+        Self {
+            buffers,
+            current: None,
+            next: 0,
+            used: 0,
+            budget: 0,
+            encoder: None,
+        }
+    }
+
+    // Picks the buffer for this frame's uploads, budget is in bytes
+    pub fn begin_frame(&mut self, device: &wgpu::Device, budget: u64) {
+        device.poll(wgpu::Maintain::Poll);
+        let staging = &mut self.buffers[self.next];
+        if let StagingState::Mapping(future) = &mut staging.state {
+            match poll_once(future) {
+                Some(Ok(())) => staging.state = StagingState::Mapped,
+                // The buffer stays unusable, the next frame tries the same one again
+                Some(Err(e)) => error!("Failed to map a staging buffer: {e}"),
+                None => {}
+            }
+        }
+        self.current = match staging.state {
+            StagingState::Mapped => Some(self.next),
+            StagingState::Mapping(_) => None,
+        };
+        self.used = 0;
+        self.budget = budget.min(STAGING_BUFFER_SIZE);
+    }
+
+    // Whether this frame can still upload the bytes. The first upload of a frame may go over the
+    // budget so a mesh bigger than it still gets uploaded
+    pub fn has_room(&self, bytes: u64) -> bool {
+        self.current.is_some()
+            && self.used + bytes <= STAGING_BUFFER_SIZE
+            && (self.used == 0 || self.used + bytes <= self.budget)
+    }
+
+    // The upload command encoder, copies between GPU buffers recorded here run in order with the uploads
+    pub fn encoder(&mut self, device: &wgpu::Device) -> &mut wgpu::CommandEncoder {
+        self.encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Upload Encoder"),
+            })
+        })
+    }
+
+    // Copies the data into the target at the offset once the uploads are submitted. Returns false if
+    // there's no room, check has_room first. The data length has to be a multiple of four
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        target: &wgpu::Buffer,
+        offset: u64,
+        data: &[u8],
+    ) -> bool {
+        let bytes = data.len() as u64;
+        let index = match self.current {
+            Some(index) if self.has_room(bytes) => index,
+            _ => return false,
+        };
+        if bytes == 0 {
+            return true;
+        }
+        let start = self.used;
+        self.buffers[index]
+            .buffer
+            .slice(start..start + bytes)
+            .get_mapped_range_mut()
+            .copy_from_slice(data);
+        self.used += bytes;
+        self.encoder(device);
+        if let Some(encoder) = &mut self.encoder {
+            let staging = &self.buffers[index].buffer;
+            encoder.copy_buffer_to_buffer(staging, start, target, offset, bytes);
+        }
+        true
+    }
+
+    // Submits the frame's uploads ahead of the rendering and starts mapping the buffer again
+    pub fn submit(&mut self, queue: &wgpu::Queue) {
+        profile_scope!("submit_uploads");
+        let index = match self.current.take() {
+            Some(index) if self.used > 0 => index,
+            // The buffer stays mapped for the next frame
+            _ => {
+                if let Some(encoder) = self.encoder.take() {
+                    queue.submit(std::iter::once(encoder.finish()));
+                }
+                return;
+            }
+        };
+        let staging = &mut self.buffers[index];
+        staging.buffer.unmap();
+        if let Some(encoder) = self.encoder.take() {
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+        staging.state =
+            StagingState::Mapping(Box::pin(staging.buffer.slice(..).map_async(MapMode::Write)));
+        self.next = (index + 1) % self.buffers.len();
+    }
+}
+
+// Checks a future without blocking, map futures resolve while the device is polled
+fn poll_once<T>(future: &mut Pin<Box<dyn Future<Output = T> + Send>>) -> Option<T> {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+    let waker = unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) };
+    match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(result) => Some(result),
+        Poll::Pending => None,
+    }
+}
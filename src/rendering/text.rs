@@ -0,0 +1,64 @@
+use std::{future::Future, pin::Pin};
+
+use tracing::warn;
+use wgpu::util::StagingBelt;
+use wgpu_glyph::{ab_glyph::FontArc, GlyphBrush, GlyphBrushBuilder, Section};
+use winit::dpi::PhysicalSize;
+
+// DejaVu Sans Mono, bundled so text looks the same on every machine. The DejaVu fonts can be
+// redistributed freely
+const FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+pub const FONT_SIZE: f32 = 18.0;
+
+// Screen space text drawn over the scene, sections are queued during the frame and drawn by render
+pub struct TextLayer {
+    brush: GlyphBrush<()>,
+    staging_belt: StagingBelt,
+    // Hands back the last frame's staging buffers once the GPU is done with them
+    recall: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl TextLayer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let font = FontArc::try_from_slice(FONT).unwrap();
+        Self {
+            brush: GlyphBrushBuilder::using_font(font).build(device, format),
+            staging_belt: StagingBelt::new(1024),
+            recall: None,
+        }
+    }
+
+    pub fn queue(&mut self, section: Section) {
+        self.brush.queue(section);
+    }
+
+    pub fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        size: PhysicalSize<u32>,
+    ) {
+        // The previous frame was submitted long ago, so this rarely waits
+        if let Some(recall) = self.recall.take() {
+            device.poll(wgpu::Maintain::Wait);
+            pollster::block_on(recall);
+        }
+        if let Err(e) = self.brush.draw_queued(
+            device,
+            &mut self.staging_belt,
+            encoder,
+            view,
+            size.width,
+            size.height,
+        ) {
+            warn!("Failed to draw text: {e}");
+        }
+        self.staging_belt.finish();
+    }
+
+    // Has to be called once the frame that drew the text is submitted
+    pub fn submitted(&mut self) {
+        self.recall = Some(Box::pin(self.staging_belt.recall()));
+    }
+}
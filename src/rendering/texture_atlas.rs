@@ -0,0 +1,301 @@
+use std::{
+    num::NonZeroU8,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use glam::Vec2;
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, GenericImageView, Rgba, RgbaImage,
+};
+use parking_lot::{Mutex, RwLock};
+use tracing::{info, warn};
+
+use crate::{
+    assets, config,
+    state::State,
+    voxels::{
+        voxel_registry::{get_voxel_by_id, voxel_id_mappings},
+        voxel_scene::VoxelScene,
+        voxel_textures::{set_uv_table, TextureFiltering, UvRect, UvTable},
+    },
+};
+
+use super::{material::MaterialDiffuseTexture, texture::Texture};
+
+// Tiles take the size of the largest texture within these bounds, so a pack can bring more detailed
+// textures than the ones it replaces. Both are powers of two so every mip level halves evenly
+const MIN_TILE_SIZE: u32 = 16;
+const MAX_TILE_SIZE: u32 = 256;
+
+static REBUILD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // The settings the atlas' sampler was made with
+    static ref FILTERING: Mutex<Option<(TextureFiltering, u8)>> = Mutex::new(None);
+}
+
+pub struct TextureAtlas {
+    // The full atlas and its mip levels
+    pub levels: Vec<RgbaImage>,
+    pub uv_table: UvTable,
+}
+
+// Copies the tile into the atlas with its edge texels repeated padding texels outwards, so
+// sampling near the edge of a face and the smaller mip levels don't pick up the neighbouring tiles
+fn place_tile(atlas: &mut RgbaImage, tile: &RgbaImage, x: u32, y: u32, padding: u32) {
+    let size = tile.width() as i64;
+    let padding = padding as i64;
+    for dy in -padding..size + padding {
+        for dx in -padding..size + padding {
+            let texel = tile.get_pixel(dx.clamp(0, size - 1) as u32, dy.clamp(0, size - 1) as u32);
+            let (px, py) = (
+                (x as i64 + padding + dx) as u32,
+                (y as i64 + padding + dy) as u32,
+            );
+            atlas.put_pixel(px, py, *texel);
+        }
+    }
+}
+
+// Packs the textures named by the voxels into a square grid of tiles. The first tile is white and
+// sits at the origin, so voxels without a texture and meshes without texture coordinates keep
+// their vertex color. Each tile is padded by a quarter of its size and shrunk on its own for the
+// mip levels, until the padding is down to one texel
+pub fn build_atlas(
+    voxels: &[(u16, String)],
+    texture: impl Fn(&str) -> Option<Arc<DynamicImage>>,
+) -> TextureAtlas {
+    let mut names: Vec<&str> = Vec::new();
+    let mut images = Vec::new();
+    for (_, name) in voxels {
+        if names.contains(&name.as_str()) {
+            continue;
+        }
+        match texture(name) {
+            Some(image) => {
+                names.push(name);
+                images.push(image);
+            }
+            None => warn!("There is no texture named {name}, the voxels using it keep their color"),
+        }
+    }
+
+    let tile_size = images
+        .iter()
+        .map(|image| image.width().max(image.height()))
+        .max()
+        .unwrap_or(MIN_TILE_SIZE)
+        .clamp(MIN_TILE_SIZE, MAX_TILE_SIZE)
+        .next_power_of_two();
+    let padding = tile_size / 4;
+    let cell = tile_size + 2 * padding;
+    let columns = ((images.len() + 1) as f32).sqrt().ceil() as u32;
+    let size = columns * cell;
+    let origin = |index: u32| ((index % columns) * cell, (index / columns) * cell);
+
+    let mut tiles = vec![RgbaImage::from_pixel(tile_size, tile_size, Rgba([255; 4]))];
+    tiles.extend(images.iter().map(|image| {
+        image
+            .resize_exact(tile_size, tile_size, FilterType::Nearest)
+            .to_rgba8()
+    }));
+    let mut levels = Vec::new();
+    for level in 0..=padding.trailing_zeros() {
+        if level > 0 {
+            let half = tile_size >> level;
+            for tile in &mut tiles {
+                *tile = imageops::resize(tile, half, half, FilterType::Triangle);
+            }
+        }
+        let mut atlas = RgbaImage::new(size >> level, size >> level);
+        for (index, tile) in tiles.iter().enumerate() {
+            let (x, y) = origin(index as u32);
+            place_tile(&mut atlas, tile, x >> level, y >> level, padding >> level);
+        }
+        levels.push(atlas);
+    }
+
+    let rect = |index: u32| {
+        let (x, y) = origin(index);
+        let min = Vec2::new((x + padding) as f32, (y + padding) as f32);
+        UvRect {
+            min: min / size as f32,
+            max: (min + tile_size as f32) / size as f32,
+        }
+    };
+    let ids = voxels.iter().map(|(id, _)| *id as usize + 1).max();
+    let mut uv_tiles = vec![None; ids.unwrap_or(0)];
+    for (id, name) in voxels {
+        if let Some(index) = names.iter().position(|n| n == name) {
+            uv_tiles[*id as usize] = Some(rect(index as u32 + 1));
+        }
+    }
+
+    TextureAtlas {
+        levels,
+        uv_table: UvTable {
+            tiles: uv_tiles,
+            white: Vec2::splat(cell as f32 * 0.5 / size as f32),
+        },
+    }
+}
+
+fn atlas_sampler(
+    device: &wgpu::Device,
+    filtering: TextureFiltering,
+    anisotropy: u8,
+) -> wgpu::Sampler {
+    let (filter, anisotropy_clamp) = match filtering {
+        TextureFiltering::Nearest => (wgpu::FilterMode::Nearest, None),
+        TextureFiltering::Trilinear => (
+            wgpu::FilterMode::Linear,
+            NonZeroU8::new(anisotropy).filter(|a| a.get() > 1),
+        ),
+    };
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("voxel atlas"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        // Magnified texels stay sharp in every mode
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: filter,
+        mipmap_filter: filter,
+        anisotropy_clamp,
+        ..Default::default()
+    })
+}
+
+// The atlas for the registered voxels and the loaded textures, sampled as the settings say. The UV
+// table is swapped in at the same time, meshes made afterwards point into the new atlas
+pub fn upload_voxel_atlas(state: &State) -> Result<Texture> {
+    let voxels = voxel_id_mappings()
+        .into_iter()
+        .filter_map(|(id, _)| Some((id, get_voxel_by_id(id)?.texture.clone()?)))
+        .collect::<Vec<_>>();
+    let atlas = build_atlas(&voxels, assets::texture_image);
+    let config = config::current();
+    let sampler = atlas_sampler(&state.device, config.texture_filtering, config.anisotropy);
+    let texture = Texture::from_mip_levels(
+        &state.device,
+        &state.queue,
+        &atlas.levels,
+        sampler,
+        Some("voxel atlas"),
+    )?;
+    set_uv_table(atlas.uv_table);
+    *FILTERING.lock() = Some((config.texture_filtering, config.anisotropy));
+    Ok(texture)
+}
+
+// Whether the filtering settings changed since the atlas was uploaded
+pub fn filtering_changed() -> bool {
+    let config = config::current();
+    *FILTERING.lock() != Some((config.texture_filtering, config.anisotropy))
+}
+
+// Uploads the atlas again with the current filtering. The tiles stay where they were, so the
+// meshes don't need remeshing
+pub fn refilter(state: &State, material: &RwLock<MaterialDiffuseTexture>) -> Result<()> {
+    let texture = upload_voxel_atlas(state)?;
+    material.write().diffuse_texture = Arc::new(texture);
+    info!("Changed the texture filtering");
+    Ok(())
+}
+
+// Asked for by the texturepack command, the frame loop does the rebuild since it owns the device
+pub fn request_rebuild() {
+    REBUILD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn take_rebuild_request() -> bool {
+    REBUILD_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+// Loads the textures of the current texture pack, replaces the material's atlas and remeshes every
+// chunk. Chunks keep the old look until their new mesh is in
+pub fn rebuild(
+    state: &State,
+    material: &RwLock<MaterialDiffuseTexture>,
+    scene: &VoxelScene,
+) -> Result<()> {
+    assets::reload_textures();
+    let texture = upload_voxel_atlas(state)?;
+    material.write().diffuse_texture = Arc::new(texture);
+    scene.remesh_all();
+    info!("Rebuilt the texture atlas");
+    Ok(())
+}
+
+#[cfg(test)]
+mod texture_atlas_tests {
+    use super::*;
+
+    fn solid(size: u32, value: u8) -> Arc<DynamicImage> {
+        Arc::new(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            size,
+            size,
+            Rgba([value, 0, 0, 255]),
+        )))
+    }
+
+    #[test]
+    fn voxels_sharing_a_texture_share_a_tile() {
+        let voxels = [
+            (1, "stone".to_string()),
+            (2, "dirt".to_string()),
+            (4, "stone".to_string()),
+            (5, "missing".to_string()),
+        ];
+        let atlas = build_atlas(&voxels, |name| match name {
+            "stone" => Some(solid(16, 10)),
+            "dirt" => Some(solid(32, 20)),
+            _ => None,
+        });
+        // White, stone and dirt fit in two by two cells of the largest texture and its padding
+        assert_eq!(atlas.levels[0].dimensions(), (96, 96));
+        let table = &atlas.uv_table;
+        assert_eq!(table.tile(1), table.tile(4));
+        assert_ne!(table.tile(1), table.tile(2));
+        assert!(table.tile(0).is_none() && table.tile(3).is_none() && table.tile(5).is_none());
+
+        let texel = |uv: Vec2| {
+            let pixel = (uv * 96.0).floor();
+            *atlas.levels[0].get_pixel(pixel.x as u32, pixel.y as u32)
+        };
+        assert_eq!(texel(table.white), Rgba([255; 4]));
+        assert_eq!(texel(Vec2::ZERO), Rgba([255; 4]));
+        assert_eq!(texel(table.tile(1).unwrap().min), Rgba([10, 0, 0, 255]));
+        assert_eq!(texel(table.tile(2).unwrap().max), Rgba([20, 0, 0, 255]));
+    }
+
+    #[test]
+    fn mip_levels_keep_the_tiles_apart() {
+        let atlas = build_atlas(&[(1, "dirt".to_string())], |_| Some(solid(32, 20)));
+        let sizes = atlas
+            .levels
+            .iter()
+            .map(|level| level.width())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [96, 48, 24, 12]);
+        // The dirt cell is six texels wide at the smallest level, one of them padding on each side
+        let smallest = &atlas.levels[3];
+        assert_eq!(*smallest.get_pixel(6, 0), Rgba([20, 0, 0, 255]));
+        assert_eq!(*smallest.get_pixel(11, 5), Rgba([20, 0, 0, 255]));
+        assert_eq!(*smallest.get_pixel(5, 5), Rgba([255; 4]));
+    }
+
+    #[test]
+    fn voxels_without_textures_get_a_white_atlas() {
+        let atlas = build_atlas(&[], |_| None);
+        assert_eq!(atlas.levels.len(), 3);
+        assert_eq!(atlas.levels[0].dimensions(), (24, 24));
+        assert!(atlas.uv_table.tiles.is_empty());
+        assert_eq!(atlas.uv_table.white, Vec2::splat(0.5));
+    }
+}
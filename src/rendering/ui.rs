@@ -0,0 +1,121 @@
+use egui::{ClippedPrimitive, Context, TexturesDelta};
+use egui_wgpu::renderer::{RenderPass, ScreenDescriptor};
+use flume::{Receiver, Sender};
+use parking_lot::Mutex;
+use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
+
+pub type Panel = Box<dyn FnMut(&Context) + Send>;
+
+lazy_static! {
+    // Built every frame in the order they were added
+    static ref PANELS: Mutex<Vec<(String, Panel)>> = Mutex::new(Vec::new());
+    static ref RESIZE_SUBSCRIBERS: Mutex<Vec<Sender<PhysicalSize<u32>>>> = Mutex::new(Vec::new());
+}
+
+// Adds immediate mode UI built with egui every frame, a panel with the same name is replaced.
+// Panels can't add or remove panels themselves
+pub fn add_panel(name: &str, panel: impl FnMut(&Context) + Send + 'static) {
+    let mut panels = PANELS.lock();
+    let panel: Panel = Box::new(panel);
+    match panels.iter_mut().find(|(existing, _)| existing == name) {
+        Some((_, existing)) => *existing = panel,
+        None => panels.push((name.to_string(), panel)),
+    }
+}
+
+// Returns false if there was no panel with the name
+pub fn remove_panel(name: &str) -> bool {
+    let mut panels = PANELS.lock();
+    let count = panels.len();
+    panels.retain(|(existing, _)| existing != name);
+    panels.len() != count
+}
+
+// Receives the window's new size in pixels after it changes, after the render targets were
+// recreated. Minimizing isn't a resize, the size received last stays valid
+pub fn subscribe_resize() -> Receiver<PhysicalSize<u32>> {
+    let (sender, receiver) = flume::unbounded();
+    RESIZE_SUBSCRIBERS.lock().push(sender);
+    receiver
+}
+
+pub(crate) fn notify_resize(size: PhysicalSize<u32>) {
+    RESIZE_SUBSCRIBERS
+        .lock()
+        .retain(|subscriber| subscriber.send(size).is_ok());
+}
+
+// The egui panels, drawn over the scene and under the text layer
+pub struct UiLayer {
+    context: Context,
+    input: egui_winit::State,
+    pass: RenderPass,
+    // Tessellated by run and drawn by the next render
+    frame: Option<(Vec<ClippedPrimitive>, TexturesDelta)>,
+}
+
+impl UiLayer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, window: &Window) -> Self {
+        let max_texture_side = device.limits().max_texture_dimension_2d as usize;
+        Self {
+            context: Context::default(),
+            input: egui_winit::State::new(max_texture_side, window),
+            pass: RenderPass::new(device, format, 1),
+            frame: None,
+        }
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    // True when egui uses the event, such as a click on a panel or typing into a text field
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        self.input.on_event(&self.context, event)
+    }
+
+    // Builds every panel, has to be called once a frame before render
+    pub fn run(&mut self, window: &Window) {
+        let input = self.input.take_egui_input(window);
+        let output = {
+            let mut panels = PANELS.lock();
+            self.context.run(input, |context| {
+                for (_, panel) in panels.iter_mut() {
+                    panel(context);
+                }
+            })
+        };
+        self.input
+            .handle_platform_output(window, &self.context, output.platform_output);
+        let primitives = self.context.tessellate(output.shapes);
+        self.frame = Some((primitives, output.textures_delta));
+    }
+
+    pub fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        size: PhysicalSize<u32>,
+    ) {
+        let (primitives, textures) = match self.frame.take() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let screen = ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: self.context.pixels_per_point(),
+        };
+        for (id, delta) in &textures.set {
+            self.pass.update_texture(device, queue, *id, delta);
+        }
+        self.pass
+            .update_buffers(device, queue, &primitives, &screen);
+        // Loads what's already in the view instead of clearing it
+        self.pass.execute(encoder, view, &primitives, &screen, None);
+        for id in &textures.free {
+            self.pass.free_texture(id);
+        }
+    }
+}
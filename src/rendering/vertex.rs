@@ -17,6 +17,7 @@ impl Vertex {
         }
     }
 
+    #[cfg(feature = "render")]
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
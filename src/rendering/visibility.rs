@@ -0,0 +1,256 @@
+use std::collections::{HashSet, VecDeque};
+
+use glam::{IVec3, Mat4, Vec3, Vec4, Vec4Swizzles};
+
+use crate::voxels::{
+    chunk_visibility::FaceConnectivity,
+    voxel_scene::{VoxelScene, CHUNK_SIZE},
+    voxel_shapes::voxel_directions,
+};
+
+// Distances in voxels from the camera at which chunks drop to the next level of detail, level 0
+// is drawn at full detail
+pub const LOD_DISTANCES: [f32; 3] = [64.0, 128.0, 256.0];
+
+// An axis aligned box in scene space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    // Voxels are centered on their position, so a chunk starts half a voxel before its first one
+    pub fn of_chunk(chunk_pos: IVec3) -> Self {
+        let min = (chunk_pos * CHUNK_SIZE as i32).as_vec3() - 0.5;
+        Self::new(min, min + CHUNK_SIZE as f32)
+    }
+
+    // The bounds of the transformed corners
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        (0..8)
+            .map(|i| {
+                transform.transform_point3(Vec3::new(
+                    if i & 1 == 0 { self.min.x } else { self.max.x },
+                    if i & 2 == 0 { self.min.y } else { self.max.y },
+                    if i & 4 == 0 { self.min.z } else { self.max.z },
+                ))
+            })
+            .fold(
+                Self::new(Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |bounds, corner| Self::new(bounds.min.min(corner), bounds.max.max(corner)),
+            )
+    }
+
+    // 0 for points inside the box
+    pub fn distance_to(&self, point: Vec3) -> f32 {
+        (point.clamp(self.min, self.max) - point).length()
+    }
+}
+
+// The six planes of a camera's view volume, pointing inwards. Boxes fully outside any of them
+// can't be seen and are left out of the draw commands
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    // From a projection with depth from 0 to 1 like the one cameras build
+    pub fn from_view_projection(matrix: Mat4) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| matrix.row(i));
+        let planes =
+            [w + x, w - x, w + y, w - y, z, w - z].map(|plane| plane / plane.xyz().length());
+        Self { planes }
+    }
+
+    // Conservative, boxes near a corner of the frustum can pass without being visible
+    pub fn intersects_box(&self, min: Vec3, max: Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal
+            let normal = plane.xyz();
+            let corner = Vec3::new(
+                if normal.x >= 0.0 { max.x } else { min.x },
+                if normal.y >= 0.0 { max.y } else { min.y },
+                if normal.z >= 0.0 { max.z } else { min.z },
+            );
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn intersects(&self, bounds: &Aabb) -> bool {
+        self.intersects_box(bounds.min, bounds.max)
+    }
+}
+
+// Where the camera is and what it sees, copied out of the camera so visibility can be worked out
+// without it
+#[derive(Clone, Copy, Debug)]
+pub struct CameraView {
+    pub position: Vec3,
+    // Projection times view, with depth from 0 to 1
+    pub view_projection: Mat4,
+}
+
+impl CameraView {
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(self.view_projection)
+    }
+
+    pub fn chunk(&self) -> IVec3 {
+        VoxelScene::chunk_at(&self.position.round().as_ivec3())
+    }
+
+    // Chunks within the radius the frustum sees that aren't hidden behind other chunks
+    pub fn visible_chunks(
+        &self,
+        radius: i32,
+        connectivity: impl Fn(IVec3) -> Option<FaceConnectivity>,
+    ) -> HashSet<IVec3> {
+        let frustum = self.frustum();
+        visible_chunks(self.chunk(), radius, connectivity, |chunk| {
+            frustum.intersects(&Aabb::of_chunk(chunk))
+        })
+    }
+
+    pub fn lod_level(&self, bounds: &Aabb) -> usize {
+        lod_level(self.position, bounds, &LOD_DISTANCES)
+    }
+}
+
+// The chunks that may be seen from the camera's chunk, found by walking from it through faces that
+// connect. The walk never turns back towards the camera, and only steps into chunks the caller
+// accepts, which is usually a frustum test. Chunks without connectivity aren't loaded and stop it
+pub fn visible_chunks(
+    camera_chunk: IVec3,
+    radius: i32,
+    connectivity: impl Fn(IVec3) -> Option<FaceConnectivity>,
+    in_view: impl Fn(IVec3) -> bool,
+) -> HashSet<IVec3> {
+    let mut visible = HashSet::new();
+    // Chunk, the face it was entered through and the directions walked so far
+    let mut queue = VecDeque::new();
+    visible.insert(camera_chunk);
+    queue.push_back((camera_chunk, None, 0_u8));
+    while let Some((chunk, entered, walked)) = queue.pop_front() {
+        let connectivity = match connectivity(chunk) {
+            Some(connectivity) => connectivity,
+            None => continue,
+        };
+        for (face, direction) in voxel_directions::ALL.iter().enumerate() {
+            // Each face's opposite is next to it in voxel_directions
+            let opposite = face ^ 1;
+            if walked & 1 << opposite != 0 {
+                continue;
+            }
+            if entered.map_or(false, |entered| !connectivity.connects(entered, face)) {
+                continue;
+            }
+            let next = chunk + direction.as_vec();
+            if (next - camera_chunk).abs().max_element() > radius
+                || visible.contains(&next)
+                || !in_view(next)
+            {
+                continue;
+            }
+            visible.insert(next);
+            queue.push_back((next, Some(opposite), walked | 1 << face));
+        }
+    }
+    visible
+}
+
+// The level of detail for a box, one level lower for every distance the nearest point of the box
+// is past. Distances are ascending
+pub fn lod_level(camera: Vec3, bounds: &Aabb, distances: &[f32]) -> usize {
+    let distance = bounds.distance_to(camera);
+    distances
+        .iter()
+        .take_while(|threshold| distance >= **threshold)
+        .count()
+}
+
+#[cfg(test)]
+mod visibility_tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // 90 degrees wide and seeing 100 voxels far
+    fn camera(position: Vec3, target: Vec3) -> CameraView {
+        let projection = Mat4::perspective_lh(90f32.to_radians(), 1.0, 0.1, 100.0);
+        CameraView {
+            position,
+            view_projection: projection * Mat4::look_at_lh(position, target, Vec3::Y),
+        }
+    }
+
+    #[test]
+    fn boxes_behind_the_camera_are_culled() {
+        let projection = Mat4::perspective_lh(70f32.to_radians(), 1.0, 0.1, 100.0);
+        let frustum = Frustum::from_view_projection(projection);
+        assert!(frustum.intersects_box(Vec3::new(-1.0, -1.0, 10.0), Vec3::new(1.0, 1.0, 12.0)));
+        assert!(!frustum.intersects_box(Vec3::new(-1.0, -1.0, -12.0), Vec3::new(1.0, 1.0, -10.0)));
+        assert!(!frustum.intersects_box(Vec3::new(50.0, -1.0, 10.0), Vec3::new(52.0, 1.0, 12.0)));
+        assert!(!frustum.intersects_box(Vec3::new(-1.0, -1.0, 150.0), Vec3::new(1.0, 1.0, 160.0)));
+    }
+
+    #[test]
+    fn turning_the_camera_changes_the_chunks_in_view() {
+        let forward = camera(Vec3::new(8.0, 8.0, 8.0), Vec3::new(8.0, 8.0, 100.0));
+        let frustum = forward.frustum();
+        assert!(frustum.intersects(&Aabb::of_chunk(IVec3::new(0, 0, 2))));
+        assert!(!frustum.intersects(&Aabb::of_chunk(IVec3::new(0, 0, -2))));
+        // Past the far plane
+        assert!(!frustum.intersects(&Aabb::of_chunk(IVec3::new(0, 0, 8))));
+
+        let back = camera(Vec3::new(8.0, 8.0, 8.0), Vec3::new(8.0, 8.0, -100.0));
+        let frustum = back.frustum();
+        assert!(frustum.intersects(&Aabb::of_chunk(IVec3::new(0, 0, -2))));
+        assert!(!frustum.intersects(&Aabb::of_chunk(IVec3::new(0, 0, 2))));
+
+        // A box rotated a quarter turn around the origin ends up on the other axis
+        let rotated = Aabb::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 1.0, 1.0))
+            .transformed(&Mat4::from_rotation_y(90f32.to_radians()));
+        assert!(rotated.min.abs_diff_eq(Vec3::new(0.0, 0.0, -2.0), 1e-5));
+        assert!(rotated.max.abs_diff_eq(Vec3::new(1.0, 1.0, -1.0), 1e-5));
+    }
+
+    #[test]
+    fn walls_hide_the_chunks_behind_them() {
+        let view = camera(Vec3::new(8.0, 8.0, 8.0), Vec3::new(8.0, 8.0, 100.0));
+        // A row of loaded chunks ahead of the camera, the second one a solid wall
+        let mut loaded = HashMap::new();
+        for z in 0..5 {
+            loaded.insert(IVec3::new(0, 0, z), FaceConnectivity::ALL);
+        }
+        let open = view.visible_chunks(4, |chunk| loaded.get(&chunk).copied());
+        assert!((0..5).all(|z| open.contains(&IVec3::new(0, 0, z))));
+        assert!(!open.contains(&IVec3::new(0, 0, -1)));
+
+        loaded.insert(IVec3::new(0, 0, 2), FaceConnectivity::NONE);
+        let walled = view.visible_chunks(4, |chunk| loaded.get(&chunk).copied());
+        // The wall itself is seen, nothing past it
+        assert!(walled.contains(&IVec3::new(0, 0, 2)));
+        assert!(!walled.contains(&IVec3::new(0, 0, 3)));
+        assert!(!walled.contains(&IVec3::new(0, 0, 4)));
+    }
+
+    #[test]
+    fn detail_drops_with_distance() {
+        let view = camera(Vec3::ZERO, Vec3::Z);
+        let chunk = |z| Aabb::of_chunk(IVec3::new(0, 0, z));
+        // The camera is inside the first chunk
+        assert_eq!(view.lod_level(&chunk(0)), 0);
+        assert_eq!(chunk(0).distance_to(Vec3::ZERO), 0.0);
+        assert_eq!(view.lod_level(&chunk(4)), 0);
+        assert_eq!(view.lod_level(&chunk(5)), 1);
+        assert_eq!(view.lod_level(&chunk(9)), 2);
+        assert_eq!(view.lod_level(&chunk(20)), 3);
+        assert_eq!(lod_level(Vec3::ZERO, &chunk(20), &[]), 0);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{bail, Result};
+use glam::{IVec3, Quat};
+use legion::IntoQuery;
+use parking_lot::RwLock;
+use tracing::error;
+
+use crate::{
+    asset_types::mesh::Mesh,
+    ecs::{
+        components::{
+            camera::Camera,
+            rendering_components::MeshRenderer,
+            transformation_components::{Position, Rotation},
+        },
+        systems::render_systems::{construct_buffers, construct_instances, construct_lights},
+        world::World,
+    },
+    state::State,
+    voxels::voxel_scene::{VoxelScene, CHUNK_SIZE},
+};
+
+use super::{backend::RenderBackend, material::Material};
+
+// Draws the world to the window with wgpu. Chunk meshes become mesh renderer entities drawn on the
+// render layer with the material
+pub struct WgpuBackend {
+    state: Arc<RwLock<State>>,
+    world: Arc<RwLock<World>>,
+    scene: Arc<RwLock<VoxelScene>>,
+    material: Arc<RwLock<dyn Material>>,
+    render_layer: String,
+    // Remeshed chunks reuse the renderer they already have
+    chunk_meshes: HashMap<IVec3, Arc<RwLock<Mesh>>>,
+}
+
+impl WgpuBackend {
+    pub fn new(
+        state: Arc<RwLock<State>>,
+        world: Arc<RwLock<World>>,
+        scene: Arc<RwLock<VoxelScene>>,
+        material: Arc<RwLock<dyn Material>>,
+        render_layer: String,
+    ) -> Self {
+        Self {
+            state,
+            world,
+            scene,
+            material,
+            render_layer,
+            chunk_meshes: HashMap::new(),
+        }
+    }
+}
+
+impl RenderBackend for WgpuBackend {
+    fn name(&self) -> &'static str {
+        "wgpu"
+    }
+
+    fn upload_chunk_mesh(&mut self, position: IVec3, mesh: Mesh) {
+        if let Some(existing) = self.chunk_meshes.get(&position) {
+            let mut existing_lock = existing.write();
+            existing_lock.set_vertices(mesh.get_vertices().clone());
+            existing_lock.set_indices(mesh.get_indices().clone());
+            return;
+        }
+        let mesh = Arc::new(RwLock::new(mesh));
+        self.chunk_meshes.insert(position, Arc::clone(&mesh));
+        self.world.write().legion_world.push((
+            Position(position.as_vec3() * CHUNK_SIZE as f32),
+            Rotation(Quat::IDENTITY),
+            MeshRenderer::new(mesh, Arc::clone(&self.material), self.render_layer.clone()),
+        ));
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.state
+            .write()
+            .resize(winit::dpi::PhysicalSize::new(width, height));
+    }
+
+    fn render_frame(&mut self) -> Result<()> {
+        let world_lock = self.world.read();
+        let cameras = <&Camera>::query()
+            .iter(&world_lock.legion_world)
+            .map(|camera| Arc::clone(&camera.camera))
+            .collect();
+        let mut state_lock = self.state.write();
+        let scene = self.scene.read();
+        construct_buffers(&state_lock, &world_lock.legion_world);
+        construct_instances(&state_lock, &world_lock.legion_world, &scene);
+        construct_lights(&mut state_lock, &world_lock.legion_world, &scene);
+        match state_lock.render(cameras) {
+            Ok(()) => Ok(()),
+            // Reconfigure the surface if lost or if it no longer matches the window
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                let size = state_lock.size;
+                state_lock.resize(size);
+                Ok(())
+            }
+            Err(wgpu::SurfaceError::OutOfMemory) => bail!("The GPU is out of memory"),
+            // Timeouts should be resolved by the next frame
+            Err(e) => {
+                error!("{e:?}");
+                Ok(())
+            }
+        }
+    }
+}
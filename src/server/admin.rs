@@ -0,0 +1,288 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::Arc,
+    thread,
+};
+
+use anyhow::{bail, Result};
+use glam::IVec3;
+use legion::IntoQuery;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{
+    ecs::components::{player_components::PlayerId, transformation_components::Position},
+    voxels::{
+        chunk_stats::{generation_stats, voxel_name},
+        voxel_registry::get_voxel_by_id,
+        voxel_scene::VoxelScene,
+    },
+};
+
+use super::Server;
+
+// Tools send one JSON-RPC request per line and get one response line back. The methods are
+// command {line}, voxel {position}, chunk {position} and chunk_stats {position} with a chunk
+// position, stats and players
+pub const DEFAULT_ADMIN_ADDRESS: &str = "127.0.0.1:25575";
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// A command that ran and failed
+const COMMAND_FAILED: i64 = -32000;
+
+// A JSON-RPC 2.0 request, one per line. Requests without an id are notifications and get no answer
+#[derive(Debug, PartialEq)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    params: Value,
+}
+
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+fn parse_request(line: &str) -> Result<Request, RpcError> {
+    let json: Value = serde_json::from_str(line)
+        .map_err(|e| RpcError::new(PARSE_ERROR, format!("The request isn't JSON: {e}")))?;
+    if json.get("jsonrpc").and_then(|v| v.as_str()) != Some("2.0") {
+        return Err(RpcError::new(
+            INVALID_REQUEST,
+            "Only JSON-RPC 2.0 is supported",
+        ));
+    }
+    let method = json
+        .get("method")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::new(INVALID_REQUEST, "The request has no method"))?;
+    Ok(Request {
+        id: json.get("id").cloned(),
+        method: method.to_string(),
+        params: json.get("params").cloned().unwrap_or(Value::Null),
+    })
+}
+
+fn position_param(params: &Value) -> Result<IVec3, RpcError> {
+    let position = params
+        .get("position")
+        .and_then(|v| v.as_array())
+        .map(|values| values.iter().map(|v| v.as_i64()).collect::<Vec<_>>());
+    match position.as_deref() {
+        Some([Some(x), Some(y), Some(z)]) => Ok(IVec3::new(*x as i32, *y as i32, *z as i32)),
+        _ => Err(RpcError::new(
+            INVALID_PARAMS,
+            "\"position\" has to be a list of three whole numbers",
+        )),
+    }
+}
+
+fn respond(server: &Server, request: &Request) -> Result<Value, RpcError> {
+    match request.method.as_str() {
+        "command" => {
+            let line = request
+                .params
+                .get("line")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "\"line\" has to be a command"))?;
+            server
+                .run_console_command(line)
+                .map(Value::String)
+                .map_err(|e| RpcError::new(COMMAND_FAILED, e.to_string()))
+        }
+        "voxel" => {
+            let position = position_param(&request.params)?;
+            Ok(match server.scene.read().voxel_at(&position) {
+                Some(voxel) => json!({
+                    "id": voxel.id,
+                    "name": get_voxel_by_id(voxel.id).map(|profile| profile.name.clone()),
+                    "shape": voxel.shape.data,
+                    "state": voxel.state,
+                }),
+                // Not loaded
+                None => Value::Null,
+            })
+        }
+        "chunk" => {
+            let position = position_param(&request.params)?;
+            let scene = server.scene.read();
+            Ok(match scene.chunks.get(&position) {
+                Some(chunk) => json!({
+                    "empty": chunk.is_empty,
+                    "dirty": chunk.dirty,
+                    "player_modified": chunk.player_modified,
+                    "solid_voxels": chunk.voxels().iter().filter(|v| v.id != 0).count(),
+                }),
+                None => Value::Null,
+            })
+        }
+        // What the generator gives the chunk, loaded or not
+        "chunk_stats" => {
+            let position = position_param(&request.params)?;
+            let stats = generation_stats(position)
+                .map_err(|e| RpcError::new(COMMAND_FAILED, e.to_string()))?;
+            let voxels = stats
+                .sorted_histogram()
+                .into_iter()
+                .map(|(id, count)| json!({ "id": id, "name": voxel_name(id), "count": count }))
+                .collect::<Vec<_>>();
+            Ok(json!({
+                "biome": stats.biome,
+                "min_density": stats.min_density,
+                "max_density": stats.max_density,
+                "solid_voxels": stats.solid_voxels(),
+                "voxels": voxels,
+            }))
+        }
+        "stats" => {
+            let world = server.world.read();
+            let scene = server.scene.read();
+            let (mut empty, mut dirty) = (0, 0);
+            for chunk in scene.chunks.iter() {
+                empty += chunk.is_empty as usize;
+                dirty += chunk.dirty as usize;
+            }
+            Ok(json!({
+                "chunks": scene.chunks.len(),
+                "empty_chunks": empty,
+                "dirty_chunks": dirty,
+                "entities": world.legion_world.len(),
+                "players": <&PlayerId>::query().iter(&world.legion_world).count(),
+            }))
+        }
+        "players" => {
+            let world = server.world.read();
+            let players = <(&PlayerId, &Position)>::query()
+                .iter(&world.legion_world)
+                .map(|(id, position)| {
+                    json!({
+                        "id": id.to_uuid_string(),
+                        "position": position.0.to_array(),
+                        "chunk": VoxelScene::chunk_at(&position.0.floor().as_ivec3()).to_array(),
+                    })
+                })
+                .collect();
+            Ok(Value::Array(players))
+        }
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("There is no method {method}"),
+        )),
+    }
+}
+
+// The answer to one line, None for notifications
+fn handle_line(server: &Server, line: &str) -> Option<Value> {
+    let (id, result) = match parse_request(line) {
+        Ok(request) => {
+            let result = respond(server, &request);
+            (request.id?, result)
+        }
+        Err(e) => (Value::Null, Err(e)),
+    };
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": e.code, "message": e.message },
+        }),
+    })
+}
+
+fn serve(server: &Server, stream: TcpStream) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_line(server, &line) {
+            writeln!(writer, "{response}")?;
+        }
+    }
+    Ok(())
+}
+
+impl Server {
+    // Answers JSON-RPC requests from tools on the same machine, with the console's permissions.
+    // Only loopback addresses are accepted since anyone who can connect can run every command
+    pub fn listen_admin(self: &Arc<Self>, address: impl ToSocketAddrs) -> Result<SocketAddr> {
+        let addresses = address.to_socket_addrs()?.collect::<Vec<_>>();
+        if let Some(address) = addresses.iter().find(|a| !a.ip().is_loopback()) {
+            bail!("The admin interface only listens on loopback addresses, not {address}");
+        }
+        let listener = TcpListener::bind(&addresses[..])?;
+        let address = listener.local_addr()?;
+        info!("Admin interface listening on {address}");
+        let server = Arc::clone(self);
+        thread::Builder::new()
+            .name("admin listener".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("Failed to accept an admin connection: {e}");
+                            continue;
+                        }
+                    };
+                    let server = Arc::clone(&server);
+                    let spawned = thread::Builder::new()
+                        .name("admin connection".to_string())
+                        .spawn(move || {
+                            if let Err(e) = serve(&server, stream) {
+                                warn!("Admin connection closed: {e}");
+                            }
+                        });
+                    if let Err(e) = spawned {
+                        warn!("Failed to start an admin connection: {e}");
+                    }
+                }
+            })?;
+        Ok(address)
+    }
+}
+
+#[cfg(test)]
+mod admin_tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_json_rpc() {
+        let request = parse_request(
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "voxel", "params": {"position": [1, -2, 3]}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.id, Some(json!(4)));
+        assert_eq!(position_param(&request.params), Ok(IVec3::new(1, -2, 3)));
+        assert_eq!(parse_request("voxel 1 2 3").unwrap_err().code, PARSE_ERROR);
+        assert_eq!(
+            parse_request(r#"{"id": 1, "method": "stats"}"#)
+                .unwrap_err()
+                .code,
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            position_param(&json!({ "position": [1, 2] }))
+                .unwrap_err()
+                .code,
+            INVALID_PARAMS
+        );
+    }
+}
@@ -0,0 +1,1108 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use glam::{IVec3, UVec3, Vec3};
+use legion::{Entity, EntityStore, IntoQuery};
+use tracing::warn;
+
+use crate::{
+    data_packs::find_resource,
+    ecs::{
+        components::{
+            player_components::{GameMode, PlayerId},
+            transformation_components::Position,
+        },
+        entities::{
+            item_drops::{spawn_dropped_item, MAX_STACK_SIZE},
+            player::set_game_mode,
+        },
+    },
+    error::WrongUsage,
+    export::{gltf::export_gltf, obj::export_obj, vox::export_vox, ExportRegion},
+    map::WorldMap,
+    memory::MemoryUsage,
+    network::{messages::ServerMessage, network_stats::connection_stats},
+    persistence::{pruning::PruneOptions, world_stats},
+    plugins::hot_reload::reload_plugins,
+    random,
+    voxels::{
+        chunk_stats::generation_stats,
+        jigsaw::{JigsawAssembler, JigsawLimits, PlacedPiece},
+        placeholders::{get_placeholder_handler, PlaceholderContext},
+        regions::{ProtectedRegion, RegionArea, Subject},
+        schematic::{
+            PlacementRule, Schematic, SchematicTransform, VoxelMask, MAX_FOUNDATION_DEPTH,
+        },
+        tick_monitor::tick_stats,
+        voxel_data::VoxelData,
+        voxel_registry::{get_voxel_by_name, loaded_mods},
+        voxel_scene::{VoxelChunk, VoxelScene, CHUNK_SIZE},
+        voxel_shapes::voxel_shape,
+    },
+};
+
+use super::{
+    replay::{self, ReplayEvent, REPLAY_FILE},
+    Server,
+};
+
+// The most items a single give spawns
+const MAX_GIVE_COUNT: u32 = 16 * MAX_STACK_SIZE;
+const MAP_FILE: &str = "map.png";
+const PRESET_FILE: &str = "preset.json";
+// The limits of the jigsaw command when it's given none
+const DEFAULT_JIGSAW_DEPTH: u32 = 6;
+const DEFAULT_JIGSAW_SIZE: u32 = 64;
+
+pub type CommandHandler = Arc<dyn Fn(&mut CommandContext, &[&str]) -> Result<String> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Command {
+    pub name: String,
+    pub usage: String,
+    pub description: String,
+    // Only operators and the server console can run it
+    pub operator_only: bool,
+    pub handler: CommandHandler,
+}
+
+impl Command {
+    pub fn new(
+        usage: &str,
+        description: &str,
+        operator_only: bool,
+        handler: impl Fn(&mut CommandContext, &[&str]) -> Result<String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: usage
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            usage: usage.to_string(),
+            description: description.to_string(),
+            operator_only,
+            handler: Arc::new(handler),
+        }
+    }
+}
+
+// What a command runs with. Commands can't reach the sessions, what they send is delivered by the server once they return
+pub struct CommandContext<'a> {
+    pub server: &'a Server,
+    // The player running the command, None on the server console
+    pub sender: Option<(PlayerId, Entity)>,
+    pub operator: bool,
+    // Chunks sent again to every remote client
+    pub resync_chunks: Vec<IVec3>,
+    // Messages for one player, or for every player when the id is None
+    pub messages: Vec<(Option<PlayerId>, ServerMessage)>,
+}
+
+impl<'a> CommandContext<'a> {
+    pub fn new(server: &'a Server, sender: Option<(PlayerId, Entity)>, operator: bool) -> Self {
+        Self {
+            server,
+            sender,
+            operator,
+            resync_chunks: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn sender_name(&self) -> String {
+        self.sender
+            .map_or("Server".to_string(), |(id, _)| display_name(id))
+    }
+
+    // The player running the command, for commands that act on the sender when no player is named
+    pub fn require_player(&self) -> Result<(PlayerId, Entity)> {
+        self.sender
+            .ok_or_else(|| anyhow!("A player has to be named on the console"))
+    }
+
+    pub fn broadcast(&mut self, text: String) {
+        let sender = Some(self.sender_name());
+        self.messages
+            .push((None, ServerMessage::Chat { sender, text }));
+    }
+}
+
+// Every command the server console, the debug console and chat can run, by name
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Command>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register(Command::new("help", "Lists the commands", false, help));
+        registry.register(Command::new(
+            "list",
+            "Lists the players online",
+            false,
+            list,
+        ));
+        registry.register(Command::new(
+            "memory",
+            "Shows the memory used by chunks, meshes, light and entities",
+            false,
+            memory,
+        ));
+        registry.register(Command::new(
+            "mods",
+            "Lists the loaded mods in load order",
+            false,
+            mods,
+        ));
+        registry.register(Command::new(
+            "netstats",
+            "Shows the bytes and packets sent and received on each connection by message category",
+            true,
+            network_statistics,
+        ));
+        registry.register(Command::new(
+            "say <text>",
+            "Sends a chat message to everyone",
+            false,
+            say,
+        ));
+        registry.register(Command::new("save", "Saves the world", true, save));
+        registry.register(Command::new(
+            "stats",
+            "Shows the world's statistics and how long it has been played",
+            false,
+            statistics,
+        ));
+        registry.register(Command::new(
+            "tps",
+            "Shows how many ticks the simulation runs per second and what it skips to keep up",
+            false,
+            ticks_per_second,
+        ));
+        registry.register(Command::new(
+            "tp [player] <x> <y> <z>",
+            "Teleports a player, ~ is relative to their position",
+            true,
+            teleport,
+        ));
+        registry.register(Command::new(
+            "gamemode [player] <creative|survival|spectator>",
+            "Changes a player's game mode, spectators fly through terrain unseen and can't edit",
+            true,
+            game_mode,
+        ));
+        registry.register(Command::new(
+            "give [player] <voxel> [count]",
+            "Drops voxels at a player, the count is required when a player is named",
+            true,
+            give,
+        ));
+        registry.register(Command::new(
+            "setvoxel <x> <y> <z> <voxel>",
+            "Places a voxel, ~ is relative to the sender and Empty clears it",
+            true,
+            set_voxel,
+        ));
+        registry.register(Command::new(
+            "regen [x y z]",
+            "Generates a chunk again, the sender's chunk if no position is given",
+            true,
+            regenerate,
+        ));
+        registry.register(Command::new(
+            "chunkstats [x y z]",
+            "Shows the biome, density range and voxel counts the generator gives a chunk",
+            false,
+            chunk_stats,
+        ));
+        registry.register(Command::new(
+            "exportmap [path]",
+            "Saves a top down map of the saved and loaded chunks as a PNG, in the world folder by default",
+            true,
+            export_map,
+        ));
+        registry.register(Command::new(
+            "export <x1> <y1> <z1> <x2> <y2> <z2> <path>",
+            "Saves the loaded voxels in a box for other tools, as .vox for MagicaVoxel or as an .obj or .glb mesh",
+            true,
+            export,
+        ));
+        registry.register(Command::new(
+            "prune [radius] [unmodified]",
+            "Saves, then removes saved chunks further than the radius from the sender and with unmodified the ones no player edited, they are generated again when visited",
+            true,
+            prune,
+        ));
+        registry.register(Command::new(
+            "exportpreset [path]",
+            "Saves the seed, biome overrides and dimensions to create the same world from with --preset, in the world folder by default",
+            true,
+            export_preset,
+        ));
+        registry.register(Command::new(
+            "record [path|stop]",
+            "Records edits and spawns to replay the world with --replay, in the world folder by default",
+            true,
+            record,
+        ));
+        registry.register(Command::new(
+            "structure <name> [rotation|random] [conform]",
+            "Places a structure from the resources at the sender, rotated by quarter turns. Conform extends its foundation down to the terrain",
+            true,
+            place_structure,
+        ));
+        registry.register(Command::new(
+            "jigsaw <pool> [max depth] [max size]",
+            "Assembles a structure at the sender from a pool's pieces, joining their sockets",
+            true,
+            place_jigsaw,
+        ));
+        registry.register(Command::new(
+            "structuremask <name> <voxel|all> <replace|onlyair|keep> [skip percent]",
+            "Sets how a structure's voxels are placed, onlyair only fills air and keep leaves the terrain",
+            true,
+            structure_mask,
+        ));
+        registry.register(Command::new(
+            "structuremarker <name> <x> <y> <z> <kind|none> [data]",
+            "Marks a spot in a structure that is filled in when it's placed, loot takes items like \"torch 1-3, stone 2\", spawn an entity and jigsaw structures like \"room|corridor\"",
+            true,
+            structure_marker,
+        ));
+        registry.register(Command::new(
+            "reload",
+            "Loads the plugins again, only in dev mode",
+            true,
+            reload,
+        ));
+        registry.register(Command::new(
+            "op <player>",
+            "Makes a player an operator",
+            true,
+            op,
+        ));
+        registry.register(Command::new(
+            "deop <player>",
+            "Removes an operator",
+            true,
+            deop,
+        ));
+        registry.register(Command::new(
+            "region <list|box|claim|remove|allow|deny> [name] [...]",
+            "Protects a box with box <name> x1 y1 z1 x2 y2 z2 or the sender's chunk with claim <name>, \
+             allow or deny <name> <player|group:name|everyone> sets who can edit it",
+            true,
+            region,
+        ));
+        registry.register(Command::new(
+            "group <add|remove> <group> <player>",
+            "Adds a player to a group region rules can name, or removes them",
+            true,
+            group,
+        ));
+        registry
+    }
+
+    // A command with the same name as an existing one replaces it
+    pub fn register(&mut self, command: Command) {
+        if let Some(previous) = self.commands.insert(command.name.clone(), command) {
+            warn!(
+                "Command {} was registered twice, the last one is used",
+                previous.name
+            );
+        }
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &Command> {
+        self.commands.values()
+    }
+
+    // Runs a line such as "tp 0 80 0", a leading slash is ignored. Returns the output for the sender
+    pub fn execute(&self, context: &mut CommandContext, line: &str) -> Result<String> {
+        let mut words = line.trim().trim_start_matches('/').split_whitespace();
+        let name = words.next().ok_or_else(|| anyhow!("No command given"))?;
+        let command = self
+            .commands
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown command {name}, type help for a list of commands"))?;
+        if command.operator_only && !context.operator {
+            bail!("Only operators can use {name}");
+        }
+        let args = words.collect::<Vec<_>>();
+        (command.handler)(context, &args).map_err(|e| match e.is::<WrongUsage>() {
+            true => anyhow!("Usage: {}", command.usage),
+            false => e,
+        })
+    }
+}
+
+// The first part of the player's id, shown in chat and command output
+pub fn display_name(id: PlayerId) -> String {
+    id.to_uuid_string()[..8].to_string()
+}
+
+// Finds an online player by the start of their id
+pub fn find_player(server: &Server, prefix: &str) -> Result<(PlayerId, Entity)> {
+    let world_lock = server.world.read();
+    let mut query = <(Entity, &PlayerId)>::query();
+    let mut matches = query
+        .iter(&world_lock.legion_world)
+        .filter(|(_, id)| id.to_uuid_string().starts_with(prefix))
+        .map(|(entity, id)| (*id, *entity));
+    match (matches.next(), matches.next()) {
+        (Some(player), None) => Ok(player),
+        (Some(_), Some(_)) => bail!("{prefix} matches several players"),
+        (None, _) => bail!("No player online matches {prefix}"),
+    }
+}
+
+fn player_position(server: &Server, entity: Entity) -> Result<Vec3> {
+    let world_lock = server.world.read();
+    let entry = world_lock.legion_world.entry_ref(entity)?;
+    Ok(entry.get_component::<Position>()?.0)
+}
+
+// A number, or ~ followed by an optional offset from the current value
+fn parse_coordinate(text: &str, current: f32) -> Result<f32> {
+    let (base, offset) = match text.strip_prefix('~') {
+        Some("") => return Ok(current),
+        Some(offset) => (current, offset),
+        None => (0.0, text),
+    };
+    let offset = offset
+        .parse::<f32>()
+        .map_err(|_| anyhow!("{text} isn't a coordinate"))?;
+    Ok(base + offset)
+}
+
+fn help(context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    // The registry is already read by the running command
+    let registry = context.server.commands.read_recursive();
+    let lines = registry
+        .commands()
+        .filter(|command| context.operator || !command.operator_only)
+        .map(|command| format!("{} - {}", command.usage, command.description))
+        .collect::<Vec<_>>();
+    Ok(lines.join("\n"))
+}
+
+fn list(context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    let world_lock = context.server.world.read();
+    let mut query = <(&PlayerId, &Position)>::query();
+    let players = query.iter(&world_lock.legion_world).collect::<Vec<_>>();
+    let mut lines = vec![format!("{} players online", players.len())];
+    for (id, position) in players {
+        lines.push(format!("{} at {}", id.to_uuid_string(), position.0.round()));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn memory(context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    let world_lock = context.server.world.read();
+    let scene = context.server.scene.read();
+    Ok(MemoryUsage::measure(&world_lock.legion_world, &scene).to_string())
+}
+
+fn mods(_context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    let mods = loaded_mods();
+    let mut lines = vec![format!("{} mods loaded", mods.len())];
+    for (id, version) in mods {
+        lines.push(format!("{id} {version}"));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn network_statistics(_context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    let connections = connection_stats();
+    if connections.is_empty() {
+        return Ok("No connections are open".to_string());
+    }
+    Ok(connections
+        .iter()
+        .map(|stats| stats.to_string())
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn say(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    if args.is_empty() {
+        bail!(WrongUsage);
+    }
+    context.broadcast(args.join(" "));
+    Ok(String::new())
+}
+
+fn save(context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    let saved = context.server.autosave.flush_blocking()?;
+    Ok(format!("Saved {saved} chunks"))
+}
+
+fn statistics(_context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    let stats = world_stats::current();
+    let minutes = (stats.play_time / 60.0) as u64;
+    Ok(format!(
+        "Played for {}h {}m, {} voxels broken, {} placed, {:.0} blocks traveled, {} chunks generated",
+        minutes / 60,
+        minutes % 60,
+        stats.voxels_broken,
+        stats.voxels_placed,
+        stats.distance_traveled,
+        stats.chunks_generated
+    ))
+}
+
+fn ticks_per_second(_context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    Ok(tick_stats().to_string())
+}
+
+fn teleport(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let ((id, entity), coordinates) = match args.len() {
+        3 => (context.require_player()?, args),
+        4 => (find_player(context.server, args[0])?, &args[1..]),
+        _ => bail!(WrongUsage),
+    };
+    let current = player_position(context.server, entity)?;
+    let position = Vec3::new(
+        parse_coordinate(coordinates[0], current.x)?,
+        parse_coordinate(coordinates[1], current.y)?,
+        parse_coordinate(coordinates[2], current.z)?,
+    );
+    // The player is moved once the chunks around the destination are loaded
+    context.server.teleport(id, entity, position);
+    Ok(format!("Teleporting {} to {}", display_name(id), position))
+}
+
+fn game_mode(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let ((id, entity), name) = match args {
+        [name] => (context.require_player()?, *name),
+        [player, name] => (find_player(context.server, player)?, *name),
+        _ => bail!(WrongUsage),
+    };
+    let game_mode = GameMode::from_name(name).ok_or_else(|| anyhow!("Unknown game mode {name}"))?;
+    set_game_mode(
+        &mut context.server.world.write().legion_world,
+        entity,
+        game_mode,
+    );
+    // Remote clients move their own player, so they change its mode themselves
+    context
+        .messages
+        .push((Some(id), ServerMessage::GameModeChanged(game_mode)));
+    Ok(format!("{} is now in {name} mode", display_name(id)))
+}
+
+fn give(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let ((id, entity), voxel, count) = match args {
+        [voxel] => (context.require_player()?, *voxel, "1"),
+        [voxel, count] if context.sender.is_some() => (context.require_player()?, *voxel, *count),
+        [player, voxel, count] => (find_player(context.server, player)?, *voxel, *count),
+        _ => bail!(WrongUsage),
+    };
+    let profile = get_voxel_by_name(voxel.to_string())
+        .filter(|profile| profile.id != 0)
+        .ok_or_else(|| anyhow!("Unknown voxel {voxel}"))?;
+    let count = count
+        .parse::<u32>()
+        .map_err(|_| anyhow!("{count} isn't a number"))?
+        .clamp(1, MAX_GIVE_COUNT);
+    let position = player_position(context.server, entity)?;
+
+    let mut world_lock = context.server.world.write();
+    let scene = context.server.scene.read();
+    let mut remaining = count;
+    while remaining > 0 {
+        let stack = remaining.min(MAX_STACK_SIZE);
+        let event = ReplayEvent::SpawnItem {
+            position,
+            voxel: profile.id,
+            count: stack,
+        };
+        replay::capture(&scene, &world_lock, &event);
+        spawn_dropped_item(&mut world_lock.legion_world, position, profile.id, stack);
+        replay::record(event);
+        remaining -= stack;
+    }
+    Ok(format!(
+        "Gave {count} {} to {}",
+        profile.display_name(),
+        display_name(id)
+    ))
+}
+
+fn set_voxel(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (coordinates, voxel) = match args {
+        [x, y, z, voxel] => ([*x, *y, *z], *voxel),
+        _ => bail!(WrongUsage),
+    };
+    // Relative coordinates need a sender, absolute ones also work on the console
+    let current = match context.sender {
+        Some((_, entity)) => player_position(context.server, entity)?,
+        None => Vec3::ZERO,
+    };
+    let position = Vec3::new(
+        parse_coordinate(coordinates[0], current.x)?,
+        parse_coordinate(coordinates[1], current.y)?,
+        parse_coordinate(coordinates[2], current.z)?,
+    )
+    .floor()
+    .as_ivec3();
+    let profile =
+        get_voxel_by_name(voxel.to_string()).ok_or_else(|| anyhow!("Unknown voxel {voxel}"))?;
+    let data = VoxelData {
+        shape: voxel_shape::CUBE,
+        state: 0,
+        id: profile.id,
+    };
+
+    let world_lock = context.server.world.read();
+    let scene = context.server.scene.read();
+    let event = ReplayEvent::SetVoxel {
+        position,
+        voxel: data,
+    };
+    replay::capture(&scene, &world_lock, &event);
+    world_lock
+        .edit(&scene, "setvoxel", |scene| scene.set_voxel(&position, data))
+        .ok_or_else(|| anyhow!("{position} isn't in a loaded chunk"))?;
+    replay::record(event);
+    Ok(format!("Placed {} at {position}", profile.display_name()))
+}
+
+// The chunk named by x y z, or the sender's chunk without arguments
+fn chunk_argument(context: &CommandContext, args: &[&str]) -> Result<IVec3> {
+    Ok(match args {
+        [] => {
+            let (_, entity) = context.require_player()?;
+            let position = player_position(context.server, entity)?;
+            VoxelScene::chunk_at(&position.floor().as_ivec3())
+        }
+        [x, y, z] => {
+            let parse = |text: &str| {
+                text.parse::<i32>()
+                    .map_err(|_| anyhow!("{text} isn't a chunk coordinate"))
+            };
+            IVec3::new(parse(*x)?, parse(*y)?, parse(*z)?)
+        }
+        _ => bail!(WrongUsage),
+    })
+}
+
+// Replaces a loaded chunk with freshly generated terrain, edits in it are lost
+fn regenerate(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let chunk_pos = chunk_argument(context, args)?;
+    let scene = context.server.scene.read();
+    if scene.voxel_at(&(chunk_pos * CHUNK_SIZE as i32)).is_none() {
+        bail!("Chunk {chunk_pos} isn't loaded");
+    }
+    let chunk = VoxelChunk::generate(chunk_pos)?;
+    scene.insert_chunk(chunk_pos, chunk.voxels().clone(), chunk.biome.clone());
+    // The saved copy is replaced on the next autosave
+    scene.mark_chunk_dirty(&chunk_pos);
+    context.resync_chunks.push(chunk_pos);
+    Ok(format!("Regenerated chunk {chunk_pos}"))
+}
+
+// Generates the chunk on the side to describe it, the loaded chunk isn't touched
+fn chunk_stats(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let chunk_pos = chunk_argument(context, args)?;
+    Ok(generation_stats(chunk_pos)?.summary())
+}
+
+fn export_map(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let path = match args {
+        [] => match context.server.save.directory() {
+            Some(directory) => directory.join(MAP_FILE),
+            None => bail!("The world is only kept in memory, give a path for the map"),
+        },
+        [path] => PathBuf::from(path),
+        _ => bail!(WrongUsage),
+    };
+    let mut map = WorldMap::new();
+    map.add_saved(&context.server.save.chunk_storage())?;
+    map.add_loaded(&context.server.scene.read(), None);
+    let image = map
+        .render_all()
+        .ok_or_else(|| anyhow!("There are no chunks to map"))?;
+    image.save_png(&path)?;
+    Ok(format!(
+        "Saved a {}x{} map to {}",
+        image.width,
+        image.height,
+        path.display()
+    ))
+}
+
+// The format is chosen by the file extension
+fn export(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (coordinates, path) = match args {
+        [coordinates @ .., path] if coordinates.len() == 6 => (coordinates, PathBuf::from(path)),
+        _ => bail!(WrongUsage),
+    };
+    let current = match context.sender {
+        Some((_, entity)) => player_position(context.server, entity)?,
+        None => Vec3::ZERO,
+    };
+    let corner = |c: &[&str]| -> Result<IVec3> {
+        Ok(Vec3::new(
+            parse_coordinate(c[0], current.x)?,
+            parse_coordinate(c[1], current.y)?,
+            parse_coordinate(c[2], current.z)?,
+        )
+        .floor()
+        .as_ivec3())
+    };
+    let region = ExportRegion::new(corner(&coordinates[..3])?, corner(&coordinates[3..])?);
+    let scene = context.server.scene.read();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let exported = match extension.to_ascii_lowercase().as_str() {
+        "vox" => format!("{} voxels", export_vox(&scene, region, &path)?),
+        "obj" => format!("{} triangles", export_obj(&scene, region, &path)?),
+        "glb" => format!("{} triangles", export_gltf(&scene, region, &path)?),
+        _ => bail!("Unknown export format \"{extension}\", use .vox, .obj or .glb"),
+    };
+    Ok(format!("Exported {exported} to {}", path.display()))
+}
+
+fn export_preset(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let path = match args {
+        [] => match context.server.save.directory() {
+            Some(directory) => directory.join(PRESET_FILE),
+            None => bail!("The world is only kept in memory, give a path for the preset"),
+        },
+        [path] => PathBuf::from(path),
+        _ => bail!(WrongUsage),
+    };
+    context.server.save.export_preset(&path)?;
+    Ok(format!("Saved the world preset to {}", path.display()))
+}
+
+fn prune(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let mut options = PruneOptions {
+        center: IVec3::ZERO,
+        radius: None,
+        remove_unmodified: false,
+    };
+    for arg in args {
+        match *arg {
+            "unmodified" => options.remove_unmodified = true,
+            radius => {
+                let radius = radius
+                    .parse::<u32>()
+                    .map_err(|_| anyhow!("{radius} isn't a radius in chunks"))?;
+                options.radius = Some(radius);
+            }
+        }
+    }
+    if options.radius.is_none() && !options.remove_unmodified {
+        bail!(WrongUsage);
+    }
+    if let Some((_, entity)) = context.sender {
+        let position = player_position(context.server, entity)?.floor().as_ivec3();
+        options.center = VoxelScene::chunk_at(&position);
+    }
+    // Loaded chunks aren't written again unless they change, their edits have to be on disk first
+    context.server.autosave.flush_blocking()?;
+    let report = context.server.save.prune_chunks(&options)?;
+    Ok(format!(
+        "Removed {} of {} saved chunks, freeing {} KiB",
+        report.removed,
+        report.examined,
+        report.bytes_freed / 1024
+    ))
+}
+
+// Starts recording the world's inputs for --replay, or stops and ends the recording with a checksum
+fn record(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let path = match args {
+        ["stop"] => {
+            return match replay::stop_recording(&context.server.scene.read())? {
+                Some(path) => Ok(format!("Saved the replay to {}", path.display())),
+                None => bail!("Nothing is being recorded"),
+            }
+        }
+        [] => match context.server.save.directory() {
+            Some(directory) => directory.join(REPLAY_FILE),
+            None => bail!("The world is only kept in memory, give a path for the replay"),
+        },
+        [path] => PathBuf::from(path),
+        _ => bail!(WrongUsage),
+    };
+    replay::start_recording(&path, &context.server.save)?;
+    Ok(format!("Recording to {}", path.display()))
+}
+
+fn place_structure(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (name, options) = match args {
+        [name, options @ ..] if options.len() <= 2 => (*name, options),
+        _ => bail!(WrongUsage),
+    };
+    let (_, entity) = context.require_player()?;
+    let origin = player_position(context.server, entity)?.floor().as_ivec3();
+    let structure = Schematic::load_structure(name)?;
+    let mut transform = SchematicTransform::default();
+    let mut conform = false;
+    for option in options {
+        match *option {
+            "conform" => conform = true,
+            // Seeded by the spot, so the same place gets the same turn
+            "random" => {
+                let mut rng = random::overworld()
+                    .position(origin)
+                    .derive_name("structure rotation")
+                    .rng();
+                transform = SchematicTransform::random(&mut rng);
+            }
+            rotation => {
+                let rotation = rotation
+                    .parse::<u8>()
+                    .map_err(|_| anyhow!("{rotation} isn't a number of quarter turns"))?;
+                transform.rotation = rotation % 4;
+            }
+        }
+    }
+
+    let mut world_lock = context.server.world.write();
+    let scene = context.server.scene.read();
+    let placed = match conform {
+        true => structure.place_conforming(&scene, origin, transform),
+        false => structure.place(&scene, origin, transform),
+    };
+    let mut placeholders = PlaceholderContext::new(&scene, &mut world_lock.legion_world);
+    let resolved = structure.resolve_placeholders(&mut placeholders, origin, transform);
+    let mut boxes = placeholders.placed;
+    drop(scene);
+    drop(world_lock);
+
+    let (min, max) = structure.bounds(origin, transform);
+    let foundation = match conform {
+        true => IVec3::Y * MAX_FOUNDATION_DEPTH,
+        false => IVec3::ZERO,
+    };
+    boxes.push((min - foundation, max));
+    resync_boxes(context, &boxes);
+    Ok(format!(
+        "Placed {name} with {placed} voxels and {resolved} placeholders"
+    ))
+}
+
+fn place_jigsaw(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (pool, limits) = match args {
+        [pool, limits @ ..] if limits.len() <= 2 => (*pool, limits),
+        _ => bail!(WrongUsage),
+    };
+    let mut limits = limits
+        .iter()
+        .map(|limit| {
+            limit
+                .parse::<u32>()
+                .map_err(|_| anyhow!("{limit} isn't a number"))
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter();
+    let limits = JigsawLimits {
+        max_depth: limits.next().unwrap_or(DEFAULT_JIGSAW_DEPTH),
+        max_size: limits.next().unwrap_or(DEFAULT_JIGSAW_SIZE) as i32,
+    };
+    let (_, entity) = context.require_player()?;
+    let origin = player_position(context.server, entity)?.floor().as_ivec3();
+    let mut rng = random::overworld().position(origin).derive_name(pool).rng();
+    let pieces = JigsawAssembler::new(limits).assemble(pool, origin, &mut rng)?;
+
+    let mut world_lock = context.server.world.write();
+    let scene = context.server.scene.read();
+    let mut placeholders = PlaceholderContext::new(&scene, &mut world_lock.legion_world);
+    let mut placed = 0;
+    for piece in &pieces {
+        placed += piece.schematic.place(&scene, piece.origin, piece.transform);
+        piece
+            .schematic
+            .resolve_placeholders(&mut placeholders, piece.origin, piece.transform);
+    }
+    let mut boxes = placeholders.placed;
+    drop(scene);
+    drop(world_lock);
+
+    boxes.extend(pieces.iter().map(PlacedPiece::bounds));
+    resync_boxes(context, &boxes);
+    Ok(format!(
+        "Assembled {} pieces from {pool} with {placed} voxels",
+        pieces.len()
+    ))
+}
+
+// Resends every chunk the boxes touch, the corners are inclusive
+fn resync_boxes(context: &mut CommandContext, boxes: &[(IVec3, IVec3)]) {
+    for (min, max) in boxes {
+        let (min, max) = (VoxelScene::chunk_at(min), VoxelScene::chunk_at(max));
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    context.resync_chunks.push(IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+    context.resync_chunks.sort_by_key(|c| (c.x, c.y, c.z));
+    context.resync_chunks.dedup();
+}
+
+// Changes the structure's file where it was found, which can be in a data pack
+fn structure_mask(_context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (name, voxel, rule, skip_chance) = match args {
+        [name, voxel, rule] => (*name, *voxel, *rule, 0),
+        [name, voxel, rule, skip] => (
+            *name,
+            *voxel,
+            *rule,
+            skip.parse::<u8>()
+                .ok()
+                .filter(|skip| *skip <= 100)
+                .ok_or_else(|| anyhow!("{skip} isn't a percentage"))?,
+        ),
+        _ => bail!(WrongUsage),
+    };
+    let rule = PlacementRule::from_name(rule).ok_or_else(|| anyhow!("Unknown rule {rule}"))?;
+    let id = match voxel {
+        "all" => None,
+        voxel => Some(
+            get_voxel_by_name(voxel.to_string())
+                .ok_or_else(|| anyhow!("There is no voxel named {voxel}"))?
+                .id,
+        ),
+    };
+    let path = find_resource(&format!("structures/{name}.schematic"))
+        .ok_or_else(|| anyhow!("There is no structure named {name}"))?;
+    let mut structure = Schematic::load(&path)?;
+    let changed = structure.set_masks_for(id, VoxelMask { rule, skip_chance });
+    structure.save(&path)?;
+    Ok(format!("Changed how {changed} voxels of {name} are placed"))
+}
+
+fn structure_marker(_context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (name, position, kind, data) = match args {
+        [name, x, y, z, kind, data @ ..] => (*name, [*x, *y, *z], *kind, data.join(" ")),
+        _ => bail!(WrongUsage),
+    };
+    let position = position
+        .map(|coordinate| coordinate.parse::<u32>())
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow!("The position is counted from the structure's corner, 0 and up"))?;
+    let position = UVec3::from_slice(&position);
+    let path = find_resource(&format!("structures/{name}.schematic"))
+        .ok_or_else(|| anyhow!("There is no structure named {name}"))?;
+    let mut structure = Schematic::load(&path)?;
+    if position.cmpge(structure.size).any() {
+        bail!("{position} is outside {name}, which is {}", structure.size);
+    }
+    let message = match kind {
+        "none" => {
+            let removed = structure.set_placeholder(position, None);
+            format!("Removed {removed} placeholders from {name}")
+        }
+        kind => {
+            if get_placeholder_handler(kind).is_none() {
+                bail!("There is no placeholder handler for {kind}");
+            }
+            structure.set_placeholder(position, Some((kind, &data)));
+            format!("Marked {position} of {name} as {kind}")
+        }
+    };
+    structure.save(&path)?;
+    Ok(message)
+}
+
+fn reload(_context: &mut CommandContext, _args: &[&str]) -> Result<String> {
+    let reloaded = reload_plugins(false)?;
+    Ok(format!(
+        "Reloaded {} mods {}",
+        reloaded.len(),
+        reloaded.join(", ")
+    ))
+}
+
+fn op(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (id, _) = match args {
+        [player] => find_player(context.server, player)?,
+        _ => bail!(WrongUsage),
+    };
+    context.server.set_operator(id, true)?;
+    Ok(format!("{} is now an operator", display_name(id)))
+}
+
+fn deop(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (id, _) = match args {
+        [player] => find_player(context.server, player)?,
+        _ => bail!(WrongUsage),
+    };
+    context.server.set_operator(id, false)?;
+    Ok(format!("{} is no longer an operator", display_name(id)))
+}
+
+// An online player by the start of their id, or any player by their full id
+fn find_player_id(server: &Server, name: &str) -> Result<PlayerId> {
+    match PlayerId::parse(name) {
+        Some(id) => Ok(id),
+        None => Ok(find_player(server, name)?.0),
+    }
+}
+
+fn region(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let server = context.server;
+    match args {
+        ["list"] => {
+            let regions = server.regions();
+            let lines = regions
+                .iter()
+                .map(|region| {
+                    let area = match &region.area {
+                        RegionArea::Box { min, max } => format!("{min} to {max}"),
+                        RegionArea::Chunks(chunks) => format!("{} chunks", chunks.len()),
+                    };
+                    let rules = region
+                        .rules()
+                        .iter()
+                        .map(|(subject, allow)| {
+                            format!("{}{}", if *allow { "+" } else { "-" }, subject.name())
+                        })
+                        .collect::<Vec<_>>();
+                    let everyone = if region.allow_everyone {
+                        "open"
+                    } else {
+                        "closed"
+                    };
+                    format!("{}: {area}, {everyone} {}", region.name, rules.join(" "))
+                })
+                .collect::<Vec<_>>();
+            match lines.is_empty() {
+                true => Ok("There are no protected regions".to_string()),
+                false => Ok(lines.join("\n")),
+            }
+        }
+        ["box", name, coordinates @ ..] if coordinates.len() == 6 => {
+            let current = match context.sender {
+                Some((_, entity)) => player_position(server, entity)?,
+                None => Vec3::ZERO,
+            };
+            let corner = |c: &[&str]| -> Result<IVec3> {
+                Ok(Vec3::new(
+                    parse_coordinate(c[0], current.x)?,
+                    parse_coordinate(c[1], current.y)?,
+                    parse_coordinate(c[2], current.z)?,
+                )
+                .floor()
+                .as_ivec3())
+            };
+            let (a, b) = (corner(&coordinates[..3])?, corner(&coordinates[3..])?);
+            server.protect_region(ProtectedRegion::new(name, RegionArea::new_box(a, b)))?;
+            Ok(format!(
+                "Protected {name} from {} to {}",
+                a.min(b),
+                a.max(b)
+            ))
+        }
+        ["claim", name] => {
+            let (_, entity) = context.require_player()?;
+            let chunk = VoxelScene::chunk_at(&player_position(server, entity)?.floor().as_ivec3());
+            server.update_regions(|regions| {
+                match regions.get_mut(name) {
+                    Some(region) => match &mut region.area {
+                        RegionArea::Chunks(chunks) => {
+                            chunks.insert(chunk);
+                        }
+                        RegionArea::Box { .. } => bail!("{name} is a box, not a set of chunks"),
+                    },
+                    None => regions.add(ProtectedRegion::new(
+                        name,
+                        RegionArea::Chunks([chunk].into_iter().collect()),
+                    )),
+                }
+                Ok(format!("Added chunk {chunk} to {name}"))
+            })
+        }
+        ["remove", name] => server.update_regions(|regions| match regions.remove(name) {
+            true => Ok(format!("Removed the region {name}")),
+            false => bail!("There's no region named {name}"),
+        }),
+        [rule @ ("allow" | "deny"), name, subject] => {
+            let allow = *rule == "allow";
+            let subject = match *subject {
+                "everyone" => None,
+                text => match Subject::parse(text) {
+                    Some(subject) => Some(subject),
+                    None => Some(Subject::Player(find_player_id(server, text)?)),
+                },
+            };
+            server.update_regions(|regions| {
+                let region = regions
+                    .get_mut(name)
+                    .ok_or_else(|| anyhow!("There's no region named {name}"))?;
+                let who = match subject {
+                    Some(subject) => {
+                        let who = match &subject {
+                            Subject::Player(id) => display_name(*id),
+                            Subject::Group(group) => format!("The group {group}"),
+                        };
+                        region.set_rule(subject, Some(allow));
+                        who
+                    }
+                    None => {
+                        region.allow_everyone = allow;
+                        "Everyone".to_string()
+                    }
+                };
+                let can = if allow { "can" } else { "can't" };
+                Ok(format!("{who} {can} edit {name}"))
+            })
+        }
+        _ => bail!(WrongUsage),
+    }
+}
+
+fn group(context: &mut CommandContext, args: &[&str]) -> Result<String> {
+    let (member, group, player) = match args {
+        ["add", group, player] => (true, *group, *player),
+        ["remove", group, player] => (false, *group, *player),
+        _ => bail!(WrongUsage),
+    };
+    let id = find_player_id(context.server, player)?;
+    context.server.update_regions(|regions| {
+        regions.set_member(group, id, member);
+        Ok(())
+    })?;
+    Ok(match member {
+        true => format!("{} is now in {group}", display_name(id)),
+        false => format!("{} is no longer in {group}", display_name(id)),
+    })
+}
+
+#[cfg(test)]
+mod commands_tests {
+    use super::*;
+
+    #[test]
+    fn coordinates_can_be_relative() {
+        assert_eq!(parse_coordinate("12.5", 3.0).unwrap(), 12.5);
+        assert_eq!(parse_coordinate("~", 3.0).unwrap(), 3.0);
+        assert_eq!(parse_coordinate("~-1", 3.0).unwrap(), 2.0);
+        assert!(parse_coordinate("north", 3.0).is_err());
+    }
+
+    #[test]
+    fn command_name_comes_from_usage() {
+        let command = Command::new(
+            "tp [player] <x> <y> <z>",
+            "",
+            true,
+            |_, _| Ok(String::new()),
+        );
+        assert_eq!(command.name, "tp");
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    io::{self, BufRead},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use glam::IVec3;
+use parking_lot::RwLock;
+use tracing::{error, info, warn};
+
+use crate::{
+    ecs::world::World,
+    jobs::PendingJobs,
+    persistence::{
+        world_preset::WorldPreset,
+        world_save::{WorldMetadata, WorldSave},
+    },
+    voxels::voxel_scene::VoxelScene,
+};
+
+use super::{admin::DEFAULT_ADMIN_ADDRESS, Server, SPAWN_POSITION};
+
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:25565";
+// Chunks generated around the spawn before anyone joins, the radius is horizontal and the height counts up from y 0
+const SPAWN_RADIUS: i32 = 8;
+const SPAWN_HEIGHT: i32 = 5;
+
+pub struct HeadlessOptions {
+    pub world_path: PathBuf,
+    pub address: String,
+    // Allows reloading plugins while the server runs
+    pub dev: bool,
+    // Where the admin interface listens, off unless given
+    pub admin: Option<String>,
+    // Creates the world from this preset if there is no world yet
+    pub preset: Option<PathBuf>,
+}
+
+impl HeadlessOptions {
+    // Reads --world <path>, --address <host:port>, --admin [host:port], --preset <path> and --dev, anything else is
+    // ignored with a warning. --allow-newer-world is read by main for every mode
+    pub fn from_args(args: impl Iterator<Item = String>) -> Self {
+        let mut options = Self {
+            world_path: PathBuf::from("./saves/world"),
+            address: DEFAULT_ADDRESS.to_string(),
+            dev: false,
+            admin: None,
+            preset: None,
+        };
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.peek()) {
+                ("--world", Some(_)) => options.world_path = PathBuf::from(args.next().unwrap()),
+                ("--address", Some(_)) => options.address = args.next().unwrap(),
+                ("--admin", Some(next)) if !next.starts_with("--") => options.admin = args.next(),
+                ("--admin", _) => options.admin = Some(DEFAULT_ADMIN_ADDRESS.to_string()),
+                ("--dev", _) => options.dev = true,
+                ("--preset", Some(_)) => options.preset = Some(PathBuf::from(args.next().unwrap())),
+                ("--server" | "--allow-newer-world", _) => {}
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        options
+    }
+}
+
+// Runs the server core without a window or renderer until stop is entered on the console
+pub fn run(options: HeadlessOptions) -> Result<()> {
+    let world = Arc::new(RwLock::new(World::new()));
+    let save = match options.preset {
+        Some(preset) if !WorldSave::exists(&options.world_path) => {
+            let preset = WorldPreset::load(&preset)?;
+            WorldSave::create_from_preset(options.world_path, "world".to_string(), &preset)?
+        }
+        preset => {
+            if preset.is_some() {
+                warn!("Ignoring --preset, the world already exists");
+            }
+            WorldSave::open_or_create(
+                options.world_path,
+                WorldMetadata::new("world".to_string(), rand::random(), "plains".to_string()),
+            )?
+        }
+    };
+    let save = Arc::new(save);
+    let server = Server::start(save, world);
+
+    let spawn_chunk = VoxelScene::chunk_at(&SPAWN_POSITION.as_ivec3());
+    let scene = &server.scene;
+    for x in -SPAWN_RADIUS..=SPAWN_RADIUS {
+        for y in 0..SPAWN_HEIGHT {
+            for z in -SPAWN_RADIUS..=SPAWN_RADIUS {
+                let chunk_pos = IVec3::new(spawn_chunk.x + x, y, spawn_chunk.z + z);
+                scene.read().initialize_and_generate_chunk(chunk_pos);
+            }
+        }
+    }
+
+    server.listen(&options.address)?;
+    if let Some(address) = &options.admin {
+        server.listen_admin(address.as_str())?;
+    }
+    info!("Server started, type help for a list of commands");
+    run_console(&server);
+
+    match server.shutdown(PendingJobs::Cancel) {
+        Ok(saved) => info!("Saved {saved} chunks"),
+        Err(e) => error!("Failed to save the world: {e}"),
+    }
+    Ok(())
+}
+
+// Reads commands from stdin until stop is entered or stdin is closed, everything but stop goes through
+// the server's command registry
+fn run_console(server: &Server) {
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to read the console: {e}");
+                return;
+            }
+        };
+        match line.trim() {
+            "" => {}
+            "stop" => return,
+            line => {
+                match server.run_console_command(line) {
+                    Ok(output) => output.lines().for_each(|l| println!("{l}")),
+                    Err(e) => println!("{e}"),
+                }
+                if line == "help" {
+                    println!("stop - Saves the world and stops the server");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,812 @@
+use std::{
+    collections::HashSet,
+    net::{TcpListener, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use flume::{Receiver, Sender};
+use glam::{EulerRot, IVec3, Quat, Vec3};
+use legion::{Entity, EntityStore};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use tracing::{error, info, warn};
+
+use self::{
+    commands::{display_name, CommandContext, CommandRegistry},
+    replay::ReplayEvent,
+    validation::{validate_break, validate_place, EditRateLimiter, EditRejection},
+};
+use crate::{
+    crash_report,
+    ecs::{
+        components::{
+            player_components::{Player, PlayerId},
+            transformation_components::Position,
+        },
+        entities::player::spawn_player,
+        world::{AreaReady, World},
+    },
+    events::{self, BlockBroken, PlayerJoined},
+    jobs::{self, PendingJobs},
+    network::{
+        chunk_stream::ChunkStreamer,
+        connection::Connection,
+        handshake::{check_protocol_version, check_voxel_ids, JoinRequest},
+        messages::{ClientMessage, ServerMessage, MAX_CHAT_LENGTH},
+        replication::{EntityReplicator, NetworkIds, ReplicatedEntity, DEFAULT_ENTITY_UPDATE_RATE},
+        send_queue::{Priority, SendQueue, DEFAULT_BANDWIDTH},
+    },
+    persistence::{
+        autosave::{Autosave, DEFAULT_AUTOSAVE_INTERVAL},
+        player_data::PlayerData,
+        world_save::WorldSave,
+        world_stats,
+    },
+    plugins::hot_reload::{dev_mode, reload_plugins},
+    random,
+    voxels::{
+        regions::{ProtectedRegion, Regions},
+        voxel_data::VoxelData,
+        voxel_interaction::{
+            entity_colliders, player_break_voxel, player_place_voxel, EditingPlayer,
+        },
+        voxel_registry::voxel_id_mappings,
+        voxel_scene::VoxelScene,
+        voxel_simulation::{VoxelSimulation, TICKS_PER_SECOND},
+    },
+};
+
+pub mod admin;
+pub mod commands;
+pub mod headless;
+pub mod null_render;
+pub mod pregen;
+pub mod preview;
+pub mod replay;
+pub mod validation;
+
+pub const SPAWN_POSITION: Vec3 = Vec3::new(0.0, 80.0, 0.0);
+// One player id per line, operators can run the commands that change the world
+const OPERATORS_FILE: &str = "operators";
+// Protected regions and the player groups their rules name
+const REGIONS_FILE: &str = "regions.json";
+// How often changed plugins are reloaded in dev mode
+const PLUGIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// Chunks around a teleport's destination that are ready before the player is moved there
+const TELEPORT_PRELOAD_RADIUS: i32 = 2;
+// Teleports waiting longer than this for their area go ahead anyway
+const TELEPORT_TIMEOUT: Duration = Duration::from_secs(10);
+// Players moving further than this in one tick were teleported, which isn't counted as traveled
+const MAX_TRAVEL_STEP: f32 = 16.0;
+
+// A connected client, the player is spawned once the client has joined
+struct Session {
+    connection: Connection,
+    player: Option<(PlayerId, Entity)>,
+    // Local clients read the server's scene and world directly, so only remote clients have chunks
+    // and entities replicated to them
+    streamer: Option<ChunkStreamer>,
+    replicator: Option<EntityReplicator>,
+    edit_limiter: EditRateLimiter,
+    // Sequences of the accepted edits waiting to be confirmed to a remote client
+    applied_edits: Vec<u32>,
+    // Where the player was last tick, for the distance traveled statistic
+    last_position: Option<Vec3>,
+    queue: SendQueue,
+}
+
+// A player that's moved once the chunks around where they're going are ready
+struct PendingTeleport {
+    id: PlayerId,
+    entity: Entity,
+    position: Vec3,
+    ready: AreaReady,
+    started: Instant,
+}
+
+// The server core owns the world, generation and simulation. Clients only talk to it through messages,
+// either over an in process connection for singleplayer or over a socket for multiplayer
+pub struct Server {
+    pub save: Arc<WorldSave>,
+    pub scene: Arc<RwLock<VoxelScene>>,
+    pub world: Arc<RwLock<World>>,
+    pub autosave: Arc<Autosave>,
+    new_connections: Sender<Connection>,
+    voxel_changes: Receiver<(IVec3, VoxelData)>,
+    // Entity transform updates sent to each client per second
+    entity_update_rate: AtomicU32,
+    // Bytes per second sent to each remote client
+    bandwidth: AtomicUsize,
+    regions: RwLock<Regions>,
+    // Shared by chat and the console, plugins can add their own commands
+    pub commands: RwLock<CommandRegistry>,
+    operators: RwLock<HashSet<PlayerId>>,
+    // Console lines run on the server thread, which owns the sessions the output is sent to
+    console_commands: Sender<(String, Sender<Result<String>>)>,
+    teleports: Mutex<Vec<PendingTeleport>>,
+    // Cleared on shutdown, the session and simulation threads exit on their next tick
+    running: Arc<AtomicBool>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Server {
+    pub fn start(save: Arc<WorldSave>, world: Arc<RwLock<World>>) -> Arc<Self> {
+        random::set_world_seed(save.metadata().seed);
+        let mut voxel_scene = VoxelScene::new();
+        voxel_scene.set_storage(save.chunk_storage());
+        let scene = Arc::new(RwLock::new(voxel_scene));
+
+        let autosave = Autosave::start(
+            Arc::clone(&save),
+            Arc::clone(&scene),
+            Arc::clone(&world),
+            DEFAULT_AUTOSAVE_INTERVAL,
+        );
+
+        let running = Arc::new(AtomicBool::new(true));
+        let simulation = VoxelSimulation::new(Arc::clone(&scene), Arc::clone(&world));
+        let simulation_running = Arc::clone(&running);
+        let simulation = thread::Builder::new()
+            .name("simulation".to_string())
+            .spawn(move || simulation.run(simulation_running))
+            .unwrap();
+
+        let voxel_changes = scene.read().get_voxel_change_receiver();
+        let (new_connections, connection_receiver) = flume::unbounded();
+        let (console_commands, command_receiver) = flume::unbounded();
+        let operators = load_operators(&save);
+        let regions = load_regions(&save).unwrap_or_else(|e| {
+            error!("Failed to load the protected regions, the world has none: {e}");
+            Regions::default()
+        });
+        let server = Arc::new(Self {
+            save,
+            scene,
+            world,
+            autosave,
+            new_connections,
+            voxel_changes,
+            entity_update_rate: AtomicU32::new(DEFAULT_ENTITY_UPDATE_RATE),
+            bandwidth: AtomicUsize::new(DEFAULT_BANDWIDTH),
+            regions: RwLock::new(regions),
+            commands: RwLock::new(CommandRegistry::with_builtins()),
+            operators: RwLock::new(operators),
+            console_commands,
+            teleports: Mutex::new(Vec::new()),
+            running,
+            threads: Mutex::new(vec![simulation]),
+        });
+
+        let sessions = Arc::clone(&server);
+        let sessions = thread::Builder::new()
+            .name("server".to_string())
+            .spawn(move || sessions.run_sessions(connection_receiver, command_receiver))
+            .unwrap();
+        server.threads.lock().push(sessions);
+
+        let autosave = Arc::downgrade(&server.autosave);
+        let emergency_save = move || match autosave.upgrade() {
+            Some(autosave) => autosave.emergency_save(),
+            None => Ok(0),
+        };
+        crash_report::set_world(Some((
+            server.save.metadata().name,
+            Box::new(emergency_save),
+        )));
+
+        server
+    }
+
+    // Stops everything in an order that leaves a complete save: background jobs other than saves are
+    // refused and the queued ones cancelled or drained, clients are disconnected and the simulation
+    // stopped, then the world is saved and the job workers joined. Returns the chunks saved
+    pub fn shutdown(&self, pending: PendingJobs) -> Result<usize> {
+        info!("Shutting down");
+        crash_report::set_world(None);
+        jobs::stop(pending);
+        self.running.store(false, Ordering::Relaxed);
+        for thread in self.threads.lock().drain(..) {
+            if thread.join().is_err() {
+                error!("A server thread panicked while shutting down");
+            }
+        }
+        if let Err(e) = replay::stop_recording(&self.scene.read()) {
+            warn!("Couldn't finish the replay: {e}");
+        }
+        let saved = self.autosave.stop();
+        jobs::shutdown();
+        saved
+    }
+
+    // Returns the client end of a new in process connection
+    pub fn connect_local(&self) -> Connection {
+        let (client, server) = Connection::local_pair();
+        self.new_connections.send(server).unwrap();
+        client
+    }
+
+    // Accepts clients on the address until the process exits
+    pub fn listen(&self, address: impl ToSocketAddrs) -> Result<()> {
+        let listener = TcpListener::bind(address)?;
+        info!("Listening for clients on {}", listener.local_addr()?);
+        let new_connections = self.new_connections.clone();
+        thread::Builder::new()
+            .name("listener".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream
+                        .map_err(anyhow::Error::from)
+                        .and_then(Connection::from_stream)
+                    {
+                        Ok(connection) => {
+                            if new_connections.send(connection).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => warn!("Failed to accept a client: {e}"),
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    pub fn set_entity_update_rate(&self, updates_per_second: u32) {
+        self.entity_update_rate
+            .store(updates_per_second.max(1), Ordering::Relaxed);
+    }
+
+    pub fn set_bandwidth(&self, bytes_per_second: usize) {
+        self.bandwidth
+            .store(bytes_per_second.max(1), Ordering::Relaxed);
+    }
+
+    // Edits inside the region are rejected unless its rules allow them
+    pub fn protect_region(&self, region: ProtectedRegion) -> Result<()> {
+        info!("Protected region {}", region.name);
+        self.update_regions(|regions| {
+            regions.add(region);
+            Ok(())
+        })
+    }
+
+    // Changes the regions and saves them to the world
+    pub fn update_regions<T>(&self, change: impl FnOnce(&mut Regions) -> Result<T>) -> Result<T> {
+        let mut regions = self.regions.write();
+        let result = change(&mut regions)?;
+        let json = serde_json::to_string_pretty(&regions.to_json())?;
+        self.save.write_file(REGIONS_FILE, json.as_bytes())?;
+        Ok(result)
+    }
+
+    pub fn regions(&self) -> RwLockReadGuard<'_, Regions> {
+        self.regions.read()
+    }
+
+    pub fn is_operator(&self, id: PlayerId) -> bool {
+        self.operators.read().contains(&id)
+    }
+
+    pub fn set_operator(&self, id: PlayerId, operator: bool) -> Result<()> {
+        let mut operators = self.operators.write();
+        match operator {
+            true => operators.insert(id),
+            false => operators.remove(&id),
+        };
+        let contents = operators
+            .iter()
+            .map(|id| format!("{}\n", id.to_uuid_string()))
+            .collect::<String>();
+        self.save.write_file(OPERATORS_FILE, contents.as_bytes())
+    }
+
+    // Runs a command with the console's permissions and waits for its output
+    pub fn run_console_command(&self, line: &str) -> Result<String> {
+        let (reply_sender, reply_receiver) = flume::bounded(1);
+        self.console_commands
+            .send((line.to_string(), reply_sender))
+            .map_err(|_| anyhow!("The server has stopped"))?;
+        reply_receiver.recv()?
+    }
+
+    fn run_sessions(
+        &self,
+        connection_receiver: Receiver<Connection>,
+        command_receiver: Receiver<(String, Sender<Result<String>>)>,
+    ) {
+        let tick_length = Duration::from_secs_f64(1.0 / TICKS_PER_SECOND as f64);
+        let mut sessions: Vec<Session> = Vec::new();
+        let mut network_ids = NetworkIds::new();
+        let mut plugin_check = Instant::now();
+        while self.running.load(Ordering::Relaxed) {
+            let tick_start = Instant::now();
+            for connection in connection_receiver.try_iter() {
+                info!("{} connected", connection.remote);
+                let remote = !connection.is_local;
+                sessions.push(Session {
+                    connection,
+                    player: None,
+                    streamer: remote.then(ChunkStreamer::new),
+                    replicator: remote.then(EntityReplicator::new),
+                    edit_limiter: EditRateLimiter::new(),
+                    applied_edits: Vec::new(),
+                    last_position: None,
+                    queue: SendQueue::new(),
+                });
+            }
+            for (line, reply) in command_receiver.try_iter() {
+                let _ = reply.send(self.execute_command(&mut sessions, None, true, &line));
+            }
+            self.finish_teleports(&mut sessions);
+            if dev_mode() && plugin_check.elapsed() >= PLUGIN_CHECK_INTERVAL {
+                plugin_check = Instant::now();
+                let _ = reload_plugins(true);
+            }
+
+            let changes = self.voxel_changes.try_iter().collect::<Vec<_>>();
+            let entities = match sessions.iter().any(|s| s.replicator.is_some()) {
+                true => network_ids.collect(&self.world.read().legion_world),
+                false => Vec::new(),
+            };
+            let mut index = 0;
+            while index < sessions.len() {
+                match self.update_session(&mut sessions, index, &changes, &entities) {
+                    Ok(true) => index += 1,
+                    Ok(false) => self.close_session(sessions.swap_remove(index), None),
+                    Err(e) => self.close_session(sessions.swap_remove(index), Some(e.to_string())),
+                }
+            }
+
+            let elapsed = tick_start.elapsed();
+            if elapsed < tick_length {
+                thread::sleep(tick_length - elapsed);
+            }
+        }
+        for session in sessions.drain(..) {
+            self.close_session(session, Some("The server is shutting down".to_string()));
+        }
+    }
+
+    // Handles every message the client has sent, returns false once the client has disconnected
+    fn update_session(
+        &self,
+        sessions: &mut [Session],
+        index: usize,
+        changes: &[(IVec3, VoxelData)],
+        entities: &[ReplicatedEntity],
+    ) -> Result<bool> {
+        // Edits from the previous tick are confirmed after this tick's deltas, which include their results
+        let confirmed_edits = std::mem::take(&mut sessions[index].applied_edits);
+        while let Some(message) = sessions[index].connection.try_recv::<ClientMessage>()? {
+            match message {
+                // A refused join closes the session, the reason is sent to the client as it disconnects
+                ClientMessage::Join {
+                    protocol_version,
+                    request,
+                } => {
+                    let reply = self.join(sessions, index, protocol_version, request)?;
+                    sessions[index].queue.push(&reply, Priority::Control)?;
+                }
+                ClientMessage::BreakVoxel { sequence, position } => {
+                    self.apply_edit(&mut sessions[index], sequence, position, None)?;
+                }
+                ClientMessage::PlaceVoxel {
+                    sequence,
+                    position,
+                    voxel,
+                } => {
+                    self.apply_edit(&mut sessions[index], sequence, position, Some(voxel))?;
+                }
+                ClientMessage::StartBreak(position) => {
+                    sessions[index].edit_limiter.start_break(position);
+                }
+                ClientMessage::Disconnect => return Ok(false),
+                ClientMessage::ChunkAck(sequence) => {
+                    if let Some(streamer) = &mut sessions[index].streamer {
+                        streamer.acknowledge(sequence);
+                    }
+                }
+                ClientMessage::ResyncChunk(position) => {
+                    if let Some(streamer) = &mut sessions[index].streamer {
+                        streamer.resync(position);
+                    }
+                }
+                ClientMessage::Chat(text) => self.chat(sessions, index, &text)?,
+            }
+        }
+
+        // Nothing is streamed or replicated before the player has joined
+        let session = &mut sessions[index];
+        let player = session.player.map(|(_, entity)| entity);
+        let position = player.and_then(|entity| player_state(&self.world.read(), entity));
+        if let Some((_, position)) = position {
+            if let Some(last) = session.last_position.replace(position) {
+                let step = position.distance(last);
+                if step <= MAX_TRAVEL_STEP {
+                    world_stats::record(|stats| stats.distance_traveled += step as f64);
+                }
+            }
+            let center = VoxelScene::chunk_at(&position.round().as_ivec3());
+            if let Some(streamer) = &mut session.streamer {
+                for (position, voxel) in changes {
+                    streamer.send_delta(*position, *voxel, center, &mut session.queue)?;
+                }
+                for sequence in confirmed_edits {
+                    session
+                        .queue
+                        .push(&ServerMessage::EditAccepted(sequence), Priority::Near)?;
+                }
+                streamer.update(&self.scene.read(), center, &mut session.queue)?;
+            }
+            if let Some(replicator) = &mut session.replicator {
+                let update_rate = self.entity_update_rate.load(Ordering::Relaxed);
+                replicator.update(entities, player, center, update_rate, &mut session.queue)?;
+            }
+        }
+        let bandwidth = self.bandwidth.load(Ordering::Relaxed);
+        session.queue.flush(&session.connection, bandwidth)?;
+        Ok(true)
+    }
+
+    // Validates and applies a break, or a place when a voxel is given. Local clients share the server's
+    // scene and don't predict, so only remote clients are told about accepted edits
+    fn apply_edit(
+        &self,
+        session: &mut Session,
+        sequence: u32,
+        position: IVec3,
+        placed: Option<VoxelData>,
+    ) -> Result<()> {
+        let world_lock = self.world.read();
+        let scene = self.scene.read();
+        let state = session.player.and_then(|(id, entity)| {
+            player_state(&world_lock, entity).map(|(player, eye)| (id, player, eye))
+        });
+        let editor = match state {
+            Some((id, player, eye)) => EditingPlayer {
+                id,
+                player,
+                eye,
+                // Whoever plays on the machine running the server owns it
+                operator: session.connection.is_local || self.is_operator(id),
+            },
+            None => {
+                return reject_edit(
+                    &scene,
+                    &mut session.queue,
+                    sequence,
+                    position,
+                    EditRejection::Invalid,
+                )
+            }
+        };
+        let regions = self.regions.read();
+        let limiter = &mut session.edit_limiter;
+        let colliders = match placed {
+            Some(_) => entity_colliders(&world_lock.legion_world),
+            None => Vec::new(),
+        };
+        let result = match placed {
+            Some(voxel) => validate_place(
+                &scene, &regions, limiter, &editor, position, voxel, &colliders,
+            ),
+            None => validate_break(&scene, &regions, limiter, &editor, position),
+        };
+        // Subscribers can still stop a valid break, the client undoes it like any other rejection
+        let result = result.and_then(|()| match placed {
+            Some(_) => Ok(()),
+            None => {
+                let mut event = BlockBroken {
+                    position,
+                    voxel: scene.voxel_at(&position).ok_or(EditRejection::Invalid)?,
+                    player: editor.id,
+                };
+                match events::emit(&mut event) {
+                    true => Ok(()),
+                    false => Err(EditRejection::Cancelled),
+                }
+            }
+        });
+        match result {
+            Ok(()) => {
+                match placed {
+                    Some(voxel) => {
+                        let event = ReplayEvent::SetVoxel { position, voxel };
+                        replay::capture(&scene, &world_lock, &event);
+                        if player_place_voxel(
+                            &scene, &regions, &editor, position, voxel, &colliders,
+                        ) {
+                            replay::record(event);
+                        }
+                        world_stats::record(|stats| stats.voxels_placed += 1);
+                    }
+                    None => {
+                        let drops = editor.player.game_mode.drops_items();
+                        let event = ReplayEvent::BreakVoxel { position, drops };
+                        replay::capture(&scene, &world_lock, &event);
+                        if player_break_voxel(&scene, &regions, &editor, position).is_some() {
+                            replay::record(event);
+                        }
+                        world_stats::record(|stats| stats.voxels_broken += 1);
+                    }
+                }
+                if !session.connection.is_local {
+                    session.applied_edits.push(sequence);
+                }
+                Ok(())
+            }
+            Err(rejection) => {
+                reject_edit(&scene, &mut session.queue, sequence, position, rejection)
+            }
+        }
+    }
+
+    // Broadcasts the text to every player, or runs it as a command if it starts with a slash
+    fn chat(&self, sessions: &mut [Session], index: usize, text: &str) -> Result<()> {
+        let (id, entity) = match sessions[index].player {
+            Some(player) => player,
+            None => bail!("The client sent a chat message before joining"),
+        };
+        let text = text
+            .trim()
+            .chars()
+            .take(MAX_CHAT_LENGTH)
+            .collect::<String>();
+        if text.is_empty() {
+            return Ok(());
+        }
+        if text.starts_with('/') {
+            // Whoever plays on the machine running the server owns it
+            let operator = sessions[index].connection.is_local || self.is_operator(id);
+            let output = match self.execute_command(sessions, Some((id, entity)), operator, &text) {
+                Ok(output) => output,
+                Err(e) => e.to_string(),
+            };
+            if !output.is_empty() {
+                let reply = ServerMessage::Chat {
+                    sender: None,
+                    text: output,
+                };
+                sessions[index].queue.push(&reply, Priority::Near)?;
+            }
+            return Ok(());
+        }
+        let name = display_name(id);
+        info!(target: "chat", "<{name}> {text}");
+        let message = ServerMessage::Chat {
+            sender: Some(name),
+            text,
+        };
+        deliver(sessions, None, &message);
+        Ok(())
+    }
+
+    // Runs a command and hands what it sent to the sessions
+    fn execute_command(
+        &self,
+        sessions: &mut [Session],
+        sender: Option<(PlayerId, Entity)>,
+        operator: bool,
+        line: &str,
+    ) -> Result<String> {
+        let mut context = CommandContext::new(self, sender, operator);
+        let result = self.commands.read().execute(&mut context, line);
+        for chunk_pos in context.resync_chunks {
+            for session in sessions.iter_mut() {
+                if let Some(streamer) = &mut session.streamer {
+                    streamer.resync(chunk_pos);
+                }
+            }
+        }
+        for (target, message) in context.messages {
+            if let ServerMessage::Chat { sender, text } = &message {
+                info!(target: "chat", "<{}> {text}", sender.as_deref().unwrap_or("Server"));
+            }
+            deliver(sessions, target, &message);
+        }
+        result
+    }
+
+    // Starts preparing the area around the position, the player is moved there once it's ready
+    pub fn teleport(&self, id: PlayerId, entity: Entity, position: Vec3) {
+        let ready = {
+            let world = self.world.read();
+            let scene = self.scene.read();
+            world.prepare_area(&scene, position, TELEPORT_PRELOAD_RADIUS)
+        };
+        let mut teleports = self.teleports.lock();
+        // A newer teleport of the same player replaces the one still waiting
+        teleports.retain(|teleport| teleport.id != id);
+        teleports.push(PendingTeleport {
+            id,
+            entity,
+            position,
+            ready,
+            started: Instant::now(),
+        });
+    }
+
+    fn finish_teleports(&self, sessions: &mut [Session]) {
+        let mut teleports = self.teleports.lock();
+        let mut index = 0;
+        while index < teleports.len() {
+            let teleport = &mut teleports[index];
+            let waited = teleport.started.elapsed();
+            match teleport.ready.check() {
+                Some(true) => {}
+                Some(false) => warn!("Some chunks around {} were cancelled", teleport.position),
+                None if waited >= TELEPORT_TIMEOUT => warn!(
+                    "The chunks around {} weren't ready after {waited:.1?}, teleporting anyway",
+                    teleport.position
+                ),
+                None => {
+                    index += 1;
+                    continue;
+                }
+            }
+            let teleport = teleports.swap_remove(index);
+            if let Some(mut entry) = self.world.write().legion_world.entry(teleport.entity) {
+                if let Ok(position) = entry.get_component_mut::<Position>() {
+                    position.0 = teleport.position;
+                }
+            }
+            // Remote clients move their own player, so they're told where it is now
+            deliver(
+                sessions,
+                Some(teleport.id),
+                &ServerMessage::Teleport(teleport.position),
+            );
+        }
+    }
+
+    fn join(
+        &self,
+        sessions: &mut [Session],
+        index: usize,
+        protocol_version: u32,
+        request: Option<JoinRequest>,
+    ) -> Result<ServerMessage> {
+        check_protocol_version(protocol_version)?;
+        let request = request.ok_or_else(|| anyhow!("The join request couldn't be read"))?;
+        check_voxel_ids(&voxel_id_mappings(), &request.voxel_ids)?;
+        let player_id = request.player_id;
+        if sessions[index].player.is_some() {
+            bail!("The client has already joined");
+        }
+        if sessions
+            .iter()
+            .any(|s| matches!(s.player, Some((id, _)) if id == player_id))
+        {
+            bail!("This player is already connected");
+        }
+        let storage = self.save.player_storage();
+        match storage.authenticate(player_id, request.secret) {
+            Ok(true) => {}
+            Ok(false) => bail!("This player id belongs to someone else"),
+            Err(e) => {
+                error!(
+                    "Failed to check the secret of player {}: {e}",
+                    player_id.to_uuid_string()
+                );
+                bail!("Failed to check the player's identity");
+            }
+        }
+
+        let mut event = PlayerJoined {
+            player: player_id,
+            reason: None,
+        };
+        if !events::emit(&mut event) {
+            bail!(event
+                .reason
+                .unwrap_or_else(|| "The server refused the join".to_string()));
+        }
+
+        let mut world_lock = self.world.write();
+        let entity = spawn_player(
+            &mut world_lock.legion_world,
+            SPAWN_POSITION,
+            Quat::from_euler(EulerRot::XYZ, 0.0, (45.0 as f32).to_radians(), 0.0),
+            Player::default(),
+        );
+        // The saved position and game mode replace the defaults if this player has played the world before
+        if let Err(e) = storage.join(&mut world_lock.legion_world, entity, player_id) {
+            world_lock.legion_world.remove(entity);
+            error!("Failed to load player {}: {e}", player_id.to_uuid_string());
+            bail!("Failed to load the player data");
+        }
+
+        let entry = world_lock.legion_world.entry_ref(entity).unwrap();
+        let data = PlayerData::from_entry(&entry).unwrap();
+        sessions[index].player = Some((player_id, entity));
+        info!(
+            "{} joined as {}",
+            sessions[index].connection.remote,
+            player_id.to_uuid_string()
+        );
+        Ok(ServerMessage::JoinAccepted {
+            world_name: self.save.metadata().name,
+            position: data.position,
+            rotation: data.rotation,
+            game_mode: data.game_mode,
+        })
+    }
+
+    // Saves and removes the player, the connection may already be gone so the reason is sent on a best effort basis
+    fn close_session(&self, session: Session, error: Option<String>) {
+        if let Some(reason) = &error {
+            let _ = session.connection.send(&ServerMessage::Disconnected {
+                reason: reason.clone(),
+            });
+        }
+        info!(
+            "{} disconnected{}",
+            session.connection.remote,
+            error.map_or(String::new(), |e| format!(": {e}"))
+        );
+        if let Some((_, entity)) = session.player {
+            let mut world_lock = self.world.write();
+            let entry = world_lock.legion_world.entry_ref(entity).ok();
+            if let Some(data) = entry.and_then(|entry| PlayerData::from_entry(&entry)) {
+                self.autosave.save_player(data);
+            }
+            world_lock.legion_world.remove(entity);
+        }
+    }
+}
+
+// Tells the client what the voxel really is so it can undo its prediction
+fn reject_edit(
+    scene: &VoxelScene,
+    queue: &mut SendQueue,
+    sequence: u32,
+    position: IVec3,
+    rejection: EditRejection,
+) -> Result<()> {
+    let rejected = ServerMessage::EditRejected {
+        sequence,
+        position,
+        voxel: scene.voxel_at(&position),
+        reason: rejection.to_string(),
+    };
+    queue.push(&rejected, Priority::Near)
+}
+
+// Sends to the player with the id, or to every player when it's None. A full queue closes its session
+// on the next push, so failures are ignored here
+fn deliver(sessions: &mut [Session], target: Option<PlayerId>, message: &ServerMessage) {
+    for session in sessions.iter_mut() {
+        let joined = match session.player {
+            Some((id, _)) => target.map_or(true, |target| target == id),
+            None => false,
+        };
+        if joined {
+            let _ = session.queue.push(message, Priority::Near);
+        }
+    }
+}
+
+fn load_operators(save: &WorldSave) -> HashSet<PlayerId> {
+    let contents = save.read_file(OPERATORS_FILE).ok().flatten();
+    let contents = String::from_utf8(contents.unwrap_or_default()).unwrap_or_default();
+    contents.lines().filter_map(PlayerId::parse).collect()
+}
+
+// A world without the file has no regions
+fn load_regions(save: &WorldSave) -> Result<Regions> {
+    match save.read_file(REGIONS_FILE)? {
+        Some(bytes) => Regions::from_json(&serde_json::from_slice(&bytes)?),
+        None => Ok(Regions::default()),
+    }
+}
+
+fn player_state(world: &World, entity: Entity) -> Option<(Player, Vec3)> {
+    let entry = world.legion_world.entry_ref(entity).ok()?;
+    let player = *entry.get_component::<Player>().ok()?;
+    let position = entry.get_component::<Position>().ok()?.0;
+    Some((player, position))
+}
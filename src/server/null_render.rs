@@ -0,0 +1,150 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use glam::IVec3;
+use parking_lot::RwLock;
+use tracing::{info, warn};
+
+use crate::{
+    config,
+    ecs::world::World,
+    jobs::PendingJobs,
+    persistence::world_save::{WorldMetadata, WorldSave, WorldSettings},
+    rendering::backend::{NullBackend, RenderBackend},
+    voxels::voxel_scene::VoxelScene,
+};
+
+use super::{pregen::parse_number, Server, SPAWN_POSITION};
+
+const FRAME_LENGTH: Duration = Duration::from_micros(16_667);
+
+pub struct NullRenderOptions {
+    pub frames: u64,
+    // Replaces the random seed, for runs that should mesh the same terrain every time
+    pub seed: Option<u64>,
+    // Chunk columns in every horizontal direction around the spawn chunk
+    pub radius: i32,
+    // Chunk layers counting up from y 0
+    pub height: i32,
+}
+
+impl NullRenderOptions {
+    // Reads --frames <count>, --seed <seed>, --radius <chunks> and --height <chunks>
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self {
+            frames: 300,
+            seed: None,
+            radius: 2,
+            height: 4,
+        };
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.peek()) {
+                ("--frames", Some(_)) => options.frames = parse_number(&arg, args.next())?,
+                ("--seed", Some(_)) => options.seed = Some(parse_number(&arg, args.next())?),
+                ("--radius", Some(_)) => options.radius = parse_number(&arg, args.next())?,
+                ("--height", Some(_)) => options.height = parse_number(&arg, args.next())?,
+                ("--null-render", _) => {}
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        if options.radius < 0 || options.height < 1 {
+            bail!("The radius can't be negative and the height has to be at least 1");
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NullRenderReport {
+    pub frames: u64,
+    pub chunks: usize,
+    pub vertices: usize,
+}
+
+// Runs the server and the frame loop against the null backend on an in-memory world, so the
+// generation and meshing a client would see can be checked without a window or a GPU. Fails when
+// no chunk was meshed in time
+pub fn run(options: NullRenderOptions) -> Result<NullRenderReport> {
+    let metadata = WorldMetadata::new(
+        "null render".to_string(),
+        options.seed.unwrap_or_else(rand::random),
+        "plains".to_string(),
+    );
+    let save = WorldSave::open_or_create_with(
+        PathBuf::new(),
+        metadata,
+        WorldSettings { in_memory: true },
+    )?;
+    let server = Server::start(Arc::new(save), Arc::new(RwLock::new(World::new())));
+    let (mesh_sender, meshes) = flume::unbounded();
+    server.scene.write().set_mesh_sender(mesh_sender);
+    let spawn_chunk = VoxelScene::chunk_at(&SPAWN_POSITION.as_ivec3());
+    for x in -options.radius..=options.radius {
+        for y in 0..options.height {
+            for z in -options.radius..=options.radius {
+                let position = IVec3::new(spawn_chunk.x + x, y, spawn_chunk.z + z);
+                server.scene.read().initialize_and_generate_chunk(position);
+            }
+        }
+    }
+
+    let mut backend = NullBackend::new();
+    let start = Instant::now();
+    for _ in 0..options.frames {
+        let frame_start = Instant::now();
+        server
+            .scene
+            .read()
+            .spawn_queued_meshes(config::current().remesh_budget);
+        for (position, mesh) in meshes.try_iter() {
+            backend.upload_chunk_mesh(position, mesh);
+        }
+        backend.render_frame()?;
+        let elapsed = frame_start.elapsed();
+        if elapsed < FRAME_LENGTH {
+            thread::sleep(FRAME_LENGTH - elapsed);
+        }
+    }
+    server.shutdown(PendingJobs::Cancel)?;
+
+    let report = NullRenderReport {
+        frames: backend.frames(),
+        chunks: backend.chunk_count(),
+        vertices: backend.vertex_count(),
+    };
+    info!(
+        "Rendered {} frames with the {} backend in {:.1?}, {} chunks meshed with {} vertices",
+        report.frames,
+        backend.name(),
+        start.elapsed(),
+        report.chunks,
+        report.vertices
+    );
+    if report.chunks == 0 {
+        bail!("No chunk was meshed in {} frames", report.frames);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod null_render_tests {
+    use super::*;
+
+    #[test]
+    fn options_are_read_from_the_arguments() {
+        let args = ["--null-render", "--frames", "60", "--height", "2"];
+        let options = NullRenderOptions::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!(options.frames, 60);
+        assert_eq!(options.radius, 2);
+        assert_eq!(options.height, 2);
+
+        let args = ["--height", "0"];
+        assert!(NullRenderOptions::from_args(args.iter().map(|arg| arg.to_string())).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc, time::Instant};
+
+use anyhow::{bail, Result};
+use glam::IVec3;
+use tracing::{info, warn};
+
+use crate::{
+    persistence::{
+        chunk_storage::ChunkPayload,
+        world_save::{WorldMetadata, WorldSave},
+        world_stats,
+    },
+    progress::{Progress, ProgressBar},
+    random,
+    voxels::{generation_pipeline::GenerationPipeline, voxel_scene::VoxelScene},
+};
+
+use super::SPAWN_POSITION;
+
+pub struct PregenOptions {
+    pub world_path: PathBuf,
+    // Used when the world is created, an existing world has to have the same seed
+    pub seed: Option<u64>,
+    // Chunks in every horizontal direction around the spawn chunk
+    pub radius: i32,
+    // Chunk layers counting up from y 0
+    pub height: i32,
+    // Generates chunks that are already saved again, edits in them are lost
+    pub force: bool,
+}
+
+impl PregenOptions {
+    // Reads --world <path>, --seed <seed>, --radius <chunks>, --height <chunks> and --force
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self {
+            world_path: PathBuf::from("./saves/world"),
+            seed: None,
+            radius: 16,
+            height: 5,
+            force: false,
+        };
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.peek()) {
+                ("--world", Some(_)) => options.world_path = PathBuf::from(args.next().unwrap()),
+                ("--seed", Some(_)) => options.seed = Some(parse_number(&arg, args.next())?),
+                ("--radius", Some(_)) => options.radius = parse_number(&arg, args.next())?,
+                ("--height", Some(_)) => options.height = parse_number(&arg, args.next())?,
+                ("--force", _) => options.force = true,
+                ("--pregen" | "--allow-newer-world", _) => {}
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        if options.radius < 0 || options.height < 1 {
+            bail!("The radius can't be negative and the height has to be at least 1");
+        }
+        Ok(options)
+    }
+}
+
+pub(super) fn parse_number<T: std::str::FromStr>(arg: &str, value: Option<String>) -> Result<T> {
+    let value = value.unwrap_or_default();
+    value
+        .parse::<T>()
+        .map_err(|_| anyhow::anyhow!("{arg} expects a number, got {value}"))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PregenReport {
+    pub generated: usize,
+    // Already saved and left alone
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+// Generates and saves every chunk in the area on the generation workers, without a server running
+pub fn run(options: PregenOptions) -> Result<PregenReport> {
+    let start = Instant::now();
+    let save = WorldSave::open_or_create(
+        options.world_path.clone(),
+        WorldMetadata::new(
+            "world".to_string(),
+            options.seed.unwrap_or_else(rand::random),
+            "plains".to_string(),
+        ),
+    )?;
+    let seed = save.metadata().seed;
+    if options.seed.map_or(false, |wanted| wanted != seed) {
+        bail!(
+            "{} already has the seed {seed}",
+            options.world_path.display()
+        );
+    }
+    // Features are placed from the world seed
+    random::set_world_seed(seed);
+
+    let storage = save.chunk_storage();
+    let stored = match options.force {
+        true => HashSet::new(),
+        false => storage.stored_chunks()?.into_iter().collect(),
+    };
+    let spawn_chunk = VoxelScene::chunk_at(&SPAWN_POSITION.as_ivec3());
+    let mut report = PregenReport::default();
+    // Shares the base terrain of neighbouring chunks between their features stages
+    let pipeline = GenerationPipeline::new();
+    let (sender, receiver) = flume::unbounded();
+    for x in -options.radius..=options.radius {
+        for y in 0..options.height {
+            for z in -options.radius..=options.radius {
+                let position = IVec3::new(spawn_chunk.x + x, y, spawn_chunk.z + z);
+                if stored.contains(&position) {
+                    report.skipped += 1;
+                    continue;
+                }
+                let (storage, sender) = (Arc::clone(&storage), sender.clone());
+                pipeline.generate(position, move |result| {
+                    let saved = result.map_err(anyhow::Error::from).and_then(|(chunk, _)| {
+                        storage.save(&ChunkPayload {
+                            position,
+                            voxels: chunk.voxels().clone(),
+                            entities: Vec::new(),
+                            light: None,
+                            biome: chunk.biome.as_deref().map(str::to_string),
+                            player_modified: false,
+                        })
+                    });
+                    sender.send((position, saved)).unwrap();
+                });
+            }
+        }
+    }
+    drop(sender);
+
+    let total = ((options.radius * 2 + 1).pow(2) * options.height) as usize - report.skipped;
+    info!(
+        "Generating {total} chunks with seed {seed}, {} are already saved",
+        report.skipped
+    );
+    let progress = Progress::start("Pregenerating");
+    progress.stage("Generating", total as u64);
+    let mut bar = ProgressBar::new("chunks");
+    // A job that panicked never reports, the channel closes once every job is done
+    for (position, saved) in receiver.iter() {
+        match saved {
+            Ok(()) => {
+                report.generated += 1;
+                world_stats::record(|stats| stats.chunks_generated += 1);
+            }
+            Err(e) => {
+                report.failed += 1;
+                warn!("Failed to generate or save chunk {position}: {e}");
+            }
+        }
+        progress.advance(1);
+        bar.update(&progress.snapshot());
+    }
+    bar.finish();
+    progress.finish();
+
+    save.write_manifest()?;
+    info!(
+        "Generated {} chunks in {:.1?}, {} failed",
+        report.generated,
+        start.elapsed(),
+        report.failed
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod pregen_tests {
+    use super::*;
+
+    #[test]
+    fn options_are_read_from_the_arguments() {
+        let args = ["--pregen", "--seed", "42", "--radius", "3", "--force"];
+        let options = PregenOptions::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.radius, 3);
+        assert_eq!(options.height, 5);
+        assert!(options.force);
+
+        let args = ["--radius", "wide"];
+        assert!(PregenOptions::from_args(args.iter().map(|arg| arg.to_string())).is_err());
+    }
+}
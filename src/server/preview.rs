@@ -0,0 +1,275 @@
+use std::{collections::HashMap, path::PathBuf, time::Instant};
+
+use anyhow::{anyhow, bail, Result};
+use glam::{IVec2, IVec3, Vec3};
+use tracing::{info, warn};
+
+use crate::{
+    error::AssemblageError,
+    jobs::{self, JobClass},
+    map::{MapTile, WorldMap},
+    persistence::world_preset::WorldPreset,
+    progress::{Progress, ProgressBar},
+    random::{self, OVERWORLD},
+    voxels::{
+        biome_edges::BiomeEdges,
+        biome_profile::{get_biome_by_name, set_world_biomes, BiomeProfile, SampleContext},
+        dimension::{dimension_or_default, set_world_dimensions, Dimension},
+        voxel_registry::get_voxel_by_id,
+        voxel_scene::{biome_at, VoxelScene, CHUNK_SIZE},
+    },
+};
+
+use super::{pregen::parse_number, SPAWN_POSITION};
+
+// What the pixels of the preview show
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreviewLayer {
+    // The surface voxel's color, like the map of a generated world
+    Terrain,
+    // Brighter the higher the surface is
+    Heights,
+    // A color for each biome, its map tint if it has one
+    Biomes,
+}
+
+impl PreviewLayer {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "terrain" => Ok(Self::Terrain),
+            "heights" => Ok(Self::Heights),
+            "biomes" => Ok(Self::Biomes),
+            _ => bail!("Unknown preview layer {name}, expected terrain, heights or biomes"),
+        }
+    }
+}
+
+pub struct PreviewOptions {
+    // Its seed and biome overrides are previewed
+    pub preset: Option<PathBuf>,
+    // Replaces the preset's seed, a random one is picked when neither is given
+    pub seed: Option<u64>,
+    // Chunk columns in every horizontal direction around the spawn chunk
+    pub radius: i32,
+    // Chunk layers counting up from y 0, surfaces above them aren't found
+    pub height: i32,
+    pub layer: PreviewLayer,
+    pub output: PathBuf,
+}
+
+impl PreviewOptions {
+    // Reads --preset <path>, --seed <seed>, --radius <chunks>, --height <chunks>,
+    // --layer <terrain|heights|biomes> and --output <path>
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self {
+            preset: None,
+            seed: None,
+            radius: 16,
+            height: 5,
+            layer: PreviewLayer::Terrain,
+            output: PathBuf::from("./preview.png"),
+        };
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.peek()) {
+                ("--preset", Some(_)) => options.preset = Some(PathBuf::from(args.next().unwrap())),
+                ("--seed", Some(_)) => options.seed = Some(parse_number(&arg, args.next())?),
+                ("--radius", Some(_)) => options.radius = parse_number(&arg, args.next())?,
+                ("--height", Some(_)) => options.height = parse_number(&arg, args.next())?,
+                ("--layer", Some(_)) => {
+                    options.layer = PreviewLayer::from_name(&args.next().unwrap())?
+                }
+                ("--output", Some(_)) => options.output = PathBuf::from(args.next().unwrap()),
+                ("--preview", _) => {}
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        if options.radius < 0 || options.height < 1 {
+            bail!("The radius can't be negative and the height has to be at least 1");
+        }
+        Ok(options)
+    }
+}
+
+// Renders a map of the area a world with the seed and preset would have, from the generator's
+// formulas alone. No voxels are stored and no features are placed, so browsing seeds is quick
+pub fn run(options: PreviewOptions) -> Result<u64> {
+    let start = Instant::now();
+    let preset = options
+        .preset
+        .as_deref()
+        .map(WorldPreset::load)
+        .transpose()?;
+    let seed = options
+        .seed
+        .or_else(|| preset.as_ref().map(|preset| preset.seed))
+        .unwrap_or_else(rand::random);
+    let mut biomes = HashMap::new();
+    for (name, biome) in preset.iter().flat_map(|preset| &preset.biome_overrides) {
+        let profile = BiomeProfile::from_value(biome)
+            .map_err(|e| anyhow!("The preset's biome {name} can't be loaded: {e}"))?;
+        biomes.insert(name.clone(), profile);
+    }
+    let mut dimensions = HashMap::new();
+    for (name, dimension) in preset.iter().flat_map(|preset| &preset.dimensions) {
+        let dimension = Dimension::from_value(dimension)
+            .map_err(|e| anyhow!("The preset's dimension {name} can't be loaded: {e}"))?;
+        dimensions.insert(name.clone(), dimension);
+    }
+    set_world_biomes(biomes);
+    let generator_preset = preset
+        .as_ref()
+        .map_or("plains", |preset| preset.generator_preset.as_str());
+    set_world_dimensions(generator_preset, dimensions);
+    random::set_world_seed(seed);
+
+    let spawn_chunk = VoxelScene::chunk_at(&SPAWN_POSITION.as_ivec3());
+    let (sender, receiver) = flume::unbounded();
+    for x in -options.radius..=options.radius {
+        for z in -options.radius..=options.radius {
+            let column = IVec2::new(spawn_chunk.x + x, spawn_chunk.z + z);
+            let (sender, height, layer) = (sender.clone(), options.height, options.layer);
+            jobs::spawn(JobClass::Generation, &[], move || {
+                sender
+                    .send((column, sample_column(column, height, layer)))
+                    .unwrap();
+            });
+        }
+    }
+    drop(sender);
+
+    info!("Previewing seed {seed}");
+    let progress = Progress::start("Previewing");
+    progress.stage("Sampling", (options.radius * 2 + 1).pow(2) as u64);
+    let mut bar = ProgressBar::new("columns");
+    let mut map = WorldMap::new();
+    for (column, tile) in receiver.iter() {
+        match tile {
+            Ok(tile) => map.insert(column, tile),
+            Err(e) => warn!("Failed to preview chunk column {column}: {e}"),
+        }
+        progress.advance(1);
+        bar.update(&progress.snapshot());
+    }
+    bar.finish();
+    progress.finish();
+
+    let image = map
+        .render_all()
+        .ok_or_else(|| anyhow!("No chunk column could be previewed"))?;
+    image.save_png(&options.output)?;
+    info!(
+        "Saved the preview of seed {seed} to {} in {:.1?}",
+        options.output.display(),
+        start.elapsed()
+    );
+    Ok(seed)
+}
+
+// Finds the surface of each position in the column the way generation would fill it, from the
+// top layer down, stopping once every position has one
+fn sample_column(
+    column: IVec2,
+    height: i32,
+    layer: PreviewLayer,
+) -> Result<MapTile, AssemblageError> {
+    let size = CHUNK_SIZE as i32;
+    let area = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+    let mut heights = vec![None; area];
+    let mut colors = vec![Vec3::ZERO; area];
+    let dimension = dimension_or_default(OVERWORLD);
+    for y in (0..height).rev().filter(|y| dimension.contains_layer(*y)) {
+        let chunk = IVec3::new(column.x, y, column.y);
+        let biome_name = biome_at(chunk);
+        let biome = get_biome_by_name(biome_name.clone())
+            .ok_or_else(|| AssemblageError::unknown("biome", &biome_name))?;
+        let edges = BiomeEdges::around(chunk);
+        let mut context = SampleContext {
+            position: chunk * size,
+            slope: Vec3::ZERO,
+            depth: 0.0,
+            moisture: dimension.climate.moisture,
+            temperature: dimension.climate.temperature,
+            density: 0.0,
+            edge_distance: 0.0,
+        };
+        for index in 0..area {
+            if heights[index].is_some() {
+                continue;
+            }
+            let (x, z) = (index as i32 / size, index as i32 % size);
+            for voxel_y in (0..size).rev() {
+                context.position = chunk * size + IVec3::new(x, voxel_y, z);
+                context.edge_distance = edges.distance(context.position);
+                context.density = biome.sample_density(&context);
+                let (voxel, fluid) = match context.density > 0.0 {
+                    true => (biome.sample_voxel(&mut context), false),
+                    false => match biome.sample_fluid(&context, dimension.sea_level.as_ref()) {
+                        Some(fluid) => (fluid, true),
+                        None => continue,
+                    },
+                };
+                let color = get_voxel_by_id(voxel.id).map_or(Vec3::ZERO, |p| p.color.truncate());
+                heights[index] = Some(context.position.y);
+                colors[index] = match layer {
+                    PreviewLayer::Terrain => match biome.map_tint() {
+                        Some(tint) => color.lerp(tint.truncate(), tint.w),
+                        None => color,
+                    },
+                    // Water keeps its own color so coasts and lakes stand out
+                    _ if fluid => color,
+                    PreviewLayer::Heights => {
+                        Vec3::splat(context.position.y as f32 / (height * size) as f32)
+                    }
+                    PreviewLayer::Biomes => biome_color(&biome_name, &biome),
+                };
+                break;
+            }
+        }
+        if heights.iter().all(Option::is_some) {
+            break;
+        }
+    }
+    Ok(MapTile::from_surface(heights, colors))
+}
+
+// Biomes without a map tint get a color from their name, the same in every preview
+fn biome_color(name: &str, biome: &BiomeProfile) -> Vec3 {
+    if let Some(tint) = biome.map_tint() {
+        return tint.truncate();
+    }
+    let hash = name.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    Vec3::new(
+        (hash & 0xff) as f32,
+        (hash >> 8 & 0xff) as f32,
+        (hash >> 16 & 0xff) as f32,
+    ) / 255.0
+}
+
+#[cfg(test)]
+mod preview_tests {
+    use super::*;
+
+    #[test]
+    fn options_are_read_from_the_arguments() {
+        let args = [
+            "--preview",
+            "--seed",
+            "7",
+            "--layer",
+            "biomes",
+            "--output",
+            "a.png",
+        ];
+        let options = PreviewOptions::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.layer, PreviewLayer::Biomes);
+        assert_eq!(options.output, PathBuf::from("a.png"));
+        assert_eq!(options.radius, 16);
+
+        let args = ["--layer", "caves"];
+        assert!(PreviewOptions::from_args(args.iter().map(|arg| arg.to_string())).is_err());
+    }
+}
@@ -0,0 +1,857 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use glam::{IVec3, Vec3};
+use parking_lot::{Mutex, RwLock};
+use tracing::{info, warn};
+
+use crate::{
+    ecs::{entities::item_drops::spawn_dropped_item, world::World},
+    environment::{self, world_time::WorldTime},
+    persistence::{
+        binary::{crc32, ByteReader, ByteWriter},
+        chunk_storage::ChunkPayload,
+        entity_persistence::{collect_chunk_entities, spawn_loaded_entities},
+        world_preset::WorldPreset,
+        world_save::{engine_version, WorldSave, WorldSettings},
+    },
+    random,
+    voxels::{
+        voxel_data::VoxelData,
+        voxel_scene::{VoxelChunk, VoxelScene},
+        voxel_shapes::VoxelShape,
+        voxel_simulation::VoxelSimulation,
+    },
+};
+
+pub const REPLAY_FORMAT_VERSION: u64 = 2;
+// Written to the world folder when the record command isn't given a path
+pub const REPLAY_FILE: &str = "replay.jsonl";
+// Chunks this many chunks or closer to a changed one, counted along the axes, are captured with
+// it. Only chunks with all six neighbours loaded tick, so this keeps the changed chunk and its
+// neighbours ticking during playback
+const CAPTURE_DISTANCE: i32 = 2;
+
+lazy_static! {
+    static ref RECORDER: Mutex<Option<ReplayRecorder>> = Mutex::new(None);
+}
+
+// Something from outside the simulation that changed the world. Playback applies it before the
+// tick it happened on and lets the simulation do the rest
+#[derive(Clone, Copy)]
+pub enum ReplayEvent {
+    // Placed by a player or a command
+    SetVoxel {
+        position: IVec3,
+        voxel: VoxelData,
+    },
+    // Broken by a player, dropped as an item unless their game mode doesn't drop any
+    BreakVoxel {
+        position: IVec3,
+        drops: bool,
+    },
+    SpawnItem {
+        position: Vec3,
+        voxel: u16,
+        count: u32,
+    },
+}
+
+impl ReplayEvent {
+    // The chunk the event changes
+    pub fn chunk(&self) -> IVec3 {
+        match self {
+            Self::SetVoxel { position, .. } | Self::BreakVoxel { position, .. } => {
+                VoxelScene::chunk_at(position)
+            }
+            Self::SpawnItem { position, .. } => VoxelScene::chunk_at(&position.floor().as_ivec3()),
+        }
+    }
+
+    fn apply(&self, scene: &VoxelScene, world: &mut World) {
+        match *self {
+            Self::SetVoxel { position, voxel } => {
+                scene.mark_player_modified(&position);
+                scene.set_voxel(&position, voxel);
+            }
+            Self::BreakVoxel { position, drops } => {
+                scene.mark_player_modified(&position);
+                match drops {
+                    true => scene.break_voxel(&position),
+                    false => scene
+                        .voxel_at(&position)
+                        .and_then(|voxel| scene.set_voxel(&position, VoxelData { id: 0, ..voxel })),
+                };
+            }
+            Self::SpawnItem {
+                position,
+                voxel,
+                count,
+            } => {
+                spawn_dropped_item(&mut world.legion_world, position, voxel, count);
+            }
+        }
+    }
+
+    pub fn to_json(&self, tick: u64) -> serde_json::Value {
+        match *self {
+            Self::SetVoxel { position, voxel } => {
+                let id = voxel.id;
+                serde_json::json!({
+                    "Tick": tick,
+                    "Event": "Set Voxel",
+                    "Position": position.to_array(),
+                    "Id": id,
+                    "Shape": voxel.shape.data,
+                    "State": voxel.state,
+                })
+            }
+            Self::BreakVoxel { position, drops } => serde_json::json!({
+                "Tick": tick,
+                "Event": "Break Voxel",
+                "Position": position.to_array(),
+                "Drops": drops,
+            }),
+            Self::SpawnItem {
+                position,
+                voxel,
+                count,
+            } => serde_json::json!({
+                "Tick": tick,
+                "Event": "Spawn Item",
+                "Position": position.to_array(),
+                "Id": voxel,
+                "Count": count,
+            }),
+        }
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> Result<Self> {
+        let number = |name: &str| {
+            json.get(name)
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| anyhow!("The replay event is missing \"{name}\""))
+        };
+        let position = || -> Result<[f64; 3]> {
+            let values = json
+                .get("Position")
+                .and_then(|v| v.as_array())
+                .filter(|values| values.len() == 3)
+                .ok_or_else(|| anyhow!("The replay event's \"Position\" isn't 3 numbers"))?;
+            let mut position = [0.0; 3];
+            for (coordinate, value) in position.iter_mut().zip(values) {
+                *coordinate = value
+                    .as_f64()
+                    .ok_or_else(|| anyhow!("The replay event's \"Position\" isn't 3 numbers"))?;
+            }
+            Ok(position)
+        };
+        let voxel_position = || position().map(|p| IVec3::from(p.map(|c| c as i32)));
+        match json.get("Event").and_then(|v| v.as_str()) {
+            Some("Set Voxel") => Ok(Self::SetVoxel {
+                position: voxel_position()?,
+                voxel: VoxelData {
+                    shape: VoxelShape {
+                        data: number("Shape")? as u8,
+                    },
+                    state: number("State")? as u8,
+                    id: number("Id")? as u16,
+                },
+            }),
+            Some("Break Voxel") => Ok(Self::BreakVoxel {
+                position: voxel_position()?,
+                drops: json.get("Drops").and_then(|v| v.as_bool()).unwrap_or(true),
+            }),
+            Some("Spawn Item") => Ok(Self::SpawnItem {
+                position: Vec3::from(position()?.map(|c| c as f32)),
+                voxel: number("Id")? as u16,
+                count: number("Count")? as u32,
+            }),
+            Some(other) => bail!("Unknown replay event {other}"),
+            None => bail!("The replay event is missing \"Event\""),
+        }
+    }
+}
+
+// CRC-32 of the chunks' voxels in position order, chunks that aren't loaded count as empty
+pub fn checksum(scene: &VoxelScene, chunks: &[IVec3]) -> u32 {
+    let mut chunks = chunks.to_vec();
+    chunks.sort_by_key(|chunk| chunk.to_array());
+    let mut bytes = Vec::new();
+    for position in chunks {
+        for coordinate in position.to_array() {
+            bytes.extend_from_slice(&coordinate.to_le_bytes());
+        }
+        if let Some(chunk) = scene.chunks.get(&position) {
+            for voxel in chunk.voxels() {
+                bytes.extend_from_slice(&{ voxel.id }.to_le_bytes());
+                bytes.push(voxel.shape.data);
+                bytes.push(voxel.state);
+            }
+        }
+    }
+    crc32(bytes.iter())
+}
+
+// The payload as it's stored on disk, deflated and written as hex
+fn encode_snapshot(payload: &ChunkPayload) -> Result<String> {
+    let mut writer = ByteWriter::new();
+    payload.write(&mut writer);
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(&writer.bytes)?;
+    Ok(encoder
+        .finish()?
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+fn decode_snapshot(hex: &str) -> Result<ChunkPayload> {
+    if !hex.is_ascii() || hex.len() % 2 != 0 {
+        bail!("The chunk snapshot isn't hex");
+    }
+    let compressed = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow!("The chunk snapshot isn't hex"))?;
+    let mut bytes = Vec::new();
+    DeflateDecoder::new(&compressed[..]).read_to_end(&mut bytes)?;
+    ChunkPayload::read(&mut ByteReader::new(&bytes))
+}
+
+struct ReplayRecorder {
+    path: PathBuf,
+    file: BufWriter<File>,
+    // Compared by their checksum when playback ends
+    chunks: HashSet<IVec3>,
+    // The chunks already written with their voxels and entities
+    captured: HashSet<IVec3>,
+}
+
+impl ReplayRecorder {
+    // Lines are flushed as they're written, so a recording of a crash keeps everything before it
+    fn write_line(&mut self, json: &serde_json::Value) -> Result<()> {
+        writeln!(self.file, "{json}")?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    // Chunks that aren't loaded are left out, they don't tick in the recorded world either
+    fn capture(&mut self, scene: &VoxelScene, world: &World, center: IVec3) -> Result<()> {
+        let tick = environment::current().time.tick;
+        let range = -CAPTURE_DISTANCE..=CAPTURE_DISTANCE;
+        for x in range.clone() {
+            for y in range.clone() {
+                for z in range.clone() {
+                    let offset = IVec3::new(x, y, z);
+                    let position = center + offset;
+                    if offset.abs().dot(IVec3::ONE) > CAPTURE_DISTANCE
+                        || self.captured.contains(&position)
+                    {
+                        continue;
+                    }
+                    let payload = match scene.chunks.get(&position) {
+                        Some(chunk) => ChunkPayload {
+                            position,
+                            voxels: chunk.voxels().clone(),
+                            entities: collect_chunk_entities(&world.legion_world, position),
+                            light: None,
+                            biome: chunk.biome.as_deref().map(str::to_string),
+                            player_modified: chunk.player_modified,
+                        },
+                        None => continue,
+                    };
+                    self.write_line(&serde_json::json!({
+                        "Tick": tick,
+                        "Event": "Chunk",
+                        "Payload": encode_snapshot(&payload)?,
+                    }))?;
+                    self.captured.insert(position);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Starts writing the world's inputs to the file along with the world's seed and settings, replacing
+// a recording that's running. The chunks are written as the events reach them, see capture.
+// Players aren't recorded, only what they changed
+pub fn start_recording(path: &Path, save: &WorldSave) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Couldn't create {}", path.display()))?;
+    let metadata = save.metadata();
+    let mut recorder = ReplayRecorder {
+        path: path.to_path_buf(),
+        file: BufWriter::new(file),
+        chunks: HashSet::new(),
+        captured: HashSet::new(),
+    };
+    recorder.write_line(&serde_json::json!({
+        "Format Version": REPLAY_FORMAT_VERSION,
+        "Engine Version": engine_version().to_string(),
+        "Preset": WorldPreset::from_metadata(&metadata).to_json(),
+        "Settings": metadata.settings,
+        "Start Tick": environment::current().time.tick,
+    }))?;
+    if let Some(previous) = RECORDER.lock().replace(recorder) {
+        warn!(
+            "Stopped recording to {} without a checksum",
+            previous.path.display()
+        );
+    }
+    info!("Recording the world's inputs to {}", path.display());
+    Ok(())
+}
+
+pub fn is_recording() -> bool {
+    RECORDER.lock().is_some()
+}
+
+// Called before the event is applied. The first time a recording reaches a chunk it writes the
+// chunk and the loaded chunks around it as they are, playback starts them from there instead of
+// generating them again. A recording that can't be written to is stopped
+pub fn capture(scene: &VoxelScene, world: &World, event: &ReplayEvent) {
+    let mut recorder = RECORDER.lock();
+    let written = match recorder.as_mut() {
+        Some(recorder) => recorder.capture(scene, world, event.chunk()),
+        None => return,
+    };
+    if let Err(e) = written {
+        warn!("Stopped recording, the replay couldn't be written: {e}");
+        *recorder = None;
+    }
+}
+
+// Writes the event at the current world tick if a recording is running, after capture. A recording
+// that can't be written to is stopped
+pub fn record(event: ReplayEvent) {
+    let mut recorder = RECORDER.lock();
+    let written = match recorder.as_mut() {
+        Some(recorder) => {
+            recorder.chunks.insert(event.chunk());
+            recorder.write_line(&event.to_json(environment::current().time.tick))
+        }
+        None => return,
+    };
+    if let Err(e) = written {
+        warn!("Stopped recording, the replay couldn't be written: {e}");
+        *recorder = None;
+    }
+}
+
+// Ends the recording with a checksum of the loaded chunks it changed, returns where it was written
+pub fn stop_recording(scene: &VoxelScene) -> Result<Option<PathBuf>> {
+    let mut recorder = match RECORDER.lock().take() {
+        Some(recorder) => recorder,
+        None => return Ok(None),
+    };
+    let chunks = recorder
+        .chunks
+        .iter()
+        .copied()
+        .filter(|chunk| scene.chunks.contains_key(chunk))
+        .collect::<Vec<_>>();
+    recorder.write_line(&serde_json::json!({
+        "Tick": environment::current().time.tick,
+        "Event": "End",
+        "Checksum": checksum(scene, &chunks),
+        "Chunks": chunks.iter().map(|chunk| chunk.to_array()).collect::<Vec<_>>(),
+    }))?;
+    info!("Stopped recording to {}", recorder.path.display());
+    Ok(Some(recorder.path))
+}
+
+// How a recording ended, the chunks still loaded then and their checksum
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayEnd {
+    pub tick: u64,
+    pub checksum: u32,
+    pub chunks: Vec<IVec3>,
+}
+
+pub struct Replay {
+    pub preset: WorldPreset,
+    pub settings: BTreeMap<String, serde_json::Value>,
+    pub start_tick: u64,
+    // The chunks as the recording first saw them, with the tick they were captured on
+    pub snapshots: Vec<(u64, ChunkPayload)>,
+    // In the order they happened, with the tick they happened on
+    pub events: Vec<(u64, ReplayEvent)>,
+    // None when the recording was never stopped, for example because the game crashed
+    pub end: Option<ReplayEnd>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read the replay {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("{} isn't a valid replay", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str::<serde_json::Value>);
+        let header = lines
+            .next()
+            .ok_or_else(|| anyhow!("The replay is empty"))??;
+        let version = header
+            .get("Format Version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| anyhow!("The replay is missing \"Format Version\""))?;
+        if version > REPLAY_FORMAT_VERSION {
+            bail!("Replay format version {version} is newer than the supported version {REPLAY_FORMAT_VERSION}");
+        }
+        if version < 2 {
+            bail!("Replay format version {version} doesn't store the chunks it starts from, record it again");
+        }
+        let mut replay = Self {
+            preset: WorldPreset::from_json(
+                header
+                    .get("Preset")
+                    .ok_or_else(|| anyhow!("The replay is missing \"Preset\""))?,
+            )?,
+            settings: header.get("Settings").and_then(|v| v.as_object()).map_or(
+                BTreeMap::new(),
+                |settings| {
+                    settings
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect()
+                },
+            ),
+            start_tick: header
+                .get("Start Tick")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0),
+            snapshots: Vec::new(),
+            events: Vec::new(),
+            end: None,
+        };
+        for line in lines {
+            let line = line?;
+            let tick = line
+                .get("Tick")
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| anyhow!("A replay event is missing \"Tick\""))?;
+            let event = line.get("Event").and_then(|v| v.as_str());
+            if event == Some("Chunk") {
+                let payload = line
+                    .get("Payload")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow!("A replay chunk is missing \"Payload\""))?;
+                replay.snapshots.push((tick, decode_snapshot(payload)?));
+                continue;
+            }
+            if event != Some("End") {
+                replay.events.push((tick, ReplayEvent::from_json(&line)?));
+                continue;
+            }
+            let chunks =
+                line.get("Chunks")
+                    .and_then(|v| v.as_array())
+                    .map_or(Vec::new(), |chunks| {
+                        chunks
+                            .iter()
+                            .filter_map(|chunk| {
+                                let chunk = chunk.as_array()?;
+                                let coordinate = |i: usize| Some(chunk.get(i)?.as_i64()? as i32);
+                                Some(IVec3::new(coordinate(0)?, coordinate(1)?, coordinate(2)?))
+                            })
+                            .collect()
+                    });
+            replay.end = Some(ReplayEnd {
+                tick,
+                checksum: line
+                    .get("Checksum")
+                    .and_then(|v| v.as_u64())
+                    .ok_or_else(|| anyhow!("The replay's end is missing \"Checksum\""))?
+                    as u32,
+                chunks,
+            });
+            break;
+        }
+        Ok(replay)
+    }
+
+    // Every chunk an event changes
+    pub fn chunks(&self) -> HashSet<IVec3> {
+        self.events.iter().map(|(_, event)| event.chunk()).collect()
+    }
+}
+
+pub struct ReplayOptions {
+    pub path: PathBuf,
+}
+
+impl ReplayOptions {
+    // Reads --replay <file>, anything else is ignored with a warning
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut path = None;
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.peek()) {
+                ("--replay", Some(_)) => path = args.next().map(PathBuf::from),
+                ("--allow-newer-world", _) => {}
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        Ok(Self {
+            path: path.ok_or_else(|| anyhow!("--replay expects the replay file"))?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub ticks: u64,
+    pub events: usize,
+    pub checksum: Option<u32>,
+}
+
+// Runs the recording again in memory, tick by tick as fast as it goes. The captured chunks are
+// loaded on the tick they were captured on and each event is applied before the tick it happened
+// on, the simulation does the rest. Fails when the changed chunks end up different from the
+// recording
+pub fn run(options: ReplayOptions) -> Result<ReplayReport> {
+    let start = Instant::now();
+    let mut replay = Replay::load(&options.path)?;
+    let mut metadata = replay.preset.to_metadata("replay".to_string());
+    metadata.settings = replay.settings.clone();
+    let save = WorldSave::open_or_create_with(
+        PathBuf::new(),
+        metadata,
+        WorldSettings { in_memory: true },
+    )?;
+    random::set_world_seed(save.metadata().seed);
+    environment::set_world_time(WorldTime::new(replay.start_tick));
+    let scene = Arc::new(RwLock::new(VoxelScene::new()));
+    let world = Arc::new(RwLock::new(World::new()));
+
+    let mut simulation = VoxelSimulation::new(Arc::clone(&scene), Arc::clone(&world));
+    let mut schedule = VoxelSimulation::entity_schedule();
+    let mut resources = simulation.resources();
+    let end_tick = match &replay.end {
+        Some(end) => end.tick,
+        None => replay
+            .events
+            .last()
+            .map_or(replay.start_tick, |(tick, _)| *tick),
+    };
+    let mut snapshots = std::mem::take(&mut replay.snapshots).into_iter().peekable();
+    let mut events = replay.events.iter().peekable();
+    loop {
+        let tick = environment::current().time.tick;
+        {
+            let mut world_lock = world.write();
+            let scene_lock = scene.read();
+            while let Some((_, payload)) = snapshots.next_if(|(at, _)| *at <= tick) {
+                load_snapshot(&scene_lock, &mut world_lock, payload);
+            }
+            while let Some((_, event)) = events.next_if(|(at, _)| *at <= tick) {
+                event.apply(&scene_lock, &mut world_lock);
+            }
+        }
+        if tick >= end_tick {
+            break;
+        }
+        simulation.step(&mut schedule, &mut resources);
+    }
+
+    let ticks = end_tick.saturating_sub(replay.start_tick);
+    let end = match &replay.end {
+        Some(end) => end,
+        None => {
+            warn!(
+                "The recording wasn't stopped, replayed its {} events over {ticks} ticks without a checksum to compare",
+                replay.events.len()
+            );
+            return Ok(ReplayReport {
+                ticks,
+                events: replay.events.len(),
+                checksum: None,
+            });
+        }
+    };
+    let checksum = checksum(&scene.read(), &end.chunks);
+    if checksum != end.checksum {
+        bail!(
+            "The replay diverged from the recording, its {} chunks have the checksum {checksum:08x} instead of {:08x}",
+            end.chunks.len(),
+            end.checksum
+        );
+    }
+    info!(
+        "Replayed {} events over {ticks} ticks in {:.1?}, the {} chunks they changed match the recording",
+        replay.events.len(),
+        start.elapsed(),
+        end.chunks.len()
+    );
+    Ok(ReplayReport {
+        ticks,
+        events: replay.events.len(),
+        checksum: Some(checksum),
+    })
+}
+
+fn load_snapshot(scene: &VoxelScene, world: &mut World, payload: ChunkPayload) {
+    let mut chunk = VoxelChunk::from_voxels(payload.position, payload.voxels);
+    chunk.player_modified = payload.player_modified;
+    chunk.biome = payload.biome.map(Arc::from);
+    scene.chunks.insert(payload.position, chunk);
+    spawn_loaded_entities(&mut world.legion_world, payload.entities);
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+    use crate::voxels::{
+        voxel_registry::get_voxel_by_name, voxel_scene::CHUNK_SIZE, voxel_shapes::voxel_shape,
+    };
+
+    #[test]
+    fn replays_are_read_back_as_written() {
+        let preset = WorldPreset {
+            seed: 7,
+            generator_preset: "plains".to_string(),
+            biome_overrides: BTreeMap::new(),
+            dimensions: BTreeMap::new(),
+        };
+        let events = [
+            ReplayEvent::SetVoxel {
+                position: IVec3::new(-3, 5, 17),
+                voxel: VoxelData {
+                    shape: voxel_shape::CUBE,
+                    state: 2,
+                    id: 4,
+                },
+            },
+            ReplayEvent::BreakVoxel {
+                position: IVec3::new(1, 2, 3),
+                drops: false,
+            },
+            ReplayEvent::SpawnItem {
+                position: Vec3::new(0.5, 80.0, -1.5),
+                voxel: 4,
+                count: 64,
+            },
+        ];
+        let mut lines = vec![serde_json::json!({
+            "Format Version": REPLAY_FORMAT_VERSION,
+            "Preset": preset.to_json(),
+            "Settings": { "Weather": false },
+            "Start Tick": 100,
+        })];
+        lines.extend(
+            events
+                .iter()
+                .enumerate()
+                .map(|(i, event)| event.to_json(100 + i as u64)),
+        );
+        lines.push(serde_json::json!({
+            "Tick": 140, "Event": "End", "Checksum": 1234, "Chunks": [[-1, 0, 1]],
+        }));
+        let text = lines
+            .iter()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let replay = Replay::parse(&text).unwrap();
+        assert_eq!(replay.preset, preset);
+        assert_eq!(replay.settings["Weather"], false);
+        assert_eq!(replay.start_tick, 100);
+        assert_eq!(replay.events.len(), 3);
+        for ((tick, read), written) in replay.events.iter().zip(&events) {
+            assert_eq!(read.to_json(*tick), written.to_json(*tick));
+        }
+        assert_eq!(replay.events[2].0, 102);
+        assert_eq!(
+            replay.chunks(),
+            [
+                IVec3::new(-1, 0, 1),
+                IVec3::new(0, 0, 0),
+                IVec3::new(0, 5, -1)
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            replay.end,
+            Some(ReplayEnd {
+                tick: 140,
+                checksum: 1234,
+                chunks: vec![IVec3::new(-1, 0, 1)],
+            })
+        );
+
+        // A recording cut short by a crash has no end
+        let cut = text.lines().take(2).collect::<Vec<_>>().join("\n");
+        assert!(Replay::parse(&cut).unwrap().end.is_none());
+    }
+
+    #[test]
+    fn options_need_a_replay_file() {
+        let args = [
+            "--replay",
+            "saves/world/replay.jsonl",
+            "--allow-newer-world",
+        ];
+        let options = ReplayOptions::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!(options.path, PathBuf::from("saves/world/replay.jsonl"));
+        assert!(ReplayOptions::from_args(["--replay".to_string()].into_iter()).is_err());
+    }
+
+    #[test]
+    fn checksums_follow_the_voxels() {
+        let scene = VoxelScene::new();
+        let chunk = IVec3::new(0, 0, 0);
+        scene
+            .chunks
+            .insert(chunk, crate::voxels::voxel_scene::VoxelChunk::new(chunk));
+        let empty = checksum(&scene, &[chunk]);
+        assert_eq!(empty, checksum(&scene, &[chunk]));
+        scene.set_voxel(
+            &IVec3::new(1, 1, 1),
+            VoxelData {
+                shape: voxel_shape::CUBE,
+                state: 0,
+                id: 1,
+            },
+        );
+        assert_ne!(checksum(&scene, &[chunk]), empty);
+        // Unloaded chunks only count their position
+        assert_ne!(
+            checksum(&scene, &[IVec3::ONE]),
+            checksum(&scene, &[IVec3::NEG_ONE])
+        );
+    }
+
+    // Grass spreads over layers of dirt on random ticks while edits are recorded. Playback starts
+    // from the captured chunks and has to end with the same voxels
+    #[test]
+    fn recordings_with_ticking_voxels_replay_to_the_same_checksum() {
+        let id = |name: &str| get_voxel_by_name(name.to_string()).unwrap().id;
+        let (grass, dirt, stone) = (id("grass"), id("dirt"), id("stone"));
+        let cube = |id| VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id,
+        };
+        let preset = WorldPreset {
+            seed: 11,
+            generator_preset: "plains".to_string(),
+            biome_overrides: BTreeMap::new(),
+            dimensions: BTreeMap::new(),
+        };
+        let save = WorldSave::open_or_create_with(
+            PathBuf::new(),
+            preset.to_metadata("recorded".to_string()),
+            WorldSettings { in_memory: true },
+        )
+        .unwrap();
+        random::set_world_seed(preset.seed);
+        environment::set_world_time(WorldTime::new(0));
+
+        // Every neighbour of the middle chunk is loaded, so it ticks
+        let scene = Arc::new(RwLock::new(VoxelScene::new()));
+        let world = Arc::new(RwLock::new(World::new()));
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let position = IVec3::new(x, y, z);
+                    scene
+                        .read()
+                        .chunks
+                        .insert(position, VoxelChunk::new(position));
+                }
+            }
+        }
+        let size = CHUNK_SIZE as i32;
+        let count_grass = |scene: &VoxelScene| {
+            scene
+                .chunks
+                .get(&IVec3::ZERO)
+                .unwrap()
+                .voxels()
+                .iter()
+                .filter(|v| v.id == grass)
+                .count()
+        };
+        for y in (0..size).step_by(2) {
+            for x in 0..size {
+                for z in 0..size {
+                    let voxel = match (x + z) % 2 {
+                        0 => grass,
+                        _ => dirt,
+                    };
+                    scene.read().set_voxel(&IVec3::new(x, y, z), cube(voxel));
+                }
+            }
+        }
+        let planted = count_grass(&scene.read());
+
+        let path =
+            std::env::temp_dir().join(format!("assemblage_replay_{}.jsonl", std::process::id()));
+        start_recording(&path, &save).unwrap();
+        let edits = [
+            (
+                20,
+                ReplayEvent::SetVoxel {
+                    position: IVec3::new(3, 1, 3),
+                    voxel: cube(stone),
+                },
+            ),
+            (
+                60,
+                ReplayEvent::BreakVoxel {
+                    position: IVec3::new(5, 4, 6),
+                    drops: true,
+                },
+            ),
+            (
+                100,
+                ReplayEvent::SetVoxel {
+                    position: IVec3::new(8, 7, 8),
+                    voxel: cube(grass),
+                },
+            ),
+        ];
+        let mut simulation = VoxelSimulation::new(Arc::clone(&scene), Arc::clone(&world));
+        let mut schedule = VoxelSimulation::entity_schedule();
+        let mut resources = simulation.resources();
+        for step in 0..300 {
+            for (_, event) in edits.iter().filter(|(at, _)| *at == step) {
+                let mut world_lock = world.write();
+                let scene_lock = scene.read();
+                capture(&scene_lock, &world_lock, event);
+                event.apply(&scene_lock, &mut world_lock);
+                record(*event);
+            }
+            simulation.step(&mut schedule, &mut resources);
+        }
+        // The grass spread after the chunks were captured, playback has to tick them as well
+        assert!(count_grass(&scene.read()) > planted + 1);
+        let expected = checksum(&scene.read(), &[IVec3::ZERO]);
+        stop_recording(&scene.read()).unwrap();
+
+        let replay = Replay::load(&path).unwrap();
+        // The middle chunk with its neighbours and the chunks next to those, the corners aren't needed
+        assert_eq!(replay.snapshots.len(), 19);
+        assert!(replay.snapshots.iter().all(|(tick, _)| *tick == 20));
+        let report = run(ReplayOptions { path: path.clone() }).unwrap();
+        assert_eq!(report.events, 3);
+        assert_eq!(report.checksum, Some(expected));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+use std::{collections::HashMap, fmt, time::Instant};
+
+use glam::{IVec3, Vec3};
+
+use crate::voxels::{
+    regions::Regions,
+    voxel_breaking::{break_time, BreakingTool},
+    voxel_data::VoxelData,
+    voxel_interaction::{overlaps_colliders, EditingPlayer},
+    voxel_registry::get_voxel_by_id,
+    voxel_scene::VoxelScene,
+};
+
+// Edits a player can make in a burst before the rate limit applies
+pub const EDIT_BURST: f32 = 20.0;
+// Edits regained per second once the burst is used up
+pub const EDITS_PER_SECOND: f32 = 10.0;
+// Survival breaks arriving slightly early are accepted, packets sent a tick apart can arrive together
+const BREAK_TIME_TOLERANCE: f32 = 0.2;
+// Started breaks are forgotten after this many seconds, partly broken voxels heal well before
+const BREAK_START_EXPIRY: f32 = 60.0;
+// Voxels a session may be breaking at once, further starts are ignored
+const MAX_STARTED_BREAKS: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EditRejection {
+    OutOfReach,
+    RateLimited,
+    // A survival player broke voxels faster than the break time allows
+    TooFast,
+    Protected,
+    Invalid,
+    // The voxel would be placed inside the player or another entity
+    Obstructed,
+    // An event handler cancelled the edit
+    Cancelled,
+    Spectating,
+}
+
+impl fmt::Display for EditRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EditRejection::OutOfReach => "The voxel is out of reach",
+            EditRejection::RateLimited => "Too many edits, slow down",
+            EditRejection::TooFast => "The voxel was broken too quickly",
+            EditRejection::Protected => "The voxel is in a protected region",
+            EditRejection::Invalid => "The edit isn't possible",
+            EditRejection::Obstructed => "Something is in the way",
+            EditRejection::Cancelled => "The edit was cancelled",
+            EditRejection::Spectating => "Spectators can't edit the world",
+        })
+    }
+}
+
+// Token bucket per session, plus when the session started breaking each voxel in survival
+pub struct EditRateLimiter {
+    tokens: f32,
+    last_refill: Instant,
+    started_breaks: HashMap<IVec3, Instant>,
+}
+
+impl EditRateLimiter {
+    pub fn new() -> Self {
+        Self {
+            tokens: EDIT_BURST,
+            last_refill: Instant::now(),
+            started_breaks: HashMap::new(),
+        }
+    }
+
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * EDITS_PER_SECOND).min(EDIT_BURST);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    // Sent by the client when it starts hitting a voxel. Starting again restarts the timer, the
+    // client only does so once the voxel has healed
+    pub fn start_break(&mut self, position: IVec3) {
+        let now = Instant::now();
+        self.started_breaks
+            .retain(|_, started| now.duration_since(*started).as_secs_f32() < BREAK_START_EXPIRY);
+        if self.started_breaks.len() < MAX_STARTED_BREAKS
+            || self.started_breaks.contains_key(&position)
+        {
+            self.started_breaks.insert(position, now);
+        }
+    }
+
+    // The break time is the one of the voxel being broken, it has to have passed since breaking
+    // of the position started
+    fn take_break(&mut self, position: IVec3, break_time: f32) -> bool {
+        let allowed = self.started_breaks.get(&position).map_or(false, |started| {
+            started.elapsed().as_secs_f32() >= break_time - BREAK_TIME_TOLERANCE
+        });
+        if allowed {
+            self.started_breaks.remove(&position);
+        }
+        allowed
+    }
+}
+
+// Without regions, like on a client that isn't sent them, protection isn't checked
+fn check_common(
+    scene: &VoxelScene,
+    regions: Option<&Regions>,
+    editor: &EditingPlayer,
+    position: IVec3,
+) -> Result<VoxelData, EditRejection> {
+    if !editor.player.game_mode.can_edit() {
+        return Err(EditRejection::Spectating);
+    }
+    if !editor.in_reach(position) {
+        return Err(EditRejection::OutOfReach);
+    }
+    if regions.map_or(false, |regions| !editor.may_edit(regions, position)) {
+        return Err(EditRejection::Protected);
+    }
+    scene.voxel_at(&position).ok_or(EditRejection::Invalid)
+}
+
+// Whether the voxel could be placed, without spending the rate limit. The placement preview
+// uses it to tint the ghost voxel. The colliders are the entities the voxel may not overlap,
+// see entity_colliders
+pub fn check_place(
+    scene: &VoxelScene,
+    regions: Option<&Regions>,
+    editor: &EditingPlayer,
+    position: IVec3,
+    voxel: VoxelData,
+    colliders: &[(Vec3, Vec3)],
+) -> Result<(), EditRejection> {
+    let existing = check_common(scene, regions, editor, position)?;
+    if existing.id != 0 || voxel.id == 0 || get_voxel_by_id(voxel.id).is_none() {
+        return Err(EditRejection::Invalid);
+    }
+    if overlaps_colliders(position, colliders) {
+        return Err(EditRejection::Obstructed);
+    }
+    Ok(())
+}
+
+// Checked before the break is applied, the rate limit is only spent on otherwise valid edits
+pub fn validate_break(
+    scene: &VoxelScene,
+    regions: &Regions,
+    limiter: &mut EditRateLimiter,
+    editor: &EditingPlayer,
+    position: IVec3,
+) -> Result<(), EditRejection> {
+    let existing = check_common(scene, Some(regions), editor, position)?;
+    if existing.id == 0 {
+        return Err(EditRejection::Invalid);
+    }
+    if !limiter.take() {
+        return Err(EditRejection::RateLimited);
+    }
+    // Players don't hold tools yet, so every break is checked against bare hands
+    let break_time = break_time(existing.id, &BreakingTool::HAND);
+    if !editor.player.game_mode.breaks_instantly() && !limiter.take_break(position, break_time) {
+        return Err(EditRejection::TooFast);
+    }
+    Ok(())
+}
+
+pub fn validate_place(
+    scene: &VoxelScene,
+    regions: &Regions,
+    limiter: &mut EditRateLimiter,
+    editor: &EditingPlayer,
+    position: IVec3,
+    voxel: VoxelData,
+    colliders: &[(Vec3, Vec3)],
+) -> Result<(), EditRejection> {
+    check_place(scene, Some(regions), editor, position, voxel, colliders)?;
+    if !limiter.take() {
+        return Err(EditRejection::RateLimited);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::*;
+    use crate::{
+        ecs::components::player_components::{GameMode, Player, PlayerId},
+        voxels::{
+            voxel_interaction::BREAK_TIME, voxel_scene::VoxelChunk, voxel_shapes::voxel_shape,
+        },
+    };
+
+    #[test]
+    fn rate_limiter_allows_burst_then_limits() {
+        let mut limiter = EditRateLimiter::new();
+        for _ in 0..EDIT_BURST as usize {
+            assert!(limiter.take());
+        }
+        assert!(!limiter.take());
+    }
+
+    #[test]
+    fn survival_breaks_are_timed_from_their_start() {
+        let mut limiter = EditRateLimiter::new();
+        let position = IVec3::new(1, 2, 3);
+        // Never started, then started too recently
+        assert!(!limiter.take_break(position, 0.0));
+        limiter.start_break(position);
+        assert!(!limiter.take_break(position, BREAK_TIME));
+        assert!(!limiter.take_break(IVec3::ZERO, 0.0));
+        // Within the tolerance, after which the break has to be started again
+        assert!(limiter.take_break(position, BREAK_TIME_TOLERANCE));
+        assert!(!limiter.take_break(position, BREAK_TIME_TOLERANCE));
+    }
+
+    #[test]
+    fn spectators_cant_edit() {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        let mut editor = EditingPlayer {
+            id: PlayerId(1),
+            player: Player::default(),
+            eye: Vec3::ONE,
+            operator: true,
+        };
+        let position = IVec3::new(1, 2, 1);
+        let stone = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        editor.player.game_mode = GameMode::Spectator;
+        let rejection = check_place(&scene, None, &editor, position, stone, &[]).unwrap_err();
+        assert!(rejection == EditRejection::Spectating);
+        let regions = Regions::default();
+        let mut limiter = EditRateLimiter::new();
+        let rejection = validate_break(&scene, &regions, &mut limiter, &editor, position);
+        assert!(rejection == Err(EditRejection::Spectating));
+    }
+
+    #[test]
+    fn voxels_cant_be_placed_inside_entities() {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        let editor = EditingPlayer {
+            id: PlayerId(1),
+            player: Player::default(),
+            eye: Vec3::new(2.0, 2.0, 2.0),
+            operator: true,
+        };
+        let stone = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        let colliders = [(editor.eye, Vec3::new(0.3, 0.9, 0.3))];
+        // Inside the player, then only touching the top of the collider
+        let rejection = check_place(
+            &scene,
+            None,
+            &editor,
+            IVec3::new(2, 3, 2),
+            stone,
+            &colliders,
+        );
+        assert!(rejection == Err(EditRejection::Obstructed));
+        assert!(!overlaps_colliders(IVec3::new(2, 4, 2), &colliders));
+        assert!(!overlaps_colliders(IVec3::new(3, 2, 2), &colliders));
+    }
+}
@@ -0,0 +1,105 @@
+// Vertex shader
+struct CameraUniform {
+    projection: mat4x4<f32>;
+    transform: mat4x4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct PointLight {
+    position: vec3<f32>;
+    radius: f32;
+    color: vec3<f32>;
+    intensity: f32;
+};
+
+struct LightsUniform {
+    lights: array<PointLight, 16>;
+    count: u32;
+    sun_direction: vec3<f32>;
+    sun_intensity: f32;
+    ambient: f32;
+    fog_color: vec3<f32>;
+    fog_density: f32;
+    eye: vec3<f32>;
+};
+
+[[group(2), binding(0)]]
+var<uniform> dynamic_lights: LightsUniform;
+
+// Light from moving entities, calculated per vertex since there are only a few of them
+fn sample_dynamic_lights(position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var total: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    for (var i: u32 = 0u; i < dynamic_lights.count; i = i + 1u) {
+        let light = dynamic_lights.lights[i];
+        let offset = light.position - position;
+        let distance = length(offset);
+        if (distance < light.radius) {
+            let falloff = 1.0 - distance / light.radius;
+            let facing = clamp(dot(normal, offset / max(distance, 0.001)), 0.0, 1.0) * 0.5 + 0.5;
+            total = total + light.color * light.intensity * falloff * falloff * facing;
+        }
+    }
+    return total;
+}
+
+struct VertexInput {
+    [[location(0)]] position : vec3<f32>;
+    [[location(1)]] color : vec3<f32>;
+    [[location(2)]] normal : vec3<f32>;
+    [[location(3)]] uv : vec2<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_0 : vec4<f32>;
+    [[location(6)]] model_1 : vec4<f32>;
+    [[location(7)]] model_2 : vec4<f32>;
+    [[location(8)]] model_3 : vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] position : vec3<f32>;
+    [[location(1)]] color : vec3<f32>;
+    [[location(2)]] normal : vec3<f32>;
+    [[location(3)]] uv : vec2<f32>;
+    [[location(4)]] dynamic_light : vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(in : VertexInput, instance : InstanceInput) -> VertexOutput {
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let world_position = model * vec4<f32>(in.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.projection * camera.transform * world_position;
+    out.position = world_position.xyz;
+    out.color = in.color;
+    out.normal = normalize((model * vec4<f32>(in.normal, 0.0)).xyz);
+    out.uv = in.uv;
+    out.dynamic_light = sample_dynamic_lights(out.position, out.normal);
+    return out;
+}
+
+[[group(0), binding(0)]]
+var t_diffuse: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_diffuse: sampler;
+
+ // Fragment shader
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    var col: vec4<f32> = vec4<f32>(in.color, 1.0);
+
+    var light_dot: f32 = clamp(dot(in.normal, dynamic_lights.sun_direction), 0.0, 1.0);
+    var shading: f32 = light_dot * dynamic_lights.sun_intensity;
+
+    col = vec4<f32>(col.xyz * (shading + dynamic_lights.ambient + in.dynamic_light), 1.0);
+
+    let fog_amount = dynamic_lights.fog_density * distance(in.position, dynamic_lights.eye);
+    let fog = 1.0 - exp(-fog_amount * fog_amount);
+    col = vec4<f32>(mix(col.xyz, dynamic_lights.fog_color, fog), 1.0);
+
+    return col;
+}
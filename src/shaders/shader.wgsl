@@ -7,6 +7,44 @@ struct CameraUniform {
 [[group(1), binding(0)]]
 var<uniform> camera: CameraUniform;
 
+struct PointLight {
+    position: vec3<f32>;
+    radius: f32;
+    color: vec3<f32>;
+    intensity: f32;
+};
+
+struct LightsUniform {
+    lights: array<PointLight, 16>;
+    count: u32;
+    sun_direction: vec3<f32>;
+    sun_intensity: f32;
+    ambient: f32;
+    fog_color: vec3<f32>;
+    fog_density: f32;
+    eye: vec3<f32>;
+    light_heatmap: u32;
+};
+
+[[group(2), binding(0)]]
+var<uniform> dynamic_lights: LightsUniform;
+
+// Light from moving entities, calculated per vertex since there are only a few of them
+fn sample_dynamic_lights(position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var total: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    for (var i: u32 = 0u; i < dynamic_lights.count; i = i + 1u) {
+        let light = dynamic_lights.lights[i];
+        let offset = light.position - position;
+        let distance = length(offset);
+        if (distance < light.radius) {
+            let falloff = 1.0 - distance / light.radius;
+            let facing = clamp(dot(normal, offset / max(distance, 0.001)), 0.0, 1.0) * 0.5 + 0.5;
+            total = total + light.color * light.intensity * falloff * falloff * facing;
+        }
+    }
+    return total;
+}
+
 struct VertexInput {
     [[location(0)]] position : vec3<f32>;
     [[location(1)]] color : vec3<f32>;
@@ -21,6 +59,7 @@ struct VertexOutput {
     [[location(2)]] normal : vec3<f32>;
     [[location(3)]] uv : vec2<f32>;
     [[location(4)]] camera_position : vec3<f32>;
+    [[location(5)]] dynamic_light : vec3<f32>;
 };
 
 [[stage(vertex)]]
@@ -32,6 +71,7 @@ fn vs_main(in : VertexInput) -> VertexOutput {
     out.normal = in.normal;
     out.uv = in.uv;
     out.camera_position = (camera.transform * vec4<f32>(0.0, 0.0, 0.0, 0.0)).xyz;
+    out.dynamic_light = sample_dynamic_lights(in.position, in.normal);
     return out;
 }
 
@@ -43,6 +83,16 @@ var s_diffuse: sampler;
 fn lerp(a: f32, b: f32, t: f32) -> f32 {
     return (a * (1.0 - t)) + (b * t);
 }
+// Blue through green and yellow to red as t goes from 0 to 1, the same ramp as heatmaps::heat_color
+fn heat_color(t: f32) -> vec3<f32> {
+    let scaled = clamp(t, 0.0, 1.0) * 4.0;
+    return clamp(
+        vec3<f32>(1.5 - abs(scaled - 3.0), 1.5 - abs(scaled - 2.0), 1.5 - abs(scaled - 1.0)),
+        vec3<f32>(0.0),
+        vec3<f32>(1.0)
+    );
+}
+
 fn lerp4(a: vec4<f32>, b: vec4<f32>, t: f32) -> vec4<f32>{
     return vec4<f32>(lerp(a.x, b.x, t), lerp(a.y, b.y, t), lerp(a.z, b.z, t), lerp(a.w, b.w, t));
 }
@@ -50,16 +100,27 @@ fn lerp4(a: vec4<f32>, b: vec4<f32>, t: f32) -> vec4<f32>{
  // Fragment shader
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    var col: vec4<f32> = vec4<f32>(in.color, 1.0); //textureSample(t_diffuse, s_diffuse, in.uv) * in.color;
+    // Untextured vertices point at the atlas' white tile and keep their color
+    var col: vec4<f32> = vec4<f32>(textureSample(t_diffuse, s_diffuse, in.uv).rgb * in.color, 1.0);
+
+    // Faces turned towards the sun are brighter, so the terrain's shape shows as the day goes by
+    var light_dot: f32 = clamp(dot(in.normal, dynamic_lights.sun_direction), 0.0, 1.0);
+
+    var shading: f32 = light_dot * dynamic_lights.sun_intensity;
+
+    let light = vec3<f32>(shading + dynamic_lights.ambient) + in.dynamic_light;
 
-    var light_dir: vec3<f32> = normalize(vec3<f32>(-0.5, 0.6, -0.3));
-    var ambient_light: f32 = 0.3;
-    var light_dot: f32 = clamp(dot(in.normal, light_dir), 0.0, 1.0);
+    // The light debug view shows how bright the surface is lit instead of its color, unfogged
+    if (dynamic_lights.light_heatmap != 0u) {
+        return vec4<f32>(heat_color(dot(light, vec3<f32>(0.2126, 0.7152, 0.0722)) / 1.5), 1.0);
+    }
 
-    var shading: f32 = light_dot;
+    col = vec4<f32>(col.xyz * light, 1.0);
 
-    col = vec4<f32>(col.xyz * (shading + ambient_light), 1.0);
-    col = col;
+    // Squared exponential fog, the biome's atmosphere sets its color and density
+    let fog_amount = dynamic_lights.fog_density * distance(in.position, dynamic_lights.eye);
+    let fog = 1.0 - exp(-fog_amount * fog_amount);
+    col = vec4<f32>(mix(col.xyz, dynamic_lights.fog_color, fog), 1.0);
 
     return col;
 }
\ No newline at end of file
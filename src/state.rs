@@ -1,13 +1,23 @@
 use std::sync::Arc;
 
+use crate::config::{self, EngineConfig};
+use crate::environment::{self, atmosphere};
 use crate::input_manager::set_key;
 use crate::input_manager::set_mouse_button;
 use crate::input_manager::set_mouse_pos;
 use crate::input_manager::PressState;
+use crate::profile_scope;
 use crate::rendering::camera::Camera;
+use crate::rendering::dynamic_lights::DynamicLights;
+use crate::rendering::instancing::INSTANCED_BATCHES;
 use crate::rendering::render_pass_data::render_layers;
+use crate::rendering::staging::StagingRing;
+use crate::rendering::text::TextLayer;
 use crate::rendering::texture;
-use parking_lot::RwLock;
+use crate::rendering::ui::{self, UiLayer};
+use crate::rendering::visibility::Frustum;
+use flume::Receiver;
+use parking_lot::{Mutex, RwLock};
 use wgpu::BindGroupLayout;
 use wgpu::RenderPassDepthStencilAttachment;
 use winit::event::ElementState;
@@ -23,6 +33,19 @@ pub struct State {
     pub size: winit::dpi::PhysicalSize<u32>,
     pub depth_texture: texture::Texture,
     pub camera_bind_group_layout: BindGroupLayout,
+    pub lights_bind_group_layout: BindGroupLayout,
+    pub dynamic_lights: DynamicLights,
+    // Panels built with egui, see rendering::ui::add_panel
+    pub ui: UiLayer,
+    // Console and debug text drawn over everything else
+    pub text: TextLayer,
+    // Mesh uploads, submitted by construct_buffers before the frame is rendered
+    pub staging: Mutex<StagingRing>,
+    // Terrain passes are drawn with one indirect call when the adapter supports it
+    pub multi_draw_indirect: bool,
+    // A minimized window has no surface to draw to, frames are skipped until it's restored
+    pub minimized: bool,
+    config_changes: Receiver<Arc<EngineConfig>>,
 }
 
 impl State {
@@ -44,10 +67,16 @@ impl State {
             .next()
             .unwrap(); // Finds a suitable adapter
 
+        let multi_draw_indirect = adapter
+            .features()
+            .contains(wgpu::Features::MULTI_DRAW_INDIRECT);
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    features: match multi_draw_indirect {
+                        true => wgpu::Features::MULTI_DRAW_INDIRECT,
+                        false => wgpu::Features::empty(),
+                    },
                     limits: wgpu::Limits::default(),
                     label: None,
                 },
@@ -61,7 +90,7 @@ impl State {
             format: surface.get_preferred_format(&adapter).unwrap(),
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo, // Fifo effectively acts like VSync, I don't know why.
+            present_mode: present_mode(config::current().vsync),
         };
         surface.configure(&device, &config);
 
@@ -87,6 +116,12 @@ impl State {
                 label: Some("camera_bind_group_layout"),
             });
 
+        let lights_bind_group_layout = DynamicLights::create_bind_group_layout(&device);
+        let dynamic_lights = DynamicLights::new(&device, &lights_bind_group_layout);
+        let ui = UiLayer::new(&device, config.format, window);
+        let text = TextLayer::new(&device, config.format);
+        let staging = Mutex::new(StagingRing::new(&device));
+
         Self {
             surface,
             device,
@@ -95,23 +130,51 @@ impl State {
             size,
             depth_texture,
             camera_bind_group_layout,
+            lights_bind_group_layout,
+            dynamic_lights,
+            ui,
+            text,
+            staging,
+            multi_draw_indirect,
+            minimized: size.width == 0 || size.height == 0,
+            config_changes: config::subscribe(),
         }
     }
 
+    // Also called with the current size when the surface is lost
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            // If the size is < 0 then wgpu is prone to crashing
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            //self.camera.aspect = self.config.width as f32 / self.config.height as f32;
-
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        // wgpu can't configure a surface without any pixels, which is what minimizing gives
+        if new_size.width == 0 || new_size.height == 0 {
+            self.minimized = true;
+            return;
+        }
+        self.minimized = false;
+        let max_side = self.device.limits().max_texture_dimension_2d;
+        let new_size = winit::dpi::PhysicalSize::new(
+            new_size.width.min(max_side),
+            new_size.height.min(max_side),
+        );
+        let changed = new_size != self.size;
+        self.size = new_size;
+        self.config.width = new_size.width;
+        self.config.height = new_size.height;
+        self.surface.configure(&self.device, &self.config);
+        self.recreate_targets();
+        if changed {
+            ui::notify_resize(new_size);
         }
     }
 
+    // Every texture that has the surface's size, they're recreated together after a resize
+    fn recreate_targets(&mut self) {
+        self.depth_texture =
+            texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.size.width as f32 / self.size.height as f32
+    }
+
     pub fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -153,10 +216,29 @@ impl State {
         }
     }
 
+    // Vsync can be switched in the settings file while the game runs
+    fn apply_config_changes(&mut self) {
+        if let Some(config) = self.config_changes.try_iter().last() {
+            let mode = present_mode(config.vsync);
+            if mode != self.config.present_mode {
+                self.config.present_mode = mode;
+                self.surface.configure(&self.device, &self.config);
+            }
+        }
+    }
+
     pub fn render(&mut self, cameras: Vec<Arc<RwLock<Camera>>>) -> Result<(), wgpu::SurfaceError> {
+        profile_scope!("render");
+        self.apply_config_changes();
+        if self.minimized {
+            return Ok(());
+        }
+        let sky_color =
+            atmosphere::current().sky_color(&environment::current(), atmosphere::is_underwater());
         for camera in &cameras {
-            // Write the camera uniform into the buffer
-            let camera_lock = camera.read();
+            // Write the camera uniform into the buffer, cameras follow the window's shape
+            let mut camera_lock = camera.write();
+            camera_lock.set_aspect(self.aspect());
             self.queue.write_buffer(
                 &camera_lock.buffer,
                 0,
@@ -183,9 +265,9 @@ impl State {
                         resolve_target: None,
                         ops: wgpu::Operations {
                             load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.3,
-                                g: 0.4,
-                                b: 0.6,
+                                r: sky_color.x as f64,
+                                g: sky_color.y as f64,
+                                b: sky_color.z as f64,
                                 a: 1.0,
                             }),
                             store: true,
@@ -223,7 +305,11 @@ impl State {
             }
 
             // Camera has passes, draw them
+            let frustum = Frustum::from_view_projection(
+                camera_lock.build_projection_matrix() * camera_lock.build_transform_matrix(),
+            );
             for layer in &camera_lock.render_layers {
+                profile_scope!("render_layer", layer.as_str());
                 let layer = render_layers::get_layer_by_name(layer.to_string());
                 let layer = match layer {
                     Some(l) => l,
@@ -234,7 +320,14 @@ impl State {
                 // Do a pass
                 for (_pass_id, pass_data) in &layer_lock.passes {
                     // Prepare data
-                    let pass_lock = pass_data.write();
+                    let mut pass_lock = pass_data.write();
+                    let commands = pass_lock.buffer.cull(&frustum);
+                    if commands.is_empty() {
+                        continue;
+                    }
+                    if self.multi_draw_indirect {
+                        pass_lock.buffer.write_draw_commands(self, &commands);
+                    }
                     let material_lock = pass_lock.material.read();
                     let pipeline = Arc::clone(&material_lock.get_pipeline(self));
                     let texture_bind_group =
@@ -263,24 +356,100 @@ impl State {
                     render_pass.set_pipeline(&pipeline);
                     render_pass.set_bind_group(0, &texture_bind_group, &[]);
                     render_pass.set_bind_group(1, &camera_lock.bind_group, &[]);
+                    render_pass.set_bind_group(2, &self.dynamic_lights.bind_group, &[]);
                     render_pass.set_vertex_buffer(0, pass_lock.buffer.vertex_buffer.slice(..));
                     render_pass.set_index_buffer(
                         pass_lock.buffer.index_buffer.slice(..),
                         wgpu::IndexFormat::Uint32,
                     );
-                    // println!("Drawing mesh");
-                    // println!("{} vertices", pass_lock.buffer.vertex_count);
-                    // println!("{} indices", pass_lock.buffer.index_count);
-                    render_pass.draw_indexed(0..pass_lock.buffer.index_count, 0, 0..1);
+                    match self.multi_draw_indirect {
+                        true => render_pass.multi_draw_indexed_indirect(
+                            &pass_lock.buffer.draw_buffer,
+                            0,
+                            commands.len() as u32,
+                        ),
+                        // Without the feature every visible mesh is its own draw call
+                        false => {
+                            for command in &commands {
+                                render_pass.draw_indexed(
+                                    command.index_range(),
+                                    command.base_vertex,
+                                    0..1,
+                                );
+                            }
+                        }
+                    }
                     drop(render_pass); // Required to release the borrow of encoder
                 }
             }
 
+            // Draw the instanced entities on the layers this camera renders
+            for batch in INSTANCED_BATCHES.iter() {
+                profile_scope!("instanced_batch");
+                let batch_lock = batch.value().read();
+                if batch_lock.instance_count == 0
+                    || !camera_lock.render_layers.contains(&batch_lock.render_layer)
+                {
+                    continue;
+                }
+                let pipeline = batch_lock.get_pipeline();
+                let texture_bind_group =
+                    Arc::clone(&batch_lock.material.read().get_texture_bind_group(self));
+
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Instanced Render Pass"),
+                    color_attachments: &[wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &self.depth_texture.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                render_pass.set_pipeline(&pipeline);
+                render_pass.set_bind_group(0, &texture_bind_group, &[]);
+                render_pass.set_bind_group(1, &camera_lock.bind_group, &[]);
+                render_pass.set_bind_group(2, &self.dynamic_lights.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, batch_lock.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, batch_lock.instance_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(batch_lock.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(
+                    0..batch_lock.index_count,
+                    0,
+                    0..batch_lock.instance_count,
+                );
+                drop(render_pass);
+            }
+
+            self.ui
+                .draw(&self.device, &self.queue, &mut encoder, &view, self.size);
+            self.text.draw(&self.device, &mut encoder, &view, self.size);
+
             // submit will accept anything that implements IntoIter
+            profile_scope!("submit");
             self.queue.submit(std::iter::once(encoder.finish()));
+            self.text.submitted();
             output.present();
         }
 
         Ok(())
     }
 }
+
+// Fifo waits for the display like vsync, Immediate presents as soon as a frame is done
+fn present_mode(vsync: bool) -> wgpu::PresentMode {
+    match vsync {
+        true => wgpu::PresentMode::Fifo,
+        false => wgpu::PresentMode::Immediate,
+    }
+}
@@ -0,0 +1,78 @@
+use glam::{IVec3, Vec3};
+use parking_lot::RwLock;
+
+use crate::voxels::{
+    voxel_data::VoxelData,
+    voxel_interaction::{raycast, VoxelHit},
+    voxel_scene::VoxelScene,
+};
+
+// What the crosshair points at, found once a frame by update_target. Systems read it as a
+// resource so they run after it's updated, the UI reads the latest copy through the functions below
+#[derive(Clone, Copy, Default)]
+pub struct Targeting {
+    hit: Option<VoxelHit>,
+}
+
+impl Targeting {
+    pub fn update(&mut self, scene: &VoxelScene, origin: Vec3, direction: Vec3, reach: f32) {
+        self.hit = raycast(scene, origin, direction, reach);
+        *LATEST.write() = self.hit;
+    }
+
+    pub fn hit(&self) -> Option<VoxelHit> {
+        self.hit
+    }
+
+    pub fn targeted_voxel(&self) -> Option<IVec3> {
+        self.hit.map(|hit| hit.position)
+    }
+
+    // The normal of the face the crosshair is on
+    pub fn targeted_face(&self) -> Option<IVec3> {
+        self.hit.map(|hit| hit.normal)
+    }
+
+    // Where a voxel placed against the target goes
+    pub fn placement(&self) -> Option<IVec3> {
+        self.hit.map(|hit| hit.position + hit.normal)
+    }
+}
+
+// The voxel the player would place this frame and where, checked like the server checks the
+// edit but never sent. The ghost of it is drawn green when valid and red when not
+#[derive(Clone, Copy)]
+pub struct PlacementPreview {
+    pub position: IVec3,
+    pub voxel: VoxelData,
+    pub valid: bool,
+}
+
+lazy_static! {
+    static ref LATEST: RwLock<Option<VoxelHit>> = RwLock::new(None);
+    static ref PREVIEW: RwLock<Option<PlacementPreview>> = RwLock::new(None);
+}
+
+// The target from the last frame's systems, for code that runs outside them
+pub fn latest() -> Targeting {
+    Targeting {
+        hit: *LATEST.read(),
+    }
+}
+
+pub fn targeted_voxel() -> Option<IVec3> {
+    latest().targeted_voxel()
+}
+
+pub fn targeted_face() -> Option<IVec3> {
+    latest().targeted_face()
+}
+
+pub fn placement_preview() -> Option<PlacementPreview> {
+    *PREVIEW.read()
+}
+
+// Set by player_interaction each frame, None when nothing is targeted
+pub fn set_placement_preview(preview: Option<PlacementPreview>) {
+    *PREVIEW.write() = preview;
+}
@@ -0,0 +1,76 @@
+use glam::IVec3;
+
+use crate::random::OVERWORLD;
+
+use super::voxel_scene::{biome_in, VoxelScene, CHUNK_SIZE};
+
+// Borders further away than this aren't looked for, formulas see this distance away from any border
+pub const MAX_EDGE_DISTANCE: f32 = 32.0;
+
+// The chunks around a chunk that have a different biome. Biomes are picked per chunk, so the
+// borders are chunk faces and only the horizontal distance to them counts
+#[derive(Clone, Debug, PartialEq)]
+pub struct BiomeEdges {
+    others: Vec<IVec3>,
+}
+
+impl BiomeEdges {
+    pub fn around(chunk: IVec3) -> Self {
+        Self::around_in(OVERWORLD, chunk)
+    }
+
+    pub fn around_in(dimension: &str, chunk: IVec3) -> Self {
+        let biome = biome_in(dimension, chunk);
+        let radius = (MAX_EDGE_DISTANCE / CHUNK_SIZE as f32).ceil() as i32;
+        let mut others = Vec::new();
+        for x in -radius..=radius {
+            for z in -radius..=radius {
+                let other = chunk + IVec3::new(x, 0, z);
+                if biome_in(dimension, other) != biome {
+                    others.push(other);
+                }
+            }
+        }
+        Self { others }
+    }
+
+    // How many voxels the position is from the closest voxel of another biome, capped at
+    // MAX_EDGE_DISTANCE
+    pub fn distance(&self, position: IVec3) -> f32 {
+        let size = CHUNK_SIZE as i32;
+        self.others
+            .iter()
+            .map(|chunk| {
+                let min = *chunk * size;
+                let max = min + IVec3::splat(size - 1);
+                let dx = (min.x - position.x).max(position.x - max.x).max(0);
+                let dz = (min.z - position.z).max(position.z - max.z).max(0);
+                ((dx * dx + dz * dz) as f32).sqrt()
+            })
+            .fold(MAX_EDGE_DISTANCE, f32::min)
+    }
+}
+
+// For features and scripts that place things along borders, such as a tree line
+pub fn biome_edge_distance(position: IVec3) -> f32 {
+    BiomeEdges::around(VoxelScene::chunk_at(&position)).distance(position)
+}
+
+#[cfg(test)]
+mod biome_edges_tests {
+    use super::*;
+
+    #[test]
+    fn distance_is_to_the_closest_other_chunk() {
+        let edges = BiomeEdges {
+            others: vec![IVec3::new(1, 0, 0), IVec3::new(0, 0, -2)],
+        };
+        assert_eq!(edges.distance(IVec3::new(15, 4, 8)), 1.0);
+        assert_eq!(edges.distance(IVec3::new(0, 4, 0)), 16.0);
+        assert_eq!(edges.distance(IVec3::new(0, 4, 15)), 16.0);
+        assert_eq!(
+            BiomeEdges { others: Vec::new() }.distance(IVec3::ZERO),
+            MAX_EDGE_DISTANCE
+        );
+    }
+}
@@ -1,10 +1,18 @@
-use std::{collections::HashMap, fs, sync::Arc};
+use std::{collections::HashMap, sync::Arc};
 
-use glam::{IVec3, Vec3};
+use glam::{IVec3, Vec3, Vec4};
 use parking_lot::RwLock;
+use rayon::prelude::*;
+use tracing::{debug, error};
 
+use crate::data_packs::resource_files;
+use crate::ecs::entities::spawn_rules::SpawnRule;
+use crate::error::{
+    expect_array, expect_bool, expect_f32, expect_str, read_json, required, AssemblageError,
+};
 use crate::voxels::biome_profile::instructions::{
-    DensityInstruction, DepthInstruction, MoistureInstruction, TemperatureInstruction,
+    DensityInstruction, DepthInstruction, EdgeInstruction, MoistureInstruction,
+    SteepnessInstruction, TemperatureInstruction,
 };
 
 use self::instructions::{
@@ -15,40 +23,38 @@ use self::instructions::{
 };
 
 use super::{
+    dimension::SeaLevel,
     voxel_data::VoxelData,
-    voxel_registry::get_voxel_by_name,
+    voxel_registry::{decode_color, get_voxel_by_name, parse_color},
     voxel_shapes::{voxel_shape, VoxelShape},
 };
 
 lazy_static! {
     static ref BIOMES: RwLock<HashMap<String, Arc<BiomeProfile>>> = RwLock::new(load_biomes());
+    // Biomes the loaded world overrides, looked up before the biomes from the resources folder
+    static ref WORLD_BIOMES: RwLock<HashMap<String, Arc<BiomeProfile>>> = RwLock::new(HashMap::new());
 }
 
 fn load_biomes() -> HashMap<String, Arc<BiomeProfile>> {
-    let paths = fs::read_dir("./src/resources/biome_profiles/").unwrap();
-    let mut map = HashMap::new();
-
-    for biome_file in paths.into_iter() {
-        let biome_file = biome_file.unwrap();
-
-        let name = biome_file
-            .file_name()
-            .to_string_lossy()
-            .replace(".json", "");
-
-        map.insert(
-            name.to_string(),
-            Arc::new(BiomeProfile::from_json(
-                fs::read_to_string(biome_file.path()).unwrap(),
-            )),
-        );
-
-        println!("==Created Biome Profile==");
-        println!("Name: {name}");
-        println!("");
-    }
+    resource_files("biome_profiles")
+        .into_par_iter()
+        .filter_map(|(name, path)| {
+            match read_json(&path).and_then(|json| BiomeProfile::from_value(&json)) {
+                Ok(profile) => {
+                    debug!(%name, "Created biome profile");
+                    Some((name, Arc::new(profile)))
+                }
+                Err(e) => {
+                    error!("Skipping the biome {name}: {e}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
 
-    return map;
+pub fn load() {
+    lazy_static::initialize(&BIOMES);
 }
 
 pub fn reload_biomes() {
@@ -57,7 +63,25 @@ pub fn reload_biomes() {
     lock.extend(load_biomes());
 }
 
+// Adds or replaces a biome, used by plugins. Reloading the biomes removes them, so they need to be
+// registered again afterwards
+pub fn register_biome(name: String, profile: BiomeProfile) {
+    BIOMES.write().insert(name, Arc::new(profile));
+}
+
+// Replaces the overrides of the world that was loaded before, so they don't carry over into the
+// next world. Reloading the biomes keeps them
+pub fn set_world_biomes(biomes: HashMap<String, BiomeProfile>) {
+    *WORLD_BIOMES.write() = biomes
+        .into_iter()
+        .map(|(name, profile)| (name, Arc::new(profile)))
+        .collect();
+}
+
 pub fn get_biome_by_name(name: String) -> Option<Arc<BiomeProfile>> {
+    if let Some(profile) = WORLD_BIOMES.read().get(&name) {
+        return Some(Arc::clone(profile));
+    }
     BIOMES.read().get(&name).map(|v| Arc::clone(&v))
 }
 
@@ -65,63 +89,261 @@ pub struct BiomeProfile {
     density_formula: Arc<Box<dyn Instruction<f32>>>,
     id_formula: Arc<Box<dyn Instruction<u16>>>,
     shape_formula: Arc<Box<dyn Instruction<VoxelShape>>>,
+    spawn_rules: Vec<SpawnRule>,
+    // Blended over the surface colors on the map, the alpha is how strongly
+    map_tint: Option<Vec4>,
+    ambience: Option<Ambience>,
+    aquifer: Option<Aquifer>,
+    atmosphere: AtmosphereOverrides,
+    // Stays above water below the dimension's sea level, for dry basins
+    dry: bool,
+    // Working out the slope takes six more density samples per voxel, so it's only done when a
+    // formula reads Steepness
+    uses_slope: bool,
+}
+
+// Fills the air the density formula leaves below a water level with a fluid, so caves and
+// hollows under the level come out flooded
+pub struct Aquifer {
+    // Sampled at each empty voxel, can vary with X and Z through the samplers
+    level_formula: Arc<Box<dyn Instruction<f32>>>,
+    fluid: u16,
+}
+
+impl Aquifer {
+    fn from_json(
+        json: &serde_json::Value,
+        fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
+    ) -> Result<Self, AssemblageError> {
+        let level = expect_str(required(json, "Water Level")?, "Water Level")?;
+        let fluid = expect_str(required(json, "Fluid")?, "Fluid")?;
+        Ok(Self {
+            level_formula: build_f32_instruction(level.to_string(), fields)?,
+            fluid: get_voxel_by_name(fluid.to_string())
+                .ok_or_else(|| AssemblageError::unknown("voxel", fluid))?
+                .id,
+        })
+    }
+}
+
+// What the listener hears while in the biome, sounds are named like the files in the sounds folder
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ambience {
+    // Looped for as long as the listener stays in the biome
+    pub ambient: Option<String>,
+    // One track plays at a time, picked at random
+    pub music: Vec<String>,
+    // Adds an echo to both while the listener is underground
+    pub cave_reverb: bool,
+}
+
+impl Ambience {
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let music = match json.get("Music") {
+            Some(music) => expect_array(music, "Music")?
+                .iter()
+                .map(|track| expect_str(track, "Music").map(str::to_string))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            ambient: json
+                .get("Loop")
+                .map(|v| expect_str(v, "Loop").map(str::to_string))
+                .transpose()?,
+            music,
+            cave_reverb: json
+                .get("Cave Reverb")
+                .map(|v| expect_bool(v, "Cave Reverb"))
+                .transpose()?
+                .unwrap_or(false),
+        })
+    }
+}
+
+// How the biome looks from inside it, whatever is left out keeps the default atmosphere. The
+// renderer blends between biomes as the camera moves
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AtmosphereOverrides {
+    pub fog_color: Option<Vec3>,
+    // Fog thickness per voxel, 0 for none
+    pub fog_density: Option<f32>,
+    // Multiplied with the sky color
+    pub sky_tint: Option<Vec3>,
+    // The fog seen while the camera is under water
+    pub water_color: Option<Vec3>,
+}
+
+impl AtmosphereOverrides {
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let color = |name: &str| {
+            json.get(name)
+                .map(|v| {
+                    let color = expect_str(v, name)?;
+                    parse_color(color)
+                        .map(|color| color.truncate())
+                        .ok_or_else(|| {
+                            AssemblageError::generation(format!("{name} isn't a color: {color}"))
+                        })
+                })
+                .transpose()
+        };
+        Ok(Self {
+            fog_color: color("Fog Color")?,
+            fog_density: json
+                .get("Fog Density")
+                .map(|v| expect_f32(v, "Fog Density").map(|density| density.max(0.0)))
+                .transpose()?,
+            sky_tint: color("Sky Tint")?,
+            water_color: color("Water Color")?,
+        })
+    }
 }
 
 impl BiomeProfile {
-    pub fn from_json(data: String) -> Self {
-        let json: serde_json::Value = serde_json::from_str(&data).unwrap();
+    pub fn from_json(data: String) -> Result<Self, AssemblageError> {
+        Self::from_value(&serde_json::from_str(&data)?)
+    }
+
+    pub fn from_value(json: &serde_json::Value) -> Result<Self, AssemblageError> {
         let mut fields: HashMap<&str, Arc<Box<dyn Instruction<f32>>>> = HashMap::new();
-        for field in json.get("Samplers").unwrap().as_array().unwrap() {
-            let field_type = field.get("Type").unwrap().as_str().unwrap();
-            let field_name = field.get("Name").unwrap().as_str().unwrap();
-            fields.insert(
-                field_name,
-                match field_type {
-                    "Simplex" => Arc::new(Box::new(SimplexInstruction::new(
-                        field.get("Wavelength").unwrap().as_f64().unwrap() as f32,
-                        field.get("Amplitude").unwrap().as_f64().unwrap() as f32,
-                    ))),
-                    "Formula" => build_f32_instruction(
-                        field.get("Formula").unwrap().as_str().unwrap().to_string(),
-                        &fields,
-                    ),
-                    &_ => panic!("Field type is not supported: {field_type}"),
-                },
-            );
-        }
-        Self {
-            density_formula: build_f32_instruction(
-                json.get("Voxel Density")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
-                &fields,
-            ),
-            id_formula: build_voxel_type_instruction(
-                json.get("Voxel Type")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
-                &fields,
-            ),
-            shape_formula: build_voxel_shape_instruction(
-                json.get("Voxel Shape")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
-                &fields,
-            ),
+        let mut uses_slope = false;
+        for field in expect_array(required(json, "Samplers")?, "Samplers")? {
+            let field_type = expect_str(required(field, "Type")?, "Type")?;
+            let field_name = expect_str(required(field, "Name")?, "Name")?;
+            let sampler: Arc<Box<dyn Instruction<f32>>> = match field_type {
+                "Simplex" => Arc::new(Box::new(SimplexInstruction::new(
+                    expect_f32(required(field, "Wavelength")?, "Wavelength")?,
+                    expect_f32(required(field, "Amplitude")?, "Amplitude")?,
+                ))),
+                "Formula" => {
+                    let formula = expect_str(required(field, "Formula")?, "Formula")?;
+                    uses_slope |= formula.contains("Steepness");
+                    build_f32_instruction(formula.to_string(), &fields)?
+                }
+                &_ => {
+                    return Err(AssemblageError::generation(format!(
+                        "the sampler type {field_type} is not supported"
+                    )))
+                }
+            };
+            fields.insert(field_name, sampler);
         }
+        let formula = |name: &str| -> Result<String, AssemblageError> {
+            Ok(expect_str(required(json, name)?, name)?.to_string())
+        };
+        uses_slope |= ["Voxel Type", "Voxel Shape"].iter().any(|name| {
+            json.get(name)
+                .and_then(|v| v.as_str())
+                .map_or(false, |f| f.contains("Steepness"))
+        });
+        let spawn_rules = match json.get("Spawns") {
+            Some(spawns) => expect_array(spawns, "Spawns")?
+                .iter()
+                .map(SpawnRule::from_json)
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            density_formula: build_f32_instruction(formula("Voxel Density")?, &fields)?,
+            id_formula: build_voxel_type_instruction(formula("Voxel Type")?, &fields)?,
+            shape_formula: build_voxel_shape_instruction(formula("Voxel Shape")?, &fields)?,
+            spawn_rules,
+            map_tint: json
+                .get("Map Tint")
+                .map(|tint| expect_str(tint, "Map Tint").map(decode_color))
+                .transpose()?,
+            ambience: json.get("Ambience").map(Ambience::from_json).transpose()?,
+            aquifer: json
+                .get("Aquifer")
+                .map(|aquifer| Aquifer::from_json(aquifer, &fields))
+                .transpose()?,
+            atmosphere: json
+                .get("Atmosphere")
+                .map(AtmosphereOverrides::from_json)
+                .transpose()?
+                .unwrap_or_default(),
+            dry: json
+                .get("Dry")
+                .map(|v| expect_bool(v, "Dry"))
+                .transpose()?
+                .unwrap_or(false),
+            uses_slope,
+        })
+    }
+
+    pub fn spawn_rules(&self) -> &[SpawnRule] {
+        &self.spawn_rules
+    }
+
+    pub fn map_tint(&self) -> Option<Vec4> {
+        self.map_tint
+    }
+
+    pub fn ambience(&self) -> Option<&Ambience> {
+        self.ambience.as_ref()
+    }
+
+    pub fn atmosphere(&self) -> AtmosphereOverrides {
+        self.atmosphere
     }
 
     pub fn sample_density(&self, context: &SampleContext) -> f32 {
         self.density_formula.process(context)
     }
 
-    pub fn sample_voxel(&self, context: &SampleContext) -> VoxelData {
+    // The fluid for an empty voxel below the aquifer's water level, or below the sea level when
+    // the biome has no aquifer and isn't dry
+    pub fn sample_fluid(
+        &self,
+        context: &SampleContext,
+        sea_level: Option<&SeaLevel>,
+    ) -> Option<VoxelData> {
+        let (level, fluid) = match (&self.aquifer, sea_level) {
+            (Some(aquifer), _) => (aquifer.level_formula.process(context), aquifer.fluid),
+            (None, Some(sea)) if !self.dry => (sea.level as f32, sea.fluid),
+            _ => return None,
+        };
+        if context.position.y as f32 >= level {
+            return None;
+        }
+        Some(VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: fluid,
+        })
+    }
+
+    // The direction the terrain faces at the voxel, from the density around it. Up for flat ground
+    // and for biomes that don't read Steepness
+    pub fn sample_slope(&self, context: &SampleContext) -> Vec3 {
+        if !self.uses_slope {
+            return Vec3::Y;
+        }
+        let density_at = |offset: IVec3| {
+            let context = SampleContext {
+                position: context.position + offset,
+                ..*context
+            };
+            self.density_formula.process(&context)
+        };
+        // Density falls towards the air, so the surface faces against its gradient
+        let gradient = Vec3::new(
+            density_at(IVec3::X) - density_at(-IVec3::X),
+            density_at(IVec3::Y) - density_at(-IVec3::Y),
+            density_at(IVec3::Z) - density_at(-IVec3::Z),
+        );
+        let slope = (-gradient).normalize_or_zero();
+        match slope == Vec3::ZERO {
+            true => Vec3::Y,
+            false => slope,
+        }
+    }
+
+    // Works out the slope first, the voxel type and shape formulas can read it
+    pub fn sample_voxel(&self, context: &mut SampleContext) -> VoxelData {
+        context.slope = self.sample_slope(context);
+        let context = &*context;
         let id = self.id_formula.process(context);
         let shape = self.shape_formula.process(context);
         VoxelData {
@@ -133,11 +355,14 @@ impl BiomeProfile {
 }
 
 mod instructions {
-    use std::sync::Arc;
+    use std::{cell::RefCell, collections::HashMap, sync::Arc};
 
-    use noise::{NoiseFn, Perlin};
+    use glam::IVec3;
 
     use super::SampleContext;
+    use crate::noise::perlin::{sample_grid, seed_offset};
+    use crate::random;
+    use crate::voxels::voxel_scene::{pos_to_index, VoxelScene, CHUNK_SIZE};
 
     pub trait Instruction<T>: Sync + Send {
         fn process(&self, context: &SampleContext) -> T;
@@ -300,18 +525,32 @@ mod instructions {
         }
     }
 
-    lazy_static! {
-        static ref PERLIN: Perlin = Perlin::new();
+    // Generation samples a chunk position by position, so the noise of the whole chunk is worked
+    // out by the SIMD kernel on the first sample and the rest are lookups. Grids are kept per thread
+    // by seed, frequency bits and chunk, samplers with the same wavelength share them
+    const MAX_CACHED_GRIDS: usize = 64;
+
+    thread_local! {
+        static GRIDS: RefCell<HashMap<(u64, u32, IVec3), Vec<f32>>> = RefCell::new(HashMap::new());
     }
 
     impl Instruction<f32> for SimplexInstruction {
         fn process(&self, context: &SampleContext) -> f32 {
-            PERLIN.get([
-                (context.position.x as f32 * self.frequency) as f64,
-                (context.position.y as f32 * self.frequency) as f64,
-                (context.position.z as f32 * self.frequency) as f64,
-            ]) as f32
-                * self.amplitude
+            let chunk = VoxelScene::chunk_at(&context.position);
+            let origin = chunk * CHUNK_SIZE as i32;
+            let index = pos_to_index(&(context.position - origin).as_uvec3());
+            let seed = random::overworld().feature("noise");
+            let key = (seed.0, self.frequency.to_bits(), chunk);
+            GRIDS.with(|grids| {
+                let mut grids = grids.borrow_mut();
+                if grids.len() >= MAX_CACHED_GRIDS && !grids.contains_key(&key) {
+                    grids.clear();
+                }
+                let grid = grids.entry(key).or_insert_with(|| {
+                    sample_grid(origin, CHUNK_SIZE, self.frequency, seed_offset(seed))
+                });
+                grid[index as usize]
+            }) * self.amplitude
         }
     }
 
@@ -339,6 +578,19 @@ mod instructions {
             context.density
         }
     }
+    pub struct EdgeInstruction {}
+    impl Instruction<f32> for EdgeInstruction {
+        fn process(&self, context: &SampleContext) -> f32 {
+            context.edge_distance
+        }
+    }
+    // 0 on flat ground, 1 on walls and overhangs
+    pub struct SteepnessInstruction {}
+    impl Instruction<f32> for SteepnessInstruction {
+        fn process(&self, context: &SampleContext) -> f32 {
+            (1.0 - context.slope.y).clamp(0.0, 1.0)
+        }
+    }
     pub struct YInstruction {}
     impl Instruction<f32> for XInstruction {
         fn process(&self, context: &SampleContext) -> f32 {
@@ -359,13 +611,17 @@ mod instructions {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct SampleContext {
     pub position: IVec3,
     pub depth: f32,
+    // The normal of the terrain surface, set when the voxel is sampled
     pub slope: Vec3,
     pub moisture: f32,
     pub temperature: f32,
     pub density: f32,
+    // Voxels to the closest voxel of another biome, at most MAX_EDGE_DISTANCE
+    pub edge_distance: f32,
 }
 
 fn get_instruction_params(string: String) -> Vec<String> {
@@ -393,191 +649,465 @@ fn get_instruction_params(string: String) -> Vec<String> {
     params
 }
 
+// Splits "Name(a, b)" into the name and its parameters, whitespace around either is ignored
+fn split_instruction(instruction: &str) -> Result<(&str, Vec<String>), AssemblageError> {
+    let (instruction_name, instruction_data) =
+        instruction.trim().split_once('(').ok_or_else(|| {
+            AssemblageError::generation(format!("{instruction} is not an instruction"))
+        })?;
+    Ok((
+        instruction_name.trim(),
+        get_instruction_params(instruction_data.to_string()),
+    ))
+}
+
+fn param(
+    params: &[String],
+    index: usize,
+    instruction_name: &str,
+) -> Result<String, AssemblageError> {
+    params.get(index).cloned().ok_or_else(|| {
+        AssemblageError::generation(format!(
+            "{instruction_name} needs at least {} parameters",
+            index + 1
+        ))
+    })
+}
+
+fn unknown_instruction<T>(instruction_name: &str, kind: &str) -> Result<T, AssemblageError> {
+    Err(AssemblageError::generation(format!(
+        "unable to process the instruction {instruction_name} for type {kind}"
+    )))
+}
+
 fn build_bool_instruction(
     instruction: String,
     fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
-) -> Arc<Box<dyn Instruction<bool>>> {
-    let (instruction_name, instruction_data) = instruction.split_once('(').unwrap();
-    let params = get_instruction_params(instruction_data.to_string());
-    match &instruction_name[..] {
-        "Less" => {
-            return Arc::new(Box::new(LessInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
-        }
-        &_ => panic!("Unable to process given instruction: {}", instruction_name),
+) -> Result<Arc<Box<dyn Instruction<bool>>>, AssemblageError> {
+    let (instruction_name, params) = split_instruction(&instruction)?;
+    let f32_param = |index| build_f32_instruction(param(&params, index, instruction_name)?, fields);
+    match instruction_name {
+        "Less" => Ok(Arc::new(Box::new(LessInstruction {
+            val1: f32_param(0)?,
+            val2: f32_param(1)?,
+        }))),
+        &_ => unknown_instruction(instruction_name, "bool"),
     }
 }
 
 fn build_f32_instruction(
     instruction: String,
     fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
-) -> Arc<Box<dyn Instruction<f32>>> {
+) -> Result<Arc<Box<dyn Instruction<f32>>>, AssemblageError> {
+    let instruction = instruction.trim();
     let number = instruction.parse();
 
     if let Ok(number) = number {
-        return Arc::new(Box::new(ConstInstruction { val: number }));
+        return Ok(Arc::new(Box::new(ConstInstruction { val: number })));
     }
 
-    if fields.contains_key(&instruction[..]) {
-        return Arc::clone(&fields.get(&instruction[..]).unwrap());
+    if let Some(field) = fields.get(&instruction[..]) {
+        return Ok(Arc::clone(field));
     }
 
     if !instruction.contains('(') {
-        match &instruction[..] {
-            "Depth" => {
-                return Arc::new(Box::new(DepthInstruction {}));
-            }
-            "Moisture" => {
-                return Arc::new(Box::new(MoistureInstruction {}));
-            }
-            "Temperature" => {
-                return Arc::new(Box::new(TemperatureInstruction {}));
-            }
-            "Density" => {
-                return Arc::new(Box::new(DensityInstruction {}));
-            }
-            "X" => {
-                return Arc::new(Box::new(XInstruction {}));
-            }
-            "Y" => {
-                return Arc::new(Box::new(YInstruction {}));
-            }
-            "Z" => {
-                return Arc::new(Box::new(ZInstruction {}));
+        return Ok(match &instruction[..] {
+            "Depth" => Arc::new(Box::new(DepthInstruction {})),
+            "Moisture" => Arc::new(Box::new(MoistureInstruction {})),
+            "Temperature" => Arc::new(Box::new(TemperatureInstruction {})),
+            "Density" => Arc::new(Box::new(DensityInstruction {})),
+            "Edge" => Arc::new(Box::new(EdgeInstruction {})),
+            "Steepness" => Arc::new(Box::new(SteepnessInstruction {})),
+            "X" => Arc::new(Box::new(XInstruction {})),
+            "Y" => Arc::new(Box::new(YInstruction {})),
+            "Z" => Arc::new(Box::new(ZInstruction {})),
+            &_ => {
+                return Err(AssemblageError::generation(format!(
+                    "the constant variable {instruction} was not found while constructing f32 instruction"
+                )))
             }
-            &_ => panic!(
-                "Constant variable '{}' was not found while constructing f32 instruction",
-                instruction
-            ),
-        }
-    }
-
-    let (instruction_name, instruction_data) = instruction.split_once('(').unwrap();
-    let params = get_instruction_params(instruction_data.to_string());
-    match &instruction_name[..] {
-        "If" => {
-            return Arc::new(Box::new(IfInstruction {
-                condition: build_bool_instruction(params.get(0).unwrap().to_string(), fields),
-                val1: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(2).unwrap().to_string(), fields),
-            }));
-        }
-        "Add" => {
-            return Arc::new(Box::new(AddInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
-        }
-        "Sub" => {
-            return Arc::new(Box::new(SubInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
-        }
-        "Mul" => {
-            return Arc::new(Box::new(MulInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
-        }
-        "Div" => {
-            return Arc::new(Box::new(DivInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
-        }
-        "Sin" => {
-            return Arc::new(Box::new(SinInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
-        }
-        "Cos" => {
-            return Arc::new(Box::new(CosInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
-        }
-        "Mod" => {
-            return Arc::new(Box::new(ModInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
-        }
-        "Floor" => {
-            return Arc::new(Box::new(FloorInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
-        }
-        "Ceil" => {
-            return Arc::new(Box::new(CeilInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
-        }
-        "Round" => {
-            return Arc::new(Box::new(RoundInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
-        }
-        &_ => panic!(
-            "Unable to process given instruction for type f32: {}",
-            instruction_name
-        ),
+        });
     }
+
+    let (instruction_name, params) = split_instruction(&instruction)?;
+    let f32_param = |index| build_f32_instruction(param(&params, index, instruction_name)?, fields);
+    Ok(match instruction_name {
+        "If" => Arc::new(Box::new(IfInstruction {
+            condition: build_bool_instruction(param(&params, 0, instruction_name)?, fields)?,
+            val1: f32_param(1)?,
+            val2: f32_param(2)?,
+        })),
+        "Add" => Arc::new(Box::new(AddInstruction {
+            val1: f32_param(0)?,
+            val2: f32_param(1)?,
+        })),
+        "Sub" => Arc::new(Box::new(SubInstruction {
+            val1: f32_param(0)?,
+            val2: f32_param(1)?,
+        })),
+        "Mul" => Arc::new(Box::new(MulInstruction {
+            val1: f32_param(0)?,
+            val2: f32_param(1)?,
+        })),
+        "Div" => Arc::new(Box::new(DivInstruction {
+            val1: f32_param(0)?,
+            val2: f32_param(1)?,
+        })),
+        "Sin" => Arc::new(Box::new(SinInstruction {
+            val1: f32_param(0)?,
+        })),
+        "Cos" => Arc::new(Box::new(CosInstruction {
+            val1: f32_param(0)?,
+        })),
+        "Mod" => Arc::new(Box::new(ModInstruction {
+            val1: f32_param(0)?,
+            val2: f32_param(1)?,
+        })),
+        "Floor" => Arc::new(Box::new(FloorInstruction {
+            val1: f32_param(0)?,
+        })),
+        "Ceil" => Arc::new(Box::new(CeilInstruction {
+            val1: f32_param(0)?,
+        })),
+        "Round" => Arc::new(Box::new(RoundInstruction {
+            val1: f32_param(0)?,
+        })),
+        &_ => return unknown_instruction(instruction_name, "f32"),
+    })
 }
 
 fn build_voxel_type_instruction(
     instruction: String,
     fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
-) -> Arc<Box<dyn Instruction<u16>>> {
-    let (instruction_name, instruction_data) = instruction.split_once('(').unwrap();
-
-    let params = get_instruction_params(instruction_data.to_string());
-    match &instruction_name[..] {
-        "If" => {
-            return Arc::new(Box::new(IfInstruction {
-                condition: build_bool_instruction(params.get(0).unwrap().to_string(), fields),
-                val1: build_voxel_type_instruction(params.get(1).unwrap().to_string(), fields),
-                val2: build_voxel_type_instruction(params.get(2).unwrap().to_string(), fields),
-            }));
-        }
+) -> Result<Arc<Box<dyn Instruction<u16>>>, AssemblageError> {
+    let (instruction_name, params) = split_instruction(&instruction)?;
+    let type_param =
+        |index| build_voxel_type_instruction(param(&params, index, instruction_name)?, fields);
+    match instruction_name {
+        "If" => Ok(Arc::new(Box::new(IfInstruction {
+            condition: build_bool_instruction(param(&params, 0, instruction_name)?, fields)?,
+            val1: type_param(1)?,
+            val2: type_param(2)?,
+        }))),
         "Voxel" => {
-            return Arc::new(Box::new(ConstInstruction {
-                val: get_voxel_by_name(params.get(0).unwrap().to_string())
-                    .unwrap()
-                    .id,
-            }))
+            let name = param(&params, 0, instruction_name)?;
+            let voxel = get_voxel_by_name(name.clone())
+                .ok_or_else(|| AssemblageError::unknown("voxel", &name))?;
+            Ok(Arc::new(Box::new(ConstInstruction { val: voxel.id })))
         }
-        &_ => panic!("Unable to process given instruction: {}", instruction_name),
+        &_ => unknown_instruction(instruction_name, "voxel type"),
     }
 }
 
 fn build_voxel_shape_instruction(
     instruction: String,
     fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
-) -> Arc<Box<dyn Instruction<VoxelShape>>> {
+) -> Result<Arc<Box<dyn Instruction<VoxelShape>>>, AssemblageError> {
+    let instruction = instruction.trim();
     if !instruction.contains('(') {
         // Const value
-        return Arc::new(Box::new(ConstInstruction {
+        return Ok(Arc::new(Box::new(ConstInstruction {
             val: match &instruction[..] {
                 "CUBE" => voxel_shape::CUBE,
                 "SLAB" => voxel_shape::SLAB,
-                &_ => panic!("Shape '{}' does is not defined", instruction),
+                &_ => return Err(AssemblageError::unknown("shape", &instruction)),
             },
-        }));
+        })));
     }
 
-    let (instruction_name, instruction_data) = instruction.split_once('(').unwrap();
+    let (instruction_name, params) = split_instruction(&instruction)?;
+    let shape_param =
+        |index| build_voxel_shape_instruction(param(&params, index, instruction_name)?, fields);
+    match instruction_name {
+        "If" => Ok(Arc::new(Box::new(IfInstruction {
+            condition: build_bool_instruction(param(&params, 0, instruction_name)?, fields)?,
+            val1: shape_param(1)?,
+            val2: shape_param(2)?,
+        }))),
+        &_ => unknown_instruction(instruction_name, "voxel shape"),
+    }
+}
+
+#[cfg(test)]
+mod biome_profile_tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::voxels::biome_edges::MAX_EDGE_DISTANCE;
+
+    #[test]
+    fn broken_formulas_are_errors() {
+        let fields = HashMap::new();
+        assert!(build_f32_instruction("Add(1, Y)".to_string(), &fields).is_ok());
+        assert!(matches!(
+            build_f32_instruction("Add(1)".to_string(), &fields),
+            Err(AssemblageError::Generation(_))
+        ));
+        assert!(matches!(
+            build_f32_instruction("Pow(2, 3)".to_string(), &fields),
+            Err(AssemblageError::Generation(_))
+        ));
+        assert!(matches!(
+            build_voxel_shape_instruction("STAIRS".to_string(), &fields),
+            Err(AssemblageError::Registry { .. })
+        ));
+        assert!(BiomeProfile::from_json("{\"Samplers\": []}".to_string()).is_err());
+    }
+
+    #[test]
+    fn aquifers_need_a_known_fluid() {
+        let biome = serde_json::json!({
+            "Samplers": [],
+            "Voxel Density": "Sub(5, Y)",
+            "Voxel Type": "If(Less(Y, 0), Voxel(Empty), Voxel(Empty))",
+            "Voxel Shape": "CUBE",
+            "Aquifer": { "Water Level": "Add(-12, X)", "Fluid": "liquid_nonsense" }
+        });
+        assert!(matches!(
+            BiomeProfile::from_value(&biome),
+            Err(AssemblageError::Registry { .. })
+        ));
+    }
+
+    #[test]
+    fn the_sea_fills_biomes_that_arent_dry() {
+        let biome = |dry: bool| {
+            BiomeProfile::from_value(&serde_json::json!({
+                "Samplers": [],
+                "Voxel Density": "Sub(-5, Y)",
+                "Voxel Type": "If(Less(Y, 0), Voxel(Empty), Voxel(Empty))",
+                "Voxel Shape": "CUBE",
+                "Dry": dry,
+            }))
+            .unwrap()
+        };
+        let sea = SeaLevel { level: 0, fluid: 7 };
+        let context = |y: i32| SampleContext {
+            position: IVec3::new(0, y, 0),
+            depth: 0.0,
+            slope: Vec3::ZERO,
+            moisture: 0.0,
+            temperature: 0.0,
+            density: 0.0,
+            edge_distance: 0.0,
+        };
+        let wet = biome(false);
+        assert_eq!(
+            wet.sample_fluid(&context(-1), Some(&sea)).map(|v| v.id),
+            Some(7)
+        );
+        assert!(wet.sample_fluid(&context(0), Some(&sea)).is_none());
+        assert!(wet.sample_fluid(&context(-1), None).is_none());
+        assert!(biome(true).sample_fluid(&context(-1), Some(&sea)).is_none());
+    }
+
+    #[test]
+    fn steepness_follows_the_density_surface() {
+        let steepness = |density: &str| {
+            let biome = BiomeProfile::from_value(&serde_json::json!({
+                "Samplers": [],
+                "Voxel Density": density,
+                "Voxel Type": "If(Less(Steepness, 0.5), Voxel(Empty), Voxel(Empty))",
+                "Voxel Shape": "CUBE",
+            }))
+            .unwrap();
+            let mut context = SampleContext {
+                position: IVec3::new(3, 2, -4),
+                depth: 0.0,
+                slope: Vec3::ZERO,
+                moisture: 0.0,
+                temperature: 0.0,
+                density: 0.0,
+                edge_distance: 0.0,
+            };
+            biome.sample_voxel(&mut context);
+            SteepnessInstruction {}.process(&context)
+        };
+        assert_eq!(steepness("Sub(5, Y)"), 0.0);
+        assert!((steepness("Sub(X, Y)") - (1.0 - 0.5f32.sqrt())).abs() < 1e-5);
+        assert_eq!(steepness("Sub(5, X)"), 1.0);
+    }
+
+    // Random formulas, written out with random whitespace, have to evaluate like this
+    // interpreter does
+    #[derive(Debug, Clone)]
+    enum Expr {
+        Const(f32),
+        Var(&'static str),
+        Unary(&'static str, Box<Expr>),
+        Binary(&'static str, Box<Expr>, Box<Expr>),
+        // If(Less(a, b), c, d)
+        If(Box<[Expr; 4]>),
+    }
+
+    impl Expr {
+        fn render(&self, spaces: &mut impl Iterator<Item = &'static str>) -> String {
+            let (s0, s1) = (spaces.next().unwrap_or(""), spaces.next().unwrap_or(""));
+            match self {
+                Expr::Const(val) => format!("{s0}{val}{s1}"),
+                Expr::Var(name) => format!("{s0}{name}{s1}"),
+                Expr::Unary(name, a) => format!("{s0}{name}{s1}({})", a.render(spaces)),
+                Expr::Binary(name, a, b) => {
+                    let a = a.render(spaces);
+                    format!("{s0}{name}{s1}({a},{})", b.render(spaces))
+                }
+                Expr::If(exprs) => {
+                    let [a, b, c, d] = &**exprs;
+                    let (a, b) = (a.render(spaces), b.render(spaces));
+                    let condition = format!("Less({a},{b})");
+                    let (c, d) = (c.render(spaces), d.render(spaces));
+                    format!("{s0}If{s1}({condition},{c},{d})")
+                }
+            }
+        }
+
+        fn evaluate(&self, context: &SampleContext) -> f32 {
+            match self {
+                Expr::Const(val) => *val,
+                Expr::Var(name) => match *name {
+                    "X" => context.position.x as f32,
+                    "Y" => context.position.y as f32,
+                    "Z" => context.position.z as f32,
+                    "Depth" => context.depth,
+                    "Moisture" => context.moisture,
+                    "Temperature" => context.temperature,
+                    "Edge" => context.edge_distance,
+                    "Steepness" => (1.0 - context.slope.y).clamp(0.0, 1.0),
+                    _ => context.density,
+                },
+                Expr::Unary(name, a) => {
+                    let a = a.evaluate(context);
+                    match *name {
+                        "Sin" => a.sin(),
+                        "Cos" => a.cos(),
+                        "Floor" => a.floor(),
+                        "Ceil" => a.ceil(),
+                        _ => a.round(),
+                    }
+                }
+                Expr::Binary(name, a, b) => {
+                    let (a, b) = (a.evaluate(context), b.evaluate(context));
+                    match *name {
+                        "Add" => a + b,
+                        "Sub" => a - b,
+                        "Mul" => a * b,
+                        "Div" => a / b,
+                        _ => a % b,
+                    }
+                }
+                Expr::If(exprs) => {
+                    let [a, b, c, d] = &**exprs;
+                    if a.evaluate(context) < b.evaluate(context) {
+                        c.evaluate(context)
+                    } else {
+                        d.evaluate(context)
+                    }
+                }
+            }
+        }
+    }
+
+    fn expr() -> impl Strategy<Value = Expr> {
+        let leaf = prop_oneof![
+            (-1000.0f32..1000.0).prop_map(Expr::Const),
+            (-100i32..100).prop_map(|val| Expr::Const(val as f32)),
+            prop::sample::select(vec![
+                "X",
+                "Y",
+                "Z",
+                "Depth",
+                "Moisture",
+                "Temperature",
+                "Density",
+                "Edge",
+                "Steepness"
+            ])
+            .prop_map(Expr::Var),
+        ];
+        leaf.prop_recursive(6, 64, 4, |inner| {
+            prop_oneof![
+                (
+                    prop::sample::select(vec!["Sin", "Cos", "Floor", "Ceil", "Round"]),
+                    inner.clone()
+                )
+                    .prop_map(|(name, a)| Expr::Unary(name, Box::new(a))),
+                (
+                    prop::sample::select(vec!["Add", "Sub", "Mul", "Div", "Mod"]),
+                    inner.clone(),
+                    inner.clone()
+                )
+                    .prop_map(|(name, a, b)| Expr::Binary(
+                        name,
+                        Box::new(a),
+                        Box::new(b)
+                    )),
+                [inner.clone(), inner.clone(), inner.clone(), inner]
+                    .prop_map(|exprs| Expr::If(Box::new(exprs))),
+            ]
+        })
+    }
+
+    fn whitespace() -> impl Strategy<Value = Vec<&'static str>> {
+        prop::collection::vec(
+            prop::sample::select(vec!["", "", " ", "  ", "\t", "\n"]),
+            256,
+        )
+    }
+
+    fn context() -> impl Strategy<Value = SampleContext> {
+        (
+            prop::array::uniform3(-512i32..512),
+            prop::array::uniform4(-64.0f32..64.0),
+            0.0..MAX_EDGE_DISTANCE,
+        )
+            .prop_map(
+                |(position, [depth, moisture, temperature, density], edge_distance)| {
+                    SampleContext {
+                        position: IVec3::from(position),
+                        depth,
+                        slope: Vec3::Y,
+                        moisture,
+                        temperature,
+                        density,
+                        edge_distance,
+                    }
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn formulas_evaluate_like_the_reference(
+            expr in expr(),
+            spaces in whitespace(),
+            context in context(),
+        ) {
+            let formula = expr.render(&mut spaces.into_iter());
+            let instruction = build_f32_instruction(formula.clone(), &HashMap::new());
+            prop_assert!(instruction.is_ok(), "{formula} didn't parse: {:?}", instruction.err());
+            let (val, expected) = (instruction.unwrap().process(&context), expr.evaluate(&context));
+            prop_assert!(
+                val == expected || (val.is_nan() && expected.is_nan()),
+                "{formula} gave {val} instead of {expected}"
+            );
+        }
+
+        #[test]
+        fn params_split_at_the_top_level(exprs in prop::collection::vec(expr(), 1..5)) {
+            let params = exprs
+                .iter()
+                .map(|expr| expr.render(&mut std::iter::repeat(" ")))
+                .collect::<Vec<_>>();
+            prop_assert_eq!(
+                get_instruction_params(format!("{})", params.join(","))),
+                params.iter().map(|param| param.trim().to_string()).collect::<Vec<_>>()
+            );
+        }
 
-    let params = get_instruction_params(instruction_data.to_string());
-    match &instruction_name[..] {
-        "If" => {
-            return Arc::new(Box::new(IfInstruction {
-                condition: build_bool_instruction(params.get(0).unwrap().to_string(), fields),
-                val1: build_voxel_shape_instruction(params.get(1).unwrap().to_string(), fields),
-                val2: build_voxel_shape_instruction(params.get(2).unwrap().to_string(), fields),
-            }));
+        #[test]
+        fn garbage_formulas_dont_panic(formula in "[A-Za-z0-9(), .-]{0,40}") {
+            let _ = build_f32_instruction(formula.clone(), &HashMap::new());
+            let _ = build_bool_instruction(formula, &HashMap::new());
         }
-        &_ => panic!("Unable to process given instruction: {}", instruction_name),
     }
 }
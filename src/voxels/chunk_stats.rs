@@ -0,0 +1,112 @@
+use std::{collections::HashMap, fmt::Write};
+
+use glam::IVec3;
+
+use crate::error::AssemblageError;
+
+use super::{
+    voxel_registry::get_voxel_by_id,
+    voxel_scene::{VoxelChunk, CHUNK_SIZE},
+};
+
+// What the generator made of a chunk, so biome authors can check a biome's formulas without
+// looking at the terrain. Edits and saved chunks aren't included, the chunk is generated again
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkStats {
+    pub position: IVec3,
+    // Chunks are generated from a single biome
+    pub biome: String,
+    pub min_density: f32,
+    pub max_density: f32,
+    // Voxel ids with how many of the chunk's voxels have them, air included
+    pub histogram: HashMap<u16, usize>,
+}
+
+impl ChunkStats {
+    pub fn new(position: IVec3, biome: &str) -> Self {
+        Self {
+            position,
+            biome: biome.to_string(),
+            min_density: f32::INFINITY,
+            max_density: f32::NEG_INFINITY,
+            histogram: HashMap::new(),
+        }
+    }
+
+    pub fn add_density(&mut self, density: f32) {
+        self.min_density = self.min_density.min(density);
+        self.max_density = self.max_density.max(density);
+    }
+
+    pub fn count_voxels(&mut self, chunk: &VoxelChunk) {
+        self.histogram.clear();
+        for voxel in chunk.voxels() {
+            *self.histogram.entry(voxel.id).or_default() += 1;
+        }
+    }
+
+    pub fn solid_voxels(&self) -> usize {
+        self.histogram
+            .iter()
+            .filter(|(id, _)| **id != 0)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    // Most common first, ties by id so the order doesn't change between runs
+    pub fn sorted_histogram(&self) -> Vec<(u16, usize)> {
+        let mut histogram = self
+            .histogram
+            .iter()
+            .map(|(id, count)| (*id, *count))
+            .collect::<Vec<_>>();
+        histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        histogram
+    }
+
+    pub fn summary(&self) -> String {
+        let total = CHUNK_SIZE.pow(3) as f32;
+        let mut summary = format!(
+            "Chunk {} in {}: density {:.2} to {:.2}, {} solid voxels",
+            self.position,
+            self.biome,
+            self.min_density,
+            self.max_density,
+            self.solid_voxels()
+        );
+        for (id, count) in self.sorted_histogram() {
+            let name = voxel_name(id);
+            let share = count as f32 / total * 100.0;
+            let _ = write!(summary, "\n  {name}: {count} ({share:.1}%)");
+        }
+        summary
+    }
+}
+
+pub fn voxel_name(id: u16) -> String {
+    match id {
+        0 => "Empty".to_string(),
+        id => get_voxel_by_id(id).map_or_else(|| format!("#{id}"), |profile| profile.name.clone()),
+    }
+}
+
+pub fn generation_stats(position: IVec3) -> Result<ChunkStats, AssemblageError> {
+    VoxelChunk::generate_with_stats(position).map(|(_, stats)| stats)
+}
+
+#[cfg(test)]
+mod chunk_stats_tests {
+    use super::*;
+
+    #[test]
+    fn densities_and_voxels_are_counted() {
+        let mut stats = ChunkStats::new(IVec3::ZERO, "plains");
+        for density in [0.5, -3.0, 2.0] {
+            stats.add_density(density);
+        }
+        stats.histogram.extend([(0, 10), (4, 3), (2, 3)]);
+        assert_eq!((stats.min_density, stats.max_density), (-3.0, 2.0));
+        assert_eq!(stats.solid_voxels(), 6);
+        assert_eq!(stats.sorted_histogram(), vec![(0, 10), (2, 3), (4, 3)]);
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+
+use glam::{IVec3, UVec3};
+
+use super::{
+    voxel_data::VoxelData,
+    voxel_scene::{pos_to_index, VoxelChunk, CHUNK_SIZE},
+    voxel_shapes::{voxel_directions, voxel_shape},
+};
+
+// Which faces of a chunk can see each other through the chunk, one bit for each pair of faces
+// in voxel_directions order. A face connects to another when the air touching it flows to the
+// other without passing through full cubes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaceConnectivity(u64);
+
+impl FaceConnectivity {
+    // Empty chunks, and chunks that haven't been looked at yet
+    pub const ALL: Self = Self(u64::MAX);
+    pub const NONE: Self = Self(0);
+
+    pub fn of_chunk(chunk: &VoxelChunk) -> Self {
+        if chunk.is_empty {
+            return Self::ALL;
+        }
+        let size = CHUNK_SIZE as i32;
+        let voxels = chunk.voxels();
+        let mut visited = vec![false; voxels.len()];
+        let mut connectivity = Self::NONE;
+        let mut queue = VecDeque::new();
+        for start in 0..voxels.len() {
+            if visited[start] || blocks_sight(&voxels[start]) {
+                continue;
+            }
+            // Flood fills one pocket of air and connects every face it touches
+            let mut faces = 0_u8;
+            visited[start] = true;
+            queue.push_back(index_to_ivec(start));
+            while let Some(position) = queue.pop_front() {
+                faces |= touched_faces(position);
+                for direction in voxel_directions::ALL {
+                    let next = position + direction.as_vec();
+                    if next.cmplt(IVec3::ZERO).any() || next.cmpge(IVec3::splat(size)).any() {
+                        continue;
+                    }
+                    let index = pos_to_index(&next.as_uvec3()) as usize;
+                    if !visited[index] && !blocks_sight(&voxels[index]) {
+                        visited[index] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+            for a in 0..6 {
+                for b in 0..6 {
+                    if faces & 1 << a != 0 && faces & 1 << b != 0 {
+                        connectivity.0 |= 1 << (a * 6 + b);
+                    }
+                }
+            }
+        }
+        connectivity
+    }
+
+    // Faces are indexes into voxel_directions::ALL
+    pub fn connects(&self, from: usize, to: usize) -> bool {
+        self.0 & 1 << (from * 6 + to) != 0
+    }
+}
+
+fn blocks_sight(voxel: &VoxelData) -> bool {
+    voxel.id != 0 && voxel.shape.extract_shape() == voxel_shape::CUBE.extract_shape()
+}
+
+fn index_to_ivec(index: usize) -> IVec3 {
+    let size = CHUNK_SIZE as usize;
+    UVec3::new(
+        (index / (size * size)) as u32,
+        (index % (size * size) / size) as u32,
+        (index % size) as u32,
+    )
+    .as_ivec3()
+}
+
+// The faces of the chunk a voxel lies on, as bits in voxel_directions order
+fn touched_faces(position: IVec3) -> u8 {
+    let last = CHUNK_SIZE as i32 - 1;
+    voxel_directions::ALL
+        .iter()
+        .enumerate()
+        .filter(|(_, direction)| {
+            let axis = direction.as_vec();
+            let coordinate = position.dot(axis.abs());
+            match axis.dot(IVec3::ONE) > 0 {
+                true => coordinate == last,
+                false => coordinate == 0,
+            }
+        })
+        .fold(0, |faces, (face, _)| faces | 1 << face)
+}
+
+#[cfg(test)]
+mod chunk_visibility_tests {
+    use super::*;
+
+    #[test]
+    fn walls_split_the_faces() {
+        let stone = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        let size = CHUNK_SIZE as i32;
+        let mut voxels = vec![
+            VoxelData {
+                shape: voxel_shape::CUBE,
+                state: 0,
+                id: 0,
+            };
+            (size * size * size) as usize
+        ];
+        // A solid floor halfway up, the top and bottom faces can't see each other
+        for x in 0..size {
+            for z in 0..size {
+                voxels[pos_to_index(&UVec3::new(x as u32, 8, z as u32)) as usize] = stone;
+            }
+        }
+        let connectivity =
+            FaceConnectivity::of_chunk(&VoxelChunk::from_voxels(IVec3::ZERO, voxels));
+        let (north, east, up, down) = (0, 2, 4, 5);
+        assert!(!connectivity.connects(up, down));
+        assert!(connectivity.connects(north, east));
+        assert!(connectivity.connects(north, up));
+        assert!(connectivity.connects(east, down));
+    }
+}
@@ -0,0 +1,258 @@
+use std::{collections::HashMap, ops::RangeInclusive, sync::Arc};
+
+use glam::IVec3;
+use parking_lot::RwLock;
+
+use crate::{
+    error::{expect_array, expect_f32, expect_i32, expect_str, required, AssemblageError},
+    random::{world_seed, OVERWORLD},
+    voxels::voxel_registry::get_voxel_by_name,
+};
+
+// Biomes of a dimension with more than one are picked per region of this many chunks across, so a
+// biome is big enough to walk through
+const BIOME_REGION_SIZE: i32 = 8;
+
+lazy_static! {
+    static ref DIMENSIONS: RwLock<HashMap<String, Arc<Dimension>>> =
+        RwLock::new(default_dimensions());
+}
+
+// Worlds that don't configure their dimensions only have the overworld
+fn default_dimensions() -> HashMap<String, Arc<Dimension>> {
+    HashMap::from([(OVERWORLD.to_string(), Arc::new(Dimension::default()))])
+}
+
+// The stages a dimension's chunks go through, the base terrain is always generated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GenerationStage {
+    Terrain,
+    Features,
+}
+
+impl GenerationStage {
+    fn from_name(name: &str) -> Result<Self, AssemblageError> {
+        match name {
+            "Terrain" => Ok(Self::Terrain),
+            "Features" => Ok(Self::Features),
+            _ => Err(AssemblageError::asset(format!(
+                "the generation stage {name} is not supported"
+            ))),
+        }
+    }
+}
+
+// The Temperature and Moisture every biome formula in the dimension sees
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Climate {
+    pub temperature: f32,
+    pub moisture: f32,
+}
+
+// Empty voxels below the level are filled with the fluid in every biome that isn't dry, so oceans
+// don't need an aquifer in each biome
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SeaLevel {
+    pub level: i32,
+    pub fluid: u16,
+}
+
+impl SeaLevel {
+    fn from_value(json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let fluid = expect_str(required(json, "Fluid")?, "Fluid")?;
+        Ok(Self {
+            level: expect_i32(required(json, "Level")?, "Level")?,
+            fluid: get_voxel_by_name(fluid.to_string())
+                .ok_or_else(|| AssemblageError::unknown("voxel", fluid))?
+                .id,
+        })
+    }
+}
+
+// How a dimension of the world generates, read from the "Dimensions" of a world preset. Each
+// dimension has its own biomes, climate, chunk layers and stages, so a cave dimension and a sky
+// dimension can be saved in the same world
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dimension {
+    pub biomes: Vec<String>,
+    pub climate: Climate,
+    // The chunk layers that have terrain, chunks above and below are left empty
+    pub height: RangeInclusive<i32>,
+    pub stages: Vec<GenerationStage>,
+    pub sea_level: Option<SeaLevel>,
+}
+
+// The overworld as it generated before dimensions could be configured
+impl Default for Dimension {
+    fn default() -> Self {
+        Self {
+            biomes: vec!["plains".to_string()],
+            climate: Climate::default(),
+            height: i32::MIN..=i32::MAX,
+            stages: vec![GenerationStage::Terrain, GenerationStage::Features],
+            sea_level: None,
+        }
+    }
+}
+
+impl Dimension {
+    // Fields that are left out keep the overworld's settings
+    pub fn from_value(json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let mut dimension = Self::default();
+        if let Some(biomes) = json.get("Biomes") {
+            dimension.biomes = expect_array(biomes, "Biomes")?
+                .iter()
+                .map(|biome| expect_str(biome, "Biomes").map(str::to_string))
+                .collect::<Result<_, _>>()?;
+            if dimension.biomes.is_empty() {
+                return Err(AssemblageError::asset("\"Biomes\" has to list a biome"));
+            }
+        }
+        if let Some(climate) = json.get("Climate") {
+            dimension.climate = Climate {
+                temperature: expect_f32(required(climate, "Temperature")?, "Temperature")?,
+                moisture: expect_f32(required(climate, "Moisture")?, "Moisture")?,
+            };
+        }
+        if let Some(height) = json.get("Height") {
+            let min = expect_i32(required(height, "Min")?, "Min")?;
+            let max = expect_i32(required(height, "Max")?, "Max")?;
+            if min > max {
+                return Err(AssemblageError::asset(
+                    "\"Height\" has a \"Min\" above its \"Max\"",
+                ));
+            }
+            dimension.height = min..=max;
+        }
+        if let Some(stages) = json.get("Stages") {
+            dimension.stages = expect_array(stages, "Stages")?
+                .iter()
+                .map(|stage| GenerationStage::from_name(expect_str(stage, "Stages")?))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(sea_level) = json.get("Sea Level") {
+            dimension.sea_level = Some(SeaLevel::from_value(sea_level)?);
+        }
+        Ok(dimension)
+    }
+
+    pub fn has_stage(&self, stage: GenerationStage) -> bool {
+        self.stages.contains(&stage)
+    }
+
+    pub fn contains_layer(&self, chunk_y: i32) -> bool {
+        self.height.contains(&chunk_y)
+    }
+
+    // The biome the chunk is generated with. With several biomes each region of chunks picks one
+    // from the dimension's seed, whole columns share a biome
+    pub fn biome_at(&self, name: &str, chunk: IVec3) -> &str {
+        if self.biomes.len() == 1 {
+            return &self.biomes[0];
+        }
+        let region = IVec3::new(
+            chunk.x.div_euclid(BIOME_REGION_SIZE),
+            0,
+            chunk.z.div_euclid(BIOME_REGION_SIZE),
+        );
+        let pick = world_seed()
+            .dimension(name)
+            .feature("biome")
+            .chunk(region)
+            .0;
+        &self.biomes[(pick % self.biomes.len() as u64) as usize]
+    }
+}
+
+// The overworld generates the generator preset biome unless the world configures the overworld
+// itself
+fn world_dimensions(
+    generator_preset: &str,
+    dimensions: HashMap<String, Dimension>,
+) -> HashMap<String, Arc<Dimension>> {
+    let overworld = Dimension {
+        biomes: vec![generator_preset.to_string()],
+        ..Dimension::default()
+    };
+    let mut registered = HashMap::from([(OVERWORLD.to_string(), Arc::new(overworld))]);
+    registered.extend(
+        dimensions
+            .into_iter()
+            .map(|(name, dimension)| (name, Arc::new(dimension))),
+    );
+    registered
+}
+
+// Replaces the dimensions of the world that was loaded before, so none of them carry over
+pub fn set_world_dimensions(generator_preset: &str, dimensions: HashMap<String, Dimension>) {
+    *DIMENSIONS.write() = world_dimensions(generator_preset, dimensions);
+}
+
+pub fn get_dimension(name: &str) -> Option<Arc<Dimension>> {
+    DIMENSIONS.read().get(name).map(Arc::clone)
+}
+
+// Falls back to the default overworld for dimensions that were never registered
+pub fn dimension_or_default(name: &str) -> Arc<Dimension> {
+    get_dimension(name).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod dimension_tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_keep_the_overworld_settings() {
+        let dimension = Dimension::from_value(&serde_json::json!({
+            "Biomes": ["caverns"],
+            "Height": { "Min": -8, "Max": -1 },
+            "Stages": ["Terrain"],
+        }))
+        .unwrap();
+        assert_eq!(dimension.biomes, ["caverns"]);
+        assert_eq!(dimension.climate, Climate::default());
+        assert!(dimension.contains_layer(-8) && !dimension.contains_layer(0));
+        assert!(!dimension.has_stage(GenerationStage::Features));
+    }
+
+    #[test]
+    fn rejects_an_inverted_height() {
+        let json = serde_json::json!({ "Height": { "Min": 4, "Max": 2 } });
+        assert!(Dimension::from_value(&json).is_err());
+    }
+
+    #[test]
+    fn the_overworld_uses_the_generator_preset() {
+        let dimensions = world_dimensions("desert", HashMap::new());
+        assert_eq!(dimensions[OVERWORLD].biomes, ["desert"]);
+        assert_eq!(dimensions.len(), 1);
+
+        let caves = Dimension {
+            biomes: vec!["caverns".to_string()],
+            ..Dimension::default()
+        };
+        let overworld = Dimension {
+            biomes: vec!["forest".to_string()],
+            ..Dimension::default()
+        };
+        let dimensions = world_dimensions(
+            "desert",
+            HashMap::from([
+                ("caves".to_string(), caves.clone()),
+                (OVERWORLD.to_string(), overworld),
+            ]),
+        );
+        assert_eq!(dimensions[OVERWORLD].biomes, ["forest"]);
+        assert_eq!(*dimensions["caves"], caves);
+    }
+
+    #[test]
+    fn biomes_are_shared_by_a_region() {
+        let dimension = Dimension {
+            biomes: vec!["clouds".to_string(), "islands".to_string()],
+            ..Dimension::default()
+        };
+        let biome = dimension.biome_at("sky", IVec3::new(0, 0, 0));
+        assert_eq!(dimension.biome_at("sky", IVec3::new(7, 5, 7)), biome);
+    }
+}
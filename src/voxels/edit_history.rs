@@ -0,0 +1,298 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    mem::size_of,
+};
+
+use anyhow::Result;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use glam::IVec3;
+use parking_lot::Mutex;
+use tracing::error;
+
+use crate::{
+    config,
+    persistence::binary::{ByteReader, ByteWriter},
+};
+
+use super::{voxel_data::VoxelData, voxel_scene::VoxelScene, voxel_shapes::VoxelShape};
+
+#[derive(Clone, Copy)]
+pub struct VoxelChange {
+    pub position: IVec3,
+    pub before: VoxelData,
+    pub after: VoxelData,
+}
+
+// Every change made by one edit, undone and redone together
+#[derive(Clone)]
+pub struct EditOperation {
+    pub label: String,
+    pub changes: Vec<VoxelChange>,
+}
+
+impl EditOperation {
+    fn compress(&self) -> Result<CompressedOperation> {
+        let mut writer = ByteWriter::new();
+        writer.write_leb128(self.changes.len() as u64);
+        for change in &self.changes {
+            writer.write_ivec3(change.position);
+            write_voxel(&mut writer, change.before);
+            write_voxel(&mut writer, change.after);
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&writer.bytes)?;
+        Ok(CompressedOperation {
+            label: self.label.clone(),
+            changes: encoder.finish()?,
+        })
+    }
+}
+
+// An operation as it waits on the undo and redo stacks, edits touch runs of the same voxels so the
+// changes shrink well
+struct CompressedOperation {
+    label: String,
+    changes: Vec<u8>,
+}
+
+impl CompressedOperation {
+    fn decompress(&self) -> Result<EditOperation> {
+        let mut bytes = Vec::new();
+        DeflateDecoder::new(&self.changes[..]).read_to_end(&mut bytes)?;
+        let mut reader = ByteReader::new(&bytes);
+        let count = reader.read_leb128()? as usize;
+        let mut changes = Vec::with_capacity(count);
+        for _ in 0..count {
+            changes.push(VoxelChange {
+                position: reader.read_ivec3()?,
+                before: read_voxel(&mut reader)?,
+                after: read_voxel(&mut reader)?,
+            });
+        }
+        Ok(EditOperation {
+            label: self.label.clone(),
+            changes,
+        })
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.label.capacity() + self.changes.capacity()
+    }
+}
+
+fn write_voxel(writer: &mut ByteWriter, voxel: VoxelData) {
+    writer.write_u8(voxel.shape.data);
+    writer.write_u8(voxel.state);
+    writer.write_leb128(voxel.id as u64);
+}
+
+fn read_voxel(reader: &mut ByteReader) -> Result<VoxelData> {
+    Ok(VoxelData {
+        shape: VoxelShape {
+            data: reader.read_u8()?,
+        },
+        state: reader.read_u8()?,
+        id: reader.read_leb128()? as u16,
+    })
+}
+
+pub struct EditHistory {
+    undo_stack: VecDeque<CompressedOperation>,
+    redo_stack: Vec<CompressedOperation>,
+    // Bytes both stacks hold together, the oldest operations are forgotten past it
+    pub max_memory: usize,
+    memory_usage: usize,
+}
+
+impl EditHistory {
+    pub fn new() -> Self {
+        Self::with_max_memory(config::current().undo_memory)
+    }
+
+    pub fn with_max_memory(max_memory: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            max_memory,
+            memory_usage: 0,
+        }
+    }
+
+    // A new edit makes the undone operations unreachable, so the redo stack is cleared. The newest
+    // operation is kept even when it alone is over the limit, so the last edit can always be undone
+    pub fn push(&mut self, operation: EditOperation) {
+        if operation.changes.is_empty() {
+            return;
+        }
+        let operation = match operation.compress() {
+            Ok(operation) => operation,
+            Err(e) => {
+                error!(
+                    "Failed to store {} in the undo history: {e}",
+                    operation.label
+                );
+                return;
+            }
+        };
+        self.redo_stack.clear();
+        self.memory_usage = operation.memory_usage();
+        self.memory_usage += self
+            .undo_stack
+            .iter()
+            .map(CompressedOperation::memory_usage)
+            .sum::<usize>();
+        self.undo_stack.push_back(operation);
+        while self.memory_usage > self.max_memory && self.undo_stack.len() > 1 {
+            if let Some(oldest) = self.undo_stack.pop_front() {
+                self.memory_usage -= oldest.memory_usage();
+            }
+        }
+    }
+
+    // Bytes held by the undo and redo stacks
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    pub fn undo_count(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    // Undoes up to count operations, returns the number that were undone
+    pub fn undo(&mut self, scene: &VoxelScene, count: usize) -> usize {
+        let mut undone = 0;
+        while undone < count {
+            let compressed = match self.undo_stack.pop_back() {
+                Some(compressed) => compressed,
+                None => break,
+            };
+            let operation = match compressed.decompress() {
+                Ok(operation) => operation,
+                Err(e) => {
+                    error!("Failed to undo {}: {e}", compressed.label);
+                    self.memory_usage -= compressed.memory_usage();
+                    continue;
+                }
+            };
+            // Changes are reverted newest first so a voxel edited twice ends up with its original value
+            for change in &operation.changes {
+                scene.mark_player_modified(&change.position);
+            }
+            let reverted = operation.changes.iter().rev();
+            scene.set_voxels(reverted.map(|change| (change.position, change.before)));
+            self.redo_stack.push(compressed);
+            undone += 1;
+        }
+        undone
+    }
+
+    // Redoes up to count operations, returns the number that were redone
+    pub fn redo(&mut self, scene: &VoxelScene, count: usize) -> usize {
+        let mut redone = 0;
+        while redone < count {
+            let compressed = match self.redo_stack.pop() {
+                Some(compressed) => compressed,
+                None => break,
+            };
+            let operation = match compressed.decompress() {
+                Ok(operation) => operation,
+                Err(e) => {
+                    error!("Failed to redo {}: {e}", compressed.label);
+                    self.memory_usage -= compressed.memory_usage();
+                    continue;
+                }
+            };
+            for change in &operation.changes {
+                scene.mark_player_modified(&change.position);
+            }
+            let changes = operation.changes.iter();
+            scene.set_voxels(changes.map(|change| (change.position, change.after)));
+            self.undo_stack.push_back(compressed);
+            redone += 1;
+        }
+        redone
+    }
+}
+
+// Runs the edit with the scene journal open and records everything it changed as one operation,
+// edits made inside another recorded edit become part of the outer operation
+pub fn record_edit<R>(
+    history: &Mutex<EditHistory>,
+    scene: &VoxelScene,
+    label: &str,
+    edit: impl FnOnce(&VoxelScene) -> R,
+) -> R {
+    if !scene.begin_journal() {
+        return edit(scene);
+    }
+    let result = edit(scene);
+    let changes = scene.end_journal();
+    for change in &changes {
+        scene.mark_player_modified(&change.position);
+    }
+    history.lock().push(EditOperation {
+        label: label.to_string(),
+        changes,
+    });
+    result
+}
+
+#[cfg(test)]
+mod edit_history_tests {
+    use super::*;
+
+    fn operation(label: &str, count: i32) -> EditOperation {
+        let voxel = |id| VoxelData {
+            shape: VoxelShape { data: 0 },
+            state: 0,
+            id,
+        };
+        EditOperation {
+            label: label.to_string(),
+            changes: (0..count)
+                .map(|x| VoxelChange {
+                    position: IVec3::new(x, 4, -x),
+                    before: voxel(0),
+                    after: voxel(x as u16),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn compressed_operations_keep_their_changes() {
+        let original = operation("fill", 500);
+        let compressed = original.compress().unwrap();
+        let restored = compressed.decompress().unwrap();
+        assert_eq!(restored.label, "fill");
+        assert_eq!(restored.changes.len(), 500);
+        let change = restored.changes[321];
+        assert_eq!(change.position, IVec3::new(321, 4, -321));
+        assert_eq!({ change.after.id }, 321);
+    }
+
+    #[test]
+    fn oldest_operations_are_evicted_past_the_memory_limit() {
+        let size = operation("edit", 200).compress().unwrap().memory_usage();
+        let mut history = EditHistory::with_max_memory(size * 3);
+        for _ in 0..10 {
+            history.push(operation("edit", 200));
+        }
+        assert_eq!(history.undo_count(), 3);
+        assert!(history.memory_usage() <= size * 3);
+
+        // The newest operation stays even when it alone is over the limit
+        history.max_memory = 0;
+        history.push(operation("edit", 200));
+        assert_eq!(history.undo_count(), 1);
+    }
+}
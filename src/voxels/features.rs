@@ -0,0 +1,102 @@
+use std::{collections::HashMap, sync::Arc};
+
+use glam::IVec3;
+use parking_lot::RwLock;
+use tracing::warn;
+
+use crate::random::{self, WorldRng};
+
+use super::{
+    voxel_data::VoxelData,
+    voxel_scene::{VoxelChunk, VoxelScene},
+};
+
+// Adds things like trees and ores to a chunk after its terrain is generated. Features only change the
+// chunk they're placed in, so a chunk comes out the same no matter which of its neighbours exist
+pub trait FeaturePlacer: Send + Sync {
+    // The rng is seeded from the world seed, the chunk position and the feature name, placing the
+    // same chunk twice gives the same result. The neighbours have their base terrain only, without
+    // features, so what a feature sees doesn't depend on the order chunks are generated in
+    fn place(&self, chunk: &mut VoxelChunk, neighbors: &NeighborTerrain, rng: &mut WorldRng);
+}
+
+// The base terrain of the 3×3 chunks around and including the one features are placed in, at the
+// same height
+#[derive(Default)]
+pub struct NeighborTerrain {
+    chunks: HashMap<IVec3, Arc<VoxelChunk>>,
+}
+
+impl NeighborTerrain {
+    pub fn new(chunks: HashMap<IVec3, Arc<VoxelChunk>>) -> Self {
+        Self { chunks }
+    }
+
+    // Every position the features of the chunk can look at, the chunk itself included
+    pub fn positions(chunk_pos: IVec3) -> impl Iterator<Item = IVec3> {
+        (-1..=1).flat_map(move |x| (-1..=1).map(move |z| chunk_pos + IVec3::new(x, 0, z)))
+    }
+
+    // None outside the neighbourhood, or where a neighbour's terrain failed to generate
+    pub fn voxel_at(&self, position: &IVec3) -> Option<VoxelData> {
+        let chunk = self.chunks.get(&VoxelScene::chunk_at(position))?;
+        chunk.voxel_scenespace_at(position).copied()
+    }
+}
+
+lazy_static! {
+    // Placed in registration order
+    static ref FEATURES: RwLock<Vec<(String, Arc<dyn FeaturePlacer>)>> = RwLock::new(Vec::new());
+}
+
+// A feature with the same name as an existing one replaces it and keeps its place in the order
+pub fn register_feature(name: &str, feature: Arc<dyn FeaturePlacer>) {
+    let mut features = FEATURES.write();
+    match features.iter_mut().find(|(existing, _)| existing == name) {
+        Some((_, existing)) => {
+            warn!("Feature {name} was replaced");
+            *existing = feature;
+        }
+        None => features.push((name.to_string(), feature)),
+    }
+}
+
+pub fn unregister_feature(name: &str) {
+    FEATURES.write().retain(|(existing, _)| existing != name);
+}
+
+pub fn place_features(chunk: &mut VoxelChunk, neighbors: &NeighborTerrain) {
+    let features = FEATURES.read();
+    let seed = random::overworld().chunk(chunk.position);
+    for (name, feature) in features.iter() {
+        let mut rng = seed.feature(name).rng();
+        feature.place(chunk, neighbors, &mut rng);
+    }
+}
+
+#[cfg(test)]
+mod features_tests {
+    use super::*;
+    use crate::voxels::{voxel_scene::CHUNK_SIZE, voxel_shapes::voxel_shape};
+
+    #[test]
+    fn neighbors_are_read_across_the_border() {
+        let center = IVec3::new(2, 1, -3);
+        let positions = NeighborTerrain::positions(center).collect::<Vec<_>>();
+        assert_eq!(positions.len(), 9);
+        assert!(positions.iter().all(|position| position.y == center.y));
+
+        let east = center + IVec3::X;
+        let mut chunk = VoxelChunk::new(east);
+        let stone = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 3,
+        };
+        let border = east * CHUNK_SIZE as i32;
+        chunk.set_voxel_scenespace(&border, stone);
+        let neighbors = NeighborTerrain::new([(east, Arc::new(chunk))].into_iter().collect());
+        assert_eq!(neighbors.voxel_at(&border).map(|voxel| voxel.id), Some(3));
+        assert!(neighbors.voxel_at(&(border - IVec3::X)).is_none());
+    }
+}
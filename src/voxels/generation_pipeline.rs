@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use glam::IVec3;
+use parking_lot::Mutex;
+use tracing::{debug_span, warn};
+
+use crate::{
+    error::AssemblageError,
+    jobs::{self, JobClass, JobHandle, JobScheduler},
+    random::OVERWORLD,
+};
+
+use super::{dimension::dimension_or_default, features::NeighborTerrain, voxel_scene::VoxelChunk};
+
+// A chunk's base terrain and how long it took, failed chunks are kept so their features stage
+// finds out
+type BaseTerrain = Result<(Arc<VoxelChunk>, Duration), AssemblageError>;
+
+#[derive(Default)]
+struct PipelineState {
+    base_jobs: HashMap<IVec3, JobHandle>,
+    base_terrain: HashMap<IVec3, Arc<BaseTerrain>>,
+    // Feature stages that haven't finished, they hold on to the base terrain around them
+    features: HashMap<IVec3, JobHandle>,
+}
+
+// Cancelled features stages, like those of chunks unloaded before they were generated, don't
+// count
+fn needs_base(features: &HashMap<IVec3, JobHandle>, base: IVec3) -> bool {
+    NeighborTerrain::positions(base).any(|position| {
+        features
+            .get(&position)
+            .map_or(false, |handle| !handle.is_cancelled())
+    })
+}
+
+// Generates chunks in two stages on the job scheduler. Base terrain comes first, the features stage
+// of a chunk only runs once the base terrain of the 3×3 chunks around it is done, so features can
+// look across the chunk border. Base terrain is shared by the chunks around it and dropped once
+// none of them is waiting for it. Each pipeline generates the chunks of one dimension
+#[derive(Clone)]
+pub struct GenerationPipeline {
+    state: Arc<Mutex<PipelineState>>,
+    dimension: Arc<str>,
+    // Runs the stages instead of the engine's scheduler
+    scheduler: Option<Arc<JobScheduler>>,
+}
+
+impl Default for GenerationPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GenerationPipeline {
+    pub fn new() -> Self {
+        Self::for_dimension(OVERWORLD)
+    }
+
+    pub fn for_dimension(name: &str) -> Self {
+        Self {
+            state: Arc::default(),
+            dimension: Arc::from(name),
+            scheduler: None,
+        }
+    }
+
+    pub fn on_scheduler(mut self, scheduler: Arc<JobScheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    fn spawn(&self, dependencies: &[JobHandle], work: impl FnOnce() + Send + 'static) -> JobHandle {
+        match &self.scheduler {
+            Some(scheduler) => scheduler.spawn(JobClass::Generation, dependencies, work),
+            None => jobs::spawn(JobClass::Generation, dependencies, work),
+        }
+    }
+
+    pub fn dimension(&self) -> &str {
+        &self.dimension
+    }
+
+    // Spawns the chunk's features stage after the base stages it depends on, or returns the one
+    // already waiting. The finished chunk is handed to done on the features job, with the time it
+    // took to generate
+    pub fn generate(
+        &self,
+        position: IVec3,
+        done: impl FnOnce(Result<(VoxelChunk, Duration), AssemblageError>) + Send + 'static,
+    ) -> JobHandle {
+        let mut state = self.state.lock();
+        if let Some(handle) = state.features.get(&position) {
+            if !handle.is_cancelled() {
+                return handle.clone();
+            }
+        }
+        let dependencies = NeighborTerrain::positions(position)
+            .filter_map(|base| self.request_base(&mut state, base))
+            .collect::<Vec<_>>();
+        let pipeline = self.clone();
+        let handle = self.spawn(&dependencies, move || {
+            let result = pipeline.place_features(position);
+            pipeline.finish(position);
+            done(result);
+        });
+        state.features.insert(position, handle.clone());
+        handle
+    }
+
+    // The base job of the chunk, None when its base terrain is already there
+    fn request_base(&self, state: &mut PipelineState, position: IVec3) -> Option<JobHandle> {
+        if state.base_terrain.contains_key(&position) {
+            return None;
+        }
+        if let Some(handle) = state.base_jobs.get(&position) {
+            return Some(handle.clone());
+        }
+        let pipeline = self.clone();
+        let handle = self.spawn(&[], move || {
+            let start = Instant::now();
+            let base = VoxelChunk::generate_base_in(&pipeline.dimension, position)
+                .map(|(chunk, _)| (Arc::new(chunk), start.elapsed()));
+            let mut state = pipeline.state.lock();
+            state.base_jobs.remove(&position);
+            // Nothing waits for it anymore when the chunks around were unloaded in the meantime
+            if needs_base(&state.features, position) {
+                state.base_terrain.insert(position, Arc::new(base));
+            }
+        });
+        state.base_jobs.insert(position, handle.clone());
+        Some(handle)
+    }
+
+    fn place_features(&self, position: IVec3) -> Result<(VoxelChunk, Duration), AssemblageError> {
+        let _span = debug_span!("place_features", %position).entered();
+        let terrain = {
+            let state = self.state.lock();
+            NeighborTerrain::positions(position)
+                .filter_map(|base| Some((base, Arc::clone(state.base_terrain.get(&base)?))))
+                .collect::<Vec<_>>()
+        };
+        let mut neighbors = HashMap::new();
+        let mut base = None;
+        for (neighbor, terrain) in terrain {
+            match &*terrain {
+                Ok((chunk, time)) => {
+                    if neighbor == position {
+                        base = Some((chunk.as_ref().clone(), *time));
+                    }
+                    neighbors.insert(neighbor, Arc::clone(chunk));
+                }
+                Err(e) if neighbor == position => return Err(AssemblageError::generation(e)),
+                Err(e) => warn!("Placing the features of chunk {position} without {neighbor}: {e}"),
+            }
+        }
+        let (chunk, base_time) = base.ok_or_else(|| {
+            AssemblageError::generation(format!("the base terrain of {position} is missing"))
+        })?;
+        let start = Instant::now();
+        let dimension = dimension_or_default(&self.dimension);
+        let chunk = chunk.with_features(&dimension, &NeighborTerrain::new(neighbors));
+        Ok((chunk, base_time + start.elapsed()))
+    }
+
+    // Drops the base terrain no features stage is waiting for anymore, along with the cancelled
+    // stages
+    fn finish(&self, position: IVec3) {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        state.features.remove(&position);
+        state.features.retain(|_, handle| !handle.is_cancelled());
+        let features = &state.features;
+        state
+            .base_terrain
+            .retain(|base, _| needs_base(features, *base));
+    }
+}
+
+#[cfg(test)]
+mod generation_pipeline_tests {
+    use rand::seq::SliceRandom;
+
+    use super::*;
+    use crate::random::Seed;
+
+    // Generates the chunks in the given order on a scheduler with that many generation threads,
+    // the voxels of each chunk by position
+    fn generate_region(threads: usize, order: &[IVec3]) -> HashMap<IVec3, Vec<(u16, u8, u8)>> {
+        let scheduler = Arc::new(JobScheduler::new([0, threads, 0, 0]));
+        let pipeline = GenerationPipeline::new().on_scheduler(Arc::clone(&scheduler));
+        let (sender, receiver) = flume::unbounded();
+        for position in order {
+            let (position, sender) = (*position, sender.clone());
+            pipeline.generate(position, move |result| {
+                let (chunk, _) = result.unwrap();
+                let voxels = chunk
+                    .voxels()
+                    .iter()
+                    .map(|voxel| ({ voxel.id }, { voxel.shape.data }, { voxel.state }))
+                    .collect();
+                sender.send((position, voxels)).unwrap();
+            });
+        }
+        drop(sender);
+        let region = receiver.iter().collect();
+        scheduler.shutdown();
+        region
+    }
+
+    #[test]
+    fn thread_counts_generate_the_same_region() {
+        let mut order = Vec::new();
+        for x in -2..2 {
+            for y in 0..2 {
+                for z in -2..2 {
+                    order.push(IVec3::new(x, y, z));
+                }
+            }
+        }
+        let expected = generate_region(1, &order);
+        assert_eq!(expected.len(), order.len());
+        // Requested in a different order each time, so stages finish in a different order too
+        for (seed, threads) in [4, 16, 16].into_iter().enumerate() {
+            order.shuffle(&mut Seed(seed as u64).rng());
+            let region = generate_region(threads, &order);
+            for (position, voxels) in &expected {
+                assert!(
+                    region.get(position) == Some(voxels),
+                    "chunk {position} differs with {threads} threads"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,523 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+use glam::{IVec2, IVec3};
+use parking_lot::{Mutex, RwLock};
+use rand::{seq::SliceRandom, Rng};
+use tracing::{debug, error, warn};
+
+use crate::{
+    data_packs::resource_files,
+    error::{
+        expect_array, expect_bool, expect_f32s, expect_str, expect_u64, read_json, required,
+        AssemblageError,
+    },
+    random::{self, WorldRng},
+};
+
+use super::{
+    features::{register_feature, FeaturePlacer, NeighborTerrain},
+    placeholders::Placeholder,
+    schematic::{Schematic, SchematicTransform},
+    voxel_registry::voxel_has_tag,
+    voxel_scene::{VoxelChunk, VoxelScene, CHUNK_SIZE},
+};
+
+// Assemblies of structures generated with the terrain are kept for the chunks around them, this
+// many at most before they're assembled again
+const MAX_CACHED_ASSEMBLIES: usize = 256;
+
+// Where a piece connects to another, a "socket" placeholder with the data "<facing> <pool> [name]".
+// It's joined to a socket with the same name on a piece from the pool, facing the other way
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Socket {
+    pub position: IVec3,
+    pub facing: IVec3,
+    pub pool: String,
+    pub name: String,
+}
+
+impl Socket {
+    pub fn parse(placeholder: &Placeholder) -> Result<Self> {
+        let mut words = placeholder.data.split_whitespace();
+        let (facing, pool) = match (words.next(), words.next()) {
+            (Some(facing), Some(pool)) => (facing, pool),
+            _ => bail!("A socket needs a facing and a pool"),
+        };
+        let facing = match facing {
+            "north" => IVec3::Z,
+            "south" => -IVec3::Z,
+            "east" => IVec3::X,
+            "west" => -IVec3::X,
+            "up" => IVec3::Y,
+            "down" => -IVec3::Y,
+            facing => bail!("{facing} isn't a direction"),
+        };
+        Ok(Self {
+            position: placeholder.position.as_ivec3(),
+            facing,
+            pool: pool.to_string(),
+            name: words.next().unwrap_or("default").to_string(),
+        })
+    }
+
+    // The sockets of a schematic, where they are and face once it's placed
+    fn placed(schematic: &Schematic, origin: IVec3, transform: SchematicTransform) -> Vec<Socket> {
+        schematic
+            .placeholders
+            .iter()
+            .filter(|placeholder| placeholder.kind == "socket")
+            .filter_map(|placeholder| match Socket::parse(placeholder) {
+                Ok(socket) => Some(socket),
+                Err(e) => {
+                    warn!("Ignoring the socket at {}: {e}", placeholder.position);
+                    None
+                }
+            })
+            .map(|socket| Socket {
+                position: origin + transform.apply(socket.position, schematic.size),
+                facing: transform.apply_direction(socket.facing),
+                ..socket
+            })
+            .collect()
+    }
+}
+
+// The structures a socket can lead to. The fallback caps sockets that nothing else fits and the
+// ones at the depth limit, like a wall closing a corridor
+#[derive(Clone, Debug, PartialEq)]
+pub struct StructurePool {
+    // Structure names and their weights
+    pub pieces: Vec<(String, u32)>,
+    pub fallback: Option<String>,
+}
+
+impl StructurePool {
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let pieces = expect_array(required(json, "pieces")?, "pieces")?
+            .iter()
+            .map(|piece| {
+                let structure = expect_str(required(piece, "structure")?, "structure")?;
+                let weight = piece
+                    .get("weight")
+                    .map_or(Ok(1), |weight| expect_u64(weight, "weight"))?;
+                Ok((structure.to_string(), weight as u32))
+            })
+            .collect::<Result<Vec<_>, AssemblageError>>()?;
+        let fallback = json
+            .get("fallback")
+            .map(|fallback| expect_str(fallback, "fallback").map(str::to_string))
+            .transpose()?;
+        Ok(Self { pieces, fallback })
+    }
+
+    // Every piece once, heavier ones more likely to come first
+    fn shuffled(&self, rng: &mut WorldRng) -> Vec<String> {
+        let mut pieces = self
+            .pieces
+            .iter()
+            .filter(|(_, weight)| *weight > 0)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut order = Vec::with_capacity(pieces.len());
+        while let Ok(picked) = pieces.choose_weighted(rng, |(_, weight)| *weight) {
+            let picked = picked.0.clone();
+            pieces.retain(|(name, _)| *name != picked);
+            order.push(picked);
+        }
+        order
+    }
+}
+
+lazy_static! {
+    static ref POOLS: RwLock<HashMap<String, StructurePool>> = RwLock::new(load_pools());
+}
+
+fn load_pools() -> HashMap<String, StructurePool> {
+    let mut pools = HashMap::new();
+    for (name, path) in resource_files("structure_pools") {
+        match read_json(&path).and_then(|json| StructurePool::from_json(&json)) {
+            Ok(pool) => {
+                debug!(%name, "Loaded structure pool");
+                pools.insert(name, pool);
+            }
+            Err(e) => error!("Skipping the structure pool {name}: {e}"),
+        }
+    }
+    pools
+}
+
+pub fn register_structure_pool(name: &str, pool: StructurePool) {
+    if POOLS.write().insert(name.to_string(), pool).is_some() {
+        warn!("Structure pool {name} was replaced");
+    }
+}
+
+pub fn get_structure_pool(name: &str) -> Option<StructurePool> {
+    POOLS.read().get(name).cloned()
+}
+
+// Loads the pools and registers the structures in "jigsaw_structures" as features
+pub fn load() {
+    lazy_static::initialize(&POOLS);
+    for (name, path) in resource_files("jigsaw_structures") {
+        match read_json(&path).and_then(|json| JigsawStructure::from_json(&name, &json)) {
+            Ok(structure) => register_feature(&name, Arc::new(structure)),
+            Err(e) => error!("Skipping the jigsaw structure {name}: {e}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JigsawLimits {
+    // How many connections away from the start piece pieces can be
+    pub max_depth: u32,
+    // How far pieces can reach from the start along x and z
+    pub max_size: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlacedPiece {
+    pub structure: String,
+    pub schematic: Arc<Schematic>,
+    pub origin: IVec3,
+    pub transform: SchematicTransform,
+    pub depth: u32,
+}
+
+impl PlacedPiece {
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        self.schematic.bounds(self.origin, self.transform)
+    }
+}
+
+// Grows a structure from a start piece by joining pieces to its open sockets, breadth first so
+// every branch gets to grow before the limits are reached. Pieces never overlap
+pub struct JigsawAssembler {
+    limits: JigsawLimits,
+    structures: HashMap<String, Arc<Schematic>>,
+}
+
+impl JigsawAssembler {
+    pub fn new(limits: JigsawLimits) -> Self {
+        Self {
+            limits,
+            structures: HashMap::new(),
+        }
+    }
+
+    // Uses the schematic instead of loading the structure
+    pub fn with_structure(mut self, name: &str, schematic: Schematic) -> Self {
+        self.structures
+            .insert(name.to_string(), Arc::new(schematic));
+        self
+    }
+
+    fn structure(&mut self, name: &str) -> Result<Arc<Schematic>> {
+        if let Some(schematic) = self.structures.get(name) {
+            return Ok(Arc::clone(schematic));
+        }
+        let schematic = Arc::new(Schematic::load_structure(name)?);
+        self.structures
+            .insert(name.to_string(), Arc::clone(&schematic));
+        Ok(schematic)
+    }
+
+    // The start piece is picked from the pool and placed with its minimum corner at the origin
+    pub fn assemble(
+        &mut self,
+        start_pool: &str,
+        origin: IVec3,
+        rng: &mut WorldRng,
+    ) -> Result<Vec<PlacedPiece>> {
+        let pool = get_structure_pool(start_pool)
+            .ok_or_else(|| AssemblageError::unknown("structure pool", start_pool))?;
+        let start = pool
+            .shuffled(rng)
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("The structure pool {start_pool} has no pieces"))?;
+        let schematic = self.structure(&start)?;
+        let transform = SchematicTransform::random(rng);
+        let mut open = Socket::placed(&schematic, origin, transform)
+            .into_iter()
+            .map(|socket| (socket, 1))
+            .collect::<VecDeque<_>>();
+        let mut pieces = vec![PlacedPiece {
+            structure: start,
+            schematic,
+            origin,
+            transform,
+            depth: 0,
+        }];
+
+        while let Some((socket, depth)) = open.pop_front() {
+            let pool = match get_structure_pool(&socket.pool) {
+                Some(pool) => pool,
+                None => {
+                    warn!("There is no structure pool named {}", socket.pool);
+                    continue;
+                }
+            };
+            let candidates = match depth > self.limits.max_depth {
+                true => Vec::new(),
+                false => pool.shuffled(rng),
+            };
+            let piece = candidates
+                .iter()
+                .find_map(|name| self.fit(name, &socket, depth, origin, &pieces, rng));
+            let piece = match piece {
+                Some(piece) => piece,
+                // The fallback's own sockets stay closed
+                None => {
+                    let fallback = pool.fallback.as_deref().and_then(|fallback| {
+                        self.fit(fallback, &socket, depth, origin, &pieces, rng)
+                    });
+                    pieces.extend(fallback);
+                    continue;
+                }
+            };
+            for next in Socket::placed(&piece.schematic, piece.origin, piece.transform) {
+                // The socket the piece was joined by is already taken
+                if next.position != socket.position + socket.facing {
+                    open.push_back((next, depth + 1));
+                }
+            }
+            pieces.push(piece);
+        }
+        Ok(pieces)
+    }
+
+    // The piece joined to the socket by one of its own, in a random turn that doesn't overlap the
+    // pieces so far or leave the size limit
+    fn fit(
+        &mut self,
+        name: &str,
+        socket: &Socket,
+        depth: u32,
+        start: IVec3,
+        pieces: &[PlacedPiece],
+        rng: &mut WorldRng,
+    ) -> Option<PlacedPiece> {
+        let schematic = match self.structure(name) {
+            Ok(schematic) => schematic,
+            Err(e) => {
+                warn!("Skipping the piece {name}: {e}");
+                return None;
+            }
+        };
+        let first_turn = rng.gen_range(0..4);
+        for turn in 0..4 {
+            let transform = SchematicTransform {
+                rotation: (first_turn + turn) % 4,
+                mirror_x: false,
+            };
+            for own in Socket::placed(&schematic, IVec3::ZERO, transform) {
+                if own.name != socket.name || own.facing != -socket.facing {
+                    continue;
+                }
+                let origin = socket.position + socket.facing - own.position;
+                let bounds = schematic.bounds(origin, transform);
+                if !within(bounds, start, self.limits.max_size)
+                    || pieces.iter().any(|piece| overlaps(piece.bounds(), bounds))
+                {
+                    continue;
+                }
+                return Some(PlacedPiece {
+                    structure: name.to_string(),
+                    schematic: Arc::clone(&schematic),
+                    origin,
+                    transform,
+                    depth,
+                });
+            }
+        }
+        None
+    }
+}
+
+fn overlaps(a: (IVec3, IVec3), b: (IVec3, IVec3)) -> bool {
+    a.0.cmple(b.1).all() && b.0.cmple(a.1).all()
+}
+
+fn within((min, max): (IVec3, IVec3), start: IVec3, size: i32) -> bool {
+    (min.x - start.x).abs().max((max.x - start.x).abs()) <= size
+        && (min.z - start.z).abs().max((max.z - start.z).abs()) <= size
+}
+
+// A structure generated with the terrain, like a village or a dungeon. The world is split into
+// square regions, each gets one start at a random spot that's assembled and placed a chunk at a
+// time. Placeholders other than sockets need the world and are left out
+pub struct JigsawStructure {
+    name: String,
+    start_pool: String,
+    // Region width in chunks
+    spacing: i32,
+    limits: JigsawLimits,
+    // The lowest and highest start
+    height: (i32, i32),
+    // Starts on the ground between the heights instead of anywhere between them
+    surface: bool,
+    assemblies: Mutex<HashMap<IVec2, Arc<Vec<PlacedPiece>>>>,
+}
+
+impl JigsawStructure {
+    pub fn from_json(name: &str, json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let height = expect_f32s::<2>(required(json, "height")?, "height")?;
+        let limits = JigsawLimits {
+            max_depth: expect_u64(required(json, "max_depth")?, "max_depth")? as u32,
+            max_size: expect_u64(required(json, "max_size")?, "max_size")? as i32,
+        };
+        Ok(Self {
+            name: name.to_string(),
+            start_pool: expect_str(required(json, "start_pool")?, "start_pool")?.to_string(),
+            spacing: (expect_u64(required(json, "spacing")?, "spacing")? as i32).max(1),
+            limits,
+            height: (
+                height[0].min(height[1]) as i32,
+                height[0].max(height[1]) as i32,
+            ),
+            surface: json
+                .get("surface")
+                .map_or(Ok(false), |surface| expect_bool(surface, "surface"))?,
+            assemblies: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn assembly(&self, region: IVec2) -> Arc<Vec<PlacedPiece>> {
+        if let Some(assembly) = self.assemblies.lock().get(&region) {
+            return Arc::clone(assembly);
+        }
+        // Assembled without the lock, two chunks may both do it but they get the same pieces
+        let assembly = Arc::new(self.assemble(region).unwrap_or_else(|e| {
+            warn!("Skipping {} in region {region}: {e}", self.name);
+            Vec::new()
+        }));
+        let mut assemblies = self.assemblies.lock();
+        if assemblies.len() >= MAX_CACHED_ASSEMBLIES {
+            assemblies.clear();
+        }
+        assemblies.insert(region, Arc::clone(&assembly));
+        assembly
+    }
+
+    fn assemble(&self, region: IVec2) -> Result<Vec<PlacedPiece>> {
+        let mut rng = random::overworld()
+            .chunk(IVec3::new(region.x, 0, region.y))
+            .feature(&self.name)
+            .rng();
+        let width = self.spacing * CHUNK_SIZE as i32;
+        let x = region.x * width + rng.gen_range(0..width);
+        let z = region.y * width + rng.gen_range(0..width);
+        let y = match self.surface {
+            true => match surface_height(x, z, self.height) {
+                Some(y) => y,
+                None => return Ok(Vec::new()),
+            },
+            false => rng.gen_range(self.height.0..=self.height.1),
+        };
+        JigsawAssembler::new(self.limits).assemble(&self.start_pool, IVec3::new(x, y, z), &mut rng)
+    }
+}
+
+impl FeaturePlacer for JigsawStructure {
+    fn place(&self, chunk: &mut VoxelChunk, _neighbors: &NeighborTerrain, _rng: &mut WorldRng) {
+        let min = chunk.scenespace_pos();
+        let max = min + IVec3::splat(CHUNK_SIZE as i32 - 1);
+        let width = self.spacing * CHUNK_SIZE as i32;
+        let reach = self.limits.max_size;
+        for x in (min.x - reach).div_euclid(width)..=(max.x + reach).div_euclid(width) {
+            for z in (min.z - reach).div_euclid(width)..=(max.z + reach).div_euclid(width) {
+                for piece in self.assembly(IVec2::new(x, z)).iter() {
+                    if overlaps(piece.bounds(), (min, max)) {
+                        piece
+                            .schematic
+                            .place_in_chunk(chunk, piece.origin, piece.transform);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Just above the highest ground of the base terrain in the column, the same whichever chunk asks
+fn surface_height(x: i32, z: i32, (low, high): (i32, i32)) -> Option<i32> {
+    let top = VoxelScene::chunk_at(&IVec3::new(x, high, z));
+    let bottom = VoxelScene::chunk_at(&IVec3::new(x, low, z));
+    for chunk_y in (bottom.y..=top.y).rev() {
+        let (chunk, _) = VoxelChunk::generate_base(IVec3::new(top.x, chunk_y, top.z)).ok()?;
+        let chunk_min = chunk.scenespace_pos().y;
+        let chunk_max = chunk_min + CHUNK_SIZE as i32 - 1;
+        for y in (chunk_min.max(low)..=chunk_max.min(high)).rev() {
+            let voxel = chunk.voxel_scenespace_at(&IVec3::new(x, y, z))?;
+            if voxel.id != 0 && !voxel_has_tag(voxel.id, "fluid") {
+                return Some(y + 1);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod jigsaw_tests {
+    use glam::UVec3;
+
+    use super::*;
+
+    #[test]
+    fn corridors_grow_both_ways_until_the_depth_limit() {
+        let mut corridor = Schematic::empty(UVec3::new(1, 1, 3));
+        corridor.set_placeholder(
+            UVec3::new(0, 0, 0),
+            Some(("socket", "south test_corridors")),
+        );
+        corridor.set_placeholder(
+            UVec3::new(0, 0, 2),
+            Some(("socket", "north test_corridors")),
+        );
+        register_structure_pool(
+            "test_corridors",
+            StructurePool {
+                pieces: vec![("test_corridor".to_string(), 1)],
+                fallback: None,
+            },
+        );
+        let limits = JigsawLimits {
+            max_depth: 3,
+            max_size: 64,
+        };
+        let mut rng = random::overworld().derive_name("test").rng();
+        let pieces = JigsawAssembler::new(limits)
+            .with_structure("test_corridor", corridor)
+            .assemble("test_corridors", IVec3::ZERO, &mut rng)
+            .unwrap();
+
+        assert_eq!(pieces.len(), 7);
+        assert_eq!(pieces.iter().map(|piece| piece.depth).max(), Some(3));
+        for (index, piece) in pieces.iter().enumerate() {
+            for other in &pieces[index + 1..] {
+                assert!(!overlaps(piece.bounds(), other.bounds()));
+            }
+        }
+    }
+
+    #[test]
+    fn sockets_turn_with_their_piece() {
+        let placeholder = Placeholder {
+            position: UVec3::new(2, 0, 0),
+            kind: "socket".to_string(),
+            data: "east halls door".to_string(),
+        };
+        let socket = Socket::parse(&placeholder).unwrap();
+        assert_eq!(socket.facing, IVec3::X);
+        assert_eq!(socket.name, "door");
+        let turned = SchematicTransform {
+            rotation: 1,
+            mirror_x: false,
+        };
+        assert_eq!(turned.apply_direction(socket.facing), -IVec3::Z);
+    }
+}
@@ -1,6 +1,26 @@
+pub mod biome_edges;
 pub mod biome_profile;
+pub mod chunk_stats;
+pub mod chunk_visibility;
+pub mod dimension;
+pub mod edit_history;
+pub mod features;
+pub mod generation_pipeline;
+pub mod jigsaw;
+pub mod placeholders;
+pub mod regions;
+pub mod schematic;
+pub mod tick_monitor;
+pub mod voxel_batch;
+pub mod voxel_behavior;
+pub mod voxel_breaking;
 pub mod voxel_data;
+pub mod voxel_interaction;
+pub mod voxel_light;
 pub mod voxel_mesh;
 pub mod voxel_registry;
 pub mod voxel_scene;
 pub mod voxel_shapes;
+pub mod voxel_signal;
+pub mod voxel_simulation;
+pub mod voxel_textures;
@@ -0,0 +1,207 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::*;
+use glam::{IVec3, UVec3, Vec3};
+use parking_lot::RwLock;
+use rand::{seq::SliceRandom, Rng};
+use tracing::warn;
+
+use crate::{
+    ecs::entities::{entity_registry::get_entity_profile, item_drops::spawn_dropped_item},
+    random::WorldRng,
+};
+
+use super::{
+    schematic::{Schematic, SchematicTransform},
+    voxel_registry::get_voxel_by_name,
+    voxel_scene::VoxelScene,
+};
+
+// Jigsaw connections can lead back to the structure they came from, placing stops this many
+// structures deep
+pub const MAX_JIGSAW_DEPTH: u32 = 8;
+
+// A marker in a schematic resolved when the schematic is placed, like the loot of a chest or where
+// a monster stands. The kind names the handler, what the data means is up to it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placeholder {
+    // Relative to the schematic's minimum corner
+    pub position: UVec3,
+    pub kind: String,
+    pub data: String,
+}
+
+// What resolving a placeholder can change
+pub struct PlaceholderContext<'a> {
+    pub scene: &'a VoxelScene,
+    pub world: &'a mut legion::World,
+    // How many jigsaw connections led to the structure being resolved
+    pub depth: u32,
+    // The corners of the structures placed by handlers, so callers can resend their chunks
+    pub placed: Vec<(IVec3, IVec3)>,
+}
+
+impl<'a> PlaceholderContext<'a> {
+    pub fn new(scene: &'a VoxelScene, world: &'a mut legion::World) -> Self {
+        Self {
+            scene,
+            world,
+            depth: 0,
+            placed: Vec::new(),
+        }
+    }
+}
+
+pub trait PlaceholderHandler: Send + Sync {
+    // The rng is seeded from the world seed, the position and the kind, so placing a structure at
+    // the same spot gives the same contents
+    fn resolve(
+        &self,
+        context: &mut PlaceholderContext,
+        position: IVec3,
+        transform: SchematicTransform,
+        data: &str,
+        rng: &mut WorldRng,
+    ) -> Result<()>;
+}
+
+lazy_static! {
+    static ref HANDLERS: RwLock<HashMap<String, Arc<dyn PlaceholderHandler>>> =
+        RwLock::new(builtin_handlers());
+}
+
+fn builtin_handlers() -> HashMap<String, Arc<dyn PlaceholderHandler>> {
+    let mut handlers: HashMap<String, Arc<dyn PlaceholderHandler>> = HashMap::new();
+    handlers.insert("loot".to_string(), Arc::new(LootHandler {}));
+    handlers.insert("spawn".to_string(), Arc::new(SpawnHandler {}));
+    handlers.insert("jigsaw".to_string(), Arc::new(JigsawHandler {}));
+    handlers.insert("socket".to_string(), Arc::new(SocketHandler {}));
+    handlers
+}
+
+// Plugins can add kinds or replace the built in loot, spawn, jigsaw and socket handlers
+pub fn register_placeholder_handler(kind: &str, handler: Arc<dyn PlaceholderHandler>) {
+    if HANDLERS.write().insert(kind.to_string(), handler).is_some() {
+        warn!("Placeholder handler {kind} was replaced");
+    }
+}
+
+pub fn get_placeholder_handler(kind: &str) -> Option<Arc<dyn PlaceholderHandler>> {
+    HANDLERS.read().get(kind).cloned()
+}
+
+// Items dropped at the placeholder, the data lists voxels with a count or a range of counts like
+// "torch 1-3, stone 4". An item that rolls 0 is left out
+pub struct LootHandler {}
+
+impl PlaceholderHandler for LootHandler {
+    fn resolve(
+        &self,
+        context: &mut PlaceholderContext,
+        position: IVec3,
+        _transform: SchematicTransform,
+        data: &str,
+        rng: &mut WorldRng,
+    ) -> Result<()> {
+        for (id, min, max) in parse_loot(data)? {
+            let count = rng.gen_range(min..=max);
+            if count > 0 {
+                spawn_dropped_item(context.world, center(position), id, count);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_loot(data: &str) -> Result<Vec<(u16, u32, u32)>> {
+    data.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, counts) = entry.split_once(' ').unwrap_or((entry, "1"));
+            let id = get_voxel_by_name(name.to_string())
+                .ok_or_else(|| anyhow!("There is no voxel named {name}"))?
+                .id;
+            let counts = counts.trim();
+            let (min, max) = counts.split_once('-').unwrap_or((counts, counts));
+            let (min, max) = (min.trim().parse::<u32>()?, max.trim().parse::<u32>()?);
+            ensure!(min <= max, "{entry} has a count range that goes down");
+            Ok((id, min, max))
+        })
+        .collect()
+}
+
+// Spawns the entity profile the data names
+pub struct SpawnHandler {}
+
+impl PlaceholderHandler for SpawnHandler {
+    fn resolve(
+        &self,
+        context: &mut PlaceholderContext,
+        position: IVec3,
+        _transform: SchematicTransform,
+        data: &str,
+        _rng: &mut WorldRng,
+    ) -> Result<()> {
+        let profile = get_entity_profile(data.trim())
+            .ok_or_else(|| anyhow!("There is no entity named {data}"))?;
+        profile.spawn(context.world, center(position));
+        Ok(())
+    }
+}
+
+// Places another structure with its minimum corner at the placeholder, turned like the one it's
+// attached to. The data names the structure, or several like "room|corridor|stairs" to pick one at
+// random, which is how dungeons grow differently every time
+pub struct JigsawHandler {}
+
+impl PlaceholderHandler for JigsawHandler {
+    fn resolve(
+        &self,
+        context: &mut PlaceholderContext,
+        position: IVec3,
+        transform: SchematicTransform,
+        data: &str,
+        rng: &mut WorldRng,
+    ) -> Result<()> {
+        if context.depth >= MAX_JIGSAW_DEPTH {
+            return Ok(());
+        }
+        let pool = data
+            .split('|')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        let name = pool
+            .choose(rng)
+            .ok_or_else(|| anyhow!("The jigsaw connection names no structure"))?;
+        let structure = Schematic::load_structure(name)?;
+        structure.place(context.scene, position, transform);
+        context.placed.push(structure.bounds(position, transform));
+        context.depth += 1;
+        structure.resolve_placeholders(context, position, transform);
+        context.depth -= 1;
+        Ok(())
+    }
+}
+
+// Sockets are where the jigsaw assembler joined pieces, see jigsaw::Socket. Once the pieces are placed
+// there's nothing left to do
+pub struct SocketHandler {}
+
+impl PlaceholderHandler for SocketHandler {
+    fn resolve(
+        &self,
+        _context: &mut PlaceholderContext,
+        _position: IVec3,
+        _transform: SchematicTransform,
+        _data: &str,
+        _rng: &mut WorldRng,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn center(position: IVec3) -> Vec3 {
+    position.as_vec3() + Vec3::new(0.5, 0.0, 0.5)
+}
@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use glam::IVec3;
+use serde_json::{json, Value};
+
+use crate::ecs::components::player_components::PlayerId;
+
+use super::voxel_scene::VoxelScene;
+
+// Operators are members of this group without being listed in it
+pub const OPERATOR_GROUP: &str = "operators";
+
+// The part of the world a region covers, both corners of a box are inclusive
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegionArea {
+    Box { min: IVec3, max: IVec3 },
+    Chunks(HashSet<IVec3>),
+}
+
+impl RegionArea {
+    pub fn new_box(a: IVec3, b: IVec3) -> Self {
+        RegionArea::Box {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn contains(&self, position: IVec3) -> bool {
+        match self {
+            RegionArea::Box { min, max } => {
+                position.cmpge(*min).all() && position.cmple(*max).all()
+            }
+            RegionArea::Chunks(chunks) => chunks.contains(&VoxelScene::chunk_at(&position)),
+        }
+    }
+}
+
+// Who a rule applies to, written as a player id or "group:name" in the regions file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Subject {
+    Player(PlayerId),
+    Group(String),
+}
+
+impl Subject {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.strip_prefix("group:") {
+            Some(group) if !group.is_empty() => Some(Subject::Group(group.to_lowercase())),
+            Some(_) => None,
+            None => PlayerId::parse(text).map(Subject::Player),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Subject::Player(id) => id.to_uuid_string(),
+            Subject::Group(group) => format!("group:{group}"),
+        }
+    }
+}
+
+// A named area where edits are denied unless a rule allows them. A rule for the player decides
+// over rules for their groups, and a denying group rule over an allowing one
+#[derive(Clone, Debug)]
+pub struct ProtectedRegion {
+    pub name: String,
+    pub area: RegionArea,
+    // Whether players no rule applies to can edit
+    pub allow_everyone: bool,
+    rules: Vec<(Subject, bool)>,
+}
+
+impl ProtectedRegion {
+    pub fn new(name: &str, area: RegionArea) -> Self {
+        Self {
+            name: name.to_string(),
+            area,
+            allow_everyone: false,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn contains(&self, position: IVec3) -> bool {
+        self.area.contains(position)
+    }
+
+    pub fn rules(&self) -> &[(Subject, bool)] {
+        &self.rules
+    }
+
+    // Replaces the subject's rule, None removes it
+    pub fn set_rule(&mut self, subject: Subject, allow: Option<bool>) {
+        self.rules.retain(|(existing, _)| *existing != subject);
+        if let Some(allow) = allow {
+            self.rules.push((subject, allow));
+        }
+    }
+
+    fn rule(&self, subject: &Subject) -> Option<bool> {
+        self.rules
+            .iter()
+            .find(|(existing, _)| existing == subject)
+            .map(|(_, allow)| *allow)
+    }
+
+    pub fn allows(&self, player: PlayerId, groups: &[&str]) -> bool {
+        if let Some(allow) = self.rule(&Subject::Player(player)) {
+            return allow;
+        }
+        groups
+            .iter()
+            .filter_map(|group| self.rule(&Subject::Group(group.to_string())))
+            .reduce(|a, b| a && b)
+            .unwrap_or(self.allow_everyone)
+    }
+
+    fn to_json(&self) -> Value {
+        let subjects = |allow: bool| {
+            self.rules
+                .iter()
+                .filter(|(_, a)| *a == allow)
+                .map(|(subject, _)| subject.name())
+                .collect::<Vec<_>>()
+        };
+        let mut json = json!({
+            "Name": self.name,
+            "Allow Everyone": self.allow_everyone,
+            "Allow": subjects(true),
+            "Deny": subjects(false),
+        });
+        match &self.area {
+            RegionArea::Box { min, max } => {
+                json["Min"] = json!(min.to_array());
+                json["Max"] = json!(max.to_array());
+            }
+            RegionArea::Chunks(chunks) => {
+                let mut chunks = chunks.iter().map(|c| c.to_array()).collect::<Vec<_>>();
+                chunks.sort_unstable();
+                json["Chunks"] = json!(chunks);
+            }
+        }
+        json
+    }
+
+    fn from_json(json: &Value) -> Result<Self> {
+        let name = json
+            .get("Name")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("A region is missing \"Name\""))?;
+        let area = match (json.get("Min"), json.get("Max"), json.get("Chunks")) {
+            (Some(min), Some(max), _) => RegionArea::new_box(parse_ivec3(min)?, parse_ivec3(max)?),
+            (_, _, Some(Value::Array(chunks))) => {
+                RegionArea::Chunks(chunks.iter().map(parse_ivec3).collect::<Result<_>>()?)
+            }
+            _ => {
+                return Err(anyhow!(
+                    "The region {name} needs \"Min\" and \"Max\" or \"Chunks\""
+                ))
+            }
+        };
+        let mut region = ProtectedRegion::new(name, area);
+        region.allow_everyone = json
+            .get("Allow Everyone")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        for (key, allow) in [("Allow", true), ("Deny", false)] {
+            let subjects = json.get(key).and_then(|v| v.as_array());
+            for subject in subjects.into_iter().flatten() {
+                let subject = subject.as_str().and_then(Subject::parse).ok_or_else(|| {
+                    anyhow!("{subject} in the region {name} isn't a player or group")
+                })?;
+                region.set_rule(subject, Some(allow));
+            }
+        }
+        Ok(region)
+    }
+}
+
+fn parse_ivec3(json: &Value) -> Result<IVec3> {
+    match json
+        .as_array()
+        .map(|a| a.iter().map(|v| v.as_i64()).collect::<Vec<_>>())
+    {
+        Some(values) => match values[..] {
+            [Some(x), Some(y), Some(z)] => Ok(IVec3::new(x as i32, y as i32, z as i32)),
+            _ => Err(anyhow!("{json} isn't a position")),
+        },
+        None => Err(anyhow!("{json} isn't a position")),
+    }
+}
+
+// Every region of a world and the groups their rules can name
+#[derive(Default)]
+pub struct Regions {
+    regions: Vec<ProtectedRegion>,
+    groups: BTreeMap<String, HashSet<PlayerId>>,
+}
+
+impl Regions {
+    // A region with the same name is replaced
+    pub fn add(&mut self, region: ProtectedRegion) {
+        self.remove(&region.name);
+        self.regions.push(region);
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let count = self.regions.len();
+        self.regions.retain(|region| region.name != name);
+        self.regions.len() != count
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut ProtectedRegion> {
+        self.regions.iter_mut().find(|region| region.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProtectedRegion> {
+        self.regions.iter()
+    }
+
+    pub fn set_member(&mut self, group: &str, player: PlayerId, member: bool) {
+        let group = group.to_lowercase();
+        match member {
+            true => {
+                self.groups.entry(group).or_default().insert(player);
+            }
+            false => {
+                if let Some(members) = self.groups.get_mut(&group) {
+                    members.remove(&player);
+                    if members.is_empty() {
+                        self.groups.remove(&group);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn groups_of(&self, player: PlayerId, operator: bool) -> Vec<&str> {
+        let listed = self
+            .groups
+            .iter()
+            .filter(|(_, members)| members.contains(&player))
+            .map(|(group, _)| group.as_str());
+        operator
+            .then_some(OPERATOR_GROUP)
+            .into_iter()
+            .chain(listed)
+            .collect()
+    }
+
+    // Every region containing the voxel has to allow the edit
+    pub fn can_edit(&self, player: PlayerId, operator: bool, position: IVec3) -> bool {
+        let mut containing = self
+            .regions
+            .iter()
+            .filter(|r| r.contains(position))
+            .peekable();
+        if containing.peek().is_none() {
+            return true;
+        }
+        let groups = self.groups_of(player, operator);
+        containing.all(|region| region.allows(player, &groups))
+    }
+
+    pub fn to_json(&self) -> Value {
+        let groups = self
+            .groups
+            .iter()
+            .map(|(group, members)| {
+                let mut members = members
+                    .iter()
+                    .map(PlayerId::to_uuid_string)
+                    .collect::<Vec<_>>();
+                members.sort_unstable();
+                (group.clone(), json!(members))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        json!({
+            "Groups": groups,
+            "Regions": self.regions.iter().map(ProtectedRegion::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let mut regions = Regions::default();
+        for (group, members) in json
+            .get("Groups")
+            .and_then(|v| v.as_object())
+            .into_iter()
+            .flatten()
+        {
+            for member in members.as_array().into_iter().flatten() {
+                let id = member
+                    .as_str()
+                    .and_then(PlayerId::parse)
+                    .ok_or_else(|| anyhow!("{member} in the group {group} isn't a player id"))?;
+                regions.set_member(group, id, true);
+            }
+        }
+        for region in json
+            .get("Regions")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+        {
+            regions.add(ProtectedRegion::from_json(region)?);
+        }
+        Ok(regions)
+    }
+}
+
+#[cfg(test)]
+mod regions_tests {
+    use super::*;
+
+    #[test]
+    fn protected_region_is_inclusive() {
+        let area = RegionArea::new_box(IVec3::new(4, 0, 4), IVec3::new(-4, 10, -4));
+        assert!(area.contains(IVec3::new(4, 10, -4)));
+        assert!(!area.contains(IVec3::new(5, 0, 0)));
+    }
+
+    #[test]
+    fn player_rules_decide_over_groups() {
+        let (builder, visitor) = (PlayerId::new(), PlayerId::new());
+        let mut region =
+            ProtectedRegion::new("spawn", RegionArea::new_box(IVec3::ZERO, IVec3::ONE));
+        region.set_rule(Subject::Group("builders".to_string()), Some(true));
+        region.set_rule(Subject::Player(visitor), Some(false));
+        let mut regions = Regions::default();
+        regions.add(region);
+        regions.set_member("builders", builder, true);
+        regions.set_member("builders", visitor, true);
+
+        assert!(regions.can_edit(builder, false, IVec3::ZERO));
+        assert!(!regions.can_edit(visitor, false, IVec3::ZERO));
+        assert!(!regions.can_edit(PlayerId::new(), true, IVec3::ZERO));
+        assert!(regions.can_edit(visitor, false, IVec3::splat(2)));
+
+        let loaded = Regions::from_json(&regions.to_json()).unwrap();
+        assert!(loaded.can_edit(builder, false, IVec3::ZERO));
+        assert!(!loaded.can_edit(visitor, false, IVec3::ZERO));
+    }
+}
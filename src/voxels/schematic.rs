@@ -0,0 +1,796 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use glam::{IVec3, Quat, UVec3, Vec3};
+use rand::Rng;
+use tracing::{error, warn};
+
+use crate::{
+    data_packs::find_resource,
+    persistence::{
+        atomic_file::write_atomic,
+        binary::{ByteReader, ByteWriter},
+        chunk_storage::same_voxel,
+        entity_persistence::{collect_chunk_entities, owning_chunk, PersistentId, SavedEntity},
+    },
+    random,
+};
+
+use super::{
+    placeholders::{get_placeholder_handler, Placeholder, PlaceholderContext},
+    voxel_data::VoxelData,
+    voxel_registry::{get_voxel_by_id, get_voxel_by_name, voxel_has_tag},
+    voxel_scene::{VoxelChunk, VoxelScene},
+    voxel_shapes::{voxel_shape, VoxelShape},
+};
+
+const SCHEMATIC_MAGIC: &str = "ASSEMBLAGE SCHEMATIC";
+// Version 2 added the placement masks, version 3 the placeholders
+const SCHEMATIC_FORMAT_VERSION: u64 = 3;
+// Foundations stop this far below a conforming schematic even when the terrain is further down
+pub const MAX_FOUNDATION_DEPTH: i32 = 16;
+
+// What placing a schematic voxel does to the voxel already in the scene
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlacementRule {
+    // The schematic's voxel is placed, air clears the scene
+    Replace,
+    // Only placed where the scene is air, so terrain pokes through
+    OnlyAir,
+    // The scene keeps its voxel
+    KeepTerrain,
+}
+
+impl PlacementRule {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "replace" => Some(PlacementRule::Replace),
+            "onlyair" => Some(PlacementRule::OnlyAir),
+            "keep" | "keepterrain" => Some(PlacementRule::KeepTerrain),
+            _ => None,
+        }
+    }
+
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(PlacementRule::Replace),
+            1 => Ok(PlacementRule::OnlyAir),
+            2 => Ok(PlacementRule::KeepTerrain),
+            value => bail!("Unknown placement rule {value}"),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            PlacementRule::Replace => 0,
+            PlacementRule::OnlyAir => 1,
+            PlacementRule::KeepTerrain => 2,
+        }
+    }
+}
+
+// How one voxel of a schematic is placed, so ruins can crumble and sink into the terrain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxelMask {
+    pub rule: PlacementRule,
+    // Percent chance the voxel is left out, rolled again for each placement
+    pub skip_chance: u8,
+}
+
+impl VoxelMask {
+    // Air keeps the terrain and everything else replaces it, which is how schematics without
+    // masks are placed
+    pub fn default_for(voxel: &VoxelData) -> Self {
+        Self {
+            rule: match voxel.id {
+                0 => PlacementRule::KeepTerrain,
+                _ => PlacementRule::Replace,
+            },
+            skip_chance: 0,
+        }
+    }
+
+    fn allows(&self, existing: Option<VoxelData>) -> bool {
+        match self.rule {
+            PlacementRule::Replace => true,
+            PlacementRule::OnlyAir => existing.map_or(false, |voxel| voxel.id == 0),
+            PlacementRule::KeepTerrain => false,
+        }
+    }
+}
+
+// How a schematic is oriented when placed, the mirror is applied before the rotation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct SchematicTransform {
+    // Quarter turns around the y axis
+    pub rotation: u8,
+    pub mirror_x: bool,
+}
+
+impl SchematicTransform {
+    // Any of the four quarter turns, never mirrored
+    pub fn random(rng: &mut impl Rng) -> Self {
+        Self {
+            rotation: rng.gen_range(0..4),
+            mirror_x: false,
+        }
+    }
+
+    pub fn rotated(&self) -> Self {
+        Self {
+            rotation: (self.rotation + 1) % 4,
+            ..*self
+        }
+    }
+
+    // Maps a position inside a schematic of the given size to its offset from the placement origin
+    pub fn apply(&self, position: IVec3, size: UVec3) -> IVec3 {
+        let size = size.as_ivec3();
+        let mut position = position;
+        if self.mirror_x {
+            position.x = size.x - 1 - position.x;
+        }
+        for _ in 0..self.rotation % 4 {
+            position = IVec3::new(position.z, position.y, -position.x);
+        }
+        position
+    }
+
+    pub fn apply_shape(&self, shape: VoxelShape) -> VoxelShape {
+        let mut shape = if self.mirror_x {
+            shape.mirrored_x()
+        } else {
+            shape
+        };
+        for _ in 0..self.rotation % 4 {
+            shape = shape.rotated_y();
+        }
+        shape
+    }
+
+    // Turns a direction such as a socket's facing, unlike a position it doesn't depend on the size
+    pub fn apply_direction(&self, direction: IVec3) -> IVec3 {
+        let mut direction = direction;
+        if self.mirror_x {
+            direction.x = -direction.x;
+        }
+        for _ in 0..self.rotation % 4 {
+            direction = IVec3::new(direction.z, direction.y, -direction.x);
+        }
+        direction
+    }
+
+    fn apply_vec(&self, position: Vec3, size: UVec3) -> Vec3 {
+        let mut position = position;
+        if self.mirror_x {
+            position.x = size.x as f32 - 1.0 - position.x;
+        }
+        for _ in 0..self.rotation % 4 {
+            position = Vec3::new(position.z, position.y, -position.x);
+        }
+        position
+    }
+
+    fn apply_rotation(&self, rotation: Quat) -> Quat {
+        Quat::from_rotation_y(std::f32::consts::FRAC_PI_2 * (self.rotation % 4) as f32) * rotation
+    }
+}
+
+// A copied box of voxels and the entities inside it, used for copy and paste and by structure generation
+#[derive(Clone, Debug)]
+pub struct Schematic {
+    pub size: UVec3,
+    // Ordered x, then y, then z
+    voxels: Vec<VoxelData>,
+    // One for each voxel, in the same order
+    masks: Vec<VoxelMask>,
+    // Positions are relative to the minimum corner
+    pub entities: Vec<SavedEntity>,
+    pub placeholders: Vec<Placeholder>,
+}
+
+impl Schematic {
+    fn index(&self, position: UVec3) -> usize {
+        ((position.x * self.size.y + position.y) * self.size.z + position.z) as usize
+    }
+
+    pub fn voxel_at(&self, position: UVec3) -> VoxelData {
+        self.voxels[self.index(position)]
+    }
+
+    pub fn mask_at(&self, position: UVec3) -> VoxelMask {
+        self.masks[self.index(position)]
+    }
+
+    pub fn set_mask(&mut self, position: UVec3, mask: VoxelMask) {
+        let index = self.index(position);
+        self.masks[index] = mask;
+    }
+
+    // Sets the mask of every voxel with the id, None for all of them. Returns how many changed
+    pub fn set_masks_for(&mut self, id: Option<u16>, mask: VoxelMask) -> usize {
+        let mut changed = 0;
+        for (voxel, existing) in self.voxels.iter().zip(self.masks.iter_mut()) {
+            if id.map_or(true, |id| voxel.id == id) {
+                *existing = mask;
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    // Air everywhere, with the masks air gets by default
+    pub fn empty(size: UVec3) -> Self {
+        let air = VoxelData {
+            shape: VoxelShape::default(),
+            state: 0,
+            id: 0,
+        };
+        let voxels = vec![air; (size.x * size.y * size.z) as usize];
+        Self {
+            size,
+            masks: voxels.iter().map(VoxelMask::default_for).collect(),
+            voxels,
+            entities: Vec::new(),
+            placeholders: Vec::new(),
+        }
+    }
+
+    pub fn set_voxel(&mut self, position: UVec3, voxel: VoxelData) {
+        let index = self.index(position);
+        self.voxels[index] = voxel;
+        self.masks[index] = VoxelMask::default_for(&voxel);
+    }
+
+    // The corners of the box the schematic covers when placed, both inclusive
+    pub fn bounds(&self, origin: IVec3, transform: SchematicTransform) -> (IVec3, IVec3) {
+        let corners = [IVec3::ZERO, self.size.as_ivec3() - IVec3::ONE]
+            .map(|corner| origin + transform.apply(corner, self.size));
+        (corners[0].min(corners[1]), corners[0].max(corners[1]))
+    }
+
+    // Copies the voxels between the two corners, both inclusive
+    pub fn capture(scene: &VoxelScene, a: IVec3, b: IVec3) -> Self {
+        let min = a.min(b);
+        let size = (a.max(b) - min + IVec3::ONE).as_uvec3();
+        let mut voxels = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let position = min + UVec3::new(x, y, z).as_ivec3();
+                    voxels.push(scene.voxel_at(&position).unwrap_or(VoxelData {
+                        shape: VoxelShape::default(),
+                        state: 0,
+                        id: 0,
+                    }));
+                }
+            }
+        }
+        let masks = voxels.iter().map(VoxelMask::default_for).collect();
+        Self {
+            size,
+            voxels,
+            masks,
+            entities: Vec::new(),
+            placeholders: Vec::new(),
+        }
+    }
+
+    // Adds the persistent entities inside the box that starts at min
+    pub fn capture_entities(&mut self, world: &legion::World, min: IVec3) {
+        let max = min + self.size.as_ivec3() - IVec3::ONE;
+        let (min_chunk, max_chunk) = (owning_chunk(min.as_vec3()), owning_chunk(max.as_vec3()));
+        let mut chunks = Vec::new();
+        for x in min_chunk.x..=max_chunk.x {
+            for y in min_chunk.y..=max_chunk.y {
+                for z in min_chunk.z..=max_chunk.z {
+                    chunks.push(IVec3::new(x, y, z));
+                }
+            }
+        }
+        for chunk in chunks {
+            for mut entity in collect_chunk_entities(world, chunk) {
+                let cell = entity.position.round().as_ivec3();
+                if cell.cmplt(min).any() || cell.cmpgt(max).any() {
+                    continue;
+                }
+                entity.position -= min.as_vec3();
+                self.entities.push(entity);
+            }
+        }
+    }
+
+    // The voxels that aren't skipped and where they go. The skip chances are rolled from the world
+    // seed and the origin in the same order every time, so placing at the same spot gives the same
+    // result and placing a schematic a chunk at a time agrees with placing it at once
+    fn placements(
+        &self,
+        origin: IVec3,
+        transform: SchematicTransform,
+    ) -> impl Iterator<Item = (IVec3, VoxelData, VoxelMask)> + '_ {
+        let mut rng = random::overworld()
+            .position(origin)
+            .derive_name("schematic")
+            .rng();
+        let size = self.size;
+        (0..size.x)
+            .flat_map(move |x| {
+                (0..size.y).flat_map(move |y| (0..size.z).map(move |z| UVec3::new(x, y, z)))
+            })
+            .filter_map(move |local| {
+                let (voxel, mask) = (self.voxel_at(local), self.mask_at(local));
+                if mask.rule == PlacementRule::KeepTerrain {
+                    return None;
+                }
+                if mask.skip_chance > 0 && rng.gen_range(0..100) < mask.skip_chance {
+                    return None;
+                }
+                let position = origin + transform.apply(local.as_ivec3(), size);
+                let voxel = VoxelData {
+                    shape: transform.apply_shape(voxel.shape),
+                    ..voxel
+                };
+                Some((position, voxel, mask))
+            })
+    }
+
+    // Each voxel is placed as its mask says, returns the number of voxels placed
+    pub fn place(&self, scene: &VoxelScene, origin: IVec3, transform: SchematicTransform) -> usize {
+        let placements = self
+            .placements(origin, transform)
+            .filter(|(position, _, mask)| mask.allows(scene.voxel_at(position)))
+            .map(|(position, voxel, _)| (position, voxel));
+        scene.set_voxels(placements).len()
+    }
+
+    // Places the part of the schematic inside the chunk, for structures generated with the terrain
+    pub fn place_in_chunk(
+        &self,
+        chunk: &mut VoxelChunk,
+        origin: IVec3,
+        transform: SchematicTransform,
+    ) -> usize {
+        let mut placed = 0;
+        for (position, voxel, mask) in self.placements(origin, transform) {
+            let existing = match chunk.voxel_scenespace_at(&position) {
+                Some(existing) => *existing,
+                None => continue,
+            };
+            if mask.allows(Some(existing)) && chunk.set_voxel_scenespace(&position, voxel) {
+                placed += 1;
+            }
+        }
+        placed
+    }
+
+    // Places the schematic and extends each column of its bottom layer down to the terrain, so it
+    // doesn't float where it sits on a slope. Returns the number of voxels placed, the foundation
+    // included
+    pub fn place_conforming(
+        &self,
+        scene: &VoxelScene,
+        origin: IVec3,
+        transform: SchematicTransform,
+    ) -> usize {
+        let mut placed = self.place(scene, origin, transform);
+        for x in 0..self.size.x {
+            for z in 0..self.size.z {
+                let local = UVec3::new(x, 0, z);
+                let (voxel, mask) = (self.voxel_at(local), self.mask_at(local));
+                if voxel.id == 0 || mask.rule == PlacementRule::KeepTerrain {
+                    continue;
+                }
+                // Full blocks, a column of stairs wouldn't hold anything up
+                let foundation = VoxelData {
+                    shape: voxel_shape::CUBE,
+                    state: 0,
+                    id: voxel.id,
+                };
+                let bottom = origin + transform.apply(local.as_ivec3(), self.size);
+                for depth in 1..=MAX_FOUNDATION_DEPTH {
+                    let position = bottom - IVec3::Y * depth;
+                    // Unloaded voxels are None, the foundation stops there too
+                    match scene.voxel_at(&position) {
+                        Some(existing) if !is_ground(existing) => {}
+                        _ => break,
+                    }
+                    if scene.set_voxel(&position, foundation).is_some() {
+                        placed += 1;
+                    }
+                }
+            }
+        }
+        placed
+    }
+
+    // Entities get new ids so pasting the same schematic twice doesn't create duplicates
+    pub fn place_entities(
+        &self,
+        world: &mut legion::World,
+        origin: IVec3,
+        transform: SchematicTransform,
+    ) {
+        for entity in &self.entities {
+            let mut entity = entity.clone();
+            entity.id = PersistentId::new().0;
+            entity.position = origin.as_vec3() + transform.apply_vec(entity.position, self.size);
+            entity.rotation = entity.rotation.map(|r| transform.apply_rotation(r));
+            entity.spawn(world);
+        }
+    }
+
+    // Replaces the placeholders at the position, None removes them. Returns how many were removed
+    pub fn set_placeholder(&mut self, position: UVec3, placeholder: Option<(&str, &str)>) -> usize {
+        let before = self.placeholders.len();
+        self.placeholders.retain(|p| p.position != position);
+        let removed = before - self.placeholders.len();
+        if let Some((kind, data)) = placeholder {
+            self.placeholders.push(Placeholder {
+                position,
+                kind: kind.to_string(),
+                data: data.to_string(),
+            });
+        }
+        removed
+    }
+
+    // Runs the handler of each placeholder, after the voxels are placed so loot and jigsaw
+    // connections land in the finished structure. Returns how many were resolved, failures and
+    // unknown kinds are logged and skipped
+    pub fn resolve_placeholders(
+        &self,
+        context: &mut PlaceholderContext,
+        origin: IVec3,
+        transform: SchematicTransform,
+    ) -> usize {
+        let mut resolved = 0;
+        for placeholder in &self.placeholders {
+            let position = origin + transform.apply(placeholder.position.as_ivec3(), self.size);
+            let handler = match get_placeholder_handler(&placeholder.kind) {
+                Some(handler) => handler,
+                None => {
+                    warn!("There is no placeholder handler for {}", placeholder.kind);
+                    continue;
+                }
+            };
+            let mut rng = random::overworld()
+                .position(position)
+                .derive_name(&placeholder.kind)
+                .rng();
+            match handler.resolve(context, position, transform, &placeholder.data, &mut rng) {
+                Ok(()) => resolved += 1,
+                Err(e) => error!(
+                    "Failed to resolve the {} placeholder at {position}: {e}",
+                    placeholder.kind
+                ),
+            }
+        }
+        resolved
+    }
+
+    // Voxel ids are stored by name so schematics survive changes to the voxel registry
+    pub fn write(&self, writer: &mut ByteWriter) {
+        writer.write_string(SCHEMATIC_MAGIC);
+        writer.write_leb128(SCHEMATIC_FORMAT_VERSION);
+        writer.write_leb128(self.size.x as u64);
+        writer.write_leb128(self.size.y as u64);
+        writer.write_leb128(self.size.z as u64);
+
+        let mut palette: HashMap<u16, u64> = HashMap::new();
+        let mut names = Vec::new();
+        for voxel in &self.voxels {
+            let id = voxel.id;
+            palette.entry(id).or_insert_with(|| {
+                names.push(get_voxel_by_id(id).map_or("Empty".to_string(), |p| p.name.clone()));
+                names.len() as u64 - 1
+            });
+        }
+        writer.write_leb128(names.len() as u64);
+        for name in &names {
+            writer.write_string(name);
+        }
+
+        let mut runs: Vec<(u64, VoxelData)> = Vec::new();
+        for voxel in &self.voxels {
+            match runs.last_mut() {
+                Some((count, last)) if same_voxel(last, voxel) => *count += 1,
+                _ => runs.push((1, *voxel)),
+            }
+        }
+        writer.write_leb128(runs.len() as u64);
+        for (count, voxel) in runs {
+            writer.write_leb128(count);
+            writer.write_u8(voxel.shape.data);
+            writer.write_u8(voxel.state);
+            writer.write_leb128(palette[&{ voxel.id }]);
+        }
+
+        let mut mask_runs: Vec<(u64, VoxelMask)> = Vec::new();
+        for mask in &self.masks {
+            match mask_runs.last_mut() {
+                Some((count, last)) if last == mask => *count += 1,
+                _ => mask_runs.push((1, *mask)),
+            }
+        }
+        writer.write_leb128(mask_runs.len() as u64);
+        for (count, mask) in mask_runs {
+            writer.write_leb128(count);
+            writer.write_u8(mask.rule.to_u8());
+            writer.write_u8(mask.skip_chance);
+        }
+
+        writer.write_leb128(self.entities.len() as u64);
+        for entity in &self.entities {
+            entity.write(writer);
+        }
+
+        writer.write_leb128(self.placeholders.len() as u64);
+        for placeholder in &self.placeholders {
+            writer.write_leb128(placeholder.position.x as u64);
+            writer.write_leb128(placeholder.position.y as u64);
+            writer.write_leb128(placeholder.position.z as u64);
+            writer.write_string(&placeholder.kind);
+            writer.write_string(&placeholder.data);
+        }
+    }
+
+    pub fn read(reader: &mut ByteReader) -> Result<Self> {
+        if reader.read_string()? != SCHEMATIC_MAGIC {
+            bail!("File is not a schematic");
+        }
+        let version = reader.read_leb128()?;
+        if version > SCHEMATIC_FORMAT_VERSION {
+            bail!(
+                "Schematic format version {version} is newer than the supported version {SCHEMATIC_FORMAT_VERSION}"
+            );
+        }
+        let size = UVec3::new(
+            reader.read_leb128()? as u32,
+            reader.read_leb128()? as u32,
+            reader.read_leb128()? as u32,
+        );
+        let volume = (size.x as usize) * (size.y as usize) * (size.z as usize);
+
+        let palette_length = reader.read_leb128()?;
+        let mut ids = Vec::new();
+        for _ in 0..palette_length {
+            let name = reader.read_string()?;
+            ids.push(match get_voxel_by_name(name.clone()) {
+                Some(profile) => profile.id,
+                None => {
+                    warn!("Schematic uses unknown voxel \"{name}\", replacing it with air");
+                    0
+                }
+            });
+        }
+
+        let run_count = reader.read_leb128()?;
+        let mut voxels = Vec::with_capacity(volume);
+        for _ in 0..run_count {
+            let count = reader.read_leb128()? as usize;
+            let shape = VoxelShape {
+                data: reader.read_u8()?,
+            };
+            let state = reader.read_u8()?;
+            let palette_index = reader.read_leb128()? as usize;
+            let id = *ids
+                .get(palette_index)
+                .ok_or_else(|| anyhow!("Palette index {palette_index} is out of range"))?;
+            if voxels.len() + count > volume {
+                bail!("Schematic contains more than {volume} voxels");
+            }
+            voxels.extend(std::iter::repeat(VoxelData { shape, state, id }).take(count));
+        }
+        if voxels.len() != volume {
+            bail!(
+                "Schematic only contains {} of {volume} voxels",
+                voxels.len()
+            );
+        }
+
+        let masks = match version {
+            1 => voxels.iter().map(VoxelMask::default_for).collect(),
+            _ => read_masks(reader, volume)?,
+        };
+
+        let entity_count = reader.read_leb128()?;
+        let mut entities = Vec::new();
+        for _ in 0..entity_count {
+            entities.push(SavedEntity::read(reader)?);
+        }
+
+        let mut placeholders = Vec::new();
+        if version >= 3 {
+            for _ in 0..reader.read_leb128()? {
+                let position = UVec3::new(
+                    reader.read_leb128()? as u32,
+                    reader.read_leb128()? as u32,
+                    reader.read_leb128()? as u32,
+                );
+                if position.cmpge(size).any() {
+                    bail!("Placeholder at {position} is outside the schematic");
+                }
+                placeholders.push(Placeholder {
+                    position,
+                    kind: reader.read_string()?,
+                    data: reader.read_string()?,
+                });
+            }
+        }
+        Ok(Self {
+            size,
+            voxels,
+            masks,
+            entities,
+            placeholders,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = ByteWriter::new();
+        self.write(&mut writer);
+        write_atomic(path, &writer.bytes)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        Self::read(&mut ByteReader::new(&bytes))
+    }
+
+    // Loads structures/name.schematic from the resources, data packs can add or replace structures
+    pub fn load_structure(name: &str) -> Result<Self> {
+        let path = find_resource(&format!("structures/{name}.schematic"))
+            .ok_or_else(|| anyhow!("There is no structure named {name}"))?;
+        Self::load(&path)
+    }
+}
+
+// What a foundation rests on, fluids are filled like air
+fn is_ground(voxel: VoxelData) -> bool {
+    voxel.id != 0 && !voxel_has_tag(voxel.id, "fluid")
+}
+
+fn read_masks(reader: &mut ByteReader, volume: usize) -> Result<Vec<VoxelMask>> {
+    let run_count = reader.read_leb128()?;
+    let mut masks = Vec::with_capacity(volume);
+    for _ in 0..run_count {
+        let count = reader.read_leb128()? as usize;
+        let rule = PlacementRule::from_u8(reader.read_u8()?)?;
+        let skip_chance = reader.read_u8()?.min(100);
+        if masks.len() + count > volume {
+            bail!("Schematic contains more than {volume} masks");
+        }
+        masks.extend(std::iter::repeat(VoxelMask { rule, skip_chance }).take(count));
+    }
+    if masks.len() != volume {
+        bail!("Schematic only contains {} of {volume} masks", masks.len());
+    }
+    Ok(masks)
+}
+
+#[cfg(test)]
+mod schematic_tests {
+    use super::*;
+
+    #[test]
+    fn masks_and_placeholders_survive_a_round_trip() {
+        let air = VoxelData {
+            shape: VoxelShape::default(),
+            state: 0,
+            id: 0,
+        };
+        let voxels = vec![air; 8];
+        let mut schematic = Schematic {
+            size: UVec3::new(2, 2, 2),
+            masks: voxels.iter().map(VoxelMask::default_for).collect(),
+            voxels,
+            entities: Vec::new(),
+            placeholders: Vec::new(),
+        };
+        schematic.set_placeholder(UVec3::new(0, 1, 0), Some(("loot", "torch 1-3")));
+        let crumbling = VoxelMask {
+            rule: PlacementRule::OnlyAir,
+            skip_chance: 30,
+        };
+        schematic.set_mask(UVec3::new(1, 0, 1), crumbling);
+
+        let mut writer = ByteWriter::new();
+        schematic.write(&mut writer);
+        let read = Schematic::read(&mut ByteReader::new(&writer.bytes)).unwrap();
+        assert_eq!(read.masks, schematic.masks);
+        assert_eq!(read.mask_at(UVec3::new(1, 0, 1)), crumbling);
+        assert_eq!(read.mask_at(UVec3::ZERO).rule, PlacementRule::KeepTerrain);
+        assert_eq!(read.placeholders, schematic.placeholders);
+    }
+
+    #[test]
+    fn transforms_turn_positions_clockwise() {
+        let size = UVec3::new(3, 1, 2);
+        let turned = SchematicTransform {
+            rotation: 1,
+            mirror_x: false,
+        };
+        assert_eq!(
+            turned.apply(IVec3::new(1, 0, 0), size),
+            IVec3::new(0, 0, -1)
+        );
+        assert_eq!(turned.apply(IVec3::new(0, 0, 1), size), IVec3::new(1, 0, 0));
+        let full_turn = SchematicTransform {
+            rotation: 4,
+            mirror_x: false,
+        };
+        assert_eq!(
+            full_turn.apply(IVec3::new(2, 0, 1), size),
+            IVec3::new(2, 0, 1)
+        );
+        // The mirror is applied first, inside the schematic's own box
+        let mirrored = SchematicTransform {
+            rotation: 1,
+            mirror_x: true,
+        };
+        assert_eq!(
+            mirrored.apply(IVec3::new(0, 0, 1), size),
+            IVec3::new(1, 0, -2)
+        );
+    }
+
+    #[test]
+    fn captured_voxels_are_placed_turned() {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        let min = IVec3::new(1, 1, 1);
+        for (state, offset) in [IVec3::ZERO, IVec3::X, IVec3::Z * 2]
+            .into_iter()
+            .enumerate()
+        {
+            let voxel = VoxelData {
+                shape: voxel_shape::CORNER_STAIR,
+                state: state as u8 + 1,
+                id: 1,
+            };
+            scene.set_voxel(&(min + offset), voxel);
+        }
+        let captured = Schematic::capture(&scene, min, min + IVec3::new(1, 0, 2));
+        assert_eq!(captured.size, UVec3::new(2, 1, 3));
+
+        let mut writer = ByteWriter::new();
+        captured.write(&mut writer);
+        let schematic = Schematic::read(&mut ByteReader::new(&writer.bytes)).unwrap();
+        let origin = IVec3::new(8, 1, 8);
+        let transform = SchematicTransform {
+            rotation: 1,
+            mirror_x: false,
+        };
+        assert_eq!(schematic.place(&scene, origin, transform), 3);
+
+        // (x, z) goes to (z, -x)
+        for (state, position) in [
+            (1, origin),
+            (2, origin - IVec3::Z),
+            (3, origin + IVec3::X * 2),
+        ] {
+            let placed = scene.voxel_at(&position).unwrap();
+            assert_eq!({ placed.state }, state);
+            assert_eq!({ placed.shape }, voxel_shape::CORNER_STAIR.rotated_y());
+        }
+        let (low, high) = schematic.bounds(origin, transform);
+        assert_eq!((low, high), (origin - IVec3::Z, origin + IVec3::X * 2));
+    }
+
+    #[test]
+    fn random_transforms_use_every_rotation() {
+        let mut rng = random::overworld().derive_name("test").rng();
+        let mut seen = [false; 4];
+        for _ in 0..64 {
+            let transform = SchematicTransform::random(&mut rng);
+            assert!(!transform.mirror_x);
+            seen[transform.rotation as usize] = true;
+        }
+        assert_eq!(seen, [true; 4]);
+    }
+}
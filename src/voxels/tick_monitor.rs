@@ -0,0 +1,245 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+use tracing::{info, warn};
+
+// Ticks the averages are taken over, five seconds at full speed
+const WINDOW: usize = 100;
+// Ticks between changes of the overload level, so a single slow tick doesn't change it
+const LEVEL_HOLD: u64 = 40;
+// The share of the tick length the average tick may take before the simulation sheds work, and the
+// share it has to fall under before the work comes back
+const OVERLOADED: f64 = 0.9;
+const RECOVERED: f64 = 0.5;
+// The simulation catches up on missed ticks by running them back to back, ticks further behind
+// than this are dropped so an overloaded server doesn't spiral
+pub const MAX_CATCH_UP_TICKS: u32 = 20;
+
+lazy_static! {
+    static ref CURRENT: RwLock<TickStats> = RwLock::new(TickStats::default());
+}
+
+// The work an overloaded simulation leaves out, each level includes the ones before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OverloadLevel {
+    Normal,
+    // Crops, snow and other random ticks wait until the server keeps up again
+    SkipRandomTicks,
+    // Fewer neighbour updates per tick, so flowing fluids and signals spread more slowly
+    ThrottleNeighborUpdates,
+}
+
+impl Default for OverloadLevel {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl OverloadLevel {
+    fn heavier(self) -> Self {
+        match self {
+            Self::Normal => Self::SkipRandomTicks,
+            _ => Self::ThrottleNeighborUpdates,
+        }
+    }
+
+    fn lighter(self) -> Self {
+        match self {
+            Self::ThrottleNeighborUpdates => Self::SkipRandomTicks,
+            _ => Self::Normal,
+        }
+    }
+}
+
+// How the simulation has kept up over the last few seconds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TickStats {
+    pub ticks_per_second: f64,
+    pub mean_tick: Duration,
+    pub longest_tick: Duration,
+    pub overload: OverloadLevel,
+    // Ticks dropped since the server started because the simulation was too far behind
+    pub skipped_ticks: u64,
+}
+
+impl fmt::Display for TickStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} TPS, {:.1?} per tick on average and {:.1?} at most, {} ticks skipped",
+            self.ticks_per_second, self.mean_tick, self.longest_tick, self.skipped_ticks
+        )?;
+        match self.overload {
+            OverloadLevel::Normal => Ok(()),
+            OverloadLevel::SkipRandomTicks => write!(f, ", random ticks paused"),
+            OverloadLevel::ThrottleNeighborUpdates => {
+                write!(f, ", random ticks paused and neighbour updates throttled")
+            }
+        }
+    }
+}
+
+// The latest stats of the running simulation
+pub fn tick_stats() -> TickStats {
+    *CURRENT.read()
+}
+
+// Times the simulation's ticks and decides what an overloaded simulation leaves out
+pub struct TickMonitor {
+    tick_length: Duration,
+    // When the recent ticks started and how long they took, oldest first
+    ticks: VecDeque<(Instant, Duration)>,
+    ticks_measured: u64,
+    level: OverloadLevel,
+    level_changed: u64,
+    skipped_ticks: u64,
+}
+
+impl TickMonitor {
+    pub fn new(ticks_per_second: u32) -> Self {
+        Self {
+            tick_length: Duration::from_secs_f64(1.0 / ticks_per_second as f64),
+            ticks: VecDeque::with_capacity(WINDOW),
+            ticks_measured: 0,
+            level: OverloadLevel::Normal,
+            level_changed: 0,
+            skipped_ticks: 0,
+        }
+    }
+
+    pub fn tick_length(&self) -> Duration {
+        self.tick_length
+    }
+
+    pub fn overload(&self) -> OverloadLevel {
+        self.level
+    }
+
+    pub fn record_skipped(&mut self, ticks: u64) {
+        self.skipped_ticks += ticks;
+        warn!("The simulation is {ticks} ticks behind, skipping them");
+    }
+
+    // Called after every tick with when it started and how long it took
+    pub fn record(&mut self, start: Instant, duration: Duration) {
+        if self.ticks.len() == WINDOW {
+            self.ticks.pop_front();
+        }
+        self.ticks.push_back((start, duration));
+        self.ticks_measured += 1;
+        self.update_level();
+        *CURRENT.write() = self.stats();
+    }
+
+    fn mean_tick(&self) -> Duration {
+        match self.ticks.len() {
+            0 => Duration::ZERO,
+            count => {
+                self.ticks
+                    .iter()
+                    .map(|(_, duration)| *duration)
+                    .sum::<Duration>()
+                    / count as u32
+            }
+        }
+    }
+
+    fn update_level(&mut self) {
+        if self.ticks_measured - self.level_changed < LEVEL_HOLD {
+            return;
+        }
+        let load = self.mean_tick().as_secs_f64() / self.tick_length.as_secs_f64();
+        let level = match load {
+            load if load > OVERLOADED => self.level.heavier(),
+            load if load < RECOVERED => self.level.lighter(),
+            _ => self.level,
+        };
+        if level != self.level {
+            match level > self.level {
+                true => warn!(
+                    "The simulation is overloaded at {:.0}% of each tick, {level:?}",
+                    load * 100.0
+                ),
+                false => info!("The simulation is catching up, {level:?}"),
+            }
+            self.level = level;
+            self.level_changed = self.ticks_measured;
+        }
+    }
+
+    pub fn stats(&self) -> TickStats {
+        let ticks_per_second = match (self.ticks.front(), self.ticks.back()) {
+            (Some((first, _)), Some((last, _))) if self.ticks.len() > 1 => {
+                (self.ticks.len() - 1) as f64 / last.duration_since(*first).as_secs_f64().max(1e-6)
+            }
+            _ => 0.0,
+        };
+        TickStats {
+            ticks_per_second,
+            mean_tick: self.mean_tick(),
+            longest_tick: self
+                .ticks
+                .iter()
+                .map(|(_, duration)| *duration)
+                .max()
+                .unwrap_or_default(),
+            overload: self.level,
+            skipped_ticks: self.skipped_ticks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tick_monitor_tests {
+    use super::*;
+
+    fn run_ticks(
+        monitor: &mut TickMonitor,
+        start: Instant,
+        count: u32,
+        duration: Duration,
+    ) -> Instant {
+        let mut time = start;
+        for _ in 0..count {
+            monitor.record(time, duration);
+            time += monitor.tick_length().max(duration);
+        }
+        time
+    }
+
+    #[test]
+    fn tps_follows_the_tick_starts() {
+        let mut monitor = TickMonitor::new(20);
+        run_ticks(&mut monitor, Instant::now(), 10, Duration::from_millis(5));
+        let stats = monitor.stats();
+        assert!((stats.ticks_per_second - 20.0).abs() < 0.01);
+        assert_eq!(stats.mean_tick, Duration::from_millis(5));
+        assert_eq!(stats.overload, OverloadLevel::Normal);
+    }
+
+    #[test]
+    fn overload_sheds_random_ticks_first_and_recovers() {
+        let mut monitor = TickMonitor::new(20);
+        let slow = Duration::from_millis(80);
+        let time = run_ticks(&mut monitor, Instant::now(), LEVEL_HOLD as u32, slow);
+        assert_eq!(monitor.overload(), OverloadLevel::SkipRandomTicks);
+        assert!(monitor.stats().ticks_per_second < 20.0);
+        let time = run_ticks(&mut monitor, time, LEVEL_HOLD as u32, slow);
+        assert_eq!(monitor.overload(), OverloadLevel::ThrottleNeighborUpdates);
+
+        // Only lightens once the slow ticks have left the window
+        let time = run_ticks(&mut monitor, time, WINDOW as u32, Duration::from_millis(1));
+        assert_eq!(monitor.overload(), OverloadLevel::SkipRandomTicks);
+        run_ticks(
+            &mut monitor,
+            time,
+            LEVEL_HOLD as u32,
+            Duration::from_millis(1),
+        );
+        assert_eq!(monitor.overload(), OverloadLevel::Normal);
+    }
+}
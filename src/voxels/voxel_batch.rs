@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use glam::IVec3;
+
+use super::{edit_history::VoxelChange, voxel_data::VoxelData, voxel_scene::VoxelScene};
+
+// Voxel edits staged to be set together with VoxelScene::set_voxels, so a script or command
+// changing thousands of voxels remeshes each chunk once instead of once per voxel
+#[derive(Default)]
+pub struct VoxelBatch {
+    voxels: Vec<(IVec3, VoxelData)>,
+    // Index of the latest staged voxel at each position
+    staged: HashMap<IVec3, usize>,
+}
+
+impl VoxelBatch {
+    // A voxel staged twice is set to the later one
+    pub fn set_voxel(&mut self, position: IVec3, voxel: VoxelData) {
+        match self.staged.get(&position) {
+            Some(&index) => self.voxels[index].1 = voxel,
+            None => {
+                self.staged.insert(position, self.voxels.len());
+                self.voxels.push((position, voxel));
+            }
+        }
+    }
+
+    // The staged voxel, or the scene's until something is staged there
+    pub fn voxel_at(&self, scene: &VoxelScene, position: &IVec3) -> Option<VoxelData> {
+        match self.staged.get(position) {
+            Some(&index) => Some(self.voxels[index].1),
+            None => scene.voxel_at(position),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    // Sets the staged voxels, returns the changes made in loaded chunks
+    pub fn commit(self, scene: &VoxelScene) -> Vec<VoxelChange> {
+        scene.set_voxels(self.voxels)
+    }
+}
+
+#[cfg(test)]
+mod voxel_batch_tests {
+    use super::*;
+    use crate::voxels::{voxel_scene::VoxelChunk, voxel_shapes::voxel_shape};
+
+    fn voxel(id: u16) -> VoxelData {
+        VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id,
+        }
+    }
+
+    #[test]
+    fn later_edits_replace_earlier_ones() {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        let position = IVec3::new(1, 2, 3);
+        let staged = scene.batch(|batch| {
+            batch.set_voxel(position, voxel(1));
+            batch.set_voxel(IVec3::ZERO, voxel(1));
+            batch.set_voxel(position, voxel(0));
+            assert_eq!({ batch.voxel_at(&scene, &position).unwrap().id }, 0);
+            // Outside the loaded chunk
+            batch.set_voxel(IVec3::new(-1, 0, 0), voxel(1));
+            batch.len()
+        });
+        assert_eq!(staged, 3);
+        assert_eq!({ scene.voxel_at(&IVec3::ZERO).unwrap().id }, 1);
+        assert_eq!({ scene.voxel_at(&position).unwrap().id }, 0);
+
+        let mut batch = VoxelBatch::default();
+        batch.set_voxel(position, voxel(1));
+        batch.set_voxel(IVec3::new(-1, 0, 0), voxel(1));
+        let changes = batch.commit(&scene);
+        assert_eq!(changes.len(), 1);
+        assert_eq!({ changes[0].before.id }, 0);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{collections::HashMap, sync::Arc};
+
+use glam::IVec3;
+use parking_lot::RwLock;
+use rand::Rng;
+use tracing::warn;
+
+use crate::{environment, random};
+
+use super::{
+    voxel_data::VoxelData,
+    voxel_registry::get_voxel_by_name,
+    voxel_scene::VoxelScene,
+    voxel_shapes::{voxel_directions, VoxelDirection},
+};
+
+// Behaviors give voxels logic that runs during the simulation tick
+// A voxel profile opts into a behavior with the "behavior" field in its json
+pub trait VoxelBehavior: Sync + Send {
+    fn on_random_tick(&self, _scene: &VoxelScene, _position: IVec3, _voxel: VoxelData) {}
+    fn on_scheduled_tick(&self, _scene: &VoxelScene, _position: IVec3, _voxel: VoxelData) {}
+    fn on_neighbor_changed(
+        &self,
+        _scene: &VoxelScene,
+        _position: IVec3,
+        _voxel: VoxelData,
+        _neighbor_position: IVec3,
+    ) {
+    }
+    // Called on signal consumers when the signal around them may have changed
+    fn on_signal_changed(
+        &self,
+        _scene: &VoxelScene,
+        _position: IVec3,
+        _voxel: VoxelData,
+        _power: u8,
+    ) {
+    }
+}
+
+lazy_static! {
+    static ref BEHAVIORS: RwLock<HashMap<String, Arc<dyn VoxelBehavior>>> =
+        RwLock::new(load_behaviors());
+}
+
+fn load_behaviors() -> HashMap<String, Arc<dyn VoxelBehavior>> {
+    let mut map: HashMap<String, Arc<dyn VoxelBehavior>> = HashMap::new();
+    map.insert("grass".to_string(), Arc::new(GrassBehavior {}));
+    map.insert(
+        "crop".to_string(),
+        Arc::new(CropBehavior {
+            max_stage: 7,
+            growth_chance: 0.3,
+        }),
+    );
+    map.insert("melt".to_string(), Arc::new(MeltBehavior { chance: 0.25 }));
+    map.insert("attached".to_string(), Arc::new(AttachedBehavior {}));
+    map.insert("powered".to_string(), Arc::new(PoweredBehavior {}));
+    map
+}
+
+pub fn get_behavior_by_name(name: &str) -> Option<Arc<dyn VoxelBehavior>> {
+    BEHAVIORS.read().get(name).map(|b| Arc::clone(b))
+}
+
+// Adds a behavior voxels can name in their profile, plugins register theirs before the voxel registry loads.
+// Profiles keep the behavior they loaded with, so replacing one only affects voxels loaded afterwards
+pub fn register_behavior(name: &str, behavior: Arc<dyn VoxelBehavior>) {
+    if BEHAVIORS
+        .write()
+        .insert(name.to_string(), behavior)
+        .is_some()
+    {
+        warn!("Behavior {name} was replaced");
+    }
+}
+
+fn is_covered(scene: &VoxelScene, position: IVec3) -> bool {
+    scene
+        .voxel_at(&(position + voxel_directions::UP.as_vec()))
+        .map_or(false, |above| above.id != 0)
+}
+
+// Spreads onto neighbouring dirt that has open air above it, turns back into dirt when covered
+pub struct GrassBehavior {}
+
+impl VoxelBehavior for GrassBehavior {
+    fn on_random_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        let dirt = match get_voxel_by_name("dirt".to_string()) {
+            Some(dirt) => dirt.id,
+            None => return,
+        };
+
+        if is_covered(scene, position) {
+            scene.set_voxel(&position, VoxelData { id: dirt, ..voxel });
+            return;
+        }
+
+        let mut rng = random::voxel_rng(position, "grass");
+        let target = position
+            + IVec3::new(
+                rng.gen_range(-1..=1),
+                rng.gen_range(-1..=1),
+                rng.gen_range(-1..=1),
+            );
+        let target_voxel = match scene.voxel_at(&target) {
+            Some(v) => v,
+            None => return,
+        };
+        if target_voxel.id == dirt && !is_covered(scene, target) {
+            scene.set_voxel(
+                &target,
+                VoxelData {
+                    id: voxel.id,
+                    ..target_voxel
+                },
+            );
+        }
+    }
+}
+
+// Uses the voxel state as the growth stage, grows faster while it rains
+pub struct CropBehavior {
+    pub max_stage: u8,
+    pub growth_chance: f32,
+}
+
+impl VoxelBehavior for CropBehavior {
+    fn on_random_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        if voxel.state >= self.max_stage {
+            return;
+        }
+        let chance = self.growth_chance * environment::current().weather.current.growth_boost();
+        if random::voxel_rng(position, "crop").gen::<f32>() >= chance {
+            return;
+        }
+        scene.set_voxel(
+            &position,
+            VoxelData {
+                state: voxel.state + 1,
+                ..voxel
+            },
+        );
+    }
+}
+
+// Removes the voxel with the given chance per random tick while it is above freezing, intended for ice and snow
+pub struct MeltBehavior {
+    pub chance: f32,
+}
+
+impl VoxelBehavior for MeltBehavior {
+    fn on_random_tick(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData) {
+        if environment::current().temperature() <= 0.0 {
+            return;
+        }
+        if random::voxel_rng(position, "melt").gen::<f32>() < self.chance {
+            scene.set_voxel(&position, VoxelData { id: 0, ..voxel });
+        }
+    }
+}
+
+// Voxels like torches that are attached to a neighbour, the lower 3 bits of the state store the direction of that neighbour
+// The voxel is removed once the neighbour it is attached to is gone
+pub struct AttachedBehavior {}
+
+impl AttachedBehavior {
+    pub fn attached_direction(voxel: &VoxelData) -> VoxelDirection {
+        VoxelDirection {
+            data: (voxel.state & 0b_0000_0111).min(5),
+        }
+    }
+}
+
+impl VoxelBehavior for AttachedBehavior {
+    fn on_neighbor_changed(
+        &self,
+        scene: &VoxelScene,
+        position: IVec3,
+        voxel: VoxelData,
+        neighbor_position: IVec3,
+    ) {
+        let support = position + Self::attached_direction(&voxel).as_vec();
+        if support != neighbor_position {
+            return;
+        }
+        if scene.voxel_at(&support).map_or(false, |v| v.id == 0) {
+            scene.set_voxel(&position, VoxelData { id: 0, ..voxel });
+        }
+    }
+}
+
+// Lamps, doors and anything else that is switched on by a signal, the lowest bit of the state is set while powered
+pub struct PoweredBehavior {}
+
+impl VoxelBehavior for PoweredBehavior {
+    fn on_signal_changed(&self, scene: &VoxelScene, position: IVec3, voxel: VoxelData, power: u8) {
+        let powered = (power > 0) as u8;
+        if voxel.state & 1 != powered {
+            scene.set_voxel(
+                &position,
+                VoxelData {
+                    state: (voxel.state & !1) | powered,
+                    ..voxel
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use glam::IVec3;
+use parking_lot::RwLock;
+
+use super::{voxel_interaction::BREAK_TIME, voxel_registry::get_voxel_by_id};
+
+// Cracks drawn on a voxel being broken go through this many stages
+pub const CRACK_STAGES: u8 = 10;
+// Seconds a voxel keeps its progress after it was last hit
+const DECAY_DELAY: f32 = 1.0;
+// Progress lost per second once the delay has passed
+const DECAY_RATE: f32 = 0.5;
+
+// What a voxel is broken with, bare hands until the game hands out tools
+#[derive(Clone, Debug, PartialEq)]
+pub struct BreakingTool {
+    // Multiplies the break speed on every voxel
+    pub speed: f32,
+    // Voxels with one of these tags break faster again by the effective speed
+    pub effective_tags: Vec<String>,
+    pub effective_speed: f32,
+}
+
+impl BreakingTool {
+    pub const HAND: BreakingTool = BreakingTool {
+        speed: 1.0,
+        effective_tags: Vec::new(),
+        effective_speed: 1.0,
+    };
+}
+
+// Seconds it takes the tool to break the voxel, 0 for voxels without hardness
+pub fn break_time(id: u16, tool: &BreakingTool) -> f32 {
+    let profile = match get_voxel_by_id(id) {
+        Some(profile) => profile,
+        None => return BREAK_TIME,
+    };
+    let mut speed = tool.speed;
+    if tool.effective_tags.iter().any(|tag| profile.has_tag(tag)) {
+        speed *= tool.effective_speed;
+    }
+    BREAK_TIME * profile.hardness / speed.max(f32::EPSILON)
+}
+
+struct PartialBreak {
+    // A different voxel at the position starts over
+    id: u16,
+    progress: f32,
+    // Seconds since the voxel was last hit
+    idle: f32,
+}
+
+lazy_static! {
+    static ref LATEST: RwLock<Vec<(IVec3, u8)>> = RwLock::new(Vec::new());
+}
+
+// The voxels partly broken by the local player. Leaving a voxel keeps its progress for a moment,
+// after which it slowly heals
+#[derive(Default)]
+pub struct VoxelBreaking {
+    partial: HashMap<IVec3, PartialBreak>,
+}
+
+impl VoxelBreaking {
+    // Adds the seconds spent hitting the voxel, returns true once it breaks
+    pub fn hit(&mut self, position: IVec3, id: u16, tool: &BreakingTool, delta: f32) -> bool {
+        let time = break_time(id, tool);
+        let partial = self.partial.entry(position).or_insert(PartialBreak {
+            id,
+            progress: 0.0,
+            idle: 0.0,
+        });
+        if partial.id != id {
+            partial.id = id;
+            partial.progress = 0.0;
+        }
+        partial.idle = 0.0;
+        partial.progress += match time > 0.0 {
+            true => delta / time,
+            false => 1.0,
+        };
+        if partial.progress < 1.0 {
+            return false;
+        }
+        self.partial.remove(&position);
+        true
+    }
+
+    // Decays the voxels that weren't hit this frame, called once a frame after the hits
+    pub fn update(&mut self, delta: f32) {
+        for partial in self.partial.values_mut() {
+            if partial.idle >= DECAY_DELAY {
+                partial.progress -= delta * DECAY_RATE;
+            }
+            partial.idle += delta;
+        }
+        self.partial.retain(|_, partial| partial.progress > 0.0);
+        *LATEST.write() = self
+            .partial
+            .iter()
+            .map(|(position, partial)| (*position, crack_stage(partial.progress)))
+            .collect();
+    }
+
+    // From 0 to 1
+    pub fn progress(&self, position: IVec3) -> f32 {
+        self.partial
+            .get(&position)
+            .map_or(0.0, |partial| partial.progress)
+    }
+}
+
+fn crack_stage(progress: f32) -> u8 {
+    ((progress * CRACK_STAGES as f32) as u8).min(CRACK_STAGES - 1)
+}
+
+// The crack stage of every partly broken voxel as of the last frame, for the renderer
+pub fn crack_stages() -> Vec<(IVec3, u8)> {
+    LATEST.read().clone()
+}
+
+#[cfg(test)]
+mod voxel_breaking_tests {
+    use super::*;
+
+    #[test]
+    fn progress_decays_after_the_delay() {
+        let mut breaking = VoxelBreaking::default();
+        let position = IVec3::new(1, 2, 3);
+        assert!(!breaking.hit(position, u16::MAX, &BreakingTool::HAND, BREAK_TIME / 2.0));
+        breaking.update(DECAY_DELAY);
+        assert_eq!(breaking.progress(position), 0.5);
+        breaking.update(0.4);
+        assert!((breaking.progress(position) - 0.3).abs() < 1e-6);
+        breaking.update(1.0);
+        assert_eq!(breaking.progress(position), 0.0);
+    }
+
+    #[test]
+    fn breaking_takes_the_break_time() {
+        let mut breaking = VoxelBreaking::default();
+        let position = IVec3::ZERO;
+        assert!(!breaking.hit(position, u16::MAX, &BreakingTool::HAND, BREAK_TIME * 0.55));
+        assert_eq!(crack_stage(breaking.progress(position)), 5);
+        assert!(breaking.hit(position, u16::MAX, &BreakingTool::HAND, BREAK_TIME * 0.5));
+        assert_eq!(breaking.progress(position), 0.0);
+    }
+}
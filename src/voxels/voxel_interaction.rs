@@ -0,0 +1,176 @@
+use glam::{IVec3, Vec3};
+use legion::{component, IntoQuery};
+
+use crate::ecs::components::{
+    physics_components::{Collider, NoEntityCollision},
+    player_components::{Player, PlayerId},
+    transformation_components::Position,
+};
+
+use super::{regions::Regions, voxel_data::VoxelData, voxel_scene::VoxelScene};
+
+// Seconds it takes to break a voxel of hardness 1 by hand in survival mode
+pub const BREAK_TIME: f32 = 0.75;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelHit {
+    pub position: IVec3,
+    // Face of the voxel that was hit, the position + normal is where a voxel would be placed
+    pub normal: IVec3,
+    pub voxel: VoxelData,
+    pub distance: f32,
+}
+
+// Steps through the voxel grid along the ray and returns the first solid voxel
+pub fn raycast(
+    scene: &VoxelScene,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<VoxelHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+
+    // Voxels are centered on their integer position, shift so they span whole units
+    let start = origin + 0.5;
+    let mut cell = start.floor().as_ivec3();
+    let step = direction.signum().as_ivec3();
+    let delta = (1.0 / direction).abs();
+    let mut next = Vec3::new(
+        boundary_distance(start.x, direction.x),
+        boundary_distance(start.y, direction.y),
+        boundary_distance(start.z, direction.z),
+    );
+    let mut normal = IVec3::ZERO;
+    let mut distance = 0.0;
+
+    while distance <= max_distance {
+        if let Some(voxel) = scene.voxel_at(&cell) {
+            if voxel.id != 0 {
+                return Some(VoxelHit {
+                    position: cell,
+                    normal,
+                    voxel,
+                    distance,
+                });
+            }
+        }
+
+        let axis = if next.x < next.y && next.x < next.z {
+            0
+        } else if next.y < next.z {
+            1
+        } else {
+            2
+        };
+        distance = next[axis];
+        next[axis] += delta[axis];
+        cell[axis] += step[axis];
+        normal = IVec3::ZERO;
+        normal[axis] = -step[axis];
+    }
+    None
+}
+
+fn boundary_distance(start: f32, direction: f32) -> f32 {
+    if direction > 0.0 {
+        (start.floor() + 1.0 - start) / direction
+    } else if direction < 0.0 {
+        (start - start.floor()) / -direction
+    } else {
+        f32::INFINITY
+    }
+}
+
+// The center and half extents of every entity a placed voxel may not overlap. Dropped items and
+// anything else that doesn't collide with other entities are left out
+pub fn entity_colliders(world: &legion::World) -> Vec<(Vec3, Vec3)> {
+    let mut query = <(&Position, &Collider)>::query().filter(!component::<NoEntityCollision>());
+    query
+        .iter(world)
+        .map(|(position, collider)| (position.0, collider.half_extents))
+        .collect()
+}
+
+// Whether a voxel at the position would overlap any of the colliders, touching isn't overlapping
+pub fn overlaps_colliders(position: IVec3, colliders: &[(Vec3, Vec3)]) -> bool {
+    colliders.iter().any(|(center, half_extents)| {
+        let offset = (position.as_vec3() - *center).abs();
+        offset.cmplt(*half_extents + 0.5).all()
+    })
+}
+
+pub fn in_reach(player: &Player, eye: Vec3, position: IVec3) -> bool {
+    eye.distance(position.as_vec3()) <= player.reach
+}
+
+// A player making an edit, with what reach and region checks need to know about them
+#[derive(Clone, Copy)]
+pub struct EditingPlayer {
+    pub id: PlayerId,
+    pub player: Player,
+    pub eye: Vec3,
+    pub operator: bool,
+}
+
+impl EditingPlayer {
+    pub fn in_reach(&self, position: IVec3) -> bool {
+        in_reach(&self.player, self.eye, position)
+    }
+
+    pub fn may_edit(&self, regions: &Regions, position: IVec3) -> bool {
+        regions.can_edit(self.id, self.operator, position)
+    }
+
+    // Whether the player's game mode, reach and the regions all allow editing the voxel
+    fn can_edit(&self, regions: &Regions, position: IVec3) -> bool {
+        self.player.game_mode.can_edit()
+            && self.in_reach(position)
+            && self.may_edit(regions, position)
+    }
+}
+
+// Creative players remove the voxel outright, everyone else drops it as an item
+pub fn player_break_voxel(
+    scene: &VoxelScene,
+    regions: &Regions,
+    editor: &EditingPlayer,
+    position: IVec3,
+) -> Option<VoxelData> {
+    if !editor.can_edit(regions, position) {
+        return None;
+    }
+    scene.mark_player_modified(&position);
+    match editor.player.game_mode.drops_items() {
+        true => scene.break_voxel(&position),
+        false => {
+            let voxel = scene.voxel_at(&position).filter(|voxel| voxel.id != 0)?;
+            scene.set_voxel(&position, VoxelData { id: 0, ..voxel });
+            Some(voxel)
+        }
+    }
+}
+
+// Voxels can only be placed into air within reach, outside regions the player can't edit and
+// clear of the entity colliders, returns whether the voxel was placed
+pub fn player_place_voxel(
+    scene: &VoxelScene,
+    regions: &Regions,
+    editor: &EditingPlayer,
+    position: IVec3,
+    voxel: VoxelData,
+    colliders: &[(Vec3, Vec3)],
+) -> bool {
+    if !editor.can_edit(regions, position) || overlaps_colliders(position, colliders) {
+        return false;
+    }
+    match scene.voxel_at(&position) {
+        Some(existing) if existing.id == 0 => {
+            scene.mark_player_modified(&position);
+            scene.set_voxel(&position, voxel).is_some()
+        }
+        _ => false,
+    }
+}
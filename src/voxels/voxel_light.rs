@@ -0,0 +1,278 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use dashmap::DashMap;
+use glam::{IVec3, UVec3};
+use tracing::debug_span;
+
+use crate::jobs::{self, JobClass, JobHandle};
+
+use super::{
+    voxel_registry::get_voxel_by_id,
+    voxel_scene::{index_to_pos, pos_to_index, ChunkMap, VoxelChunk, VoxelScene, CHUNK_SIZE},
+    voxel_shapes::{voxel_directions, VoxelDirection},
+};
+
+pub const MAX_LIGHT: u8 = 15;
+const VOXEL_COUNT: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+// Light levels of every voxel of a chunk from emitting voxels, in the chunk's voxel order. Sky light
+// isn't stored, see spawner::sky_light_at
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkLight {
+    levels: Vec<u8>,
+}
+
+impl ChunkLight {
+    pub fn level_at(&self, position: &UVec3) -> u8 {
+        self.levels[pos_to_index(position) as usize]
+    }
+
+    pub fn levels(&self) -> &[u8] {
+        &self.levels
+    }
+}
+
+// How much light a voxel gives off, from its profile's "light"
+fn emission(id: u16) -> u8 {
+    match id {
+        0 => 0,
+        _ => get_voxel_by_id(id).map_or(0, |profile| profile.light),
+    }
+}
+
+// Light spreads through air, fluids and the voxels giving it off
+fn lets_light_through(id: u16) -> bool {
+    id == 0
+        || get_voxel_by_id(id).map_or(false, |profile| {
+            profile.light > 0 || profile.has_tag("fluid")
+        })
+}
+
+// The voxels on the chunk's face in the direction, in an order that matches the neighbour's
+// opposite face voxel for voxel
+fn face_positions(direction: VoxelDirection) -> impl Iterator<Item = UVec3> {
+    let normal = direction.as_vec();
+    let axis = (0..3).find(|axis| normal[*axis] != 0).unwrap();
+    let depth = match normal[axis] > 0 {
+        true => CHUNK_SIZE - 1,
+        false => 0,
+    };
+    (0..CHUNK_SIZE).flat_map(move |u| {
+        (0..CHUNK_SIZE).map(move |v| match axis {
+            0 => UVec3::new(depth, u, v),
+            1 => UVec3::new(u, depth, v),
+            _ => UVec3::new(u, v, depth),
+        })
+    })
+}
+
+// Floods the chunk from its emitting voxels and from the light its neighbours published on the
+// faces touching it, by direction from this chunk. Missing faces are dark
+pub fn propagate(chunk: &VoxelChunk, incoming: &[Option<Vec<u8>>; 6]) -> ChunkLight {
+    let mut levels = vec![0; VOXEL_COUNT];
+    let mut open = vec![false; VOXEL_COUNT];
+    let mut queue = VecDeque::new();
+    for index in 0..VOXEL_COUNT {
+        let position = index_to_pos(index as u32);
+        let id = chunk.voxel_at(&position).id;
+        open[index] = lets_light_through(id);
+        raise(&mut levels, &mut queue, position, emission(id));
+    }
+    for (direction, face) in voxel_directions::ALL.iter().zip(incoming) {
+        let face = match face {
+            Some(face) => face,
+            None => continue,
+        };
+        for (position, level) in face_positions(*direction).zip(face) {
+            if open[pos_to_index(&position) as usize] {
+                raise(&mut levels, &mut queue, position, level.saturating_sub(1));
+            }
+        }
+    }
+
+    while let Some(position) = queue.pop_front() {
+        let level = levels[pos_to_index(&position) as usize];
+        if level <= 1 {
+            continue;
+        }
+        for direction in voxel_directions::ALL {
+            let next = position.as_ivec3() + direction.as_vec();
+            if next.min_element() < 0 || next.max_element() >= CHUNK_SIZE as i32 {
+                continue;
+            }
+            let next = next.as_uvec3();
+            if open[pos_to_index(&next) as usize] {
+                raise(&mut levels, &mut queue, next, level - 1);
+            }
+        }
+    }
+    ChunkLight { levels }
+}
+
+// Queues the voxel to spread its light when the level is brighter than what it has
+fn raise(levels: &mut [u8], queue: &mut VecDeque<UVec3>, position: UVec3, level: u8) {
+    let index = pos_to_index(&position) as usize;
+    if level > levels[index] {
+        levels[index] = level;
+        queue.push_back(position);
+    }
+}
+
+// Voxel light of the loaded chunks, worked out one chunk at a time on the lighting jobs. A chunk
+// never reads its neighbours' voxels or light, only the faces they published in the border
+// exchange. When a chunk's faces change the neighbours facing them are queued to be lit again, so
+// light crosses chunk borders a job at a time until nothing changes
+#[derive(Clone, Default)]
+pub struct VoxelLighting {
+    lights: Arc<DashMap<IVec3, ChunkLight, ahash::RandomState>>,
+    // The levels on each face of every lit chunk, by chunk and direction index. Chunks without light
+    // publish nothing
+    borders: Arc<DashMap<(IVec3, usize), Vec<u8>, ahash::RandomState>>,
+    // Lighting jobs that haven't started, a chunk queued again before then is lit once. A mesh job
+    // for the chunk starting first lights it instead, see take_queued
+    queued: Arc<DashMap<IVec3, JobHandle, ahash::RandomState>>,
+}
+
+impl VoxelLighting {
+    pub fn level_at(&self, position: &IVec3) -> u8 {
+        let chunk_pos = VoxelScene::chunk_at(position);
+        let local = (*position - chunk_pos * CHUNK_SIZE as i32).as_uvec3();
+        self.lights
+            .get(&chunk_pos)
+            .map_or(0, |light| light.level_at(&local))
+    }
+
+    pub fn chunk_light(&self, chunk_pos: &IVec3) -> Option<ChunkLight> {
+        self.lights.get(chunk_pos).map(|light| light.clone())
+    }
+
+    // Chunks waiting for or running a lighting job
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    // Queues a lighting job for the chunk, the scene's chunks are read when it starts
+    pub fn request(&self, chunks: &ChunkMap, position: IVec3) {
+        let lighting = self.clone();
+        let chunks = Arc::clone(chunks);
+        // The entry stays locked until the handle is in, a job starting right away waits for it
+        // before taking itself off the queue
+        self.queued.entry(position).or_insert_with(|| {
+            jobs::spawn(JobClass::Lighting, &[], move || {
+                let _span = debug_span!("light_chunk", %position).entered();
+                // Changes from here on need a new job
+                lighting.queued.remove(&position);
+                lighting.light_chunk(&chunks, position);
+            })
+        });
+    }
+
+    // Takes the chunk's lighting job off the queue unless it started, for a job about to read the
+    // chunk anyway to light it with light_snapshot instead. Edits after this queue a new job
+    pub fn take_queued(&self, position: &IVec3) -> bool {
+        match self.queued.remove(position) {
+            Some((_, handle)) => {
+                handle.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The neighbours keep the light they received from the chunk until they're lit again
+    pub fn remove(&self, position: &IVec3) {
+        if let Some((_, handle)) = self.queued.remove(position) {
+            handle.cancel();
+        }
+        self.lights.remove(position);
+        for index in 0..voxel_directions::ALL.len() {
+            self.borders.remove(&(*position, index));
+        }
+    }
+
+    pub fn light_chunk(&self, chunks: &ChunkMap, position: IVec3) {
+        // The chunk can be unloaded while the job waits
+        let chunk = match chunks.get(&position) {
+            Some(chunk) => chunk.clone(),
+            None => return,
+        };
+        self.light_snapshot(chunks, &chunk);
+    }
+
+    // Lights a copy of the chunk the caller already took, neighbours whose incoming light changed
+    // are queued if they're among the chunks
+    pub fn light_snapshot(&self, chunks: &ChunkMap, chunk: &VoxelChunk) {
+        let position = chunk.position;
+        let mut incoming: [Option<Vec<u8>>; 6] = Default::default();
+        for (index, direction) in voxel_directions::ALL.iter().enumerate() {
+            let neighbour = position + direction.as_vec();
+            let face = direction.flip().data as usize;
+            incoming[index] = self.borders.get(&(neighbour, face)).map(|f| f.clone());
+        }
+        let light = match chunk.is_empty && incoming.iter().all(Option::is_none) {
+            true => ChunkLight {
+                levels: vec![0; VOXEL_COUNT],
+            },
+            false => propagate(chunk, &incoming),
+        };
+
+        for (index, direction) in voxel_directions::ALL.iter().enumerate() {
+            let face = face_positions(*direction)
+                .map(|local| light.level_at(&local))
+                .collect::<Vec<_>>();
+            let face = face.iter().any(|level| *level > 1).then(|| face);
+            let previous = match &face {
+                Some(face) => self.borders.insert((position, index), face.clone()),
+                None => self
+                    .borders
+                    .remove(&(position, index))
+                    .map(|(_, face)| face),
+            };
+            let neighbour = position + direction.as_vec();
+            if previous != face && chunks.contains_key(&neighbour) {
+                self.request(chunks, neighbour);
+            }
+        }
+        if light.levels.iter().any(|level| *level > 0) {
+            self.lights.insert(position, light);
+        } else {
+            self.lights.remove(&position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod voxel_light_tests {
+    use super::*;
+
+    #[test]
+    fn faces_line_up_across_the_border() {
+        let east = face_positions(voxel_directions::EAST).collect::<Vec<_>>();
+        let west = face_positions(voxel_directions::WEST).collect::<Vec<_>>();
+        assert_eq!(east.len(), (CHUNK_SIZE * CHUNK_SIZE) as usize);
+        for (a, b) in east.iter().zip(&west) {
+            assert_eq!(a.x, CHUNK_SIZE - 1);
+            assert_eq!(b.x, 0);
+            assert_eq!((a.y, a.z), (b.y, b.z));
+        }
+    }
+
+    #[test]
+    fn light_enters_through_the_faces() {
+        let chunk = VoxelChunk::new(IVec3::ZERO);
+        let mut incoming: [Option<Vec<u8>>; 6] = Default::default();
+        // A level 10 voxel just past the chunk's lower y face, at x 3 and z 5
+        let mut face = vec![0; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+        let down = face_positions(voxel_directions::DOWN)
+            .position(|p| p == UVec3::new(3, 0, 5))
+            .unwrap();
+        face[down] = 10;
+        incoming[voxel_directions::DOWN.data as usize] = Some(face);
+
+        let light = propagate(&chunk, &incoming);
+        assert_eq!(light.level_at(&UVec3::new(3, 0, 5)), 9);
+        assert_eq!(light.level_at(&UVec3::new(3, 4, 5)), 5);
+        assert_eq!(light.level_at(&UVec3::new(5, 1, 6)), 5);
+        assert_eq!(light.level_at(&UVec3::new(3, 9, 5)), 0);
+    }
+}
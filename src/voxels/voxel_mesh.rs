@@ -1,3 +1,5 @@
+use glam::Vec3;
+
 use crate::asset_types::mesh::Mesh;
 
 use self::voxel_meshes::SHAPE_MESHES;
@@ -93,6 +95,38 @@ pub struct VoxelMesh {
     pub bottom: Mesh,
 }
 
+pub fn load() {
+    lazy_static::initialize(&SHAPE_MESHES);
+}
+
 pub fn get_voxel_mesh(shape: VoxelShape) -> &'static VoxelMesh {
     SHAPE_MESHES[shape.extract_shape() as usize]
 }
+
+// Applies the shape's flips, then its rotations, to a vertex position or normal of its mesh
+pub fn orient(shape: VoxelShape, v: Vec3) -> Vec3 {
+    let mut v = v;
+    if shape.extract_flip_x() {
+        v.x *= -1.0;
+    }
+    if shape.extract_flip_y() {
+        v.y *= -1.0;
+    }
+    if shape.extract_flip_z() {
+        v.z *= -1.0;
+    }
+    if shape.extract_rotate_x() {
+        (v.y, v.z) = (v.z, -v.y);
+    }
+    if shape.extract_rotate_z() {
+        (v.x, v.y) = (v.y, -v.x);
+    }
+    v
+}
+
+// Whether the shape's flips turn its triangles inside out, their winding has to be reversed
+pub fn flips_winding(shape: VoxelShape) -> bool {
+    (shape.extract_flip_x() as u32 + shape.extract_flip_y() as u32 + shape.extract_flip_z() as u32)
+        % 2
+        == 1
+}
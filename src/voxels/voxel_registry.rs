@@ -1,16 +1,45 @@
-use std::fs;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
+use anyhow::{anyhow, bail, Result};
 use glam::Vec4;
 use multi_map::MultiMap;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    audio::sound_material::SoundMaterial,
+    data_packs::resource_files,
+    error::{expect_array, expect_str, read_json, AssemblageError},
+    localization::translate,
+    plugins::manifest::Version,
+};
+
+use super::{
+    voxel_behavior::{get_behavior_by_name, VoxelBehavior},
+    voxel_light::MAX_LIGHT,
+    voxel_signal::SignalKind,
+};
 
 type VoxelMap = MultiMap<u16, String, VoxelProfile>;
 
 lazy_static! {
     static ref VOXELS: VoxelMap = load_voxels();
+    // Voxels registered by plugins, added after the ones from the resources folder when the registry loads
+    static ref REGISTERED_VOXELS: Mutex<Vec<(String, serde_json::Value)>> = Mutex::new(Vec::new());
+    // The mods that registered voxels in load order, fixed once the registry loads like the voxel ids
+    static ref LOADED_MODS: Mutex<Vec<(String, Version)>> = Mutex::new(Vec::new());
 }
 
+// Set once the registry has loaded, voxel ids can't change after that
+static FROZEN: AtomicBool = AtomicBool::new(false);
+
 fn load_voxels() -> VoxelMap {
-    let paths = fs::read_dir("./src/resources/voxel_profiles").unwrap();
+    FROZEN.store(true, Ordering::SeqCst);
+    let voxel_files = resource_files("voxel_profiles");
 
     let mut map = MultiMap::new();
 
@@ -20,75 +49,221 @@ fn load_voxels() -> VoxelMap {
         VoxelProfile {
             id: 0,
             name: "Empty".to_string(),
+            translation_key: "voxel.Empty".to_string(),
             color: Vec4::ZERO,
+            behavior: None,
+            tags: Vec::new(),
+            signal: None,
+            sound_material: None,
+            hardness: 0.0,
+            texture: None,
+            light: 0,
         },
     );
 
+    // Files are parsed in parallel, ids still follow the file order. A broken file is skipped, the
+    // voxels after it take its id
+    let parsed = voxel_files
+        .into_par_iter()
+        .map(|(name, path)| (name, read_json(&path)))
+        .collect::<Vec<_>>();
     let mut id: u16 = 1;
-    for voxel_file in paths.into_iter() {
-        let voxel_file = voxel_file.unwrap();
-        let file_contents = fs::read_to_string(voxel_file.path()).unwrap();
-        let json: serde_json::Value =
-            serde_json::from_str(&file_contents).expect("JSON failed to parse");
-        let color = decode_color(json.get("color").map_or("#ffff", |v| v.as_str().unwrap()));
-        let name = voxel_file
-            .file_name()
-            .to_string_lossy()
-            .replace(".json", "");
-
-        let profile = VoxelProfile {
-            name: name.clone(),
-            id,
-            color,
-        };
-        map.insert(id, name.clone(), profile);
-
-        println!("==Created Voxel Profile==");
-        println!("Name: {name}");
-        println!("id: {id}");
-        println!("color: {color}");
-        println!("");
-
-        id += 1;
+    for (name, json) in parsed {
+        match json.and_then(|json| profile_from_json(id, name.clone(), &json)) {
+            Ok(profile) => {
+                debug!(%name, id, color = %profile.color, "Created voxel profile");
+                map.insert(id, name, profile);
+                id += 1;
+            }
+            Err(e) => error!("Skipping the voxel {name}: {e}"),
+        }
+    }
+
+    for (name, json) in REGISTERED_VOXELS.lock().drain(..) {
+        if map.get_alt(&name).is_some() {
+            warn!("Voxel {name} is already defined, the registered one is ignored");
+            continue;
+        }
+        // Checked when it was registered
+        match profile_from_json(id, name.clone(), &json) {
+            Ok(profile) => {
+                debug!(%name, id, color = %profile.color, "Created voxel profile");
+                map.insert(id, name, profile);
+                id += 1;
+            }
+            Err(e) => error!("Skipping the registered voxel {name}: {e}"),
+        }
+    }
+
+    let mods = LOADED_MODS.lock();
+    if !mods.is_empty() {
+        let mods = mods
+            .iter()
+            .map(|(id, version)| format!("{id} {version}"))
+            .collect::<Vec<_>>();
+        info!(
+            "Voxel registry loaded {} voxels with the mods {}",
+            map.iter().count(),
+            mods.join(", ")
+        );
     }
 
     return map;
 }
 
-fn decode_color(color_string: &str) -> Vec4 {
-    let len = color_string.len() - 1; // -1 because of the hashtag at the front of the string
-                                      // RGB
-    if len == 3 {
-        let r = u8::from_str_radix(&color_string.get(1..2).unwrap(), 16).unwrap() as f32 / 15.0;
-        let g = u8::from_str_radix(&color_string.get(2..3).unwrap(), 16).unwrap() as f32 / 15.0;
-        let b = u8::from_str_radix(&color_string.get(3..4).unwrap(), 16).unwrap() as f32 / 15.0;
-        return Vec4::new(r, g, b, 1.0);
+// Loads the registry now rather than on first access, see assets::load_assets
+pub fn load() {
+    lazy_static::initialize(&VOXELS);
+}
+
+fn profile_from_json(
+    id: u16,
+    name: String,
+    json: &serde_json::Value,
+) -> Result<VoxelProfile, AssemblageError> {
+    let color = match json.get("color") {
+        Some(v) => {
+            let color = expect_str(v, "color")?;
+            parse_color(color).ok_or_else(|| {
+                AssemblageError::asset(format!("the color {color} isn't a hex color"))
+            })?
+        }
+        None => Vec4::ONE,
+    };
+    let behavior = match json.get("behavior") {
+        Some(v) => {
+            let behavior_name = expect_str(v, "behavior")?;
+            Some(
+                get_behavior_by_name(behavior_name)
+                    .ok_or_else(|| AssemblageError::unknown("behavior", behavior_name))?,
+            )
+        }
+        None => None,
+    };
+    let tags = match json.get("tags") {
+        Some(v) => expect_array(v, "tags")?
+            .iter()
+            .map(|tag| expect_str(tag, "tags").map(str::to_string))
+            .collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+
+    let signal = json.get("signal").map(SignalKind::from_json).transpose()?;
+    // "sound" is the name older profiles use
+    let sound_material = match json.get("sound_material").or_else(|| json.get("sound")) {
+        Some(v) => Some(SoundMaterial::from_name(expect_str(v, "sound_material")?)?),
+        None => None,
+    };
+    let translation_key = match json.get("translation_key") {
+        Some(v) => expect_str(v, "translation_key")?.to_string(),
+        None => format!("voxel.{name}"),
+    };
+    let hardness = match json.get("hardness") {
+        Some(v) => match v.as_f64() {
+            Some(hardness) if hardness >= 0.0 => hardness as f32,
+            _ => {
+                return Err(AssemblageError::asset(
+                    "hardness has to be a number of at least 0",
+                ))
+            }
+        },
+        None => 1.0,
+    };
+    let texture = json
+        .get("texture")
+        .map(|v| expect_str(v, "texture").map(str::to_string))
+        .transpose()?;
+    let light = match json.get("light") {
+        Some(v) => match v.as_u64() {
+            Some(light) if light <= MAX_LIGHT as u64 => light as u8,
+            _ => {
+                return Err(AssemblageError::asset(format!(
+                    "light has to be a whole number from 0 to {MAX_LIGHT}"
+                )))
+            }
+        },
+        None => 0,
+    };
+
+    Ok(VoxelProfile {
+        name,
+        translation_key,
+        id,
+        color,
+        behavior,
+        tags,
+        signal,
+        sound_material,
+        hardness,
+        texture,
+        light,
+    })
+}
+
+// Adds a voxel defined like the files in the resources folder. Only possible before the registry
+// has loaded, the json is checked here so the plugin hears about mistakes
+pub fn register_voxel(name: &str, json: serde_json::Value) -> Result<()> {
+    if FROZEN.load(Ordering::SeqCst) {
+        bail!("Voxel {name} was registered after the voxel registry loaded");
     }
-    // RGBA
-    if len == 4 {
-        let r = u8::from_str_radix(&color_string.get(1..2).unwrap(), 16).unwrap() as f32 / 15.0;
-        let g = u8::from_str_radix(&color_string.get(2..3).unwrap(), 16).unwrap() as f32 / 15.0;
-        let b = u8::from_str_radix(&color_string.get(3..4).unwrap(), 16).unwrap() as f32 / 15.0;
-        let a = u8::from_str_radix(&color_string.get(4..5).unwrap(), 16).unwrap() as f32 / 15.0;
-        return Vec4::new(r, g, b, a);
+    if name.is_empty() || name == "Empty" {
+        bail!("{name:?} isn't a valid voxel name");
     }
-    // RRGGBB
-    if len == 6 {
-        let r = u8::from_str_radix(&color_string.get(1..3).unwrap(), 16).unwrap() as f32 / 255.0;
-        let g = u8::from_str_radix(&color_string.get(3..5).unwrap(), 16).unwrap() as f32 / 255.0;
-        let b = u8::from_str_radix(&color_string.get(5..7).unwrap(), 16).unwrap() as f32 / 255.0;
-        return Vec4::new(r, g, b, 1.0);
+    profile_from_json(0, name.to_string(), &json)
+        .map_err(|e| anyhow!("Voxel {name} can't be registered: {e}"))?;
+
+    let mut registered = REGISTERED_VOXELS.lock();
+    if registered.iter().any(|(existing, _)| existing == name) {
+        bail!("Voxel {name} was registered twice");
     }
-    // RRGGBBAA
-    if len == 8 {
-        let r = u8::from_str_radix(&color_string.get(1..3).unwrap(), 16).unwrap() as f32 / 255.0;
-        let g = u8::from_str_radix(&color_string.get(3..5).unwrap(), 16).unwrap() as f32 / 255.0;
-        let b = u8::from_str_radix(&color_string.get(5..7).unwrap(), 16).unwrap() as f32 / 255.0;
-        let a = u8::from_str_radix(&color_string.get(7..9).unwrap(), 16).unwrap() as f32 / 255.0;
-        return Vec4::new(r, g, b, a);
+    registered.push((name.to_string(), json));
+    Ok(())
+}
+
+// The mods that were loaded in load order, has to be set before the registry loads
+pub fn set_loaded_mods(mods: Vec<(String, Version)>) -> Result<()> {
+    if FROZEN.load(Ordering::SeqCst) {
+        bail!("The mod list was set after the voxel registry loaded");
     }
+    *LOADED_MODS.lock() = mods;
+    Ok(())
+}
+
+pub fn loaded_mods() -> Vec<(String, Version)> {
+    LOADED_MODS.lock().clone()
+}
+
+pub fn is_frozen() -> bool {
+    FROZEN.load(Ordering::SeqCst)
+}
 
-    return Vec4::new(0.0, 0.0, 0.0, 1.0);
+// Colors that aren't valid hex colors are black
+pub fn decode_color(color_string: &str) -> Vec4 {
+    parse_color(color_string).unwrap_or(Vec4::new(0.0, 0.0, 0.0, 1.0))
+}
+
+// Reads #RGB, #RGBA, #RRGGBB or #RRGGBBAA
+pub fn parse_color(color_string: &str) -> Option<Vec4> {
+    let digits = color_string.strip_prefix('#')?;
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let (width, max) = match digits.len() {
+        3 | 4 => (1, 15.0),
+        6 | 8 => (2, 255.0),
+        _ => return None,
+    };
+    let channels = (0..digits.len() / width)
+        .map(|i| {
+            u8::from_str_radix(&digits[i * width..(i + 1) * width], 16).unwrap_or(0) as f32 / max
+        })
+        .collect::<Vec<_>>();
+    Some(Vec4::new(
+        channels[0],
+        channels[1],
+        channels[2],
+        channels.get(3).copied().unwrap_or(1.0),
+    ))
 }
 
 pub fn get_voxel_by_name(name: String) -> Option<&'static VoxelProfile> {
@@ -99,9 +274,47 @@ pub fn get_voxel_by_id(id: u16) -> Option<&'static VoxelProfile> {
     return VOXELS.get(&id);
 }
 
+// Every voxel id with its name, sorted by id
+pub fn voxel_id_mappings() -> Vec<(u16, String)> {
+    let mut mappings = VOXELS
+        .iter()
+        .map(|(id, (name, _))| (*id, name.clone()))
+        .collect::<Vec<_>>();
+    mappings.sort();
+    mappings
+}
+
 #[derive(Clone)]
 pub struct VoxelProfile {
     pub id: u16,
     pub name: String,
+    // The key of the name shown to players, "voxel.<name>" unless the profile sets one
+    pub translation_key: String,
     pub color: Vec4,
+    pub behavior: Option<Arc<dyn VoxelBehavior>>,
+    pub tags: Vec<String>,
+    pub signal: Option<SignalKind>,
+    // Picks the sound sets of its break, place and footstep sounds, such as "break_stone"
+    pub sound_material: Option<SoundMaterial>,
+    // Multiplies the time it takes to break, 0 breaks right away
+    pub hardness: f32,
+    // A file from the textures folders drawn on every face instead of the color
+    pub texture: Option<String>,
+    // The light level the voxel gives off, lights the voxels around it on the lighting jobs
+    pub light: u8,
+}
+
+impl VoxelProfile {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    // The name in the current locale, the registry name when it isn't translated
+    pub fn display_name(&self) -> String {
+        translate(&self.translation_key).unwrap_or_else(|| self.name.clone())
+    }
+}
+
+pub fn voxel_has_tag(id: u16, tag: &str) -> bool {
+    get_voxel_by_id(id).map_or(false, |profile| profile.has_tag(tag))
 }
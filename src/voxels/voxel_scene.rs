@@ -1,48 +1,133 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
 
-use dashmap::{DashMap, DashSet};
+use dashmap::DashMap;
 use flume::{Receiver, Sender};
 use glam::{IVec3, UVec3, Vec3};
-use rayon::ThreadPool;
+use parking_lot::Mutex;
+use tracing::{debug_span, error, warn};
 
 use crate::asset_types::mesh::Mesh;
+use crate::error::AssemblageError;
+use crate::events::{self, ChunkGenerated};
+use crate::jobs::{self, JobClass, JobHandle};
+use crate::persistence::chunk_storage::ChunkStorage;
+use crate::persistence::entity_persistence::SavedEntity;
+use crate::persistence::world_stats;
+use crate::profile_scope;
+use crate::random::OVERWORLD;
 use crate::rendering::vertex::Vertex;
+use crate::voxels::biome_edges::BiomeEdges;
 use crate::voxels::biome_profile::{get_biome_by_name, SampleContext};
+use crate::voxels::chunk_stats::ChunkStats;
+use crate::voxels::chunk_visibility::FaceConnectivity;
+use crate::voxels::dimension::{dimension_or_default, Dimension, GenerationStage};
+use crate::voxels::features::{place_features, NeighborTerrain};
+use crate::voxels::generation_pipeline::GenerationPipeline;
 use crate::voxels::voxel_data::VoxelData;
 use crate::voxels::voxel_shapes::voxel_shape;
 
-use super::voxel_mesh::get_voxel_mesh;
-use super::voxel_registry;
+use super::edit_history::VoxelChange;
+use super::voxel_batch::VoxelBatch;
+use super::voxel_light::VoxelLighting;
+use super::voxel_mesh::{self, get_voxel_mesh};
+use super::voxel_registry::{self, voxel_has_tag};
 use super::voxel_shapes::{voxel_directions, VoxelDirection, VoxelShape};
+use super::voxel_textures::{self, box_uv, UvTable};
 
 pub const CHUNK_SIZE: u32 = 16;
-type ChunkMap = Arc<DashMap<IVec3, VoxelChunk, ahash::RandomState>>;
+pub const GRAVITY_TICK_DELAY: u32 = 2;
+pub type ChunkMap = Arc<DashMap<IVec3, VoxelChunk, ahash::RandomState>>;
 
+// Chunks waiting for a mesh job in the order they were edited, each chunk is only listed once
+#[derive(Default)]
+struct RemeshQueue {
+    order: VecDeque<IVec3>,
+    queued: HashSet<IVec3>,
+}
+
+impl RemeshQueue {
+    fn push(&mut self, chunk_pos: IVec3) {
+        if self.queued.insert(chunk_pos) {
+            self.order.push_back(chunk_pos);
+        }
+    }
+
+    fn pop(&mut self) -> Option<IVec3> {
+        let chunk_pos = self.order.pop_front()?;
+        self.queued.remove(&chunk_pos);
+        Some(chunk_pos)
+    }
+
+    fn len(&self) -> usize {
+        self.order.len()
+    }
+}
+
+// Locked after the World when both are needed, see World
 pub struct VoxelScene {
     pub chunks: ChunkMap,
-    initialization_queue: Arc<DashSet<IVec3>>,
-    initialization_channel: (
-        Sender<(IVec3, Option<Sender<IVec3>>)>,
-        Receiver<(IVec3, Option<Sender<IVec3>>)>,
-    ),
-    generation_channel: (Sender<IVec3>, Receiver<IVec3>),
-    generation_pre_processor_channel: (Sender<IVec3>, Receiver<IVec3>),
-    thread_pool: ThreadPool,
+    // Chunks waiting on their initialization job, so a chunk is only loaded or generated once
+    initializing: Arc<DashMap<IVec3, JobHandle, ahash::RandomState>>,
+    // Chunks that aren't saved are generated here, features wait for the terrain around them
+    generation: GenerationPipeline,
+    mesh_sender: Option<Sender<(IVec3, Mesh)>>,
+    // Edited chunks are meshed a few per frame by spawn_queued_meshes, so an explosion or a big fill
+    // is spread over several frames instead of stalling one
+    remesh_queue: Mutex<RemeshQueue>,
+    // Mesh jobs that haven't started yet. A job reads the chunk when it starts, so a chunk edited
+    // again before then doesn't need a second job
+    pending_meshes: Arc<DashMap<IVec3, JobHandle, ahash::RandomState>>,
+    // Worked out by the mesh jobs, since they run whenever a chunk changes
+    connectivity: Arc<DashMap<IVec3, FaceConnectivity, ahash::RandomState>>,
+    // How long the chunks generated this session took, loaded chunks aren't listed
+    generation_times: Arc<DashMap<IVec3, Duration, ahash::RandomState>>,
+    // Positions paired with the number of ticks to wait, drained by the voxel simulation
+    scheduled_tick_channel: (Sender<(IVec3, u32)>, Receiver<(IVec3, u32)>),
+    // Voxel position mapped to the neighbours that changed since its last update
+    neighbor_updates: Arc<DashMap<IVec3, Vec<IVec3>, ahash::RandomState>>,
+    // Chunks are loaded from here before falling back to generating them
+    storage: Option<Arc<ChunkStorage>>,
+    // Entities that were saved with chunks, drained by the voxel simulation which owns the entity world
+    loaded_entity_channel: (Sender<Vec<SavedEntity>>, Receiver<Vec<SavedEntity>>),
+    // Voxels removed with break_voxel or dropped with drop_item, turned into dropped items by the
+    // voxel simulation
+    item_drop_channel: (Sender<(IVec3, VoxelData)>, Receiver<(IVec3, VoxelData)>),
+    // Every voxel changed with set_voxel, drained by the server to replicate the change to clients
+    voxel_change_channel: (Sender<(IVec3, VoxelData)>, Receiver<(IVec3, VoxelData)>),
+    // Changes made while a journal is open, keyed by the thread that opened it. Each thread only
+    // records its own edits so the simulation running at the same time doesn't end up in the undo
+    // history, and edits on different threads don't wait for each other
+    journals: Mutex<HashMap<ThreadId, Vec<VoxelChange>>>,
+    // The number of open journals, set_voxels skips the journal lock while there are none
+    open_journals: AtomicUsize,
+    // Light from emitting voxels, every chunk is lit again after it loads or changes
+    lighting: VoxelLighting,
 }
 
 impl VoxelScene {
     pub fn new() -> Self {
         Self {
             chunks: Arc::new(DashMap::default()),
-            initialization_queue: Arc::new(DashSet::default()),
-            initialization_channel: flume::unbounded(),
-            generation_channel: flume::unbounded(),
-            generation_pre_processor_channel: flume::unbounded(),
-            thread_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(8)
-                .build()
-                .unwrap(),
+            initializing: Arc::new(DashMap::default()),
+            generation: GenerationPipeline::new(),
+            mesh_sender: None,
+            remesh_queue: Mutex::new(RemeshQueue::default()),
+            pending_meshes: Arc::new(DashMap::default()),
+            connectivity: Arc::new(DashMap::default()),
+            generation_times: Arc::new(DashMap::default()),
+            scheduled_tick_channel: flume::unbounded(),
+            neighbor_updates: Arc::new(DashMap::default()),
+            storage: None,
+            loaded_entity_channel: flume::unbounded(),
+            item_drop_channel: flume::unbounded(),
+            voxel_change_channel: flume::unbounded(),
+            journals: Mutex::new(HashMap::new()),
+            open_journals: AtomicUsize::new(0),
+            lighting: VoxelLighting::default(),
         }
     }
 
@@ -53,187 +138,561 @@ impl VoxelScene {
             .map(|chunk| chunk.voxel_scenespace_at(position).unwrap().to_owned())
     }
 
-    pub fn chunk_at(position: &IVec3) -> IVec3 {
-        IVec3::new(
-            position.x.div_floor(CHUNK_SIZE as i32),
-            position.y.div_floor(CHUNK_SIZE as i32),
-            position.z.div_floor(CHUNK_SIZE as i32),
+    // The biome the voxel's chunk was generated with, read from the chunk so the selection isn't
+    // worked out again. Chunks that aren't loaded or don't know theirs take the one the dimension
+    // picks for them
+    pub fn biome_at(&self, position: &IVec3) -> String {
+        let chunk_pos = Self::chunk_at(position);
+        self.chunks
+            .get(&chunk_pos)
+            .and_then(|chunk| chunk.biome.as_deref().map(str::to_string))
+            .unwrap_or_else(|| biome_in(self.dimension(), chunk_pos))
+    }
+
+    // The light level from emitting voxels, 0 until the chunk's lighting job ran
+    pub fn light_at(&self, position: &IVec3) -> u8 {
+        self.lighting.level_at(position)
+    }
+
+    // Returns the voxel that was replaced, or None if the chunk is not loaded
+    pub fn set_voxel(&self, position: &IVec3, voxel: VoxelData) -> Option<VoxelData> {
+        self.set_voxels([(*position, voxel)])
+            .pop()
+            .map(|change| change.before)
+    }
+
+    // Sets the voxels, locking each chunk once and queueing each chunk for remeshing once
+    // however many of its voxels changed. Returns the changes made, voxels in chunks that aren't
+    // loaded are left out
+    pub fn set_voxels(
+        &self,
+        voxels: impl IntoIterator<Item = (IVec3, VoxelData)>,
+    ) -> Vec<VoxelChange> {
+        let mut by_chunk: HashMap<IVec3, Vec<(IVec3, VoxelData)>> = HashMap::new();
+        for (position, voxel) in voxels {
+            by_chunk
+                .entry(Self::chunk_at(&position))
+                .or_default()
+                .push((position, voxel));
+        }
+        let mut changes = Vec::new();
+        for (chunk_pos, voxels) in by_chunk {
+            let mut chunk = match self.chunks.get_mut(&chunk_pos) {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+            for (position, voxel) in voxels {
+                let target = chunk.voxel_scenespace_at_mut(&position).unwrap();
+                let before = *target;
+                *target = voxel;
+                if voxel.id != 0 {
+                    chunk.is_empty = false;
+                }
+                changes.push(VoxelChange {
+                    position,
+                    before,
+                    after: voxel,
+                });
+            }
+            chunk.dirty = true;
+        }
+
+        // A thread's own journal is opened on that thread, so the count is never behind for it
+        if self.open_journals.load(Ordering::Relaxed) > 0 {
+            if let Some(journal) = self.journals.lock().get_mut(&thread::current().id()) {
+                journal.extend_from_slice(&changes);
+            }
+        }
+
+        // Each touched chunk is remeshed and lit once however many of its voxels changed, the light
+        // reaches the neighbours through the border exchange
+        let mut remesh = HashSet::new();
+        let mut relight = HashSet::new();
+        for change in &changes {
+            let (position, voxel) = (change.position, change.after);
+            self.voxel_change_channel.0.send((position, voxel)).unwrap();
+            // Gravity affected voxels need to check their support after being placed
+            if voxel_has_tag(voxel.id, "gravity") {
+                self.schedule_tick(position, GRAVITY_TICK_DELAY);
+            }
+            remesh.insert(Self::chunk_at(&position));
+            relight.insert(Self::chunk_at(&position));
+            for direction in voxel_directions::ALL {
+                let neighbour = position + direction.as_vec();
+                // Neighbours are notified on the next simulation tick, repeated changes to the
+                // same voxel are merged
+                let mut changed = self.neighbor_updates.entry(neighbour).or_default();
+                if !changed.contains(&position) {
+                    changed.push(position);
+                }
+                // Voxels on the border of a chunk can change which faces the neighbouring chunk
+                // shows
+                remesh.insert(Self::chunk_at(&neighbour));
+            }
+        }
+        for chunk_pos in remesh {
+            self.request_remesh(chunk_pos);
+        }
+        for chunk_pos in relight {
+            self.lighting.request(&self.chunks, chunk_pos);
+        }
+        changes
+    }
+
+    // Runs the edit against a batch and sets everything it staged at once, for edits that change
+    // many voxels. The edit reads the voxels it staged through the batch
+    pub fn batch<R>(&self, edit: impl FnOnce(&mut VoxelBatch) -> R) -> R {
+        let mut batch = VoxelBatch::default();
+        let result = edit(&mut batch);
+        batch.commit(self);
+        result
+    }
+
+    // Voxels on the border of a chunk can change which faces the neighbouring chunk shows
+    fn remesh_around(&self, position: &IVec3) {
+        let chunk_pos = Self::chunk_at(position);
+        self.request_remesh(chunk_pos);
+        for direction in voxel_directions::ALL {
+            let neighbour_chunk = Self::chunk_at(&(*position + direction.as_vec()));
+            if neighbour_chunk != chunk_pos {
+                self.request_remesh(neighbour_chunk);
+            }
+        }
+    }
+
+    // Applies a change replicated from a server, the server already ran the simulation for it
+    // so no ticks or neighbour updates are scheduled. Returns false if the chunk isn't loaded
+    pub fn apply_replicated_voxel(&self, position: &IVec3, voxel: VoxelData) -> bool {
+        let applied = self
+            .chunks
+            .get_mut(&Self::chunk_at(position))
+            .map(|mut chunk| {
+                *chunk.voxel_scenespace_at_mut(position).unwrap() = voxel;
+                if voxel.id != 0 {
+                    chunk.is_empty = false;
+                }
+            })
+            .is_some();
+        if applied {
+            self.remesh_around(position);
+        }
+        applied
+    }
+
+    // Removes the voxel and drops it as an item, returns the voxel that was broken
+    pub fn break_voxel(&self, position: &IVec3) -> Option<VoxelData> {
+        let voxel = self.voxel_at(position).filter(|voxel| voxel.id != 0)?;
+        self.set_voxel(position, VoxelData { id: 0, ..voxel });
+        self.drop_item(position, voxel);
+        Some(voxel)
+    }
+
+    // Drops the voxel as an item at the position without changing the scene
+    pub fn drop_item(&self, position: &IVec3, voxel: VoxelData) {
+        self.item_drop_channel.0.send((*position, voxel)).unwrap();
+    }
+
+    // Opens a journal for the current thread, returns false if it already has one open. Journals
+    // of other threads don't get in the way
+    pub fn begin_journal(&self) -> bool {
+        let mut journals = self.journals.lock();
+        let id = thread::current().id();
+        if journals.contains_key(&id) {
+            return false;
+        }
+        journals.insert(id, Vec::new());
+        self.open_journals.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    // Closes the current thread's journal and returns the changes made since it was opened
+    pub fn end_journal(&self) -> Vec<VoxelChange> {
+        match self.journals.lock().remove(&thread::current().id()) {
+            Some(changes) => {
+                self.open_journals.fetch_sub(1, Ordering::Relaxed);
+                changes
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // Marks the chunk containing the voxel as edited by a player, which protects it from pruning
+    pub fn mark_player_modified(&self, position: &IVec3) {
+        if let Some(mut chunk) = self.chunks.get_mut(&Self::chunk_at(position)) {
+            chunk.player_modified = true;
+            chunk.dirty = true;
+        }
+    }
+
+    // Replaces a chunk with one received from a server, the neighbours are remeshed since their borders may change
+    pub fn insert_chunk(&self, position: IVec3, voxels: Vec<VoxelData>, biome: Option<Arc<str>>) {
+        let mut chunk = VoxelChunk::from_voxels(position, voxels);
+        chunk.biome = biome;
+        self.chunks.insert(position, chunk);
+        self.lighting.request(&self.chunks, position);
+        self.request_remesh(position);
+        for direction in voxel_directions::ALL {
+            self.request_remesh(position + direction.as_vec());
+        }
+    }
+
+    // A chunk that is still being initialized is cancelled along with its mesh
+    pub fn remove_chunk(&self, position: &IVec3) {
+        if let Some((_, handle)) = self.initializing.remove(position) {
+            handle.cancel();
+        }
+        self.pending_meshes.remove(position);
+        self.connectivity.remove(position);
+        self.generation_times.remove(position);
+        self.lighting.remove(position);
+        self.chunks.remove(position);
+    }
+
+    pub fn generation_time(&self, chunk_pos: &IVec3) -> Option<Duration> {
+        self.generation_times.get(chunk_pos).map(|time| *time)
+    }
+
+    // None for chunks that aren't loaded. Chunks that haven't been meshed yet count as open
+    pub fn face_connectivity(&self, chunk_pos: &IVec3) -> Option<FaceConnectivity> {
+        let chunk = self.chunks.get(chunk_pos)?;
+        if chunk.is_empty {
+            return Some(FaceConnectivity::ALL);
+        }
+        Some(
+            self.connectivity
+                .get(chunk_pos)
+                .map_or(FaceConnectivity::ALL, |connectivity| *connectivity),
         )
     }
 
-    pub fn request_initialize_chunk(
-        queue: Arc<DashSet<IVec3>>,
-        sender: Sender<(IVec3, Option<Sender<IVec3>>)>,
-        request: (IVec3, Option<Sender<IVec3>>),
-    ) {
-        if queue.contains(&request.0) {
+    pub fn mark_chunk_dirty(&self, chunk_pos: &IVec3) {
+        if let Some(mut chunk) = self.chunks.get_mut(chunk_pos) {
+            chunk.dirty = true;
+        }
+    }
+
+    // Must be called before the chunk processors are set up
+    pub fn set_storage(&mut self, storage: Arc<ChunkStorage>) {
+        self.storage = Some(storage);
+    }
+
+    // Chunks that aren't saved are generated the way the dimension's settings describe, the
+    // overworld by default. Must be called before any chunk is requested
+    pub fn set_dimension(&mut self, name: &str) {
+        self.generation = GenerationPipeline::for_dimension(name);
+    }
+
+    pub fn dimension(&self) -> &str {
+        self.generation.dimension()
+    }
+
+    pub fn get_storage(&self) -> Option<Arc<ChunkStorage>> {
+        self.storage.clone()
+    }
+
+    pub fn get_loaded_entity_receiver(&self) -> Receiver<Vec<SavedEntity>> {
+        self.loaded_entity_channel.1.clone()
+    }
+
+    pub fn get_voxel_change_receiver(&self) -> Receiver<(IVec3, VoxelData)> {
+        self.voxel_change_channel.1.clone()
+    }
+
+    pub fn get_item_drop_receiver(&self) -> Receiver<(IVec3, VoxelData)> {
+        self.item_drop_channel.1.clone()
+    }
+
+    pub fn schedule_tick(&self, position: IVec3, delay: u32) {
+        self.scheduled_tick_channel
+            .0
+            .send((position, delay))
+            .unwrap();
+    }
+
+    pub fn get_scheduled_tick_receiver(&self) -> Receiver<(IVec3, u32)> {
+        self.scheduled_tick_channel.1.clone()
+    }
+
+    // Removes up to max pending neighbour updates, each with every neighbour that changed since
+    // the voxel's last update. The rest are left for the next tick. The map's order differs between
+    // runs, so the updates and their neighbours are sorted by position to apply and shed them the
+    // same way every time
+    pub fn take_neighbor_updates(&self, max: usize) -> Vec<(IVec3, Vec<IVec3>)> {
+        let mut positions = self
+            .neighbor_updates
+            .iter()
+            .map(|update| *update.key())
+            .collect::<Vec<_>>();
+        positions.sort_unstable_by_key(|position| position.to_array());
+        positions.truncate(max);
+        positions
+            .into_iter()
+            .filter_map(|position| self.neighbor_updates.remove(&position))
+            .map(|(position, mut neighbours)| {
+                neighbours.sort_unstable_by_key(|neighbour| neighbour.to_array());
+                (position, neighbours)
+            })
+            .collect()
+    }
+
+    // Queues the chunk for spawn_queued_meshes rather than meshing it right away
+    pub fn request_remesh(&self, chunk_pos: IVec3) {
+        if self.mesh_sender.is_none()
+            || self
+                .chunks
+                .get(&chunk_pos)
+                .map_or(true, |chunk| chunk.is_empty)
+        {
             return;
         }
-        queue.insert(request.0);
-        sender.send(request).unwrap();
+        self.remesh_queue.lock().push(chunk_pos);
     }
 
-    pub fn setup_chunk_processors(&mut self, mesh_sender: Sender<(IVec3, Mesh)>) {
-        for _i in 0..3 {
-            let chunks_clone = Arc::clone(&self.chunks);
-            let initialization_channel_receiver = self.initialization_channel.1.clone();
-            self.thread_pool.spawn(move || {
-                VoxelScene::initialization_processor(chunks_clone, initialization_channel_receiver);
-            });
+    // Every loaded chunk, for changes to how all voxels look such as a new texture atlas
+    pub fn remesh_all(&self) {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|chunk| *chunk.key())
+            .collect::<Vec<_>>();
+        for chunk in chunks {
+            self.request_remesh(chunk);
         }
+    }
 
-        for _i in 0..3 {
-            let chunks_clone = Arc::clone(&self.chunks);
-            let generation_channel_receiver = self.generation_channel.1.clone();
-            let mesh_sender_clone = mesh_sender.clone();
-            self.thread_pool.spawn(move || {
-                VoxelScene::generation_processor(
-                    chunks_clone,
-                    generation_channel_receiver,
-                    mesh_sender_clone,
-                );
+    // Starts mesh jobs for up to budget queued chunks, called once a frame. Chunks whose last job
+    // hasn't started are skipped without using the budget. Returns how many chunks are left queued
+    pub fn spawn_queued_meshes(&self, budget: usize) -> usize {
+        profile_scope!("spawn_queued_meshes");
+        let mut queue = self.remesh_queue.lock();
+        let mut spawned = 0;
+        while spawned < budget {
+            let chunk_pos = match queue.pop() {
+                Some(chunk_pos) => chunk_pos,
+                None => break,
+            };
+            let pending = self.pending_meshes.get(&chunk_pos).map_or(false, |handle| {
+                !handle.is_finished() && !handle.is_cancelled()
             });
+            if !pending {
+                self.spawn_mesh_job(chunk_pos, &[]);
+                spawned += 1;
+            }
         }
+        queue.len()
+    }
 
-        for _i in 0..2 {
-            let chunks_clone = Arc::clone(&self.chunks);
-            let generation_pre_processor_receiver = self.generation_pre_processor_channel.1.clone();
-            let initialization_queue_clone = Arc::clone(&self.initialization_queue);
-            let initialization_sender = self.initialization_channel.0.clone();
-            let generation_sender_clone = self.generation_channel.0.clone();
-            self.thread_pool.spawn(move || {
-                VoxelScene::generation_pre_processor(
-                    chunks_clone,
-                    generation_pre_processor_receiver,
-                    initialization_queue_clone,
-                    initialization_sender,
-                    generation_sender_clone,
-                );
-            });
+    // A chunk only ticks once all of its neighbours are loaded, so behaviors can safely read across borders
+    pub fn is_chunk_ticking(&self, chunk_pos: &IVec3) -> bool {
+        self.chunks.contains_key(chunk_pos)
+            && voxel_directions::ALL
+                .iter()
+                .all(|direction| self.chunks.contains_key(&(*chunk_pos + direction.as_vec())))
+    }
+
+    // Sorted by position like the neighbour updates, so chunks tick in the same order every run
+    pub fn ticking_chunks(&self) -> Vec<IVec3> {
+        let mut loaded = self
+            .chunks
+            .iter()
+            .map(|chunk| *chunk.key())
+            .collect::<Vec<_>>();
+        loaded.sort_unstable_by_key(|chunk_pos| chunk_pos.to_array());
+        loaded
+            .into_iter()
+            .filter(|chunk_pos| self.is_chunk_ticking(chunk_pos))
+            .collect()
+    }
+
+    pub fn chunk_at(position: &IVec3) -> IVec3 {
+        IVec3::new(
+            position.x.div_floor(CHUNK_SIZE as i32),
+            position.y.div_floor(CHUNK_SIZE as i32),
+            position.z.div_floor(CHUNK_SIZE as i32),
+        )
+    }
+
+    // Loads or generates the chunk on the job scheduler unless it is loaded already, returns the job
+    // to wait on while it is still being initialized
+    fn request_initialize_chunk(&self, position: IVec3) -> Option<JobHandle> {
+        if self.chunks.contains_key(&position) {
+            return None;
         }
+        let handle = self
+            .initializing
+            .entry(position)
+            .or_insert_with(|| {
+                let chunks = Arc::clone(&self.chunks);
+                let initializing = Arc::clone(&self.initializing);
+                let generation_times = Arc::clone(&self.generation_times);
+                let storage = self.storage.clone();
+                let lighting = self.lighting.clone();
+                // Saved chunks are complete, only the others go through the generation stages
+                if storage
+                    .as_ref()
+                    .map_or(true, |storage| !storage.contains(&position))
+                {
+                    return self.generation.generate(position, move |result| {
+                        let chunk = match result {
+                            Ok((chunk, time)) => {
+                                generation_times.insert(position, time);
+                                world_stats::record(|stats| stats.chunks_generated += 1);
+                                chunk
+                            }
+                            // The chunk stays empty for this session and isn't saved
+                            Err(e) => {
+                                error!("Failed to generate chunk {position}: {e}");
+                                VoxelChunk::new(position)
+                            }
+                        };
+                        chunks.entry(position).or_insert(chunk);
+                        lighting.request(&chunks, position);
+                        initializing.remove(&position);
+                    });
+                }
+                let loaded_entity_sender = self.loaded_entity_channel.0.clone();
+                let dimension = self.dimension().to_string();
+                jobs::spawn(JobClass::Generation, &[], move || {
+                    VoxelScene::initialize_chunk(
+                        &chunks,
+                        &generation_times,
+                        &dimension,
+                        position,
+                        storage,
+                        &loaded_entity_sender,
+                    );
+                    lighting.request(&chunks, position);
+                    initializing.remove(&position);
+                })
+            })
+            .clone();
+        Some(handle)
+    }
 
-        println!(
-            "[INFO] World generation initialized with {} threads",
-            self.thread_pool.current_num_threads()
-        );
+    // Without a mesh sender chunks are generated but never meshed, for servers with nothing to draw.
+    // Has to be set before chunks are requested
+    pub fn set_mesh_sender(&mut self, mesh_sender: Sender<(IVec3, Mesh)>) {
+        self.mesh_sender = Some(mesh_sender);
     }
 
+    // The chunk is meshed once it and its neighbours are initialized, since the faces on its border
+    // depend on them
     pub fn initialize_and_generate_chunk(&self, position: IVec3) {
-        VoxelScene::request_initialize_chunk(
-            Arc::clone(&self.initialization_queue),
-            self.initialization_channel.0.clone(),
-            (
-                position,
-                Some(self.generation_pre_processor_channel.0.clone()),
-            ),
-        );
+        let dependencies = self.request_initialize_around(position);
+        self.spawn_mesh_job(position, &dependencies);
     }
 
-    pub fn initialization_processor(
-        chunks: ChunkMap,
-        pos_receiver: Receiver<(IVec3, Option<Sender<IVec3>>)>,
-    ) {
-        println!("Started initialization processor");
-        loop {
-            let mut chunks_to_process = pos_receiver.try_iter().collect::<Vec<_>>();
-            if chunks_to_process.len() == 0 {
-                chunks_to_process = vec![pos_receiver.recv().unwrap()]; // Nothing to process, wait for something
-            }
-            chunks_to_process.iter().for_each(|(chunk_pos, callback)| {
-                if chunks.contains_key(&chunk_pos) {
-                    println!("INITIALIZING CHUNK THAT ALREADY EXISTS!");
-                    return;
-                }
-                let mut chunk = VoxelChunk::new(*chunk_pos);
-
-                // Set chunk data
-                let biome = get_biome_by_name("plains".to_string()).unwrap();
-                let chunk_pos_scenespace = chunk.scenespace_pos();
-                let mut context = SampleContext {
-                    position: chunk_pos_scenespace,
-                    slope: Vec3::ZERO,
-                    depth: 0.0,
-                    moisture: 0.0,
-                    temperature: 0.0,
-                    density: 0.0,
-                };
-                chunk
-                    .voxels
-                    .iter_mut()
-                    .enumerate()
-                    .for_each(|(index, voxel)| {
-                        let voxel_pos = index_to_pos(index as u32);
-                        context.position = voxel_pos.as_ivec3() + chunk_pos_scenespace;
-                        context.density = biome.sample_density(&context);
-                        if context.density > 0.0 {
-                            chunk.is_empty = false;
-                            *voxel = biome.sample_voxel(&context);
-                        }
-                    });
+    // Loads or generates the chunk with its neighbours and meshes it, unless that's done or
+    // underway already. The chunk is ready once the returned jobs finish
+    pub fn prepare_chunk(&self, position: IVec3) -> Vec<JobHandle> {
+        if let Some(handle) = self.pending_meshes.get(&position) {
+            return vec![handle.clone()];
+        }
+        let meshed = self.chunks.get(&position).map_or(false, |chunk| {
+            chunk.is_empty || self.connectivity.contains_key(&position)
+        });
+        if meshed {
+            return Vec::new();
+        }
+        let dependencies = self.request_initialize_around(position);
+        // Without a mesh sender there's nothing to mesh, loading is all there is to wait for
+        match self.spawn_mesh_job(position, &dependencies) {
+            Some(mesh) => vec![mesh],
+            None => dependencies,
+        }
+    }
 
-                chunks.insert(*chunk_pos, chunk);
-                callback.as_ref().map(|s| s.send(*chunk_pos));
-            });
+    fn request_initialize_around(&self, position: IVec3) -> Vec<JobHandle> {
+        let mut dependencies = Vec::new();
+        dependencies.extend(self.request_initialize_chunk(position));
+        for direction in voxel_directions::ALL {
+            dependencies.extend(self.request_initialize_chunk(position + direction.as_vec()));
         }
+        dependencies
     }
 
-    pub fn generation_processor(
-        chunks: ChunkMap,
-        pos_receiver: Receiver<IVec3>,
-        mesh_sender: Sender<(IVec3, Mesh)>,
-    ) {
-        println!("Started generation processor");
-        loop {
-            let chunk_pos = pos_receiver.recv().unwrap();
-            let chunk = (*chunks.get(&chunk_pos).unwrap()).clone();
-            let chunks_clone = Arc::clone(&chunks);
-            let mesh = chunk.generate_mesh(chunks_clone);
-            mesh_sender.send((chunk_pos, mesh)).unwrap();
-        }
-    }
-
-    pub fn generation_pre_processor(
-        chunks: ChunkMap,
-        pos_receiver: Receiver<IVec3>,
-        initialization_queue: Arc<DashSet<IVec3>>,
-        initialization_sender: Sender<(IVec3, Option<Sender<IVec3>>)>,
-        pos_sender: Sender<IVec3>,
+    fn spawn_mesh_job(&self, position: IVec3, dependencies: &[JobHandle]) -> Option<JobHandle> {
+        let mesh_sender = self.mesh_sender.clone()?;
+        let chunks = Arc::clone(&self.chunks);
+        let pending_meshes = Arc::clone(&self.pending_meshes);
+        let connectivity = Arc::clone(&self.connectivity);
+        let lighting = self.lighting.clone();
+        let handle = jobs::spawn(JobClass::Meshing, dependencies, move || {
+            let _span = debug_span!("mesh_chunk", %position).entered();
+            // Edits from here on need a new job
+            pending_meshes.remove(&position);
+            // A relight still waiting for the chunk is done here from the copy taken for the mesh,
+            // instead of copying the chunk again on a lighting job
+            let relight = lighting.take_queued(&position);
+            // The chunk can be unloaded while the job waits
+            let chunk = match chunks.get(&position) {
+                Some(chunk) => chunk.clone(),
+                None => return,
+            };
+            if relight {
+                lighting.light_snapshot(&chunks, &chunk);
+            }
+            if chunk.is_empty {
+                return;
+            }
+            let mesh = chunk.generate_mesh(Arc::clone(&chunks));
+            connectivity.insert(position, FaceConnectivity::of_chunk(&chunk));
+            let _ = mesh_sender.send((position, mesh));
+        });
+        self.pending_meshes.insert(position, handle.clone());
+        Some(handle)
+    }
+
+    fn initialize_chunk(
+        chunks: &ChunkMap,
+        generation_times: &DashMap<IVec3, Duration, ahash::RandomState>,
+        dimension: &str,
+        chunk_pos: IVec3,
+        storage: Option<Arc<ChunkStorage>>,
+        loaded_entity_sender: &Sender<Vec<SavedEntity>>,
     ) {
-        println!("Started generation pre-processor");
-        // store a list of chunk positions
-        let mut chunks_to_generate = VecDeque::new();
-        loop {
-            let mut chunk_positions = pos_receiver.try_iter().collect::<Vec<_>>();
-            chunk_positions.extend(chunks_to_generate.iter());
-            chunks_to_generate.clear();
-            if chunk_positions.len() == 0 {
-                chunk_positions = vec![pos_receiver.recv().unwrap()]; // Nothing left in queue, wait for something
+        if chunks.contains_key(&chunk_pos) {
+            return;
+        }
+        let _span = debug_span!("initialize_chunk", position = %chunk_pos).entered();
+        let saved = storage.as_ref().and_then(|storage| {
+            storage.load(&chunk_pos).unwrap_or_else(|e| {
+                warn!("Failed to load chunk {chunk_pos}, generating it instead: {e}");
+                None
+            })
+        });
+        let chunk = match saved {
+            Some(payload) => {
+                if !payload.entities.is_empty() {
+                    loaded_entity_sender.send(payload.entities).unwrap();
+                }
+                let mut chunk = VoxelChunk::from_voxels(chunk_pos, payload.voxels);
+                chunk.player_modified = payload.player_modified;
+                // Chunks saved before biomes were stored take the one the dimension picks
+                let biome = payload
+                    .biome
+                    .unwrap_or_else(|| biome_in(dimension, chunk_pos));
+                chunk.biome = Some(Arc::from(biome));
+                chunk
             }
-            for chunk_pos in chunk_positions {
-                // get a list of neighbours
-                let mut failed = false;
-                for direction in voxel_directions::ALL {
-                    let neighbour_pos = chunk_pos + direction.as_vec();
-                    if !chunks.contains_key(&neighbour_pos) {
-                        failed = true;
-                        VoxelScene::request_initialize_chunk(
-                            initialization_queue.clone(),
-                            initialization_sender.clone(),
-                            (neighbour_pos, None),
-                        );
+            None => {
+                let start = Instant::now();
+                // The chunk stays empty for this session and isn't saved
+                let chunk = match VoxelChunk::generate_in(dimension, chunk_pos) {
+                    Ok(chunk) => {
+                        world_stats::record(|stats| stats.chunks_generated += 1);
+                        chunk
                     }
-                }
-
-                // if all neighbours are initialized, schedule the chunk to be generated
-                if !failed && chunks.contains_key(&chunk_pos) {
-                    if !chunks.get(&chunk_pos).unwrap().is_empty {
-                        pos_sender.send(chunk_pos).unwrap();
+                    Err(e) => {
+                        error!("Failed to generate chunk {chunk_pos}: {e}");
+                        VoxelChunk::new(chunk_pos)
                     }
-                } else {
-                    chunks_to_generate.push_front(chunk_pos);
-                }
+                };
+                generation_times.insert(chunk_pos, start.elapsed());
+                chunk
             }
-        }
+        };
+        chunks.insert(chunk_pos, chunk);
     }
 }
 
@@ -241,6 +700,11 @@ impl VoxelScene {
 pub struct VoxelChunk {
     pub position: IVec3,
     pub is_empty: bool,
+    // Set when the chunk differs from what is saved on disk
+    pub dirty: bool,
+    pub player_modified: bool,
+    // The biome the chunk was generated with, None for chunks that don't know it
+    pub biome: Option<Arc<str>>,
     voxels: Vec<VoxelData>,
 }
 
@@ -249,6 +713,9 @@ impl VoxelChunk {
         Self {
             position,
             is_empty: true,
+            dirty: false,
+            player_modified: false,
+            biome: None,
             voxels: vec![
                 VoxelData {
                     shape: voxel_shape::CUBE,
@@ -260,6 +727,135 @@ impl VoxelChunk {
         }
     }
 
+    pub fn from_voxels(position: IVec3, voxels: Vec<VoxelData>) -> Self {
+        let is_empty = voxels.iter().all(|voxel| voxel.id == 0);
+        Self {
+            position,
+            is_empty,
+            dirty: false,
+            player_modified: false,
+            biome: None,
+            voxels,
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.voxels.capacity() * std::mem::size_of::<VoxelData>()
+    }
+
+    pub fn generate(position: IVec3) -> Result<Self, AssemblageError> {
+        Self::generate_in(OVERWORLD, position)
+    }
+
+    pub fn generate_in(dimension: &str, position: IVec3) -> Result<Self, AssemblageError> {
+        let (chunk, _) = Self::generate_with_stats_in(dimension, position)?;
+        events::emit(&mut ChunkGenerated { position });
+        Ok(chunk)
+    }
+
+    // Generates the chunk and describes the result, the stats cost little next to the sampling.
+    // Listeners aren't told about the chunk, it may only be generated to look at it. The base
+    // terrain of the neighbours is generated for the features as well, the scene shares it between
+    // chunks through its generation pipeline instead
+    pub fn generate_with_stats(position: IVec3) -> Result<(Self, ChunkStats), AssemblageError> {
+        Self::generate_with_stats_in(OVERWORLD, position)
+    }
+
+    pub fn generate_with_stats_in(
+        dimension: &str,
+        position: IVec3,
+    ) -> Result<(Self, ChunkStats), AssemblageError> {
+        let (mut chunk, mut stats) = Self::generate_base_in(dimension, position)?;
+        if dimension_or_default(dimension).has_stage(GenerationStage::Features) {
+            let neighbors = NeighborTerrain::positions(position)
+                .filter(|neighbor| *neighbor != position)
+                .filter_map(|neighbor| {
+                    let (base, _) = Self::generate_base_in(dimension, neighbor).ok()?;
+                    Some((neighbor, Arc::new(base)))
+                })
+                .chain([(position, Arc::new(chunk.clone()))])
+                .collect();
+            place_features(&mut chunk, &NeighborTerrain::new(neighbors));
+        }
+        stats.count_voxels(&chunk);
+        Ok((chunk, stats))
+    }
+
+    // The second generation stage, once the base terrain of the chunk and its neighbours exists.
+    // Dimensions without a features stage keep the base terrain as it is
+    pub fn with_features(mut self, dimension: &Dimension, neighbors: &NeighborTerrain) -> Self {
+        if dimension.has_stage(GenerationStage::Features) {
+            place_features(&mut self, neighbors);
+        }
+        events::emit(&mut ChunkGenerated {
+            position: self.position,
+        });
+        self
+    }
+
+    // The first generation stage, the terrain from the biome's formulas without any features. The
+    // stats only have the densities
+    pub fn generate_base(position: IVec3) -> Result<(Self, ChunkStats), AssemblageError> {
+        Self::generate_base_in(OVERWORLD, position)
+    }
+
+    // Chunks outside the dimension's height, or in a dimension without a terrain stage, stay empty
+    pub fn generate_base_in(
+        dimension_name: &str,
+        position: IVec3,
+    ) -> Result<(Self, ChunkStats), AssemblageError> {
+        let _span = debug_span!("generate_chunk", dimension = dimension_name, %position).entered();
+        profile_scope!("generate_chunk");
+        let mut chunk = VoxelChunk::new(position);
+
+        // Set chunk data
+        let dimension = dimension_or_default(dimension_name);
+        let biome_name = dimension.biome_at(dimension_name, position);
+        chunk.biome = Some(Arc::from(biome_name));
+        let mut stats = ChunkStats::new(position, biome_name);
+        if !dimension.contains_layer(position.y) || !dimension.has_stage(GenerationStage::Terrain) {
+            return Ok((chunk, stats));
+        }
+        let biome = get_biome_by_name(biome_name.to_string())
+            .ok_or_else(|| AssemblageError::unknown("biome", biome_name))?;
+        let edges = BiomeEdges::around_in(dimension_name, position);
+        let chunk_pos_scenespace = chunk.scenespace_pos();
+        let mut context = SampleContext {
+            position: chunk_pos_scenespace,
+            slope: Vec3::ZERO,
+            depth: 0.0,
+            moisture: dimension.climate.moisture,
+            temperature: dimension.climate.temperature,
+            density: 0.0,
+            edge_distance: 0.0,
+        };
+        chunk
+            .voxels
+            .iter_mut()
+            .enumerate()
+            .for_each(|(index, voxel)| {
+                let voxel_pos = index_to_pos(index as u32);
+                context.position = voxel_pos.as_ivec3() + chunk_pos_scenespace;
+                context.edge_distance = edges.distance(context.position);
+                context.density = biome.sample_density(&context);
+                stats.add_density(context.density);
+                if context.density > 0.0 {
+                    chunk.is_empty = false;
+                    *voxel = biome.sample_voxel(&mut context);
+                } else if let Some(fluid) =
+                    biome.sample_fluid(&context, dimension.sea_level.as_ref())
+                {
+                    chunk.is_empty = false;
+                    *voxel = fluid;
+                }
+            });
+        Ok((chunk, stats))
+    }
+
+    pub fn voxels(&self) -> &Vec<VoxelData> {
+        &self.voxels
+    }
+
     pub fn voxel_scenespace_at_mut(&mut self, position: &IVec3) -> Option<&mut VoxelData> {
         let localized_pos = *position - (self.position * CHUNK_SIZE as i32);
         if localized_pos.x >= CHUNK_SIZE as i32
@@ -288,6 +884,18 @@ impl VoxelChunk {
         Some(self.voxel_at(&localized_pos.as_uvec3()))
     }
 
+    // Returns false if the position is outside the chunk
+    pub fn set_voxel_scenespace(&mut self, position: &IVec3, voxel: VoxelData) -> bool {
+        match self.voxel_scenespace_at_mut(position) {
+            Some(target) => *target = voxel,
+            None => return false,
+        }
+        if voxel.id != 0 {
+            self.is_empty = false;
+        }
+        true
+    }
+
     pub fn voxel_at(&self, position: &UVec3) -> &VoxelData {
         &self.voxels.get(pos_to_index(&position) as usize).unwrap()
     }
@@ -303,8 +911,10 @@ impl VoxelChunk {
     }
 
     pub fn generate_mesh(&self, scene_chunks: ChunkMap) -> Mesh {
+        profile_scope!("mesh_chunk");
         let mut vertices = vec![];
         let mut indices = vec![];
+        let uv_table = voxel_textures::uv_table();
 
         for x in 0..CHUNK_SIZE {
             for y in 0..CHUNK_SIZE {
@@ -319,6 +929,7 @@ impl VoxelChunk {
                             scene_chunks_clone,
                             self,
                             &pos,
+                            &uv_table,
                             &mut vertices,
                             &mut indices,
                         );
@@ -340,7 +951,18 @@ impl VoxelChunk {
     }
 }
 
-fn index_to_pos(index: u32) -> UVec3 {
+// The biome an overworld chunk is generated with
+pub fn biome_at(chunk_pos: IVec3) -> String {
+    biome_in(OVERWORLD, chunk_pos)
+}
+
+pub fn biome_in(dimension: &str, chunk_pos: IVec3) -> String {
+    dimension_or_default(dimension)
+        .biome_at(dimension, chunk_pos)
+        .to_string()
+}
+
+pub fn index_to_pos(index: u32) -> UVec3 {
     let x = index / (CHUNK_SIZE * CHUNK_SIZE);
     let y = index % (CHUNK_SIZE * CHUNK_SIZE) / CHUNK_SIZE;
     let z = index % CHUNK_SIZE;
@@ -361,6 +983,7 @@ fn generate_faces(
     scene_chunks: ChunkMap,
     chunk: &VoxelChunk,
     position: &UVec3,
+    uv_table: &UvTable,
     vertices: &mut Vec<Vertex>,
     indices: &mut Vec<u32>,
 ) {
@@ -388,19 +1011,19 @@ fn generate_faces(
         })
     };
 
-    let color = voxel_registry::get_voxel_by_id(voxel.id)
-        .unwrap()
-        .color
-        .into();
+    // Textured voxels show the texture as it is, the others their color
+    let tile = uv_table.tile(voxel.id);
+    let color = match tile {
+        Some(_) => [1.0; 4],
+        None => voxel_registry::get_voxel_by_id(voxel.id)
+            .unwrap()
+            .color
+            .into(),
+    };
     let mut append_mesh = |mesh: &Mesh| {
         let index_offset = vertices.len() as u32;
 
-        let flip_x = voxel.shape.extract_flip_x();
-        let flip_y = voxel.shape.extract_flip_y();
-        let flip_z = voxel.shape.extract_flip_z();
-        let flip_count = (flip_x as u32 + flip_y as u32 + flip_z as u32) % 2;
-
-        if flip_count & 1 == 0 {
+        if !voxel_mesh::flips_winding(voxel.shape) {
             let mut new_indices = mesh.get_indices().clone();
             new_indices
                 .iter_mut()
@@ -421,29 +1044,14 @@ fn generate_faces(
         mesh.get_vertices().iter().for_each(|v| {
             let mut vert = v.clone();
             vert.color = color;
-            if flip_x {
-                vert.position[0] *= -1.0;
-                vert.normal[0] *= -1.0;
-            }
-            if voxel.shape.extract_flip_y() {
-                vert.position[1] *= -1.0;
-                vert.normal[1] *= -1.0;
-            }
-            if voxel.shape.extract_flip_z() {
-                vert.position[2] *= -1.0;
-                vert.normal[2] *= -1.0;
+            let local = voxel_mesh::orient(voxel.shape, vert.position.into());
+            vert.normal = voxel_mesh::orient(voxel.shape, vert.normal.into()).into();
+            vert.uv = match tile {
+                Some(tile) => tile.map(box_uv(local, vert.normal.into())),
+                None => uv_table.white,
             }
-            if voxel.shape.extract_rotate_x() {
-                (vert.position[1], vert.position[2]) = (vert.position[2], -vert.position[1]);
-                (vert.normal[1], vert.normal[2]) = (vert.normal[2], -vert.normal[1]);
-            }
-            if voxel.shape.extract_rotate_z() {
-                (vert.position[0], vert.position[1]) = (vert.position[1], -vert.position[0]);
-                (vert.normal[0], vert.normal[1]) = (vert.normal[1], -vert.normal[0]);
-            }
-            vert.position[0] += f_position.x;
-            vert.position[1] += f_position.y;
-            vert.position[2] += f_position.z;
+            .into();
+            vert.position = (local + f_position).into();
             vertices.push(vert);
         });
     };
@@ -485,3 +1093,103 @@ fn generate_faces(
         append_mesh(&shape_mesh.bottom);
     }
 }
+
+#[cfg(test)]
+mod voxel_scene_tests {
+    use super::*;
+
+    #[test]
+    fn neighbour_updates_keep_every_changed_neighbour() {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        let voxel = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        let center = IVec3::new(5, 5, 5);
+        let below = center - IVec3::Y;
+        let side = center + IVec3::X;
+        scene.set_voxels([(below, voxel), (side, voxel)]);
+        scene.set_voxel(&below, VoxelData { id: 0, ..voxel });
+
+        let updates = scene.take_neighbor_updates(usize::MAX);
+        let (_, changed) = updates
+            .iter()
+            .find(|(position, _)| *position == center)
+            .unwrap();
+        assert_eq!(changed, &vec![below, side]);
+        assert!(scene.take_neighbor_updates(usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn neighbour_updates_are_taken_in_position_order() {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        let voxel = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        scene.set_voxels((1..8).rev().map(|x| (IVec3::new(x * 2, 5, 5), voxel)));
+        let all = scene
+            .take_neighbor_updates(usize::MAX)
+            .into_iter()
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>();
+        let mut sorted = all.clone();
+        sorted.sort_by_key(|position| position.to_array());
+        assert_eq!(all, sorted);
+
+        // The updates left over for the next tick are the last ones in that order
+        scene.set_voxels((1..8).map(|x| (IVec3::new(x * 2, 5, 5), voxel)));
+        let first = scene.take_neighbor_updates(3);
+        assert_eq!(
+            first
+                .iter()
+                .map(|(position, _)| *position)
+                .collect::<Vec<_>>(),
+            all[..3]
+        );
+        assert_eq!(scene.take_neighbor_updates(usize::MAX).len(), all.len() - 3);
+    }
+
+    #[test]
+    fn journals_only_record_their_own_thread() {
+        let scene = VoxelScene::new();
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        let voxel = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        scene.set_voxel(&IVec3::ZERO, voxel);
+
+        assert!(scene.begin_journal());
+        assert!(!scene.begin_journal());
+        let other = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    assert!(scene.begin_journal());
+                    scene.set_voxel(&IVec3::ONE, voxel);
+                    scene.end_journal()
+                })
+                .join()
+                .unwrap()
+        });
+        scene.set_voxel(&IVec3::X, voxel);
+        let own = scene.end_journal();
+
+        assert_eq!(other.len(), 1);
+        assert_eq!(other[0].position, IVec3::ONE);
+        assert_eq!(own.len(), 1);
+        assert_eq!(own[0].position, IVec3::X);
+        assert!(scene.end_journal().is_empty());
+    }
+}
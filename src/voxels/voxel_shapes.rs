@@ -1,4 +1,6 @@
 use glam::IVec3;
+use tracing::warn;
+
 mod occlussion_shapes {
     const CUBE: [u8; 6] = [
         0b_1111_1111, // North
@@ -161,6 +163,25 @@ mod occlussion_shapes {
         r
     }
 
+    // The lowest of a face's quarter turns and their mirror images. The orientations don't keep the
+    // bits of a face turned the same way, so faces are compared by this instead
+    pub fn face_class(face: u8) -> u8 {
+        [face, face.reverse_bits()]
+            .into_iter()
+            .flat_map(|face| [0, 2, 4, 6].map(|turn| face.rotate_right(turn)))
+            .min()
+            .unwrap_or(face)
+    }
+
+    // A quarter turn around the y axis, moves the north face east, east south, south west and west north
+    pub fn rotate_y(sides: [u8; 6]) -> [u8; 6] {
+        [sides[3], sides[2], sides[0], sides[1], sides[4], sides[5]]
+    }
+
+    pub fn mirror_x(sides: [u8; 6]) -> [u8; 6] {
+        [sides[0], sides[1], sides[3], sides[2], sides[4], sides[5]]
+    }
+
     fn rotate_z(sides: [u8; 6]) -> [u8; 6] {
         let mut r = [0; 6];
         r[0] = sides[0].rotate_right(2); // North
@@ -310,6 +331,44 @@ pub struct VoxelShape {
     pub data: u8,
 }
 
+lazy_static! {
+    static ref ROTATED_Y: [VoxelShape; 256] =
+        transform_table("rotated", occlussion_shapes::rotate_y);
+    static ref MIRRORED_X: [VoxelShape; 256] =
+        transform_table("mirrored", occlussion_shapes::mirror_x);
+}
+
+// Works out the face shapes of every orientation now rather than on first access
+pub fn load() {
+    lazy_static::initialize(&occlussion_shapes::SHAPE_ORIENTATIONS);
+    lazy_static::initialize(&ROTATED_Y);
+    lazy_static::initialize(&MIRRORED_X);
+}
+
+// For every shape and orientation, finds the orientation of the same shape whose faces match the
+// transformed faces. The orientation bits can't turn stairs and prisms to face east or west while
+// lying flat, those are kept as they are
+fn transform_table(name: &str, transform: fn([u8; 6]) -> [u8; 6]) -> [VoxelShape; 256] {
+    let mut missing = 0;
+    let table = std::array::from_fn(|data| {
+        let shape = VoxelShape { data: data as u8 };
+        let target = transform(shape.face_classes());
+        (0..32_u8)
+            .map(|orientation| VoxelShape {
+                data: (shape.data & 0b_0000_0111) | (orientation << 3),
+            })
+            .find(|candidate| candidate.face_classes() == target)
+            .unwrap_or_else(|| {
+                missing += 1;
+                shape
+            })
+    });
+    if missing > 0 {
+        warn!("{missing} voxel shape orientations can't be {name} and are placed unchanged");
+    }
+    table
+}
+
 // [7] Rotate Z
 // [6] Rotate X
 // [5] Flip Y
@@ -328,6 +387,24 @@ impl VoxelShape {
         VoxelShape::get_face_shape(*self, face) & other_shape == other_shape
     }
 
+    fn face_classes(&self) -> [u8; 6] {
+        [0, 1, 2, 3, 4, 5].map(|data| {
+            occlussion_shapes::face_class(VoxelShape::get_face_shape(
+                *self,
+                VoxelDirection { data },
+            ))
+        })
+    }
+
+    // Quarter turn around the y axis, matching a position rotation of (x, z) to (z, -x)
+    pub fn rotated_y(&self) -> VoxelShape {
+        ROTATED_Y[self.data as usize]
+    }
+
+    pub fn mirrored_x(&self) -> VoxelShape {
+        MIRRORED_X[self.data as usize]
+    }
+
     pub fn orient_self(&mut self, orientation: VoxelOrientation) {
         self.data = (self.data & 0b_0000_0111) | orientation.data;
     }
@@ -458,3 +535,57 @@ impl VoxelOrientation {
         self.data & 0b_1000_0000 == 0b_1000_0000
     }
 }
+
+#[cfg(test)]
+mod voxel_shape_tests {
+    use super::*;
+
+    fn full_faces(shape: VoxelShape) -> Vec<VoxelDirection> {
+        voxel_directions::ALL
+            .into_iter()
+            .filter(|direction| VoxelShape::get_face_shape(shape, *direction) == 0b_1111_1111)
+            .collect()
+    }
+
+    #[test]
+    fn quarter_turns_move_the_faces_clockwise() {
+        use voxel_directions::*;
+        let mut shape = voxel_shape::CORNER_STAIR;
+        assert_eq!(full_faces(shape), [NORTH, EAST, DOWN]);
+        shape = shape.rotated_y();
+        assert_eq!(full_faces(shape), [SOUTH, EAST, DOWN]);
+        shape = shape.rotated_y();
+        assert_eq!(full_faces(shape), [SOUTH, WEST, DOWN]);
+        shape = shape.rotated_y();
+        assert_eq!(full_faces(shape), [NORTH, WEST, DOWN]);
+        shape = shape.rotated_y();
+        assert_eq!(
+            shape.face_classes(),
+            voxel_shape::CORNER_STAIR.face_classes()
+        );
+    }
+
+    #[test]
+    fn mirroring_swaps_east_and_west() {
+        use voxel_directions::*;
+        let mirrored = voxel_shape::INNER_PRISM_JUNCTION.mirrored_x();
+        assert_eq!(full_faces(mirrored), [NORTH, WEST, DOWN]);
+        // Every orientation can be mirrored, and mirroring twice gives back the same faces
+        for data in 0..255 {
+            let shape = VoxelShape { data };
+            let mirrored = shape.mirrored_x();
+            assert_eq!(
+                mirrored.face_classes(),
+                occlussion_shapes::mirror_x(shape.face_classes())
+            );
+            assert_eq!(mirrored.mirrored_x().face_classes(), shape.face_classes());
+        }
+    }
+
+    #[test]
+    fn shapes_without_a_turned_orientation_stay_unchanged() {
+        // A flat stair can't face east, the orientation bits only turn it on its side
+        assert_eq!(voxel_shape::STAIR.rotated_y(), voxel_shape::STAIR);
+        assert_eq!(voxel_shape::SLAB.rotated_y(), voxel_shape::SLAB);
+    }
+}
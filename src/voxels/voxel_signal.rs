@@ -0,0 +1,179 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use glam::IVec3;
+
+use crate::error::{expect_str, expect_u64, required, AssemblageError};
+
+use super::{
+    voxel_data::VoxelData, voxel_registry::get_voxel_by_id, voxel_scene::VoxelScene,
+    voxel_shapes::voxel_directions,
+};
+
+pub const MAX_SIGNAL: u8 = 15;
+// Stops a single update from flood filling an unreasonably large wire network
+const MAX_NETWORK_SIZE: usize = 4096;
+
+// Conductors store their signal strength in the lower 4 bits of the voxel state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalKind {
+    Emitter(u8),
+    Conductor,
+    Consumer,
+}
+
+impl SignalKind {
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, AssemblageError> {
+        let signal_type = expect_str(required(json, "type")?, "type")?;
+        Ok(match signal_type {
+            "emitter" => SignalKind::Emitter(
+                json.get("power")
+                    .map_or(Ok(MAX_SIGNAL as u64), |v| expect_u64(v, "power"))?
+                    .min(MAX_SIGNAL as u64) as u8,
+            ),
+            "conductor" => SignalKind::Conductor,
+            "consumer" => SignalKind::Consumer,
+            &_ => {
+                return Err(AssemblageError::asset(format!(
+                    "the signal type {signal_type} is not supported"
+                )))
+            }
+        })
+    }
+}
+
+pub fn signal_kind(voxel: &VoxelData) -> Option<SignalKind> {
+    get_voxel_by_id(voxel.id).and_then(|profile| profile.signal)
+}
+
+fn kind_at(scene: &VoxelScene, position: &IVec3) -> Option<(VoxelData, SignalKind)> {
+    let voxel = scene.voxel_at(position)?;
+    signal_kind(&voxel).map(|kind| (voxel, kind))
+}
+
+// The signal strength a voxel gives to its neighbours
+fn output_power(scene: &VoxelScene, position: &IVec3) -> u8 {
+    match kind_at(scene, position) {
+        Some((_, SignalKind::Emitter(power))) => power,
+        Some((voxel, SignalKind::Conductor)) => (voxel.state & 0b_0000_1111).saturating_sub(1),
+        _ => 0,
+    }
+}
+
+pub fn input_power(scene: &VoxelScene, position: &IVec3) -> u8 {
+    voxel_directions::ALL
+        .iter()
+        .map(|direction| output_power(scene, &(*position + direction.as_vec())))
+        .max()
+        .unwrap_or(0)
+}
+
+// Sorting key that orders positions bottom to top, used so updates always happen in the same order
+fn order_key(position: &IVec3) -> (i32, i32, i32) {
+    (position.y, position.x, position.z)
+}
+
+// Recalculates the wire networks touching the given positions and notifies the consumers attached to them
+pub fn update_signals(scene: &VoxelScene, origins: &[IVec3]) {
+    if origins.is_empty() {
+        return;
+    }
+
+    // Find every conductor connected to the origins
+    let mut network = HashSet::new();
+    let mut stack = Vec::new();
+    let mut consumers = HashSet::new();
+    for origin in origins {
+        stack.push(*origin);
+        for direction in voxel_directions::ALL {
+            stack.push(*origin + direction.as_vec());
+        }
+    }
+    while let Some(position) = stack.pop() {
+        if network.contains(&position) || network.len() >= MAX_NETWORK_SIZE {
+            continue;
+        }
+        match kind_at(scene, &position) {
+            Some((_, SignalKind::Conductor)) => {
+                network.insert(position);
+                for direction in voxel_directions::ALL {
+                    stack.push(position + direction.as_vec());
+                }
+            }
+            Some((_, SignalKind::Consumer)) => {
+                consumers.insert(position);
+            }
+            _ => {}
+        }
+    }
+
+    // Seed the network from the emitters next to it, then spread the signal from the strongest conductors first
+    let mut power: HashMap<IVec3, u8> = network.iter().map(|p| (*p, 0)).collect();
+    let mut queue = BinaryHeap::new();
+    for position in &network {
+        let seed = voxel_directions::ALL
+            .iter()
+            .filter_map(
+                |direction| match kind_at(scene, &(*position + direction.as_vec())) {
+                    Some((_, SignalKind::Emitter(power))) => Some(power),
+                    _ => None,
+                },
+            )
+            .max()
+            .unwrap_or(0);
+        if seed > 0 {
+            power.insert(*position, seed);
+            queue.push((seed, Reverse(order_key(position))));
+        }
+    }
+    while let Some((strength, Reverse((y, x, z)))) = queue.pop() {
+        let position = IVec3::new(x, y, z);
+        if power[&position] != strength || strength <= 1 {
+            continue;
+        }
+        for direction in voxel_directions::ALL {
+            let neighbour = position + direction.as_vec();
+            if let Some(neighbour_power) = power.get_mut(&neighbour) {
+                if *neighbour_power < strength - 1 {
+                    *neighbour_power = strength - 1;
+                    queue.push((strength - 1, Reverse(order_key(&neighbour))));
+                }
+            }
+        }
+    }
+
+    // Write the new strengths back into the scene
+    let mut ordered = network.into_iter().collect::<Vec<_>>();
+    ordered.sort_by_key(order_key);
+    for position in &ordered {
+        let voxel = match scene.voxel_at(position) {
+            Some(voxel) => voxel,
+            None => continue,
+        };
+        let strength = power[position];
+        if voxel.state & 0b_0000_1111 != strength {
+            scene.set_voxel(
+                position,
+                VoxelData {
+                    state: (voxel.state & 0b_1111_0000) | strength,
+                    ..voxel
+                },
+            );
+        }
+    }
+
+    let mut consumers = consumers.into_iter().collect::<Vec<_>>();
+    consumers.sort_by_key(order_key);
+    for position in consumers {
+        let voxel = match scene.voxel_at(&position) {
+            Some(voxel) => voxel,
+            None => continue,
+        };
+        let behavior = get_voxel_by_id(voxel.id).and_then(|p| p.behavior.clone());
+        if let Some(behavior) = behavior {
+            behavior.on_signal_changed(scene, position, voxel, input_power(scene, &position));
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use flume::Receiver;
+use glam::{IVec3, Quat, UVec3};
+use legion::{Resources, Schedule};
+use parking_lot::RwLock;
+use rand::Rng;
+use tracing::info;
+
+use crate::{
+    ecs::{
+        components::{
+            transformation_components::{Position, Rotation},
+            voxel_components::FallingVoxel,
+        },
+        entities::{
+            item_drops::spawn_dropped_item,
+            spawner::{attempt_spawns, SPAWN_INTERVAL},
+        },
+        spatial_index::SpatialIndex,
+        systems::{
+            entity_systems::{
+                apply_gravity_system, integrate_entities_system, rebuild_spatial_index_system,
+                separate_entities_system,
+            },
+            item_systems::{
+                age_dropped_items_system, merge_dropped_items_system, pickup_dropped_items_system,
+            },
+            projectile_systems::move_projectiles_system,
+            voxel_systems::update_falling_voxels_system,
+        },
+        world::World,
+    },
+    environment::{self, step_environment},
+    persistence::entity_persistence::{
+        spawn_loaded_entities, update_chunk_owners_system, ChunkOwner, PersistentId, SavedEntity,
+    },
+    profile_scope,
+    profiling::{self, FrameSource},
+    random,
+    time::Time,
+};
+
+use super::{
+    tick_monitor::{OverloadLevel, TickMonitor, MAX_CATCH_UP_TICKS},
+    voxel_data::VoxelData,
+    voxel_registry::{get_voxel_by_id, get_voxel_by_name},
+    voxel_scene::{VoxelScene, CHUNK_SIZE, GRAVITY_TICK_DELAY},
+    voxel_shapes::{voxel_directions, voxel_shape},
+    voxel_signal::update_signals,
+};
+
+pub const TICKS_PER_SECOND: u32 = 20;
+// How far above a voxel is checked for cover before snow is placed on it
+const SNOW_SKY_CHECK_HEIGHT: i32 = 32;
+// Caps the neighbour updates handled in one tick so bulk edits are spread over several ticks
+pub const MAX_NEIGHBOR_UPDATES_PER_TICK: usize = 4096;
+// The cap while the simulation is overloaded, the rest wait for later ticks
+const THROTTLED_NEIGHBOR_UPDATES_PER_TICK: usize = 512;
+
+pub struct VoxelSimulation {
+    pub scene: Arc<RwLock<VoxelScene>>,
+    pub world: Arc<RwLock<World>>,
+    pub spatial_index: Arc<RwLock<SpatialIndex>>,
+    pub tick: u64,
+    // The number of random positions picked in each ticking chunk per tick
+    pub random_tick_speed: u32,
+    scheduled_ticks: BTreeMap<u64, Vec<IVec3>>,
+    scheduled_tick_receiver: Receiver<(IVec3, u32)>,
+    loaded_entity_receiver: Receiver<Vec<SavedEntity>>,
+    item_drop_receiver: Receiver<(IVec3, VoxelData)>,
+    // Times the ticks, step leaves out work while it reports an overload
+    monitor: TickMonitor,
+}
+
+impl VoxelSimulation {
+    pub fn new(scene: Arc<RwLock<VoxelScene>>, world: Arc<RwLock<World>>) -> Self {
+        let scheduled_tick_receiver = scene.read().get_scheduled_tick_receiver();
+        let loaded_entity_receiver = scene.read().get_loaded_entity_receiver();
+        let item_drop_receiver = scene.read().get_item_drop_receiver();
+        Self {
+            scene,
+            world,
+            spatial_index: Arc::new(RwLock::new(SpatialIndex::new())),
+            tick: 0,
+            random_tick_speed: 3,
+            scheduled_ticks: BTreeMap::new(),
+            scheduled_tick_receiver,
+            loaded_entity_receiver,
+            item_drop_receiver,
+            monitor: TickMonitor::new(TICKS_PER_SECOND),
+        }
+    }
+
+    pub fn step(&mut self, entity_schedule: &mut Schedule, resources: &mut Resources) {
+        profile_scope!("simulation_tick");
+        // The world tick is saved with the world, so a reloaded world makes the same decisions
+        random::set_tick(environment::current().time.tick);
+        step_environment();
+
+        let scene_lock = self.scene.read();
+        let overload = self.monitor.overload();
+        if overload < OverloadLevel::SkipRandomTicks {
+            self.random_tick(&scene_lock);
+        }
+        let max_updates = match overload {
+            OverloadLevel::ThrottleNeighborUpdates => THROTTLED_NEIGHBOR_UPDATES_PER_TICK,
+            _ => MAX_NEIGHBOR_UPDATES_PER_TICK,
+        };
+        self.neighbor_updates(&scene_lock, max_updates);
+        let falling = self.scheduled_tick(&scene_lock);
+        drop(scene_lock);
+
+        let mut world_lock = self.world.write();
+        for entities in self.loaded_entity_receiver.try_iter() {
+            spawn_loaded_entities(&mut world_lock.legion_world, entities);
+        }
+        for (position, voxel) in self.item_drop_receiver.try_iter() {
+            spawn_dropped_item(
+                &mut world_lock.legion_world,
+                position.as_vec3(),
+                voxel.id,
+                1,
+            );
+        }
+        for (position, voxel) in falling {
+            world_lock.legion_world.push((
+                PersistentId::new(),
+                ChunkOwner(None),
+                Position(position.as_vec3()),
+                Rotation(Quat::IDENTITY),
+                FallingVoxel {
+                    voxel,
+                    velocity: 0.0,
+                },
+            ));
+        }
+        if self.tick % SPAWN_INTERVAL == 0 {
+            let scene_lock = self.scene.read();
+            attempt_spawns(
+                &scene_lock,
+                &mut world_lock.legion_world,
+                &self.spatial_index.read(),
+            );
+        }
+        drop(world_lock);
+
+        // Entities are ticked at the same fixed rate as the voxels
+        resources.insert(Time {
+            time: self.tick as f64 / TICKS_PER_SECOND as f64,
+            delta_time: 1.0 / TICKS_PER_SECOND as f64,
+        });
+        let mut world_lock = self.world.write();
+        entity_schedule.execute(&mut world_lock.legion_world, resources);
+        drop(world_lock);
+
+        self.tick += 1;
+    }
+
+    fn neighbor_updates(&self, scene: &VoxelScene, max_updates: usize) {
+        let mut signal_origins = Vec::new();
+        for (position, neighbor_positions) in scene.take_neighbor_updates(max_updates) {
+            let voxel = match scene.voxel_at(&position) {
+                Some(voxel) if voxel.id != 0 => voxel,
+                _ => continue,
+            };
+            let profile = match get_voxel_by_id(voxel.id) {
+                Some(profile) => profile,
+                None => continue,
+            };
+
+            if profile.signal.is_some() {
+                signal_origins.push(position);
+            }
+
+            // A gravity affected voxel may have lost its support
+            if profile.has_tag("gravity") {
+                scene.schedule_tick(position, GRAVITY_TICK_DELAY);
+            }
+
+            if let Some(behavior) = &profile.behavior {
+                for neighbor_position in neighbor_positions {
+                    behavior.on_neighbor_changed(scene, position, voxel, neighbor_position);
+                    // Once the behaviour replaced the voxel the rest of the neighbours no longer
+                    // apply to it
+                    let unchanged = scene
+                        .voxel_at(&position)
+                        .map_or(false, |v| v.id == voxel.id && v.state == voxel.state);
+                    if !unchanged {
+                        break;
+                    }
+                }
+            }
+        }
+
+        update_signals(scene, &signal_origins);
+    }
+
+    // Returns the voxels that started falling this tick
+    fn scheduled_tick(&mut self, scene: &VoxelScene) -> Vec<(IVec3, VoxelData)> {
+        for (position, delay) in self.scheduled_tick_receiver.try_iter() {
+            self.scheduled_ticks
+                .entry(self.tick + delay.max(1) as u64)
+                .or_default()
+                .push(position);
+        }
+
+        let mut falling = Vec::new();
+        let due = match self.scheduled_ticks.remove(&self.tick) {
+            Some(due) => due,
+            None => return falling,
+        };
+        for position in due {
+            let voxel = match scene.voxel_at(&position) {
+                Some(voxel) => voxel,
+                None => continue,
+            };
+            let profile = match get_voxel_by_id(voxel.id) {
+                Some(profile) => profile,
+                None => continue,
+            };
+
+            if profile.has_tag("gravity") {
+                let below = scene.voxel_at(&(position + voxel_directions::DOWN.as_vec()));
+                if below.map_or(false, |below| below.id == 0) {
+                    scene.set_voxel(&position, VoxelData { id: 0, ..voxel });
+                    falling.push((position, voxel));
+                    continue;
+                }
+            }
+
+            if let Some(behavior) = &profile.behavior {
+                behavior.on_scheduled_tick(scene, position, voxel);
+            }
+        }
+        falling
+    }
+
+    fn random_tick(&self, scene: &VoxelScene) {
+        let snow = match environment::current().is_snowing() {
+            true => get_voxel_by_name("snow".to_string()),
+            false => None,
+        };
+        for chunk_pos in scene.ticking_chunks() {
+            if scene.chunks.get(&chunk_pos).map_or(true, |c| c.is_empty) {
+                continue;
+            }
+            let chunk_pos_scenespace = chunk_pos * CHUNK_SIZE as i32;
+            let mut rng = random::chunk_rng(chunk_pos, "random_tick");
+            for _ in 0..self.random_tick_speed {
+                let local_pos = UVec3::new(
+                    rng.gen_range(0..CHUNK_SIZE),
+                    rng.gen_range(0..CHUNK_SIZE),
+                    rng.gen_range(0..CHUNK_SIZE),
+                );
+                let position: IVec3 = chunk_pos_scenespace + local_pos.as_ivec3();
+                let voxel = match scene.voxel_at(&position) {
+                    Some(voxel) => voxel,
+                    None => continue,
+                };
+                if voxel.id == 0 {
+                    continue;
+                }
+                if let Some(snow) = snow {
+                    Self::accumulate_snow(scene, position, voxel, snow.id);
+                }
+                let behavior = get_voxel_by_id(voxel.id).and_then(|p| p.behavior.clone());
+                if let Some(behavior) = behavior {
+                    behavior.on_random_tick(scene, position, voxel);
+                }
+            }
+        }
+    }
+
+    // Places snow on top of the voxel if it is exposed to the sky
+    fn accumulate_snow(scene: &VoxelScene, position: IVec3, voxel: VoxelData, snow_id: u16) {
+        if voxel.id == snow_id {
+            return;
+        }
+        let above = position + voxel_directions::UP.as_vec();
+        for height in 0..SNOW_SKY_CHECK_HEIGHT {
+            let sample = above + voxel_directions::UP.as_vec() * height;
+            if scene.voxel_at(&sample).map_or(false, |v| v.id != 0) {
+                return;
+            }
+        }
+        scene.set_voxel(
+            &above,
+            VoxelData {
+                shape: voxel_shape::SLAB,
+                state: 0,
+                id: snow_id,
+            },
+        );
+    }
+
+    // The entity systems run by step, in order
+    pub fn entity_schedule() -> Schedule {
+        Schedule::builder()
+            .add_system(apply_gravity_system())
+            .add_system(integrate_entities_system())
+            .add_system(separate_entities_system())
+            .add_system(move_projectiles_system())
+            .add_system(update_falling_voxels_system())
+            .add_system(age_dropped_items_system())
+            .add_system(merge_dropped_items_system())
+            // Merged stacks are removed before anything can pick them up
+            .flush()
+            .add_system(pickup_dropped_items_system())
+            .add_system(update_chunk_owners_system())
+            .flush()
+            .add_system(rebuild_spatial_index_system())
+            .build()
+    }
+
+    // What the entity systems read besides the world, step adds the time
+    pub fn resources(&self) -> Resources {
+        let mut resources = Resources::default();
+        resources.insert(Arc::clone(&self.scene));
+        resources.insert(Arc::clone(&self.spatial_index));
+        resources
+    }
+
+    // Runs the simulation at a fixed rate until running is cleared. Ticks that started late are
+    // caught up on back to back, up to MAX_CATCH_UP_TICKS behind
+    pub fn run(mut self, running: Arc<AtomicBool>) {
+        info!("Started voxel simulation at {TICKS_PER_SECOND} ticks per second");
+        let tick_length = self.monitor.tick_length();
+        let mut entity_schedule = Self::entity_schedule();
+        let mut resources = self.resources();
+        let mut next_tick = Instant::now();
+        while running.load(Ordering::Relaxed) {
+            let tick_start = Instant::now();
+            let behind = tick_start.saturating_duration_since(next_tick);
+            if behind > tick_length * MAX_CATCH_UP_TICKS {
+                let skipped = (behind.as_secs_f64() / tick_length.as_secs_f64()) as u64;
+                self.monitor.record_skipped(skipped);
+                next_tick = tick_start;
+            }
+            self.step(&mut entity_schedule, &mut resources);
+            profiling::new_frame(FrameSource::ServerTick);
+            self.monitor.record(tick_start, tick_start.elapsed());
+            next_tick += tick_length;
+            let now = Instant::now();
+            if now < next_tick {
+                std::thread::sleep(next_tick - now);
+            }
+        }
+    }
+}
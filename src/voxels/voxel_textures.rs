@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
+use parking_lot::RwLock;
+
+lazy_static! {
+    static ref UV_TABLE: RwLock<Arc<UvTable>> = RwLock::new(Arc::new(UvTable::default()));
+}
+
+// How the atlas is sampled, the "Texture Filtering" setting. Close up the texels stay sharp either
+// way, the modes differ in how far away textures blend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFiltering {
+    // Picks the closest texel of the closest mip level, blocky but never blurry
+    Nearest,
+    // Blends between texels and between mip levels, smooth in the distance
+    Trilinear,
+}
+
+impl Default for TextureFiltering {
+    fn default() -> Self {
+        Self::Nearest
+    }
+}
+
+impl TextureFiltering {
+    pub fn from_json(json: &serde_json::Value) -> Option<Self> {
+        match json.as_str()? {
+            "Nearest" => Some(Self::Nearest),
+            "Trilinear" => Some(Self::Trilinear),
+            _ => None,
+        }
+    }
+}
+
+// The anisotropy levels samplers support are powers of two up to 16, others round down
+pub fn anisotropy_level(level: u64) -> u8 {
+    let level = level.clamp(1, 16) as u8;
+    1 << (7 - level.leading_zeros())
+}
+
+// A tile of the texture atlas in texture coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvRect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl UvRect {
+    pub fn map(&self, uv: Vec2) -> Vec2 {
+        self.min + (self.max - self.min) * uv
+    }
+}
+
+// Where each voxel's texture is in the current atlas, by voxel id. Voxels without a texture are
+// drawn with their color over the atlas' white tile
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UvTable {
+    pub tiles: Vec<Option<UvRect>>,
+    pub white: Vec2,
+}
+
+impl UvTable {
+    pub fn tile(&self, id: u16) -> Option<UvRect> {
+        self.tiles.get(id as usize).copied().flatten()
+    }
+}
+
+// Swapped in whole when the atlas is rebuilt, meshes started before keep the table they read
+pub fn set_uv_table(table: UvTable) {
+    *UV_TABLE.write() = Arc::new(table);
+}
+
+pub fn uv_table() -> Arc<UvTable> {
+    Arc::clone(&UV_TABLE.read())
+}
+
+// Shape meshes don't carry texture coordinates, so every vertex is projected onto the face of the
+// voxel's box it points away from. Position is relative to the voxel's center, a whole face runs
+// from 0 to 1 with v going down like in the texture
+pub fn box_uv(position: Vec3, normal: Vec3) -> Vec2 {
+    let local = position + 0.5;
+    let abs = normal.abs();
+    let uv = if abs.x >= abs.y && abs.x >= abs.z {
+        Vec2::new(local.z, local.y)
+    } else if abs.y >= abs.z {
+        Vec2::new(local.x, local.z)
+    } else {
+        Vec2::new(local.x, local.y)
+    };
+    Vec2::new(uv.x, 1.0 - uv.y).clamp(Vec2::ZERO, Vec2::ONE)
+}
+
+#[cfg(test)]
+mod voxel_textures_tests {
+    use super::*;
+
+    #[test]
+    fn faces_span_their_tile() {
+        let tile = UvRect {
+            min: Vec2::new(0.5, 0.25),
+            max: Vec2::new(0.75, 0.5),
+        };
+        let corner = box_uv(Vec3::new(0.5, -0.5, 0.5), Vec3::X);
+        assert_eq!(corner, Vec2::new(1.0, 1.0));
+        assert_eq!(tile.map(corner), tile.max);
+        // A slab's side only covers the lower half of the tile
+        let side = box_uv(Vec3::new(-0.5, 0.0, -0.5), -Vec3::Z);
+        assert_eq!(side, Vec2::new(0.0, 0.5));
+    }
+
+    #[test]
+    fn filtering_settings_parse() {
+        let parse = |json| TextureFiltering::from_json(&json);
+        assert_eq!(
+            parse(serde_json::json!("Trilinear")),
+            Some(TextureFiltering::Trilinear)
+        );
+        assert_eq!(parse(serde_json::json!("Bilinear")), None);
+        let levels = [0, 1, 3, 8, 12, 64].map(anisotropy_level);
+        assert_eq!(levels, [1, 1, 2, 8, 8, 16]);
+    }
+
+    #[test]
+    fn missing_tiles_fall_back_to_the_color() {
+        let table = UvTable {
+            tiles: vec![
+                None,
+                Some(UvRect {
+                    min: Vec2::ZERO,
+                    max: Vec2::ONE,
+                }),
+            ],
+            white: Vec2::ZERO,
+        };
+        assert!(table.tile(0).is_none() && table.tile(7).is_none());
+        assert!(table.tile(1).is_some());
+    }
+}